@@ -0,0 +1,89 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sha2::{Digest, Sha256};
+
+use lisk_db::batch::PrefixWriteBatch;
+use lisk_db::consts;
+use lisk_db::sparse_merkle_tree::smt::{SparseMerkleTree, UpdateData, EMPTY_HASH};
+use lisk_db::sparse_merkle_tree::smt_db::InMemorySmtDB;
+use lisk_db::state::state_writer::StateWriter;
+use lisk_db::types::{KeyLength, SharedKVPair};
+
+const DATA_LEN: usize = 100_000;
+const VALUE_LEN: usize = 100;
+
+/// CountingAllocator keeps the bytes allocated now and the peak since the last reset.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// measure prints the peak bytes allocated by f on top of the bytes allocated before it.
+fn measure<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let result = f();
+    println!(
+        "{:<40} {:>10.3} MB",
+        name,
+        (PEAK.load(Ordering::SeqCst) - before) as f64 / 1_000_000.0
+    );
+    result
+}
+
+fn get_writer() -> StateWriter {
+    let mut writer = StateWriter::default();
+    for i in 0..DATA_LEN {
+        let key = [
+            &[0_u8, 0, 0, 1, 0, 0][..],
+            Sha256::digest(i.to_string().as_bytes()).as_slice(),
+        ]
+        .concat();
+        writer.cache_new(&SharedKVPair::new(&key, &[i as u8; VALUE_LEN]));
+    }
+    writer
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let writer = measure("StateWriter of 100k keys", get_writer);
+
+    // baseline of a full copy of the writer, which the commit does not make
+    measure("StateWriter clone (baseline)", || writer.clone());
+    measure("Hashed update and SMT commit", || {
+        let data = UpdateData::new_from(writer.get_hashed_updated());
+        let mut db = InMemorySmtDB::default();
+        let mut tree = SparseMerkleTree::new(&EMPTY_HASH, KeyLength(38), consts::SUBTREE_HEIGHT);
+        tree.commit(&mut db, &data).unwrap();
+    });
+    measure("State batch and diff", || {
+        let mut write_batch = PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::STATE);
+        let diff = writer.commit(&mut write_batch);
+        assert_eq!(diff.created().len(), DATA_LEN);
+    });
+
+    println!("Benchmarking successfully completed");
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use rocksdb::{IteratorMode, Options, DB};
+use sha2::{Digest, Sha256};
+use tempdir::TempDir;
+
+use lisk_db::types::KVPair;
+
+const ROUNDS: u32 = 5;
+// total bytes stored for each value size
+const TOTAL_SIZE: usize = 64 * 1024 * 1024;
+const VALUE_SIZES: [(&str, usize); 3] = [
+    ("4KB", 4 * 1024),
+    ("1MB", 1024 * 1024),
+    ("16MB", 16 * 1024 * 1024),
+];
+
+fn measure(name: &str, mut f: impl FnMut()) {
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f();
+        total += start.elapsed();
+    }
+    println!(
+        "{:<40} {:>10.3} ms",
+        name,
+        (total / ROUNDS).as_secs_f64() * 1000.0
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    for (label, size) in VALUE_SIZES {
+        let temp_dir = TempDir::new("bench_read_")?;
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, temp_dir.path())?;
+        let keys: Vec<Vec<u8>> = (0..TOTAL_SIZE / size)
+            .map(|i| Sha256::digest(i.to_string().as_bytes()).to_vec())
+            .collect();
+        for key in keys.iter() {
+            db.put(key, vec![1; size])?;
+        }
+        db.flush()?;
+
+        // get copies the value out of the pinned slice once
+        measure(&format!("get {}", label), || {
+            for key in keys.iter() {
+                assert_eq!(db.get(key).unwrap().unwrap().len(), size);
+            }
+        });
+        measure(&format!("get_pinned {}", label), || {
+            for key in keys.iter() {
+                assert_eq!(db.get_pinned(key).unwrap().unwrap().len(), size);
+            }
+        });
+
+        // iteration copies into the pair, and again into the JS buffer without zeroCopy
+        measure(&format!("iterate copy {}", label), || {
+            for key_val in db.iterator(IteratorMode::Start) {
+                let (key, value) = key_val.unwrap();
+                let pair = KVPair::new(&key, &value);
+                assert_eq!(pair.value_as_vec().len(), size);
+            }
+        });
+        measure(&format!("iterate zeroCopy {}", label), || {
+            for key_val in db.iterator(IteratorMode::Start) {
+                let (key, value) = key_val.unwrap();
+                let KVPair(_, value) = KVPair(key.into_vec(), value.into_vec());
+                assert_eq!(value.len(), size);
+            }
+        });
+    }
+
+    println!("Benchmarking successfully completed");
+
+    Ok(())
+}
@@ -1,13 +1,10 @@
 use std::error::Error;
-use std::sync::mpsc;
 
 use rocksdb::{Options, DB};
 use tempdir::TempDir;
 
 use lisk_db::batch::PrefixWriteBatch;
 use lisk_db::consts;
-use lisk_db::database::types::{DbMessage, Kind};
-use lisk_db::database::DB as LDB;
 use lisk_db::sparse_merkle_tree::smt::{SparseMerkleTree, UpdateData};
 use lisk_db::sparse_merkle_tree::smt_db;
 use lisk_db::types::{Cache, KeyLength, NestedVec, SharedKVPair};
@@ -227,9 +224,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let mut opts = Options::default();
         opts.create_if_missing(true);
         let rocks_db = DB::open(&opts, temp_dir.path())?;
-        let (tx, _) = mpsc::channel::<DbMessage>();
-        let common_db = LDB::new(rocks_db, tx, Kind::Normal);
-        let mut db = smt_db::SmtDB::new(&common_db);
+        let mut db = smt_db::SmtDB::new(&rocks_db);
 
         let root = tree.commit(&mut db, &data)?;
 
@@ -237,7 +232,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let mut write_batch = PrefixWriteBatch::new();
         write_batch.set_prefix(&consts::Prefix::SMT);
         db.batch.iterate(&mut write_batch);
-        common_db.write(write_batch.batch)?;
+        rocks_db.write(write_batch.batch)?;
 
         let proof = tree.prove(
             &mut db,
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sha2::{Digest, Sha256};
+
+use lisk_db::sparse_merkle_tree::smt::{SparseMerkleTree, UpdateData};
+use lisk_db::sparse_merkle_tree::smt_db::InMemorySmtDB;
+use lisk_db::types::{Cache, KeyLength};
+
+const LEAVES: [usize; 2] = [10_000, 100_000];
+/// THREADS are the numbers of the threads updating the tree. The speedup needs a machine with the cores.
+const THREADS: [usize; 2] = [1, 4];
+
+fn hash(value: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(value);
+    hasher.finalize().to_vec()
+}
+
+/// get_data returns the leaves whose keys and values are the hashes of the index and the seed.
+fn get_data(len: usize, seed: u8) -> Cache {
+    let mut data = Cache::new();
+    for i in 0..len {
+        let key = hash(&i.to_be_bytes());
+        let value = hash(&[key.as_slice(), &[seed]].concat());
+        data.insert(key, value);
+    }
+    data
+}
+
+/// committed returns the tree and the nodes after committing the data to the empty tree.
+fn committed(data: &UpdateData, threads: usize) -> (SparseMerkleTree, InMemorySmtDB) {
+    let mut tree =
+        SparseMerkleTree::new(&[], KeyLength(32), Default::default()).with_threads(threads);
+    let mut db = InMemorySmtDB::default();
+    tree.commit(&mut db, data).unwrap();
+    (tree, db)
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("smt update");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(30));
+    for len in LEAVES {
+        let data = UpdateData::new_from(get_data(len, 0));
+        // all the leaves of the existing tree are updated
+        let updated = UpdateData::new_from(get_data(len, 1));
+        for threads in THREADS {
+            let insert = format!("insert/threads {}", threads);
+            group.bench_with_input(BenchmarkId::new(insert, len), &data, |b, data| {
+                b.iter(|| committed(data, threads))
+            });
+
+            let update = format!("update/threads {}", threads);
+            group.bench_with_input(BenchmarkId::new(update, len), &updated, |b, updated| {
+                b.iter_batched(
+                    || committed(&data, threads),
+                    |(mut tree, mut db)| tree.commit(&mut db, updated).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
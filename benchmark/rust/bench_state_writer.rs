@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use lisk_db::batch::PrefixWriteBatch;
+use lisk_db::consts;
+use lisk_db::database::reader_writer::read_writer_db;
+use lisk_db::state::state_writer::StateWriter;
+use lisk_db::types::{KVPair, SharedKVPair};
+
+const DATA_LEN: usize = 200_000;
+const ROUNDS: u32 = 5;
+const READERS: usize = 8;
+// reads of each reader, and the writer updates one key for every READERS reads
+const CONCURRENT_READS: usize = 20_000;
+
+fn get_keys() -> Vec<Vec<u8>> {
+    (0..DATA_LEN)
+        .map(|i| Sha256::digest(i.to_string().as_bytes()).to_vec())
+        .collect()
+}
+
+fn measure(name: &str, mut f: impl FnMut()) {
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f();
+        total += start.elapsed();
+    }
+    println!(
+        "{:<40} {:>10.3} ms",
+        name,
+        (total / ROUNDS).as_secs_f64() * 1000.0
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let keys = get_keys();
+
+    // StateWriter keeps HashMap and sorts the keys on commit
+    measure("StateWriter cache", || {
+        let mut writer = StateWriter::default();
+        for key in keys.iter() {
+            writer.cache_existing(&SharedKVPair::new(key, key));
+        }
+    });
+    let mut writer = StateWriter::default();
+    for key in keys.iter() {
+        writer.cache_existing(&SharedKVPair::new(key, key));
+        writer.update(&KVPair::new(key, &key[..16]))?;
+    }
+    measure("StateWriter snapshot and restore", || {
+        let index = writer.snapshot();
+        writer.restore_snapshot(index).unwrap();
+    });
+    measure("StateWriter commit (sorted)", || {
+        let mut write_batch = PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::STATE);
+        writer.commit(&mut write_batch);
+    });
+
+    // 8 readers of the cached keys and 1 writer, as the async tasks of the block execution
+    let keys = Arc::new(keys);
+    let mutex_writer = Arc::new(Mutex::new(writer.clone()));
+    measure("StateWriter Mutex 8 readers 1 writer", || {
+        let readers: Vec<thread::JoinHandle<()>> = (0..READERS)
+            .map(|r| {
+                let keys = Arc::clone(&keys);
+                let writer = Arc::clone(&mutex_writer);
+                thread::spawn(move || {
+                    for i in 0..CONCURRENT_READS {
+                        let key = &keys[(r * CONCURRENT_READS + i) % DATA_LEN];
+                        let mut writer = writer.lock().unwrap();
+                        read_writer_db::get_with_writer(&mut writer, key, None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for key in keys.iter().take(CONCURRENT_READS / READERS) {
+            let mut writer = mutex_writer.lock().unwrap();
+            writer.update(&KVPair::new(key, &key[..8])).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    });
+    let rw_lock_writer = Arc::new(RwLock::new(writer.clone()));
+    measure("StateWriter RwLock 8 readers 1 writer", || {
+        let readers: Vec<thread::JoinHandle<()>> = (0..READERS)
+            .map(|r| {
+                let keys = Arc::clone(&keys);
+                let writer = Arc::clone(&rw_lock_writer);
+                thread::spawn(move || {
+                    for i in 0..CONCURRENT_READS {
+                        let key = &keys[(r * CONCURRENT_READS + i) % DATA_LEN];
+                        read_writer_db::get_with_locked_writer(&writer, key, None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for key in keys.iter().take(CONCURRENT_READS / READERS) {
+            let mut writer = rw_lock_writer.write().unwrap();
+            writer.update(&KVPair::new(key, &key[..8])).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    });
+
+    // baseline of the alternative which keeps the cache in BTreeMap
+    measure("HashMap insert", || {
+        let mut map = HashMap::new();
+        for key in keys.iter() {
+            map.insert(key.clone(), key.clone());
+        }
+    });
+    measure("BTreeMap insert", || {
+        let mut map = BTreeMap::new();
+        for key in keys.iter() {
+            map.insert(key.clone(), key.clone());
+        }
+    });
+    let hash_map: HashMap<Vec<u8>, Vec<u8>> =
+        keys.iter().map(|k| (k.clone(), k.clone())).collect();
+    let btree_map: BTreeMap<Vec<u8>, Vec<u8>> =
+        keys.iter().map(|k| (k.clone(), k.clone())).collect();
+    measure("HashMap clone", || {
+        assert_eq!(hash_map.clone().len(), DATA_LEN);
+    });
+    measure("BTreeMap clone", || {
+        assert_eq!(btree_map.clone().len(), DATA_LEN);
+    });
+    measure("HashMap sorted iteration", || {
+        let mut sorted: Vec<&Vec<u8>> = hash_map.keys().collect();
+        sorted.sort_unstable();
+        assert_eq!(sorted.len(), DATA_LEN);
+    });
+    measure("BTreeMap iteration", || {
+        assert_eq!(btree_map.keys().count(), DATA_LEN);
+    });
+
+    println!("Benchmarking successfully completed");
+
+    Ok(())
+}
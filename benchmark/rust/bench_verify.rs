@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use lisk_db::sparse_merkle_tree::smt::{SparseMerkleTree, UpdateData};
+use lisk_db::sparse_merkle_tree::smt_db::InMemorySmtDB;
+use lisk_db::types::{Cache, KeyLength, NestedVec, SharedKVPair};
+
+const ROUNDS: u32 = 5;
+const DATA_LEN: usize = 100_000;
+const QUERY_LENS: [usize; 4] = [1, 64, 512, 4096];
+
+fn hash(prefix: &str, i: usize) -> Vec<u8> {
+    Sha256::digest(format!("{}{}", prefix, i).as_bytes()).to_vec()
+}
+
+fn measure(name: &str, queries: usize, mut f: impl FnMut()) {
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f();
+        total += start.elapsed();
+    }
+    let average = total / ROUNDS;
+    // time per query stays flat when the verification is O(n log n)
+    println!(
+        "{:<40} {:>10.3} ms {:>10.3} us/query",
+        name,
+        average.as_secs_f64() * 1000.0,
+        average.as_secs_f64() * 1_000_000.0 / queries as f64
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut data = UpdateData::new_from(Cache::new());
+    for i in 0..DATA_LEN {
+        data.insert(SharedKVPair(&hash("key", i), &hash("value", i)));
+    }
+    let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+    let mut db = InMemorySmtDB::default();
+    let root = tree.commit(&mut db, &data)?;
+    let root = root.lock().unwrap().to_vec();
+
+    for query_len in QUERY_LENS {
+        // half of the queries are included and the other half are not
+        let query_keys: NestedVec = (0..query_len)
+            .map(|i| {
+                if i % 2 == 0 {
+                    hash("key", i)
+                } else {
+                    hash("missing", i)
+                }
+            })
+            .collect();
+        let proof = tree.prove(&mut db, &query_keys)?;
+        measure(&format!("verify {} queries", query_len), query_len, || {
+            assert!(SparseMerkleTree::verify(&query_keys, &proof, &root, KeyLength(32)).unwrap());
+        });
+    }
+
+    println!("Benchmarking successfully completed");
+
+    Ok(())
+}
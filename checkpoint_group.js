@@ -0,0 +1,43 @@
+/*
+ * Copyright © 2022 Lisk Foundation
+ *
+ * See the LICENSE file at the top-level directory of this distribution
+ * for licensing information.
+ *
+ * Unless otherwise agreed in a custom licensing agreement with the Lisk Foundation,
+ * no part of this software, including this file, may be copied, modified,
+ * propagated, or distributed except according to the terms contained in the
+ * LICENSE file.
+ *
+ * Removal or modification of this copyright notice is prohibited.
+ */
+'use strict';
+
+const {
+    checkpoint_group_new,
+    checkpoint_group_verify,
+} = require("./bin-package/index.node");
+
+// CheckpointGroup coordinates the checkpoints of the blockchain database and the state database.
+// The manifest is written at manifestPath when both checkpoints finished.
+class CheckpointGroup {
+    constructor(manifestPath) {
+        this._token = checkpoint_group_new(manifestPath);
+    }
+
+    // verify validates the restored pair of the checkpoints with the manifest
+    static async verify(manifestPath) {
+        return new Promise((resolve, reject) => {
+            checkpoint_group_verify(manifestPath, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+}
+
+module.exports = {
+    CheckpointGroup,
+};
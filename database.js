@@ -19,14 +19,36 @@ const {
     db_close,
     db_get,
     db_exists,
+    db_exists_prefix,
     db_set,
     db_del,
     db_write,
+    db_write_prepared,
+    db_conditional_write,
+    db_recover_staged,
     db_iterate,
     db_checkpoint,
+    db_checkpoint_with_group,
+    db_compact,
+    db_flush,
+    db_delete_range,
+    db_stats,
+    db_approximate_size,
+    db_count_range,
+    db_pending_operations,
+    db_get_metrics,
+    db_with_prefix,
+    prefixed_db_get,
+    prefixed_db_exists,
+    prefixed_db_set,
+    prefixed_db_del,
+    prefixed_db_write,
+    prefixed_db_iterate,
     batch_new,
     batch_set,
     batch_del,
+    batch_reset,
+    batch_close,
     in_memory_db_new,
     in_memory_db_clone,
     in_memory_db_get,
@@ -35,6 +57,10 @@ const {
     in_memory_db_clear,
     in_memory_db_write,
     in_memory_db_iterate,
+    in_memory_db_snapshot,
+    in_memory_db_restore_snapshot,
+    in_memory_db_snapshot_to_file,
+    in_memory_db_load_from_file,
 } = require("./bin-package/index.node");
 const { Readable } = require('stream');
 const { NotFoundError } = require('./error');
@@ -50,7 +76,7 @@ class Reader {
         return new Promise((resolve, reject) => {
             db_get.call(this._db, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Specified key ${key.toString('hex')} does not exist`));
                     }
                     return reject(err);
@@ -71,6 +97,18 @@ class Reader {
         });
     }
 
+    // existsPrefix returns true if any key starts with the prefix, using the prefix filters if configured
+    async existsPrefix(prefix) {
+        return new Promise((resolve, reject) => {
+            db_exists_prefix.call(this._db, prefix, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
     iterate(options = {}) {
         return new Iterator(this._db, db_iterate, getOptionsWithDefault(options));
     }
@@ -80,6 +118,78 @@ class Reader {
     }
 }
 
+class PrefixedDatabase {
+    constructor(db) {
+        this._db = db;
+    }
+
+    async get(key) {
+        return new Promise((resolve, reject) => {
+            prefixed_db_get.call(this._db, key, (err, result) => {
+                if (err) {
+                    if (err.code === 'ERR_NOT_FOUND') {
+                        return reject(new NotFoundError(`Specified key ${key.toString('hex')} does not exist`));
+                    }
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async has(key) {
+        return new Promise((resolve, reject) => {
+            prefixed_db_exists.call(this._db, key, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async set(key, value) {
+        return new Promise((resolve, reject) => {
+            prefixed_db_set.call(this._db, key, value, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async del(key) {
+        return new Promise((resolve, reject) => {
+            prefixed_db_del.call(this._db, key, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async write(batch) {
+        return new Promise((resolve, reject) => {
+            prefixed_db_write.call(this._db, batch.inner, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    iterate(options = {}) {
+        return new Iterator(this._db, prefixed_db_iterate, getOptionsWithDefault(options));
+    }
+
+    createReadStream(options = {}) {
+        return new Iterator(this._db, prefixed_db_iterate, getOptionsWithDefault(options));
+    }
+}
+
 class Database {
     constructor(path, opts = {}) {
         this._db = db_new(path, opts);
@@ -89,7 +199,7 @@ class Database {
         return new Promise((resolve, reject) => {
             db_get.call(this._db, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Specified key ${key.toString('hex')} does not exist`));
                     }
                     return reject(err);
@@ -132,9 +242,22 @@ class Database {
         });
     }
 
-    async write(batch) {
+    async write(batch, options = {}) {
+        return new Promise((resolve, reject) => {
+            db_write.call(this._db, batch.inner, options, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // conditionalWrite writes the batch atomically only if every condition holds, otherwise it rejects with ERR_CONDITION_FAILED.
+    // No other write of this database is applied between the check and the write
+    async conditionalWrite(conditions, batch) {
         return new Promise((resolve, reject) => {
-            db_write.call(this._db, batch.inner, err => {
+            db_conditional_write.call(this._db, conditions, batch.inner, err => {
                 if (err) {
                     return reject(err);
                 }
@@ -143,6 +266,31 @@ class Database {
         });
     }
 
+    // writePrepared stages the batch under the id without applying it. The id is passed to StateDB commit as stagedId
+    async writePrepared(id, batch) {
+        return new Promise((resolve, reject) => {
+            db_write_prepared.call(this._db, id, batch.inner, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // recoverStaged applies the staged batches committed by the stateDB, and discards the others.
+    // It must not be called while a commit with stagedId is in progress
+    async recoverStaged(stateDB) {
+        return new Promise((resolve, reject) => {
+            db_recover_staged.call(this._db, stateDB._db, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
     iterate(options = {}) {
         return new Iterator(this._db, db_iterate, getOptionsWithDefault(options));
     }
@@ -152,7 +300,7 @@ class Database {
     }
 
     async clear(options = {}) {
-        if (options.gte && options.lte) {
+        if ((options.gte && options.lte) || options.prefix) {
             const stream = this.createReadStream(getOptionsWithDefault(options));
             const batch = new Batch();
             await new Promise((resolve, reject) => {
@@ -185,13 +333,59 @@ class Database {
         return new Reader(this._db);
     }
 
-    close() {
-        db_close.call(this._db);
+    // withPrefix returns the handle where the keys are prefixed, sharing the connection of the database
+    withPrefix(prefix) {
+        return new PrefixedDatabase(db_with_prefix.call(this._db, prefix));
     }
 
-    async checkpoint(path) {
+    // close resolves after the connection is released, so that the files of the database are no longer held
+    async close() {
+        await new Promise((resolve, reject) => {
+            db_close.call(this._db, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // checkpoint creates the blockchain checkpoint at the path. The files and the directory entries of the checkpoint
+    // are fsynced before it resolves unless sync is false, so that the checkpoint survives a power loss
+    async checkpoint(path, options = {}) {
+        const defaultOptions = {
+            sync: options.sync !== undefined ? options.sync : true,
+            flushBeforeCheckpoint: options.flushBeforeCheckpoint !== undefined ? options.flushBeforeCheckpoint : false,
+        };
+        return new Promise((resolve, reject) => {
+            db_checkpoint.call(this._db, path, defaultOptions, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // checkpointWithGroup creates the blockchain checkpoint of the group
+    async checkpointWithGroup(group, path, options = {}) {
+        const defaultOptions = {
+            sync: options.sync !== undefined ? options.sync : true,
+            flushBeforeCheckpoint: options.flushBeforeCheckpoint !== undefined ? options.flushBeforeCheckpoint : false,
+        };
+        return new Promise((resolve, reject) => {
+            db_checkpoint_with_group.call(this._db, group._token, path, defaultOptions, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async compact(start, end) {
         return new Promise((resolve, reject) => {
-            db_checkpoint.call(this._db, path, err => {
+            db_compact.call(this._db, start, end, err => {
                 if (err) {
                     return reject(err);
                 }
@@ -199,15 +393,82 @@ class Database {
             });
         });
     }
+
+    async flush() {
+        return new Promise((resolve, reject) => {
+            db_flush.call(this._db, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async deleteRange(start, end) {
+        return new Promise((resolve, reject) => {
+            db_delete_range.call(this._db, start, end, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async stats() {
+        return new Promise((resolve, reject) => {
+            db_stats.call(this._db, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // approximateSize estimates the bytes of the range in the SST files and the memtables
+    async approximateSize(start, end) {
+        return new Promise((resolve, reject) => {
+            db_approximate_size.call(this._db, start, end, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // countRange counts the keys in the range, and stops counting at the limit
+    async countRange(options = {}) {
+        return new Promise((resolve, reject) => {
+            db_count_range.call(this._db, getOptionsWithDefault(options), (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // pendingOperations returns the number of the operations queued on the database thread
+    pendingOperations() {
+        return db_pending_operations.call(this._db);
+    }
+
+    // getMetrics returns the latency histograms of the operations, or null if metrics are disabled
+    getMetrics(reset = false) {
+        return db_get_metrics.call(this._db, reset);
+    }
 }
 
 class InMemoryIterator extends Readable {
-    constructor(db, options) {
+    constructor(db, iterateFunc, options) {
         super();
         this._db = db;
         this._options = options;
         Readable.call(this, { objectMode: true });
-        in_memory_db_iterate.call(
+        iterateFunc.call(
             this._db,
             this._options,
             (err, results) => {
@@ -236,12 +497,21 @@ class Batch {
         return this._batch;
     }
 
-    set(key, value) {
-        batch_set.call(this._batch, key, value);
+    set(key, value, kind) {
+        batch_set.call(this._batch, key, value, kind);
+    }
+
+    del(key, kind) {
+        batch_del.call(this._batch, key, kind);
+    }
+
+    // reset clears the batch written by Database for the reuse
+    reset() {
+        batch_reset.call(this._batch);
     }
 
-    del(key) {
-        batch_del.call(this._batch, key);
+    close() {
+        batch_close.call(this._batch);
     }
 }
 
@@ -256,7 +526,7 @@ class InMemoryDatabase {
         return new Promise((resolve, reject) => {
             in_memory_db_get.call(this._db, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Specified key ${key.toString('hex')} does not exist`));
                     }
                     return reject(err);
@@ -270,7 +540,7 @@ class InMemoryDatabase {
         return new Promise((resolve, reject) => {
             in_memory_db_get.call(this._db, key, (err) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return resolve(false);
                     }
                     return reject(err);
@@ -306,11 +576,11 @@ class InMemoryDatabase {
     }
 
     iterate(options = {}) {
-        return new InMemoryIterator(this._db, getOptionsWithDefault(options));
+        return new InMemoryIterator(this._db, in_memory_db_iterate, getOptionsWithDefault(options));
     }
 
     createReadStream(options = {}) {
-        return new InMemoryIterator(this._db, getOptionsWithDefault(options));
+        return new InMemoryIterator(this._db, in_memory_db_iterate, getOptionsWithDefault(options));
     }
 
     async clear(options = {}) {
@@ -325,10 +595,42 @@ class InMemoryDatabase {
         const cloned = in_memory_db_clone.call(this._db);
         return new InMemoryDatabase(cloned);
     }
+
+    snapshot() {
+        return in_memory_db_snapshot.call(this._db);
+    }
+
+    restoreSnapshot(index = 0) {
+        in_memory_db_restore_snapshot.call(this._db, index);
+    }
+
+    // snapshotToFile writes the snapshot to the file. It is flushed to the disk with { sync: true }.
+    async snapshotToFile(path, options = {}) {
+        return new Promise((resolve, reject) => {
+            in_memory_db_snapshot_to_file.call(this._db, path, { sync: !!options.sync }, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    static async loadFromFile(path) {
+        return new Promise((resolve, reject) => {
+            in_memory_db_load_from_file(path, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(new InMemoryDatabase(result));
+            });
+        });
+    }
 }
 
 module.exports = {
     Database,
     InMemoryDatabase,
+    InMemoryIterator,
     Batch,
 };
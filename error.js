@@ -14,6 +14,10 @@
 'use strict';
 
 class NotFoundError extends Error {
+    constructor(message) {
+        super(message);
+        this.code = 'ERR_NOT_FOUND';
+    }
 }
 
 module.exports = {
@@ -22,6 +22,8 @@ class Iterator extends Readable {
         this._iterateFunc = iterateFunc;
         this._options = options;
         this.queue = []
+        // truncated is set at the end if the iteration is stopped by maxIterationResultSize
+        this.truncated = false;
         Readable.call(this, { objectMode: true });
         this._iterateFunc.call(
             this._db,
@@ -33,7 +35,12 @@ class Iterator extends Readable {
                 }
                 this.push(val);
             },
-            () => {
+            (err, truncated) => {
+                if (err) {
+                    this.emit('error', err);
+                    return;
+                }
+                this.truncated = !!truncated;
                 this.push(null);
             },
         );
@@ -13,16 +13,48 @@
  */
 'use strict';
 
+const {
+    shutdown_all,
+    key_encode,
+    key_decode,
+    key_constants,
+} = require("./bin-package/index.node");
 const { Batch, Database, InMemoryDatabase } = require('./database');
-const { StateDB } = require('./state_db');
+const { StateDB, InMemoryStateDB } = require('./state_db');
 const { NotFoundError } = require('./error');
 const { SparseMerkleTree } = require('./sparse_merkle_tree');
+const { CheckpointGroup } = require('./checkpoint_group');
+
+const shutdownAll = async (options = {}) => new Promise((resolve, reject) => {
+    shutdown_all(options, (err, result) => {
+        if (err) {
+            reject(err);
+            return;
+        }
+        resolve(result);
+    });
+});
+
+// keyEncode returns the key of the module store in the keyspace of the kind, which is "state" or "raw"
+const keyEncode = (kind, moduleId, storePrefix, key) => key_encode(kind, moduleId, storePrefix, key);
+
+// keyDecode splits the key in the keyspace of the kind into { moduleId, storePrefix, key }
+const keyDecode = (kind, fullKey) => key_decode(kind, fullKey);
+
+// constants returns the prefixes of the keyspaces and the lengths of the parts of the keys with the default key length
+const constants = () => key_constants();
 
 module.exports = {
     Database,
     InMemoryDatabase,
     Batch,
     StateDB,
+    InMemoryStateDB,
     NotFoundError,
     SparseMerkleTree,
+    CheckpointGroup,
+    shutdownAll,
+    keyEncode,
+    keyDecode,
+    constants,
 };
\ No newline at end of file
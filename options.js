@@ -18,6 +18,12 @@ const getOptionsWithDefault = options => ({
     reverse: options.reverse !== undefined ? options.reverse : false,
     gte: options.gte !== undefined ? options.gte : undefined,
     lte: options.lte !== undefined ? options.lte : undefined,
+    prefix: options.prefix !== undefined ? options.prefix : undefined,
+    chunkSize: options.chunkSize !== undefined ? options.chunkSize : undefined,
+    keysOnly: options.keysOnly !== undefined ? options.keysOnly : false,
+    inventory: options.inventory !== undefined ? options.inventory : false,
+    minValueSize: options.minValueSize !== undefined ? options.minValueSize : undefined,
+    zeroCopy: options.zeroCopy !== undefined ? options.zeroCopy : false,
 });
 
 module.exports = {
@@ -17,8 +17,13 @@ const {
     in_memory_smt_update,
     in_memory_smt_prove,
     in_memory_smt_verify,
+    in_memory_smt_verify_batch,
     in_memory_smt_calculate_root,
+    in_memory_smt_calculate_root_from_data,
     in_memory_smt_remove_keys_from_proof,
+    in_memory_smt_export_nodes,
+    in_memory_smt_import_nodes,
+    in_memory_smt_run_vectors,
 } = require("./bin-package/index.node");
 const { isInclusionProofForQueryKey } = require('./utils');
 
@@ -30,9 +35,10 @@ const copyBuffer = h => {
     return copied;
 }
 class SparseMerkleTree {
-    constructor(keyLength = DEFAULT_KEY_LENGTH) {
+    // opts.hasher is the name of the hasher of the tree. sha256 is used by default
+    constructor(keyLength = DEFAULT_KEY_LENGTH, opts = {}) {
         this._keyLength = keyLength;
-        this._inner = in_memory_smt_new(keyLength);
+        this._inner = in_memory_smt_new(keyLength, opts.hasher);
     }
 
     async update(root, kvpairs) {
@@ -71,7 +77,20 @@ class SparseMerkleTree {
 
     async verify(root, queries, proof) {
         return new Promise((resolve, reject) => {
-            in_memory_smt_verify.call(null, root, queries, proof, this._keyLength, (err, result) => {
+            in_memory_smt_verify.call(this._inner, root, queries, proof, this._keyLength, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // verifyBatch verifies the entries of { root, proof, queryKeys } in a single call, and resolves with the results in the order.
+    // Malformed entry results in false without rejecting the others
+    async verifyBatch(entries) {
+        return new Promise((resolve, reject) => {
+            in_memory_smt_verify_batch.call(this._inner, entries, this._keyLength, (err, result) => {
                 if (err) {
                     return reject(err);
                 }
@@ -106,7 +125,45 @@ class SparseMerkleTree {
 
     async calculateRoot(proof) {
         return new Promise((resolve, reject) => {
-            in_memory_smt_calculate_root.call(null, proof, (err, result) => {
+            in_memory_smt_calculate_root.call(this._inner, proof, (err, result) => {
+                if (err) {
+                    reject(err);
+                    return;
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async calculateRootFromData(kvpairs) {
+        return new Promise((resolve, reject) => {
+            in_memory_smt_calculate_root_from_data.call(this._inner, kvpairs, (err, result) => {
+                if (err) {
+                    reject(err);
+                    return;
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // exportNodes returns the nodes of the tree of the root in depth-first order, children first
+    async exportNodes(root) {
+        return new Promise((resolve, reject) => {
+            in_memory_smt_export_nodes.call(this._inner, root, (err, result) => {
+                if (err) {
+                    reject(err);
+                    return;
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // importNodes rebuilds the tree from the exported nodes, and resolves with the root if all the nodes match
+    async importNodes(nodes) {
+        return new Promise((resolve, reject) => {
+            in_memory_smt_import_nodes.call(this._inner, nodes, (err, result) => {
                 if (err) {
                     reject(err);
                     return;
@@ -116,9 +173,24 @@ class SparseMerkleTree {
         });
     }
 
+    // runVectors computes the output of the test vectors in the schema of the lisk-tree fixtures.
+    // input is the object or its JSON string, and it resolves with the object of the vectors with the output
+    static async runVectors(input) {
+        const inputJSON = typeof input === 'string' ? input : JSON.stringify(input);
+        return new Promise((resolve, reject) => {
+            in_memory_smt_run_vectors(inputJSON, (err, result) => {
+                if (err) {
+                    reject(err);
+                    return;
+                }
+                resolve(JSON.parse(result));
+            });
+        });
+    }
+
     async removeKeysFromProof(proof, removedKeys) {
         return new Promise((resolve, reject) => {
-            in_memory_smt_remove_keys_from_proof.call(null, proof, removedKeys, (err, result) => {
+            in_memory_smt_remove_keys_from_proof.call(this._inner, proof, removedKeys, (err, result) => {
                 if (err) {
                     reject(err);
                     return;
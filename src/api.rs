@@ -0,0 +1,17 @@
+/// api exposes the plain Rust types to use the state storage without neon.
+/// Build with `default-features = false, features = ["rust-api"]` to leave out the JS bindings,
+/// and use StateStore to commit the StateWriter to the rocksdb at the path.
+pub use crate::database::traits::Actions;
+pub use crate::database::types::DbOptions;
+pub use crate::diff::Diff;
+pub use crate::sparse_merkle_tree::smt::{
+    Proof, QueryProof, SMTError, SparseMerkleTree as SMT, UpdateData, EMPTY_HASH,
+};
+pub use crate::sparse_merkle_tree::smt_db::{InMemorySmtDB, SmtDB};
+pub use crate::state::current_state::{CurrentState, CurrentStateError};
+pub use crate::state::in_memory_state_db::InMemoryStateDB;
+pub use crate::state::migrations::MigrationError;
+pub use crate::state::spill::SpillError;
+pub use crate::state::state_writer::{StateWriter, StateWriterError};
+pub use crate::state::store::{DataStoreError, HistoricalState, StateStore};
+pub use crate::types::{BlockHeight, Cache, HashKind, HashWithKind, KVPair, KeyLength};
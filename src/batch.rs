@@ -1,21 +1,119 @@
 /// batch provides a batch feature for Database.
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "node")]
 use neon::prelude::*;
+#[cfg(feature = "node")]
 use neon::types::buffer::TypedArray;
-
-use crate::database::traits::{DatabaseKind, JsNewWithArcMutex, NewDBWithKeyLength};
-use crate::database::types::{JsArcMutex, Kind as DBKind};
-use crate::types::{KVPair, KeyLength};
-
+use thiserror::Error;
+
+use crate::consts::Prefix;
+use crate::database::registry::{Registration, REGISTRY};
+#[cfg(feature = "node")]
+use crate::database::traits::JsNewWithArcMutex;
+use crate::database::traits::{Closable, DatabaseKind, NewDBWithKeyLength};
+#[cfg(feature = "node")]
+use crate::database::types::JsArcMutex;
+use crate::database::types::Kind as DBKind;
+#[cfg(feature = "node")]
+use crate::database::types::SizeLimitError;
+#[cfg(feature = "node")]
+use crate::error::{self, DbError};
+use crate::types::{KeyLength, SharedKVPair};
+
+#[cfg(feature = "node")]
 pub type SendableWriteBatch = JsArcMutex<WriteBatch>;
 
+/// BatchWriter receives the borrowed pairs, so that the caller does not copy the data only to pass it.
 pub trait BatchWriter {
-    fn put(&mut self, pair: &KVPair);
+    fn put(&mut self, pair: &SharedKVPair);
     fn delete(&mut self, key: &[u8]);
 }
 
+/// BatchKind is the keyspace of the operation in the batch.
+/// Operations of different kinds in the same batch are written atomically.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchKind {
+    State,
+    Diff,
+    Smt,
+    /// Raw writes the key as it is.
+    Raw,
+}
+
+impl BatchKind {
+    /// new returns the kind of the name, or None if the name is unknown.
+    pub fn new(name: &str) -> Option<Self> {
+        match name {
+            "state" => Some(BatchKind::State),
+            "diff" => Some(BatchKind::Diff),
+            "smt" => Some(BatchKind::Smt),
+            "raw" => Some(BatchKind::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn prefix(self) -> &'static [u8] {
+        match self {
+            BatchKind::State => Prefix::STATE,
+            BatchKind::Diff => Prefix::DIFF,
+            BatchKind::Smt => Prefix::SMT,
+            BatchKind::Raw => &[],
+        }
+    }
+
+    pub fn key(self, key: &[u8]) -> Vec<u8> {
+        [self.prefix(), key].concat()
+    }
+
+    /// get_kind returns the kind of the argument at the index. It is Raw when the argument is not given, undefined or null.
+    #[cfg(feature = "node")]
+    fn get_kind(ctx: &mut FunctionContext, index: i32) -> NeonResult<Self> {
+        let value = match ctx.argument_opt(index) {
+            Some(value) => value,
+            None => return Ok(BatchKind::Raw),
+        };
+        if value.is_a::<JsUndefined, _>(ctx) || value.is_a::<JsNull, _>(ctx) {
+            return Ok(BatchKind::Raw);
+        }
+        let name = value.downcast_or_throw::<JsString, _>(ctx)?.value(ctx);
+        match BatchKind::new(&name) {
+            Some(kind) => Ok(kind),
+            None => {
+                error::invalid_argument("kind must be one of state, diff, smt or raw").throw(ctx)
+            },
+        }
+    }
+}
+
+/// BatchConsumed is the error of using the batch which is written by Database, or is being written.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("WriteBatch is consumed by a write")]
+pub struct BatchConsumed;
+
+/// BatchState is the progress of the write of the batch by Database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BatchState {
+    Open,
+    Writing,
+    /// Consumed is the batch written successfully. It cannot be updated or written until it is reset.
+    Consumed,
+}
+
 /// WriteBatch is a container for rocksdb::WriteBatch
 pub struct WriteBatch {
     pub batch: rocksdb::WriteBatch,
+    registration: Registration,
+    state: BatchState,
+}
+
+/// BatchClaim is the write of the batch in progress, which rejects the updates and the other writes of the batch.
+/// The batch is consumed when the write succeeds. It is released for the retry when the write fails,
+/// or when the claim is dropped without finishing.
+pub struct BatchClaim {
+    batch: Arc<Mutex<WriteBatch>>,
+    finished: bool,
 }
 
 /// PrefixWriteBatch updates rocksdb batch with defined prefix.
@@ -48,6 +146,8 @@ impl NewDBWithKeyLength for WriteBatch {
     fn new_db_with_key_length(_: Option<KeyLength>) -> Self {
         Self {
             batch: rocksdb::WriteBatch::default(),
+            registration: REGISTRY.register(Self::NAME, None),
+            state: BatchState::Open,
         }
     }
 }
@@ -58,12 +158,95 @@ impl DatabaseKind for WriteBatch {
     }
 }
 
+#[cfg(feature = "node")]
 impl JsNewWithArcMutex for WriteBatch {}
-impl Finalize for WriteBatch {}
+#[cfg(feature = "node")]
+impl Finalize for WriteBatch {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        self.mark_closed();
+    }
+}
+
+impl Closable for WriteBatch {
+    const NAME: &'static str = "WriteBatch";
+
+    fn closed(&self) -> &AtomicBool {
+        self.registration.closed()
+    }
+}
+
+impl WriteBatch {
+    /// check_not_consumed returns an error if the batch is being written or is already written.
+    pub fn check_not_consumed(&self) -> Result<(), BatchConsumed> {
+        match self.state {
+            BatchState::Open => Ok(()),
+            BatchState::Writing | BatchState::Consumed => Err(BatchConsumed),
+        }
+    }
+
+    /// reset clears the operations and the consumed state, so that the batch can be reused.
+    /// The batch being written cannot be reset.
+    pub fn reset(&mut self) -> Result<(), BatchConsumed> {
+        if self.state == BatchState::Writing {
+            return Err(BatchConsumed);
+        }
+        self.batch.clear();
+        self.state = BatchState::Open;
+        Ok(())
+    }
+}
+
+impl BatchClaim {
+    /// new claims the batch for the write, and rejects the batch which is being written or is already written.
+    pub fn new(batch: &Arc<Mutex<WriteBatch>>) -> Result<Self, BatchConsumed> {
+        let mut inner = batch.lock().unwrap();
+        inner.check_not_consumed()?;
+        inner.state = BatchState::Writing;
+        Ok(Self {
+            batch: Arc::clone(batch),
+            finished: false,
+        })
+    }
+
+    /// finish consumes the batch if the write succeeded, and releases it otherwise.
+    pub fn finish(mut self, written: bool) {
+        self.finished = true;
+        self.batch.lock().unwrap().state = if written {
+            BatchState::Consumed
+        } else {
+            BatchState::Open
+        };
+    }
+}
+
+impl Drop for BatchClaim {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.batch.lock().unwrap().state = BatchState::Open;
+        }
+    }
+}
+
+#[cfg(feature = "node")]
 impl WriteBatch {
+    /// throw_if_consumed throws BatchConsumed error to JS if the batch is being written or is already written.
+    fn throw_if_consumed<'a, C: Context<'a>>(&self, ctx: &mut C) -> NeonResult<()> {
+        self.check_not_consumed()
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
+
+    /// js_set is handler for JS ffi.
+    /// js "this" - WriteBatch.
+    /// - @params(0) - key to set. It must not be empty, and the sizes are checked with the limits of the database on write.
+    /// - @params(1) - value to set.
+    /// - @params(2) - kind of the key "state" | "diff" | "smt" | "raw". Default is "raw".
     pub fn js_set(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
         let value = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let kind = BatchKind::get_kind(&mut ctx, 2)?;
+        if key.is_empty() {
+            return DbError::from(SizeLimitError::EmptyKey).throw(&mut ctx);
+        }
 
         // Get the `this` value as a `JsBox<Database>`
         let batch = ctx
@@ -72,14 +255,24 @@ impl WriteBatch {
 
         let batch = batch.borrow();
         let mut inner_batch = batch.lock().unwrap();
+        inner_batch.throw_if_closed(&mut ctx)?;
+        inner_batch.throw_if_consumed(&mut ctx)?;
 
-        inner_batch.batch.put(key, value);
+        inner_batch.batch.put(kind.key(&key), value);
 
         Ok(ctx.undefined())
     }
 
+    /// js_del is handler for JS ffi.
+    /// js "this" - WriteBatch.
+    /// - @params(0) - key to delete. It must not be empty.
+    /// - @params(1) - kind of the key "state" | "diff" | "smt" | "raw". Default is "raw".
     pub fn js_del(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let kind = BatchKind::get_kind(&mut ctx, 1)?;
+        if key.is_empty() {
+            return DbError::from(SizeLimitError::EmptyKey).throw(&mut ctx);
+        }
         // Get the `this` value as a `JsBox<Database>`
         let batch = ctx
             .this()
@@ -87,15 +280,52 @@ impl WriteBatch {
 
         let batch = batch.borrow();
         let mut inner_batch = batch.lock().unwrap();
+        inner_batch.throw_if_closed(&mut ctx)?;
+        inner_batch.throw_if_consumed(&mut ctx)?;
 
-        inner_batch.batch.delete(key);
+        inner_batch.batch.delete(kind.key(&key));
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_reset is handler for JS ffi.
+    /// js "this" - WriteBatch.
+    /// It clears the operations and the consumed state, so that the batch written by Database can be reused.
+    /// It throws ERR_BATCH_CONSUMED while the batch is being written.
+    pub fn js_reset(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let batch = ctx
+            .this()
+            .downcast_or_throw::<SendableWriteBatch, _>(&mut ctx)?;
+
+        let batch = batch.borrow();
+        let mut inner_batch = batch.lock().unwrap();
+        inner_batch.throw_if_closed(&mut ctx)?;
+        inner_batch
+            .reset()
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_close is handler for JS ffi.
+    /// js "this" - WriteBatch.
+    /// It clears the batch, and the batch cannot be used afterwards.
+    pub fn js_close(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let batch = ctx
+            .this()
+            .downcast_or_throw::<SendableWriteBatch, _>(&mut ctx)?;
+
+        let batch = batch.borrow();
+        let mut inner_batch = batch.lock().unwrap();
+        inner_batch.batch.clear();
+        inner_batch.mark_closed();
 
         Ok(ctx.undefined())
     }
 }
 
 impl<'a> BatchWriter for PrefixWriteBatch<'a> {
-    fn put(&mut self, pair: &KVPair) {
+    fn put(&mut self, pair: &SharedKVPair) {
         self.batch
             .put([self.prefix.unwrap(), pair.key()].concat(), pair.value());
     }
@@ -164,6 +394,83 @@ mod tests {
         assert_eq!(write_batch.batch.len(), 2);
     }
 
+    #[test]
+    fn test_closed_write_batch() {
+        let write_batch = WriteBatch::new_db_with_key_length(None);
+        assert_eq!(write_batch.check_open(), Ok(()));
+
+        write_batch.mark_closed();
+        assert_eq!(
+            write_batch.check_open().unwrap_err().to_string(),
+            "WriteBatch is closed"
+        );
+        // clone is a new batch which is not closed
+        assert_eq!(write_batch.clone().check_open(), Ok(()));
+    }
+
+    #[test]
+    fn test_batch_claim() {
+        let batch = Arc::new(Mutex::new(WriteBatch::new_db_with_key_length(None)));
+        batch.lock().unwrap().batch.put([1], [2]);
+
+        // failed write leaves the batch for the retry
+        let claim = BatchClaim::new(&batch).unwrap();
+        assert_eq!(
+            batch.lock().unwrap().check_not_consumed(),
+            Err(BatchConsumed)
+        );
+        assert!(BatchClaim::new(&batch).is_err());
+        claim.finish(false);
+        assert_eq!(batch.lock().unwrap().check_not_consumed(), Ok(()));
+
+        // claim dropped without the write is released
+        drop(BatchClaim::new(&batch).unwrap());
+        assert_eq!(batch.lock().unwrap().check_not_consumed(), Ok(()));
+
+        let claim = BatchClaim::new(&batch).unwrap();
+        assert_eq!(batch.lock().unwrap().reset(), Err(BatchConsumed));
+        claim.finish(true);
+        assert_eq!(
+            batch.lock().unwrap().check_not_consumed(),
+            Err(BatchConsumed)
+        );
+        assert!(BatchClaim::new(&batch).is_err());
+        // clone is a new batch which is not consumed
+        assert_eq!(batch.lock().unwrap().clone().check_not_consumed(), Ok(()));
+
+        batch.lock().unwrap().reset().unwrap();
+        assert_eq!(batch.lock().unwrap().batch.len(), 0);
+        assert!(BatchClaim::new(&batch).is_ok());
+    }
+
+    #[test]
+    fn test_batch_claim_concurrent() {
+        let batch = Arc::new(Mutex::new(WriteBatch::new_db_with_key_length(None)));
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let batch = Arc::clone(&batch);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    match BatchClaim::new(&batch) {
+                        Ok(claim) => {
+                            claim.finish(true);
+                            true
+                        },
+                        Err(_) => false,
+                    }
+                })
+            })
+            .collect();
+        let claimed = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|claimed| *claimed)
+            .count();
+        assert_eq!(claimed, 1);
+    }
+
     #[test]
     fn test_put_and_delete_for_prefix_write_batch() {
         let mut write_batch = PrefixWriteBatch::default();
@@ -177,6 +484,30 @@ mod tests {
         assert_eq!(write_batch.batch.len(), 2);
     }
 
+    #[test]
+    fn test_batch_kind() {
+        assert_eq!(BatchKind::new("state"), Some(BatchKind::State));
+        assert_eq!(BatchKind::new("diff"), Some(BatchKind::Diff));
+        assert_eq!(BatchKind::new("smt"), Some(BatchKind::Smt));
+        assert_eq!(BatchKind::new("raw"), Some(BatchKind::Raw));
+        assert_eq!(BatchKind::new("State"), None);
+        assert_eq!(BatchKind::new(""), None);
+
+        assert_eq!(
+            BatchKind::State.key(&[7, 7]),
+            [consts::Prefix::STATE, &[7, 7]].concat()
+        );
+        assert_eq!(
+            BatchKind::Diff.key(&[7, 7]),
+            [consts::Prefix::DIFF, &[7, 7]].concat()
+        );
+        assert_eq!(
+            BatchKind::Smt.key(&[7, 7]),
+            [consts::Prefix::SMT, &[7, 7]].concat()
+        );
+        assert_eq!(BatchKind::Raw.key(&[7, 7]), vec![7, 7]);
+    }
+
     #[test]
     fn test_set_prefix() {
         let mut write_batch = PrefixWriteBatch::default();
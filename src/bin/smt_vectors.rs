@@ -0,0 +1,17 @@
+// smt_vectors prints the SMT test vectors of the input file with the output computed.
+// Usage: cargo run --features test-vectors --bin smt_vectors -- <input.json>
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use lisk_db::sparse_merkle_tree::test_vectors;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .ok_or("usage: smt_vectors <input.json>")?;
+    let input = fs::read_to_string(path)?;
+    println!("{}", test_vectors::run_vectors(&input)?);
+
+    Ok(())
+}
@@ -15,6 +15,12 @@ pub enum CodecError {
     NoTermination,
     #[error("Invalid wire type")]
     InvalidWireType,
+    #[error("Unexpected bytes at `{0}`")]
+    UnexpectedBytes(usize),
+    #[error("Unsupported version `{0}`")]
+    UnsupportedVersion(u8),
+    #[error("Referenced value `{0}` is missing")]
+    MissingValue(String),
 }
 
 ///Reader maintains the bytes and the state of read bytes during the decoding.
@@ -69,6 +75,43 @@ fn read_varint(data: &[u8], offset: usize) -> Result<(u32, usize), CodecError> {
     Err(CodecError::NoTermination)
 }
 
+fn write_varint_u64(value: u64) -> Vec<u8> {
+    let mut value = value;
+    let mut result = Vec::with_capacity(MAX_VARINT_LEN);
+    while value > 0x7f {
+        result.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    result.push(value as u8);
+
+    result
+}
+
+/// read_varint_u64 reads the varint up to 64 bits from the given bytes starting from the offset.
+/// it returns the value read as varint and the size it used.
+fn read_varint_u64(data: &[u8], offset: usize) -> Result<(u64, usize), CodecError> {
+    let mut result: u64 = 0;
+    let mut index = offset;
+    let mut shift = 0;
+    while shift < 64 {
+        if index >= data.len() {
+            return Err(CodecError::InvalidBytesLength);
+        }
+        let bit = data[index] as u64;
+        index += 1;
+        if index == offset + MAX_VARINT_LEN && bit > 0x01 {
+            return Err(CodecError::OutOfRange);
+        }
+        result |= (bit & 0x7f_u64) << shift;
+        if (bit & 0x80) == 0 {
+            return Ok((result, index - offset));
+        }
+
+        shift += 7;
+    }
+    Err(CodecError::NoTermination)
+}
+
 fn read_key(val: u32) -> Result<(u32, u32), CodecError> {
     let wire_type = val & 7;
     if wire_type != 0 && wire_type != 2 {
@@ -82,7 +125,7 @@ impl<'a> Reader<'a> {
     fn read_only_bytes(&mut self) -> Result<Vec<u8>, CodecError> {
         let (result, size) = read_varint(self.data, self.index)?;
         self.index += size;
-        if result as usize > self.data.len() {
+        if self.index + result as usize > self.end {
             return Err(CodecError::InvalidBytesLength);
         }
         let decoded = self.data[self.index..self.index + result as usize].to_vec();
@@ -131,6 +174,45 @@ impl<'a> Reader<'a> {
         Ok(result)
     }
 
+    /// read_u32 reads next field as varint.
+    /// When next field does not match, it returns 0.
+    pub fn read_u32(&mut self, field_number: u32) -> Result<u32, CodecError> {
+        let ok = self.check(field_number)?;
+        if !ok {
+            return Ok(0);
+        }
+        let (result, size) = read_varint(self.data, self.index)?;
+        self.index += size;
+
+        Ok(result)
+    }
+
+    /// read_u64 reads next field as varint up to 64 bits.
+    /// When next field does not match, it returns 0.
+    pub fn read_u64(&mut self, field_number: u32) -> Result<u64, CodecError> {
+        let ok = self.check(field_number)?;
+        if !ok {
+            return Ok(0);
+        }
+        let (result, size) = read_varint_u64(self.data, self.index)?;
+        self.index += size;
+
+        Ok(result)
+    }
+
+    /// is_end returns true when all the bytes are read.
+    pub fn is_end(&self) -> bool {
+        self.index >= self.end
+    }
+
+    /// check_end returns UnexpectedBytes error with the index if not all the bytes are read.
+    pub fn check_end(&self) -> Result<(), CodecError> {
+        if !self.is_end() {
+            return Err(CodecError::UnexpectedBytes(self.index));
+        }
+        Ok(())
+    }
+
     /// read_bytes reads next field as bytes.
     /// When next field does not match, it returns empty bytes.
     pub fn read_bytes(&mut self, field_number: u32) -> Result<Vec<u8>, CodecError> {
@@ -171,6 +253,20 @@ impl Writer {
         self.result.extend(value);
     }
 
+    /// write_u32 encodes u32 as varint to the writer with specified field number
+    pub fn write_u32(&mut self, field_number: u32, value: u32) {
+        self.write_key(0, field_number);
+        self.write_varint(value);
+    }
+
+    /// write_u64 encodes u64 as varint to the writer with specified field number
+    pub fn write_u64(&mut self, field_number: u32, value: u64) {
+        self.write_key(0, field_number);
+        let val_bytes = write_varint_u64(value);
+        self.size += val_bytes.len();
+        self.result.extend(val_bytes);
+    }
+
     /// write_bytes encodes slice of bytes slice to the writer with specified field number
     pub fn write_bytes_slice(&mut self, field_number: u32, values: &[Vec<u8>]) {
         if values.is_empty() {
@@ -245,6 +341,76 @@ mod tests {
         assert_eq!(res, vec![]);
     }
 
+    #[test]
+    fn test_reader_read_u32() {
+        let mut writer = Writer::new();
+        writer.write_u32(1, 0);
+        writer.write_u32(2, 300);
+        writer.write_u32(3, u32::MAX);
+        writer.write_bytes(4, &[1, 2, 3]);
+
+        let mut reader = Reader::new(writer.result());
+        assert_eq!(reader.read_u32(1).unwrap(), 0);
+        assert_eq!(reader.read_u32(1).unwrap(), 0);
+        assert_eq!(reader.read_u32(2).unwrap(), 300);
+        assert_eq!(reader.read_u32(3).unwrap(), u32::MAX);
+        assert!(!reader.is_end());
+        assert_eq!(reader.read_bytes(4).unwrap(), vec![1, 2, 3]);
+        assert!(reader.is_end());
+    }
+
+    #[test]
+    fn test_reader_read_u64() {
+        let mut writer = Writer::new();
+        writer.write_u64(1, 300);
+        writer.write_u64(2, u32::MAX as u64 + 1);
+        writer.write_u64(3, u64::MAX);
+        writer.write_u32(4, 300);
+
+        let mut reader = Reader::new(writer.result());
+        assert_eq!(reader.read_u64(2).unwrap(), 0);
+        assert_eq!(reader.read_u64(1).unwrap(), 300);
+        assert_eq!(reader.read_u64(2).unwrap(), u32::MAX as u64 + 1);
+        assert_eq!(reader.read_u64(3).unwrap(), u64::MAX);
+        // varint of u32 is read as u64
+        assert_eq!(reader.read_u64(4).unwrap(), 300);
+        assert!(reader.is_end());
+
+        // 10th byte can only hold the last bit
+        let mut reader = Reader::new(&[
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02,
+        ]);
+        assert!(matches!(reader.read_u64(1), Err(CodecError::OutOfRange)));
+    }
+
+    #[test]
+    fn test_reader_read_bytes_out_of_range() {
+        // field 1 with length 10 but only 2 bytes follow
+        let mut reader = Reader::new(&[0x0a, 0x0a, 1, 2]);
+        assert!(matches!(
+            reader.read_bytes(1),
+            Err(CodecError::InvalidBytesLength)
+        ));
+    }
+
+    #[test]
+    fn test_reader_check_end() {
+        let mut writer = Writer::new();
+        writer.write_bytes(1, &[1, 2, 3]);
+        writer.write_bytes(3, &[4]);
+
+        let mut reader = Reader::new(writer.result());
+        reader.read_bytes(1).unwrap();
+        // field 3 is not read when field 2 is expected
+        assert_eq!(reader.read_bytes(2).unwrap(), vec![]);
+        assert!(matches!(
+            reader.check_end(),
+            Err(CodecError::UnexpectedBytes(5))
+        ));
+        reader.read_bytes(3).unwrap();
+        assert!(reader.check_end().is_ok());
+    }
+
     #[test]
     fn test_new_writer() {
         let writer = Writer::new();
@@ -0,0 +1,412 @@
+/// backends wires each database into the conformance harness.
+use std::sync::mpsc;
+
+use tempdir::TempDir;
+
+use crate::batch::PrefixWriteBatch;
+use crate::conformance::{Backend, Capabilities};
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
+use crate::database::in_memory::in_memory_db;
+use crate::database::options::IterationOption;
+use crate::database::reader_writer::read_writer_db;
+use crate::database::traits::Unwrap;
+use crate::database::types::{DbMessage, Kind};
+use crate::database::utils::{get_iteration_mode, next_chunk};
+use crate::database::DB;
+use crate::state::bloom::StateBloom;
+use crate::state::state_writer::StateWriter;
+use crate::types::KVPair;
+
+type RawIterItem = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>;
+
+fn open_db(name: &str, kind: Kind) -> (TempDir, DB) {
+    let temp_dir = TempDir::new(name).unwrap();
+    let rocks_db = rocksdb::DB::open_default(&temp_dir).unwrap();
+    let (tx, _) = mpsc::channel::<DbMessage>();
+    (temp_dir, DB::new(rocks_db, tx, kind))
+}
+
+/// collect iterates the same as js_iterate of the databases.
+/// Small chunk size is used to cross the chunk boundaries.
+fn collect(
+    mut iter: impl Iterator<Item = RawIterItem>,
+    options: &IterationOption,
+    prefix: &[u8],
+) -> Vec<KVPair> {
+    let mut result = vec![];
+    let mut counter = 0;
+    loop {
+        let (pairs, completed) = next_chunk(&mut iter, options, &mut counter, 3, prefix).unwrap();
+        result.extend(pairs);
+        if completed {
+            return result;
+        }
+    }
+}
+
+/// DatabaseBackend is Database without prefix.
+pub struct DatabaseBackend {
+    _dir: TempDir,
+    db: DB,
+}
+
+impl DatabaseBackend {
+    pub fn new() -> Self {
+        let (_dir, db) = open_db("conformance_database", Kind::Normal);
+        Self { _dir, db }
+    }
+}
+
+impl Default for DatabaseBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for DatabaseBackend {
+    fn name(&self) -> &'static str {
+        "Database"
+    }
+
+    /// iteration over the whole database keeps the default bound for the existing iterations.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            open_range: false,
+            ..Capabilities::ALL
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.db.put(key, value).unwrap();
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        self.db.delete(key).unwrap();
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(key).unwrap()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        self.db.key_exists(key).unwrap()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        let iter = self
+            .db
+            .iterator(get_iteration_mode(options, &mut vec![], &[]));
+        collect(iter, options, &[])
+    }
+}
+
+/// StateDBBackend is StateDB which stores the keys with state prefix, and checks existence with bloom filter.
+/// Other namespaces are populated to check the iteration does not leak out of the state.
+pub struct StateDBBackend {
+    _dir: TempDir,
+    db: DB,
+    bloom: StateBloom,
+}
+
+impl StateDBBackend {
+    pub fn new() -> Self {
+        let (_dir, db) = open_db("conformance_state_db", Kind::State);
+        populate_other_namespaces(&db);
+        Self {
+            _dir,
+            db,
+            bloom: StateBloom::new(0.01),
+        }
+    }
+}
+
+impl Default for StateDBBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn populate_other_namespaces(db: &DB) {
+    for prefix in [Prefix::SMT, Prefix::DIFF, Prefix::CURRENT_STATE] {
+        db.put(&[prefix, &[0]].concat(), &[1]).unwrap();
+        db.put(&[prefix, &[255, 255]].concat(), &[1]).unwrap();
+    }
+}
+
+impl Backend for StateDBBackend {
+    fn name(&self) -> &'static str {
+        "StateDB"
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.db.put(&Kind::State.key(key.to_vec()), value).unwrap();
+        self.bloom.insert([key.to_vec()].iter());
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        self.db.delete(&Kind::State.key(key.to_vec())).unwrap();
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(&Kind::State.key(key.to_vec())).unwrap()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        if !self.bloom.may_exist(&self.db, key).unwrap() {
+            return false;
+        }
+        self.db.key_exists(&Kind::State.key(key.to_vec())).unwrap()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        let iter = self
+            .db
+            .iterator(get_iteration_mode(options, &mut vec![], Prefix::STATE));
+        collect(iter, options, Prefix::STATE)
+    }
+}
+
+/// ReaderBackend is the snapshot reader of StateDB. Snapshot is taken on each read.
+pub struct ReaderBackend {
+    _dir: TempDir,
+    db: DB,
+}
+
+impl ReaderBackend {
+    pub fn new() -> Self {
+        let (_dir, db) = open_db("conformance_reader", Kind::State);
+        populate_other_namespaces(&db);
+        Self { _dir, db }
+    }
+}
+
+impl Default for ReaderBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for ReaderBackend {
+    fn name(&self) -> &'static str {
+        "Reader"
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.db.put(&Kind::State.key(key.to_vec()), value).unwrap();
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        self.db.delete(&Kind::State.key(key.to_vec())).unwrap();
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let conn = self.db.arc_clone();
+        let snapshot = conn.unwrap().snapshot();
+        snapshot.get(Kind::State.key(key.to_vec())).unwrap()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        let conn = self.db.arc_clone();
+        let snapshot = conn.unwrap().snapshot();
+        let iter = snapshot.iterator(get_iteration_mode(options, &mut vec![], Prefix::STATE));
+        collect(iter, options, Prefix::STATE)
+    }
+}
+
+/// ReadWriterBackend is the read writer of StateDB. persist commits the writer to the database.
+pub struct ReadWriterBackend {
+    _dir: TempDir,
+    db: DB,
+    writer: StateWriter,
+}
+
+impl ReadWriterBackend {
+    pub fn new() -> Self {
+        let (_dir, db) = open_db("conformance_read_writer", Kind::State);
+        populate_other_namespaces(&db);
+        Self {
+            _dir,
+            db,
+            writer: StateWriter::default(),
+        }
+    }
+
+    fn stored(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let conn = self.db.arc_clone();
+        let snapshot = conn.unwrap().snapshot();
+        snapshot.get(Kind::State.key(key.to_vec())).unwrap()
+    }
+}
+
+impl Default for ReadWriterBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for ReadWriterBackend {
+    fn name(&self) -> &'static str {
+        "ReadWriter"
+    }
+
+    /// upsert rejects the empty value, as the SMT treats it as the deletion on commit.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            empty_values: false,
+            ..Capabilities::ALL
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        let stored = self.stored(key);
+        read_writer_db::upsert(&mut self.writer, key, value, stored).unwrap();
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        let stored = self.stored(key);
+        read_writer_db::delete_with_writer(&mut self.writer, key, stored).unwrap();
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let stored = self.stored(key);
+        read_writer_db::get_with_writer(&mut self.writer, key, stored).unwrap()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        let conn = self.db.arc_clone();
+        let snapshot = StateSnapshot::new(conn.unwrap());
+        let stored = read_writer_db::stored_range(&snapshot, options).unwrap();
+        read_writer_db::merge_range(&mut self.writer, &stored, options).unwrap()
+    }
+
+    fn persist(&mut self) {
+        let mut write_batch = PrefixWriteBatch::new();
+        write_batch.set_prefix(&Prefix::STATE);
+        self.writer.commit(&mut write_batch).unwrap();
+        self.db.write(write_batch.batch).unwrap();
+        self.writer = StateWriter::default();
+    }
+}
+
+/// InMemoryBackend is in_memory_db.
+pub struct InMemoryBackend {
+    db: in_memory_db::Database,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self {
+            db: in_memory_db::Database::new(),
+        }
+    }
+}
+
+impl Default for InMemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for InMemoryBackend {
+    fn name(&self) -> &'static str {
+        "InMemoryDatabase"
+    }
+
+    /// iteration keeps the default bound as Database.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            open_range: false,
+            ..Capabilities::ALL
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.db.set_key_value(&KVPair::new(key, value));
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        self.db.del(key);
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(key).cloned()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        self.db.get(key).is_some()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        in_memory_db::get_key_value_pairs(&self.db, options)
+    }
+}
+
+/// StateWriterBackend is StateWriter without the underlying storage.
+pub struct StateWriterBackend {
+    writer: StateWriter,
+}
+
+impl StateWriterBackend {
+    pub fn new() -> Self {
+        Self {
+            writer: StateWriter::default(),
+        }
+    }
+}
+
+impl Default for StateWriterBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for StateWriterBackend {
+    fn name(&self) -> &'static str {
+        "StateWriter"
+    }
+
+    /// get_range returns the cache as a map, and ReadWriter orders and limits after merging with the storage.
+    /// upsert rejects the empty value as ReadWriter.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ordered_iteration: false,
+            limit: false,
+            empty_values: false,
+            open_range: true,
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        read_writer_db::upsert(&mut self.writer, key, value, None).unwrap();
+    }
+
+    fn del(&mut self, key: &[u8]) {
+        read_writer_db::delete_with_writer(&mut self.writer, key, None).unwrap();
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        read_writer_db::get_with_writer(&mut self.writer, key, None).unwrap()
+    }
+
+    fn exists(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair> {
+        self.writer
+            .get_range(options)
+            .unwrap()
+            .iter()
+            .map(|(k, v)| KVPair::new(k, v))
+            .collect()
+    }
+}
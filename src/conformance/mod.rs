@@ -0,0 +1,413 @@
+/// conformance provides the harness to check all the backends agree on get, exists and iterate semantics.
+/// Each backend implements Backend, and run_suite checks it against the reference model.
+/// The module is enabled with the "testing" feature.
+pub mod backends;
+
+use std::collections::BTreeMap;
+
+use crate::database::options::IterationOption;
+use crate::database::utils::with_default_bound;
+use crate::types::KVPair;
+
+/// Capabilities declares which part of the common surface the backend supports.
+/// When a flag is false, the harness performs the operation on the result instead of the backend,
+/// or expects the documented behavior from the model. The reason must be documented on the backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// ordered_iteration is true if iterate returns the pairs ordered by the key with the direction.
+    pub ordered_iteration: bool,
+    /// limit is true if iterate applies the limit.
+    pub limit: bool,
+    /// empty_values is true if set accepts the empty value. Otherwise, the harness sets a single byte instead.
+    pub empty_values: bool,
+    /// open_range is true if the range is open to the side which is not specified.
+    /// Otherwise, the model expects the missing side to default to the bound of with_default_bound.
+    pub open_range: bool,
+}
+
+impl Capabilities {
+    pub const ALL: Capabilities = Capabilities {
+        ordered_iteration: true,
+        limit: true,
+        empty_values: true,
+        open_range: true,
+    };
+}
+
+/// Backend is the common surface of get, exists and iterate shared by the databases.
+/// Keys given and returned does not include any prefix.
+pub trait Backend {
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ALL
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]);
+
+    fn del(&mut self, key: &[u8]);
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn exists(&mut self, key: &[u8]) -> bool;
+
+    fn iterate(&mut self, options: &IterationOption) -> Vec<KVPair>;
+
+    /// persist stores pending changes to the underlying storage if the backend has one.
+    fn persist(&mut self) {}
+}
+
+/// Model is the reference behavior using sorted map.
+#[derive(Default)]
+struct Model {
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Model {
+    // map_or is used instead of is_none_or, which requires Rust 1.82.
+    #[allow(clippy::unnecessary_map_or)]
+    fn iterate(&self, options: &IterationOption) -> Vec<KVPair> {
+        let in_range = |key: &Vec<u8>| {
+            options.gte.as_ref().map_or(true, |gte| key >= gte)
+                && options.lte.as_ref().map_or(true, |lte| key <= lte)
+                && options
+                    .prefix
+                    .as_ref()
+                    .map_or(true, |prefix| key.starts_with(prefix))
+        };
+        let mut result: Vec<KVPair> = self
+            .data
+            .iter()
+            .filter(|(k, _)| in_range(k))
+            .map(|(k, v)| KVPair::new(k, v))
+            .collect();
+        if options.reverse {
+            result.reverse();
+        }
+        if options.limit != -1 {
+            result.truncate(options.limit as usize);
+        }
+        result
+    }
+}
+
+/// XorShift is a deterministic random generator for the property checks.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bytes(&mut self) -> Vec<u8> {
+        // small alphabet with 0x00 and 0xff to create prefix relations between the keys
+        const ALPHABET: [u8; 4] = [0, 1, 254, 255];
+        let len = self.below(4) as usize;
+        (0..len)
+            .map(|_| ALPHABET[self.below(ALPHABET.len() as u64) as usize])
+            .collect()
+    }
+}
+
+pub fn options(
+    gte: Option<&[u8]>,
+    lte: Option<&[u8]>,
+    reverse: bool,
+    limit: i64,
+) -> IterationOption {
+    IterationOption {
+        limit,
+        reverse,
+        gte: gte.map(|v| v.to_vec()),
+        lte: lte.map(|v| v.to_vec()),
+        prefix: None,
+        keys_only: false,
+        inventory: false,
+        min_value_size: None,
+    }
+}
+
+/// sample_keys includes unicode, 0xff heavy and prefix related keys.
+pub fn sample_keys() -> Vec<Vec<u8>> {
+    vec![
+        vec![],
+        vec![0],
+        vec![0, 0],
+        vec![0, 255],
+        vec![1, 2, 3],
+        vec![1, 2, 3, 0],
+        "ключ".as_bytes().to_vec(),
+        "键".as_bytes().to_vec(),
+        vec![254, 255],
+        vec![255],
+        vec![255, 0],
+        vec![255, 255],
+        vec![255, 255, 255, 255],
+    ]
+}
+
+/// iterate_with_capabilities performs the operation which the backend opts out.
+fn iterate_with_capabilities<B: Backend>(
+    backend: &mut B,
+    options: &IterationOption,
+) -> Vec<KVPair> {
+    let mut result = backend.iterate(options);
+    let capabilities = backend.capabilities();
+    if !capabilities.ordered_iteration {
+        result.sort_by(|a, b| a.key().cmp(b.key()));
+        if options.reverse {
+            result.reverse();
+        }
+    }
+    if !capabilities.limit && options.limit != -1 {
+        result.truncate(options.limit as usize);
+    }
+    result
+}
+
+fn assert_iterate<B: Backend>(backend: &mut B, model: &Model, options: &IterationOption) {
+    let expected = if backend.capabilities().open_range {
+        model.iterate(options)
+    } else {
+        model.iterate(&with_default_bound(options))
+    };
+    assert_eq!(
+        iterate_with_capabilities(backend, options),
+        expected,
+        "{}: iterate with {:?}",
+        backend.name(),
+        options
+    );
+}
+
+fn assert_get<B: Backend>(backend: &mut B, model: &Model, key: &[u8]) {
+    assert_eq!(
+        backend.get(key),
+        model.data.get(key).cloned(),
+        "{}: get {:?}",
+        backend.name(),
+        key
+    );
+    assert_eq!(
+        backend.exists(key),
+        model.data.contains_key(key),
+        "{}: exists {:?}",
+        backend.name(),
+        key
+    );
+}
+
+/// check_missing_keys checks get and exists for the keys never set.
+pub fn check_missing_keys<B: Backend>(mut backend: B) {
+    let model = Model::default();
+    for key in sample_keys() {
+        assert_get(&mut backend, &model, &key);
+    }
+    assert_iterate(&mut backend, &model, &options(None, None, false, -1));
+    assert_iterate(&mut backend, &model, &options(None, None, true, -1));
+}
+
+/// check_empty_values checks empty value is stored and distinguished from missing key.
+pub fn check_empty_values<B: Backend>(mut backend: B) {
+    let mut model = Model::default();
+    for key in sample_keys() {
+        backend.set(&key, &[]);
+        model.data.insert(key, vec![]);
+    }
+    backend.persist();
+    for key in sample_keys() {
+        assert_get(&mut backend, &model, &key);
+    }
+    assert_iterate(&mut backend, &model, &options(None, None, false, -1));
+}
+
+/// check_set_and_delete checks overwriting and deleting the keys.
+pub fn check_set_and_delete<B: Backend>(mut backend: B) {
+    let mut model = Model::default();
+    for (i, key) in sample_keys().into_iter().enumerate() {
+        backend.set(&key, &[i as u8]);
+        model.data.insert(key, vec![i as u8]);
+    }
+    backend.persist();
+    for (i, key) in sample_keys().into_iter().enumerate() {
+        match i % 3 {
+            0 => {
+                backend.del(&key);
+                model.data.remove(&key);
+            },
+            1 => {
+                backend.set(&key, &[i as u8, i as u8]);
+                model.data.insert(key, vec![i as u8, i as u8]);
+            },
+            _ => {},
+        }
+    }
+    // deleting the key which does not exist
+    backend.del(&[9, 9, 9]);
+    for key in sample_keys() {
+        assert_get(&mut backend, &model, &key);
+    }
+    assert_iterate(&mut backend, &model, &options(None, None, false, -1));
+    backend.persist();
+    for key in sample_keys() {
+        assert_get(&mut backend, &model, &key);
+    }
+    assert_iterate(&mut backend, &model, &options(None, None, true, -1));
+}
+
+/// check_iteration checks ordering, bounds and limits of the iteration.
+pub fn check_iteration<B: Backend>(mut backend: B) {
+    let mut model = Model::default();
+    for (i, key) in sample_keys().into_iter().enumerate() {
+        backend.set(&key, &[i as u8]);
+        model.data.insert(key, vec![i as u8]);
+    }
+    backend.persist();
+    let bounds: Vec<Option<Vec<u8>>> = vec![
+        None,
+        Some(vec![]),
+        Some(vec![0]),
+        Some(vec![0, 128]),
+        Some(vec![1, 2, 3]),
+        Some(vec![254]),
+        Some(vec![255]),
+        Some(vec![255, 255]),
+        Some(vec![255, 255, 255, 255, 255]),
+    ];
+    for gte in bounds.iter() {
+        for lte in bounds.iter() {
+            for reverse in [false, true] {
+                for limit in [-1, 0, 1, 3, 100] {
+                    let opts = options(gte.as_deref(), lte.as_deref(), reverse, limit);
+                    assert_iterate(&mut backend, &model, &opts);
+                }
+            }
+        }
+    }
+    for prefix in bounds.iter().flatten() {
+        for reverse in [false, true] {
+            for limit in [-1, 1] {
+                let opts = IterationOption {
+                    prefix: Some(prefix.clone()),
+                    ..options(None, None, reverse, limit)
+                };
+                assert_iterate(&mut backend, &model, &opts);
+            }
+        }
+    }
+}
+
+/// check_random_operations checks the random sequence of operations against the model.
+pub fn check_random_operations<B: Backend>(mut backend: B, seed: u64) {
+    let empty_values = backend.capabilities().empty_values;
+    let mut model = Model::default();
+    let mut rng = XorShift(seed.max(1));
+    for step in 0..300 {
+        let key = rng.bytes();
+        match rng.below(3) {
+            0 => {
+                backend.del(&key);
+                model.data.remove(&key);
+            },
+            _ => {
+                let mut value = rng.bytes();
+                if value.is_empty() && !empty_values {
+                    value = vec![0];
+                }
+                backend.set(&key, &value);
+                model.data.insert(key.clone(), value);
+            },
+        }
+        if step % 50 == 49 {
+            backend.persist();
+        }
+        if step % 10 == 0 {
+            assert_get(&mut backend, &model, &rng.bytes());
+            let gte = if rng.below(2) == 0 {
+                None
+            } else {
+                Some(rng.bytes())
+            };
+            let lte = if rng.below(2) == 0 {
+                None
+            } else {
+                Some(rng.bytes())
+            };
+            let limit = rng.below(6) as i64 - 1;
+            let opts = options(gte.as_deref(), lte.as_deref(), rng.below(2) == 0, limit);
+            assert_iterate(&mut backend, &model, &opts);
+        }
+    }
+}
+
+/// run_suite runs all the checks with the backend created by the factory.
+pub fn run_suite<B: Backend>(new_backend: impl Fn() -> B) {
+    check_missing_keys(new_backend());
+    if new_backend().capabilities().empty_values {
+        check_empty_values(new_backend());
+    }
+    check_set_and_delete(new_backend());
+    check_iteration(new_backend());
+    for seed in [1, 42, 2022] {
+        check_random_operations(new_backend(), seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backends::*;
+    use super::*;
+
+    #[test]
+    fn test_model_iterate() {
+        let mut model = Model::default();
+        for key in sample_keys() {
+            model.data.insert(key.clone(), key);
+        }
+        let result = model.iterate(&options(Some(&[255]), None, true, 2));
+        assert_eq!(
+            result,
+            vec![
+                KVPair::new(&[255, 255, 255, 255], &[255, 255, 255, 255]),
+                KVPair::new(&[255, 255], &[255, 255]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_database_conformance() {
+        run_suite(DatabaseBackend::new);
+    }
+
+    #[test]
+    fn test_state_db_conformance() {
+        run_suite(StateDBBackend::new);
+    }
+
+    #[test]
+    fn test_reader_conformance() {
+        run_suite(ReaderBackend::new);
+    }
+
+    #[test]
+    fn test_read_writer_conformance() {
+        run_suite(ReadWriterBackend::new);
+    }
+
+    #[test]
+    fn test_in_memory_db_conformance() {
+        run_suite(InMemoryBackend::new);
+    }
+
+    #[test]
+    fn test_state_writer_conformance() {
+        run_suite(StateWriterBackend::new);
+    }
+}
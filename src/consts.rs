@@ -2,8 +2,46 @@ use crate::types::{KeyLength, SubtreeHeight, SubtreeHeightKind};
 
 /// KEY_LENGTH is default key length for state_db.
 pub const KEY_LENGTH: KeyLength = KeyLength(38);
+/// MAX_VALUE_SIZE is the default maximum bytes of a value written to the database.
+pub const MAX_VALUE_SIZE: usize = 10 * 1024 * 1024;
+/// MAX_KEY_SIZE_OVER_KEY_LENGTH is the default bytes allowed for a key beyond the configured key length.
+pub const MAX_KEY_SIZE_OVER_KEY_LENGTH: usize = 64;
 /// SUBTREE_HEIGHT is default subtree height for state_db.
 pub const SUBTREE_HEIGHT: SubtreeHeight = SubtreeHeight(SubtreeHeightKind::Four);
+/// BLOOM_FILTER_FALSE_POSITIVE_RATE is default false positive rate for the state bloom filter.
+pub const BLOOM_FILTER_FALSE_POSITIVE_RATE: f64 = 0.01;
+/// STATE_BLOOM_BITS_PER_KEY is the default bits per key of the rocksdb bloom filters of the state keys.
+pub const STATE_BLOOM_BITS_PER_KEY: u32 = 10;
+/// VALUE_COMPRESSION_THRESHOLD is the default size of the state values compressed on commit.
+pub const VALUE_COMPRESSION_THRESHOLD: usize = 256;
+/// PROFILE_RANGE_MAX_ROWS is the default row cap of profile_range.
+pub const PROFILE_RANGE_MAX_ROWS: u64 = 100_000;
+/// PROFILE_RANGE_PROGRESS_INTERVAL is the default number of keys between the progress reports.
+pub const PROFILE_RANGE_PROGRESS_INTERVAL: u64 = 1_000;
+/// VERIFY_NODES_MAX_ERRORS is the default number of mismatched node keys reported by verify_nodes.
+pub const VERIFY_NODES_MAX_ERRORS: usize = 100;
+/// VERIFY_NODES_PROGRESS_INTERVAL is the default number of nodes between the progress reports.
+pub const VERIFY_NODES_PROGRESS_INTERVAL: u64 = 10_000;
+/// STATE_SNAPSHOT_PROGRESS_INTERVAL is the default number of records between the progress reports of the state snapshot.
+pub const STATE_SNAPSHOT_PROGRESS_INTERVAL: u64 = 10_000;
+/// PRUNE_SMT_BATCH_SIZE is the default number of the unreachable nodes deleted in a batch by prune_smt.
+pub const PRUNE_SMT_BATCH_SIZE: usize = 1_000;
+/// REBUILD_SMT_BATCH_SIZE is the default number of the state keys inserted to the tree in a batch by rebuild_smt.
+pub const REBUILD_SMT_BATCH_SIZE: usize = 10_000;
+/// SHUTDOWN_TIMEOUT_MS is the default time to wait for the pending operations on shutdown.
+pub const SHUTDOWN_TIMEOUT_MS: u64 = 5_000;
+/// ITERATE_CHUNK_SIZE is the default number of pairs sent to JS at once on iteration.
+pub const ITERATE_CHUNK_SIZE: usize = 1_000;
+/// BULK_LOAD_WRITE_BUFFER_SIZE is the size of the memtables while the state_db is in the bulk load.
+pub const BULK_LOAD_WRITE_BUFFER_SIZE: usize = 256 * 1024 * 1024;
+/// DEFAULT_WRITE_BUFFER_SIZE is the rocksdb default size of the memtables restored after the bulk load.
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+/// COMMIT_CHUNK_SIZE is the number of the keys updated in the tree between the progress reports of the commit.
+pub const COMMIT_CHUNK_SIZE: usize = 10_000;
+/// KEYSPACE_MIGRATION_BATCH_SIZE is the number of keys moved to the column families in a batch.
+pub const KEYSPACE_MIGRATION_BATCH_SIZE: usize = 10_000;
+/// MIGRATION_BATCH_SIZE is the number of the heights written in a batch by the format migrations.
+pub const MIGRATION_BATCH_SIZE: usize = 1_000;
 
 /// PREFIX_LEAF_HASH is prefix for creating leaf node hash.
 pub static PREFIX_LEAF_HASH: &[u8] = "LSK_SMTL_".as_bytes();
@@ -11,6 +49,10 @@ pub static PREFIX_LEAF_HASH: &[u8] = "LSK_SMTL_".as_bytes();
 pub static PREFIX_BRANCH_HASH: &[u8] = "LSK_SMTB_".as_bytes();
 /// PREFIX_EMPTY is prefix for creating empty node hash.
 pub static PREFIX_EMPTY: &[u8] = &[2];
+/// STAGED_WRITE_PREFIX is reserved in the Database for the batches staged by write_prepared.
+pub static STAGED_WRITE_PREFIX: &[u8] = "LDB_STAGED_".as_bytes();
+/// PREFIX_FILTER_LENGTH_KEY is reserved in the Database for the prefix length of the prefix filters.
+pub static PREFIX_FILTER_LENGTH_KEY: &[u8] = "LDB_PREFIX_FILTER_LENGTH".as_bytes();
 
 /// Prefix is the database prefix to separate the keys in the state_db.
 pub struct Prefix;
@@ -23,4 +65,26 @@ impl Prefix {
     pub const DIFF: &'static [u8] = &[2];
     /// CURRENT_STATE maintains current version and the root hash of the state_db.
     pub const CURRENT_STATE: &'static [u8] = &[3];
+    /// CURRENT_STATE_BACKUP maintains the legacy current state record after the migration.
+    pub const CURRENT_STATE_BACKUP: &'static [u8] = &[4];
+    /// JOURNAL maintains the progress of the commit written in chunks.
+    pub const JOURNAL: &'static [u8] = &[5];
+    /// KEYSPACE_FORMAT maintains the format of the keyspace, which is set after the keys are moved to the column families.
+    pub const KEYSPACE_FORMAT: &'static [u8] = &[6];
+    /// ROOT_HISTORY maintains the state root committed at each height.
+    pub const ROOT_HISTORY: &'static [u8] = &[7];
+    /// ROOT_HISTORY_PRUNED maintains the height below which the root history is removed with the diffs.
+    pub const ROOT_HISTORY_PRUNED: &'static [u8] = &[8];
+    /// STAGED_COMMIT maintains the ids of the Database batches staged for the commits until they are recovered.
+    pub const STAGED_COMMIT: &'static [u8] = &[9];
+    /// DIFF_VALUE maintains the values referenced by the hash from the diffs with the reference counts.
+    pub const DIFF_VALUE: &'static [u8] = &[10];
+    /// FORMAT_VERSION maintains the on-disk format version, which is set when the database is created or migrated.
+    pub const FORMAT_VERSION: &'static [u8] = &[11];
+    /// PREFIX_FILTER_LENGTH maintains the prefix length of the prefix filters of the state keys, which is set on the first open with it.
+    pub const PREFIX_FILTER_LENGTH: &'static [u8] = &[12];
+    /// BULK_LOADED maintains the ranges of the heights committed in the bulk load, which have no diff, by the last height.
+    pub const BULK_LOADED: &'static [u8] = &[13];
+    /// SMT_REBUILD maintains the nodes written by rebuild_smt until they replace the nodes with Prefix::SMT.
+    pub const SMT_REBUILD: &'static [u8] = &[14];
 }
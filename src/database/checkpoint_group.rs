@@ -0,0 +1,523 @@
+/// checkpoint_group coordinates the checkpoints of the blockchain database and the state database taken for the same backup,
+/// so that the restored pair can be validated to be taken together.
+/// Each member records its outcome in the group, and the manifest is written once both members finished.
+/// The manifest is written to a temporary path and renamed, so that a crash never leaves a partial manifest,
+/// and the pair without the manifest is not a complete group.
+/// The manifest file consists of:
+/// - header: magic bytes and the version of the format.
+/// - complete flag: 1 if both checkpoints are created and verified, 0 otherwise.
+/// - key length of the state, and the state root prefixed by its length, which is empty if the state checkpoint is not created.
+/// - paths: the path of the blockchain checkpoint and of the state checkpoint, each prefixed by its length.
+/// - checksum: sha256 of all the above.
+///
+/// All the numbers are big endian.
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use neon::prelude::*;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::database::checkpoint_info_to_js_object;
+use crate::database::db_base::CheckpointInfo;
+use crate::database::DB;
+use crate::error::{self, DbError};
+use crate::types::KeyLength;
+use crate::utils;
+
+pub const MAGIC: &[u8] = b"lisk-db:checkpoint-group";
+pub const VERSION: u32 = 1;
+
+const CHECKSUM_LENGTH: usize = 32;
+
+/// NEXT_TOKEN is the token of the next group.
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+/// GROUPS holds the groups whose manifest is not written yet.
+static GROUPS: Mutex<BTreeMap<u64, Arc<CheckpointGroup>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Error, Debug)]
+pub enum CheckpointGroupError {
+    #[error("checkpoint group {0} does not exist or is already finished")]
+    UnknownGroup(u64),
+    #[error("manifest `{0}` already exists")]
+    ManifestExists(String),
+    #[error("{0} checkpoint is already taken in the group")]
+    DuplicateMember(&'static str),
+    #[error("checkpoint group is incomplete")]
+    Incomplete,
+    #[error("invalid checkpoint group manifest: {0}")]
+    InvalidManifest(String),
+    #[error("state root {0} does not match the manifest")]
+    RootMismatch(String),
+    #[error("Manifest file error `{0}`")]
+    Io(#[from] io::Error),
+}
+
+/// Member is the database taking the checkpoint in the group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Member {
+    Blockchain = 0,
+    State = 1,
+}
+
+impl Member {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Blockchain => "blockchain",
+            Self::State => "state",
+        }
+    }
+}
+
+/// Manifest is the record of the group written after both members finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// complete is true when both checkpoints are created and verified.
+    pub complete: bool,
+    pub key_length: KeyLength,
+    /// root is the state root of the state checkpoint. It is empty if the state checkpoint is not created.
+    pub root: Vec<u8>,
+    pub blockchain_path: String,
+    pub state_path: String,
+}
+
+/// Outcome is the result of the checkpoint of a member.
+#[derive(Clone, Debug)]
+struct Outcome {
+    path: String,
+    key_length: Option<KeyLength>,
+    /// info is the checkpoint which is created and verified, and None if the checkpoint failed.
+    info: Option<CheckpointInfo>,
+}
+
+enum MemberState {
+    Waiting,
+    Started,
+    Finished(Outcome),
+}
+
+/// CheckpointGroup waits for the outcomes of the members, and writes the manifest with them.
+pub struct CheckpointGroup {
+    token: u64,
+    manifest_path: PathBuf,
+    members: Mutex<[MemberState; 2]>,
+}
+
+/// Membership is the checkpoint of a member joined to the group.
+/// If it is dropped before finishing, the member is recorded as failed, so that the manifest marks the group incomplete.
+pub struct Membership {
+    group: Arc<CheckpointGroup>,
+    member: Member,
+    path: String,
+    key_length: Option<KeyLength>,
+    finished: bool,
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = [MAGIC, &VERSION.to_be_bytes(), &[self.complete as u8]].concat();
+        bytes.extend_from_slice(&self.key_length.0.to_be_bytes());
+        for field in [
+            self.root.as_slice(),
+            self.blockchain_path.as_bytes(),
+            self.state_path.as_bytes(),
+        ] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CheckpointGroupError> {
+        if bytes.len() < MAGIC.len() + 4 + CHECKSUM_LENGTH || !bytes.starts_with(MAGIC) {
+            return Err(invalid_manifest("invalid header"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+        if Sha256::digest(body).as_slice() != checksum {
+            return Err(invalid_manifest("checksum does not match"));
+        }
+        let mut rest = &body[MAGIC.len()..];
+        let version = u32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid_manifest(format!("unsupported version {}", version)));
+        }
+
+        let complete = match take(&mut rest, 1)? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(invalid_manifest("invalid complete flag")),
+        };
+        let key_length = KeyLength(u16::from_be_bytes(take(&mut rest, 2)?.try_into().unwrap()));
+        let mut fields = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let length = u32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap()) as usize;
+            fields.push(take(&mut rest, length)?.to_vec());
+        }
+        if !rest.is_empty() {
+            return Err(invalid_manifest("unexpected trailing bytes"));
+        }
+        let state_path = String::from_utf8(fields.pop().unwrap())
+            .map_err(|_| invalid_manifest("path is not utf8"))?;
+        let blockchain_path = String::from_utf8(fields.pop().unwrap())
+            .map_err(|_| invalid_manifest("path is not utf8"))?;
+
+        Ok(Self {
+            complete,
+            key_length,
+            root: fields.pop().unwrap(),
+            blockchain_path,
+            state_path,
+        })
+    }
+}
+
+fn invalid_manifest(reason: impl Into<String>) -> CheckpointGroupError {
+    CheckpointGroupError::InvalidManifest(reason.into())
+}
+
+/// take splits the field of the length from the start of the bytes.
+fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8], CheckpointGroupError> {
+    let current: &'a [u8] = *bytes;
+    if current.len() < length {
+        return Err(invalid_manifest("unexpected end"));
+    }
+    let (field, rest) = current.split_at(length);
+    *bytes = rest;
+    Ok(field)
+}
+
+/// write_manifest writes the manifest to a temporary path and renames it, and flushes both to the disk.
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    fs::write(&temp_path, manifest.encode())?;
+    fs::File::open(&temp_path)?.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    utils::sync_path(path)
+}
+
+/// read_manifest reads the manifest, and rejects the one whose checksum does not match.
+pub fn read_manifest(path: &Path) -> Result<Manifest, CheckpointGroupError> {
+    Manifest::decode(&fs::read(path)?)
+}
+
+/// new_group registers the group writing the manifest at the path, and returns its token.
+pub fn new_group(manifest_path: PathBuf) -> Result<u64, CheckpointGroupError> {
+    if manifest_path.exists() {
+        return Err(CheckpointGroupError::ManifestExists(
+            manifest_path.to_string_lossy().to_string(),
+        ));
+    }
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::SeqCst);
+    let group = CheckpointGroup {
+        token,
+        manifest_path,
+        members: Mutex::new([MemberState::Waiting, MemberState::Waiting]),
+    };
+    GROUPS.lock().unwrap().insert(token, Arc::new(group));
+    Ok(token)
+}
+
+/// join starts the checkpoint of the member at the path in the group of the token.
+/// key_length is the key length of the state database, and it is only set for the state member.
+pub fn join(
+    token: u64,
+    member: Member,
+    path: &str,
+    key_length: Option<KeyLength>,
+) -> Result<Membership, CheckpointGroupError> {
+    let group = GROUPS
+        .lock()
+        .unwrap()
+        .get(&token)
+        .cloned()
+        .ok_or(CheckpointGroupError::UnknownGroup(token))?;
+    {
+        let mut members = group.members.lock().unwrap();
+        let state = &mut members[member as usize];
+        if !matches!(state, MemberState::Waiting) {
+            return Err(CheckpointGroupError::DuplicateMember(member.name()));
+        }
+        *state = MemberState::Started;
+    }
+
+    Ok(Membership {
+        group,
+        member,
+        path: path.to_string(),
+        key_length,
+        finished: false,
+    })
+}
+
+impl CheckpointGroup {
+    /// record sets the outcome of the member, and writes the manifest when both members finished.
+    /// It returns the manifest if it is written by this member.
+    fn record(
+        &self,
+        member: Member,
+        outcome: Outcome,
+    ) -> Result<Option<Manifest>, CheckpointGroupError> {
+        let mut members = self.members.lock().unwrap();
+        members[member as usize] = MemberState::Finished(outcome);
+        let (blockchain, state) = match &*members {
+            [MemberState::Finished(blockchain), MemberState::Finished(state)] => {
+                (blockchain, state)
+            },
+            _ => return Ok(None),
+        };
+        GROUPS.lock().unwrap().remove(&self.token);
+
+        let manifest = Manifest {
+            complete: blockchain.info.is_some() && state.info.is_some(),
+            key_length: state.key_length.unwrap_or(KeyLength(0)),
+            root: state
+                .info
+                .as_ref()
+                .and_then(|info| info.root.clone())
+                .unwrap_or_default(),
+            blockchain_path: blockchain.path.clone(),
+            state_path: state.path.clone(),
+        };
+        write_manifest(&self.manifest_path, &manifest)?;
+
+        Ok(Some(manifest))
+    }
+}
+
+impl Membership {
+    /// finish records the result of the checkpoint in the group, and returns it.
+    /// If the checkpoint is created but the manifest cannot be written, the error of the manifest is returned.
+    pub fn finish(
+        mut self,
+        result: Result<CheckpointInfo, DbError>,
+    ) -> Result<CheckpointInfo, DbError> {
+        self.finished = true;
+        let outcome = Outcome {
+            path: self.path.clone(),
+            key_length: self.key_length,
+            info: result.as_ref().ok().cloned(),
+        };
+        let recorded = self.group.record(self.member, outcome);
+        let info = result?;
+        recorded?;
+
+        Ok(info)
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let outcome = Outcome {
+            path: self.path.clone(),
+            key_length: self.key_length,
+            info: None,
+        };
+        // the error cannot be reported, and the group without the manifest is incomplete anyway.
+        let _ = self.group.record(self.member, outcome);
+    }
+}
+
+/// verify reads the manifest, and verifies both checkpoints of the complete group.
+/// The state root of the state checkpoint must be the root recorded in the manifest.
+pub fn verify(manifest_path: &Path) -> Result<(CheckpointInfo, CheckpointInfo), DbError> {
+    let manifest = read_manifest(manifest_path)?;
+    if !manifest.complete {
+        return Err(CheckpointGroupError::Incomplete.into());
+    }
+    let blockchain = DB::verify_checkpoint(&manifest.blockchain_path, None)?;
+    let state = DB::verify_checkpoint(&manifest.state_path, Some(manifest.key_length))?;
+    if state.root.as_deref() != Some(manifest.root.as_slice()) {
+        let root = state.root.map(hex::encode).unwrap_or_default();
+        return Err(CheckpointGroupError::RootMismatch(root).into());
+    }
+
+    Ok((blockchain, state))
+}
+
+/// get_token reads the token of the group from the argument.
+pub fn get_token(ctx: &mut FunctionContext, index: i32) -> NeonResult<u64> {
+    let token = ctx.argument::<JsNumber>(index)?.value(ctx);
+    if token < 1.0 || token.fract() != 0.0 || token > u64::MAX as f64 {
+        return error::invalid_argument("checkpoint group token must be a positive integer")
+            .throw(ctx);
+    }
+    Ok(token as u64)
+}
+
+/// js_new is handler for JS ffi.
+/// - @params(0) - path to write the manifest of the group. It must not exist.
+/// - @returns - token of the group.
+pub fn js_new(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+    let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let token =
+        new_group(PathBuf::from(path)).or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+    Ok(ctx.number(token as f64))
+}
+
+/// js_verify is handler for JS ffi.
+/// It verifies the checkpoints of the manifest in a dedicated thread.
+/// - @params(0) - path of the manifest.
+/// - @params(1) - callback to return the result.
+/// - @callback(0) - Error. If the group is incomplete or a checkpoint is invalid, it will call the callback with ERR_CORRUPTED code.
+/// - @callback(1) - { blockchain: { size: u64, sstFiles: u64 }, state: { size: u64, sstFiles: u64, root: &[u8] } }.
+pub fn js_verify(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+    let channel = ctx.channel();
+
+    thread::spawn(move || {
+        let result = verify(Path::new(&path));
+
+        channel.send(move |mut ctx| {
+            let callback = callback.into_inner(&mut ctx);
+            let this = ctx.undefined();
+            let args: Vec<Handle<JsValue>> = match result {
+                Ok((blockchain, state)) => {
+                    let obj = ctx.empty_object();
+                    let blockchain = checkpoint_info_to_js_object(&mut ctx, &blockchain)?;
+                    obj.set(&mut ctx, "blockchain", blockchain)?;
+                    let state = checkpoint_info_to_js_object(&mut ctx, &state)?;
+                    obj.set(&mut ctx, "state", state)?;
+                    vec![ctx.null().upcast(), obj.upcast()]
+                },
+                Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+            };
+            callback.call(&mut ctx, this, args)?;
+
+            Ok(())
+        });
+    });
+
+    Ok(ctx.undefined())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn info(root: Option<Vec<u8>>) -> CheckpointInfo {
+        CheckpointInfo {
+            size: 1,
+            sst_files: 0,
+            root,
+        }
+    }
+
+    #[test]
+    fn test_manifest_encode_decode() {
+        let manifest = Manifest {
+            complete: true,
+            key_length: KeyLength(38),
+            root: vec![3; 32],
+            blockchain_path: String::from("/backup/blockchain.db"),
+            state_path: String::from("/backup/state.db"),
+        };
+        let encoded = manifest.encode();
+        assert_eq!(Manifest::decode(&encoded).unwrap(), manifest);
+
+        let mut corrupted = encoded.clone();
+        corrupted[MAGIC.len() + 4] = 0;
+        assert!(matches!(
+            Manifest::decode(&corrupted),
+            Err(CheckpointGroupError::InvalidManifest(_))
+        ));
+        assert!(matches!(
+            Manifest::decode(&encoded[..encoded.len() - 1]),
+            Err(CheckpointGroupError::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn test_group_writes_manifest_after_both_members() {
+        let temp_dir = TempDir::new("test_checkpoint_group_complete").unwrap();
+        let manifest_path = temp_dir.path().join("manifest");
+        let token = new_group(manifest_path.clone()).unwrap();
+
+        let blockchain = join(token, Member::Blockchain, "blockchain", None).unwrap();
+        assert!(matches!(
+            join(token, Member::Blockchain, "blockchain", None),
+            Err(CheckpointGroupError::DuplicateMember("blockchain"))
+        ));
+        let state = join(token, Member::State, "state", Some(KeyLength(38))).unwrap();
+        blockchain.finish(Ok(info(None))).unwrap();
+        assert!(!manifest_path.exists());
+        state.finish(Ok(info(Some(vec![3; 32])))).unwrap();
+
+        let manifest = read_manifest(&manifest_path).unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                complete: true,
+                key_length: KeyLength(38),
+                root: vec![3; 32],
+                blockchain_path: String::from("blockchain"),
+                state_path: String::from("state"),
+            }
+        );
+        // finished group cannot be joined, and the manifest cannot be replaced
+        assert!(matches!(
+            join(token, Member::State, "state", None),
+            Err(CheckpointGroupError::UnknownGroup(_))
+        ));
+        assert!(matches!(
+            new_group(manifest_path),
+            Err(CheckpointGroupError::ManifestExists(_))
+        ));
+    }
+
+    #[test]
+    fn test_group_incomplete_after_failure() {
+        let temp_dir = TempDir::new("test_checkpoint_group_incomplete").unwrap();
+
+        // second checkpoint fails after the first one is created
+        let manifest_path = temp_dir.path().join("failed");
+        let token = new_group(manifest_path.clone()).unwrap();
+        let blockchain = join(token, Member::Blockchain, "blockchain", None).unwrap();
+        let state = join(token, Member::State, "state", Some(KeyLength(38))).unwrap();
+        blockchain.finish(Ok(info(None))).unwrap();
+        let err = state
+            .finish(Err(DbError::Unknown(String::from("failed"))))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+        let manifest = read_manifest(&manifest_path).unwrap();
+        assert!(!manifest.complete);
+        assert!(manifest.root.is_empty());
+        assert!(matches!(
+            verify(&manifest_path),
+            Err(DbError::CheckpointGroup(CheckpointGroupError::Incomplete))
+        ));
+
+        // member dropped without finishing is failed
+        let manifest_path = temp_dir.path().join("dropped");
+        let token = new_group(manifest_path.clone()).unwrap();
+        let blockchain = join(token, Member::Blockchain, "blockchain", None).unwrap();
+        drop(join(token, Member::State, "state", Some(KeyLength(38))).unwrap());
+        assert!(!manifest_path.exists());
+        blockchain.finish(Ok(info(None))).unwrap();
+        assert!(!read_manifest(&manifest_path).unwrap().complete);
+
+        // no temporary file is left
+        let mut names: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["dropped", "failed"]);
+    }
+}
@@ -0,0 +1,325 @@
+/// chunked_write provides non-atomic write of the large batch for the bulk load.
+/// The batch is split into chunks bounded by the size, and each chunk is written with the journal recording the progress.
+/// When the write is interrupted, writing the same batch again resumes from the chunk after the last written one.
+#[cfg(test)]
+use std::cell::Cell;
+
+use thiserror::Error;
+
+use crate::database::column_families;
+
+#[derive(Error, Debug)]
+pub enum ChunkedWriteError {
+    #[error("{0}")]
+    RocksDB(#[from] rocksdb::Error),
+    #[error("Journal does not match the batch being written")]
+    JournalMismatch,
+    #[error("Chunked write is interrupted at chunk `{0}`")]
+    Interrupted(usize),
+    #[error("Batch cannot be written in chunks: {0}")]
+    InvalidBatch(String),
+}
+
+/// BatchOp is an operation of the batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum BatchOp {
+    Put(Box<[u8]>, Box<[u8]>),
+    Delete(Box<[u8]>),
+}
+
+impl BatchOp {
+    fn size(&self) -> usize {
+        match self {
+            BatchOp::Put(key, value) => key.len() + value.len(),
+            BatchOp::Delete(key) => key.len(),
+        }
+    }
+
+    /// add adds the operation to the batch routed to the column families.
+    fn add(&self, conn: &rocksdb::DB, batch: &mut rocksdb::WriteBatch) {
+        match self {
+            BatchOp::Put(key, value) => column_families::put(conn, batch, key, value),
+            BatchOp::Delete(key) => column_families::delete(conn, batch, key),
+        }
+    }
+}
+
+/// OpCollector collects the operations of the batch in the order they are added.
+#[derive(Default)]
+struct OpCollector {
+    ops: Vec<BatchOp>,
+}
+
+impl rocksdb::WriteBatchIterator for OpCollector {
+    fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
+        self.ops.push(BatchOp::Put(key, value));
+    }
+
+    fn delete(&mut self, key: Box<[u8]>) {
+        self.ops.push(BatchOp::Delete(key));
+    }
+}
+
+/// collect_ops returns the operations of the batch in the order they are added.
+/// rocksdb::WriteBatch::iterate stops at the first operation other than put and delete, such as a range deletion,
+/// so that the batch is rejected if any operation is not collected.
+fn collect_ops(batch: &rocksdb::WriteBatch) -> Result<Vec<BatchOp>, ChunkedWriteError> {
+    let mut collector = OpCollector::default();
+    batch.iterate(&mut collector);
+    if collector.ops.len() != batch.len() {
+        return Err(ChunkedWriteError::InvalidBatch(
+            "only put and delete can be written in chunks".to_string(),
+        ));
+    }
+    Ok(collector.ops)
+}
+
+#[cfg(test)]
+thread_local! {
+    /// FAILPOINT interrupts the write before writing the chunk at the index.
+    static FAILPOINT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// set_failpoint interrupts the next chunked writes on the current thread before writing the chunk at the index.
+#[cfg(test)]
+pub(crate) fn set_failpoint(index: Option<usize>) {
+    FAILPOINT.with(|failpoint| failpoint.set(index));
+}
+
+#[cfg(test)]
+fn is_failpoint(index: usize) -> bool {
+    FAILPOINT.with(|failpoint| failpoint.get()) == Some(index)
+}
+
+#[cfg(not(test))]
+fn is_failpoint(_: usize) -> bool {
+    false
+}
+
+/// split_into_chunks splits the operations in the order of the batch, so that the chunks are the same for the same batch.
+/// Each chunk has at least one operation.
+fn split_into_chunks(
+    batch: &rocksdb::WriteBatch,
+    max_batch_bytes: usize,
+) -> Result<Vec<Vec<BatchOp>>, ChunkedWriteError> {
+    let ops = collect_ops(batch)?;
+
+    let mut chunks = vec![];
+    let mut chunk = vec![];
+    let mut size = 0;
+    for op in ops {
+        if !chunk.is_empty() && size + op.size() > max_batch_bytes {
+            chunks.push(chunk);
+            chunk = vec![];
+            size = 0;
+        }
+        size += op.size();
+        chunk.push(op);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    Ok(chunks)
+}
+
+/// written_chunks returns the number of the chunks written by the previous attempt of the same batch.
+fn written_chunks(
+    conn: &rocksdb::DB,
+    journal_key: &[u8],
+    identity: &[u8],
+) -> Result<usize, ChunkedWriteError> {
+    let journal = match conn.get(journal_key)? {
+        Some(journal) => journal,
+        None => return Ok(0),
+    };
+    if journal.len() != identity.len() + 4 || !journal.starts_with(identity) {
+        return Err(ChunkedWriteError::JournalMismatch);
+    }
+    let mut count = [0; 4];
+    count.copy_from_slice(&journal[identity.len()..]);
+    Ok(u32::from_be_bytes(count) as usize)
+}
+
+/// write_in_chunks writes the batch in chunks of max_batch_bytes, each with write_opts.
+/// identity must be the same for the same batch, and it is used to check the journal belongs to the batch on resume.
+/// The journal is removed with the last chunk.
+pub fn write_in_chunks(
+    conn: &rocksdb::DB,
+    batch: &rocksdb::WriteBatch,
+    max_batch_bytes: usize,
+    journal_key: &[u8],
+    identity: &[u8],
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), ChunkedWriteError> {
+    let chunks = split_into_chunks(batch, max_batch_bytes)?;
+    let written = written_chunks(conn, journal_key, identity)?;
+    for (index, chunk) in chunks.iter().enumerate().skip(written) {
+        if is_failpoint(index) {
+            return Err(ChunkedWriteError::Interrupted(index));
+        }
+        let mut write_batch = rocksdb::WriteBatch::default();
+        for op in chunk {
+            op.add(conn, &mut write_batch);
+        }
+        if index + 1 == chunks.len() {
+            column_families::delete(conn, &mut write_batch, journal_key);
+        } else {
+            let progress = [identity, &(index as u32 + 1).to_be_bytes()].concat();
+            column_families::put(conn, &mut write_batch, journal_key, &progress);
+        }
+        conn.write_opt(write_batch, write_opts)?;
+    }
+    // empty batch does not write any chunk
+    if chunks.is_empty() {
+        conn.delete_opt(journal_key, write_opts)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    const JOURNAL: &[u8] = &[9, 9];
+
+    fn sample_batch() -> rocksdb::WriteBatch {
+        let mut batch = rocksdb::WriteBatch::default();
+        for i in 0..20_u8 {
+            batch.put([i], [i; 10]);
+        }
+        batch.delete([3]);
+        batch.put([5], [55; 10]);
+        batch
+    }
+
+    fn assert_contents(db: &rocksdb::DB) {
+        for i in 0..20_u8 {
+            let expected = match i {
+                3 => None,
+                5 => Some(vec![55; 10]),
+                _ => Some(vec![i; 10]),
+            };
+            assert_eq!(db.get([i]).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_split_into_chunks() {
+        let chunks = split_into_chunks(&sample_batch(), 33).unwrap();
+        // each put is 11 bytes
+        assert_eq!(chunks.len(), 8);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.iter().map(|op| op.size()).sum::<usize>() <= 33));
+        let ops: Vec<BatchOp> = chunks.into_iter().flatten().collect();
+        assert_eq!(ops.len(), 22);
+        // operations keep the order of the batch
+        assert_eq!(ops[3], BatchOp::Put(Box::new([3]), Box::new([3; 10])));
+        assert_eq!(
+            ops[20..22],
+            [
+                BatchOp::Delete(Box::new([3])),
+                BatchOp::Put(Box::new([5]), Box::new([55; 10]))
+            ]
+        );
+
+        // operation larger than the limit has its own chunk
+        assert_eq!(split_into_chunks(&sample_batch(), 1).unwrap().len(), 22);
+        assert_eq!(
+            split_into_chunks(&sample_batch(), usize::MAX)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_write_in_chunks_resume() {
+        let temp_dir = TempDir::new("test_write_in_chunks_resume").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let batch = sample_batch();
+
+        let write_opts = rocksdb::WriteOptions::default();
+
+        set_failpoint(Some(3));
+        assert!(matches!(
+            write_in_chunks(&db, &batch, 33, JOURNAL, b"batch", &write_opts),
+            Err(ChunkedWriteError::Interrupted(3))
+        ));
+        set_failpoint(None);
+        // first 3 chunks are written
+        assert_eq!(db.get([8]).unwrap(), Some(vec![8; 10]));
+        assert_eq!(db.get([9]).unwrap(), None);
+        assert!(db.get(JOURNAL).unwrap().is_some());
+
+        // different batch cannot be written until the interrupted one is completed
+        assert!(matches!(
+            write_in_chunks(&db, &batch, 33, JOURNAL, b"other", &write_opts),
+            Err(ChunkedWriteError::JournalMismatch)
+        ));
+
+        // resume writes only the rest
+        db.put([0], [100]).unwrap();
+        write_in_chunks(&db, &batch, 33, JOURNAL, b"batch", &write_opts).unwrap();
+        assert_eq!(db.get([0]).unwrap(), Some(vec![100]));
+        db.put([0], [0; 10]).unwrap();
+        assert_contents(&db);
+        assert_eq!(db.get(JOURNAL).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_in_chunks_matches_atomic_write() {
+        let temp_dir = TempDir::new("test_write_in_chunks_atomic").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let write_opts = rocksdb::WriteOptions::default();
+        write_in_chunks(&db, &sample_batch(), 40, JOURNAL, b"batch", &write_opts).unwrap();
+        assert_contents(&db);
+        assert_eq!(db.get(JOURNAL).unwrap(), None);
+
+        let empty = rocksdb::WriteBatch::default();
+        write_in_chunks(&db, &empty, 40, JOURNAL, b"empty", &write_opts).unwrap();
+        assert_eq!(db.get(JOURNAL).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_in_chunks_unsupported_operation() {
+        let temp_dir = TempDir::new("test_write_in_chunks_unsupported").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put([1], [1]);
+        batch.merge([1], [2]);
+        assert!(matches!(
+            write_in_chunks(
+                &db,
+                &batch,
+                40,
+                JOURNAL,
+                b"batch",
+                &rocksdb::WriteOptions::default()
+            ),
+            Err(ChunkedWriteError::InvalidBatch(_))
+        ));
+        assert_eq!(db.get([1]).unwrap(), None);
+
+        // operations after the range deletion are not dropped silently
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put([1], [1]);
+        batch.delete_range([1], [2]);
+        batch.put([2], [2]);
+        assert!(matches!(
+            write_in_chunks(
+                &db,
+                &batch,
+                40,
+                JOURNAL,
+                b"batch",
+                &rocksdb::WriteOptions::default()
+            ),
+            Err(ChunkedWriteError::InvalidBatch(_))
+        ));
+        assert_eq!(db.get([1]).unwrap(), None);
+        assert_eq!(db.get([2]).unwrap(), None);
+    }
+}
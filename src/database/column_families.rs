@@ -0,0 +1,611 @@
+/// column_families routes the keys of the state_db to the column families by the prefix.
+/// The keys keep the prefix in the column family, so that the ranges and the iteration bounds are the same
+/// as in the default column family. Database without the column families, such as the normal database or
+/// the state_db opened with legacy-keyspace feature, keeps all the keys in the default column family.
+use std::path::Path;
+
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, IteratorMode, Options, ReadOptions, WriteBatch,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
+
+use crate::consts::{self, Prefix};
+use crate::database::{prefix_filter, utils};
+
+/// STATE is the column family of the keys with Prefix::STATE.
+pub const STATE: &str = "state";
+/// SMT is the column family of the keys with Prefix::SMT.
+pub const SMT: &str = "smt";
+/// DIFF is the column family of the keys with Prefix::DIFF.
+pub const DIFF: &str = "diff";
+
+/// ROUTES maps the prefix to the column family.
+const ROUTES: [(&[u8], &str); 3] = [
+    (Prefix::STATE, STATE),
+    (Prefix::SMT, SMT),
+    (Prefix::DIFF, DIFF),
+];
+
+/// FORMAT_COLUMN_FAMILIES is the value of Prefix::KEYSPACE_FORMAT set after the keys are moved.
+const FORMAT_COLUMN_FAMILIES: &[u8] = &[1];
+
+/// name returns the column family of the key, or None if the key is kept in the default column family.
+pub fn name(key: &[u8]) -> Option<&'static str> {
+    ROUTES
+        .iter()
+        .find(|(prefix, _)| key.starts_with(prefix))
+        .map(|(_, name)| *name)
+}
+
+/// handle returns the column family of the key if the database has it.
+pub fn handle<'a>(conn: &'a rocksdb::DB, key: &[u8]) -> Option<&'a ColumnFamily> {
+    name(key).and_then(|name| conn.cf_handle(name))
+}
+
+/// handles returns the column families of the database except the default one.
+pub fn handles(conn: &rocksdb::DB) -> Vec<&ColumnFamily> {
+    ROUTES
+        .iter()
+        .filter_map(|(_, name)| conn.cf_handle(name))
+        .collect()
+}
+
+/// is_enabled returns true if the database has the column families.
+pub fn is_enabled(conn: &rocksdb::DB) -> bool {
+    conn.cf_handle(STATE).is_some()
+}
+
+/// open opens the database with all the existing column families.
+/// Writable database creates the missing column families unless legacy-keyspace feature is enabled.
+pub fn open(
+    opts: &Options,
+    path: impl AsRef<Path>,
+    readonly: bool,
+) -> Result<rocksdb::DB, rocksdb::Error> {
+    open_with_state_options(opts, opts, path, readonly, true)
+}
+
+/// open_with_state_options opens the database as open, with state_opts on the STATE column family.
+/// Existing database without the column families is opened as it is unless migrate is set,
+/// so that it is not changed before the migration is allowed.
+pub fn open_with_state_options(
+    opts: &Options,
+    state_opts: &Options,
+    path: impl AsRef<Path>,
+    readonly: bool,
+    migrate: bool,
+) -> Result<rocksdb::DB, rocksdb::Error> {
+    let path = path.as_ref();
+    // listing fails if the database is not created yet
+    let mut names = rocksdb::DB::list_cf(opts, path).unwrap_or_default();
+    let legacy = names.len() == 1;
+    if !readonly && !cfg!(feature = "legacy-keyspace") && (migrate || !legacy) {
+        for (_, name) in ROUTES.iter() {
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    let mut opts = opts.clone();
+    opts.create_missing_column_families(true);
+    let descriptors = descriptors(names, &opts, state_opts);
+    if readonly {
+        rocksdb::DB::open_cf_descriptors_read_only(&opts, path, descriptors, false)
+    } else {
+        rocksdb::DB::open_cf_descriptors(&opts, path, descriptors)
+    }
+}
+
+/// open_as_secondary opens the database with all the existing column families as the secondary instance,
+/// which keeps its own logs in secondary_path. STATE column family is opened with state_opts.
+pub fn open_as_secondary(
+    opts: &Options,
+    state_opts: &Options,
+    path: impl AsRef<Path>,
+    secondary_path: impl AsRef<Path>,
+) -> Result<rocksdb::DB, rocksdb::Error> {
+    let names = rocksdb::DB::list_cf(opts, path.as_ref())?;
+    let descriptors = descriptors(names, opts, state_opts);
+    rocksdb::DB::open_cf_descriptors_as_secondary(opts, path, secondary_path, descriptors)
+}
+
+/// descriptors returns the descriptors of the column families, with state_opts on the STATE column family.
+fn descriptors(
+    names: Vec<String>,
+    opts: &Options,
+    state_opts: &Options,
+) -> Vec<ColumnFamilyDescriptor> {
+    names
+        .into_iter()
+        .map(|name| {
+            let cf_opts = if name == STATE { state_opts } else { opts };
+            ColumnFamilyDescriptor::new(name, cf_opts.clone())
+        })
+        .collect()
+}
+
+/// is_pending returns true if the database has the column families, and the keys are not moved to them yet.
+pub fn is_pending(conn: &rocksdb::DB) -> Result<bool, rocksdb::Error> {
+    Ok(is_enabled(conn) && conn.get(Prefix::KEYSPACE_FORMAT)?.is_none())
+}
+
+/// migrate moves the prefixed keys in the default column family to the column families,
+/// and sets Prefix::KEYSPACE_FORMAT. The keys are moved in batches, and each batch puts and deletes
+/// the same keys, so that the interrupted migration is continued on the next open.
+/// on_progress is called with the number of the moved keys after each batch.
+/// It returns the number of the moved keys.
+pub fn migrate(
+    conn: &rocksdb::DB,
+    on_progress: &mut dyn FnMut(usize),
+) -> Result<usize, rocksdb::Error> {
+    if !is_pending(conn)? {
+        return Ok(0);
+    }
+    let mut moved = 0;
+    for (prefix, _) in ROUTES.iter() {
+        let cf = match handle(conn, prefix) {
+            Some(cf) => cf,
+            None => continue,
+        };
+        let mut batch = WriteBatch::default();
+        // iterator reads from the implicit snapshot, so that the moved keys do not affect it
+        let iter = conn.iterator(IteratorMode::From(prefix, rocksdb::Direction::Forward));
+        for key_val in iter {
+            let (key, value) = key_val?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            batch.put_cf(cf, &key, &value);
+            batch.delete(&key);
+            moved += 1;
+            if moved % consts::KEYSPACE_MIGRATION_BATCH_SIZE == 0 {
+                conn.write(std::mem::take(&mut batch))?;
+                on_progress(moved);
+            }
+        }
+        conn.write(batch)?;
+        on_progress(moved);
+        conn.compact_range(Some(prefix.to_vec()), Some(utils::next_prefix(prefix)));
+    }
+    conn.put(Prefix::KEYSPACE_FORMAT, FORMAT_COLUMN_FAMILIES)?;
+
+    Ok(moved)
+}
+
+/// get returns the value of the key from its column family.
+pub fn get(conn: &rocksdb::DB, key: &[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+    match handle(conn, key) {
+        Some(cf) => conn.get_cf(cf, key),
+        None => conn.get(key),
+    }
+}
+
+/// iterator_opt iterates the column family of the prefix. The iteration is not bounded to the prefix,
+/// and it is in total order regardless of the prefix extractor.
+pub fn iterator_opt<'a>(
+    conn: &'a rocksdb::DB,
+    prefix: &[u8],
+    mode: IteratorMode,
+    mut opts: ReadOptions,
+) -> rocksdb::DBIterator<'a> {
+    opts.set_total_order_seek(true);
+    match handle(conn, prefix) {
+        Some(cf) => conn.iterator_cf_opt(cf, opts, mode),
+        None => conn.iterator_opt(mode, opts),
+    }
+}
+
+/// iterator iterates the column family of the prefix with the default read options.
+pub fn iterator<'a>(
+    conn: &'a rocksdb::DB,
+    prefix: &[u8],
+    mode: IteratorMode,
+) -> rocksdb::DBIterator<'a> {
+    iterator_opt(conn, prefix, mode, ReadOptions::default())
+}
+
+/// exists_prefix returns true if any key with the prefix is accepted, seeking the column family of the prefix.
+/// key_length is the prefix length of the prefix extractor of the column family, if configured.
+pub fn exists_prefix(
+    conn: &rocksdb::DB,
+    prefix: &[u8],
+    key_length: Option<usize>,
+    accept: impl FnMut(&[u8]) -> bool,
+) -> Result<bool, rocksdb::Error> {
+    let opts = prefix_filter::prefix_read_options(prefix, key_length);
+    let iter = match handle(conn, prefix) {
+        Some(cf) => conn.raw_iterator_cf_opt(cf, opts),
+        None => conn.raw_iterator_opt(opts),
+    };
+    prefix_filter::exists_prefix(iter, prefix, accept)
+}
+
+/// Router rebuilds the batch with the operations on the column families of the keys.
+struct Router<'a> {
+    conn: &'a rocksdb::DB,
+    batch: WriteBatch,
+}
+
+impl rocksdb::WriteBatchIterator for Router<'_> {
+    fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
+        put(self.conn, &mut self.batch, &key, &value);
+    }
+
+    fn delete(&mut self, key: Box<[u8]>) {
+        delete(self.conn, &mut self.batch, &key);
+    }
+}
+
+/// route returns the batch with the operations moved to the column families of the keys.
+/// Range deletions are not kept, so that they must be added with delete_range after routing.
+pub fn route(conn: &rocksdb::DB, batch: WriteBatch) -> WriteBatch {
+    if !is_enabled(conn) {
+        return batch;
+    }
+    let mut router = Router {
+        conn,
+        batch: WriteBatch::default(),
+    };
+    batch.iterate(&mut router);
+    router.batch
+}
+
+/// put adds the put of the key to the routed batch.
+pub fn put(conn: &rocksdb::DB, batch: &mut WriteBatch, key: &[u8], value: &[u8]) {
+    match handle(conn, key) {
+        Some(cf) => batch.put_cf(cf, key, value),
+        None => batch.put(key, value),
+    }
+}
+
+/// delete adds the deletion of the key to the routed batch.
+pub fn delete(conn: &rocksdb::DB, batch: &mut WriteBatch, key: &[u8]) {
+    match handle(conn, key) {
+        Some(cf) => batch.delete_cf(cf, key),
+        None => batch.delete(key),
+    }
+}
+
+/// delete_range adds the deletion of the range to the routed batch.
+/// The range is deleted in the column family of start, so that it must not span the prefixes.
+pub fn delete_range(conn: &rocksdb::DB, batch: &mut WriteBatch, start: &[u8], end: &[u8]) {
+    match handle(conn, start) {
+        Some(cf) => batch.delete_range_cf(cf, start, end),
+        None => batch.delete_range(start, end),
+    }
+}
+
+/// write_opt writes the batch routed to the column families.
+pub fn write_opt(
+    conn: &rocksdb::DB,
+    batch: WriteBatch,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), rocksdb::Error> {
+    conn.write_opt(route(conn, batch), write_opts)
+}
+
+/// write writes the batch routed to the column families with the default write options.
+pub fn write(conn: &rocksdb::DB, batch: WriteBatch) -> Result<(), rocksdb::Error> {
+    conn.write(route(conn, batch))
+}
+
+/// set_options sets the mutable options on the default and all the column families.
+pub fn set_options(conn: &rocksdb::DB, opts: &[(&str, &str)]) -> Result<(), rocksdb::Error> {
+    conn.set_options(opts)?;
+    for cf in handles(conn) {
+        conn.set_options_cf(cf, opts)?;
+    }
+    Ok(())
+}
+
+/// compact_range compacts the range in the column family of the start, or of the end if start is not specified.
+/// Unbounded range compacts all the column families.
+pub fn compact_range(conn: &rocksdb::DB, start: Option<&[u8]>, end: Option<&[u8]>) {
+    match start.or(end) {
+        Some(key) => match handle(conn, key) {
+            Some(cf) => conn.compact_range_cf(cf, start, end),
+            None => conn.compact_range(start, end),
+        },
+        None => {
+            conn.compact_range(start, end);
+            for cf in handles(conn) {
+                conn.compact_range_cf(cf, start, end);
+            }
+        },
+    }
+}
+
+/// ApproximateSize is the estimated bytes of a key range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApproximateSize {
+    /// sst_size is the total size of the SST files overlapping the range.
+    pub sst_size: u64,
+    /// memtable_size is the size of the memtables of the column family holding the range.
+    /// The memtables are not split by the keys, so that it is shared by the ranges in the same column family.
+    pub memtable_size: u64,
+}
+
+/// approximate_sizes estimates the sizes of the ranges from start (inclusive) to end (exclusive).
+/// Empty end is unbounded. Each range is looked up in the column family of its start,
+/// and the live files are read once for all the ranges.
+pub fn approximate_sizes(
+    conn: &rocksdb::DB,
+    ranges: &[(Vec<u8>, Vec<u8>)],
+) -> Result<Vec<ApproximateSize>, rocksdb::Error> {
+    let files = conn.live_files()?;
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            let cf = handle(conn, start);
+            let cf_name = cf.and(name(start)).unwrap_or(DEFAULT_COLUMN_FAMILY_NAME);
+            let sst_size = files
+                .iter()
+                .filter(|file| file.column_family_name == cf_name)
+                .filter(|file| {
+                    let after_start = file
+                        .end_key
+                        .as_deref()
+                        .map_or(true, |key| key >= start.as_slice());
+                    let before_end = end.is_empty()
+                        || file
+                            .start_key
+                            .as_deref()
+                            .map_or(true, |key| key < end.as_slice());
+                    after_start && before_end
+                })
+                .map(|file| file.size as u64)
+                .sum();
+            let memtable_size = match cf {
+                Some(cf) => conn.property_int_value_cf(cf, "rocksdb.cur-size-all-mem-tables")?,
+                None => conn.property_int_value("rocksdb.cur-size-all-mem-tables")?,
+            }
+            .unwrap_or_default();
+            Ok(ApproximateSize {
+                sst_size,
+                memtable_size,
+            })
+        })
+        .collect()
+}
+
+/// StateSnapshot is the snapshot of the state_db reading the keys from their column families.
+pub struct StateSnapshot<'a> {
+    conn: &'a rocksdb::DB,
+    snapshot: rocksdb::Snapshot<'a>,
+}
+
+impl<'a> StateSnapshot<'a> {
+    /// new takes the snapshot of all the column families.
+    pub fn new(conn: &'a rocksdb::DB) -> Self {
+        Self {
+            conn,
+            snapshot: conn.snapshot(),
+        }
+    }
+
+    /// get returns the value of the key from its column family.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        match handle(self.conn, key) {
+            Some(cf) => self.snapshot.get_cf(cf, key),
+            None => self.snapshot.get(key),
+        }
+    }
+
+    /// iterator_opt iterates the column family of the prefix. The iteration is not bounded to the prefix,
+    /// and it is in total order regardless of the prefix extractor.
+    pub fn iterator_opt(
+        &self,
+        prefix: &[u8],
+        mode: IteratorMode,
+        mut opts: ReadOptions,
+    ) -> rocksdb::DBIterator<'_> {
+        opts.set_total_order_seek(true);
+        match handle(self.conn, prefix) {
+            Some(cf) => self.snapshot.iterator_cf_opt(cf, opts, mode),
+            None => self.snapshot.iterator_opt(mode, opts),
+        }
+    }
+
+    /// iterator iterates the column family of the prefix with the default read options.
+    pub fn iterator(&self, prefix: &[u8], mode: IteratorMode) -> rocksdb::DBIterator<'_> {
+        self.iterator_opt(prefix, mode, ReadOptions::default())
+    }
+
+    /// exists_prefix returns true if any key with the prefix in the snapshot is accepted.
+    /// key_length is the prefix length of the prefix extractor of the column family, if configured.
+    pub fn exists_prefix(
+        &self,
+        prefix: &[u8],
+        key_length: Option<usize>,
+        accept: impl FnMut(&[u8]) -> bool,
+    ) -> Result<bool, rocksdb::Error> {
+        let opts = prefix_filter::prefix_read_options(prefix, key_length);
+        let iter = match handle(self.conn, prefix) {
+            Some(cf) => self.snapshot.raw_iterator_cf_opt(cf, opts),
+            None => self.snapshot.raw_iterator_opt(opts),
+        };
+        prefix_filter::exists_prefix(iter, prefix, accept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn legacy_db(path: &Path) -> rocksdb::DB {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        rocksdb::DB::open(&opts, path).unwrap()
+    }
+
+    fn open_db(path: &Path) -> rocksdb::DB {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        open(&opts, path, false).unwrap()
+    }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name(&[0, 1, 2]), Some(STATE));
+        assert_eq!(name(&[1, 1, 2]), Some(SMT));
+        assert_eq!(name(&[2, 1, 2]), Some(DIFF));
+        assert_eq!(name(Prefix::CURRENT_STATE), None);
+        assert_eq!(name(&[]), None);
+    }
+
+    #[test]
+    fn test_route_without_column_families() {
+        let temp_dir = TempDir::new("test_cf_legacy").unwrap();
+        let conn = legacy_db(temp_dir.path());
+        assert!(!is_enabled(&conn));
+
+        let mut batch = WriteBatch::default();
+        batch.put([0, 1], [1]);
+        write(&conn, batch).unwrap();
+        assert_eq!(conn.get([0, 1]).unwrap(), Some(vec![1]));
+        assert_eq!(get(&conn, &[0, 1]).unwrap(), Some(vec![1]));
+        assert_eq!(migrate(&conn, &mut |_| {}).unwrap(), 0);
+    }
+
+    #[cfg(not(feature = "legacy-keyspace"))]
+    #[test]
+    fn test_route_with_column_families() {
+        let temp_dir = TempDir::new("test_cf_route").unwrap();
+        let conn = open_db(temp_dir.path());
+        assert!(is_enabled(&conn));
+
+        let mut batch = WriteBatch::default();
+        batch.put([0, 1], [1]);
+        batch.put([1, 1], [2]);
+        batch.put([2, 1], [3]);
+        batch.put(Prefix::CURRENT_STATE, [4]);
+        batch.delete([0, 2]);
+        let mut batch = route(&conn, batch);
+        delete_range(&conn, &mut batch, &[2, 0], &[2, 1]);
+        conn.write(batch).unwrap();
+
+        assert_eq!(conn.get([0, 1]).unwrap(), None);
+        assert_eq!(conn.get(Prefix::CURRENT_STATE).unwrap(), Some(vec![4]));
+        let cf = conn.cf_handle(SMT).unwrap();
+        assert_eq!(conn.get_cf(cf, [1, 1]).unwrap(), Some(vec![2]));
+        for (key, value) in [([0, 1], 1), ([1, 1], 2), ([2, 1], 3)] {
+            assert_eq!(get(&conn, &key).unwrap(), Some(vec![value]));
+        }
+
+        let snapshot = StateSnapshot::new(&conn);
+        conn.put_cf(cf, [1, 2], [5]).unwrap();
+        assert_eq!(snapshot.get(&[1, 1]).unwrap(), Some(vec![2]));
+        assert_eq!(snapshot.get(&[1, 2]).unwrap(), None);
+        // reverse iteration from the next prefix stays in the column family
+        let keys: Vec<Vec<u8>> = snapshot
+            .iterator(
+                Prefix::STATE,
+                IteratorMode::From(Prefix::SMT, rocksdb::Direction::Reverse),
+            )
+            .map(|key_val| key_val.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(keys, vec![vec![0, 1]]);
+    }
+
+    #[cfg(not(feature = "legacy-keyspace"))]
+    #[test]
+    fn test_migrate() {
+        let temp_dir = TempDir::new("test_cf_migrate").unwrap();
+        {
+            let conn = legacy_db(temp_dir.path());
+            for i in 0..10_u8 {
+                conn.put([0, i], [i]).unwrap();
+                conn.put([1, i], [i]).unwrap();
+                conn.put([2, i], [i]).unwrap();
+            }
+            conn.put(Prefix::CURRENT_STATE, [9]).unwrap();
+        }
+        // column families are not created without migrate
+        let conn = open_with_state_options(
+            &Options::default(),
+            &Options::default(),
+            temp_dir.path(),
+            false,
+            false,
+        )
+        .unwrap();
+        assert!(!is_enabled(&conn));
+        drop(conn);
+
+        let conn = open_db(temp_dir.path());
+        assert_eq!(get(&conn, &[0, 3]).unwrap(), None);
+        let mut reported = vec![];
+        assert_eq!(
+            migrate(&conn, &mut |moved| reported.push(moved)).unwrap(),
+            30
+        );
+        assert_eq!(reported.last(), Some(&30));
+        assert_eq!(migrate(&conn, &mut |_| {}).unwrap(), 0);
+        for i in 0..10_u8 {
+            for prefix in 0..3_u8 {
+                assert_eq!(conn.get([prefix, i]).unwrap(), None);
+                assert_eq!(get(&conn, &[prefix, i]).unwrap(), Some(vec![i]));
+            }
+        }
+        assert_eq!(conn.get(Prefix::CURRENT_STATE).unwrap(), Some(vec![9]));
+        drop(conn);
+
+        // column families are opened on read only without the legacy keys
+        let conn = open(&Options::default(), temp_dir.path(), true).unwrap();
+        assert_eq!(get(&conn, &[1, 4]).unwrap(), Some(vec![4]));
+    }
+
+    #[cfg(not(feature = "legacy-keyspace"))]
+    #[test]
+    fn test_migrate_resumes() {
+        let temp_dir = TempDir::new("test_cf_migrate_resumes").unwrap();
+        let conn = open_db(temp_dir.path());
+        let cf = conn.cf_handle(STATE).unwrap();
+        // interrupted migration moved some of the keys without setting the format
+        for i in 0..5_u8 {
+            conn.put_cf(cf, [0, i], [i]).unwrap();
+        }
+        for i in 5..10_u8 {
+            conn.put([0, i], [i]).unwrap();
+        }
+
+        assert_eq!(migrate(&conn, &mut |_| {}).unwrap(), 5);
+        for i in 0..10_u8 {
+            assert_eq!(get(&conn, &[0, i]).unwrap(), Some(vec![i]));
+        }
+        assert!(conn.get(Prefix::KEYSPACE_FORMAT).unwrap().is_some());
+    }
+
+    #[cfg(not(feature = "legacy-keyspace"))]
+    #[test]
+    fn test_approximate_sizes() {
+        let temp_dir = TempDir::new("test_cf_approximate_sizes").unwrap();
+        let conn = open_db(temp_dir.path());
+        let mut batch = WriteBatch::default();
+        for i in 0..100_u8 {
+            batch.put([0, 1, i], [i; 100]);
+            batch.put([1, 1, i], [i; 100]);
+        }
+        write(&conn, batch).unwrap();
+
+        let ranges = vec![
+            (vec![0, 1], vec![0, 2]),
+            (vec![0, 2], vec![0, 3]),
+            (vec![1], vec![]),
+        ];
+        let sizes = approximate_sizes(&conn, &ranges).unwrap();
+        assert_eq!(sizes[0].sst_size, 0);
+        assert!(sizes[0].memtable_size > 0);
+
+        conn.flush().unwrap();
+        for cf in handles(&conn) {
+            conn.flush_cf(cf).unwrap();
+        }
+        let sizes = approximate_sizes(&conn, &ranges).unwrap();
+        assert!(sizes[0].sst_size > 0);
+        assert_eq!(sizes[1].sst_size, 0);
+        assert!(sizes[2].sst_size > 0);
+    }
+}
@@ -0,0 +1,183 @@
+/// conditional_write provides the compare-and-set write of a Database batch.
+/// The conditions are checked against the latest data and the batch is written only if all of them hold.
+/// The check and the write are linearizable as long as every other write to the connection holds the same lock,
+/// which the Database does for the writes from the JS thread, and the writes on the DB thread are serialized by the thread.
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+use crate::database::column_families;
+
+/// Condition is the expected state of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// Equals holds if the key exists with the value.
+    Equals(Vec<u8>),
+    /// NotExists holds if the key does not exist.
+    NotExists,
+}
+
+/// KeyCondition is the condition of the key checked before the write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyCondition {
+    pub key: Vec<u8>,
+    pub condition: Condition,
+}
+
+#[derive(Error, Debug)]
+pub enum ConditionalWriteError {
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+    /// Failed is the first key whose condition does not hold. Nothing is written.
+    #[error("condition failed for key {}", hex::encode(.0))]
+    Failed(Vec<u8>),
+}
+
+impl KeyCondition {
+    /// holds returns true if the current value satisfies the condition.
+    pub fn holds(&self, current: Option<&[u8]>) -> bool {
+        match (&self.condition, current) {
+            (Condition::Equals(expected), Some(value)) => expected.as_slice() == value,
+            (Condition::NotExists, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// check returns Failed with the first key in the order of the conditions which does not hold.
+pub fn check(
+    conn: &rocksdb::DB,
+    conditions: &[KeyCondition],
+) -> Result<(), ConditionalWriteError> {
+    for condition in conditions {
+        let current = column_families::get(conn, &condition.key)?;
+        if !condition.holds(current.as_deref()) {
+            return Err(ConditionalWriteError::Failed(condition.key.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// write_if writes the batch atomically if all the conditions hold.
+/// The write lock is held from the check until the batch is written.
+pub fn write_if(
+    conn: &rocksdb::DB,
+    write_lock: &Mutex<()>,
+    conditions: &[KeyCondition],
+    batch: rocksdb::WriteBatch,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), ConditionalWriteError> {
+    let _guard = write_lock.lock().unwrap();
+    check(conn, conditions)?;
+    conn.write_opt(batch, write_opts)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::Arc;
+    use std::thread;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn equals(key: &[u8], value: &[u8]) -> KeyCondition {
+        KeyCondition {
+            key: key.to_vec(),
+            condition: Condition::Equals(value.to_vec()),
+        }
+    }
+
+    fn not_exists(key: &[u8]) -> KeyCondition {
+        KeyCondition {
+            key: key.to_vec(),
+            condition: Condition::NotExists,
+        }
+    }
+
+    fn put_batch(key: &[u8], value: &[u8]) -> rocksdb::WriteBatch {
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put(key, value);
+        batch
+    }
+
+    #[test]
+    fn test_write_if() {
+        let temp_dir = TempDir::new("test_conditional_write").unwrap();
+        let conn = rocksdb::DB::open_default(temp_dir.path()).unwrap();
+        let lock = Mutex::new(());
+        let write_opts = rocksdb::WriteOptions::default();
+        conn.put([1], [1]).unwrap();
+
+        write_if(
+            &conn,
+            &lock,
+            &[equals(&[1], &[1]), not_exists(&[2])],
+            put_batch(&[2], &[2]),
+            &write_opts,
+        )
+        .unwrap();
+        assert_eq!(conn.get([2]).unwrap(), Some(vec![2]));
+
+        // the first failing key is reported, and nothing is written
+        for (conditions, failed) in [
+            (vec![equals(&[1], &[2]), not_exists(&[2])], vec![1]),
+            (vec![equals(&[1], &[1]), not_exists(&[2])], vec![2]),
+            (vec![equals(&[3], &[1])], vec![3]),
+        ] {
+            let err = write_if(
+                &conn,
+                &lock,
+                &conditions,
+                put_batch(&[4], &[4]),
+                &write_opts,
+            )
+            .unwrap_err();
+            assert!(matches!(err, ConditionalWriteError::Failed(key) if key == failed));
+        }
+        assert_eq!(conn.get([4]).unwrap(), None);
+        assert_eq!(
+            ConditionalWriteError::Failed(vec![0xab]).to_string(),
+            "condition failed for key ab"
+        );
+    }
+
+    #[test]
+    fn test_write_if_is_linearizable() {
+        let temp_dir = TempDir::new("test_conditional_write_concurrent").unwrap();
+        let conn = Arc::new(rocksdb::DB::open_default(temp_dir.path()).unwrap());
+        let lock = Arc::new(Mutex::new(()));
+        conn.put([0], 0_u32.to_be_bytes()).unwrap();
+
+        // each increment succeeds only on the value it read, so that no increment is lost
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let conn = Arc::clone(&conn);
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    let mut succeeded = 0;
+                    while succeeded < 50 {
+                        let current = conn.get([0]).unwrap().unwrap();
+                        let next = u32::from_be_bytes(current.as_slice().try_into().unwrap()) + 1;
+                        let result = write_if(
+                            &conn,
+                            &lock,
+                            &[equals(&[0], &current)],
+                            put_batch(&[0], &next.to_be_bytes()),
+                            &rocksdb::WriteOptions::default(),
+                        );
+                        if result.is_ok() {
+                            succeeded += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(conn.get([0]).unwrap(), Some(200_u32.to_be_bytes().to_vec()));
+    }
+}
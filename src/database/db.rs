@@ -1,36 +1,83 @@
 /// db is the interface for Database binding using rocksDB.
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
-
-use crate::batch;
-use crate::database::options::IterationOption;
-use crate::database::traits::{JsNewWithBoxRef, Unwrap};
+use sha2::{Digest, Sha256};
+
+use crate::batch::{self, BatchClaim};
+use crate::database::checkpoint_group::{self, Member};
+use crate::database::chunked_write;
+use crate::database::conditional_write::{self, Condition, KeyCondition};
+use crate::database::metrics::{self, Operation};
+use crate::database::options::{
+    CheckpointOption, ChunkedIterationOption, IterationOption, WriteOption,
+};
+use crate::database::prefix_filter;
+use crate::database::staged;
+use crate::database::traits::{Closable, JsNewWithBoxRef, Unwrap};
 use crate::database::types::JsBoxRef;
 use crate::database::utils;
 use crate::database::DB;
+use crate::error::{self, DbError};
+use crate::state::state_db::SharedStateDB;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Error {
     message: String,
 }
 
+/// conditions_from_js returns the conditions of the argument at the index.
+/// Each entry must have either expectedValue or mustNotExist set to true.
+fn conditions_from_js(ctx: &mut FunctionContext, index: i32) -> NeonResult<Vec<KeyCondition>> {
+    let values = ctx.argument::<JsArray>(index)?.to_vec(ctx)?;
+    let mut conditions = Vec::with_capacity(values.len());
+    for value in values {
+        let obj = value.downcast_or_throw::<JsObject, _>(ctx)?;
+        let key = obj
+            .get::<JsTypedArray<u8>, _, _>(ctx, "key")?
+            .as_slice(ctx)
+            .to_vec();
+        let expected = obj
+            .get_opt::<JsTypedArray<u8>, _, _>(ctx, "expectedValue")?
+            .map(|val| val.as_slice(ctx).to_vec());
+        let must_not_exist = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "mustNotExist")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        let condition = match (expected, must_not_exist) {
+            (Some(expected), false) => Condition::Equals(expected),
+            (None, true) => Condition::NotExists,
+            _ => {
+                return error::invalid_argument(
+                    "condition must have either expectedValue or mustNotExist",
+                )
+                .throw(ctx)
+            },
+        };
+        conditions.push(KeyCondition { key, condition });
+    }
+    Ok(conditions)
+}
+
 pub type SharedDatabase = JsBoxRef<Database>;
 pub type Database = DB;
 impl JsNewWithBoxRef for Database {}
 impl Database {
-    fn send_over_channel(
+    pub(crate) fn send_over_channel<E: Into<DbError> + Send + 'static>(
         channel: &Channel,
         callback: Root<JsFunction>,
-        result: Result<(), rocksdb::Error>,
+        result: Result<(), E>,
     ) {
         channel.send(move |mut ctx| {
             let callback = callback.into_inner(&mut ctx);
             let this = ctx.undefined();
             let args: Vec<Handle<JsValue>> = match result {
                 Ok(_) => vec![ctx.null().upcast()],
-                Err(err) => vec![ctx.error(&err)?.upcast()],
+                Err(err) => {
+                    let err: DbError = err.into();
+                    vec![err.to_js(&mut ctx)?.upcast()]
+                },
             };
 
             callback.call(&mut ctx, this, args)?;
@@ -49,31 +96,54 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_backpressure(&mut ctx)?;
         let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
 
         let conn = db.arc_clone();
-        db.send(move |channel| {
+        let durability = db.durability();
+        let callback = db.watch_callback(callback);
+        db.send_write(move |channel| {
+            let callback = match callback.start() {
+                Some(callback) => callback,
+                None => return,
+            };
             let mut batch = rocksdb::WriteBatch::default();
-            let conn_iter = conn.unwrap().iterator(rocksdb::IteratorMode::Start);
+            let conn_iter = conn.unwrap().iterator_opt(
+                rocksdb::IteratorMode::Start,
+                prefix_filter::total_order_read_options(),
+            );
             for key_val in conn_iter {
                 batch.delete(&(key_val.unwrap().0));
             }
-            let result = conn.unwrap().write(batch);
+            let result = conn
+                .unwrap()
+                .write_opt(batch, &durability.write_options(false));
             Database::send_over_channel(channel, callback, result);
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
     /// js_close is handler for JS ffi.
     /// js "this" - DB.
+    /// - @params(0) - callback called after the connection is released (optional).
+    /// - @callback(0) - Error.
     pub fn js_close(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
-        ctx.this()
-            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?
-            .borrow_mut()
-            .close()
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        let callback = ctx
+            .argument_opt(0)
+            .and_then(|val| val.downcast::<JsFunction, _>(&mut ctx).ok())
+            .map(|val| val.root(&mut ctx));
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let mut db = db.borrow_mut();
+        db.close()
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        if let Some(callback) = callback {
+            db.on_released(ctx.channel(), callback);
+        }
 
         Ok(ctx.undefined())
     }
@@ -82,7 +152,7 @@ impl Database {
     /// js "this" - DB.
     /// - @params(0) - key to get from db.
     /// - @params(1) - callback to return the fetched value.
-    /// - @callback(0) - Error. If data is not found, it will call the callback with "No data" as a first args.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
     /// - @callback(1) - [u8]. Value associated with the key.
     pub fn js_get(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
@@ -91,9 +161,10 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
 
         db.get_by_key(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -111,9 +182,31 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
 
         db.exists(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_exists_prefix is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - prefix to check existence of any key with it from db.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error
+    /// - @callback(1) - bool
+    pub fn js_exists_prefix(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let prefix = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.exists_prefix(prefix, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -132,12 +225,16 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_over_limits(&mut ctx, &key, Some(&value))?;
+        db.throw_if_backpressure(&mut ctx)?;
 
         let result = db.put(&key, &value);
-        db.send(move |channel| {
+        // the write is applied on the call, so that it is not timed out
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
             Database::send_over_channel(channel, callback, result);
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -154,58 +251,251 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_over_limits(&mut ctx, &key, None)?;
+        db.throw_if_backpressure(&mut ctx)?;
 
         let result = db.delete(&key);
-        db.send(move |channel| {
+        // the write is applied on the call, so that it is not timed out
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
             Database::send_over_channel(channel, callback, result);
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
     /// js_write is handler for JS ffi.
+    /// The batch is consumed when it is written, and it cannot be updated or written again until it is reset.
     /// js "this" - DB.
-    /// - @params(0) - Batch
-    /// - @params(1) - callback to return the fetched value.
+    /// - @params(0) - Batch. Nothing is written if any operation is not within the size limits.
+    /// It must not be consumed, nor being written by another call. Failed write leaves it unconsumed for the retry.
+    /// - @params(1) - options { maxBatchBytes: u32, journalKey: &[u8] }. Batch is written in chunks if maxBatchBytes is specified.
+    /// - @params(2) - callback to return the fetched value.
     /// - @callback(0) - Error
     pub fn js_write(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let batch = ctx
             .argument::<batch::SendableWriteBatch>(0)?
             .downcast_or_throw::<batch::SendableWriteBatch, _>(&mut ctx)?;
-        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let options = ctx.argument_opt(1);
+        let options = WriteOption::new(&mut ctx, options)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
 
         let db = ctx
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_backpressure(&mut ctx)?;
 
         let batch = Arc::clone(&batch.borrow());
+        batch.lock().unwrap().throw_if_closed(&mut ctx)?;
+        db.throw_if_batch_over_limits(&mut ctx, &batch.lock().unwrap().batch)?;
+        let claim = BatchClaim::new(&batch).or_else(|err| DbError::from(err).throw(&mut ctx))?;
         let conn = db.arc_clone();
-        db.send(move |channel| {
-            let write_batch = rocksdb::WriteBatch::default();
-            let inner_batch = batch.lock().unwrap();
-            let mut write_batch = batch::WriteBatch { batch: write_batch };
-            inner_batch.batch.iterate(&mut write_batch);
-            let result = conn.unwrap().write(write_batch.batch);
+        let durability = db.durability();
+        let callback = db.watch_callback(callback);
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            let callback = match callback.start() {
+                Some(callback) => callback,
+                None => return,
+            };
+            let write_batch = batch.lock().unwrap().clone();
+            let result = match options.max_batch_bytes {
+                Some(max_batch_bytes) => {
+                    let identity = Sha256::digest(write_batch.batch.data()).to_vec();
+                    chunked_write::write_in_chunks(
+                        conn.unwrap(),
+                        &write_batch.batch,
+                        max_batch_bytes,
+                        &options.journal_key,
+                        &identity,
+                        &durability.write_options(false),
+                    )
+                    .map_err(DbError::from)
+                },
+                None => conn
+                    .unwrap()
+                    .write_opt(write_batch.batch, &durability.write_options(false))
+                    .map_err(DbError::from),
+            };
+            claim.finish(result.is_ok());
+            Database::send_over_channel(channel, callback, result);
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_conditional_write is handler for JS ffi.
+    /// The conditions are checked and the batch is written on the DB thread while holding the write lock of set and del,
+    /// so that no other write of the Database is applied between the check and the write.
+    /// js "this" - DB.
+    /// - @params(0) - conditions [{ key: &[u8], expectedValue?: &[u8], mustNotExist?: bool }], checked in order.
+    /// - @params(1) - Batch. It is written atomically only if all the conditions hold.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error. ERR_CONDITION_FAILED with the first failing key if any condition does not hold.
+    pub fn js_conditional_write(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let conditions = conditions_from_js(&mut ctx, 0)?;
+        let batch = ctx
+            .argument::<batch::SendableWriteBatch>(1)?
+            .downcast_or_throw::<batch::SendableWriteBatch, _>(&mut ctx)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        for condition in conditions.iter() {
+            db.throw_if_over_limits(&mut ctx, &condition.key, None)?;
+        }
+        db.throw_if_backpressure(&mut ctx)?;
+
+        let batch = Arc::clone(&batch.borrow());
+        batch.lock().unwrap().throw_if_closed(&mut ctx)?;
+        db.throw_if_batch_over_limits(&mut ctx, &batch.lock().unwrap().batch)?;
+        let claim = BatchClaim::new(&batch).or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        let conn = db.arc_clone();
+        let write_lock = db.write_lock();
+        let durability = db.durability();
+        let callback = db.watch_callback(callback);
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            let callback = match callback.start() {
+                Some(callback) => callback,
+                None => return,
+            };
+            let write_batch = batch.lock().unwrap().clone();
+            let result = conditional_write::write_if(
+                conn.unwrap(),
+                &write_lock,
+                &conditions,
+                write_batch.batch,
+                &durability.write_options(false),
+            );
+            // the batch is released for the retry when the conditions do not hold
+            claim.finish(result.is_ok());
             Database::send_over_channel(channel, callback, result);
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_write_prepared is handler for JS ffi.
+    /// The batch is staged under the id without applying it, until it is resolved by js_recover_staged.
+    /// js "this" - DB.
+    /// - @params(0) - id of the staged batch, which is passed to the StateDB commit as stagedId.
+    /// - @params(1) - Batch. Nothing is staged if any operation is not within the size limits.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error
+    pub fn js_write_prepared(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let id = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        if id.is_empty() {
+            return error::invalid_argument("id must not be empty").throw(&mut ctx);
+        }
+        let batch = ctx
+            .argument::<batch::SendableWriteBatch>(1)?
+            .downcast_or_throw::<batch::SendableWriteBatch, _>(&mut ctx)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_backpressure(&mut ctx)?;
+
+        let batch = Arc::clone(&batch.borrow());
+        batch.lock().unwrap().throw_if_closed(&mut ctx)?;
+        db.throw_if_batch_over_limits(&mut ctx, &batch.lock().unwrap().batch)?;
+        let claim = BatchClaim::new(&batch).or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        let conn = db.arc_clone();
+        let durability = db.durability();
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            let write_batch = batch.lock().unwrap().clone();
+            let result = staged::write_prepared(
+                conn.unwrap(),
+                &id,
+                &write_batch.batch,
+                &durability.write_options(false),
+            );
+            claim.finish(result.is_ok());
+            Database::send_over_channel(channel, callback, result);
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_recover_staged is handler for JS ffi.
+    /// Staged batches marked by the commits of the StateDB are applied, and the others are discarded.
+    /// It must not be called while a commit with stagedId is in progress.
+    /// js "this" - DB.
+    /// - @params(0) - StateDB which the batches are staged for.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { applied: &[u8][], discarded: &[u8][] }. Ids of the staged batches.
+    pub fn js_recover_staged(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let state_db = ctx.argument::<SharedStateDB>(0)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let state_db = state_db.borrow();
+        state_db.throw_if_closed(&mut ctx)?;
+        let state_conn = state_db.arc_conn();
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.arc_clone();
+        let durability = db.durability();
+        db.send_write(move |channel| {
+            let result = staged::recover(
+                conn.unwrap(),
+                state_conn.unwrap(),
+                &durability.write_options(false),
+            );
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(report) => {
+                        let obj = ctx.empty_object();
+                        let applied = report.applied.into_iter().map(Some).collect();
+                        let applied = utils::values_to_js_array(&mut ctx, applied)?;
+                        obj.set(&mut ctx, "applied", applied)?;
+                        let discarded = report.discarded.into_iter().map(Some).collect();
+                        let discarded = utils::values_to_js_array(&mut ctx, discarded)?;
+                        obj.set(&mut ctx, "discarded", discarded)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
     /// js_iterate is handler for JS ffi.
     /// js "this" - DB.
-    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8]}.
+    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], chunkSize: u32, keysOnly: bool}.
     /// - @params(1) - Callback to be called on each data iteration.
     /// - @params(2) - callback to be called when completing the iteration.
     /// - @callback1(0) - Error.
-    /// - @callback1(1) - { key: &[u8], value: &[u8]}.
-    /// - @callback(0) - void.
+    /// - @callback1(1) - { key: &[u8], value: &[u8]}. Value is not set with keysOnly.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool. True if the iteration is stopped by maxIterationResultSize.
     pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let option_inputs = ctx.argument::<JsObject>(0)?;
-        let options = IterationOption::new(&mut ctx, option_inputs);
+        let mut options = ChunkedIterationOption::new(&mut ctx, option_inputs)?;
         let callback_on_data = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
         let callback_done = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
 
@@ -213,47 +503,171 @@ impl Database {
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        options.max_result_size = db.max_iteration_result_size();
 
-        let callback_on_data = Arc::new(Mutex::new(callback_on_data));
         let conn = db.arc_clone();
-        db.send(move |channel| {
-            let iter =
-                conn.unwrap()
-                    .iterator(utils::get_iteration_mode(&options, &mut vec![], false));
-            for (counter, key_val) in iter.enumerate() {
-                if utils::is_key_out_of_range(
-                    &options,
-                    &(key_val.as_ref().unwrap().0),
-                    counter as i64,
-                    false,
-                ) {
-                    break;
-                }
-                let callback_on_data = Arc::clone(&callback_on_data);
-                channel.send(move |mut ctx| {
-                    let obj = ctx.empty_object();
-                    let key_res =
-                        JsBuffer::external(&mut ctx, key_val.as_ref().unwrap().0.clone());
-                    let val_res = JsBuffer::external(&mut ctx, key_val.unwrap().1);
-                    obj.set(&mut ctx, "key", key_res)?;
-                    obj.set(&mut ctx, "value", val_res)?;
-                    let callback = callback_on_data.lock().unwrap().to_inner(&mut ctx);
-                    let this = ctx.undefined();
-                    let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), obj.upcast()];
-                    callback.call(&mut ctx, this, args)?;
-                    Ok(())
-                });
-            }
-            channel.send(move |mut ctx| {
-                let callback_done = callback_done.into_inner(&mut ctx);
-                let this = ctx.undefined();
-                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
-                callback_done.call(&mut ctx, this, args)?;
+        let closed = db.closed_flag();
+        db.send_timed(db.timer(Operation::Iterate), move |channel| {
+            let mut start = vec![];
+            let iter = conn.unwrap().iterator_opt(
+                utils::get_iteration_mode(&options.iteration, &mut start, &[]),
+                utils::get_read_options(&options.iteration),
+            );
+            utils::send_in_chunks(
+                channel,
+                iter,
+                &options,
+                &[],
+                &closed,
+                callback_on_data,
+                callback_done,
+            );
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
-                Ok(())
-            });
+        Ok(ctx.undefined())
+    }
+
+    /// js_count_range is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - Options for the range. {limit: u32, gte: &[u8], lte: &[u8], prefix: &[u8]}. Counting stops at the limit.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - number of the keys in the range.
+    pub fn js_count_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let option_inputs = ctx.argument::<JsObject>(0)?;
+        let options = IterationOption::new(&mut ctx, option_inputs)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.arc_clone();
+        db.send(move |channel| {
+            let mut start = vec![];
+            let iter = conn.unwrap().iterator_opt(
+                utils::get_iteration_mode(&options, &mut start, &[]),
+                prefix_filter::total_order_read_options(),
+            );
+            let result = utils::count_in_range(iter, &options, 0, &[], |_| false);
+            utils::send_count(channel, callback, result);
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_compact is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - start key of the range to compact. Compacts from the beginning if null.
+    /// - @params(1) - end key of the range to compact. Compacts until the end if null.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_compact(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let start = utils::get_optional_bytes(&mut ctx, 0)?;
+        let end = utils::get_optional_bytes(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.compact(start, end, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_approximate_size is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - start key of the range. Starts from the beginning if null.
+    /// - @params(1) - end key of the range, exclusive. Continues until the end if null.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { sstSize: number, memtableSize: number }.
+    pub fn js_approximate_size(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let start = utils::get_optional_bytes(&mut ctx, 0)?;
+        let end = utils::get_optional_bytes(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let range = (start.unwrap_or_default(), end.unwrap_or_default());
+        db.approximate_sizes(vec![range], true, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_flush is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_flush(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.flush(callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_delete_range is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - start key of the range to delete (inclusive).
+    /// - @params(1) - end key of the range to delete (exclusive).
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_delete_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let start = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let end = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_backpressure(&mut ctx)?;
+
+        db.delete_range(start, end, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_stats is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { [name: string]: number }. Properties which are not available are omitted.
+    pub fn js_stats(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.stats(callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -261,20 +675,90 @@ impl Database {
     /// js_checkpoint is handler for JS ffi.
     /// js "this" - DB.
     /// - @params(0) - path to create the checkpoint.
-    /// - @params(1) - callback to return the result.
-    /// - @callback(0) - Error.
+    /// - @params(1) - Options for checkpoint. {sync: bool, flushBeforeCheckpoint: bool}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error. If the created checkpoint cannot be opened, it will call the callback with the error.
+    /// - @callback(1) - { size: u64, sstFiles: u64 }.
     pub fn js_checkpoint(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
-        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let option = ctx.argument_opt(1);
+        let option = CheckpointOption::new(&mut ctx, option)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
 
         let db = ctx
             .this()
             .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
 
-        db.checkpoint(path, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        db.checkpoint(path, option, None, None, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
+
+    /// js_checkpoint_with_group is handler for JS ffi.
+    /// The checkpoint is the blockchain member of the group, and the manifest is written when both members finished.
+    /// js "this" - DB.
+    /// - @params(0) - token of the checkpoint group.
+    /// - @params(1) - path to create the checkpoint.
+    /// - @params(2) - Options for checkpoint. {sync: bool, flushBeforeCheckpoint: bool}.
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error. If the manifest cannot be written, it will call the callback with the error.
+    /// - @callback(1) - { size: u64, sstFiles: u64 }.
+    pub fn js_checkpoint_with_group(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let token = checkpoint_group::get_token(&mut ctx, 0)?;
+        let path = ctx.argument::<JsString>(1)?.value(&mut ctx);
+        let option = ctx.argument_opt(2);
+        let option = CheckpointOption::new(&mut ctx, option)?;
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let membership = checkpoint_group::join(token, Member::Blockchain, &path, None)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        db.checkpoint(path, option, None, Some(membership), callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_pending_operations is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @returns - number of the operations queued on the DB thread, including the delayed writes.
+    pub fn js_pending_operations(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let pending = db.borrow().pending_operations();
+
+        Ok(ctx.number(pending as f64))
+    }
+
+    /// js_get_metrics is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - reset the counters after the snapshot (optional).
+    /// - @return - { bucketBoundsMs: number[], [operation]: { queueWait, execution } }, or null if metrics are disabled.
+    pub fn js_get_metrics(mut ctx: FunctionContext) -> JsResult<JsValue> {
+        let reset = ctx
+            .argument_opt(0)
+            .and_then(|val| val.downcast::<JsBoolean, _>(&mut ctx).ok())
+            .map(|val| val.value(&mut ctx))
+            .unwrap_or(false);
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let snapshot = match db.metrics() {
+            Some(metrics) => metrics.snapshot(reset),
+            None => return Ok(ctx.null().upcast()),
+        };
+
+        Ok(metrics::metrics_to_js_object(&mut ctx, &snapshot)?.upcast())
+    }
 }
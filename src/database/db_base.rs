@@ -1,20 +1,256 @@
 /// db_base provides common functionality for Database.
-use std::sync::{mpsc, Arc};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use neon::context::Context;
 use neon::event::Channel;
 use neon::handle::{Handle, Root};
-use neon::types::{Finalize, JsBuffer, JsFunction, JsValue};
+use neon::object::Object;
+use neon::result::{JsResult, NeonResult};
+use neon::types::{Finalize, JsArray, JsBuffer, JsFunction, JsObject, JsValue};
 use rocksdb::checkpoint::Checkpoint;
+use tempdir::TempDir;
+use thiserror::Error;
 
-use crate::database::traits::{NewDBWithContext, Unwrap};
-use crate::database::types::{ArcOptionDB, DbMessage, DbOptions, Kind};
+use crate::consts::{self, Prefix};
+use crate::database::checkpoint_group::Membership;
+use crate::database::chunked_write::{self, ChunkedWriteError};
+use crate::database::column_families::{self, ApproximateSize};
+use crate::database::metrics::{Metrics, Operation, Timer};
+use crate::database::options::CheckpointOption;
+use crate::database::prefix_filter;
+use crate::database::registry::{self, ClosedFlag, Registration, REGISTRY};
+use crate::database::timeout::{Pending, Watchdog};
+use crate::database::traits::{Closable, NewDBWithContext, Unwrap};
+use crate::database::types::{
+    ArcOptionDB, DbCallback, DbMessage, DbOptions, Kind, SizeLimits, WriteDurability, WriteLimit,
+};
+use crate::error::DbError;
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt;
+use crate::state::current_state::CurrentState;
+use crate::state::value_compression;
+use crate::types::{KeyLength, VecOption};
+use crate::utils;
+
+/// NUM_LEVELS is the number of levels reported with num-files-at-level property.
+const NUM_LEVELS: usize = 7;
+
+/// STATS_PROPERTIES is the list of rocksdb properties reported by stats, with the name exposed to JS.
+const STATS_PROPERTIES: [(&str, &str); 4] = [
+    ("rocksdb.estimate-num-keys", "estimateNumKeys"),
+    ("rocksdb.cur-size-all-mem-tables", "curSizeAllMemTables"),
+    (
+        "rocksdb.estimate-pending-compaction-bytes",
+        "estimatePendingCompactionBytes",
+    ),
+    ("rocksdb.block-cache-usage", "blockCacheUsage"),
+];
+
+/// SHARED_PROPERTIES is the properties of the resources shared by the column families, which are not summed up.
+const SHARED_PROPERTIES: [&str; 1] = ["rocksdb.block-cache-usage"];
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("{0}")]
+    RocksDB(#[from] rocksdb::Error),
+    #[error("failed to sync checkpoint: `{0}`")]
+    Sync(#[from] io::Error),
+    #[error("invalid checkpoint: {0}")]
+    Invalid(String),
+}
+
+/// CheckpointInfo is the metadata of the verified checkpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckpointInfo {
+    /// size is the total bytes of the files in the checkpoint directory.
+    pub size: u64,
+    pub sst_files: u64,
+    /// root is the state root read from the checkpoint. It is only set for the state database.
+    pub root: VecOption,
+}
+
+/// Backpressure is the error of the write rejected because the queue of the database thread is full.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("backpressure: {0} operations are pending")]
+pub struct Backpressure(pub usize);
+
+impl From<Backpressure> for DbError {
+    fn from(err: Backpressure) -> Self {
+        DbError::Busy(err.0)
+    }
+}
+
+impl From<CheckpointError> for DbError {
+    fn from(err: CheckpointError) -> Self {
+        match err {
+            CheckpointError::RocksDB(err) => DbError::RocksDb(err),
+            CheckpointError::Sync(err) => DbError::Io(err),
+            err @ CheckpointError::Invalid(_) => DbError::Corrupted(err.to_string()),
+        }
+    }
+}
+
+/// checkpoint_info_to_js_object converts the info to { size: u64, sstFiles: u64, root?: &[u8] }.
+pub fn checkpoint_info_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    info: &CheckpointInfo,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let size = ctx.number(info.size as f64);
+    obj.set(ctx, "size", size)?;
+    let sst_files = ctx.number(info.sst_files as f64);
+    obj.set(ctx, "sstFiles", sst_files)?;
+    if let Some(root) = info.root.as_ref() {
+        let root = JsBuffer::external(ctx, root.clone());
+        obj.set(ctx, "root", root)?;
+    }
+
+    Ok(obj)
+}
+
+/// approximate_size_to_js_object converts the size to { sstSize: number, memtableSize: number }.
+pub fn approximate_size_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    size: &ApproximateSize,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let sst_size = ctx.number(size.sst_size as f64);
+    obj.set(ctx, "sstSize", sst_size)?;
+    let memtable_size = ctx.number(size.memtable_size as f64);
+    obj.set(ctx, "memtableSize", memtable_size)?;
+
+    Ok(obj)
+}
+
+/// PendingCallback is the callback of the operation queued on the database thread.
+/// The operation must call it only if start returns it, as it is called by the watchdog on timeout.
+pub type PendingCallback = Pending<Root<JsFunction>>;
+
+/// WriteQueue counts the operations queued on the database thread,
+/// and holds the writes delayed by the back-pressure until the queue drains below the low-water mark.
+#[derive(Default)]
+pub struct WriteQueue {
+    limit: Option<WriteLimit>,
+    queued: AtomicUsize,
+    delayed: Mutex<VecDeque<DbCallback>>,
+}
 
 pub struct DB {
     tx: mpsc::Sender<DbMessage>,
     db_kind: Kind,
     db: ArcOptionDB,
+    registration: Registration,
+    queue: Arc<WriteQueue>,
+    durability: WriteDurability,
+    // secondary_dir must be dropped after db, see open_connection.
+    secondary_dir: Option<TempDir>,
+    size_limits: SizeLimits,
+    metrics: Option<Arc<Metrics>>,
+    watchdog: Option<Arc<Watchdog<Root<JsFunction>>>>,
+    /// write_lock is held by the writes from the JS thread and by the conditional writes from the check until the write.
+    write_lock: Arc<Mutex<()>>,
+    prefix_key_length: Option<usize>,
+    max_iteration_result_size: Option<usize>,
+    released: Arc<Released>,
+}
+
+type ReleasedCallback = Box<dyn FnOnce() + Send>;
+
+/// Released calls the callbacks after the connection is dropped on close, when the LOCK file is released.
+#[derive(Default)]
+struct Released {
+    state: Mutex<(bool, Vec<ReleasedCallback>)>,
+}
+
+impl Released {
+    fn set(&self) {
+        let callbacks = {
+            let mut state = self.state.lock().unwrap();
+            state.0 = true;
+            std::mem::take(&mut state.1)
+        };
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// on_released calls the callback after the release, or immediately if it is already released.
+    fn on_released(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        if !state.0 {
+            state.1.push(Box::new(callback));
+            return;
+        }
+        drop(state);
+        callback();
+    }
+}
+
+impl WriteQueue {
+    pub fn new(limit: Option<WriteLimit>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// pending returns the number of the operations queued on the thread or delayed.
+    pub fn pending(&self) -> usize {
+        self.queued.load(Ordering::SeqCst) + self.delayed.lock().unwrap().len()
+    }
+
+    /// check_write returns Backpressure error if the queue is full and the writes are rejected instead of delayed.
+    pub fn check_write(&self) -> Result<(), Backpressure> {
+        match self.limit {
+            Some(limit) if !limit.block_on_full => {
+                let pending = self.pending();
+                if pending >= limit.max_pending {
+                    return Err(Backpressure(pending));
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// finish is called on the thread after each queued operation.
+    /// The delayed writes are run in order while the queue is below the low-water mark.
+    fn finish(&self, channel: &Channel) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        let low_water = match self.limit {
+            Some(limit) => limit.low_water(),
+            None => return,
+        };
+        loop {
+            let callback = {
+                let mut delayed = self.delayed.lock().unwrap();
+                if self.queued.load(Ordering::SeqCst) >= low_water {
+                    return;
+                }
+                match delayed.pop_front() {
+                    Some(callback) => callback,
+                    None => return,
+                }
+            };
+            callback(channel);
+        }
+    }
+
+    /// flush runs all the delayed writes, so that their callbacks are called before the thread stops.
+    fn flush(&self, channel: &Channel) {
+        let delayed: Vec<DbCallback> = self.delayed.lock().unwrap().drain(..).collect();
+        for callback in delayed {
+            callback(channel);
+        }
+    }
 }
 
 impl Unwrap for ArcOptionDB {
@@ -29,75 +265,470 @@ impl NewDBWithContext for DB {
         path: String,
         opts: DbOptions,
         db_kind: Kind,
-    ) -> Result<Self, rocksdb::Error>
+    ) -> Result<Self, DbError>
     where
         C: Context<'a>,
     {
-        // Channel for sending callbacks to execute on the sqlite connection thread
-        let (tx, rx) = mpsc::channel::<DbMessage>();
+        // the thread is started only after the connection is opened, so that nothing is left on failure
+        let (conn, secondary_dir) = Self::open_connection(&path, &opts, &db_kind)?;
+        Ok(Self::start(ctx, conn, secondary_dir, &opts, db_kind))
+    }
+}
 
-        let channel = ctx.channel();
+/// is_lock_error returns true if the error is of acquiring the LOCK file of the database held by another instance.
+fn is_lock_error(err: &rocksdb::Error) -> bool {
+    err.kind() == rocksdb::ErrorKind::IOError && err.as_ref().to_lowercase().contains("lock")
+}
+
+/// DB dereferences to the connection, so that the types taking rocksdb::DB can be used on it.
+impl Deref for DB {
+    type Target = rocksdb::DB;
+
+    fn deref(&self) -> &rocksdb::DB {
+        self.db()
+    }
+}
+
+/// finalize closes the DB dropped by JS without close, so that the thread stops and the connection is released.
+impl Finalize for DB {
+    fn finalize<'a, C: Context<'a>>(mut self, _: &mut C) {
+        let _ = self.close();
+    }
+}
+
+impl Closable for DB {
+    const NAME: &'static str = "Database";
+
+    fn closed(&self) -> &AtomicBool {
+        self.registration.closed()
+    }
+}
+
+impl DB {
+    fn db(&self) -> &rocksdb::DB {
+        self.db.unwrap()
+    }
 
+    pub fn new(db: rocksdb::DB, tx: mpsc::Sender<DbMessage>, db_kind: Kind) -> Self {
+        let name = match db_kind {
+            Kind::State => "StateDB",
+            _ => "Database",
+        };
+        let registration = REGISTRY.register(name, None);
+        let closer_tx = tx.clone();
+        // the thread stops after the queued operations
+        registration.set_closer(move || {
+            let _ = closer_tx.send(DbMessage::Close);
+        });
+        Self {
+            tx,
+            db_kind,
+            db: Arc::new(Some(db)),
+            registration,
+            queue: Arc::new(WriteQueue::default()),
+            durability: WriteDurability::default(),
+            secondary_dir: None,
+            size_limits: SizeLimits::default(),
+            metrics: None,
+            watchdog: None,
+            write_lock: Arc::new(Mutex::new(())),
+            prefix_key_length: None,
+            max_iteration_result_size: None,
+            released: Arc::new(Released::default()),
+        }
+    }
+
+    /// open_connection opens the connection of the kind without starting the DB thread.
+    /// The database locked by another instance fails with DbError::Locked, unless exclusive is false
+    /// and it is opened as the read-only secondary instance, which reads the data at the time of opening.
+    /// The secondary instance writes its own logs to the returned directory, which must be kept while it is open.
+    pub fn open_connection(
+        path: &str,
+        opts: &DbOptions,
+        db_kind: &Kind,
+    ) -> Result<(rocksdb::DB, Option<TempDir>), DbError> {
         let mut option = rocksdb::Options::default();
         option.create_if_missing(true);
+        opts.tuning.apply(&mut option);
+        // prefix filters of state_db are on the state keys only, which are in the STATE column family
+        let mut state_option = option.clone();
+        // most of the point lookups of the state are for the keys not existing, so the bloom filters are on by default
+        let state_tuning = opts
+            .tuning
+            .with_bloom_bits_per_key_or(consts::STATE_BLOOM_BITS_PER_KEY);
+        if *db_kind == Kind::State {
+            state_tuning.apply(&mut state_option);
+        }
+        if let Some(length) = opts.prefix_key_length(db_kind) {
+            let (target, tuning) = if *db_kind == Kind::State {
+                (&mut state_option, &state_tuning)
+            } else {
+                (&mut option, &opts.tuning)
+            };
+            prefix_filter::configure(target, tuning, length);
+        }
+        let length_key = if *db_kind == Kind::State {
+            Prefix::PREFIX_FILTER_LENGTH
+        } else {
+            consts::PREFIX_FILTER_LENGTH_KEY
+        };
 
-        let db: rocksdb::DB = if opts.is_readonly() {
-            rocksdb::DB::open_for_read_only(&option, path, false)?
+        // state_db keys are stored in the column families of the prefixes
+        let result = if *db_kind == Kind::State {
+            column_families::open_with_state_options(
+                &option,
+                &state_option,
+                path,
+                opts.is_readonly(),
+                opts.auto_migrate,
+            )
+        } else if opts.is_readonly() {
+            rocksdb::DB::open_for_read_only(&option, path, false)
         } else {
-            rocksdb::DB::open(&option, path)?
+            rocksdb::DB::open(&option, path)
         };
+        match result {
+            Ok(conn) => {
+                prefix_filter::check_length(
+                    &conn,
+                    length_key,
+                    opts.prefix_filter_length,
+                    opts.is_readonly(),
+                )?;
+                Ok((conn, None))
+            },
+            Err(err) if is_lock_error(&err) && !opts.exclusive => {
+                let secondary_dir = TempDir::new("lisk-db-secondary")?;
+                // secondary instance requires all the files to be kept open
+                option.set_max_open_files(-1);
+                state_option.set_max_open_files(-1);
+                let conn = if *db_kind == Kind::State {
+                    column_families::open_as_secondary(
+                        &option,
+                        &state_option,
+                        path,
+                        secondary_dir.path(),
+                    )?
+                } else {
+                    rocksdb::DB::open_as_secondary(&option, path, secondary_dir.path())?
+                };
+                prefix_filter::check_length(&conn, length_key, opts.prefix_filter_length, true)?;
+                Ok((conn, Some(secondary_dir)))
+            },
+            Err(err) if is_lock_error(&err) => Err(DbError::Locked(path.to_string())),
+            Err(err) => Err(err.into()),
+        }
+    }
 
+    /// start starts the DB thread on the opened connection.
+    pub fn start<'a, C: Context<'a>>(
+        ctx: &mut C,
+        conn: rocksdb::DB,
+        secondary_dir: Option<TempDir>,
+        opts: &DbOptions,
+        db_kind: Kind,
+    ) -> Self {
+        // Channel for sending callbacks to execute on the sqlite connection thread
+        let (tx, rx) = mpsc::channel::<DbMessage>();
+
+        let channel = ctx.channel();
+
+        let queue = Arc::new(WriteQueue::new(opts.write_limit));
+        let thread_queue = Arc::clone(&queue);
+        let watchdog = opts
+            .operation_timeout
+            .map(|timeout| Arc::new(Self::start_watchdog(ctx, timeout)));
+        let thread_watchdog = watchdog.clone();
         thread::spawn(move || {
             while let Ok(message) = rx.recv() {
                 match message {
                     DbMessage::Callback(f) => {
                         f(&channel);
+                        thread_queue.finish(&channel);
+                    },
+                    DbMessage::Close => {
+                        thread_queue.flush(&channel);
+                        break;
                     },
-                    DbMessage::Close => return,
                 }
             }
+            // all the queued operations have started, so that nothing is left to time out
+            if let Some(watchdog) = thread_watchdog {
+                watchdog.stop();
+            }
         });
 
-        Ok(Self::new(db, tx, db_kind))
+        let mut db = Self::new(conn, tx, db_kind);
+        db.queue = queue;
+        db.durability = opts.durability;
+        db.secondary_dir = secondary_dir;
+        db.size_limits = opts.size_limits;
+        if opts.metrics {
+            db.metrics = Some(Arc::new(Metrics::new()));
+        }
+        db.watchdog = watchdog;
+        db.prefix_key_length = opts.prefix_key_length(&db_kind);
+        db.max_iteration_result_size = opts.max_iteration_result_size;
+        db
     }
-}
 
-impl Finalize for DB {}
-impl DB {
-    fn db(&self) -> &rocksdb::DB {
-        self.db.unwrap()
+    /// start_watchdog starts the watchdog calling the callbacks with ERR_TIMEOUT on the JS thread.
+    fn start_watchdog<'a, C: Context<'a>>(
+        ctx: &mut C,
+        timeout: Duration,
+    ) -> Watchdog<Root<JsFunction>> {
+        let channel = ctx.channel();
+        let timeout_ms = timeout.as_millis() as u64;
+        Watchdog::start(timeout, move |callback: Root<JsFunction>| {
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> =
+                    vec![DbError::Timeout(timeout_ms).to_js(&mut ctx)?.upcast()];
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
     }
 
-    pub fn new(db: rocksdb::DB, tx: mpsc::Sender<DbMessage>, db_kind: Kind) -> Self {
-        Self {
-            tx,
-            db_kind,
-            db: Arc::new(Some(db)),
+    /// watch_callback returns the callback of the operation to be sent.
+    /// If operationTimeoutMs is set and the operation does not start within it, the callback is called with ERR_TIMEOUT,
+    /// and the operation is skipped when it starts. It must only be used by the operations which run their work
+    /// in the sent closure after start, so that the operation timed out is never applied and it is safe to retry.
+    /// The results computed on the JS thread, such as set and del, are sent without it.
+    /// Commit and revert must not use it, as they are exempt from the timeout.
+    pub fn watch_callback(&self, callback: Root<JsFunction>) -> PendingCallback {
+        match self.watchdog.as_ref() {
+            Some(watchdog) => watchdog.watch(callback),
+            None => Pending::new(callback),
         }
     }
 
+    /// is_secondary returns true if the database is opened as the secondary instance.
+    pub fn is_secondary(&self) -> bool {
+        self.secondary_dir.is_some()
+    }
+
+    /// durability returns the WAL options of the writes.
+    pub fn durability(&self) -> WriteDurability {
+        self.durability
+    }
+
+    /// size_limits returns the limits of the keys and the values accepted on write.
+    pub fn size_limits(&self) -> SizeLimits {
+        self.size_limits
+    }
+
+    /// prefix_key_length returns the length of the stored key prefix of the prefix filters. None if they are disabled.
+    pub fn prefix_key_length(&self) -> Option<usize> {
+        self.prefix_key_length
+    }
+
+    /// max_iteration_result_size returns the bytes of the result of an iterate. Unlimited if None.
+    pub fn max_iteration_result_size(&self) -> Option<usize> {
+        self.max_iteration_result_size
+    }
+
+    /// metrics returns the collector of the latency histograms. None if metrics are disabled.
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// timer starts measuring the operation. None if metrics are disabled, so that nothing is recorded.
+    pub fn timer(&self, operation: Operation) -> Option<Timer> {
+        self.metrics
+            .as_ref()
+            .map(|metrics| Timer::new(metrics.clone(), operation))
+    }
+
+    /// closed_flag returns the closed flag for the operations running in the DB thread.
+    pub fn closed_flag(&self) -> ClosedFlag {
+        self.registration.closed_flag()
+    }
+
+    /// registration_id returns the id of the DB in the registry.
+    pub fn registration_id(&self) -> u64 {
+        self.registration.id()
+    }
+
     // Idiomatic rust would take an owned `self` to prevent use after close
     // However, it's not possible to prevent JavaScript from continuing to hold a closed database
+    /// close is no-op if the DB is already closed.
+    /// The operations queued before the close are processed before the thread stops.
+    /// The readers created from the DB are closed, and the connection is dropped after all of them release it.
     pub fn close(&mut self) -> Result<(), mpsc::SendError<DbMessage>> {
-        self.db = Arc::new(None);
-        self.tx.send(DbMessage::Close)
+        if self.db.is_none() {
+            return Ok(());
+        }
+        // the DB closed by the shutdown has sent the close already
+        let result = if self.is_closed() {
+            Ok(())
+        } else {
+            self.mark_closed();
+            self.tx.send(DbMessage::Close)
+        };
+        REGISTRY.close_children(self.registration.id());
+        let conn = std::mem::replace(&mut self.db, Arc::new(None));
+        let secondary_dir = self.secondary_dir.take();
+        let released = Arc::clone(&self.released);
+        let in_use = Arc::strong_count(&conn) > 1;
+        // the last reference is dropped by the release, so that the callbacks are called after the drop
+        let release = move || {
+            while Arc::strong_count(&conn) > 1 {
+                thread::sleep(registry::POLL_INTERVAL);
+            }
+            drop(conn);
+            drop(secondary_dir);
+            released.set();
+        };
+        // the connection not used by any operation or reader is released before the close returns
+        if in_use {
+            thread::spawn(release);
+        } else {
+            release();
+        }
+        result
+    }
+
+    /// on_released calls the callback on the JS thread after the connection is released by close.
+    pub fn on_released(&self, channel: Channel, callback: Root<JsFunction>) {
+        self.released.on_released(move || {
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
+                callback.call(&mut ctx, this, args)?;
+                Ok(())
+            });
+        });
     }
 
     pub fn send(
         &self,
         callback: impl FnOnce(&Channel) + Send + 'static,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
-        self.tx.send(DbMessage::Callback(Box::new(callback)))
+        let operation = self.registration.start_operation();
+        self.queue.queued.fetch_add(1, Ordering::SeqCst);
+        let result = self.tx.send(DbMessage::Callback(Box::new(move |channel| {
+            callback(channel);
+            drop(operation);
+        })));
+        if result.is_err() {
+            self.queue.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// send_write sends the callback of the write.
+    /// When the writes are delayed on full, the callback is kept until the queue drains below the low-water mark,
+    /// and the later writes are kept behind it to preserve the order.
+    pub fn send_write(
+        &self,
+        callback: impl FnOnce(&Channel) + Send + 'static,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let limit = match self.queue.limit {
+            Some(limit) if limit.block_on_full => limit,
+            _ => return self.send(callback),
+        };
+        let mut delayed = self.queue.delayed.lock().unwrap();
+        if delayed.is_empty() && self.queue.queued.load(Ordering::SeqCst) < limit.max_pending {
+            return self.send(callback);
+        }
+        let operation = self.registration.start_operation();
+        delayed.push_back(Box::new(move |channel| {
+            callback(channel);
+            drop(operation);
+        }));
+        Ok(())
+    }
+
+    /// send_timed sends the callback, and records it with the timer if given.
+    pub fn send_timed(
+        &self,
+        timer: Option<Timer>,
+        callback: impl FnOnce(&Channel) + Send + 'static,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        match timer {
+            Some(timer) => self.send(move |channel| timer.run(|| callback(channel))),
+            None => self.send(callback),
+        }
+    }
+
+    /// send_write_timed sends the callback of the write, and records it with the timer if given.
+    /// The time the write is delayed by the back-pressure is counted as the queue wait.
+    pub fn send_write_timed(
+        &self,
+        timer: Option<Timer>,
+        callback: impl FnOnce(&Channel) + Send + 'static,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        match timer {
+            Some(timer) => self.send_write(move |channel| timer.run(|| callback(channel))),
+            None => self.send_write(callback),
+        }
+    }
+
+    /// pending_operations returns the number of the operations queued on the thread or delayed.
+    pub fn pending_operations(&self) -> usize {
+        self.queue.pending()
+    }
+
+    /// throw_if_backpressure throws Backpressure error to JS if the write must be rejected.
+    /// It must be called before the write is applied.
+    pub fn throw_if_backpressure<'a, C: Context<'a>>(&self, ctx: &mut C) -> NeonResult<()> {
+        self.queue
+            .check_write()
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
+
+    /// throw_if_over_limits throws SizeLimit error to JS if the key or the value is not within the limits.
+    /// Only the key is checked if the value is None, such as for the deletion.
+    pub fn throw_if_over_limits<'a, C: Context<'a>>(
+        &self,
+        ctx: &mut C,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> NeonResult<()> {
+        let result = match value {
+            Some(value) => self.size_limits.check(key, value),
+            None => self.size_limits.check_key(key),
+        };
+        result.or_else(|err| DbError::from(err).throw(ctx))
     }
 
+    /// throw_if_batch_over_limits throws SizeLimit error to JS if any operation of the batch is not within the limits.
+    /// It must be called before the batch is sent, so that nothing in the batch is written.
+    pub fn throw_if_batch_over_limits<'a, C: Context<'a>>(
+        &self,
+        ctx: &mut C,
+        batch: &rocksdb::WriteBatch,
+    ) -> NeonResult<()> {
+        self.size_limits
+            .check_batch(batch)
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
+
+    /// get_by_key reads the key on the DB thread when the operation starts, so that the read timed out is not run.
     pub fn get_by_key(
         &self,
         key: Vec<u8>,
         callback: Root<JsFunction>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let timer = self.timer(Operation::Get);
         let key = self.db_kind.key(key);
-        let result = self.get(&key);
-        self.send(move |channel| {
+        let is_state = self.db_kind == Kind::State;
+        let conn = self.arc_clone();
+        let callback = self.watch_callback(callback);
+        self.send_timed(timer, move |channel| {
+            let callback = match callback.start() {
+                Some(callback) => callback,
+                None => return,
+            };
+            let mut result = column_families::get(conn.unwrap(), &key);
+            // values of the state are decompressed, and the other kinds are returned as they are stored
+            if is_state {
+                result = result.map(|value| value.map(value_compression::decode_vec));
+            }
             channel.send(move |mut ctx| {
                 let callback = callback.into_inner(&mut ctx);
                 let this = ctx.undefined();
@@ -106,8 +737,8 @@ impl DB {
                         let buffer = JsBuffer::external(&mut ctx, val);
                         vec![ctx.null().upcast(), buffer.upcast()]
                     },
-                    Ok(None) => vec![ctx.error("No data")?.upcast()],
-                    Err(err) => vec![ctx.error(&err)?.upcast()],
+                    Ok(None) => vec![DbError::NotFound.to_js(&mut ctx)?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -123,11 +754,46 @@ impl DB {
         callback: Root<JsFunction>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
         let key = self.db_kind.key(key);
-        let result = if self.db().key_may_exist(&key) {
-            self.get(&key).map(|res| res.is_some())
+        let result = self.key_exists(&key);
+        self.send_exists_result(result, callback)
+    }
+
+    /// key_exists checks the existence of the key, which already includes the prefix.
+    pub fn key_exists(&self, key: &[u8]) -> Result<bool, rocksdb::Error> {
+        let may_exist = match column_families::handle(self.db(), key) {
+            Some(cf) => self.db().key_may_exist_cf(cf, key),
+            None => self.db().key_may_exist(key),
+        };
+        if may_exist {
+            self.get(key).map(|res| res.is_some())
         } else {
             Ok(false)
-        };
+        }
+    }
+
+    /// exists_prefix checks whether any key starts with the prefix, and sends the result to the callback.
+    pub fn exists_prefix(
+        &self,
+        prefix: Vec<u8>,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let prefix = self.db_kind.key(prefix);
+        let result = self.key_prefix_exists(&prefix);
+        self.send_exists_result(result, callback)
+    }
+
+    /// key_prefix_exists checks whether any key starts with the prefix, which already includes the prefix of the kind.
+    /// The prefix at least the length of the prefix filters skips the memtables and the files without it.
+    pub fn key_prefix_exists(&self, prefix: &[u8]) -> Result<bool, rocksdb::Error> {
+        column_families::exists_prefix(self.db(), prefix, self.prefix_key_length, |_| true)
+    }
+
+    /// send_exists_result sends the result checked on the JS thread. It is not timed out, as the check already ran.
+    pub fn send_exists_result(
+        &self,
+        result: Result<bool, rocksdb::Error>,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
         self.send(move |channel| {
             channel.send(move |mut ctx| {
                 let callback = callback.into_inner(&mut ctx);
@@ -137,7 +803,7 @@ impl DB {
                         let converted = ctx.boolean(val);
                         vec![ctx.null().upcast(), converted.upcast()]
                     },
-                    Err(err) => vec![ctx.error(&err)?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -147,44 +813,295 @@ impl DB {
         })
     }
 
+    /// create_checkpoint creates the checkpoint at the path.
+    /// When sync is specified, created files and the directory entries are flushed to the disk.
+    pub fn create_checkpoint(
+        conn: &rocksdb::DB,
+        path: &str,
+        option: &CheckpointOption,
+    ) -> Result<(), CheckpointError> {
+        if option.flush_before_checkpoint {
+            conn.flush()?;
+        }
+        let checkpoint = Checkpoint::new(conn)?;
+        checkpoint.create_checkpoint(path)?;
+        if option.sync {
+            utils::sync_path(Path::new(path))?;
+        }
+
+        Ok(())
+    }
+
+    /// verify_checkpoint opens the checkpoint as readonly and reads the metadata.
+    /// When the key length is specified, the current state root is read and the root node is recomputed,
+    /// so that the checkpoint missing the SMT data is rejected. The node is hashed by the hasher recorded in the current state.
+    pub fn verify_checkpoint(
+        path: &str,
+        key_length: Option<KeyLength>,
+    ) -> Result<CheckpointInfo, CheckpointError> {
+        let conn = column_families::open(&rocksdb::Options::default(), path, true)?;
+        let mut info = CheckpointInfo::default();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            info.size += entry.metadata()?.len();
+            if entry.path().extension().map_or(false, |ext| ext == "sst") {
+                info.sst_files += 1;
+            }
+        }
+        let key_length = match key_length {
+            Some(key_length) => key_length,
+            None => return Ok(info),
+        };
+
+        let state = CurrentState::get_or_empty(&conn, &smt::EMPTY_HASH)
+            .map_err(|err| CheckpointError::Invalid(err.to_string()))?;
+        let hasher = HasherKind::from_name(state.hasher()).ok_or_else(|| {
+            CheckpointError::Invalid(format!("hasher {} is not enabled", state.hasher()))
+        })?;
+        let root = state.root().to_vec();
+        if !hasher.is_empty_hash(&root) {
+            let node = column_families::get(&conn, &[Prefix::SMT, &root].concat())?;
+            let node = node.ok_or_else(|| {
+                CheckpointError::Invalid(format!("root {} is missing", hex::encode(&root)))
+            })?;
+            let matched = smt::calculate_node_hash_with_hasher(&node, key_length, hasher)
+                .map(|hash| hash == root)
+                .unwrap_or(false);
+            if !matched {
+                return Err(CheckpointError::Invalid(format!(
+                    "root {} does not match the stored node",
+                    hex::encode(&root)
+                )));
+            }
+        }
+        info.root = Some(root);
+
+        Ok(info)
+    }
+
+    /// checkpoint creates the checkpoint in the DB thread, and verifies it before calling the callback.
+    /// The state root is verified when the key length is specified.
+    /// When the membership of a checkpoint group is specified, the result is recorded in the group.
     pub fn checkpoint(
         &self,
         path: String,
+        option: CheckpointOption,
+        key_length: Option<KeyLength>,
+        group: Option<Membership>,
         callback: Root<JsFunction>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
         let conn = Arc::clone(&self.db);
         self.send(move |channel| {
-            let result = Checkpoint::new(conn.unwrap());
-
-            if result.is_err() {
-                let err = result.err().unwrap();
-                channel.send(move |mut ctx| {
-                    let callback = callback.into_inner(&mut ctx);
-                    let this = ctx.undefined();
-                    let args = vec![ctx.error(&err)?.upcast()];
-
-                    callback.call(&mut ctx, this, args)?;
-
-                    Ok(())
-                });
-            } else if let Ok(checkpoint) = result {
-                let result = checkpoint.create_checkpoint(&path);
-
-                channel.send(move |mut ctx| {
-                    let callback = callback.into_inner(&mut ctx);
-                    let this = ctx.undefined();
-                    let args: Vec<Handle<JsValue>> = match result {
-                        Ok(()) => {
-                            vec![ctx.null().upcast()]
-                        },
-                        Err(err) => vec![ctx.error(&err)?.upcast()],
-                    };
-
-                    callback.call(&mut ctx, this, args)?;
-
-                    Ok(())
-                });
-            }
+            let result = Self::create_checkpoint(conn.unwrap(), &path, &option)
+                .and_then(|_| Self::verify_checkpoint(&path, key_length))
+                .map_err(DbError::from);
+            let result = match group {
+                Some(membership) => membership.finish(result),
+                None => result,
+            };
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(info) => {
+                        let obj = checkpoint_info_to_js_object(&mut ctx, &info)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// compact triggers the manual compaction for the range in the DB thread.
+    /// When start or end is not specified, the range is open to the corresponding side.
+    pub fn compact(
+        &self,
+        start: VecOption,
+        end: VecOption,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let (start, end) = self.db_kind.range(start, end);
+        let conn = Arc::clone(&self.db);
+        self.send(move |channel| {
+            column_families::compact_range(conn.unwrap(), start.as_deref(), end.as_deref());
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// flush flushes the memtables to the disk in the DB thread, and waits for the flush to finish.
+    /// The writes are durable after the flush even if the WAL is disabled.
+    pub fn flush(&self, callback: Root<JsFunction>) -> Result<(), mpsc::SendError<DbMessage>> {
+        let conn = Arc::clone(&self.db);
+        self.send(move |channel| {
+            let mut opts = rocksdb::FlushOptions::default();
+            opts.set_wait(true);
+            let result = conn.unwrap().flush_opt(&opts);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(_) => vec![ctx.null().upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// write_delete_range deletes the keys in the range from start (inclusive) to end (exclusive)
+    /// with a single range tombstone.
+    pub fn write_delete_range(
+        conn: &rocksdb::DB,
+        start: &[u8],
+        end: &[u8],
+        write_opts: &rocksdb::WriteOptions,
+    ) -> Result<(), rocksdb::Error> {
+        let mut batch = rocksdb::WriteBatch::default();
+        column_families::delete_range(conn, &mut batch, start, end);
+        conn.write_opt(batch, write_opts)
+    }
+
+    /// delete_range deletes the keys in the range in the DB thread.
+    pub fn delete_range(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let start = self.db_kind.key(start);
+        let end = self.db_kind.key(end);
+        let conn = Arc::clone(&self.db);
+        let durability = self.durability;
+        self.send_write(move |channel| {
+            let write_opts = durability.write_options(false);
+            let result = Self::write_delete_range(conn.unwrap(), &start, &end, &write_opts);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(_) => vec![ctx.null().upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// collect_stats reads the properties for the stats, summed up over the column families.
+    /// Properties which are not available are omitted.
+    pub fn collect_stats(conn: &rocksdb::DB) -> Vec<(String, u64)> {
+        let mut properties: Vec<(String, String)> = STATS_PROPERTIES
+            .iter()
+            .map(|(property, name)| (property.to_string(), name.to_string()))
+            .collect();
+        for level in 0..NUM_LEVELS {
+            properties.push((
+                format!("rocksdb.num-files-at-level{}", level),
+                format!("numFilesAtLevel{}", level),
+            ));
+        }
+
+        properties
+            .into_iter()
+            .filter_map(|(property, name)| {
+                let mut value = match conn.property_int_value(&property) {
+                    Ok(Some(value)) => value,
+                    _ => return None,
+                };
+                if !SHARED_PROPERTIES.contains(&property.as_str()) {
+                    for cf in column_families::handles(conn) {
+                        if let Ok(Some(cf_value)) = conn.property_int_value_cf(cf, &property) {
+                            value += cf_value;
+                        }
+                    }
+                }
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// stats reads the rocksdb properties in the DB thread.
+    pub fn stats(&self, callback: Root<JsFunction>) -> Result<(), mpsc::SendError<DbMessage>> {
+        let conn = Arc::clone(&self.db);
+        self.send(move |channel| {
+            let result = Self::collect_stats(conn.unwrap());
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let obj = ctx.empty_object();
+                for (name, value) in result {
+                    let value = ctx.number(value as f64);
+                    obj.set(&mut ctx, name.as_str(), value)?;
+                }
+                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), obj.upcast()];
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// approximate_sizes estimates the sizes of the ranges in the DB thread, see column_families::approximate_sizes.
+    /// The ranges are the physical keys. The callback receives the size of the only range if single is set,
+    /// or the array of the sizes in the order of the ranges otherwise.
+    pub fn approximate_sizes(
+        &self,
+        ranges: Vec<(Vec<u8>, Vec<u8>)>,
+        single: bool,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let conn = Arc::clone(&self.db);
+        self.send(move |channel| {
+            let result = column_families::approximate_sizes(conn.unwrap(), &ranges);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(sizes) if single => {
+                        let obj = approximate_size_to_js_object(&mut ctx, &sizes[0])?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Ok(sizes) => {
+                        let arr = JsArray::new(&mut ctx, sizes.len() as u32);
+                        for (i, size) in sizes.iter().enumerate() {
+                            let obj = approximate_size_to_js_object(&mut ctx, size)?;
+                            arr.set(&mut ctx, i as u32, obj)?;
+                        }
+                        vec![ctx.null().upcast(), arr.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
         })
     }
 
@@ -192,20 +1109,57 @@ impl DB {
         Arc::clone(&self.db)
     }
 
+    /// write_lock returns the lock held by the writes which must not interleave with the conditional writes.
+    pub fn write_lock(&self) -> Arc<Mutex<()>> {
+        Arc::clone(&self.write_lock)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), rocksdb::Error> {
-        self.db().put(key, value)
+        let _guard = self.write_lock.lock().unwrap();
+        self.db()
+            .put_opt(key, value, &self.durability.write_options(false))
     }
 
     pub fn delete(&self, key: &[u8]) -> Result<(), rocksdb::Error> {
-        self.db().delete(key)
+        let _guard = self.write_lock.lock().unwrap();
+        self.db()
+            .delete_opt(key, &self.durability.write_options(false))
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> {
-        self.db().get(key)
+        column_families::get(self.db(), key)
     }
 
     pub fn write(&self, batch: rocksdb::WriteBatch) -> Result<(), rocksdb::Error> {
-        self.db().write(batch)
+        column_families::write_opt(self.db(), batch, &self.durability.write_options(false))
+    }
+
+    /// write_in_chunks writes the batch in chunks bounded by max_batch_bytes with the durability of the database,
+    /// see chunked_write.
+    pub fn write_in_chunks(
+        &self,
+        batch: &rocksdb::WriteBatch,
+        max_batch_bytes: usize,
+        journal_key: &[u8],
+        identity: &[u8],
+    ) -> Result<(), ChunkedWriteError> {
+        chunked_write::write_in_chunks(
+            self.db(),
+            batch,
+            max_batch_bytes,
+            journal_key,
+            identity,
+            &self.durability.write_options(false),
+        )
+    }
+
+    pub fn iterator(&self, mode: rocksdb::IteratorMode) -> rocksdb::DBIterator<'_> {
+        self.db()
+            .iterator_opt(mode, prefix_filter::total_order_read_options())
+    }
+
+    pub fn property_int_value(&self, name: &str) -> Result<Option<u64>, rocksdb::Error> {
+        self.db().property_int_value(name)
     }
 
     pub fn path(&self) -> &std::path::Path {
@@ -219,6 +1173,8 @@ mod tests {
     use tempdir::TempDir;
 
     use super::*;
+    use crate::database::traits::HandleClosed;
+    use crate::database::types::{CompactionStyle, Compression, TuningOptions};
     use crate::types::KVPair;
 
     fn temp_db() -> DB {
@@ -240,6 +1196,314 @@ mod tests {
         assert_eq!(db.get(key).unwrap(), None);
     }
 
+    #[test]
+    fn test_open_connection_locked() {
+        let temp_dir = TempDir::new("test_db_locked").unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let opts = DbOptions::default();
+        let (primary, _) = DB::open_connection(path, &opts, &Kind::Normal).unwrap();
+        primary.put([1, 2], [3, 4]).unwrap();
+        primary.flush().unwrap();
+
+        match DB::open_connection(path, &opts, &Kind::Normal) {
+            Err(err @ DbError::Locked(_)) => {
+                assert_eq!(err.code(), "ERR_DB_LOCKED");
+                assert!(err.to_string().contains(path));
+            },
+            _ => panic!("locked database must not be opened"),
+        }
+
+        let mut opts = DbOptions::default();
+        opts.exclusive = false;
+        let (secondary, secondary_dir) = DB::open_connection(path, &opts, &Kind::Normal).unwrap();
+        assert!(secondary_dir.is_some());
+        assert_eq!(secondary.get([1, 2]).unwrap(), Some(vec![3, 4]));
+        assert!(secondary.put([5], [6]).is_err());
+    }
+
+    #[test]
+    fn test_open_connection_prefix_filter_length() {
+        let temp_dir = TempDir::new("test_db_prefix_filter").unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let mut opts = DbOptions::default();
+        opts.prefix_filter_length = Some(6);
+        let key_length = opts.prefix_key_length(&Kind::State);
+        {
+            let (conn, _) = DB::open_connection(path, &opts, &Kind::State).unwrap();
+            let mut batch = rocksdb::WriteBatch::default();
+            batch.put(Kind::State.key(vec![0, 0, 0, 1, 0, 0, 1]), [1]);
+            batch.put(Kind::State.key(vec![0, 0, 0, 3, 0, 0, 1]), [1]);
+            column_families::write(&conn, batch).unwrap();
+            for cf in column_families::handles(&conn) {
+                conn.flush_cf(cf).unwrap();
+            }
+            for (prefix, expected) in [
+                (vec![0, 0, 0, 1, 0, 0], true),
+                (vec![0, 0, 0, 2, 0, 0], false),
+                (vec![0, 0, 0, 3], true),
+            ] {
+                let prefix = Kind::State.key(prefix);
+                let exists =
+                    column_families::exists_prefix(&conn, &prefix, key_length, |_| true).unwrap();
+                assert_eq!(exists, expected);
+            }
+        }
+
+        // the filters of the existing files are built with the recorded length
+        opts.prefix_filter_length = Some(7);
+        match DB::open_connection(path, &opts, &Kind::State) {
+            Err(err @ DbError::PrefixFilter(_)) => assert_eq!(err.code(), "ERR_INVALID_ARGUMENT"),
+            _ => panic!("changed prefix filter length must be refused"),
+        }
+        opts.prefix_filter_length = None;
+        DB::open_connection(path, &opts, &Kind::State).unwrap();
+        opts.prefix_filter_length = Some(6);
+        DB::open_connection(path, &opts, &Kind::State).unwrap();
+    }
+
+    #[test]
+    fn test_open_with_tuning_options() {
+        let temp_dir = TempDir::new("test_db_tuning").unwrap();
+        let tuning = TuningOptions {
+            block_cache_size: Some(8 * 1024 * 1024),
+            write_buffer_size: Some(4 * 1024 * 1024),
+            max_open_files: Some(64),
+            compression: Some(Compression::Zstd),
+            compaction_style: Some(CompactionStyle::Universal),
+            bloom_bits_per_key: Some(10),
+        };
+        let mut option = rocksdb::Options::default();
+        option.create_if_missing(true);
+        tuning.apply(&mut option);
+        let rocks_db = rocksdb::DB::open(&option, &temp_dir).unwrap();
+        let (tx, _) = mpsc::channel::<DbMessage>();
+        let db = DB::new(rocks_db, tx, Kind::Normal);
+
+        db.put(&[1, 2, 3], &[4, 5, 6]).unwrap();
+        assert_eq!(db.get(&[1, 2, 3]).unwrap().unwrap(), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_tuning_option_names() {
+        assert_eq!(Compression::from_name("none"), Some(Compression::None));
+        assert_eq!(Compression::from_name("lz4"), Some(Compression::Lz4));
+        assert_eq!(Compression::from_name("zstd"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_name("snappy"), None);
+        assert_eq!(
+            CompactionStyle::from_name("level"),
+            Some(CompactionStyle::Level)
+        );
+        assert_eq!(
+            CompactionStyle::from_name("universal"),
+            Some(CompactionStyle::Universal)
+        );
+        assert_eq!(CompactionStyle::from_name("fifo"), None);
+        assert_eq!(TuningOptions::default(), DbOptions::default().tuning);
+    }
+
+    #[test]
+    fn test_kind_range() {
+        assert_eq!(Kind::Normal.range(None, None), (None, None));
+        assert_eq!(
+            Kind::Normal.range(Some(vec![1, 2]), Some(vec![3, 4])),
+            (Some(vec![1, 2]), Some(vec![3, 4]))
+        );
+        assert_eq!(
+            Kind::State.range(None, None),
+            (Some(vec![0]), Some(vec![1]))
+        );
+        assert_eq!(
+            Kind::State.range(Some(vec![1, 2]), None),
+            (Some(vec![0, 1, 2]), Some(vec![1]))
+        );
+        assert_eq!(
+            Kind::State.range(None, Some(vec![255, 255])),
+            (Some(vec![0]), Some(vec![0, 255, 255]))
+        );
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let db = temp_db();
+        for i in 0..100_u8 {
+            db.put(&[0, i], &[i; 100]).unwrap();
+            db.put(&[1, i], &[i; 100]).unwrap();
+        }
+        for i in 0..100_u8 {
+            db.delete(&[0, i]).unwrap();
+        }
+        let (start, end) = Kind::State.range(None, None);
+        db.db().compact_range(start, end);
+
+        assert_eq!(db.get(&[0, 1]).unwrap(), None);
+        assert_eq!(db.get(&[1, 1]).unwrap().unwrap(), vec![1; 100]);
+    }
+
+    #[test]
+    fn test_close_marks_closed() {
+        let mut db = temp_db();
+        assert!(!db.is_closed());
+        assert_eq!(db.check_open(), Ok(()));
+
+        // receiver of the channel is already dropped in the test
+        assert!(db.close().is_err());
+        assert!(db.is_closed());
+        assert_eq!(db.check_open(), Err(HandleClosed("Database")));
+        assert_eq!(
+            db.check_open().unwrap_err().to_string(),
+            "Database is closed"
+        );
+    }
+
+    #[test]
+    fn test_write_queue_check_write() {
+        let queue = WriteQueue::new(Some(WriteLimit {
+            max_pending: 2,
+            block_on_full: false,
+        }));
+        assert_eq!(queue.check_write(), Ok(()));
+        queue.queued.fetch_add(2, Ordering::SeqCst);
+        assert_eq!(queue.pending(), 2);
+        assert_eq!(queue.check_write(), Err(Backpressure(2)));
+        assert_eq!(
+            queue.check_write().unwrap_err().to_string(),
+            "backpressure: 2 operations are pending"
+        );
+
+        let blocking = WriteQueue::new(Some(WriteLimit {
+            max_pending: 2,
+            block_on_full: true,
+        }));
+        blocking.queued.fetch_add(3, Ordering::SeqCst);
+        assert_eq!(blocking.check_write(), Ok(()));
+
+        let unlimited = WriteQueue::default();
+        unlimited.queued.fetch_add(100, Ordering::SeqCst);
+        assert_eq!(unlimited.check_write(), Ok(()));
+    }
+
+    #[test]
+    fn test_write_without_wal_after_flush() {
+        let temp_dir = TempDir::new("test_db_disable_wal").unwrap();
+        {
+            let rocks_db = rocksdb::DB::open_default(&temp_dir).unwrap();
+            let (tx, _) = mpsc::channel::<DbMessage>();
+            let mut db = DB::new(rocks_db, tx, Kind::Normal);
+            db.durability = WriteDurability {
+                sync_writes: false,
+                disable_wal: true,
+            };
+            db.put(&[1], &[1]).unwrap();
+            db.put(&[2], &[2]).unwrap();
+            db.db().flush().unwrap();
+        }
+        let rocks_db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        assert_eq!(rocks_db.get([1]).unwrap(), Some(vec![1]));
+        assert_eq!(rocks_db.get([2]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_write_delete_range() {
+        let db = temp_db();
+        for i in 0..10_u8 {
+            db.put(&[1, i], &[i]).unwrap();
+        }
+        db.put(&[2], &[2]).unwrap();
+        let write_opts = rocksdb::WriteOptions::default();
+        DB::write_delete_range(db.db(), &[1, 3], &[1, 7], &write_opts).unwrap();
+
+        for i in 0..10_u8 {
+            let expected = if (3..7).contains(&i) {
+                None
+            } else {
+                Some(vec![i])
+            };
+            assert_eq!(db.get(&[1, i]).unwrap(), expected);
+        }
+        DB::write_delete_range(db.db(), &[1], &[2], &write_opts).unwrap();
+        assert_eq!(db.get(&[1, 0]).unwrap(), None);
+        assert_eq!(db.get(&[1, 9]).unwrap(), None);
+        assert_eq!(db.get(&[2]).unwrap().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_create_checkpoint() {
+        let db_dir = TempDir::new("test_checkpoint_db").unwrap();
+        let (tx, _) = mpsc::channel::<DbMessage>();
+        let db = DB::new(
+            rocksdb::DB::open_default(&db_dir).unwrap(),
+            tx,
+            Kind::Normal,
+        );
+        db.put(&[1, 2, 3], &[4, 5, 6]).unwrap();
+        let temp_dir = TempDir::new("test_checkpoint").unwrap();
+        #[cfg(feature = "testing")]
+        utils::take_synced_paths();
+        for (name, sync, flush_before_checkpoint) in [
+            ("synced", true, false),
+            ("not_synced", false, false),
+            ("flushed", true, true),
+        ] {
+            let path = temp_dir.path().join(name);
+            let path = path.to_str().unwrap();
+            let option = CheckpointOption {
+                sync,
+                flush_before_checkpoint,
+            };
+            DB::create_checkpoint(db.db(), path, &option).unwrap();
+            // the helper is invoked for the created path only with sync
+            #[cfg(feature = "testing")]
+            assert_eq!(
+                utils::take_synced_paths(),
+                if sync {
+                    vec![std::path::PathBuf::from(path)]
+                } else {
+                    vec![]
+                }
+            );
+
+            let info = DB::verify_checkpoint(path, None).unwrap();
+            assert!(info.size > 0);
+            assert_eq!(info.root, None);
+            if flush_before_checkpoint {
+                assert_eq!(info.sst_files, 1);
+            }
+            let checkpoint = rocksdb::DB::open_default(path).unwrap();
+            assert_eq!(checkpoint.get([1, 2, 3]).unwrap().unwrap(), vec![4, 5, 6]);
+        }
+        assert!(
+            DB::verify_checkpoint(temp_dir.path().join("not_exist").to_str().unwrap(), None)
+                .is_err()
+        );
+
+        // checkpoint cannot be created on existing directory
+        assert!(DB::create_checkpoint(
+            db.db(),
+            temp_dir.path().to_str().unwrap(),
+            &CheckpointOption::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_collect_stats() {
+        let db = temp_db();
+        for i in 0..100_u8 {
+            db.put(&[i], &[i]).unwrap();
+        }
+        let stats = DB::collect_stats(db.db());
+        let names: Vec<&str> = stats.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(names.contains(&"estimateNumKeys"));
+        assert!(names.contains(&"curSizeAllMemTables"));
+        assert!(names.contains(&"numFilesAtLevel0"));
+        assert!(names.contains(&"numFilesAtLevel6"));
+        let (_, num_keys) = stats
+            .iter()
+            .find(|(name, _)| name == "estimateNumKeys")
+            .unwrap();
+        assert_eq!(*num_keys, 100);
+    }
+
     #[test]
     fn test_write_batch() {
         let db = temp_db();
@@ -257,4 +1521,35 @@ mod tests {
             assert_eq!(db.get(pair.key()).unwrap().unwrap(), pair.value());
         }
     }
+
+    #[test]
+    fn test_close_releases_connection() {
+        let temp_dir = TempDir::new("test_db_close").unwrap();
+        let open = || {
+            let (tx, rx) = mpsc::channel::<DbMessage>();
+            let conn = rocksdb::DB::open_default(temp_dir.path()).unwrap();
+            (DB::new(conn, tx, Kind::Normal), rx)
+        };
+        let (released_tx, released_rx) = mpsc::channel();
+
+        // the connection not in use is released before the close returns
+        let (mut db, _rx) = open();
+        db.close().unwrap();
+        let tx = released_tx.clone();
+        db.released.on_released(move || tx.send(1).unwrap());
+        assert_eq!(released_rx.try_recv(), Ok(1));
+        db.close().unwrap();
+
+        // the connection held by the operation is released after it
+        let (mut db, _rx) = open();
+        let conn = db.arc_clone();
+        db.close().unwrap();
+        assert!(db.is_closed());
+        let tx = released_tx.clone();
+        db.released.on_released(move || tx.send(2).unwrap());
+        assert!(released_rx.try_recv().is_err());
+        drop(conn);
+        assert_eq!(released_rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        let (_db, _rx) = open();
+    }
 }
@@ -1,22 +1,33 @@
 /// in_memory_db provides same interface as rocksdb for in_memory state computation or mocking rocksDB without a physical storage.
-use std::cell::{RefCell, RefMut};
+use std::cell::RefCell;
 use std::cmp;
+use std::path::Path;
 use std::sync::Arc;
+use std::thread;
 
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
 
-use crate::batch;
+use crate::batch::{self, BatchClaim};
+use crate::database::in_memory::snapshot;
 use crate::database::options::IterationOption;
-use crate::database::types::JsBoxRef;
-use crate::types::{Cache, KVPair};
+use crate::database::types::{JsBoxRef, SizeLimits};
+use crate::database::utils::{is_key_in_range, sort_and_limit, with_default_bound};
+use crate::error::DbError;
+use crate::state::state_writer::StateWriterError;
+use crate::types::{Cache, KVPair, VecOption};
 use crate::utils;
 
 type SharedStateDB = JsBoxRef<Database>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct CacheData {
     data: Cache,
+    /// undo holds the previous value of the keys changed while any snapshot exists.
+    undo: Vec<(Vec<u8>, VecOption)>,
+    /// snapshots holds the snapshot id and the length of undo when it was taken, in the order of the id.
+    snapshots: Vec<(u32, usize)>,
+    counter: u32,
 }
 
 // Database to mock the rocksdb in memory.
@@ -24,31 +35,19 @@ pub struct Database {
     cache: CacheData,
 }
 
-fn sort_key_value_pair(pairs: &mut [KVPair], reverse: bool) {
-    if !reverse {
-        pairs.sort_by(|a, b| a.key().cmp(b.key()));
-        return;
-    }
-    pairs.sort_by(|a, b| b.key().cmp(a.key()));
-}
-
-fn get_key_value_pairs(db: RefMut<Database>, options: &IterationOption) -> Vec<KVPair> {
-    let no_range = options.gte.is_none() && options.lte.is_none();
-    let mut cached = if no_range {
-        db.cache_all()
-    } else {
-        let gte = options
-            .gte
-            .clone()
-            .unwrap_or_else(|| vec![0; options.lte.as_ref().unwrap().len()]);
-        let lte = options.lte.clone().unwrap_or_else(|| vec![255; gte.len()]);
-        db.cache_range(&gte, &lte)
+/// get_key_value_pairs returns the pairs in the range with the order and the limit.
+/// When either side of the range is not specified, the range defaults to the bound of with_default_bound as Database.
+pub(crate) fn get_key_value_pairs(db: &Database, options: &IterationOption) -> Vec<KVPair> {
+    let options = &with_default_bound(options);
+    let mut cached = match (&options.gte, &options.lte) {
+        (Some(gte), Some(lte)) => db.cache_range(gte, lte),
+        _ => db
+            .cache_all()
+            .into_iter()
+            .filter(|pair| is_key_in_range(options, pair.key()))
+            .collect(),
     };
-
-    sort_key_value_pair(&mut cached, options.reverse);
-    if options.limit != -1 && cached.len() > options.limit as usize {
-        cached = cached[..options.limit as usize].to_vec();
-    }
+    sort_and_limit(&mut cached, options);
 
     cached
 }
@@ -56,11 +55,74 @@ fn get_key_value_pairs(db: RefMut<Database>, options: &IterationOption) -> Vec<K
 impl rocksdb::WriteBatchIterator for CacheData {
     /// Called with a key and value that were `put` into the batch.
     fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
-        self.data.insert(key.to_vec(), value.to_vec());
+        self.insert(key.to_vec(), value.to_vec());
     }
     /// Called with a key that was `delete`d from the batch.
     fn delete(&mut self, key: Box<[u8]>) {
-        self.data.remove(&key.to_vec());
+        self.remove(&key);
+    }
+}
+
+impl CacheData {
+    fn new(data: Cache) -> Self {
+        Self {
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        let prev = self.data.insert(key.clone(), value);
+        if !self.snapshots.is_empty() {
+            self.undo.push((key, prev));
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(prev) = self.data.remove(key) {
+            if !self.snapshots.is_empty() {
+                self.undo.push((key.to_vec(), Some(prev)));
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        if self.snapshots.is_empty() {
+            self.data.clear();
+            return;
+        }
+        for (key, value) in self.data.drain() {
+            self.undo.push((key, Some(value)));
+        }
+    }
+
+    /// snapshot records the current position of the undo log and returns the snapshot id.
+    /// It does not copy the data, so that it takes the same time regardless of the size.
+    fn snapshot(&mut self) -> u32 {
+        let index = self.counter;
+        self.snapshots.push((index, self.undo.len()));
+        self.counter += 1;
+        index
+    }
+
+    /// restore_snapshot reverts the changes after the snapshot in the reverse order.
+    /// The snapshots taken after the id are dropped, and the snapshot of the id can be restored again.
+    fn restore_snapshot(&mut self, index: u32) -> Result<(), StateWriterError> {
+        let position = self
+            .snapshots
+            .iter()
+            .position(|(id, _)| *id == index)
+            .ok_or(StateWriterError::InvalidUsage)?;
+        let (_, undo_len) = self.snapshots[position];
+        while self.undo.len() > undo_len {
+            let (key, prev) = self.undo.pop().unwrap();
+            match prev {
+                Some(value) => self.data.insert(key, value),
+                None => self.data.remove(&key),
+            };
+        }
+        self.snapshots.truncate(position + 1);
+        Ok(())
     }
 }
 
@@ -86,18 +148,26 @@ impl Database {
             .collect()
     }
 
+    pub(crate) fn new() -> Self {
+        Self {
+            cache: CacheData::default(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+        self.cache.data.get(key)
+    }
+
     fn clear(&mut self) {
-        self.cache.data.clear();
+        self.cache.clear();
     }
 
-    fn set_key_value(&mut self, pair: &KVPair) {
-        self.cache
-            .data
-            .insert(pair.key_as_vec(), pair.value_as_vec());
+    pub(crate) fn set_key_value(&mut self, pair: &KVPair) {
+        self.cache.insert(pair.key_as_vec(), pair.value_as_vec());
     }
 
-    fn del(&mut self, key: &[u8]) {
-        self.cache.data.remove(key);
+    pub(crate) fn del(&mut self, key: &[u8]) {
+        self.cache.remove(key);
     }
 
     fn clone(&self) -> Self {
@@ -108,10 +178,7 @@ impl Database {
 
 impl Database {
     pub fn js_new(mut ctx: FunctionContext) -> JsResult<JsBoxRef<Database>> {
-        let db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
-        let ref_db = RefCell::new(db);
+        let ref_db = RefCell::new(Database::new());
 
         Ok(ctx.boxed(ref_db))
     }
@@ -124,12 +191,12 @@ impl Database {
 
         let db = db.borrow_mut();
         let this = ctx.undefined();
-        let args: Vec<Handle<JsValue>> = match db.cache.data.get(&key) {
+        let args: Vec<Handle<JsValue>> = match db.get(&key) {
             Some(val) => {
                 let buffer = JsBuffer::external(&mut ctx, val.to_vec());
                 vec![ctx.null().upcast(), buffer.upcast()]
             },
-            None => vec![ctx.error("No data")?.upcast()],
+            None => vec![DbError::NotFound.to_js(&mut ctx)?.upcast()],
         };
         callback.call(&mut ctx, this, args)?;
 
@@ -139,6 +206,9 @@ impl Database {
     pub fn js_set(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
         let value = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        SizeLimits::default()
+            .check(&key, &value)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
         // Get the `this` value as a `JsBox<Database>`
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let mut db = db.borrow_mut();
@@ -150,6 +220,9 @@ impl Database {
 
     pub fn js_del(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        SizeLimits::default()
+            .check_key(&key)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
         // Get the `this` value as a `JsBox<Database>`
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
@@ -170,22 +243,24 @@ impl Database {
 
     pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let option_inputs = ctx.argument::<JsObject>(0)?;
-        let options = IterationOption::new(&mut ctx, option_inputs);
+        let options = IterationOption::new(&mut ctx, option_inputs)?;
         let callback = ctx.argument::<JsFunction>(1)?;
 
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow_mut();
 
-        let kv_pairs = get_key_value_pairs(db, &options);
+        let kv_pairs = get_key_value_pairs(&db, &options);
 
         let this = ctx.undefined();
         let arr = JsArray::new(&mut ctx, kv_pairs.len() as u32);
         for (i, kv) in kv_pairs.iter().enumerate() {
             let obj = ctx.empty_object();
             let key = JsBuffer::external(&mut ctx, kv.key_as_vec());
-            let value = JsBuffer::external(&mut ctx, kv.value_as_vec());
             obj.set(&mut ctx, "key", key)?;
-            obj.set(&mut ctx, "value", value)?;
+            if !options.keys_only {
+                let value = JsBuffer::external(&mut ctx, kv.value_as_vec());
+                obj.set(&mut ctx, "value", value)?;
+            }
             arr.set(&mut ctx, i as u32, obj)?;
         }
         let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), arr.upcast()];
@@ -204,17 +279,125 @@ impl Database {
         let mut db = db.borrow_mut();
 
         let batch = Arc::clone(&batch.borrow());
-        let inner_batch = batch.lock().unwrap();
-
-        inner_batch.batch.iterate(&mut db.cache);
+        let result = BatchClaim::new(&batch)
+            .map_err(DbError::from)
+            .and_then(|claim| {
+                // the batch is applied only if all the operations are within the limits
+                let result = SizeLimits::default().check_batch(&batch.lock().unwrap().batch);
+                if result.is_ok() {
+                    batch.lock().unwrap().batch.iterate(&mut db.cache);
+                }
+                claim.finish(result.is_ok());
+                result.map_err(DbError::from)
+            });
 
         let this = ctx.undefined();
-        let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
+        let args: Vec<Handle<JsValue>> = match result {
+            Ok(()) => vec![ctx.null().upcast()],
+            Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+        };
         callback.call(&mut ctx, this, args)?;
 
         Ok(ctx.undefined())
     }
 
+    /// js_snapshot is handler for JS ffi.
+    /// js "this" - InMemoryDatabase.
+    /// - @returns - snapshot id
+    pub fn js_snapshot(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let index = db.borrow_mut().cache.snapshot();
+
+        Ok(ctx.number(index))
+    }
+
+    /// js_restore_snapshot is handler for JS ffi.
+    /// js "this" - InMemoryDatabase.
+    /// - @params(0) - snapshot id
+    pub fn js_restore_snapshot(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let index = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as u32;
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let result = db.borrow_mut().cache.restore_snapshot(index);
+
+        match result {
+            Ok(()) => Ok(ctx.undefined()),
+            Err(err) => DbError::from(err).throw(&mut ctx),
+        }
+    }
+
+    /// js_snapshot_to_file is handler for JS ffi.
+    /// js "this" - InMemoryDatabase.
+    /// - @params(0) - path of the file to write the snapshot.
+    /// - @params(1) - Options for the write. {sync: bool}. sync flushes the file and the directory entry to the disk.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_snapshot_to_file(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let sync = ctx
+            .argument::<JsObject>(1)?
+            .get_opt::<JsBoolean, _, _>(&mut ctx, "sync")?
+            .map(|val| val.value(&mut ctx))
+            .unwrap_or(false);
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let data = db.borrow().cache.data.clone();
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = snapshot::write_to_file(Path::new(&path), &data, sync);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(()) => vec![ctx.null().upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_load_from_file is handler for JS ffi.
+    /// - @params(0) - path of the snapshot file.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - new InMemoryDatabase restored from the snapshot.
+    pub fn js_load_from_file(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = snapshot::read_from_file(Path::new(&path));
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(data) => {
+                        let db = Database {
+                            cache: CacheData::new(data),
+                        };
+                        let boxed = ctx.boxed(RefCell::new(db));
+                        vec![ctx.null().upcast(), boxed.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(ctx.undefined())
+    }
+
     pub fn js_clone(mut ctx: FunctionContext) -> JsResult<SharedStateDB> {
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
@@ -224,7 +407,7 @@ impl Database {
 
         let ref_db = RefCell::new(cloned);
 
-        return Ok(ctx.boxed(ref_db));
+        Ok(ctx.boxed(ref_db))
     }
 }
 
@@ -234,9 +417,7 @@ mod tests {
 
     #[test]
     fn test_db_cache_range() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         db.set_key_value(&KVPair::new(&[3, 3, 3, 3], &[33, 33, 33, 33]));
@@ -255,9 +436,7 @@ mod tests {
 
     #[test]
     fn test_db_cache_all() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         db.set_key_value(&KVPair::new(&[3, 3, 3, 3], &[33, 33, 33, 33]));
@@ -276,9 +455,7 @@ mod tests {
 
     #[test]
     fn test_db_clear() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         assert_eq!(db.cache.data.len(), 2);
@@ -290,9 +467,7 @@ mod tests {
 
     #[test]
     fn test_db_set_kv() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         assert_eq!(db.cache.data.len(), 2);
@@ -301,16 +476,14 @@ mod tests {
         assert_eq!(db.cache.data.len(), 3);
 
         assert_eq!(
-            db.cache.data.get(&[3, 3, 3, 3].to_vec()).unwrap(),
+            db.cache.data.get([3, 3, 3, 3].as_slice()).unwrap(),
             &[33, 33, 33, 33]
         );
     }
 
     #[test]
     fn test_db_delete_key() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         db.set_key_value(&KVPair::new(&[3, 3, 3, 3], &[33, 33, 33, 33]));
@@ -318,14 +491,12 @@ mod tests {
         db.del(&[2, 2, 2, 2]);
 
         assert_eq!(db.cache.data.len(), 2);
-        assert_eq!(db.cache.data.get(&[2, 2, 2, 2].to_vec()), None);
+        assert_eq!(db.cache.data.get([2, 2, 2, 2].as_slice()), None);
     }
 
     #[test]
     fn test_db_clone() {
-        let mut db = Database {
-            cache: CacheData { data: Cache::new() },
-        };
+        let mut db = Database::new();
         db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
         db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
         db.set_key_value(&KVPair::new(&[3, 3, 3, 3], &[33, 33, 33, 33]));
@@ -334,7 +505,62 @@ mod tests {
 
         assert_eq!(cloned.cache.data.len(), 3);
         for key in cloned.cache.data.keys() {
-            assert!(db.cache.data.get(key).is_some());
+            assert!(db.cache.data.contains_key(key));
+        }
+    }
+
+    #[test]
+    fn test_db_snapshot() {
+        let mut db = Database::new();
+        db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[11, 11, 11, 11]));
+        db.set_key_value(&KVPair::new(&[2, 2, 2, 2], &[22, 22, 22, 22]));
+        let original = db.cache.data.clone();
+
+        let first = db.cache.snapshot();
+        db.set_key_value(&KVPair::new(&[1, 1, 1, 1], &[12, 12, 12, 12]));
+        db.del(&[2, 2, 2, 2]);
+        let updated = db.cache.data.clone();
+
+        let second = db.cache.snapshot();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put([3, 3, 3, 3], [33, 33, 33, 33]);
+        batch.put([3, 3, 3, 3], [34, 34, 34, 34]);
+        batch.delete([1, 1, 1, 1]);
+        batch.iterate(&mut db.cache);
+        assert_eq!(db.get(&[3, 3, 3, 3]), Some(&vec![34, 34, 34, 34]));
+        assert_eq!(
+            db.cache_all(),
+            vec![KVPair::new(&[3, 3, 3, 3], &[34, 34, 34, 34])]
+        );
+
+        db.cache.restore_snapshot(second).unwrap();
+        assert_eq!(db.cache.data, updated);
+        // same snapshot can be restored again
+        db.clear();
+        db.cache.restore_snapshot(second).unwrap();
+        assert_eq!(db.cache.data, updated);
+
+        db.cache.restore_snapshot(first).unwrap();
+        assert_eq!(db.cache.data, original);
+        // later snapshots are dropped
+        assert!(db.cache.restore_snapshot(second).is_err());
+        assert!(db.cache.restore_snapshot(100).is_err());
+    }
+
+    #[test]
+    fn test_db_snapshot_does_not_copy() {
+        let mut db = Database::new();
+        for i in 0..100u32 {
+            db.set_key_value(&KVPair::new(&i.to_be_bytes(), &[1]));
         }
+        // changes before the first snapshot are not recorded
+        assert!(db.cache.undo.is_empty());
+        let index = db.cache.snapshot();
+        assert!(db.cache.undo.is_empty());
+        db.set_key_value(&KVPair::new(&0u32.to_be_bytes(), &[2]));
+        assert_eq!(db.cache.undo.len(), 1);
+        db.cache.restore_snapshot(index).unwrap();
+        assert!(db.cache.undo.is_empty());
+        assert_eq!(db.get(&0u32.to_be_bytes()), Some(&vec![1]));
     }
 }
@@ -1 +1,3 @@
+#[cfg(feature = "node")]
 pub mod in_memory_db;
+pub mod snapshot;
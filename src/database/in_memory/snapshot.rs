@@ -0,0 +1,212 @@
+/// snapshot provides the file format to persist the cache of in_memory_db.
+/// The file is encoded with lisk-codec protocol:
+/// - field 1: magic bytes to identify the file.
+/// - field 2: version of the format.
+/// - field 3: records of key-value pair sorted by the key.
+/// - field 4: sha256 checksum of the records.
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::codec;
+use crate::types::{Cache, KVPair, KVPairCodec};
+use crate::utils;
+
+pub const MAGIC: &[u8] = b"lisk-db:in-memory";
+pub const VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Invalid snapshot header")]
+    InvalidHeader,
+    #[error("Unsupported snapshot version `{0}`")]
+    UnsupportedVersion(u32),
+    #[error("Corrupted snapshot `{0}`")]
+    Corrupted(String),
+    #[error("Snapshot file error `{0}`")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<codec::CodecError> for SnapshotError {
+    fn from(err: codec::CodecError) -> Self {
+        SnapshotError::Corrupted(err.to_string())
+    }
+}
+
+fn checksum(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for record in records {
+        hasher.update(record);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// encode the cache into the snapshot bytes.
+pub fn encode(data: &Cache) -> Vec<u8> {
+    let mut pairs: Vec<KVPair> = data.iter().map(|(k, v)| KVPair::new(k, v)).collect();
+    pairs.sort_by(|a, b| a.key().cmp(b.key()));
+    let records: Vec<Vec<u8>> = pairs.iter().map(|pair| pair.encode()).collect();
+
+    let mut writer = codec::Writer::new();
+    writer.write_bytes(1, MAGIC);
+    writer.write_u32(2, VERSION);
+    writer.write_bytes_slice(3, &records);
+    writer.write_bytes(4, &checksum(&records));
+    writer.result().to_vec()
+}
+
+/// decode the snapshot bytes into the cache.
+/// The cache is returned only when the whole input is valid.
+pub fn decode(bytes: &[u8]) -> Result<Cache, SnapshotError> {
+    let mut reader = codec::Reader::new(bytes);
+    let magic = reader
+        .read_bytes(1)
+        .map_err(|_| SnapshotError::InvalidHeader)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::InvalidHeader);
+    }
+    let version = reader.read_u32(2)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let records = reader.read_bytes_slice(3)?;
+    let expected_checksum = reader.read_bytes(4)?;
+    if !reader.is_end() {
+        return Err(SnapshotError::Corrupted(String::from(
+            "unexpected trailing bytes",
+        )));
+    }
+    if expected_checksum != checksum(&records) {
+        return Err(SnapshotError::Corrupted(String::from("checksum mismatch")));
+    }
+
+    let mut data = Cache::new();
+    for record in records.iter() {
+        let pair = KVPair::decode(record)?;
+        if data
+            .insert(pair.key_as_vec(), pair.value_as_vec())
+            .is_some()
+        {
+            return Err(SnapshotError::Corrupted(String::from("duplicate key")));
+        }
+    }
+    Ok(data)
+}
+
+/// write_to_file writes the snapshot to a temporary file and renames it to the path,
+/// so that the existing file is not replaced by incomplete snapshot.
+/// When sync is set, the file and the directory entry are flushed to the disk before returning.
+pub fn write_to_file(path: &Path, data: &Cache, sync: bool) -> Result<(), SnapshotError> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, encode(data))?;
+    fs::rename(&temp_path, path)?;
+    if sync {
+        utils::sync_path(path)?;
+    }
+    Ok(())
+}
+
+/// read_from_file reads and validates the snapshot from the path.
+pub fn read_from_file(path: &Path) -> Result<Cache, SnapshotError> {
+    let bytes = fs::read(path)?;
+    decode(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn sample_data() -> Cache {
+        let mut data = Cache::new();
+        data.insert(vec![1, 1, 1, 1], vec![11, 11, 11, 11]);
+        data.insert(vec![3, 3, 3], vec![]);
+        data.insert(vec![], vec![0]);
+        data.insert(vec![255; 300], vec![7; 1000]);
+        data
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let data = sample_data();
+        let bytes = encode(&data);
+        assert_eq!(decode(&bytes).unwrap(), data);
+
+        let empty = Cache::new();
+        assert_eq!(decode(&encode(&empty)).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_encode_is_deterministic() {
+        let data = sample_data();
+        assert_eq!(encode(&data), encode(&data.clone()));
+    }
+
+    #[test]
+    fn test_decode_invalid_header() {
+        assert!(matches!(decode(&[]), Err(SnapshotError::InvalidHeader)));
+        assert!(matches!(
+            decode(&[10, 3, 1, 2, 3]),
+            Err(SnapshotError::InvalidHeader)
+        ));
+
+        let mut writer = codec::Writer::new();
+        writer.write_bytes(1, MAGIC);
+        writer.write_u32(2, VERSION + 1);
+        assert!(matches!(
+            decode(writer.result()),
+            Err(SnapshotError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_decode_corrupted() {
+        let bytes = encode(&sample_data());
+
+        // truncated at every position must not decode
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "truncated at {}", len);
+        }
+
+        // flipped byte in the value must be detected by the checksum
+        let mut flipped = bytes.clone();
+        let index = bytes.len() - 100;
+        flipped[index] ^= 0xff;
+        assert!(decode(&flipped).is_err());
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_write_and_read_file() {
+        let temp_dir = TempDir::new("test_in_memory_snapshot").unwrap();
+        let path = temp_dir.path().join("snapshot.bin");
+        let data = sample_data();
+        write_to_file(&path, &data, false).unwrap();
+        assert_eq!(read_from_file(&path).unwrap(), data);
+        write_to_file(&path, &data, true).unwrap();
+        assert_eq!(read_from_file(&path).unwrap(), data);
+
+        assert!(matches!(
+            read_from_file(&temp_dir.path().join("unknown.bin")),
+            Err(SnapshotError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_write_to_file_syncs_the_path() {
+        let temp_dir = TempDir::new("test_in_memory_snapshot_sync").unwrap();
+        let path = temp_dir.path().join("snapshot.bin");
+        utils::take_synced_paths();
+        write_to_file(&path, &sample_data(), false).unwrap();
+        assert!(utils::take_synced_paths().is_empty());
+        write_to_file(&path, &sample_data(), true).unwrap();
+        assert_eq!(utils::take_synced_paths(), vec![path]);
+    }
+}
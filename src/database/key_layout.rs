@@ -0,0 +1,257 @@
+/// key_layout provides the layout of the keys of the module stores, which is the prefix of the keyspace followed by
+/// the module id, the store prefix and the key in the store.
+/// JS encodes and decodes the keys with it instead of concatenating the parts, so that the layout is defined once.
+#[cfg(feature = "node")]
+use neon::prelude::*;
+#[cfg(feature = "node")]
+use neon::types::buffer::TypedArray;
+use thiserror::Error;
+
+use crate::batch::BatchKind;
+#[cfg(feature = "node")]
+use crate::consts::{self, Prefix};
+#[cfg(feature = "node")]
+use crate::error::DbError;
+#[cfg(feature = "node")]
+use crate::types::KeyLength;
+use crate::types::{MODULE_ID_SIZE, PREFIX_SIZE, STORE_PREFIX_SIZE};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum KeyLayoutError {
+    /// UnsupportedKind is the error of the keyspace which does not hold the keys of the module stores.
+    #[error("kind must be one of state or raw, got {0}")]
+    UnsupportedKind(String),
+    /// InvalidPart is the error of the module id or the store prefix which does not have its length.
+    #[error("{part} must be {expected} bytes, got {got}")]
+    InvalidPart {
+        part: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    /// TooShort is the error of the key which does not have the prefixes.
+    #[error("key must be at least {min} bytes, got {got}")]
+    TooShort { min: usize, got: usize },
+    /// PrefixMismatch is the error of the key which is not in the keyspace of the kind.
+    #[error("key does not start with the prefix of the kind")]
+    PrefixMismatch,
+}
+
+/// StoreKey is the key of the module store split into the parts.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StoreKey<'a> {
+    pub module_id: &'a [u8],
+    pub store_prefix: &'a [u8],
+    pub key: &'a [u8],
+}
+
+/// store_kind returns the kind of the name if its keyspace holds the keys of the module stores.
+/// The keys of the diffs and the SMT nodes are the heights and the hashes.
+pub fn store_kind(name: &str) -> Result<BatchKind, KeyLayoutError> {
+    match BatchKind::new(name) {
+        Some(BatchKind::State) => Ok(BatchKind::State),
+        Some(BatchKind::Raw) => Ok(BatchKind::Raw),
+        _ => Err(KeyLayoutError::UnsupportedKind(name.to_string())),
+    }
+}
+
+fn check_part(part: &'static str, value: &[u8], expected: usize) -> Result<(), KeyLayoutError> {
+    if value.len() != expected {
+        return Err(KeyLayoutError::InvalidPart {
+            part,
+            expected,
+            got: value.len(),
+        });
+    }
+    Ok(())
+}
+
+/// encode returns the key of the kind, which is the key given to Kind::key for the state.
+pub fn encode(kind: BatchKind, store_key: &StoreKey) -> Result<Vec<u8>, KeyLayoutError> {
+    check_part("moduleId", store_key.module_id, MODULE_ID_SIZE)?;
+    check_part("storePrefix", store_key.store_prefix, STORE_PREFIX_SIZE)?;
+    Ok([
+        kind.prefix(),
+        store_key.module_id,
+        store_key.store_prefix,
+        store_key.key,
+    ]
+    .concat())
+}
+
+/// decode splits the key of the kind into the parts. The key in the store can be empty.
+pub fn decode(kind: BatchKind, full_key: &[u8]) -> Result<StoreKey, KeyLayoutError> {
+    let prefix = kind.prefix();
+    if full_key.len() < prefix.len() + PREFIX_SIZE {
+        return Err(KeyLayoutError::TooShort {
+            min: prefix.len() + PREFIX_SIZE,
+            got: full_key.len(),
+        });
+    }
+    if !full_key.starts_with(prefix) {
+        return Err(KeyLayoutError::PrefixMismatch);
+    }
+    let (module_id, rest) = full_key[prefix.len()..].split_at(MODULE_ID_SIZE);
+    let (store_prefix, key) = rest.split_at(STORE_PREFIX_SIZE);
+    Ok(StoreKey {
+        module_id,
+        store_prefix,
+        key,
+    })
+}
+
+/// js_key_encode is handler for JS ffi.
+/// - @params(0) - kind of the key. One of "state" and "raw".
+/// - @params(1) - module id of 4 bytes.
+/// - @params(2) - store prefix of 2 bytes.
+/// - @params(3) - key in the store.
+/// - @returns - key of the kind.
+#[cfg(feature = "node")]
+pub fn js_key_encode(mut ctx: FunctionContext) -> JsResult<JsBuffer> {
+    let name = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let module_id = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+    let store_prefix = ctx.argument::<JsTypedArray<u8>>(2)?.as_slice(&ctx).to_vec();
+    let key = ctx.argument::<JsTypedArray<u8>>(3)?.as_slice(&ctx).to_vec();
+    let encoded = store_kind(&name).and_then(|kind| {
+        encode(
+            kind,
+            &StoreKey {
+                module_id: &module_id,
+                store_prefix: &store_prefix,
+                key: &key,
+            },
+        )
+    });
+    match encoded {
+        Ok(encoded) => Ok(JsBuffer::external(&mut ctx, encoded)),
+        Err(err) => DbError::from(err).throw(&mut ctx),
+    }
+}
+
+/// js_key_decode is handler for JS ffi.
+/// - @params(0) - kind of the key. One of "state" and "raw".
+/// - @params(1) - key of the kind.
+/// - @returns - { moduleId: &[u8], storePrefix: &[u8], key: &[u8] }.
+#[cfg(feature = "node")]
+pub fn js_key_decode(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let name = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let full_key = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+    let store_key = match store_kind(&name).and_then(|kind| decode(kind, &full_key)) {
+        Ok(store_key) => store_key,
+        Err(err) => return DbError::from(err).throw(&mut ctx),
+    };
+    let obj = ctx.empty_object();
+    let module_id = JsBuffer::external(&mut ctx, store_key.module_id.to_vec());
+    obj.set(&mut ctx, "moduleId", module_id)?;
+    let store_prefix = JsBuffer::external(&mut ctx, store_key.store_prefix.to_vec());
+    obj.set(&mut ctx, "storePrefix", store_prefix)?;
+    let key = JsBuffer::external(&mut ctx, store_key.key.to_vec());
+    obj.set(&mut ctx, "key", key)?;
+    Ok(obj)
+}
+
+/// constants_to_js_object converts the layout to { prefixes: { state, smt, diff }, moduleIdLength, storePrefixLength, keyLength }.
+#[cfg(feature = "node")]
+pub fn constants_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    key_length: KeyLength,
+) -> JsResult<'a, JsObject> {
+    let prefixes = ctx.empty_object();
+    for (name, prefix) in [
+        ("state", Prefix::STATE),
+        ("smt", Prefix::SMT),
+        ("diff", Prefix::DIFF),
+    ] {
+        let prefix = JsBuffer::external(ctx, prefix.to_vec());
+        prefixes.set(ctx, name, prefix)?;
+    }
+    let obj = ctx.empty_object();
+    obj.set(ctx, "prefixes", prefixes)?;
+    let module_id_length = ctx.number(MODULE_ID_SIZE as f64);
+    obj.set(ctx, "moduleIdLength", module_id_length)?;
+    let store_prefix_length = ctx.number(STORE_PREFIX_SIZE as f64);
+    obj.set(ctx, "storePrefixLength", store_prefix_length)?;
+    let key_length = ctx.number(u16::from(key_length));
+    obj.set(ctx, "keyLength", key_length)?;
+    Ok(obj)
+}
+
+/// js_key_constants is handler for JS ffi.
+/// - @returns - { prefixes: { state: &[u8], smt: &[u8], diff: &[u8] }, moduleIdLength, storePrefixLength, keyLength } with the default key length.
+#[cfg(feature = "node")]
+pub fn js_key_constants(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    constants_to_js_object(&mut ctx, consts::KEY_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::types::Kind;
+
+    #[test]
+    fn test_key_layout_round_trip() {
+        let store_key = StoreKey {
+            module_id: &[0, 0, 0, 2],
+            store_prefix: &[0, 1],
+            key: &[7; 20],
+        };
+        for kind in [BatchKind::State, BatchKind::Raw] {
+            let encoded = encode(kind, &store_key).unwrap();
+            assert!(encoded.starts_with(kind.prefix()));
+            assert_eq!(decode(kind, &encoded).unwrap(), store_key);
+        }
+        // the state key is the key of Kind::State
+        assert_eq!(
+            encode(BatchKind::State, &store_key).unwrap(),
+            Kind::State.key([vec![0, 0, 0, 2, 0, 1], vec![7; 20]].concat())
+        );
+        // the key in the store can be empty
+        let prefix_only = decode(BatchKind::State, &[0, 0, 0, 0, 2, 0, 1]).unwrap();
+        assert!(prefix_only.key.is_empty());
+    }
+
+    #[test]
+    fn test_key_layout_malformed() {
+        assert_eq!(
+            store_kind("smt"),
+            Err(KeyLayoutError::UnsupportedKind(String::from("smt")))
+        );
+        assert_eq!(
+            encode(
+                BatchKind::State,
+                &StoreKey {
+                    module_id: &[0, 0, 2],
+                    store_prefix: &[0, 1],
+                    key: &[],
+                }
+            ),
+            Err(KeyLayoutError::InvalidPart {
+                part: "moduleId",
+                expected: 4,
+                got: 3
+            })
+        );
+        assert_eq!(
+            encode(
+                BatchKind::Raw,
+                &StoreKey {
+                    module_id: &[0, 0, 0, 2],
+                    store_prefix: &[1],
+                    key: &[],
+                }
+            ),
+            Err(KeyLayoutError::InvalidPart {
+                part: "storePrefix",
+                expected: 2,
+                got: 1
+            })
+        );
+        assert_eq!(
+            decode(BatchKind::State, &[0, 0, 0, 0, 2, 0]),
+            Err(KeyLayoutError::TooShort { min: 7, got: 6 })
+        );
+        assert_eq!(
+            decode(BatchKind::State, &[1, 0, 0, 0, 2, 0, 1]),
+            Err(KeyLayoutError::PrefixMismatch)
+        );
+    }
+}
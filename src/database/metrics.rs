@@ -0,0 +1,287 @@
+/// metrics provides the latency histograms of the operations run on the database thread.
+/// Metrics are opt-in. When disabled, the database holds no collector and the operations are sent without timers.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "node")]
+use neon::context::Context;
+#[cfg(feature = "node")]
+use neon::object::Object;
+#[cfg(feature = "node")]
+use neon::result::JsResult;
+#[cfg(feature = "node")]
+use neon::types::JsObject;
+
+/// BUCKET_BOUNDS_US is the upper bounds of the histogram buckets in microseconds, from 0.1ms to 10s.
+/// Durations over the last bound are counted in the overflow bucket.
+pub const BUCKET_BOUNDS_US: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+const NUM_BUCKETS: usize = BUCKET_BOUNDS_US.len() + 1;
+
+/// Operation is the type of the operation recorded in the metrics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Get,
+    Write,
+    Commit,
+    Prove,
+    Iterate,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 5] = [
+        Operation::Get,
+        Operation::Write,
+        Operation::Commit,
+        Operation::Prove,
+        Operation::Iterate,
+    ];
+
+    /// name returns the name of the operation exposed to JS.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::Write => "write",
+            Operation::Commit => "commit",
+            Operation::Prove => "prove",
+            Operation::Iterate => "iterate",
+        }
+    }
+}
+
+/// Histogram counts the durations in the fixed buckets.
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+/// HistogramSnapshot is the copy of the histogram. buckets are not cumulative, and the last one is the overflow.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_us: u64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let us = duration.as_micros().min(u64::MAX as u128) as u64;
+        let index = BUCKET_BOUNDS_US.partition_point(|bound| *bound < us);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// snapshot returns the copy of the counters. The counters are set to zero if reset is true.
+    fn snapshot(&self, reset: bool) -> HistogramSnapshot {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        HistogramSnapshot {
+            buckets: self.buckets.iter().map(read).collect(),
+            count: read(&self.count),
+            sum_us: read(&self.sum_us),
+        }
+    }
+}
+
+/// OperationSnapshot holds the histograms of an operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationSnapshot {
+    /// queue_wait is the time from the submission until the operation is started on the database thread.
+    pub queue_wait: HistogramSnapshot,
+    /// execution is the time the operation is run.
+    pub execution: HistogramSnapshot,
+}
+
+#[derive(Default)]
+struct OperationMetrics {
+    queue_wait: Histogram,
+    execution: Histogram,
+}
+
+/// Metrics collects the histograms of each operation.
+#[derive(Default)]
+pub struct Metrics {
+    operations: [OperationMetrics; Operation::ALL.len()],
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record adds the durations of the operation to the histograms.
+    pub fn record(&self, operation: Operation, queue_wait: Duration, execution: Duration) {
+        let metrics = &self.operations[operation as usize];
+        metrics.queue_wait.observe(queue_wait);
+        metrics.execution.observe(execution);
+    }
+
+    /// snapshot returns the histograms of all the operations. The counters are set to zero if reset is true.
+    /// Reset is done per counter, so that the operation recorded during the snapshot may be split into both.
+    pub fn snapshot(&self, reset: bool) -> Vec<(Operation, OperationSnapshot)> {
+        Operation::ALL
+            .iter()
+            .map(|operation| {
+                let metrics = &self.operations[*operation as usize];
+                let snapshot = OperationSnapshot {
+                    queue_wait: metrics.queue_wait.snapshot(reset),
+                    execution: metrics.execution.snapshot(reset),
+                };
+                (*operation, snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Timer measures an operation from its submission.
+/// The work done before the operation is queued, such as the read on the JS thread, is added to the execution with executed.
+pub struct Timer {
+    metrics: Arc<Metrics>,
+    operation: Operation,
+    started: Instant,
+    execution: Duration,
+}
+
+impl Timer {
+    pub fn new(metrics: Arc<Metrics>, operation: Operation) -> Self {
+        Self {
+            metrics,
+            operation,
+            started: Instant::now(),
+            execution: Duration::ZERO,
+        }
+    }
+
+    /// executed adds the time since the start to the execution, and starts the queue wait.
+    pub fn executed(&mut self) {
+        self.execution += self.started.elapsed();
+        self.started = Instant::now();
+    }
+
+    /// run runs f on the database thread, and records the queue wait and the execution.
+    pub fn run<T>(self, f: impl FnOnce() -> T) -> T {
+        let queue_wait = self.started.elapsed();
+        let started = Instant::now();
+        let result = f();
+        self.metrics.record(
+            self.operation,
+            queue_wait,
+            self.execution + started.elapsed(),
+        );
+        result
+    }
+}
+
+#[cfg(feature = "node")]
+fn histogram_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    histogram: &HistogramSnapshot,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let buckets = ctx.empty_array();
+    for (i, count) in histogram.buckets.iter().enumerate() {
+        let count = ctx.number(*count as f64);
+        buckets.set(ctx, i as u32, count)?;
+    }
+    obj.set(ctx, "buckets", buckets)?;
+    let count = ctx.number(histogram.count as f64);
+    obj.set(ctx, "count", count)?;
+    let sum_ms = ctx.number(histogram.sum_us as f64 / 1_000.0);
+    obj.set(ctx, "sumMs", sum_ms)?;
+
+    Ok(obj)
+}
+
+/// metrics_to_js_object converts the snapshot to
+/// { bucketBoundsMs: number[], [operation]: { queueWait: Histogram, execution: Histogram } },
+/// where Histogram is { buckets: number[], count: number, sumMs: number }.
+#[cfg(feature = "node")]
+pub fn metrics_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    snapshot: &[(Operation, OperationSnapshot)],
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let bounds = ctx.empty_array();
+    for (i, bound) in BUCKET_BOUNDS_US.iter().enumerate() {
+        let bound = ctx.number(*bound as f64 / 1_000.0);
+        bounds.set(ctx, i as u32, bound)?;
+    }
+    obj.set(ctx, "bucketBoundsMs", bounds)?;
+    for (operation, histograms) in snapshot {
+        let operation_obj = ctx.empty_object();
+        let queue_wait = histogram_to_js_object(ctx, &histograms.queue_wait)?;
+        operation_obj.set(ctx, "queueWait", queue_wait)?;
+        let execution = histogram_to_js_object(ctx, &histograms.execution)?;
+        operation_obj.set(ctx, "execution", execution)?;
+        obj.set(ctx, operation.name(), operation_obj)?;
+    }
+
+    Ok(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(50));
+        histogram.observe(Duration::from_micros(100));
+        histogram.observe(Duration::from_micros(101));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(11));
+
+        let snapshot = histogram.snapshot(false);
+        assert_eq!(snapshot.count, 5);
+        assert_eq!(snapshot.sum_us, 50 + 100 + 101 + 3_000 + 11_000_000);
+        assert_eq!(snapshot.buckets.len(), BUCKET_BOUNDS_US.len() + 1);
+        assert_eq!(snapshot.buckets[0], 2);
+        assert_eq!(snapshot.buckets[1], 1);
+        assert_eq!(snapshot.buckets[5], 1);
+        // over 10s is counted in the overflow bucket
+        assert_eq!(snapshot.buckets[NUM_BUCKETS - 1], 1);
+        assert_eq!(snapshot.buckets.iter().sum::<u64>(), snapshot.count);
+
+        assert_eq!(histogram.snapshot(true), snapshot);
+        assert_eq!(histogram.snapshot(false).count, 0);
+        assert!(histogram.snapshot(false).buckets.iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    fn test_timer() {
+        let metrics = Arc::new(Metrics::new());
+        let mut timer = Timer::new(metrics.clone(), Operation::Get);
+        std::thread::sleep(Duration::from_millis(2));
+        timer.executed();
+        let result = timer.run(|| 1);
+        assert_eq!(result, 1);
+
+        let timer = Timer::new(metrics.clone(), Operation::Commit);
+        timer.run(|| std::thread::sleep(Duration::from_millis(2)));
+
+        let snapshot = metrics.snapshot(false);
+        assert_eq!(snapshot.len(), Operation::ALL.len());
+        let (operation, get) = &snapshot[Operation::Get as usize];
+        assert_eq!(*operation, Operation::Get);
+        assert_eq!(get.execution.count, 1);
+        assert!(get.execution.sum_us >= 2_000);
+        let (_, commit) = &snapshot[Operation::Commit as usize];
+        assert_eq!(commit.queue_wait.count, 1);
+        assert!(commit.execution.sum_us >= 2_000);
+        let (_, write) = &snapshot[Operation::Write as usize];
+        assert_eq!(write.execution.count, 0);
+    }
+}
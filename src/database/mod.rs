@@ -1,11 +1,27 @@
+#[cfg(feature = "node")]
+pub mod checkpoint_group;
+pub mod chunked_write;
+pub mod column_families;
+pub mod conditional_write;
+#[cfg(feature = "node")]
 pub mod db;
 pub mod in_memory;
+pub mod key_layout;
+pub mod metrics;
 pub mod options;
+pub mod prefix_filter;
+#[cfg(feature = "node")]
+pub mod prefixed_db;
 pub mod reader_writer;
+pub mod registry;
+pub mod staged;
+pub mod timeout;
 pub mod traits;
 pub mod types;
 pub mod utils;
 
+#[cfg(feature = "node")]
 mod db_base;
 
-pub use db_base::DB;
+#[cfg(feature = "node")]
+pub use db_base::{checkpoint_info_to_js_object, DB};
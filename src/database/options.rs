@@ -1,11 +1,27 @@
 /// options provides functionality to read Database open and iteration options.
+use std::time::Duration;
+
+#[cfg(feature = "node")]
 use neon::prelude::*;
+#[cfg(feature = "node")]
 use neon::types::buffer::TypedArray;
 
 use crate::consts;
+#[cfg(feature = "node")]
 use crate::database::traits::OptionsWithContext;
 use crate::database::types::DbOptions;
-use crate::types::{KeyLength, VecOption};
+#[cfg(feature = "node")]
+use crate::database::types::{
+    CompactionStyle, Compression, StateKeyLength, TuningOptions, ValueCompression,
+    WriteDurability, WriteLimit,
+};
+#[cfg(feature = "node")]
+use crate::error;
+#[cfg(feature = "node")]
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::types::VecOption;
+#[cfg(feature = "node")]
+use crate::types::{KeyLength, PREFIX_SIZE};
 
 /// IterationOption holds iterator option for the database.
 #[derive(Clone, Debug)]
@@ -14,8 +30,127 @@ pub struct IterationOption {
     pub reverse: bool,
     pub gte: VecOption,
     pub lte: VecOption,
+    /// prefix limits the range to the keys starting with it. It cannot be used with gte or lte.
+    pub prefix: VecOption,
+    /// keys_only skips reading the values, and the pairs are returned with the empty value.
+    pub keys_only: bool,
+    /// inventory returns the byte length of the values instead of the values.
+    pub inventory: bool,
+    /// min_value_size skips the pairs whose value is shorter than it. It can only be used with inventory.
+    pub min_value_size: Option<usize>,
+}
+
+/// ItemFields is the fields of the iteration items passed to JS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemFields {
+    /// KeyValue is { key, value }.
+    KeyValue,
+    /// Key is { key } of keysOnly.
+    Key,
+    /// KeyValueSize is { key, valueSize } of inventory.
+    KeyValueSize,
 }
 
+/// CheckpointOption holds option for creating checkpoint.
+#[derive(Clone, Debug)]
+pub struct CheckpointOption {
+    /// sync flushes the created files and directory to the disk before resolving.
+    pub sync: bool,
+    /// flush_before_checkpoint flushes the memtables, so that the checkpoint does not depend on the WAL.
+    pub flush_before_checkpoint: bool,
+}
+
+/// ProveOption holds option for creating proof.
+#[derive(Clone, Debug, Default)]
+pub struct ProveOption {
+    /// include_values attaches the stored value to the inclusion queries.
+    /// Queries are the state keys instead of the hashed keys when it is true.
+    pub include_values: bool,
+    /// height proves against the state of the past height rebuilt from the stored diffs. Current state if None.
+    pub height: Option<u32>,
+    /// include_size attaches the bytes of the proof encoded by lisk-codec, so that the callers can log the proof size.
+    pub include_size: bool,
+}
+
+/// StateWriterOption holds option for creating StateWriter.
+#[derive(Clone, Debug, Default)]
+pub struct StateWriterOption {
+    /// spill_threshold is the bytes of the cache kept in the memory. The cache is not spilled if None.
+    pub spill_threshold: Option<usize>,
+}
+
+/// WriteOption holds option for writing the batch.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOption {
+    /// max_batch_bytes splits the write into the chunks for the bulk load. Single atomic batch if None.
+    pub max_batch_bytes: Option<usize>,
+    /// journal_key is the key to record the progress of the chunked write.
+    pub journal_key: Vec<u8>,
+}
+
+/// ChunkedIterationOption holds iterator option with the number of pairs sent to JS at once.
+#[derive(Clone, Debug)]
+pub struct ChunkedIterationOption {
+    pub iteration: IterationOption,
+    /// chunk_size is the maximum number of pairs read ahead of JS.
+    pub chunk_size: usize,
+    /// zero_copy moves the buffers read from rocksdb into the JS buffers instead of copying them.
+    pub zero_copy: bool,
+    /// max_result_size is the bytes of the keys and the values sent before the iteration stops as truncated.
+    /// It is set by the reader from maxIterationResultSize of the database, and unlimited if None.
+    pub max_result_size: Option<usize>,
+}
+
+/// ProfileRangeOption holds the range and the limits of the profiling scan.
+#[derive(Clone, Debug)]
+pub struct ProfileRangeOption {
+    pub iteration: IterationOption,
+    /// max_rows is the row cap of the scan.
+    pub max_rows: u64,
+    /// progress_interval is the number of keys between the progress reports.
+    pub progress_interval: u64,
+}
+
+/// VerifyNodesOption holds the limits of the SMT node scan.
+#[derive(Clone, Debug)]
+pub struct VerifyNodesOption {
+    /// max_errors is the number of mismatched node keys reported.
+    pub max_errors: usize,
+    /// progress_interval is the number of nodes between the progress reports.
+    pub progress_interval: u64,
+}
+
+/// PruneSMTOption holds the batch size of the SMT node pruning.
+#[derive(Clone, Debug)]
+pub struct PruneSMTOption {
+    /// batch_size is the number of the unreachable nodes deleted in a batch, and reported in a progress.
+    pub batch_size: usize,
+}
+
+/// RebuildSMTOption holds the batch size of the SMT rebuild.
+#[derive(Clone, Debug)]
+pub struct RebuildSMTOption {
+    /// batch_size is the number of the state keys inserted to the tree in a batch, and reported in a progress.
+    pub batch_size: usize,
+}
+
+/// StateSnapshotOption holds the progress report interval of the state snapshot export and import.
+#[derive(Clone, Debug)]
+pub struct StateSnapshotOption {
+    /// progress_interval is the number of records between the progress reports.
+    pub progress_interval: u64,
+    /// sync flushes the exported file and the directory entry to the disk before resolving. Ignored by the import.
+    pub sync: bool,
+}
+
+/// ShutdownOption holds the limit of the shutdown.
+#[derive(Clone, Debug)]
+pub struct ShutdownOption {
+    /// timeout is the time to wait for the pending operations.
+    pub timeout: Duration,
+}
+
+#[cfg(feature = "node")]
 impl OptionsWithContext for DbOptions {
     fn new_with_context<'a, C>(
         ctx: &mut C,
@@ -46,10 +181,311 @@ impl OptionsWithContext for DbOptions {
                 .unwrap_or_else(|| consts::KEY_LENGTH.into()),
         );
 
-        Ok(Self::new(readonly, key_length))
+        let mut options = Self::new(readonly, key_length);
+        options.tuning = TuningOptions::new_with_context(ctx, obj)?;
+        options.bloom_filter = get_bloom_filter(ctx, obj)?;
+        options.keep_diff_for_heights = match get_number(ctx, obj, "keepDiffForHeights")? {
+            Some(val) if val.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&val) => {
+                Some(val as u32)
+            },
+            Some(_) => {
+                return error::invalid_argument(
+                    "keepDiffForHeights must be a non-negative integer",
+                )
+                .throw(ctx)
+            },
+            None => None,
+        };
+        let block_on_full = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "blockOnFull")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        options.write_limit =
+            get_size(ctx, obj, "maxPendingOperations")?.map(|max_pending| WriteLimit {
+                max_pending,
+                block_on_full,
+            });
+        options.state_key_length = get_state_key_length(ctx, obj)?;
+        options.durability = WriteDurability::new_with_context(ctx, obj)?;
+        if let Some(max_key_size) = get_size(ctx, obj, "maxKeySize")? {
+            options.size_limits.max_key_size = max_key_size;
+        }
+        if let Some(max_value_size) = get_size(ctx, obj, "maxValueSize")? {
+            options.size_limits.max_value_size = max_value_size;
+        }
+        options.exclusive = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "exclusive")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(true);
+        options.reader_pool_size = get_size(ctx, obj, "readerPoolSize")?;
+        options.metrics = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "metrics")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        if let Some(name) = get_string(ctx, obj, "hasher")? {
+            options.hasher = hasher_from_name(ctx, &name)?;
+        }
+        options.operation_timeout =
+            get_size(ctx, obj, "operationTimeoutMs")?.map(|val| Duration::from_millis(val as u64));
+        options.diff_value_ref_threshold = get_size(ctx, obj, "diffValueRefThreshold")?;
+        options.smt_threads = get_size(ctx, obj, "smtThreads")?;
+        options.auto_migrate = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "autoMigrate")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        options.prefix_filter_length = get_size(ctx, obj, "prefixFilterLength")?;
+        options.unsafe_sync = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "unsafeSync")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        options.negative_cache_size = get_size(ctx, obj, "negativeCacheSize")?;
+        options.value_compression = get_value_compression(ctx, obj)?;
+        options.max_open_readers = get_size(ctx, obj, "maxOpenReaders")?;
+        options.max_iteration_result_size = get_size(ctx, obj, "maxIterationResultSize")?;
+
+        Ok(options)
+    }
+}
+
+#[cfg(feature = "node")]
+impl TuningOptions {
+    fn new_with_context<'a, C>(ctx: &mut C, obj: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let block_cache_size = get_size(ctx, obj, "blockCacheSize")?;
+        let write_buffer_size = get_size(ctx, obj, "writeBufferSize")?;
+        let max_open_files = match get_number(ctx, obj, "maxOpenFiles")? {
+            // -1 means unlimited for rocksdb
+            Some(val) if val == -1.0 || (val > 0.0 && val <= i32::MAX as f64) => Some(val as i32),
+            Some(_) => {
+                return error::invalid_argument("maxOpenFiles must be a positive integer or -1")
+                    .throw(ctx);
+            },
+            None => None,
+        };
+        let compression = match get_string(ctx, obj, "compression")? {
+            Some(name) => match Compression::from_name(&name) {
+                Some(val) => Some(val),
+                None => {
+                    return error::invalid_argument(format!(
+                        "compression must be one of none, lz4 or zstd, got {}",
+                        name
+                    ))
+                    .throw(ctx);
+                },
+            },
+            None => None,
+        };
+        let compaction_style = match get_string(ctx, obj, "compactionStyle")? {
+            Some(name) => match CompactionStyle::from_name(&name) {
+                Some(val) => Some(val),
+                None => {
+                    return error::invalid_argument(format!(
+                        "compactionStyle must be one of level or universal, got {}",
+                        name
+                    ))
+                    .throw(ctx);
+                },
+            },
+            None => None,
+        };
+
+        let bloom_bits_per_key = match get_number(ctx, obj, "bloomBitsPerKey")? {
+            Some(val) if (1.0..=64.0).contains(&val) => Some(val as u32),
+            Some(_) => {
+                return error::invalid_argument("bloomBitsPerKey must be between 1 and 64")
+                    .throw(ctx);
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            block_cache_size,
+            write_buffer_size,
+            max_open_files,
+            compression,
+            compaction_style,
+            bloom_bits_per_key,
+        })
     }
 }
 
+/// get_bloom_filter returns the false positive rate if bloomFilter is enabled.
+#[cfg(feature = "node")]
+impl WriteDurability {
+    fn new_with_context<'a, C>(ctx: &mut C, obj: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let sync_writes = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "syncWrites")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        let disable_wal = obj
+            .get_opt::<JsBoolean, _, _>(ctx, "disableWal")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        // rocksdb rejects the synced write without WAL
+        if sync_writes && disable_wal {
+            return error::invalid_argument("syncWrites cannot be used with disableWal")
+                .throw(ctx);
+        }
+
+        Ok(Self {
+            sync_writes,
+            disable_wal,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+fn get_bloom_filter<'a, C>(ctx: &mut C, obj: Handle<JsObject>) -> NeonResult<Option<f64>>
+where
+    C: Context<'a>,
+{
+    let enabled = obj
+        .get_opt::<JsBoolean, _, _>(ctx, "bloomFilter")?
+        .map(|val| val.value(ctx))
+        .unwrap_or(false);
+    let value = obj.get_value(ctx, "bloomFilterFalsePositiveRate")?;
+    let rate = if value.is_a::<JsUndefined, _>(ctx) || value.is_a::<JsNull, _>(ctx) {
+        consts::BLOOM_FILTER_FALSE_POSITIVE_RATE
+    } else {
+        let rate = value
+            .downcast::<JsNumber, _>(ctx)
+            .or_else(|_| {
+                error::invalid_argument("bloomFilterFalsePositiveRate must be a number").throw(ctx)
+            })?
+            .value(ctx);
+        if rate <= 0.0 || rate >= 1.0 {
+            return error::invalid_argument(
+                "bloomFilterFalsePositiveRate must be between 0 and 1",
+            )
+            .throw(ctx);
+        }
+        rate
+    };
+
+    Ok(if enabled { Some(rate) } else { None })
+}
+
+/// get_state_key_length returns the length of the state key if stateKeyLength is set.
+/// The prefix must cover the prefix of the SMT key, which is kept without hashing.
+#[cfg(feature = "node")]
+fn get_state_key_length<'a, C>(
+    ctx: &mut C,
+    obj: Handle<JsObject>,
+) -> NeonResult<Option<StateKeyLength>>
+where
+    C: Context<'a>,
+{
+    let prefix_length = get_size(ctx, obj, "statePrefixLength")?.unwrap_or(PREFIX_SIZE);
+    if prefix_length < PREFIX_SIZE {
+        return error::invalid_argument(format!(
+            "statePrefixLength must be at least {}",
+            PREFIX_SIZE
+        ))
+        .throw(ctx);
+    }
+    Ok(
+        get_size(ctx, obj, "stateKeyLength")?.map(|key_length| StateKeyLength {
+            prefix_length,
+            key_length,
+        }),
+    )
+}
+
+/// get_value_compression returns the compression of the state values if valueCompression is zstd.
+#[cfg(feature = "node")]
+fn get_value_compression<'a, C>(
+    ctx: &mut C,
+    obj: Handle<JsObject>,
+) -> NeonResult<Option<ValueCompression>>
+where
+    C: Context<'a>,
+{
+    let threshold = get_size(ctx, obj, "valueCompressionThreshold")?
+        .unwrap_or(consts::VALUE_COMPRESSION_THRESHOLD);
+    match get_string(ctx, obj, "valueCompression")?.as_deref() {
+        None | Some("none") => Ok(None),
+        Some("zstd") => Ok(Some(ValueCompression { threshold })),
+        Some(name) => error::invalid_argument(format!(
+            "valueCompression must be one of none or zstd, got {}",
+            name
+        ))
+        .throw(ctx),
+    }
+}
+
+/// get_number returns the number in the field, or throws if the field is not a number.
+#[cfg(feature = "node")]
+fn get_number<'a, C>(ctx: &mut C, obj: Handle<JsObject>, name: &str) -> NeonResult<Option<f64>>
+where
+    C: Context<'a>,
+{
+    let value = obj.get_value(ctx, name)?;
+    if value.is_a::<JsUndefined, _>(ctx) || value.is_a::<JsNull, _>(ctx) {
+        return Ok(None);
+    }
+    let number = value
+        .downcast::<JsNumber, _>(ctx)
+        .or_else(|_| error::invalid_argument(format!("{} must be a number", name)).throw(ctx))?;
+    let number = number.value(ctx);
+    if number.fract() != 0.0 {
+        return error::invalid_argument(format!("{} must be an integer", name)).throw(ctx);
+    }
+
+    Ok(Some(number))
+}
+
+#[cfg(feature = "node")]
+fn get_size<'a, C>(ctx: &mut C, obj: Handle<JsObject>, name: &str) -> NeonResult<Option<usize>>
+where
+    C: Context<'a>,
+{
+    match get_number(ctx, obj, name)? {
+        Some(val) if val > 0.0 && val <= usize::MAX as f64 => Ok(Some(val as usize)),
+        Some(_) => {
+            error::invalid_argument(format!("{} must be a positive integer", name)).throw(ctx)
+        },
+        None => Ok(None),
+    }
+}
+
+/// hasher_from_name returns the hasher of the SMT by the name.
+/// The name is rejected if it is unknown, or the feature of the hasher is not enabled.
+#[cfg(feature = "node")]
+pub fn hasher_from_name<'a, C>(ctx: &mut C, name: &str) -> NeonResult<HasherKind>
+where
+    C: Context<'a>,
+{
+    match HasherKind::from_name(name) {
+        Some(hasher) => Ok(hasher),
+        None => error::invalid_argument(format!(
+            "hasher must be one of the enabled hashers, got {}",
+            name
+        ))
+        .throw(ctx),
+    }
+}
+
+#[cfg(feature = "node")]
+fn get_string<'a, C>(ctx: &mut C, obj: Handle<JsObject>, name: &str) -> NeonResult<Option<String>>
+where
+    C: Context<'a>,
+{
+    let value = obj.get_value(ctx, name)?;
+    if value.is_a::<JsUndefined, _>(ctx) || value.is_a::<JsNull, _>(ctx) {
+        return Ok(None);
+    }
+    let value = value
+        .downcast::<JsString, _>(ctx)
+        .or_else(|_| error::invalid_argument(format!("{} must be a string", name)).throw(ctx))?;
+
+    Ok(Some(value.value(ctx)))
+}
+
 impl Default for DbOptions {
     fn default() -> Self {
         Self::new(false, consts::KEY_LENGTH)
@@ -57,7 +493,318 @@ impl Default for DbOptions {
 }
 
 impl IterationOption {
-    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> Self
+    /// item_fields returns the fields of the items of the iteration.
+    pub fn item_fields(&self) -> ItemFields {
+        if self.keys_only {
+            ItemFields::Key
+        } else if self.inventory {
+            ItemFields::KeyValueSize
+        } else {
+            ItemFields::KeyValue
+        }
+    }
+
+    /// is_reported returns true if the pair of the value is returned by the iteration, which is at least min_value_size.
+    pub fn is_reported(&self, value: &[u8]) -> bool {
+        self.min_value_size.map_or(true, |size| value.len() >= size)
+    }
+}
+
+impl Default for CheckpointOption {
+    fn default() -> Self {
+        Self {
+            sync: true,
+            flush_before_checkpoint: false,
+        }
+    }
+}
+
+#[cfg(feature = "node")]
+impl CheckpointOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Option<Handle<JsValue>>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let input = match input {
+            Some(input) if input.is_a::<JsObject, _>(ctx) => {
+                input.downcast_or_throw::<JsObject, _>(ctx)?
+            },
+            _ => return Ok(Self::default()),
+        };
+        let sync = input
+            .get_opt::<JsBoolean, _, _>(ctx, "sync")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(true);
+        let flush_before_checkpoint = input
+            .get_opt::<JsBoolean, _, _>(ctx, "flushBeforeCheckpoint")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+
+        Ok(Self {
+            sync,
+            flush_before_checkpoint,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl ProveOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Option<Handle<JsValue>>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let input = match input {
+            Some(input) if input.is_a::<JsObject, _>(ctx) => {
+                input.downcast_or_throw::<JsObject, _>(ctx)?
+            },
+            _ => return Ok(Self::default()),
+        };
+        let include_values = input
+            .get_opt::<JsBoolean, _, _>(ctx, "includeValues")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        let height = match get_number(ctx, input, "height")? {
+            Some(val) if (0.0..=u32::MAX as f64).contains(&val) => Some(val as u32),
+            Some(_) => {
+                return error::invalid_argument("height must be a non-negative integer").throw(ctx)
+            },
+            None => None,
+        };
+        let include_size = input
+            .get_opt::<JsBoolean, _, _>(ctx, "includeSize")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+
+        Ok(Self {
+            include_values,
+            height,
+            include_size,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl StateWriterOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Option<Handle<JsValue>>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let input = match input {
+            Some(input) if input.is_a::<JsObject, _>(ctx) => {
+                input.downcast_or_throw::<JsObject, _>(ctx)?
+            },
+            _ => return Ok(Self::default()),
+        };
+        let spill_threshold = get_size(ctx, input, "spillThreshold")?;
+
+        Ok(Self { spill_threshold })
+    }
+}
+
+#[cfg(feature = "node")]
+impl WriteOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Option<Handle<JsValue>>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let input = match input {
+            Some(input) if input.is_a::<JsObject, _>(ctx) => {
+                input.downcast_or_throw::<JsObject, _>(ctx)?
+            },
+            _ => return Ok(Self::default()),
+        };
+        let max_batch_bytes = match get_number(ctx, input, "maxBatchBytes")? {
+            Some(val) if val > 0.0 => val as usize,
+            Some(_) => {
+                return error::invalid_argument("maxBatchBytes must be a positive integer")
+                    .throw(ctx)
+            },
+            None => return Ok(Self::default()),
+        };
+        let journal_key = match input.get_opt::<JsTypedArray<u8>, _, _>(ctx, "journalKey")? {
+            Some(key) if !key.as_slice(ctx).is_empty() => key.as_slice(ctx).to_vec(),
+            _ => {
+                return error::invalid_argument("journalKey is required with maxBatchBytes")
+                    .throw(ctx)
+            },
+        };
+
+        Ok(Self {
+            max_batch_bytes: Some(max_batch_bytes),
+            journal_key,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl ChunkedIterationOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let iteration = IterationOption::new(ctx, input)?;
+        // chunkSize must be an integer of 1 or more, as the iteration does not proceed with the empty chunks.
+        let chunk_size = get_size(ctx, input, "chunkSize")?.unwrap_or(consts::ITERATE_CHUNK_SIZE);
+        let zero_copy = input
+            .get_opt::<JsBoolean, _, _>(ctx, "zeroCopy")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+
+        Ok(Self {
+            iteration,
+            chunk_size,
+            zero_copy,
+            max_result_size: None,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl ProfileRangeOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let iteration = IterationOption::new(ctx, input)?;
+        let max_rows = match get_number(ctx, input, "maxRows")? {
+            Some(val) if val > 0.0 => val as u64,
+            Some(_) => {
+                return error::invalid_argument("maxRows must be a positive integer").throw(ctx)
+            },
+            None => consts::PROFILE_RANGE_MAX_ROWS,
+        };
+        let progress_interval = match get_number(ctx, input, "progressInterval")? {
+            Some(val) if val > 0.0 => val as u64,
+            Some(_) => {
+                return error::invalid_argument("progressInterval must be a positive integer")
+                    .throw(ctx)
+            },
+            None => consts::PROFILE_RANGE_PROGRESS_INTERVAL,
+        };
+
+        Ok(Self {
+            iteration,
+            max_rows,
+            progress_interval,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl StateSnapshotOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let progress_interval = match get_number(ctx, input, "progressInterval")? {
+            Some(val) if val > 0.0 => val as u64,
+            Some(_) => {
+                return error::invalid_argument("progressInterval must be a positive integer")
+                    .throw(ctx)
+            },
+            None => consts::STATE_SNAPSHOT_PROGRESS_INTERVAL,
+        };
+        let sync = input
+            .get_opt::<JsBoolean, _, _>(ctx, "sync")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+
+        Ok(Self {
+            progress_interval,
+            sync,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl VerifyNodesOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let max_errors = match get_number(ctx, input, "maxErrors")? {
+            Some(val) if val > 0.0 => val as usize,
+            Some(_) => {
+                return error::invalid_argument("maxErrors must be a positive integer").throw(ctx)
+            },
+            None => consts::VERIFY_NODES_MAX_ERRORS,
+        };
+        let progress_interval = match get_number(ctx, input, "progressInterval")? {
+            Some(val) if val > 0.0 => val as u64,
+            Some(_) => {
+                return error::invalid_argument("progressInterval must be a positive integer")
+                    .throw(ctx)
+            },
+            None => consts::VERIFY_NODES_PROGRESS_INTERVAL,
+        };
+
+        Ok(Self {
+            max_errors,
+            progress_interval,
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl PruneSMTOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let batch_size = match get_number(ctx, input, "batchSize")? {
+            Some(val) if val > 0.0 => val as usize,
+            Some(_) => {
+                return error::invalid_argument("batchSize must be a positive integer").throw(ctx)
+            },
+            None => consts::PRUNE_SMT_BATCH_SIZE,
+        };
+
+        Ok(Self { batch_size })
+    }
+}
+
+#[cfg(feature = "node")]
+impl RebuildSMTOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let batch_size = match get_number(ctx, input, "batchSize")? {
+            Some(val) if val > 0.0 => val as usize,
+            Some(_) => {
+                return error::invalid_argument("batchSize must be a positive integer").throw(ctx)
+            },
+            None => consts::REBUILD_SMT_BATCH_SIZE,
+        };
+
+        Ok(Self { batch_size })
+    }
+}
+
+#[cfg(feature = "node")]
+impl ShutdownOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
+    where
+        C: Context<'a>,
+    {
+        let timeout_ms = match get_number(ctx, input, "timeoutMs")? {
+            Some(val) if val >= 0.0 => val as u64,
+            Some(_) => {
+                return error::invalid_argument("timeoutMs must be a non-negative integer")
+                    .throw(ctx)
+            },
+            None => consts::SHUTDOWN_TIMEOUT_MS,
+        };
+
+        Ok(Self {
+            timeout: Duration::from_millis(timeout_ms),
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl IterationOption {
+    pub fn new<'a, C>(ctx: &mut C, input: Handle<JsObject>) -> NeonResult<Self>
     where
         C: Context<'a>,
     {
@@ -86,11 +833,43 @@ impl IterationOption {
             .map(|val| val.map(|v| v.as_slice(ctx).to_vec()))
             .unwrap_or(None);
 
-        Self {
+        let prefix = input
+            .get_opt::<JsTypedArray<u8>, _, _>(ctx, "prefix")
+            .map(|val| val.map(|v| v.as_slice(ctx).to_vec()))
+            .unwrap_or(None);
+        if prefix.is_some() && (gte.is_some() || lte.is_some()) {
+            return error::invalid_argument("prefix cannot be used with gte or lte").throw(ctx);
+        }
+
+        let keys_only = input
+            .get_opt::<JsBoolean, _, _>(ctx, "keysOnly")
+            .map(|val| match val {
+                Some(v) => v.value(ctx),
+                None => false,
+            })
+            .unwrap_or(false);
+        let inventory = input
+            .get_opt::<JsBoolean, _, _>(ctx, "inventory")?
+            .map(|val| val.value(ctx))
+            .unwrap_or(false);
+        if inventory && keys_only {
+            return error::invalid_argument("inventory cannot be used with keysOnly").throw(ctx);
+        }
+        let min_value_size = get_size(ctx, input, "minValueSize")?;
+        if min_value_size.is_some() && !inventory {
+            return error::invalid_argument("minValueSize can only be used with inventory")
+                .throw(ctx);
+        }
+
+        Ok(Self {
             limit: limit as i64,
             reverse,
             gte,
             lte,
-        }
+            prefix,
+            keys_only,
+            inventory,
+            min_value_size,
+        })
     }
 }
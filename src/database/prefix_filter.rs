@@ -0,0 +1,235 @@
+/// prefix_filter configures the prefix extractor and the prefix bloom filters of the keyspace, so that whether any key
+/// starts with the prefix is answered by a single seek, which skips the memtables and the files without the prefix.
+/// Prefix extractor turns the default seek of the keyspace into the prefix seek, whose result is undefined beyond
+/// the prefix, so that all the other iterations of the keyspace use total_order_read_options.
+use std::convert::TryInto;
+
+use rocksdb::{DBAccess, DBRawIteratorWithThreadMode, Options, ReadOptions, SliceTransform};
+use thiserror::Error;
+
+use crate::database::types::{Kind, TuningOptions};
+use crate::database::utils;
+
+/// BLOOM_BITS_PER_KEY is the bits of the bloom filter of the files for each prefix, unless bloomBitsPerKey is set.
+const BLOOM_BITS_PER_KEY: f64 = 10.0;
+/// MEMTABLE_PREFIX_BLOOM_RATIO is the share of the write buffer used by the prefix bloom filter of the memtable.
+const MEMTABLE_PREFIX_BLOOM_RATIO: f64 = 0.1;
+
+#[derive(Error, Debug)]
+pub enum PrefixFilterError {
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+    /// Mismatch is the length which is not the length recorded on the first open with the filters.
+    /// The filters of the existing files are built with the recorded length.
+    #[error(
+        "prefix filter length {configured} does not match the length {recorded} of the database"
+    )]
+    Mismatch { recorded: usize, configured: usize },
+    #[error("recorded prefix filter length must be 4 bytes, got {0} bytes")]
+    InvalidLength(usize),
+}
+
+/// key_length returns the length of the stored key prefix, which includes the prefix of the kind.
+pub fn key_length(kind: &Kind, length: usize) -> usize {
+    kind.key(vec![]).len() + length
+}
+
+/// configure sets the prefix extractor of the key length, and the prefix bloom filters of the memtables and the files.
+pub fn configure(opts: &mut Options, tuning: &TuningOptions, key_length: usize) {
+    opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(key_length));
+    opts.set_memtable_prefix_bloom_ratio(MEMTABLE_PREFIX_BLOOM_RATIO);
+    let mut block_options = tuning.block_options();
+    let bits_per_key = tuning
+        .bloom_bits_per_key
+        .map_or(BLOOM_BITS_PER_KEY, f64::from);
+    block_options.set_bloom_filter(bits_per_key, false);
+    opts.set_block_based_table_factory(&block_options);
+}
+
+/// total_order_read_options returns the read options of the iteration which is not bounded to a prefix.
+pub fn total_order_read_options() -> ReadOptions {
+    let mut read_options = ReadOptions::default();
+    read_options.set_total_order_seek(true);
+    read_options
+}
+
+/// prefix_read_options returns the read options of the seek bounded to the prefix.
+/// The prefix filters are used if the prefix is at least the key length of the extractor.
+pub fn prefix_read_options(prefix: &[u8], key_length: Option<usize>) -> ReadOptions {
+    let mut read_options = ReadOptions::default();
+    match key_length {
+        Some(key_length) if prefix.len() >= key_length => {
+            read_options.set_prefix_same_as_start(true)
+        },
+        _ => read_options.set_total_order_seek(true),
+    }
+    let upper_bound = utils::next_prefix(prefix);
+    if !upper_bound.is_empty() {
+        read_options.set_iterate_upper_bound(upper_bound);
+    }
+    read_options
+}
+
+/// exists_prefix seeks the prefix, and returns true at the first key with the prefix which is accepted.
+/// The iterator must be created with prefix_read_options of the prefix.
+pub fn exists_prefix<D: DBAccess>(
+    mut iter: DBRawIteratorWithThreadMode<D>,
+    prefix: &[u8],
+    mut accept: impl FnMut(&[u8]) -> bool,
+) -> Result<bool, rocksdb::Error> {
+    iter.seek(prefix);
+    while let Some(key) = iter.key() {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        if accept(key) {
+            return Ok(true);
+        }
+        iter.next();
+    }
+    iter.status()?;
+    Ok(false)
+}
+
+/// check_length records the length on the first open with the filters, and refuses the other length after it.
+/// Database opened without the filters is not checked, as all the seeks are in total order.
+/// Readonly database is only checked, as the length cannot be recorded.
+pub fn check_length(
+    conn: &rocksdb::DB,
+    record_key: &[u8],
+    length: Option<usize>,
+    readonly: bool,
+) -> Result<(), PrefixFilterError> {
+    let configured = match length {
+        Some(length) => length,
+        None => return Ok(()),
+    };
+    match conn.get(record_key)? {
+        Some(bytes) => {
+            let recorded: [u8; 4] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| PrefixFilterError::InvalidLength(bytes.len()))?;
+            let recorded = u32::from_be_bytes(recorded) as usize;
+            if recorded != configured {
+                return Err(PrefixFilterError::Mismatch {
+                    recorded,
+                    configured,
+                });
+            }
+        },
+        None if readonly => {},
+        None => conn.put(record_key, (configured as u32).to_be_bytes())?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::consts;
+
+    const KEY_LENGTH: usize = 3;
+
+    fn open_db(temp_dir: &TempDir) -> rocksdb::DB {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        configure(&mut opts, &TuningOptions::default(), KEY_LENGTH);
+        rocksdb::DB::open(&opts, temp_dir.path()).unwrap()
+    }
+
+    fn exists(conn: &rocksdb::DB, prefix: &[u8], accept: impl FnMut(&[u8]) -> bool) -> bool {
+        let iter = conn.raw_iterator_opt(prefix_read_options(prefix, Some(KEY_LENGTH)));
+        exists_prefix(iter, prefix, accept).unwrap()
+    }
+
+    #[test]
+    fn test_exists_prefix() {
+        let temp_dir = TempDir::new("test_prefix_filter_exists").unwrap();
+        let conn = open_db(&temp_dir);
+        for key in [[0, 0, 1, 1], [0, 0, 3, 1], [0, 0, 3, 2]] {
+            conn.put(key, [1]).unwrap();
+        }
+        // keys in the files and in the memtable
+        conn.flush().unwrap();
+        conn.put([0, 0, 5, 1], [1]).unwrap();
+
+        for (prefix, expected) in [
+            (vec![0, 0, 1], true),
+            (vec![0, 0, 2], false),
+            (vec![0, 0, 3, 2], true),
+            (vec![0, 0, 3, 3], false),
+            (vec![0, 0, 5], true),
+            (vec![0, 0, 6], false),
+            // shorter prefixes are seeked in total order
+            (vec![0, 0], true),
+            (vec![0, 1], false),
+            (vec![], true),
+        ] {
+            assert_eq!(exists(&conn, &prefix, |_| true), expected, "{:?}", prefix);
+        }
+        // the keys not accepted are skipped
+        assert!(exists(&conn, &[0, 0, 3], |key| key == [0, 0, 3, 2]));
+        assert!(!exists(&conn, &[0, 0, 3], |_| false));
+    }
+
+    #[test]
+    fn test_total_order_iteration() {
+        let temp_dir = TempDir::new("test_prefix_filter_total_order").unwrap();
+        let conn = open_db(&temp_dir);
+        for key in [[0, 0, 1, 1], [0, 0, 3, 1]] {
+            conn.put(key, [1]).unwrap();
+        }
+        conn.flush().unwrap();
+
+        // the iteration from the prefix without any key continues to the next prefix
+        let mode = rocksdb::IteratorMode::From(&[0, 0, 2], rocksdb::Direction::Forward);
+        let keys: Vec<Vec<u8>> = conn
+            .iterator_opt(mode, total_order_read_options())
+            .map(|key_val| key_val.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(keys, vec![vec![0, 0, 3, 1]]);
+    }
+
+    #[test]
+    fn test_check_length() {
+        let temp_dir = TempDir::new("test_prefix_filter_length").unwrap();
+        let conn = open_db(&temp_dir);
+        let key = consts::PREFIX_FILTER_LENGTH_KEY;
+
+        // nothing is recorded without the filters, or by the readonly database
+        check_length(&conn, key, None, false).unwrap();
+        check_length(&conn, key, Some(6), true).unwrap();
+        assert_eq!(conn.get(key).unwrap(), None);
+
+        check_length(&conn, key, Some(6), false).unwrap();
+        assert_eq!(conn.get(key).unwrap(), Some(6_u32.to_be_bytes().to_vec()));
+        check_length(&conn, key, Some(6), true).unwrap();
+        check_length(&conn, key, None, false).unwrap();
+        for readonly in [false, true] {
+            let err = check_length(&conn, key, Some(7), readonly).unwrap_err();
+            assert!(matches!(
+                err,
+                PrefixFilterError::Mismatch {
+                    recorded: 6,
+                    configured: 7
+                }
+            ));
+        }
+
+        conn.put(key, [6]).unwrap();
+        assert!(matches!(
+            check_length(&conn, key, Some(6), false),
+            Err(PrefixFilterError::InvalidLength(1))
+        ));
+    }
+
+    #[test]
+    fn test_key_length() {
+        assert_eq!(key_length(&Kind::Normal, 6), 6);
+        assert_eq!(key_length(&Kind::State, 6), 7);
+    }
+}
@@ -0,0 +1,247 @@
+/// prefixed_db is the interface for the sub database scoped by the prefix, such as the module stores.
+/// It shares the rocksdb and the thread of the parent Database, and the keys are prefixed and stripped natively.
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+use crate::batch::{self, BatchClaim};
+use crate::database::db::{Database, SharedDatabase};
+use crate::database::metrics::Operation;
+use crate::database::options::ChunkedIterationOption;
+use crate::database::traits::{Closable, Unwrap};
+use crate::database::types::JsBoxRef;
+use crate::database::utils;
+use crate::error::{self, DbError};
+
+/// PrefixedDB holds the parent Database, so that closing the parent invalidates the handle.
+pub struct PrefixedDB {
+    prefix: Vec<u8>,
+    parent: Root<SharedDatabase>,
+}
+
+pub type SharedPrefixedDB = JsBoxRef<PrefixedDB>;
+
+impl Finalize for PrefixedDB {
+    fn finalize<'a, C: Context<'a>>(self, ctx: &mut C) {
+        self.parent.drop(ctx);
+    }
+}
+
+impl PrefixedDB {
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), key].concat()
+    }
+
+    /// js_new is handler for JS ffi.
+    /// js "this" - DB.
+    /// - @params(0) - prefix of the keys. It must not be empty.
+    /// - @returns - PrefixedDB sharing the connection of the DB.
+    pub fn js_new(mut ctx: FunctionContext) -> JsResult<SharedPrefixedDB> {
+        let prefix = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        if prefix.is_empty() {
+            return error::invalid_argument("prefix must not be empty").throw(&mut ctx);
+        }
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedDatabase, _>(&mut ctx)?;
+        db.borrow().throw_if_closed(&mut ctx)?;
+        let parent = db.root(&mut ctx);
+
+        Ok(ctx.boxed(RefCell::new(Self { prefix, parent })))
+    }
+
+    /// js_get is handler for JS ffi.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - key to get from db without the prefix.
+    /// - @params(1) - callback to return the fetched value.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
+    /// - @callback(1) - [u8]. Value associated with the key.
+    pub fn js_get(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.get_by_key(handle.key(&key), callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_exists is handler for JS ffi.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - key to check existence from db without the prefix.
+    /// - @params(1) - callback to return the fetched value.
+    /// - @callback(0) - Error
+    /// - @callback(1) - bool
+    pub fn js_exists(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.exists(handle.key(&key), callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_set is handler for JS ffi.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - key to set to the db without the prefix.
+    /// - @params(1) - value to set to the db.
+    /// - @params(2) - callback to return the fetched value.
+    /// - @callback(0) - Error
+    pub fn js_set(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let value = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        // the key must not be empty even though the prefixed key is not
+        db.throw_if_over_limits(&mut ctx, &key, None)?;
+        db.throw_if_over_limits(&mut ctx, &handle.key(&key), Some(&value))?;
+        db.throw_if_backpressure(&mut ctx)?;
+
+        let result = db.put(&handle.key(&key), &value);
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            Database::send_over_channel(channel, callback, result);
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_del is handler for JS ffi.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - key to delete from the db without the prefix.
+    /// - @params(1) - callback to return the fetched value.
+    /// - @callback(0) - Error
+    pub fn js_del(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_over_limits(&mut ctx, &key, None)?;
+        db.throw_if_over_limits(&mut ctx, &handle.key(&key), None)?;
+        db.throw_if_backpressure(&mut ctx)?;
+
+        let result = db.delete(&handle.key(&key));
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            Database::send_over_channel(channel, callback, result);
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_write is handler for JS ffi.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - Batch with the keys without the prefix.
+    /// - @params(1) - callback to return the fetched value.
+    /// - @callback(0) - Error
+    pub fn js_write(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let batch = ctx
+            .argument::<batch::SendableWriteBatch>(0)?
+            .downcast_or_throw::<batch::SendableWriteBatch, _>(&mut ctx)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_backpressure(&mut ctx)?;
+
+        let batch = Arc::clone(&batch.borrow());
+        batch.lock().unwrap().throw_if_closed(&mut ctx)?;
+        db.throw_if_batch_over_limits(&mut ctx, &batch.lock().unwrap().batch)?;
+        let claim = BatchClaim::new(&batch).or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        let prefix = handle.prefix.clone();
+        let conn = db.arc_clone();
+        let durability = db.durability();
+        db.send_write_timed(db.timer(Operation::Write), move |channel| {
+            let prefix = prefix.as_slice();
+            let mut write_batch = batch::PrefixWriteBatch::new();
+            write_batch.set_prefix(&prefix);
+            batch.lock().unwrap().batch.iterate(&mut write_batch);
+            let result = conn
+                .unwrap()
+                .write_opt(write_batch.batch, &durability.write_options(false));
+            claim.finish(result.is_ok());
+            Database::send_over_channel(channel, callback, result);
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_iterate is handler for JS ffi.
+    /// Iteration is bounded to the prefix, and the keys are returned without the prefix.
+    /// js "this" - PrefixedDB.
+    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], chunkSize: u32, keysOnly: bool}.
+    /// - @params(1) - Callback to be called on each data iteration.
+    /// - @params(2) - callback to be called when completing the iteration.
+    /// - @callback1(0) - Error.
+    /// - @callback1(1) - { key: &[u8], value: &[u8]}. Value is not set with keysOnly.
+    /// - @callback(0) - void.
+    pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let option_inputs = ctx.argument::<JsObject>(0)?;
+        let options = ChunkedIterationOption::new(&mut ctx, option_inputs)?;
+        let callback_on_data = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let callback_done = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedPrefixedDB, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let prefix = handle.prefix.clone();
+        let conn = db.arc_clone();
+        let closed = db.closed_flag();
+        db.send_timed(db.timer(Operation::Iterate), move |channel| {
+            let mut start = vec![];
+            let iter = conn.unwrap().iterator_opt(
+                utils::get_iteration_mode(&options.iteration, &mut start, &prefix),
+                utils::get_read_options(&options.iteration),
+            );
+            utils::send_in_chunks(
+                channel,
+                iter,
+                &options,
+                &prefix,
+                &closed,
+                callback_on_data,
+                callback_done,
+            );
+        })
+        .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+}
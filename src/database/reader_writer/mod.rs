@@ -1,5 +1,12 @@
+pub mod open_readers;
+#[cfg(feature = "node")]
+pub mod pool;
+pub mod range_cursor;
 pub mod read_writer_db;
+#[cfg(feature = "node")]
 pub mod reader_base;
+#[cfg(feature = "node")]
 pub mod reader_db;
 
+#[cfg(feature = "node")]
 pub use reader_base::{ReaderBase, SharedReaderBase};
@@ -0,0 +1,117 @@
+/// open_readers counts the readers and the read-writers open on StateDB, so that the number of the snapshots held
+/// at once is bounded. Each reader holds a slot, and the slot is released when the reader is closed or garbage collected.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("too many readers are open: maximum {max}")]
+pub struct TooManyReaders {
+    pub max: usize,
+}
+
+/// OpenReaders holds the time each open reader is created at by the id of its slot.
+#[derive(Default)]
+pub struct OpenReaders {
+    next_id: AtomicU64,
+    opened: Mutex<BTreeMap<u64, Instant>>,
+}
+
+/// ReaderSlot is the entry of an open reader, and it is removed from OpenReaders when dropped.
+pub struct ReaderSlot {
+    id: u64,
+    readers: Arc<OpenReaders>,
+}
+
+impl OpenReaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// open returns the slot of a new reader, or TooManyReaders if max readers are already open.
+    /// Unlimited if max is None.
+    pub fn open(self: &Arc<Self>, max: Option<usize>) -> Result<ReaderSlot, TooManyReaders> {
+        let mut opened = self.opened.lock().unwrap();
+        if let Some(max) = max {
+            if opened.len() >= max {
+                return Err(TooManyReaders { max });
+            }
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        opened.insert(id, Instant::now());
+        Ok(ReaderSlot {
+            id,
+            readers: self.clone(),
+        })
+    }
+
+    /// len returns the number of the open readers.
+    pub fn len(&self) -> usize {
+        self.opened.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ages returns the time since each open reader is created, the oldest first.
+    pub fn ages(&self) -> Vec<Duration> {
+        let now = Instant::now();
+        self.opened
+            .lock()
+            .unwrap()
+            .values()
+            .map(|created| now.saturating_duration_since(*created))
+            .collect()
+    }
+}
+
+impl Drop for ReaderSlot {
+    fn drop(&mut self) {
+        self.readers.opened.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_readers_limit() {
+        let readers = Arc::new(OpenReaders::new());
+        let first = readers.open(Some(2)).unwrap();
+        let second = readers.open(Some(2)).unwrap();
+        assert_eq!(readers.len(), 2);
+        assert_eq!(readers.open(Some(2)).err(), Some(TooManyReaders { max: 2 }));
+
+        // the slot is released on drop
+        drop(first);
+        assert_eq!(readers.len(), 1);
+        let third = readers.open(Some(2)).unwrap();
+        assert_eq!(readers.len(), 2);
+
+        drop(second);
+        drop(third);
+        assert!(readers.is_empty());
+        // unlimited
+        let slots: Vec<ReaderSlot> = (0..10).map(|_| readers.open(None).unwrap()).collect();
+        assert_eq!(readers.len(), 10);
+        drop(slots);
+        assert!(readers.is_empty());
+    }
+
+    #[test]
+    fn test_open_readers_ages() {
+        let readers = Arc::new(OpenReaders::new());
+        let _oldest = readers.open(None).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let _newest = readers.open(None).unwrap();
+        let ages = readers.ages();
+        assert_eq!(ages.len(), 2);
+        assert!(ages[0] > ages[1]);
+        assert!(ages[0] >= Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,313 @@
+/// pool provides the workers shared by the readers to run their operations on the snapshots.
+/// Each reader keeps its own snapshot, and its operations are run by one worker at a time in the submission order.
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::database::column_families::StateSnapshot;
+use crate::database::traits::Unwrap;
+use crate::database::types::ArcOptionDB;
+
+/// Task is the operation run on the snapshot of the reader.
+pub type Task = Box<dyn FnOnce(&StateSnapshot) + Send>;
+
+/// OwnedSnapshot is the snapshot holding the connection it is taken from,
+/// so that it can be moved between the workers of the pool.
+pub struct OwnedSnapshot {
+    // snapshot borrows the connection, and the fields are dropped in the declaration order.
+    // It must be declared before the connection, see new.
+    snapshot: StateSnapshot<'static>,
+    _conn: ArcOptionDB,
+}
+
+impl OwnedSnapshot {
+    /// new takes the snapshot of the connection.
+    pub fn new(conn: ArcOptionDB) -> Self {
+        // SAFETY: the lifetime of the borrow is extended to 'static. It is sound because
+        // - the database is allocated in the Arc, so that it does not move while the Arc is alive,
+        // - the Arc is kept in _conn, which is declared after snapshot, so that the snapshot is always dropped
+        //   before the Arc releases the database,
+        // - the 'static borrow never leaves the struct, as snapshot shortens it to the borrow of self.
+        let db: &'static rocksdb::DB = unsafe { &*(conn.unwrap() as *const rocksdb::DB) };
+        Self {
+            snapshot: StateSnapshot::new(db),
+            _conn: conn,
+        }
+    }
+
+    #[inline]
+    pub fn snapshot(&self) -> &StateSnapshot<'_> {
+        &self.snapshot
+    }
+}
+
+enum Message {
+    Task(Task),
+    Close,
+}
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+    /// running is true while the strand is waiting for a worker or run by a worker.
+    running: bool,
+    /// closed rejects the new tasks. The snapshot is released after the queued tasks.
+    closed: bool,
+    /// snapshot is taken out while a worker runs the task.
+    snapshot: Option<OwnedSnapshot>,
+}
+
+/// Strand is the queue of a reader. Only one worker runs its tasks at a time, so that they run in the submission order.
+pub struct Strand {
+    queue: Mutex<Queue>,
+    pool: Arc<ReaderPool>,
+}
+
+/// ReaderPool runs the tasks of the strands on the fixed number of the worker threads.
+/// Workers are stopped when the pool is dropped.
+pub struct ReaderPool {
+    sender: Mutex<mpsc::Sender<Arc<Strand>>>,
+}
+
+impl ReaderPool {
+    /// new starts the pool with size workers. At least one worker is started.
+    pub fn new(size: usize) -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel::<Arc<Strand>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let received = receiver.lock().unwrap().recv();
+                match received {
+                    Ok(strand) => strand.run_next(),
+                    Err(_) => return,
+                }
+            });
+        }
+        Arc::new(Self {
+            sender: Mutex::new(sender),
+        })
+    }
+
+    /// default_size returns the number of the CPUs, which is the default number of the workers.
+    pub fn default_size() -> usize {
+        thread::available_parallelism()
+            .map(|size| size.get())
+            .unwrap_or(1)
+    }
+
+    /// strand creates the queue of a reader holding the snapshot.
+    pub fn strand(self: &Arc<Self>, snapshot: OwnedSnapshot) -> Arc<Strand> {
+        Arc::new(Strand {
+            queue: Mutex::new(Queue {
+                snapshot: Some(snapshot),
+                ..Default::default()
+            }),
+            pool: self.clone(),
+        })
+    }
+
+    fn schedule(&self, strand: Arc<Strand>) {
+        // workers only stop after the pool is dropped, and the strand holds the pool
+        let _ = self.sender.lock().unwrap().send(strand);
+    }
+}
+
+impl Strand {
+    /// send queues the task. The task is returned if the strand is already closed.
+    pub fn send(self: &Arc<Self>, task: Task) -> Result<(), Task> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return Err(task);
+        }
+        queue.messages.push_back(Message::Task(task));
+        if queue.running {
+            return Ok(());
+        }
+        queue.running = true;
+        drop(queue);
+        self.pool.schedule(self.clone());
+        Ok(())
+    }
+
+    /// close rejects the new tasks, and releases the snapshot after the queued tasks.
+    /// The snapshot is released immediately if no task is queued or running.
+    pub fn close(self: &Arc<Self>) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return;
+        }
+        queue.closed = true;
+        if queue.running {
+            queue.messages.push_back(Message::Close);
+            return;
+        }
+        queue.snapshot = None;
+    }
+
+    /// run_next runs the first message on the worker, and schedules the strand again if more messages are queued.
+    /// Strand is scheduled for each task, so that a busy reader does not hold the worker.
+    fn run_next(self: Arc<Self>) {
+        let (message, snapshot) = {
+            let mut queue = self.queue.lock().unwrap();
+            (queue.messages.pop_front(), queue.snapshot.take())
+        };
+        let snapshot = match message {
+            Some(Message::Task(task)) => {
+                if let Some(snapshot) = snapshot.as_ref() {
+                    task(snapshot.snapshot());
+                }
+                snapshot
+            },
+            Some(Message::Close) | None => {
+                drop(snapshot);
+                None
+            },
+        };
+        let mut queue = self.queue.lock().unwrap();
+        queue.snapshot = snapshot;
+        if queue.messages.is_empty() {
+            queue.running = false;
+            return;
+        }
+        drop(queue);
+        let pool = self.pool.clone();
+        pool.schedule(self);
+    }
+
+    /// is_released returns true if the snapshot is released after close.
+    pub fn is_released(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.closed && !queue.running && queue.snapshot.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn temp_conn() -> (ArcOptionDB, TempDir) {
+        let temp_dir = TempDir::new("test_reader_pool").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 0..10_u8 {
+            conn.put([i], [i]).unwrap();
+        }
+        (Arc::new(Some(conn)), temp_dir)
+    }
+
+    #[test]
+    fn test_strand_keeps_order() {
+        let (conn, _temp_dir) = temp_conn();
+        let pool = ReaderPool::new(4);
+        let strands: Vec<Arc<Strand>> = (0..3)
+            .map(|_| pool.strand(OwnedSnapshot::new(conn.clone())))
+            .collect();
+        // writes after the snapshot are not visible to the readers
+        conn.unwrap().put([100], [100]).unwrap();
+
+        let (tx, rx) = mpsc::channel::<(usize, usize, usize)>();
+        let total = 1_000;
+        for i in 0..total {
+            for (index, strand) in strands.iter().enumerate() {
+                let tx = tx.clone();
+                // gets and iterations are interleaved on the same reader
+                let task: Task = if i % 2 == 0 {
+                    Box::new(move |snapshot| {
+                        assert_eq!(
+                            snapshot.get(&[(i % 10) as u8]).unwrap(),
+                            Some(vec![(i % 10) as u8])
+                        );
+                        assert_eq!(snapshot.get(&[100]).unwrap(), None);
+                        tx.send((index, i, 1)).unwrap();
+                    })
+                } else {
+                    Box::new(move |snapshot| {
+                        let count = snapshot
+                            .iterator(&[0], rocksdb::IteratorMode::Start)
+                            .count();
+                        tx.send((index, i, count)).unwrap();
+                    })
+                };
+                strand
+                    .send(task)
+                    .unwrap_or_else(|_| panic!("strand is closed"));
+            }
+        }
+        drop(tx);
+
+        let mut next = vec![0; strands.len()];
+        for (index, i, count) in rx.iter() {
+            assert_eq!(i, next[index], "tasks of a strand must run in order");
+            if i % 2 == 1 {
+                assert_eq!(count, 10);
+            }
+            next[index] += 1;
+        }
+        assert_eq!(next, vec![total; strands.len()]);
+    }
+
+    #[test]
+    fn test_strand_close() {
+        let (conn, _temp_dir) = temp_conn();
+        let pool = ReaderPool::new(1);
+
+        // idle strand releases the snapshot immediately
+        let idle = pool.strand(OwnedSnapshot::new(conn.clone()));
+        idle.close();
+        assert!(idle.is_released());
+        assert!(idle.send(Box::new(|_| {})).is_err());
+
+        // queued tasks run before the snapshot is released
+        let strand = pool.strand(OwnedSnapshot::new(conn.clone()));
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let done = done.clone();
+            strand
+                .send(Box::new(move |snapshot| {
+                    thread::sleep(Duration::from_millis(1));
+                    assert_eq!(snapshot.get(&[1]).unwrap(), Some(vec![1]));
+                    done.fetch_add(1, Ordering::SeqCst);
+                }))
+                .unwrap_or_else(|_| panic!("strand is closed"));
+        }
+        strand.close();
+        assert!(strand.send(Box::new(|_| {})).is_err());
+        while !strand.is_released() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(done.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_snapshot_outlives_pool_and_conn() {
+        let (conn, _temp_dir) = temp_conn();
+        let snapshot = OwnedSnapshot::new(conn.clone());
+        // snapshot holds the last reference to the database
+        drop(conn);
+        assert_eq!(snapshot.snapshot().get(&[1]).unwrap(), Some(vec![1]));
+        drop(snapshot);
+
+        let (conn, _temp_dir) = temp_conn();
+        let pool = ReaderPool::new(1);
+        let strand = pool.strand(OwnedSnapshot::new(conn));
+        // strand keeps the pool and the snapshot keeps the database after the handles are dropped
+        drop(pool);
+        let (tx, rx) = mpsc::channel();
+        strand
+            .send(Box::new(move |snapshot| {
+                tx.send(snapshot.get(&[2]).unwrap()).unwrap();
+            }))
+            .unwrap_or_else(|_| panic!("strand is closed"));
+        assert_eq!(rx.recv().unwrap(), Some(vec![2]));
+        strand.close();
+        while !strand.is_released() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(strand);
+    }
+}
@@ -0,0 +1,431 @@
+/// range_cursor provides the cursor paging the range of ReadWriter merged with the StateWriter.
+/// The cursor copies the cached entries in the range on open, and the stored pairs are read from the snapshot
+/// of ReadWriter from the last position on each page, so that the pages are consistent with the state at open.
+#[cfg(feature = "node")]
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+#[cfg(feature = "node")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "node")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "node")]
+use neon::prelude::*;
+
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
+use crate::database::options::IterationOption;
+#[cfg(feature = "node")]
+use crate::database::reader_writer::SharedReaderBase;
+#[cfg(feature = "node")]
+use crate::database::traits::{Closable, HandleClosed};
+#[cfg(feature = "node")]
+use crate::database::types::JsBoxRef;
+use crate::database::utils::*;
+#[cfg(feature = "node")]
+use crate::error::{self, DbError};
+#[cfg(feature = "node")]
+use crate::state::state_writer::SendableStateWriter;
+use crate::state::state_writer::{StateWriter, StateWriterError};
+use crate::state::value_compression;
+use crate::types::{KVPair, VecOption};
+
+/// MAX_SAFE_INTEGER is the largest integer which is exact in the JS number.
+#[cfg(feature = "node")]
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// RangeCursor merges the cached entries copied from the writer with the stored pairs page by page.
+pub struct RangeCursor {
+    options: IterationOption,
+    /// cached is the entries of the writer in the range at open, ordered with the direction. None is the deleted key.
+    cached: VecDeque<(Vec<u8>, VecOption)>,
+    /// position is the last stored key consumed with the state prefix. The next page is read after it.
+    position: VecOption,
+    returned: i64,
+    done: bool,
+}
+
+impl RangeCursor {
+    /// new copies the cached entries of the writer in the range, including the deleted keys.
+    /// Pending increments are not observed, as with the range of ReadWriter.
+    pub fn new(writer: &StateWriter, options: IterationOption) -> Result<Self, StateWriterError> {
+        let cached = writer.get_range_with_deleted(&options)?;
+        let cached: VecDeque<(Vec<u8>, VecOption)> = if options.reverse {
+            cached.into_iter().rev().collect()
+        } else {
+            cached.into_iter().collect()
+        };
+        Ok(Self {
+            options,
+            cached,
+            position: None,
+            returned: 0,
+            done: false,
+        })
+    }
+
+    /// options returns the options of the range given on open.
+    pub fn options(&self) -> &IterationOption {
+        &self.options
+    }
+
+    /// next_page returns up to count pairs following the previous page from the snapshot.
+    /// The page is empty once the range or the limit is exhausted.
+    pub fn next_page(
+        &mut self,
+        conn: &StateSnapshot,
+        count: usize,
+    ) -> Result<Vec<KVPair>, rocksdb::Error> {
+        if self.done {
+            return Ok(vec![]);
+        }
+        let stored = stored_after(conn, &self.options, self.position.clone());
+        self.merge_page(stored, count)
+    }
+
+    /// merge_page merges the cached entries with the stored pairs, which have the state prefix and follow the position
+    /// in the direction. A stored pair is overridden by the cached entry of the key, and skipped if it is deleted.
+    /// The stored pair read but not returned is read again on the next page, as the position is not moved.
+    fn merge_page(
+        &mut self,
+        stored: impl Iterator<Item = Result<KVPair, rocksdb::Error>>,
+        count: usize,
+    ) -> Result<Vec<KVPair>, rocksdb::Error> {
+        let count = if self.options.limit == -1 {
+            count
+        } else {
+            count.min((self.options.limit - self.returned).max(0) as usize)
+        };
+        let mut stored = stored.fuse();
+        let mut peeked: Option<KVPair> = None;
+        let mut page = vec![];
+        while page.len() < count {
+            if peeked.is_none() {
+                peeked = stored.next().transpose()?;
+            }
+            let order = match (self.cached.front(), peeked.as_ref()) {
+                (None, None) => {
+                    self.done = true;
+                    break;
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some(pair)) => {
+                    let order = key.as_slice().cmp(&pair.key()[Prefix::STATE.len()..]);
+                    if self.options.reverse {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                },
+            };
+            if order != Ordering::Less {
+                // stored pair is consumed, whether returned or overridden by the writer
+                let pair = peeked.take().unwrap();
+                self.position = Some(pair.key().to_vec());
+                if order == Ordering::Greater {
+                    self.push(&mut page, &pair.key()[Prefix::STATE.len()..], pair.value());
+                    continue;
+                }
+            }
+            let (key, value) = self.cached.pop_front().unwrap();
+            if let Some(value) = value {
+                self.push(&mut page, &key, &value);
+            }
+        }
+        self.returned += page.len() as i64;
+
+        Ok(page)
+    }
+
+    fn push(&self, page: &mut Vec<KVPair>, key: &[u8], value: &[u8]) {
+        if !self.options.is_reported(value) {
+            return;
+        }
+        let value: &[u8] = if self.options.keys_only { &[] } else { value };
+        page.push(KVPair::new(key, value));
+    }
+}
+
+/// stored_after iterates the stored pairs in the range with the state prefix, after the position if it is set.
+/// Values are not copied with keys_only, and they are decompressed otherwise.
+fn stored_after<'a>(
+    conn: &'a StateSnapshot,
+    options: &IterationOption,
+    position: VecOption,
+) -> impl Iterator<Item = Result<KVPair, rocksdb::Error>> + 'a {
+    let unlimited = IterationOption {
+        limit: -1,
+        ..options.clone()
+    };
+    let mut start = vec![];
+    let mode = match &position {
+        Some(position) if options.reverse => {
+            rocksdb::IteratorMode::From(position, rocksdb::Direction::Reverse)
+        },
+        Some(position) => rocksdb::IteratorMode::From(position, rocksdb::Direction::Forward),
+        None => get_iteration_mode(options, &mut start, Prefix::STATE),
+    };
+    let mut iter = conn.iterator_opt(Prefix::STATE, mode, get_read_options(options));
+    std::iter::from_fn(move || loop {
+        let (key, value) = match iter.next()? {
+            Ok(key_val) => key_val,
+            Err(err) => return Some(Err(err)),
+        };
+        if position.as_deref() == Some(&key[..])
+            || is_key_after_prefix(&unlimited, &key, Prefix::STATE)
+        {
+            continue;
+        }
+        if is_key_out_of_range(&unlimited, &key, 0, Prefix::STATE) {
+            return None;
+        }
+        let value = if unlimited.keys_only {
+            vec![]
+        } else {
+            value_compression::decode_vec(value.into_vec())
+        };
+        return Some(Ok(KVPair(key.into_vec(), value)));
+    })
+}
+
+/// RangeCursorHandle is the cursor boxed to JS. It holds the ReadWriter, whose snapshot is read by the pages.
+/// The cursor does not hold the writer, so that the commit of the writer does not affect the pages.
+#[cfg(feature = "node")]
+pub struct RangeCursorHandle {
+    /// cursor is released on close.
+    cursor: Arc<Mutex<Option<RangeCursor>>>,
+    parent: Root<SharedReaderBase>,
+    closed: AtomicBool,
+}
+
+#[cfg(feature = "node")]
+pub type SharedRangeCursor = JsBoxRef<RangeCursorHandle>;
+
+#[cfg(feature = "node")]
+impl Finalize for RangeCursorHandle {
+    fn finalize<'a, C: Context<'a>>(self, ctx: &mut C) {
+        self.parent.drop(ctx);
+    }
+}
+
+#[cfg(feature = "node")]
+impl Closable for RangeCursorHandle {
+    const NAME: &'static str = "RangeCursor";
+
+    fn closed(&self) -> &AtomicBool {
+        &self.closed
+    }
+}
+
+#[cfg(feature = "node")]
+impl RangeCursorHandle {
+    /// js_open is handler for JS ffi.
+    /// The cached entries in the range are copied from the StateWriter, so that the changes after the open
+    /// including the commit are not observed by the pages.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], keysOnly: bool}.
+    /// - @returns - RangeCursor.
+    pub fn js_open(mut ctx: FunctionContext) -> JsResult<SharedRangeCursor> {
+        let batch = ctx
+            .argument::<SendableStateWriter>(0)?
+            .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = IterationOption::new(&mut ctx, option_inputs)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        db.borrow().throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&batch.borrow());
+        let writer = writer.read().unwrap();
+        writer.throw_if_closed(&mut ctx)?;
+        let cursor = RangeCursor::new(&writer, options)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        let parent = db.root(&mut ctx);
+
+        Ok(ctx.boxed(RefCell::new(Self {
+            cursor: Arc::new(Mutex::new(Some(cursor))),
+            parent,
+            closed: AtomicBool::new(false),
+        })))
+    }
+
+    /// js_next is handler for JS ffi.
+    /// js "this" - RangeCursor.
+    /// - @params(0) - maximum number of the pairs in the page.
+    /// - @params(1) - callback to return the page.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - [{ key: &[u8], value: &[u8]}] following the previous page. It is empty when the range is exhausted.
+    pub fn js_next(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let count = ctx.argument::<JsNumber>(0)?.value(&mut ctx);
+        if count < 1.0 || count.fract() != 0.0 || count > MAX_SAFE_INTEGER {
+            return error::invalid_argument("count must be a positive integer").throw(&mut ctx);
+        }
+        let count = count as usize;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedRangeCursor, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        handle.throw_if_closed(&mut ctx)?;
+        let db = handle.parent.to_inner(&mut ctx);
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let cursor = Arc::clone(&handle.cursor);
+        db.send(move |conn, channel| {
+            // cursor is released if it is closed after the page is queued
+            let result = match cursor.lock().unwrap().as_mut() {
+                Some(cursor) => cursor
+                    .next_page(conn, count)
+                    .map(|page| (page, cursor.options().item_fields()))
+                    .map_err(DbError::from),
+                None => Err(DbError::from(HandleClosed(Self::NAME))),
+            };
+            channel.send(move |mut ctx| {
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok((page, fields)) => {
+                        let page = pairs_to_js_array(&mut ctx, &page, fields)?;
+                        vec![ctx.null().upcast(), page.upcast()]
+                    },
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+                let this = ctx.undefined();
+                let callback = callback.into_inner(&mut ctx);
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+        .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_close is handler for JS ffi.
+    /// The copied entries are released, and close is no-op if the cursor is already closed.
+    /// js "this" - RangeCursor.
+    pub fn js_close(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let handle = ctx
+            .this()
+            .downcast_or_throw::<SharedRangeCursor, _>(&mut ctx)?;
+        let handle = handle.borrow();
+        handle.mark_closed();
+        handle.cursor.lock().unwrap().take();
+
+        Ok(ctx.undefined())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SharedKVPair;
+
+    fn options(reverse: bool, limit: i64) -> IterationOption {
+        IterationOption {
+            limit,
+            reverse,
+            gte: Some(vec![1]),
+            lte: Some(vec![1, 255]),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        }
+    }
+
+    fn stored(
+        pairs: &[(&[u8], &[u8])],
+        position: &VecOption,
+        reverse: bool,
+    ) -> Vec<Result<KVPair, rocksdb::Error>> {
+        let mut pairs: Vec<KVPair> = pairs
+            .iter()
+            .map(|(key, value)| KVPair::new(&[Prefix::STATE, key].concat(), value))
+            .collect();
+        if reverse {
+            pairs.reverse();
+        }
+        pairs
+            .into_iter()
+            .filter(|pair| match position {
+                Some(position) if reverse => pair.key() < position.as_slice(),
+                Some(position) => pair.key() > position.as_slice(),
+                None => true,
+            })
+            .map(Ok)
+            .collect()
+    }
+
+    fn writer() -> StateWriter {
+        let mut writer = StateWriter::default();
+        // overrides the stored value
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 2], &[2]))
+            .unwrap();
+        writer.update(&KVPair::new(&[1, 2], &[22])).unwrap();
+        // deletes the stored value
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 3], &[3]))
+            .unwrap();
+        writer.delete(&[1, 3]).unwrap();
+        writer.cache_new(&SharedKVPair::new(&[1, 5], &[5])).unwrap();
+        writer
+    }
+
+    fn read_all(
+        cursor: &mut RangeCursor,
+        pairs: &[(&[u8], &[u8])],
+        count: usize,
+    ) -> Vec<Vec<KVPair>> {
+        let mut pages = vec![];
+        loop {
+            let stored = stored(pairs, &cursor.position, cursor.options.reverse);
+            let page = cursor.merge_page(stored.into_iter(), count).unwrap();
+            if page.is_empty() {
+                return pages;
+            }
+            pages.push(page);
+        }
+    }
+
+    const STORED: &[(&[u8], &[u8])] = &[
+        (&[1, 1], &[1]),
+        (&[1, 2], &[2]),
+        (&[1, 3], &[3]),
+        (&[1, 4], &[4]),
+    ];
+
+    #[test]
+    fn test_range_cursor_pages() {
+        let mut writer = writer();
+        let mut cursor = RangeCursor::new(&writer, options(false, -1)).unwrap();
+        // changes after the open are not observed
+        writer.cache_new(&SharedKVPair::new(&[1, 0], &[0])).unwrap();
+        writer.delete(&[1, 5]).unwrap();
+
+        let pages = read_all(&mut cursor, STORED, 2);
+        assert_eq!(
+            pages,
+            vec![
+                vec![KVPair::new(&[1, 1], &[1]), KVPair::new(&[1, 2], &[22])],
+                vec![KVPair::new(&[1, 4], &[4]), KVPair::new(&[1, 5], &[5])],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_cursor_reverse_and_limit() {
+        let mut cursor = RangeCursor::new(&writer(), options(true, 3)).unwrap();
+        let pages = read_all(&mut cursor, STORED, 2);
+        assert_eq!(
+            pages,
+            vec![
+                vec![KVPair::new(&[1, 5], &[5]), KVPair::new(&[1, 4], &[4])],
+                vec![KVPair::new(&[1, 2], &[22])],
+            ]
+        );
+    }
+}
@@ -1,59 +1,571 @@
+#[cfg(feature = "node")]
+use std::sync::Arc;
 /// read_writer is the interface for state read writer.
 /// State writer will snapshot the data and even if the change happen during the lifetime of reader writer, it will not be affected.
 /// writer will not store the data to physical storage until commit to the state db.
-use std::sync::{mpsc, Arc};
+use std::sync::RwLock;
 
+#[cfg(feature = "node")]
 use neon::context::{Context, FunctionContext};
-use neon::handle::Root;
+#[cfg(feature = "node")]
+use neon::handle::{Handle, Root};
+#[cfg(feature = "node")]
 use neon::object::Object;
+#[cfg(feature = "node")]
 use neon::result::JsResult;
-use neon::types::{buffer::TypedArray, JsBuffer, JsFunction, JsObject, JsTypedArray, JsUndefined};
+#[cfg(feature = "node")]
+use neon::types::{
+    buffer::TypedArray, JsBoolean, JsBuffer, JsFunction, JsNumber, JsObject, JsTypedArray,
+    JsUndefined, JsValue,
+};
 
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
 use crate::database::options;
+#[cfg(feature = "node")]
 use crate::database::reader_writer::{ReaderBase, SharedReaderBase};
-use crate::database::types::{Kind, SnapshotMessage};
+#[cfg(feature = "node")]
+use crate::database::traits::Closable;
+use crate::database::types::Kind;
 use crate::database::utils::*;
-use crate::state_writer;
-use crate::types::{ArcMutex, KVPair, SharedKVPair};
+#[cfg(feature = "node")]
+use crate::error;
+use crate::error::DbError;
+use crate::state::state_writer;
+use crate::state::value_compression;
+#[cfg(feature = "node")]
+use crate::types::ArcRwLock;
+use crate::types::{KVPair, SharedKVPair, VecOption};
 
+#[cfg(feature = "node")]
 pub type ReadWriter = ReaderBase;
+
+/// MAX_SAFE_INTEGER is the largest integer which is exact in the JS number.
+#[cfg(feature = "node")]
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// Upserted is the state of the key before the upsert.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Upserted {
+    /// existed is true if the key is cached in the writer and not deleted, or stored.
+    pub existed: bool,
+    /// previous_value is the value before the upsert. It is only set if requested and the key existed.
+    pub previous_value: VecOption,
+}
+
+/// upsert updates the key in the writer if cached. Otherwise, it caches the stored value and updates it,
+/// or caches as a new key if not stored.
+pub fn upsert(
+    writer: &mut state_writer::StateWriter,
+    key: &[u8],
+    new_value: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<(), state_writer::StateWriterError> {
+    upsert_with_previous(writer, key, new_value, stored, false).map(|_| ())
+}
+
+/// upsert_with_previous is upsert returning the state of the key before it.
+/// The state in the writer is used if the key is cached, and the stored value otherwise.
+/// The previous value is copied only if with_previous_value is true.
+/// Empty value is rejected, as the SMT treats it as the deletion and the key would be lost on commit.
+/// Pending increments of the key are overwritten. They are folded into the previous value if it is requested.
+pub fn upsert_with_previous(
+    writer: &mut state_writer::StateWriter,
+    key: &[u8],
+    new_value: &[u8],
+    stored: Option<Vec<u8>>,
+    with_previous_value: bool,
+) -> Result<Upserted, state_writer::StateWriterError> {
+    writer.check_not_consumed()?;
+    if new_value.is_empty() {
+        return Err(state_writer::StateWriterError::EmptyValue);
+    }
+    if with_previous_value {
+        writer.fold_increment(key, stored.clone())?;
+    } else {
+        writer.discard_increment(key);
+    }
+    if writer.is_cached(key)? {
+        let existed = !writer.is_deleted(key)?;
+        let previous_value = if with_previous_value && existed {
+            peek_with_writer(writer, key, None)?
+        } else {
+            None
+        };
+        writer.update(&KVPair::new(key, new_value))?;
+        return Ok(Upserted {
+            existed,
+            previous_value,
+        });
+    }
+    match stored {
+        Some(value) => {
+            writer.cache_existing(&SharedKVPair::new(key, &value))?;
+            writer.update(&KVPair::new(key, new_value))?;
+            Ok(Upserted {
+                existed: true,
+                previous_value: with_previous_value.then_some(value),
+            })
+        },
+        None => {
+            writer.cache_new(&SharedKVPair::new(key, new_value))?;
+            Ok(Upserted::default())
+        },
+    }
+}
+
+/// get_with_writer returns the value from the writer, or the stored value.
+/// Stored value is cached to the writer.
+pub fn get_with_writer(
+    writer: &mut state_writer::StateWriter,
+    key: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, state_writer::StateWriterError> {
+    if writer.is_cached(key)? {
+        return peek_with_writer(writer, key, None);
+    }
+    let value = match stored {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    writer.cache_existing(&SharedKVPair::new(key, &value))?;
+    Ok(Some(value))
+}
+
+/// peek_with_writer returns the value from the writer, or the stored value without caching it to the writer.
+/// Cached value is returned even if it is updated or deleted in the writer.
+pub fn peek_with_writer(
+    writer: &state_writer::StateWriter,
+    key: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, state_writer::StateWriterError> {
+    let (cached_value, deleted, exists) = writer.get(key)?;
+    if exists && !deleted {
+        return Ok(Some(cached_value));
+    }
+    if deleted {
+        return Ok(None);
+    }
+    Ok(stored)
+}
+
+/// get_many_with_writer returns the values of the keys through the writer in the order of the keys.
+/// Stored values are cached to the writer, and the key deleted in the writer is not found even if it is stored.
+pub fn get_many_with_writer(
+    writer: &mut state_writer::StateWriter,
+    keys: &[Vec<u8>],
+    stored: Vec<VecOption>,
+) -> Result<Vec<VecOption>, state_writer::StateWriterError> {
+    keys.iter()
+        .zip(stored)
+        .map(|(key, value)| get_with_writer(writer, key, value))
+        .collect()
+}
+
+/// get_with_locked_writer is get_with_writer taking the write lock only to cache the stored value.
+/// Reads of the cached keys and the keys which are not stored only take the read lock.
+pub fn get_with_locked_writer(
+    writer: &RwLock<state_writer::StateWriter>,
+    key: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, state_writer::StateWriterError> {
+    {
+        let reader = writer.read().unwrap();
+        if stored.is_none() || reader.is_cached(key)? {
+            return peek_with_writer(&reader, key, stored);
+        }
+    }
+    // the key may be cached after the read lock is released, and get_with_writer checks it again
+    get_with_writer(&mut writer.write().unwrap(), key, stored)
+}
+
+/// get_many_with_locked_writer is get_many_with_writer taking the write lock only if any stored value needs to be cached.
+pub fn get_many_with_locked_writer(
+    writer: &RwLock<state_writer::StateWriter>,
+    keys: &[Vec<u8>],
+    stored: Vec<VecOption>,
+) -> Result<Vec<VecOption>, state_writer::StateWriterError> {
+    {
+        let reader = writer.read().unwrap();
+        let mut cached = true;
+        for (key, value) in keys.iter().zip(stored.iter()) {
+            if value.is_some() && !reader.is_cached(key)? {
+                cached = false;
+                break;
+            }
+        }
+        if cached {
+            return keys
+                .iter()
+                .zip(stored)
+                .map(|(key, value)| peek_with_writer(&reader, key, value))
+                .collect();
+        }
+    }
+    get_many_with_writer(&mut writer.write().unwrap(), keys, stored)
+}
+
+/// fold_increment_with_locked_writer folds the pending increments of the key with the stored value,
+/// taking the write lock only if any increment of the key is pending.
+pub fn fold_increment_with_locked_writer(
+    writer: &RwLock<state_writer::StateWriter>,
+    key: &[u8],
+    stored: &Option<Vec<u8>>,
+) -> Result<(), state_writer::StateWriterError> {
+    if !writer.read().unwrap().has_increment(key) {
+        return Ok(());
+    }
+    writer.write().unwrap().fold_increment(key, stored.clone())
+}
+
+/// peek_incremented_with_writer is peek_with_writer returning the value after the pending increments of the key,
+/// without folding them.
+pub fn peek_incremented_with_writer(
+    writer: &state_writer::StateWriter,
+    key: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, state_writer::StateWriterError> {
+    match writer.incremented(key, stored.as_deref())? {
+        Some(value) => Ok(Some(value)),
+        None => peek_with_writer(writer, key, stored),
+    }
+}
+
+/// delete_with_writer marks the key as deleted in the writer.
+pub fn delete_with_writer(
+    writer: &mut state_writer::StateWriter,
+    key: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<(), state_writer::StateWriterError> {
+    if !writer.is_cached(key)? {
+        if let Some(value) = stored {
+            writer.cache_existing(&SharedKVPair::new(key, &value))?;
+        }
+    }
+    writer.delete(key)
+}
+
+/// merge_range merges the stored pairs in the range with the writer,
+/// and returns the result with the order and the limit specified.
+/// Stored pairs must have the state prefix.
+/// With keys_only, the pairs are returned with the empty value, and the stored pairs are not cached
+/// in the writer since their values are not read.
+/// With inventory, the stored pairs are not cached either, and the pairs not reported by min_value_size
+/// are removed before the limit, where the cached value is used if the key is cached.
+pub fn merge_range(
+    writer: &mut state_writer::StateWriter,
+    stored: &[KVPair],
+    options: &options::IterationOption,
+) -> Result<Vec<KVPair>, state_writer::StateWriterError> {
+    let mut result = writer.get_range(options)?;
+    for pair in stored.iter() {
+        let key_without_prefix = &pair.key()[Prefix::STATE.len()..];
+        let (cached_value, deleted, exists) = writer.get(key_without_prefix)?;
+        if exists && !deleted {
+            result.insert(key_without_prefix.to_vec(), cached_value);
+        } else if deleted {
+            continue;
+        } else if options.keys_only {
+            result.insert(key_without_prefix.to_vec(), vec![]);
+        } else if options.inventory {
+            result.insert(key_without_prefix.to_vec(), pair.value().to_vec());
+        } else {
+            // key is stored and returned without prefix
+            let shared_pair = SharedKVPair::new(key_without_prefix, pair.value());
+            writer.cache_existing(&shared_pair)?;
+            result.insert(shared_pair.key_as_vec(), shared_pair.value_as_vec());
+        }
+    }
+    let mut pairs: Vec<KVPair> = result
+        .iter()
+        .filter(|(_, v)| options.is_reported(v))
+        .map(|(k, v)| {
+            let value: &[u8] = if options.keys_only { &[] } else { v };
+            KVPair::new(k, value)
+        })
+        .collect();
+    sort_and_limit(&mut pairs, options);
+
+    Ok(pairs)
+}
+
+/// stored_range returns the stored pairs in the range with the state prefix.
+/// Limit is not applied since the stored pairs can be deleted in the writer.
+/// Values are not copied with keys_only.
+pub fn stored_range(
+    conn: &StateSnapshot,
+    options: &options::IterationOption,
+) -> Result<Vec<KVPair>, rocksdb::Error> {
+    stored_range_while(conn, options, |_| true).map(|(values, _)| values)
+}
+
+/// stored_range_within returns the stored pairs in the range up to max_size bytes, and true if the range is truncated.
+/// The stored pairs deleted in the writer are not counted, as they are not returned.
+pub fn stored_range_within(
+    conn: &StateSnapshot,
+    writer: &state_writer::StateWriter,
+    options: &options::IterationOption,
+    max_size: Option<usize>,
+) -> Result<(Vec<KVPair>, bool), DbError> {
+    let mut result_size = ResultSize::new(max_size);
+    let mut writer_result = Ok(());
+    let stored = stored_range_while(conn, options, |pair| {
+        match writer.is_deleted(&pair.key()[Prefix::STATE.len()..]) {
+            Ok(deleted) => deleted || result_size.fits(pair),
+            Err(err) => {
+                writer_result = Err(err);
+                false
+            },
+        }
+    })?;
+    writer_result?;
+    Ok(stored)
+}
+
+/// stored_range_while returns the stored pairs in the range while accepted, and true if stopped by it.
+fn stored_range_while(
+    conn: &StateSnapshot,
+    options: &options::IterationOption,
+    mut accept: impl FnMut(&KVPair) -> bool,
+) -> Result<(Vec<KVPair>, bool), rocksdb::Error> {
+    let unlimited = options::IterationOption {
+        limit: -1,
+        ..options.clone()
+    };
+    let mut values = vec![];
+    let mut start = vec![];
+    let iter = conn.iterator_opt(
+        Prefix::STATE,
+        get_iteration_mode(options, &mut start, Prefix::STATE),
+        get_read_options(options),
+    );
+    for key_val in iter {
+        let (key, value) = key_val?;
+        if is_key_after_prefix(options, &key, Prefix::STATE) {
+            continue;
+        }
+        if is_key_out_of_range(&unlimited, &key, 0, Prefix::STATE) {
+            break;
+        }
+        let value = if options.keys_only {
+            vec![]
+        } else {
+            value_compression::decode_vec(value.into_vec())
+        };
+        let pair = KVPair(key.into_vec(), value);
+        if !accept(&pair) {
+            return Ok((values, true));
+        }
+        values.push(pair);
+    }
+
+    Ok((values, false))
+}
+
+/// truncate_merged removes the merged pairs after the last stored pair if the stored range is truncated,
+/// since the stored pairs between them are not read, and then the pairs over max_size bytes.
+/// It returns true if the result does not hold the whole range up to the limit.
+pub fn truncate_merged(
+    pairs: &mut Vec<KVPair>,
+    stored: &[KVPair],
+    stored_truncated: bool,
+    options: &options::IterationOption,
+    max_size: Option<usize>,
+) -> bool {
+    let mut truncated = false;
+    if stored_truncated {
+        if let Some(last) = stored.last() {
+            let last = &last.key()[Prefix::STATE.len()..];
+            pairs.retain(|pair| {
+                if options.reverse {
+                    pair.key() >= last
+                } else {
+                    pair.key() <= last
+                }
+            });
+        }
+        truncated = options.limit == -1 || pairs.len() < options.limit as usize;
+    }
+    ResultSize::new(max_size).truncate(pairs) || truncated
+}
+
+/// stored_values returns the stored values of the keys in the order of the keys.
+pub fn stored_values(
+    conn: &StateSnapshot,
+    keys: &[Vec<u8>],
+) -> Result<Vec<VecOption>, rocksdb::Error> {
+    keys.iter()
+        .map(|key| {
+            conn.get(&Kind::State.key(key.clone()))
+                .map(|value| value.map(value_compression::decode_vec))
+        })
+        .collect()
+}
+
+/// count_range_with_writer counts the keys in the range merged with the writer, up to the limit.
+/// Keys cached in the writer are counted unless deleted, and the stored keys are counted only if not cached.
+pub fn count_range_with_writer(
+    conn: &StateSnapshot,
+    writer: &state_writer::StateWriter,
+    options: &options::IterationOption,
+) -> Result<i64, DbError> {
+    let mut cached = writer.count_range(options)? as i64;
+    if options.limit != -1 {
+        cached = cached.min(options.limit);
+    }
+    let mut start = vec![];
+    let iter = conn.iterator(
+        Prefix::STATE,
+        get_iteration_mode(options, &mut start, Prefix::STATE),
+    );
+    let mut writer_result = Ok(());
+    let count = count_in_range(iter, options, cached, Prefix::STATE, |key| {
+        writer.is_cached(key).unwrap_or_else(|err| {
+            writer_result = Err(err);
+            true
+        })
+    })?;
+    writer_result?;
+    Ok(count)
+}
+
+/// exists_prefix_with_writer returns true if any key with the prefix exists in the state merged with the writer.
+/// Keys cached in the writer exist unless deleted, and the stored keys deleted in the writer are skipped.
+/// key_length is the prefix length of the prefix filters of the state keys, if configured.
+pub fn exists_prefix_with_writer(
+    conn: &StateSnapshot,
+    writer: &state_writer::StateWriter,
+    prefix: &[u8],
+    key_length: Option<usize>,
+) -> Result<bool, DbError> {
+    if writer.has_prefix(prefix)? {
+        return Ok(true);
+    }
+    let prefix = Kind::State.key(prefix.to_vec());
+    let mut writer_result = Ok(());
+    let exists = conn.exists_prefix(&prefix, key_length, |key| {
+        match writer.is_deleted(&key[Prefix::STATE.len()..]) {
+            Ok(deleted) => !deleted,
+            Err(err) => {
+                writer_result = Err(err);
+                false
+            },
+        }
+    })?;
+    writer_result?;
+    Ok(exists)
+}
+
+/// stored_keys_between returns the stored pairs from start (inclusive) to end (exclusive) with the state prefix.
+pub fn stored_keys_between(
+    conn: &StateSnapshot,
+    start: &[u8],
+    end: &[u8],
+) -> Result<Vec<KVPair>, rocksdb::Error> {
+    let start = Kind::State.key(start.to_vec());
+    let end = Kind::State.key(end.to_vec());
+    let mut values = vec![];
+    let mode = rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward);
+    for key_val in conn.iterator(Prefix::STATE, mode) {
+        let (key, value) = key_val?;
+        if key.as_ref() >= end.as_slice() {
+            break;
+        }
+        values.push(KVPair(
+            key.into_vec(),
+            value_compression::decode_vec(value.into_vec()),
+        ));
+    }
+
+    Ok(values)
+}
+
+/// delete_range_with_writer marks all the keys from start (inclusive) to end (exclusive) as deleted in the writer.
+/// Stored keys are cached with the stored value before deletion, so that the commit includes them
+/// in the deleted list of the diff with the original value.
+/// Stored pairs must have the state prefix.
+pub fn delete_range_with_writer(
+    writer: &mut state_writer::StateWriter,
+    stored: &[KVPair],
+    start: &[u8],
+    end: &[u8],
+) -> Result<(), state_writer::StateWriterError> {
+    let cached_keys = writer.keys_between(start, end)?;
+    for key in cached_keys.iter() {
+        writer.delete(key)?;
+    }
+    // pending increments of the keys which are neither cached nor stored are discarded
+    for key in writer.increment_keys() {
+        if key.as_slice() >= start && key.as_slice() < end {
+            writer.delete(&key)?;
+        }
+    }
+    for pair in stored.iter() {
+        let key_without_prefix = &pair.key()[Prefix::STATE.len()..];
+        delete_with_writer(writer, key_without_prefix, Some(pair.value_as_vec()))?;
+    }
+    Ok(())
+}
+
+/// upserted_to_js_object converts the result to { existed: bool, previousValue?: [u8] }.
+#[cfg(feature = "node")]
+fn upserted_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    upserted: Upserted,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let existed = ctx.boolean(upserted.existed);
+    obj.set(ctx, "existed", existed)?;
+    if let Some(value) = upserted.previous_value {
+        let value = JsBuffer::external(ctx, value);
+        obj.set(ctx, "previousValue", value)?;
+    }
+
+    Ok(obj)
+}
+
+#[cfg(feature = "node")]
 impl ReadWriter {
-    /// update or insert the pair of key and value
+    /// update or insert the pair of key and value.
+    /// The callback is called with whether the key existed, or with { existed, previousValue } if with_previous_value is true.
     fn upsert_key(
         &self,
         callback: Root<JsFunction>,
-        writer: ArcMutex<state_writer::StateWriter>,
+        writer: ArcRwLock<state_writer::StateWriter>,
         key: Vec<u8>,
         new_value: Vec<u8>,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+        with_previous_value: bool,
+    ) -> Result<(), DbError> {
         let state_db_key = Kind::State.key(key.clone());
         self.send(move |conn, channel| {
-            let value = conn.get(&state_db_key);
+            let value = conn
+                .get(&state_db_key)
+                .map(|value| value.map(value_compression::decode_vec));
             channel.send(move |mut ctx| {
-                let args = {
-                    let mut writer = writer.lock().unwrap();
-                    let cached = writer.is_cached(&key);
-                    if cached {
-                        //  if the key already in cache so update it and returns
-                        let result = writer.update(&KVPair::new(&key, &new_value));
-                        parse_update_result(&mut ctx, result)?
-                    } else if let Ok(value) = &value {
-                        // if found the value of the key then insert into cache and update it
-                        if value.is_some() {
-                            let temp_value = value.as_ref().unwrap().to_vec();
-                            let pair = SharedKVPair::new(&key, &temp_value);
-                            writer.cache_existing(&pair);
-                            let result = writer.update(&KVPair::new(&key, &new_value));
-                            parse_update_result(&mut ctx, result)?
-                        } else {
-                            // if there is no key then make a new pair and insert into cache
-                            writer.cache_new(&SharedKVPair::new(&key, &new_value));
-                            vec![ctx.null().upcast()]
+                let args = match value {
+                    Ok(value) => {
+                        let mut writer = writer.write().unwrap();
+                        let result = upsert_with_previous(
+                            &mut writer,
+                            &key,
+                            &new_value,
+                            value,
+                            with_previous_value,
+                        );
+                        drop(writer);
+                        match result {
+                            Ok(upserted) if with_previous_value => {
+                                let obj = upserted_to_js_object(&mut ctx, upserted)?;
+                                vec![ctx.null().upcast(), obj.upcast()]
+                            },
+                            Ok(upserted) => {
+                                let existed = ctx.boolean(upserted.existed);
+                                vec![ctx.null().upcast(), existed.upcast()]
+                            },
+                            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                         }
-                    } else {
-                        let err = value.err().unwrap();
-                        vec![ctx.error(&err)?.upcast()]
-                    }
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 let this = ctx.undefined();
@@ -64,39 +576,39 @@ impl ReadWriter {
         })
     }
 
+    /// get_key_with_writer reads the key through the writer.
+    /// The stored value is cached to the writer unless peek is true.
     fn get_key_with_writer(
         &self,
         callback: Root<JsFunction>,
-        writer: ArcMutex<state_writer::StateWriter>,
+        writer: ArcRwLock<state_writer::StateWriter>,
         key: Vec<u8>,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+        peek: bool,
+    ) -> Result<(), DbError> {
         let state_db_key = Kind::State.key(key.clone());
         self.send(move |conn, channel| {
-            let value = conn.get(&state_db_key);
+            let value = conn
+                .get(&state_db_key)
+                .map(|value| value.map(value_compression::decode_vec));
             channel.send(move |mut ctx| {
-                let args = {
-                    let mut writer = writer.lock().unwrap();
-                    let (cached_value, deleted, exists) = writer.get(&key);
-                    if exists && !deleted {
-                        let buffer = JsBuffer::external(&mut ctx, cached_value);
-                        vec![ctx.null().upcast(), buffer.upcast()]
-                    } else if deleted {
-                        vec![ctx.error("No data")?.upcast()]
-                    } else if let Ok(value) = &value {
-                        // if found the value of the key then insert into cache
-                        if value.is_some() {
-                            let temp_value = value.as_ref().unwrap().to_vec();
-                            let pair = SharedKVPair::new(&key, &temp_value);
-                            writer.cache_existing(&pair);
-                            let buffer = JsBuffer::external(&mut ctx, temp_value);
-                            vec![ctx.null().upcast(), buffer.upcast()]
+                let args = match value {
+                    Ok(value) => {
+                        let value = if peek {
+                            peek_incremented_with_writer(&writer.read().unwrap(), &key, value)
                         } else {
-                            vec![ctx.error("No data")?.upcast()]
+                            fold_increment_with_locked_writer(&writer, &key, &value)
+                                .and_then(|_| get_with_locked_writer(&writer, &key, value))
+                        };
+                        match value {
+                            Ok(Some(value)) => {
+                                let buffer = JsBuffer::external(&mut ctx, value);
+                                vec![ctx.null().upcast(), buffer.upcast()]
+                            },
+                            Ok(None) => vec![DbError::NotFound.to_js(&mut ctx)?.upcast()],
+                            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                         }
-                    } else {
-                        let err = value.err().unwrap();
-                        vec![ctx.error(&err)?.upcast()]
-                    }
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 let this = ctx.undefined();
@@ -107,39 +619,124 @@ impl ReadWriter {
         })
     }
 
+    /// get_many_with_writer reads all the keys through the writer in a single message.
+    /// The stored values are cached to the writer.
+    fn get_many_with_writer(
+        &self,
+        callback: Root<JsFunction>,
+        writer: ArcRwLock<state_writer::StateWriter>,
+        keys: Vec<Vec<u8>>,
+        exists: bool,
+    ) -> Result<(), DbError> {
+        self.send(move |conn, channel| {
+            let stored = stored_values(conn, &keys);
+            channel.send(move |mut ctx| {
+                let folded = stored.map_err(DbError::from).and_then(|stored| {
+                    keys.iter()
+                        .zip(stored.iter())
+                        .try_for_each(|(key, value)| {
+                            fold_increment_with_locked_writer(&writer, key, value)
+                        })
+                        .map(|_| stored)
+                        .map_err(DbError::from)
+                });
+                let values = folded.and_then(|stored| {
+                    get_many_with_locked_writer(&writer, &keys, stored).map_err(DbError::from)
+                });
+                let args = match values {
+                    Ok(values) => {
+                        let result = if exists {
+                            let values: Vec<bool> = values.iter().map(Option::is_some).collect();
+                            bools_to_js_array(&mut ctx, &values)?
+                        } else {
+                            values_to_js_array(&mut ctx, values)?
+                        };
+                        vec![ctx.null().upcast(), result.upcast()]
+                    },
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+
+                let this = ctx.undefined();
+                let callback = callback.into_inner(&mut ctx);
+                callback.call(&mut ctx, this, args)?;
+                Ok(())
+            });
+        })
+    }
+
+    fn get_many(mut ctx: FunctionContext, exists: bool) -> JsResult<JsUndefined> {
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let keys = get_bytes_array(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        for key in keys.iter() {
+            db.throw_if_invalid_key(&mut ctx, key)?;
+        }
+        let writer = Arc::clone(&batch.borrow());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        db.get_many_with_writer(callback, writer, keys, exists)
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    fn get_key(mut ctx: FunctionContext, peek: bool) -> JsResult<JsUndefined> {
+        // Get the batch value as a `SendableStateWriter`
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let key = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        // Get the `this` value as a `SharedReaderBase`
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
+        let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        db.get_key_with_writer(callback, writer, key, peek)
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
     fn delete_key(
         &self,
         callback: Root<JsFunction>,
-        writer: ArcMutex<state_writer::StateWriter>,
+        writer: ArcRwLock<state_writer::StateWriter>,
         key: Vec<u8>,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+    ) -> Result<(), DbError> {
         let state_db_key = Kind::State.key(key.clone());
         self.send(move |conn, channel| {
-            let value = conn.get(&state_db_key);
+            let value = conn
+                .get(&state_db_key)
+                .map(|value| value.map(value_compression::decode_vec));
             channel.send(move |mut ctx| {
+                let args = match value {
+                    Ok(value) => {
+                        let mut writer = writer.write().unwrap();
+                        // the commit may consume the writer after the call is sent
+                        let result = writer
+                            .check_not_consumed()
+                            .and_then(|_| delete_with_writer(&mut writer, &key, value));
+                        match result {
+                            Ok(()) => vec![ctx.null().upcast()],
+                            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                        }
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
                 let this = ctx.undefined();
                 let callback = callback.into_inner(&mut ctx);
-                // the following scope use to release writer at the end of it
-                {
-                    let mut writer = writer.lock().unwrap();
-                    let cached = writer.is_cached(&key);
-                    if !cached {
-                        if let Ok(value) = &value {
-                            // if found the value of the key then insert into cache
-                            if value.is_some() {
-                                let temp_value = value.as_ref().unwrap().to_vec();
-                                let pair = SharedKVPair::new(&key, &temp_value);
-                                writer.cache_existing(&pair);
-                            }
-                        } else {
-                            let err = value.err().unwrap();
-                            let args = vec![ctx.error(&err)?.upcast()];
-                            callback.call(&mut ctx, this, args)?;
-                        }
-                    }
-                    writer.delete(&key);
-                }
-                let args = vec![ctx.null().upcast()];
                 callback.call(&mut ctx, this, args)?;
                 Ok(())
             });
@@ -149,42 +746,79 @@ impl ReadWriter {
     fn range(
         &self,
         callback: Root<JsFunction>,
-        writer: ArcMutex<state_writer::StateWriter>,
+        writer: ArcRwLock<state_writer::StateWriter>,
         options: options::IterationOption,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+    ) -> Result<(), DbError> {
+        let max_size = self.max_iteration_result_size();
         self.send(move |conn, channel| {
-            let values = conn
-                .iterator(get_iteration_mode(&options, &mut vec![], true))
-                .map(|key_val| {
-                    KVPair::new(&key_val.as_ref().unwrap().0.clone(), &key_val.unwrap().1)
-                })
-                .collect::<Vec<KVPair>>();
+            let values = match max_size {
+                Some(_) => stored_range_within(conn, &writer.read().unwrap(), &options, max_size),
+                None => stored_range(conn, &options)
+                    .map(|values| (values, false))
+                    .map_err(DbError::from),
+            };
             channel.send(move |mut ctx| {
-                let result = {
-                    let mut writer = writer.lock().unwrap();
-                    let mut result = writer.get_range(&options);
-                    for (counter, pair) in values.iter().enumerate() {
-                        if is_key_out_of_range(&options, pair.key(), counter as i64, true) {
-                            break;
-                        }
-                        let (_, key_without_prefix) = pair.key().split_first().unwrap();
-                        let (cached_value, deleted, exists) = writer.get(key_without_prefix);
-                        if exists && !deleted {
-                            result.insert(key_without_prefix.to_vec(), cached_value);
-                        } else if deleted {
-                            continue;
-                        } else {
-                            // key is stored and returned without prefix
-                            let shared_pair = SharedKVPair::new(key_without_prefix, pair.value());
-                            writer.cache_existing(&shared_pair);
-                            result.insert(shared_pair.key_as_vec(), shared_pair.value_as_vec());
+                let merged = values.and_then(|(values, stored_truncated)| {
+                    let mut result = {
+                        let mut writer = writer.write().unwrap();
+                        merge_range(&mut writer, &values, &options)?
+                    };
+                    let truncated = truncate_merged(
+                        &mut result,
+                        &values,
+                        stored_truncated,
+                        &options,
+                        max_size,
+                    );
+                    Ok((result, truncated))
+                });
+                let args = match merged {
+                    Ok((result, truncated)) => {
+                        let result = pairs_to_js_array(&mut ctx, &result, options.item_fields())?;
+                        let truncated = ctx.boolean(truncated);
+                        vec![ctx.null().upcast(), result.upcast(), truncated.upcast()]
+                    },
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+                let this = ctx.undefined();
+                let callback = callback.into_inner(&mut ctx);
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    /// range_pending returns the changes pending in the writer. The closure is queued to the DB thread
+    /// only to keep the order with the preceding operations, and the snapshot is not read.
+    fn range_pending(
+        &self,
+        callback: Root<JsFunction>,
+        writer: ArcRwLock<state_writer::StateWriter>,
+        options: options::IterationOption,
+    ) -> Result<(), DbError> {
+        self.send(move |_, channel| {
+            channel.send(move |mut ctx| {
+                let changes = writer.read().unwrap().get_pending_range(&options);
+                let args: Vec<Handle<JsValue>> = match changes {
+                    Ok(changes) => {
+                        let result = ctx.empty_array();
+                        for (i, (pair, state)) in changes.iter().enumerate() {
+                            let obj = iteration_item_to_js_object(
+                                &mut ctx,
+                                pair,
+                                options.item_fields(),
+                            )?;
+                            let state = ctx.string(state.as_str());
+                            obj.set(&mut ctx, "state", state)?;
+                            result.set(&mut ctx, i as u32, obj)?;
                         }
-                    }
-                    cache_to_js_array(&mut ctx, &result)?
+                        vec![ctx.null().upcast(), result.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 let this = ctx.undefined();
                 let callback = callback.into_inner(&mut ctx);
-                let args = vec![ctx.null().upcast(), result.upcast()];
                 callback.call(&mut ctx, this, args)?;
 
                 Ok(())
@@ -192,14 +826,75 @@ impl ReadWriter {
         })
     }
 
+    fn count_range(
+        &self,
+        callback: Root<JsFunction>,
+        writer: ArcRwLock<state_writer::StateWriter>,
+        options: options::IterationOption,
+    ) -> Result<(), DbError> {
+        self.send(move |conn, channel| {
+            let result = count_range_with_writer(conn, &writer.read().unwrap(), &options);
+            send_count(channel, callback, result);
+        })
+    }
+
+    fn exists_prefix(
+        &self,
+        callback: Root<JsFunction>,
+        writer: ArcRwLock<state_writer::StateWriter>,
+        prefix: Vec<u8>,
+    ) -> Result<(), DbError> {
+        let key_length = self.prefix_key_length();
+        self.send(move |conn, channel| {
+            let result =
+                exists_prefix_with_writer(conn, &writer.read().unwrap(), &prefix, key_length);
+            send_bool(channel, callback, result);
+        })
+    }
+
+    fn delete_range(
+        &self,
+        callback: Root<JsFunction>,
+        writer: ArcRwLock<state_writer::StateWriter>,
+        start: Vec<u8>,
+        end: Vec<u8>,
+    ) -> Result<(), DbError> {
+        self.send(move |conn, channel| {
+            let values = stored_keys_between(conn, &start, &end);
+            channel.send(move |mut ctx| {
+                let args = match values {
+                    Ok(values) => {
+                        let mut writer = writer.write().unwrap();
+                        // the commit may consume the writer after the call is sent
+                        let result = writer.check_not_consumed().and_then(|_| {
+                            delete_range_with_writer(&mut writer, &values, &start, &end)
+                        });
+                        match result {
+                            Ok(()) => vec![ctx.null().upcast()],
+                            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                        }
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                let this = ctx.undefined();
+                let callback = callback.into_inner(&mut ctx);
+                callback.call(&mut ctx, this, args)?;
+                Ok(())
+            });
+        })
+    }
+
     /// js_upsert_key is handler for JS ffi.
     /// it creates record if key does not exist, and if key exist, it will treat it as update.
     /// js "this" - ReadWriter.
     /// - @params(0) - StateWriter
     /// - @params(1) - key to set to the db.
     /// - @params(2) - value to set to the db.
-    /// - @params(3) - callback to return the fetched value.
+    /// - @params(3) - whether to return the previous value.
+    /// - @params(4) - callback to return the fetched value.
     /// - @callback(0) - Error
+    /// - @callback(1) - bool whether the key existed, or { existed: bool, previousValue?: [u8] } with @params(3).
     pub fn js_upsert_key(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         // Get the batch value as a `SendableStateWriter`
         let batch = ctx
@@ -207,16 +902,22 @@ impl ReadWriter {
             .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
         let key = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
         let value = ctx.argument::<JsTypedArray<u8>>(2)?.as_slice(&ctx).to_vec();
-        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+        let with_previous_value = ctx.argument::<JsBoolean>(3)?.value(&mut ctx);
+        let callback = ctx.argument::<JsFunction>(4)?.root(&mut ctx);
         // Get the `this` value as a `SharedReaderBase`
         let db = ctx
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
+        db.throw_if_over_limits(&mut ctx, &key, Some(&value))?;
 
         let writer = Arc::clone(&batch.borrow_mut());
-        db.upsert_key(callback, writer, key, value)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        writer.read().unwrap().throw_if_consumed(&mut ctx)?;
+        db.upsert_key(callback, writer, key, value, with_previous_value)
+            .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -226,9 +927,53 @@ impl ReadWriter {
     /// - @params(0) - StateWriter
     /// - @params(1) - key to get from db.
     /// - @params(2) - callback to return the fetched value.
-    /// - @callback(0) - Error. If data is not found, it will call the callback with "No data" as a first args.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
+    /// - @callback(1) - [u8]. Value associated with the key.
+    pub fn js_get_key(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_key(ctx, false)
+    }
+
+    /// js_get_key_peek is handler for JS ffi.
+    /// It is the same as js_get_key, but the stored value is not cached to the StateWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - key to get from db.
+    /// - @params(2) - callback to return the fetched value.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
     /// - @callback(1) - [u8]. Value associated with the key.
-    pub fn js_get_key(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    pub fn js_get_key_peek(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_key(ctx, true)
+    }
+
+    /// js_get_many is handler for JS ffi.
+    /// All the keys are read in a single message, and the stored values are cached to the StateWriter.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - keys to get from db.
+    /// - @params(2) - callback to return the fetched values.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - ([u8] | undefined)[]. Values in the order of the keys. Undefined if the key does not exist or is deleted in the StateWriter.
+    pub fn js_get_many(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_many(ctx, false)
+    }
+
+    /// js_exists_many is handler for JS ffi.
+    /// It is the same as js_get_many, but it returns the existence of the keys.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - keys to check existence from db.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool[]. Existence in the order of the keys.
+    pub fn js_exists_many(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_many(ctx, true)
+    }
+
+    /// js_del is handler for JS ffi.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - key to delete from the db.
+    /// - @params(2) - callback to return the fetched value.
+    /// - @callback(0) - Error
+    pub fn js_delete_key(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         // Get the batch value as a `SendableStateWriter`
         let batch = ctx
             .argument::<state_writer::SendableStateWriter>(0)?
@@ -240,34 +985,60 @@ impl ReadWriter {
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
+        db.throw_if_over_limits(&mut ctx, &key, None)?;
         let writer = Arc::clone(&batch.borrow_mut());
-        db.get_key_with_writer(callback, writer, key)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        writer.read().unwrap().throw_if_consumed(&mut ctx)?;
+        db.delete_key(callback, writer, key)
+            .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
-    /// js_del is handler for JS ffi.
+    /// js_increment is handler for JS ffi.
+    /// The delta is added to the counter in the StateWriter, and the counter is read only on get or commit.
+    /// Range, count and existsPrefix do not observe the pending increments until the key is read.
     /// js "this" - ReadWriter.
     /// - @params(0) - StateWriter
-    /// - @params(1) - key to delete from the db.
-    /// - @params(2) - callback to return the fetched value.
-    /// - @callback(0) - Error
-    pub fn js_delete_key(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    /// - @params(1) - key of the counter.
+    /// - @params(2) - delta to add to the counter. Negative delta subtracts.
+    /// - @params(3) - width of the big-endian counter, 4 or 8 bytes.
+    pub fn js_increment(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         // Get the batch value as a `SendableStateWriter`
         let batch = ctx
             .argument::<state_writer::SendableStateWriter>(0)?
             .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
         let key = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
-        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let delta = ctx.argument::<JsNumber>(2)?.value(&mut ctx);
+        let width = ctx.argument::<JsNumber>(3)?.value(&mut ctx);
+        if delta.fract() != 0.0 || delta.abs() > MAX_SAFE_INTEGER {
+            return error::invalid_argument("delta must be a safe integer").throw(&mut ctx);
+        }
+        let width = match Some(width)
+            .filter(|width| width.fract() == 0.0)
+            .and_then(|width| state_writer::CounterWidth::from_bytes(width as usize))
+        {
+            Some(width) => width,
+            None => return error::invalid_argument("width must be 4 or 8").throw(&mut ctx),
+        };
         // Get the `this` value as a `SharedReaderBase`
         let db = ctx
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
-        let db = db.borrow_mut();
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
+        db.throw_if_over_limits(&mut ctx, &key, None)?;
+
         let writer = Arc::clone(&batch.borrow_mut());
-        db.delete_key(callback, writer, key)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        writer
+            .write()
+            .unwrap()
+            .increment(&key, delta as i64, width)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -275,26 +1046,147 @@ impl ReadWriter {
     /// js_range is handler for JS ffi.
     /// js "this" - ReadWriter.
     /// - @params(0) - StateWriter
-    /// - @params(1) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8]}.
+    /// - @params(1) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], keysOnly: bool}.
     /// - @params(2) - Callback to be called on each data iteration.
     /// - @callback(0) - Error.
-    /// - @callback(1) - [{ key: &[u8], value: &[u8]}].
+    /// - @callback(1) - [{ key: &[u8], value: &[u8]}]. Value is not set with keysOnly.
+    /// - @callback(2) - bool. True if the result is truncated by maxIterationResultSize of StateDB.
     pub fn js_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         // Get the batch value as a `SendableStateWriter`
         let batch = ctx
             .argument::<state_writer::SendableStateWriter>(0)?
             .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
         let option_inputs = ctx.argument::<JsObject>(1)?;
-        let options = options::IterationOption::new(&mut ctx, option_inputs);
+        let options = options::IterationOption::new(&mut ctx, option_inputs)?;
         let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
         // Get the `this` value as a `SharedReaderBase`
         let db = ctx
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
         let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
         db.range(callback, writer, options)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_range_pending is handler for JS ffi.
+    /// It iterates only the changes pending in the StateWriter, without reading the stored values.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], keysOnly: bool}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - [{ key: &[u8], value: &[u8], state: "created" | "updated" | "deleted" }].
+    ///   Value is empty for the deleted key, and is not set with keysOnly.
+    pub fn js_range_pending(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        // Get the batch value as a `SendableStateWriter`
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::IterationOption::new(&mut ctx, option_inputs)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        // Get the `this` value as a `SharedReaderBase`
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        db.range_pending(callback, writer, options)
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_count_range_with_writer is handler for JS ffi.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - Options for the range. {limit: u32, gte: &[u8], lte: &[u8], prefix: &[u8]}. Counting stops at the limit.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - number of the keys in the range including the changes in the StateWriter.
+    pub fn js_count_range_with_writer(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        // Get the batch value as a `SendableStateWriter`
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::IterationOption::new(&mut ctx, option_inputs)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        // Get the `this` value as a `SharedReaderBase`
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        db.count_range(callback, writer, options)
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_exists_prefix is handler for JS ffi.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - prefix to check existence of any key with it.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool including the changes in the StateWriter.
+    pub fn js_exists_prefix(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        // Get the batch value as a `SendableStateWriter`
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let prefix = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        // Get the `this` value as a `SharedReaderBase`
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        db.exists_prefix(callback, writer, prefix)
+            .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_delete_range is handler for JS ffi.
+    /// js "this" - ReadWriter.
+    /// - @params(0) - StateWriter
+    /// - @params(1) - start key of the range to delete (inclusive).
+    /// - @params(2) - end key of the range to delete (exclusive).
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error
+    pub fn js_delete_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        // Get the batch value as a `SendableStateWriter`
+        let batch = ctx
+            .argument::<state_writer::SendableStateWriter>(0)?
+            .downcast_or_throw::<state_writer::SendableStateWriter, _>(&mut ctx)?;
+        let start = ctx.argument::<JsTypedArray<u8>>(1)?.as_slice(&ctx).to_vec();
+        let end = ctx.argument::<JsTypedArray<u8>>(2)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+        // Get the `this` value as a `SharedReaderBase`
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&batch.borrow_mut());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        writer.read().unwrap().throw_if_consumed(&mut ctx)?;
+        db.delete_range(callback, writer, start, end)
+            .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
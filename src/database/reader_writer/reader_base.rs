@@ -1,79 +1,193 @@
 /// reader_base provides base functionality for state reader.
 use std::cell::RefCell;
-use std::sync::mpsc;
-use std::thread;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 use neon::context::{Context, FunctionContext};
 use neon::event::Channel;
 use neon::handle::{Handle, Root};
-use neon::result::JsResult;
+use neon::result::{JsResult, NeonResult};
 use neon::types::{Finalize, JsBuffer, JsFunction, JsUndefined, JsValue};
 
-use crate::database::traits::Unwrap;
-use crate::database::types::{JsBoxRef, Kind, SnapshotMessage};
+use crate::database::column_families::StateSnapshot;
+use crate::database::reader_writer::open_readers::ReaderSlot;
+use crate::database::reader_writer::pool::{OwnedSnapshot, Strand};
+use crate::database::registry::{ClosedFlag, Registration, REGISTRY};
+use crate::database::traits::{Closable, HandleClosed};
+use crate::database::types::{JsBoxRef, Kind, SizeLimits, StateKeyLength};
+use crate::error::DbError;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::state::value_compression;
 use crate::state_db::SharedStateDB;
+use crate::types::KeyLength;
 
+/// ReaderBase holds the snapshot of StateDB. Operations are run by the reader pool of StateDB in the submission order.
 pub struct ReaderBase {
-    tx: mpsc::Sender<SnapshotMessage>,
+    strand: Arc<Strand>,
+    /// channel is released on close, so that the closed reader does not keep the event loop alive.
+    channel: Option<Arc<Channel>>,
+    registration: Registration,
+    state_key_length: Option<StateKeyLength>,
+    size_limits: SizeLimits,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    prefix_key_length: Option<usize>,
+    /// slot counts the reader as open on StateDB. It is released on close, including the close by the finalizer.
+    slot: Option<ReaderSlot>,
+    max_iteration_result_size: Option<usize>,
 }
 
 impl Finalize for ReaderBase {
-    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+    fn finalize<'a, C: Context<'a>>(mut self, _: &mut C) {
+        self.close();
         drop(self);
     }
 }
 
+impl Closable for ReaderBase {
+    const NAME: &'static str = "Reader";
+
+    fn closed(&self) -> &AtomicBool {
+        self.registration.closed()
+    }
+}
+
 pub type SharedReaderBase = JsBoxRef<ReaderBase>;
 impl ReaderBase {
     /// Idiomatic rust would take an owned `self` to prevent use after close
     /// However, it's not possible to prevent JavaScript from continuing to hold a closed database
-    fn close(&self) -> Result<(), mpsc::SendError<SnapshotMessage>> {
-        self.tx.send(SnapshotMessage::Close)
+    /// close is no-op if the reader is already closed.
+    /// The snapshot is released after the queued operations, or immediately if nothing is queued.
+    fn close(&mut self) {
+        // the reader may be closed with StateDB, and the slot is released when JS closes or drops it.
+        self.slot = None;
+        if self.is_closed() {
+            return;
+        }
+        self.mark_closed();
+        self.strand.close();
+        self.channel = None;
     }
 
     /// js_new is handler for JS ffi.
+    /// The snapshot is taken on creation, so that the reader does not observe the commits after the creation.
+    /// It throws TooManyReaders if maxOpenReaders of StateDB are already open.
     /// - @params(0) - StateDB to create the reader from.
     /// - @returns - Reader where it is snapshot of stateDB.
     pub fn js_new(mut ctx: FunctionContext) -> JsResult<JsBoxRef<Self>> {
-        // Channel for sending callbacks to execute on the sqlite connection thread
-        let (tx, rx) = mpsc::channel::<SnapshotMessage>();
-        let channel = ctx.channel();
+        let channel = Arc::new(ctx.channel());
 
         let db = ctx
             .argument::<SharedStateDB>(0)?
             .downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow();
-        let conn = db.arc_clone();
-        thread::spawn(move || {
-            let snapshot = conn.unwrap().snapshot();
-            while let Ok(message) = rx.recv() {
-                match message {
-                    SnapshotMessage::Callback(f) => {
-                        f(&snapshot, &channel);
-                    },
-                    SnapshotMessage::Close => return,
-                }
-            }
-        });
+        db.throw_if_closed(&mut ctx)?;
+        let slot = match db.open_reader() {
+            Ok(slot) => slot,
+            Err(err) => return DbError::from(err).throw(&mut ctx),
+        };
+        let strand = db.reader_pool().strand(OwnedSnapshot::new(db.arc_clone()));
+        let state_key_length = db.state_key_length();
+        let size_limits = db.size_limits();
+        let key_length = db.key_length();
+        let hasher = db.hasher();
+        let prefix_key_length = db.prefix_key_length();
+        let registration = REGISTRY.register(Self::NAME, Some(db.registration_id()));
+        let closer_strand = strand.clone();
+        registration.set_closer(move || closer_strand.close());
 
-        Ok(ctx.boxed(RefCell::new(Self { tx })))
+        Ok(ctx.boxed(RefCell::new(Self {
+            strand,
+            channel: Some(channel),
+            registration,
+            state_key_length,
+            size_limits,
+            key_length,
+            hasher,
+            prefix_key_length,
+            slot: Some(slot),
+            max_iteration_result_size: db.max_iteration_result_size(),
+        })))
     }
 
-    pub fn send(
+    /// throw_if_invalid_key throws InvalidKeyLength error to JS if the key does not have the length configured to StateDB.
+    pub fn throw_if_invalid_key<'a, C: Context<'a>>(
         &self,
-        callback: impl FnOnce(&rocksdb::Snapshot, &Channel) + Send + 'static,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
-        self.tx.send(SnapshotMessage::Callback(Box::new(callback)))
+        ctx: &mut C,
+        key: &[u8],
+    ) -> NeonResult<()> {
+        match self.state_key_length {
+            Some(length) => length
+                .check(key)
+                .or_else(|err| DbError::from(err).throw(ctx)),
+            None => Ok(()),
+        }
+    }
+
+    /// throw_if_over_limits throws SizeLimit error to JS if the key or the value is not within the limits of StateDB.
+    /// Only the key is checked if the value is None, such as for the deletion.
+    pub fn throw_if_over_limits<'a, C: Context<'a>>(
+        &self,
+        ctx: &mut C,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> NeonResult<()> {
+        let result = match value {
+            Some(value) => self.size_limits.check(key, value),
+            None => self.size_limits.check_key(key),
+        };
+        result.or_else(|err| DbError::from(err).throw(ctx))
+    }
+
+    /// key_length returns the key length of the SMT of StateDB.
+    pub fn key_length(&self) -> KeyLength {
+        self.key_length
     }
 
-    pub fn get_by_key(
+    /// hasher returns the hasher of the SMT of StateDB.
+    pub fn hasher(&self) -> HasherKind {
+        self.hasher
+    }
+
+    /// prefix_key_length returns the length of the stored key prefix of the prefix filters of StateDB.
+    pub fn prefix_key_length(&self) -> Option<usize> {
+        self.prefix_key_length
+    }
+
+    /// max_iteration_result_size returns the bytes of the result of an iterate or a range. Unlimited if None.
+    pub fn max_iteration_result_size(&self) -> Option<usize> {
+        self.max_iteration_result_size
+    }
+
+    /// closed_flag returns the closed flag for the operations running in the snapshot thread.
+    pub fn closed_flag(&self) -> ClosedFlag {
+        self.registration.closed_flag()
+    }
+
+    /// send queues the callback to run on the snapshot after the operations sent before.
+    pub fn send(
         &self,
-        key: Vec<u8>,
-        callback: Root<JsFunction>,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+        callback: impl FnOnce(&StateSnapshot, &Channel) + Send + 'static,
+    ) -> Result<(), DbError> {
+        let channel = match self.channel.as_ref() {
+            Some(channel) => channel.clone(),
+            None => return Err(HandleClosed(Self::NAME).into()),
+        };
+        let operation = self.registration.start_operation();
+        self.strand
+            .send(Box::new(move |snapshot| {
+                callback(snapshot, &channel);
+                drop(operation);
+            }))
+            .map_err(|_| DbError::from(HandleClosed(Self::NAME)))
+    }
+
+    pub fn get_by_key(&self, key: Vec<u8>, callback: Root<JsFunction>) -> Result<(), DbError> {
         let key = Kind::State.key(key);
         self.send(move |conn, channel| {
-            let result = conn.get(&key);
+            let result = conn
+                .get(&key)
+                .map(|value| value.map(value_compression::decode_vec));
 
             channel.send(move |mut ctx| {
                 let callback = callback.into_inner(&mut ctx);
@@ -83,8 +197,8 @@ impl ReaderBase {
                         let buffer = JsBuffer::external(&mut ctx, val);
                         vec![ctx.null().upcast(), buffer.upcast()]
                     },
-                    Ok(None) => vec![ctx.error("No data")?.upcast()],
-                    Err(err) => vec![ctx.error(&err)?.upcast()],
+                    Ok(None) => vec![DbError::NotFound.to_js(&mut ctx)?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -101,8 +215,7 @@ impl ReaderBase {
         let db = ctx
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
-        let db = db.borrow_mut();
-        db.close().or_else(|err| ctx.throw_error(err.to_string()))?;
+        db.borrow_mut().close();
 
         Ok(ctx.undefined())
     }
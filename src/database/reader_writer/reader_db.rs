@@ -1,27 +1,26 @@
 /// reader_db is the interface for state reader.
 /// State reader will snapshot the data and even if the change happen during the lifetime of reader, it will not be affected.
-use std::sync::{mpsc, Arc, Mutex};
-
 use neon::context::{Context, FunctionContext};
 use neon::handle::{Handle, Root};
 use neon::object::Object;
 use neon::result::JsResult;
 use neon::types::buffer::TypedArray;
-use neon::types::{JsBoolean, JsFunction, JsObject, JsTypedArray, JsUndefined, JsValue};
+use neon::types::{JsBoolean, JsBuffer, JsFunction, JsObject, JsTypedArray, JsUndefined, JsValue};
 
-use crate::database::options::IterationOption;
+use crate::consts::Prefix;
+use crate::database::options::{ChunkedIterationOption, IterationOption};
+use crate::database::reader_writer::read_writer_db::stored_values;
 use crate::database::reader_writer::{ReaderBase, SharedReaderBase};
-use crate::database::types::{Kind, SnapshotMessage};
+use crate::database::traits::Closable;
+use crate::database::types::Kind;
 use crate::database::utils::*;
-use crate::types::KVPair;
+use crate::error::DbError;
+use crate::state::state_db::proof_to_js_object;
+use crate::state::store;
 
 pub type Reader = ReaderBase;
 impl Reader {
-    fn exists(
-        &self,
-        key: Vec<u8>,
-        callback: Root<JsFunction>,
-    ) -> Result<(), mpsc::SendError<SnapshotMessage>> {
+    fn exists(&self, key: Vec<u8>, callback: Root<JsFunction>) -> Result<(), DbError> {
         let key = Kind::State.key(key);
         self.send(move |conn, channel| {
             let result = conn.get(&key);
@@ -37,7 +36,47 @@ impl Reader {
                         ctx.null().upcast(),
                         JsBoolean::new(&mut ctx, false).upcast(),
                     ],
-                    Err(err) => vec![ctx.error(&err)?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
+    fn get_many(mut ctx: FunctionContext, exists: bool) -> JsResult<JsUndefined> {
+        let keys = get_bytes_array(&mut ctx, 0)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        for key in keys.iter() {
+            db.throw_if_invalid_key(&mut ctx, key)?;
+        }
+
+        db.send(move |conn, channel| {
+            let result = stored_values(conn, &keys);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(values) if exists => {
+                        let values: Vec<bool> = values.iter().map(Option::is_some).collect();
+                        vec![
+                            ctx.null().upcast(),
+                            bools_to_js_array(&mut ctx, &values)?.upcast(),
+                        ]
+                    },
+                    Ok(values) => vec![
+                        ctx.null().upcast(),
+                        values_to_js_array(&mut ctx, values)?.upcast(),
+                    ],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -45,13 +84,16 @@ impl Reader {
                 Ok(())
             });
         })
+        .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
     }
 
     /// js_get is handler for JS ffi.
     /// js "this" - Reader.
     /// - @params(0) - key to get from db.
     /// - @params(1) - callback to return the fetched value.
-    /// - @callback(0) - Error. If data is not found, it will call the callback with "No data" as a first args.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
     /// - @callback(1) - [u8]. Value associated with the key.
     pub fn js_get(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
@@ -61,8 +103,10 @@ impl Reader {
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
 
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
         db.get_by_key(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -81,23 +125,48 @@ impl Reader {
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
 
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_key(&mut ctx, &key)?;
         db.exists(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
+    /// js_get_many is handler for JS ffi.
+    /// All the keys are read on the snapshot in a single message.
+    /// js "this" - Reader.
+    /// - @params(0) - keys to get from db.
+    /// - @params(1) - callback to return the fetched values.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - ([u8] | undefined)[]. Values in the order of the keys. Undefined if the key does not exist.
+    pub fn js_get_many(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_many(ctx, false)
+    }
+
+    /// js_exists_many is handler for JS ffi.
+    /// All the keys are checked on the snapshot in a single message.
+    /// js "this" - Reader.
+    /// - @params(0) - keys to check existence from db.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool[]. Existence in the order of the keys.
+    pub fn js_exists_many(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        Self::get_many(ctx, true)
+    }
+
     /// js_iterate is handler for JS ffi.
     /// js "this" - Reader.
-    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8]}.
+    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], chunkSize: u32, keysOnly: bool}.
     /// - @params(1) - Callback to be called on each data iteration.
     /// - @params(2) - callback to be called when completing the iteration.
     /// - @callback1(0) - Error.
-    /// - @callback1(1) - { key: &[u8], value: &[u8]}.
-    /// - @callback(0) - void.
+    /// - @callback1(1) - { key: &[u8], value: &[u8]}. Value is not set with keysOnly.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool. True if the iteration is stopped by maxIterationResultSize of StateDB.
     pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let option_inputs = ctx.argument::<JsObject>(0)?;
-        let options = IterationOption::new(&mut ctx, option_inputs);
+        let mut options = ChunkedIterationOption::new(&mut ctx, option_inputs)?;
         let callback_on_data = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
         let callback_done = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
 
@@ -105,43 +174,132 @@ impl Reader {
             .this()
             .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        options.max_result_size = db.max_iteration_result_size();
 
-        let callback_on_data = Arc::new(Mutex::new(callback_on_data));
+        let closed = db.closed_flag();
         db.send(move |conn, channel| {
-            let conn_iter = conn.iterator(get_iteration_mode(&options, &mut vec![], true));
-            for (counter, key_val) in conn_iter.enumerate() {
-                if is_key_out_of_range(
-                    &options,
-                    &(key_val.as_ref().unwrap().0),
-                    counter as i64,
-                    true,
-                ) {
-                    break;
-                }
-                let callback_on_data = Arc::clone(&callback_on_data);
-                channel.send(move |mut ctx| {
-                    let (_, key_without_prefix) =
-                        key_val.as_ref().unwrap().0.split_first().unwrap();
-                    let temp_pair =
-                        KVPair::new(key_without_prefix, &(key_val.as_ref().unwrap().1));
-                    let obj = pair_to_js_object(&mut ctx, &temp_pair)?;
-                    let callback = callback_on_data.lock().unwrap().to_inner(&mut ctx);
-                    let this = ctx.undefined();
-                    let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), obj.upcast()];
-                    callback.call(&mut ctx, this, args)?;
-                    Ok(())
-                });
-            }
+            let mut start = vec![];
+            let conn_iter = conn.iterator_opt(
+                Prefix::STATE,
+                get_iteration_mode(&options.iteration, &mut start, Prefix::STATE),
+                get_read_options(&options.iteration),
+            );
+            send_in_chunks(
+                channel,
+                conn_iter,
+                &options,
+                Prefix::STATE,
+                &closed,
+                callback_on_data,
+                callback_done,
+            );
+        })
+        .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_count_range is handler for JS ffi.
+    /// js "this" - Reader.
+    /// - @params(0) - Options for the range. {limit: u32, gte: &[u8], lte: &[u8], prefix: &[u8]}. Counting stops at the limit.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - number of the keys in the range.
+    pub fn js_count_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let option_inputs = ctx.argument::<JsObject>(0)?;
+        let options = IterationOption::new(&mut ctx, option_inputs)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.send(move |conn, channel| {
+            let mut start = vec![];
+            let iter = conn.iterator(
+                Prefix::STATE,
+                get_iteration_mode(&options, &mut start, Prefix::STATE),
+            );
+            let result = count_in_range(iter, &options, 0, Prefix::STATE, |_| false);
+            send_count(channel, callback, result);
+        })
+        .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_exists_prefix is handler for JS ffi.
+    /// js "this" - Reader.
+    /// - @params(0) - prefix to check existence of any key with it.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool
+    pub fn js_exists_prefix(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let prefix = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let prefix = Kind::State.key(prefix);
+        let key_length = db.prefix_key_length();
+        db.send(move |conn, channel| {
+            let result = conn.exists_prefix(&prefix, key_length, |_| true);
+            send_bool(channel, callback, result);
+        })
+        .or_else(|err| err.throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_prove is handler for JS ffi.
+    /// The proof is created from the nodes in the snapshot, so that it is consistent with the values read by the reader.
+    /// js "this" - Reader.
+    /// - @params(0) - queries in format of &[&[u8]].
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { root: &[u8]; proof: { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }; }. Root is the state root of the snapshot.
+    pub fn js_prove(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let queries = get_bytes_array(&mut ctx, 0)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedReaderBase, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let key_length = db.key_length();
+        let hasher = db.hasher();
+        db.send(move |conn, channel| {
+            let result = store::prove_snapshot(conn, &queries, key_length, hasher);
+
             channel.send(move |mut ctx| {
-                let callback_done = callback_done.into_inner(&mut ctx);
+                let callback = callback.into_inner(&mut ctx);
                 let this = ctx.undefined();
-                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
-                callback_done.call(&mut ctx, this, args)?;
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok((root, proof)) => {
+                        let obj = ctx.empty_object();
+                        let root = JsBuffer::external(&mut ctx, root);
+                        obj.set(&mut ctx, "root", root)?;
+                        let proof = proof_to_js_object(&mut ctx, &proof, &[])?;
+                        obj.set(&mut ctx, "proof", proof)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+
+                callback.call(&mut ctx, this, args)?;
 
                 Ok(())
             });
         })
-        .or_else(|err| ctx.throw_error(err.to_string()))?;
+        .or_else(|err| err.throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -0,0 +1,413 @@
+/// registry keeps track of the handles opened from JS, so that all of them can be closed on the process shutdown.
+/// Each handle shares its closed flag and the number of pending operations with the registry,
+/// and the entry is removed when the handle is dropped.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "node")]
+use neon::prelude::*;
+
+#[cfg(feature = "node")]
+use crate::database::options::ShutdownOption;
+use crate::database::traits::HandleClosed;
+
+/// POLL_INTERVAL is the interval to check the pending operations while waiting for them.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+type Closer = Box<dyn FnOnce() + Send>;
+
+/// REGISTRY holds all the handles opened in the process.
+pub static REGISTRY: Registry = Registry::new();
+
+struct HandleState {
+    name: &'static str,
+    parent: Option<u64>,
+    closed: Arc<AtomicBool>,
+    pending: Arc<AtomicUsize>,
+    closer: Mutex<Option<Closer>>,
+}
+
+pub struct Registry {
+    next_id: AtomicU64,
+    handles: Mutex<BTreeMap<u64, Arc<HandleState>>>,
+}
+
+/// Registration is the entry of a handle in the registry.
+pub struct Registration {
+    id: u64,
+    state: Arc<HandleState>,
+    registry: &'static Registry,
+}
+
+/// ClosedFlag shares the closed flag of the handle with the operations running in the background thread.
+#[derive(Clone)]
+pub struct ClosedFlag {
+    name: &'static str,
+    closed: Arc<AtomicBool>,
+}
+
+/// Operation counts the operation as pending on the handle until it is dropped.
+pub struct Operation(Arc<AtomicUsize>);
+
+/// HandleReport is the state of a handle at the end of the shutdown.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandleReport {
+    pub name: &'static str,
+    /// pending is the number of the operations which did not finish before the timeout.
+    pub pending: usize,
+}
+
+/// ShutdownReport holds the handles closed by the shutdown.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// closed holds the handles where all the operations finished before the timeout.
+    pub closed: Vec<HandleReport>,
+    /// forced holds the handles closed with pending operations.
+    pub forced: Vec<HandleReport>,
+}
+
+/// depth returns the number of the ancestors of the handle which are still registered.
+fn depth(handles: &BTreeMap<u64, Arc<HandleState>>, state: &HandleState) -> usize {
+    let mut depth = 0;
+    let mut parent = state.parent;
+    while let Some(state) = parent.and_then(|id| handles.get(&id)) {
+        parent = state.parent;
+        depth += 1;
+    }
+    depth
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            handles: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// register adds the handle with the name used in the report.
+    /// parent is the id of the handle which the new handle is created from, and it is closed after the new handle.
+    pub fn register(&'static self, name: &'static str, parent: Option<u64>) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let state = Arc::new(HandleState {
+            name,
+            parent,
+            closed: Arc::new(AtomicBool::new(false)),
+            pending: Arc::new(AtomicUsize::new(0)),
+            closer: Mutex::new(None),
+        });
+        self.handles.lock().unwrap().insert(id, Arc::clone(&state));
+        Registration {
+            id,
+            state,
+            registry: self,
+        }
+    }
+
+    /// len returns the number of the registered handles.
+    pub fn len(&self) -> usize {
+        self.handles.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// close_children closes the open handles created from the parent, and the handles created from them.
+    /// The parent itself is not closed.
+    pub fn close_children(&self, parent: u64) {
+        let handles = self.handles.lock().unwrap().clone();
+        let mut parents = vec![parent];
+        while let Some(parent) = parents.pop() {
+            for (id, state) in handles.iter() {
+                if state.parent != Some(parent) {
+                    continue;
+                }
+                parents.push(*id);
+                if state.closed.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                if let Some(closer) = state.closer.lock().unwrap().take() {
+                    closer();
+                }
+            }
+        }
+    }
+
+    /// shutdown closes all the open handles, children before parents, and waits for the pending operations up to the timeout.
+    /// Handles already closed are not reported.
+    pub fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let handles = self.handles.lock().unwrap().clone();
+        let mut open: Vec<(usize, u64, &Arc<HandleState>)> = handles
+            .iter()
+            .filter(|(_, state)| !state.closed.load(Ordering::SeqCst))
+            .map(|(id, state)| (depth(&handles, state), *id, state))
+            .collect();
+        // children before parents, and the later opened first
+        open.sort_by_key(|(depth, id, _)| std::cmp::Reverse((*depth, *id)));
+
+        for (_, _, state) in open.iter() {
+            state.closed.store(true, Ordering::SeqCst);
+            if let Some(closer) = state.closer.lock().unwrap().take() {
+                closer();
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        while open
+            .iter()
+            .any(|(_, _, state)| state.pending.load(Ordering::SeqCst) > 0)
+            && Instant::now() < deadline
+        {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let mut report = ShutdownReport::default();
+        for (_, _, state) in open {
+            let handle = HandleReport {
+                name: state.name,
+                pending: state.pending.load(Ordering::SeqCst),
+            };
+            if handle.pending == 0 {
+                report.closed.push(handle);
+            } else {
+                report.forced.push(handle);
+            }
+        }
+        report
+    }
+}
+
+impl Registration {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn closed(&self) -> &AtomicBool {
+        &self.state.closed
+    }
+
+    /// closed_flag returns the closed flag which can be moved to the background thread.
+    pub fn closed_flag(&self) -> ClosedFlag {
+        ClosedFlag {
+            name: self.state.name,
+            closed: Arc::clone(&self.state.closed),
+        }
+    }
+
+    /// set_closer sets the function to release the resources of the handle on shutdown.
+    pub fn set_closer(&self, closer: impl FnOnce() + Send + 'static) {
+        *self.state.closer.lock().unwrap() = Some(Box::new(closer));
+    }
+
+    /// start_operation counts a pending operation until the returned value is dropped.
+    pub fn start_operation(&self) -> Operation {
+        self.state.pending.fetch_add(1, Ordering::SeqCst);
+        Operation(Arc::clone(&self.state.pending))
+    }
+}
+
+impl ClosedFlag {
+    /// check_open returns HandleClosed error if the handle is closed.
+    pub fn check_open(&self) -> Result<(), HandleClosed> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(HandleClosed(self.name));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.handles.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "node")]
+fn handle_reports_to_js_array<'a, C: Context<'a>>(
+    ctx: &mut C,
+    handles: &[HandleReport],
+) -> JsResult<'a, JsArray> {
+    let arr = ctx.empty_array();
+    for (i, handle) in handles.iter().enumerate() {
+        let obj = ctx.empty_object();
+        let name = ctx.string(handle.name);
+        obj.set(ctx, "name", name)?;
+        let pending = ctx.number(handle.pending as f64);
+        obj.set(ctx, "pending", pending)?;
+        arr.set(ctx, i as u32, obj)?;
+    }
+    Ok(arr)
+}
+
+/// js_shutdown_all is handler for JS ffi.
+/// - @params(0) - options { timeoutMs }.
+/// - @params(1) - callback to return the result.
+/// - @callback(0) - Error.
+/// - @callback(1) - report { closed: { name, pending }[]; forced: { name, pending }[] }.
+#[cfg(feature = "node")]
+pub fn js_shutdown_all(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let option_inputs = ctx.argument::<JsObject>(0)?;
+    let options = ShutdownOption::new(&mut ctx, option_inputs)?;
+    let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+    let channel = ctx.channel();
+
+    thread::spawn(move || {
+        let report = REGISTRY.shutdown(options.timeout);
+
+        channel.send(move |mut ctx| {
+            let callback = callback.into_inner(&mut ctx);
+            let this = ctx.undefined();
+            let obj = ctx.empty_object();
+            let closed = handle_reports_to_js_array(&mut ctx, &report.closed)?;
+            obj.set(&mut ctx, "closed", closed)?;
+            let forced = handle_reports_to_js_array(&mut ctx, &report.forced)?;
+            obj.set(&mut ctx, "forced", forced)?;
+            let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), obj.upcast()];
+            callback.call(&mut ctx, this, args)?;
+
+            Ok(())
+        });
+    });
+
+    Ok(ctx.undefined())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn new_registry() -> &'static Registry {
+        Box::leak(Box::new(Registry::new()))
+    }
+
+    #[test]
+    fn test_register_and_drop() {
+        let registry = new_registry();
+        let db = registry.register("StateDB", None);
+        let reader = registry.register("Reader", Some(db.id()));
+        assert_ne!(db.id(), reader.id());
+        assert_eq!(registry.len(), 2);
+
+        let flag = reader.closed_flag();
+        assert_eq!(flag.check_open(), Ok(()));
+        reader.closed().store(true, Ordering::SeqCst);
+        assert_eq!(flag.check_open(), Err(HandleClosed("Reader")));
+
+        drop(reader);
+        assert_eq!(registry.len(), 1);
+        drop(db);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_closes_children_first() {
+        let registry = new_registry();
+        let (tx, rx) = mpsc::channel();
+        let db = registry.register("StateDB", None);
+        let reader = registry.register("Reader", Some(db.id()));
+        let other = registry.register("Database", None);
+        let closed_batch = registry.register("WriteBatch", None);
+        closed_batch.closed().store(true, Ordering::SeqCst);
+        for (registration, name) in [(&db, "StateDB"), (&reader, "Reader"), (&other, "Database")] {
+            let tx = tx.clone();
+            registration.set_closer(move || tx.send(name).unwrap());
+        }
+
+        let report = registry.shutdown(Duration::from_secs(1));
+        // reader is closed before the parent, and the later opened is closed first
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            ["Reader", "Database", "StateDB"]
+        );
+        assert_eq!(
+            report.closed,
+            ["Reader", "Database", "StateDB"]
+                .iter()
+                .map(|name| HandleReport { name, pending: 0 })
+                .collect::<Vec<_>>()
+        );
+        assert!(report.forced.is_empty());
+        assert!(db.closed().load(Ordering::SeqCst));
+        assert!(reader.closed().load(Ordering::SeqCst));
+
+        // closed handles are not closed again
+        assert_eq!(registry.shutdown(Duration::ZERO), ShutdownReport::default());
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_pending_operations() {
+        let registry = new_registry();
+        let fast = registry.register("Database", None);
+        let slow = registry.register("StateDB", None);
+
+        let operation = fast.start_operation();
+        let fast_worker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(operation);
+        });
+        // slow operation does not finish until the shutdown returns
+        let stuck = slow.start_operation();
+
+        let report = registry.shutdown(Duration::from_millis(500));
+        fast_worker.join().unwrap();
+        assert_eq!(
+            report.closed,
+            vec![HandleReport {
+                name: "Database",
+                pending: 0
+            }]
+        );
+        assert_eq!(
+            report.forced,
+            vec![HandleReport {
+                name: "StateDB",
+                pending: 1
+            }]
+        );
+        drop(stuck);
+        assert_eq!(slow.state.pending.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_close_children() {
+        let registry = new_registry();
+        let (tx, rx) = mpsc::channel();
+        let db = registry.register("StateDB", None);
+        let reader = registry.register("Reader", Some(db.id()));
+        let nested = registry.register("Iterator", Some(reader.id()));
+        let other = registry.register("Reader", None);
+        for (registration, name) in [(&db, "StateDB"), (&nested, "Iterator"), (&other, "Reader")] {
+            let tx = tx.clone();
+            registration.set_closer(move || tx.send(name).unwrap());
+        }
+
+        registry.close_children(db.id());
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["Iterator"]);
+        assert!(reader.closed().load(Ordering::SeqCst));
+        assert!(nested.closed().load(Ordering::SeqCst));
+        assert!(!db.closed().load(Ordering::SeqCst));
+        assert!(!other.closed().load(Ordering::SeqCst));
+
+        // closed children are not closed again
+        registry.close_children(db.id());
+        assert_eq!(rx.try_iter().count(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+/// staged provides the two-phase write of a Database batch together with the StateDB commit.
+/// The batch is staged in the Database under the id first, and the commit marks the id in its own atomic batch.
+/// recover applies the staged batches whose ids are marked by the commits, and discards the others,
+/// so that the Database follows the StateDB after a crash at any point.
+use crate::consts::{self, Prefix};
+use crate::database::column_families;
+
+/// StagedReport holds the ids of the staged batches resolved by recover.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StagedReport {
+    /// applied is the ids of the batches written to the Database, as the commits are marked.
+    pub applied: Vec<Vec<u8>>,
+    /// discarded is the ids of the batches removed, as the commits did not land.
+    pub discarded: Vec<Vec<u8>>,
+}
+
+/// staged_key returns the key of the staged batch of the id in the Database.
+pub fn staged_key(id: &[u8]) -> Vec<u8> {
+    [consts::STAGED_WRITE_PREFIX, id].concat()
+}
+
+/// marker_key returns the key of the mark of the id written by the commit in the StateDB.
+pub fn marker_key(id: &[u8]) -> Vec<u8> {
+    [Prefix::STAGED_COMMIT, id].concat()
+}
+
+/// write_prepared stages the batch under the id without applying it. Staging the same id again replaces the batch.
+pub fn write_prepared(
+    conn: &rocksdb::DB,
+    id: &[u8],
+    batch: &rocksdb::WriteBatch,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), rocksdb::Error> {
+    conn.put_opt(staged_key(id), batch.data(), write_opts)
+}
+
+/// keys_with_prefix returns the keys and the values starting with the prefix.
+fn keys_with_prefix(
+    conn: &rocksdb::DB,
+    prefix: &[u8],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, rocksdb::Error> {
+    let mode = rocksdb::IteratorMode::From(prefix, rocksdb::Direction::Forward);
+    let mut pairs = vec![];
+    for key_val in column_families::iterator(conn, prefix, mode) {
+        let (key, value) = key_val?;
+        if !key.starts_with(prefix) {
+            break;
+        }
+        pairs.push((key.to_vec(), value.to_vec()));
+    }
+    Ok(pairs)
+}
+
+/// recover resolves all the staged batches of the Database with the marks of the StateDB.
+/// The staged batch is applied and removed in a single batch, and the mark is removed after it.
+/// Marks without the staged batch are left by a crash in between, and they are removed.
+/// It must not run while a commit with the staged id is in progress.
+pub fn recover(
+    conn: &rocksdb::DB,
+    state_conn: &rocksdb::DB,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<StagedReport, rocksdb::Error> {
+    let mut report = StagedReport::default();
+    for (key, data) in keys_with_prefix(conn, consts::STAGED_WRITE_PREFIX)? {
+        let id = key[consts::STAGED_WRITE_PREFIX.len()..].to_vec();
+        if column_families::get(state_conn, &marker_key(&id))?.is_some() {
+            let mut batch = rocksdb::WriteBatch::from_data(&data);
+            batch.delete(&key);
+            conn.write_opt(batch, write_opts)?;
+            report.applied.push(id);
+        } else {
+            conn.delete_opt(&key, write_opts)?;
+            report.discarded.push(id);
+        }
+    }
+    let mut batch = rocksdb::WriteBatch::default();
+    for (key, _) in keys_with_prefix(state_conn, Prefix::STAGED_COMMIT)? {
+        batch.delete(key);
+    }
+    column_families::write(state_conn, batch)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn open(temp_dir: &TempDir, name: &str) -> rocksdb::DB {
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        rocksdb::DB::open(&opts, temp_dir.path().join(name)).unwrap()
+    }
+
+    fn sample_batch(i: u8) -> rocksdb::WriteBatch {
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put([i, 1], [i; 4]);
+        batch.put([i, 2], [i; 8]);
+        batch.delete([0, 0]);
+        batch
+    }
+
+    #[test]
+    fn test_recover() {
+        let temp_dir = TempDir::new("test_staged_recover").unwrap();
+        let conn = open(&temp_dir, "db");
+        let state_conn = open(&temp_dir, "state");
+        let write_opts = rocksdb::WriteOptions::default();
+        conn.put([0, 0], [1]).unwrap();
+
+        write_prepared(&conn, &[1], &sample_batch(1), &write_opts).unwrap();
+        write_prepared(&conn, &[2], &sample_batch(2), &write_opts).unwrap();
+        // only the commit of the first id landed
+        state_conn.put(marker_key(&[1]), [7; 32]).unwrap();
+        // mark of the batch applied before a crash
+        state_conn.put(marker_key(&[3]), [8; 32]).unwrap();
+        assert_eq!(conn.get([1, 1]).unwrap(), None);
+
+        let report = recover(&conn, &state_conn, &write_opts).unwrap();
+        assert_eq!(report.applied, vec![vec![1]]);
+        assert_eq!(report.discarded, vec![vec![2]]);
+        assert_eq!(conn.get([1, 1]).unwrap(), Some(vec![1; 4]));
+        assert_eq!(conn.get([1, 2]).unwrap(), Some(vec![1; 8]));
+        assert_eq!(conn.get([0, 0]).unwrap(), None);
+        assert_eq!(conn.get([2, 1]).unwrap(), None);
+        assert!(keys_with_prefix(&conn, consts::STAGED_WRITE_PREFIX)
+            .unwrap()
+            .is_empty());
+        assert!(keys_with_prefix(&state_conn, Prefix::STAGED_COMMIT)
+            .unwrap()
+            .is_empty());
+
+        let report = recover(&conn, &state_conn, &write_opts).unwrap();
+        assert_eq!(report, StagedReport::default());
+    }
+}
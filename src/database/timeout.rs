@@ -0,0 +1,234 @@
+/// timeout provides the watchdog of the operations queued on the database thread.
+/// The callback of the operation is held in a slot shared by the operation and the watchdog.
+/// Whichever takes it first calls it, so that the callback is called exactly once:
+/// the operation when it starts, or the watchdog when the operation has not started within the timeout.
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Pending holds the callback of the queued operation until it starts.
+/// Dropping it without start, such as when the operation is not sent, drops the callback without calling it.
+pub struct Pending<T> {
+    slot: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> Pending<T> {
+    /// new returns the callback which is not watched, so that start always returns it.
+    pub fn new(value: T) -> Self {
+        Self {
+            slot: Arc::new(Mutex::new(Some(value))),
+        }
+    }
+
+    /// start takes the callback when the operation starts. None if it is already called by the watchdog.
+    /// The operation must be skipped on None, and its result must be dropped.
+    pub fn start(self) -> Option<T> {
+        self.slot.lock().unwrap().take()
+    }
+}
+
+impl<T> Drop for Pending<T> {
+    fn drop(&mut self) {
+        self.slot.lock().unwrap().take();
+    }
+}
+
+struct WatchdogState<T> {
+    /// deadlines are in the order of watch, as the timeout is the same for all the operations.
+    deadlines: VecDeque<(Instant, Arc<Mutex<Option<T>>>)>,
+    stopped: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<WatchdogState<T>>,
+    changed: Condvar,
+}
+
+/// Watchdog calls on_timeout with the callbacks of the operations which have not started within the timeout.
+/// It runs on its own thread, so that the callbacks are called even if the database thread is blocked.
+pub struct Watchdog<T> {
+    timeout: Duration,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send + 'static> Watchdog<T> {
+    /// start starts the thread of the watchdog. It stops when the watchdog is stopped or dropped.
+    pub fn start(timeout: Duration, on_timeout: impl Fn(T) + Send + 'static) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(WatchdogState {
+                deadlines: VecDeque::new(),
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread_shared = Arc::clone(&shared);
+        thread::spawn(move || thread_shared.run(on_timeout));
+
+        Self { timeout, shared }
+    }
+}
+
+impl<T> Watchdog<T> {
+    /// timeout returns the time the operations can wait in the queue.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// watch returns the callback of the operation enqueued now.
+    pub fn watch(&self, value: T) -> Pending<T> {
+        let pending = Pending::new(value);
+        let mut state = self.shared.state.lock().unwrap();
+        state
+            .deadlines
+            .push_back((Instant::now() + self.timeout, Arc::clone(&pending.slot)));
+        self.shared.changed.notify_one();
+        pending
+    }
+
+    /// stop stops the thread. The callbacks not called yet are left to the operations.
+    pub fn stop(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.stopped = true;
+        state.deadlines.clear();
+        self.shared.changed.notify_one();
+    }
+}
+
+impl<T> Drop for Watchdog<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<T> Shared<T> {
+    fn run(&self, on_timeout: impl Fn(T)) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return;
+            }
+            let now = Instant::now();
+            let deadline = match state.deadlines.front() {
+                Some((deadline, _)) => *deadline,
+                None => {
+                    state = self.changed.wait(state).unwrap();
+                    continue;
+                },
+            };
+            if deadline > now {
+                state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+            let (_, slot) = state.deadlines.pop_front().expect("deadline must exist");
+            // the callback is called without the lock, so that the operations can be watched meanwhile
+            drop(state);
+            let expired = slot.lock().unwrap().take();
+            if let Some(value) = expired {
+                on_timeout(value);
+            }
+            state = self.state.lock().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_pending_without_watchdog() {
+        assert_eq!(Pending::new(1).start(), Some(1));
+    }
+
+    #[test]
+    fn test_started_before_timeout() {
+        let (tx, rx) = mpsc::channel();
+        let watchdog = Watchdog::start(Duration::from_millis(50), move |value| {
+            tx.send(value).unwrap();
+        });
+        let pending = watchdog.watch(1);
+        assert_eq!(pending.start(), Some(1));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(200)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_timed_out() {
+        let (tx, rx) = mpsc::channel();
+        let watchdog = Watchdog::start(Duration::from_millis(20), move |value| {
+            tx.send(value).unwrap();
+        });
+        let first = watchdog.watch(1);
+        let second = watchdog.watch(2);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        // the late operations get nothing to call
+        assert_eq!(first.start(), None);
+        assert_eq!(second.start(), None);
+    }
+
+    #[test]
+    fn test_dropped_before_timeout() {
+        let (tx, rx) = mpsc::channel();
+        let watchdog = Watchdog::start(Duration::from_millis(20), move |value| {
+            tx.send(value).unwrap();
+        });
+        drop(watchdog.watch(1));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(200)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+    }
+
+    /// the operation and the watchdog race for the callback at the deadline, and exactly one of them must get it.
+    #[test]
+    fn test_called_once_on_race() {
+        let (tx, rx) = mpsc::channel();
+        let watchdog = Watchdog::start(Duration::from_millis(1), move |value| {
+            tx.send(value).unwrap();
+        });
+        let count = 200;
+        let pendings: Vec<Pending<usize>> = (0..count).map(|i| watchdog.watch(i)).collect();
+        let started = thread::spawn(move || {
+            pendings
+                .into_iter()
+                .filter_map(|pending| {
+                    thread::sleep(Duration::from_micros(10));
+                    pending.start()
+                })
+                .collect::<Vec<usize>>()
+        })
+        .join()
+        .unwrap();
+        let mut called = started;
+        while called.len() < count {
+            called.push(rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        }
+        called.sort_unstable();
+        assert_eq!(called, (0..count).collect::<Vec<usize>>());
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(50)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let (tx, rx) = mpsc::channel();
+        let watchdog = Watchdog::start(Duration::from_millis(20), move |value| {
+            tx.send(value).unwrap();
+        });
+        let pending = watchdog.watch(1);
+        watchdog.stop();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(200)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(pending.start(), Some(1));
+    }
+}
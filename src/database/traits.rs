@@ -1,15 +1,62 @@
 /// traits provides common traits for database.
+#[cfg(feature = "node")]
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "node")]
 use std::sync::{Arc, Mutex};
 
+#[cfg(feature = "node")]
 use neon::context::{Context, FunctionContext};
+#[cfg(feature = "node")]
 use neon::handle::Handle;
-use neon::result::JsResult;
+#[cfg(feature = "node")]
+use neon::result::{JsResult, NeonResult};
+#[cfg(feature = "node")]
 use neon::types::{Finalize, JsNumber, JsString, JsValue};
+use thiserror::Error;
 
-use crate::database::types::{DbOptions, JsArcMutex, JsBoxRef, Kind};
+use crate::database::types::Kind;
+#[cfg(feature = "node")]
+use crate::database::types::{DbOptions, JsArcMutex, JsBoxRef};
+#[cfg(feature = "node")]
+use crate::error::DbError;
 use crate::types::{KVPair, KeyLength, VecOption};
 
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is closed")]
+pub struct HandleClosed(pub &'static str);
+
+/// Closable tracks the liveness of the object boxed to JS.
+/// JS can keep the handle after close, so js handlers must check it before using the object.
+pub trait Closable {
+    /// NAME is the type name used in the error.
+    const NAME: &'static str;
+
+    fn closed(&self) -> &AtomicBool;
+
+    fn mark_closed(&self) {
+        self.closed().store(true, Ordering::SeqCst);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed().load(Ordering::SeqCst)
+    }
+
+    fn check_open(&self) -> Result<(), HandleClosed> {
+        if self.is_closed() {
+            return Err(HandleClosed(Self::NAME));
+        }
+        Ok(())
+    }
+
+    /// throw_if_closed throws HandleClosed error to JS if the object is closed.
+    #[cfg(feature = "node")]
+    fn throw_if_closed<'a, C: Context<'a>>(&self, ctx: &mut C) -> NeonResult<()> {
+        self.check_open()
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
+}
+
 pub trait Unwrap {
     fn unwrap(&self) -> &rocksdb::DB;
 }
@@ -18,6 +65,18 @@ pub trait Actions {
     fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error>;
     fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error>;
     fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error>;
+
+    /// node_reader returns the reader of the nodes shared by the threads updating the tree in parallel.
+    /// The tree is updated sequentially if None.
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        None
+    }
+}
+
+/// NodeReader reads the nodes stored before the update from the threads.
+/// It must read the same nodes as get of the Actions.
+pub trait NodeReader: Sync {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error>;
 }
 
 pub trait NewDBWithKeyLength {
@@ -28,6 +87,7 @@ pub trait DatabaseKind {
     fn db_kind() -> Kind;
 }
 
+#[cfg(feature = "node")]
 pub trait OptionsWithContext {
     fn new_with_context<'a, C>(
         ctx: &mut C,
@@ -38,18 +98,20 @@ pub trait OptionsWithContext {
         Self: Sized;
 }
 
+#[cfg(feature = "node")]
 pub trait NewDBWithContext {
     fn new_db_with_context<'a, C>(
         ctx: &mut C,
         path: String,
         opts: DbOptions,
         db_kind: Kind,
-    ) -> Result<Self, rocksdb::Error>
+    ) -> Result<Self, DbError>
     where
         C: Context<'a>,
         Self: Sized;
 }
 
+#[cfg(feature = "node")]
 pub trait JsNewWithBoxRef {
     fn js_new_with_box_ref<T: OptionsWithContext, U: NewDBWithContext + Send + Finalize>(
         mut ctx: FunctionContext,
@@ -58,13 +120,14 @@ pub trait JsNewWithBoxRef {
         let options = ctx.argument_opt(1);
         let db_opts = T::new_with_context(&mut ctx, options)?;
         let db = U::new_db_with_context(&mut ctx, path, db_opts, Kind::Normal)
-            .or_else(|err| ctx.throw_error(&err))?;
+            .or_else(|err| err.throw(&mut ctx))?;
         let ref_db = RefCell::new(db);
 
-        return Ok(ctx.boxed(ref_db));
+        Ok(ctx.boxed(ref_db))
     }
 }
 
+#[cfg(feature = "node")]
 pub trait JsNewWithArcMutex {
     fn js_new_with_arc_mutex<T: NewDBWithKeyLength + Send + Finalize + DatabaseKind>(
         mut ctx: FunctionContext,
@@ -75,6 +138,6 @@ pub trait JsNewWithArcMutex {
             None
         };
         let ref_tree = RefCell::new(Arc::new(Mutex::new(T::new_db_with_key_length(key_length))));
-        return Ok(ctx.boxed(ref_tree));
+        Ok(ctx.boxed(ref_tree))
     }
 }
@@ -1,18 +1,31 @@
+#[cfg(feature = "node")]
 use std::cell::RefCell;
+use std::cmp;
 use std::sync::Arc;
+use std::time::Duration;
 
+#[cfg(feature = "node")]
 use neon::event::Channel;
+#[cfg(feature = "node")]
 use neon::types::JsBox;
+use thiserror::Error;
 
-use crate::consts::Prefix;
-use crate::types::{ArcMutex, KeyLength, Options};
+use crate::consts::{self, Prefix};
+use crate::database::prefix_filter;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+#[cfg(feature = "node")]
+use crate::types::{ArcMutex, ArcRwLock};
+use crate::types::{KeyLength, VecOption};
 
-type SnapshotCallback = Box<dyn FnOnce(&rocksdb::Snapshot, &Channel) + Send>;
-type DbCallback = Box<dyn FnOnce(&Channel) + Send>;
-pub type DbOptions = Options<KeyLength>;
+#[cfg(feature = "node")]
+pub type DbCallback = Box<dyn FnOnce(&Channel) + Send>;
 
+#[cfg(feature = "node")]
 pub type JsBoxRef<T> = JsBox<RefCell<T>>;
+#[cfg(feature = "node")]
 pub type JsArcMutex<T> = JsBoxRef<ArcMutex<T>>;
+#[cfg(feature = "node")]
+pub type JsArcRwLock<T> = JsBoxRef<ArcRwLock<T>>;
 pub type ArcOptionDB = Arc<Option<rocksdb::DB>>;
 
 /// Messages sent on the database channel
@@ -23,9 +36,161 @@ pub enum Message<T> {
     Close,
 }
 
-pub type SnapshotMessage = Message<SnapshotCallback>;
+#[cfg(feature = "node")]
 pub type DbMessage = Message<DbCallback>;
 
+/// Compression represents the rocksdb compression type for the database files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// CompactionStyle represents the rocksdb compaction style
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompactionStyle {
+    Level,
+    Universal,
+}
+
+/// TuningOptions holds optional rocksdb tuning parameters.
+/// Unset values keep the rocksdb defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TuningOptions {
+    pub block_cache_size: Option<usize>,
+    pub write_buffer_size: Option<usize>,
+    pub max_open_files: Option<i32>,
+    pub compression: Option<Compression>,
+    pub compaction_style: Option<CompactionStyle>,
+    /// bloom_bits_per_key enables the bloom filters of the table files with the bits for each key.
+    pub bloom_bits_per_key: Option<u32>,
+}
+
+/// ValueCompression holds the zstd compression of the state values written by commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueCompression {
+    /// threshold is the size of the values compressed. Smaller values are stored as they are.
+    pub threshold: usize,
+}
+
+/// WriteLimit holds the back-pressure limit of the operations queued on the database thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteLimit {
+    /// max_pending is the number of the queued operations where the new writes are throttled.
+    pub max_pending: usize,
+    /// block_on_full delays the writes until the queue drains below the low-water mark instead of rejecting them.
+    pub block_on_full: bool,
+}
+
+/// WriteDurability holds the WAL options of the writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteDurability {
+    /// sync_writes syncs the WAL to the disk before the write returns.
+    pub sync_writes: bool,
+    /// disable_wal skips the WAL, so that the writes are durable only after the memtables are flushed.
+    pub disable_wal: bool,
+}
+
+/// InvalidKeyLength is the error of the state key which does not have the configured length.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Invalid key length: expected {expected}, got {got}")]
+pub struct InvalidKeyLength {
+    pub expected: usize,
+    pub got: usize,
+}
+
+/// SizeLimitError is the error of the key or the value which is rejected before writing.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SizeLimitError {
+    #[error("key must not be empty")]
+    EmptyKey,
+    #[error("key is too large: maximum {max} bytes, got {got}")]
+    KeyTooLarge { max: usize, got: usize },
+    #[error("value is too large: maximum {max} bytes, got {got}")]
+    ValueTooLarge { max: usize, got: usize },
+}
+
+/// SizeLimits holds the maximum sizes of the keys and the values accepted by the write entry points.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_key_size: usize,
+    pub max_value_size: usize,
+}
+
+/// StateKeyLength is the length of the state key given to StateDB.
+/// The key is the store prefix prepended by the framework followed by the key of the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StateKeyLength {
+    pub prefix_length: usize,
+    pub key_length: usize,
+}
+
+/// DbOptions holds the options used to open Database and StateDB
+#[derive(Clone, Copy, Debug)]
+pub struct DbOptions {
+    readonly: bool,
+    key_length: KeyLength,
+    pub tuning: TuningOptions,
+    /// bloom_filter is the false positive rate of the in-memory bloom filter over the state keys.
+    /// The filter is disabled if None.
+    pub bloom_filter: Option<f64>,
+    /// keep_diff_for_heights is the number of heights to keep the diff for revert.
+    /// Older diffs are removed on commit if it is set.
+    pub keep_diff_for_heights: Option<u32>,
+    /// write_limit throttles the writes when the database thread has too many queued operations.
+    /// Writes are not throttled if None.
+    pub write_limit: Option<WriteLimit>,
+    /// state_key_length is the length of the state keys accepted by StateDB.
+    /// Keys are not validated if None.
+    pub state_key_length: Option<StateKeyLength>,
+    /// durability is the WAL options of the commit and write paths.
+    pub durability: WriteDurability,
+    /// size_limits rejects the empty keys and the keys or the values over the limits on write.
+    pub size_limits: SizeLimits,
+    /// exclusive fails opening the database locked by another instance.
+    /// If false, the locked database is opened as the read-only secondary instance instead.
+    pub exclusive: bool,
+    /// reader_pool_size is the number of the workers running the operations of the readers.
+    /// The number of the CPUs is used if None.
+    pub reader_pool_size: Option<usize>,
+    /// metrics records the latency histograms of the operations run on the database thread.
+    pub metrics: bool,
+    /// hasher is the hasher of the SMT. StateDB records it on the first commit, and rejects another one on open.
+    pub hasher: HasherKind,
+    /// operation_timeout rejects the callback of the operation which does not start on the database thread within it.
+    /// Commit and revert are not timed out, as they are legitimately slow. Operations are not timed out if None.
+    pub operation_timeout: Option<Duration>,
+    /// diff_value_ref_threshold stores the old values of the size or larger in the diffs by the hash,
+    /// and the values are kept once in the old-value store. All the values are inline if None.
+    pub diff_value_ref_threshold: Option<usize>,
+    /// smt_threads is the number of the threads updating the SMT on commit.
+    /// The number of the CPUs up to 4 is used if None.
+    pub smt_threads: Option<usize>,
+    /// auto_migrate runs the pending format migrations on open.
+    /// If false, the database written by an older format fails to open with NeedsMigration.
+    pub auto_migrate: bool,
+    /// prefix_filter_length configures the prefix extractor and the prefix bloom filters of the keys of the length,
+    /// such as the module and the store prefix of the state keys. The length does not include the prefix of StateDB.
+    /// The filters are disabled if None, and StateStore opens the database without them.
+    pub prefix_filter_length: Option<usize>,
+    /// unsafe_sync allows installing the SMT nodes received from the peers and finalizing the synced root.
+    /// It is only for the database being synced, as the current state is replaced by the synced root.
+    pub unsafe_sync: bool,
+    /// negative_cache_size is the number of the missing state keys cached to answer the repeated exists of them.
+    /// The cache is disabled if None.
+    pub negative_cache_size: Option<usize>,
+    /// value_compression compresses the state values of the threshold or larger on commit.
+    /// The values are stored as they are if None, and the compressed values are read regardless of it.
+    pub value_compression: Option<ValueCompression>,
+    /// max_open_readers is the number of the readers and the read-writers open at once.
+    /// Creating another one fails with TooManyReaders. Unlimited if None.
+    pub max_open_readers: Option<usize>,
+    /// max_iteration_result_size is the bytes of the keys and the values returned by an iterate or a range of a reader.
+    /// The result over it is truncated and flagged to JS. Unlimited if None.
+    pub max_iteration_result_size: Option<usize>,
+}
+
 /// Kind represented the kind of the database
 #[derive(PartialEq, Eq)]
 pub enum Kind {
@@ -37,9 +202,255 @@ pub enum Kind {
 }
 
 impl DbOptions {
+    #[inline]
+    pub fn new(readonly: bool, key_length: KeyLength) -> Self {
+        Self {
+            readonly,
+            key_length,
+            tuning: TuningOptions::default(),
+            bloom_filter: None,
+            keep_diff_for_heights: None,
+            write_limit: None,
+            state_key_length: None,
+            durability: WriteDurability::default(),
+            size_limits: SizeLimits::new(key_length),
+            exclusive: true,
+            reader_pool_size: None,
+            metrics: false,
+            hasher: HasherKind::default(),
+            operation_timeout: None,
+            diff_value_ref_threshold: None,
+            smt_threads: None,
+            auto_migrate: false,
+            prefix_filter_length: None,
+            unsafe_sync: false,
+            negative_cache_size: None,
+            value_compression: None,
+            max_open_readers: None,
+            max_iteration_result_size: None,
+        }
+    }
+
+    #[inline]
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// set_readonly marks the options readonly, such as when the database is opened as the secondary instance.
+    #[inline]
+    pub fn set_readonly(&mut self) {
+        self.readonly = true;
+    }
+
     #[inline]
     pub fn key_length(&self) -> KeyLength {
-        self.number
+        self.key_length
+    }
+
+    /// prefix_key_length returns the length of the stored key prefix of the prefix filters of the kind.
+    pub fn prefix_key_length(&self, kind: &Kind) -> Option<usize> {
+        self.prefix_filter_length
+            .map(|length| prefix_filter::key_length(kind, length))
+    }
+
+    /// check_state_key returns InvalidKeyLength error if the key does not have the configured length.
+    pub fn check_state_key(&self, key: &[u8]) -> Result<(), InvalidKeyLength> {
+        match self.state_key_length {
+            Some(length) => length.check(key),
+            None => Ok(()),
+        }
+    }
+}
+
+impl StateKeyLength {
+    /// total returns the expected length of the key including the prefix.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.prefix_length + self.key_length
+    }
+
+    pub fn check(&self, key: &[u8]) -> Result<(), InvalidKeyLength> {
+        if key.len() != self.total() {
+            return Err(InvalidKeyLength {
+                expected: self.total(),
+                got: key.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SizeLimits {
+    /// new returns the default limits for the key length.
+    /// Keys can be MAX_KEY_SIZE_OVER_KEY_LENGTH bytes longer than the key length, and values up to MAX_VALUE_SIZE.
+    pub fn new(key_length: KeyLength) -> Self {
+        Self {
+            max_key_size: usize::from(key_length) + consts::MAX_KEY_SIZE_OVER_KEY_LENGTH,
+            max_value_size: consts::MAX_VALUE_SIZE,
+        }
+    }
+
+    /// check_key returns error if the key is empty or over the limit.
+    pub fn check_key(&self, key: &[u8]) -> Result<(), SizeLimitError> {
+        if key.is_empty() {
+            return Err(SizeLimitError::EmptyKey);
+        }
+        if key.len() > self.max_key_size {
+            return Err(SizeLimitError::KeyTooLarge {
+                max: self.max_key_size,
+                got: key.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// check returns error if the key or the value is not within the limits.
+    pub fn check(&self, key: &[u8], value: &[u8]) -> Result<(), SizeLimitError> {
+        self.check_key(key)?;
+        if value.len() > self.max_value_size {
+            return Err(SizeLimitError::ValueTooLarge {
+                max: self.max_value_size,
+                got: value.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// check_batch returns the first error of the operations in the batch, so that the batch is rejected as a whole.
+    /// The batch from JS only has put and delete, which are visited by rocksdb::WriteBatch::iterate.
+    pub fn check_batch(&self, batch: &rocksdb::WriteBatch) -> Result<(), SizeLimitError> {
+        let mut checker = BatchSizeChecker {
+            limits: *self,
+            result: Ok(()),
+        };
+        batch.iterate(&mut checker);
+        checker.result
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self::new(consts::KEY_LENGTH)
+    }
+}
+
+/// BatchSizeChecker keeps the first error of the operations in the batch.
+struct BatchSizeChecker {
+    limits: SizeLimits,
+    result: Result<(), SizeLimitError>,
+}
+
+impl rocksdb::WriteBatchIterator for BatchSizeChecker {
+    fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
+        if self.result.is_ok() {
+            self.result = self.limits.check(&key, &value);
+        }
+    }
+
+    fn delete(&mut self, key: Box<[u8]>) {
+        if self.result.is_ok() {
+            self.result = self.limits.check_key(&key);
+        }
+    }
+}
+
+impl WriteDurability {
+    /// write_options returns the rocksdb options of the write.
+    /// sync syncs the single write even if sync_writes is not set.
+    pub fn write_options(&self, sync: bool) -> rocksdb::WriteOptions {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(self.sync_writes || sync);
+        opts.disable_wal(self.disable_wal);
+        opts
+    }
+}
+
+impl WriteLimit {
+    /// low_water returns the number of the queued operations where the delayed writes are resumed.
+    #[inline]
+    pub fn low_water(&self) -> usize {
+        cmp::max(self.max_pending / 2, 1)
+    }
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Compression::None),
+            "lz4" => Some(Compression::Lz4),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl From<Compression> for rocksdb::DBCompressionType {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::None => rocksdb::DBCompressionType::None,
+            Compression::Lz4 => rocksdb::DBCompressionType::Lz4,
+            Compression::Zstd => rocksdb::DBCompressionType::Zstd,
+        }
+    }
+}
+
+impl CompactionStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "level" => Some(CompactionStyle::Level),
+            "universal" => Some(CompactionStyle::Universal),
+            _ => None,
+        }
+    }
+}
+
+impl From<CompactionStyle> for rocksdb::DBCompactionStyle {
+    fn from(value: CompactionStyle) -> Self {
+        match value {
+            CompactionStyle::Level => rocksdb::DBCompactionStyle::Level,
+            CompactionStyle::Universal => rocksdb::DBCompactionStyle::Universal,
+        }
+    }
+}
+
+impl TuningOptions {
+    /// block_options returns the table options with the block cache if it is configured.
+    pub fn block_options(&self) -> rocksdb::BlockBasedOptions {
+        let mut block_options = rocksdb::BlockBasedOptions::default();
+        if let Some(size) = self.block_cache_size {
+            block_options.set_block_cache(&rocksdb::Cache::new_lru_cache(size));
+        }
+        if let Some(bits) = self.bloom_bits_per_key {
+            block_options.set_bloom_filter(f64::from(bits), false);
+        }
+        block_options
+    }
+
+    /// with_bloom_bits_per_key_or returns the tuning with the bloom filters of the bits if they are not configured.
+    pub fn with_bloom_bits_per_key_or(&self, bits: u32) -> Self {
+        Self {
+            bloom_bits_per_key: Some(self.bloom_bits_per_key.unwrap_or(bits)),
+            ..*self
+        }
+    }
+
+    /// apply sets the configured values on the rocksdb options.
+    pub fn apply(&self, option: &mut rocksdb::Options) {
+        if self.block_cache_size.is_some() || self.bloom_bits_per_key.is_some() {
+            option.set_block_based_table_factory(&self.block_options());
+        }
+        if let Some(size) = self.write_buffer_size {
+            option.set_write_buffer_size(size);
+        }
+        if let Some(num) = self.max_open_files {
+            option.set_max_open_files(num);
+        }
+        if let Some(compression) = self.compression {
+            option.set_compression_type(compression.into());
+        }
+        if let Some(style) = self.compaction_style {
+            option.set_compaction_style(style.into());
+        }
     }
 }
 
@@ -50,4 +461,83 @@ impl Kind {
             _ => key,
         }
     }
+
+    /// range returns the physical key range for the given range.
+    /// For State, the range is limited to the state prefix, and unbounded ends are replaced by the prefix boundaries.
+    pub fn range(&self, start: VecOption, end: VecOption) -> (VecOption, VecOption) {
+        match self {
+            Kind::State => (
+                Some(self.key(start.unwrap_or_default())),
+                Some(end.map_or_else(|| vec![Prefix::STATE[0] + 1], |end| self.key(end))),
+            ),
+            _ => (start, end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_state_key() {
+        let mut options = DbOptions::new(false, KeyLength(38));
+        assert_eq!(options.check_state_key(&[0; 3]), Ok(()));
+
+        options.state_key_length = Some(StateKeyLength {
+            prefix_length: 6,
+            key_length: 20,
+        });
+        assert_eq!(options.check_state_key(&[0; 26]), Ok(()));
+        for (key, got) in [(vec![0; 25], 25), (vec![0; 27], 27), (vec![], 0)] {
+            let err = options.check_state_key(&key).unwrap_err();
+            assert_eq!(err, InvalidKeyLength { expected: 26, got });
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid key length: expected 26, got {}", got)
+            );
+        }
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let limits = DbOptions::new(false, KeyLength(38)).size_limits;
+        assert_eq!(limits.max_key_size, 102);
+        assert_eq!(limits.max_value_size, 10 * 1024 * 1024);
+        assert_eq!(limits.check(&[0; 102], &[]), Ok(()));
+        assert_eq!(limits.check(&[], &[1]), Err(SizeLimitError::EmptyKey));
+        assert_eq!(
+            limits.check(&[0; 103], &[1]),
+            Err(SizeLimitError::KeyTooLarge { max: 102, got: 103 })
+        );
+        let limits = SizeLimits {
+            max_key_size: 4,
+            max_value_size: 8,
+        };
+        assert_eq!(
+            limits.check(&[0; 4], &[0; 9]),
+            Err(SizeLimitError::ValueTooLarge { max: 8, got: 9 })
+        );
+    }
+
+    #[test]
+    fn test_size_limits_check_batch() {
+        let limits = SizeLimits {
+            max_key_size: 4,
+            max_value_size: 8,
+        };
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put([1_u8], [0_u8; 8]);
+        batch.delete([2_u8; 4]);
+        assert_eq!(limits.check_batch(&batch), Ok(()));
+
+        // the first error is reported even if the following operations are valid
+        batch.put([3_u8], [0_u8; 9]);
+        batch.delete(Vec::<u8>::new());
+        batch.put([4_u8], [0_u8; 1]);
+        assert_eq!(
+            limits.check_batch(&batch),
+            Err(SizeLimitError::ValueTooLarge { max: 8, got: 9 })
+        );
+    }
 }
@@ -1,18 +1,41 @@
 /// utils provides common functionalities used in the DB, such as parsing JS context, iteration checks.
 use std::cmp;
+#[cfg(feature = "node")]
+use std::sync::Arc;
 
-use neon::context::Context;
-use neon::handle::Handle;
+#[cfg(feature = "node")]
+use neon::context::{Context, FunctionContext, TaskContext};
+#[cfg(feature = "node")]
+use neon::event::Channel;
+#[cfg(feature = "node")]
+use neon::handle::{Handle, Root};
+#[cfg(feature = "node")]
 use neon::object::Object;
+#[cfg(feature = "node")]
 use neon::result::NeonResult;
-use neon::types::{JsArray, JsBuffer, JsObject, JsValue};
+#[cfg(feature = "node")]
+use neon::types::buffer::TypedArray;
+#[cfg(feature = "node")]
+use neon::types::{
+    JsArray, JsBuffer, JsFunction, JsNull, JsObject, JsTypedArray, JsUndefined, JsValue,
+};
 
 use crate::consts::Prefix;
 use crate::database::options;
-use crate::state_writer;
-use crate::types::{Cache, KVPair};
+#[cfg(feature = "node")]
+use crate::database::options::ItemFields;
+use crate::database::prefix_filter;
+#[cfg(feature = "node")]
+use crate::database::registry::ClosedFlag;
+#[cfg(feature = "node")]
+use crate::error::DbError;
+use crate::state::value_compression;
+use crate::types::KVPair;
+#[cfg(feature = "node")]
+use crate::types::VecOption;
 use crate::utils::compare;
 
+#[cfg(feature = "node")]
 pub fn pair_to_js_object<'a, C: Context<'a>>(
     ctx: &mut C,
     pair: &KVPair,
@@ -26,95 +49,265 @@ pub fn pair_to_js_object<'a, C: Context<'a>>(
     Ok(obj)
 }
 
-pub fn parse_update_result<'a, C: Context<'a>>(
+/// iteration_item_to_js_object converts the pair to the object passed to JS with the fields.
+/// The object has only the key with keysOnly, and the byte length of the value instead of the value with inventory.
+#[cfg(feature = "node")]
+pub fn iteration_item_to_js_object<'a, C: Context<'a>>(
     ctx: &mut C,
-    result: Result<(), state_writer::StateWriterError>,
-) -> NeonResult<Vec<Handle<'a, JsValue>>> {
-    if result.is_err() {
-        let err = result.err().unwrap().to_string();
-        Ok(vec![ctx.error(err)?.upcast()])
-    } else {
-        Ok(vec![ctx.null().upcast()])
+    pair: &KVPair,
+    fields: ItemFields,
+) -> NeonResult<Handle<'a, JsObject>> {
+    if fields == ItemFields::KeyValue {
+        return pair_to_js_object(ctx, pair);
+    }
+    let obj = ctx.empty_object();
+    let key = JsBuffer::external(ctx, pair.key_as_vec());
+    obj.set(ctx, "key", key)?;
+    if fields == ItemFields::KeyValueSize {
+        let value_size = ctx.number(pair.value().len() as f64);
+        obj.set(ctx, "valueSize", value_size)?;
+    }
+
+    Ok(obj)
+}
+
+/// iteration_item_into_js_object moves the pair into the object passed to JS with the fields.
+/// The buffers are owned by JS, so that mutating them does not change the stored data.
+#[cfg(feature = "node")]
+pub fn iteration_item_into_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    pair: KVPair,
+    fields: ItemFields,
+) -> NeonResult<Handle<'a, JsObject>> {
+    let KVPair(key, value) = pair;
+    let obj = ctx.empty_object();
+    let key = JsBuffer::external(ctx, key);
+    obj.set(ctx, "key", key)?;
+    match fields {
+        ItemFields::KeyValue => {
+            let value = JsBuffer::external(ctx, value);
+            obj.set(ctx, "value", value)?;
+        },
+        ItemFields::KeyValueSize => {
+            let value_size = ctx.number(value.len() as f64);
+            obj.set(ctx, "valueSize", value_size)?;
+        },
+        ItemFields::Key => {},
+    }
+
+    Ok(obj)
+}
+
+/// get_optional_bytes returns bytes of the argument at the index.
+/// It returns None when the argument is not given, undefined or null.
+#[cfg(feature = "node")]
+pub fn get_optional_bytes(ctx: &mut FunctionContext, index: i32) -> NeonResult<VecOption> {
+    let value = match ctx.argument_opt(index) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if value.is_a::<JsUndefined, _>(ctx) || value.is_a::<JsNull, _>(ctx) {
+        return Ok(None);
+    }
+    let bytes = value
+        .downcast_or_throw::<JsTypedArray<u8>, _>(ctx)?
+        .as_slice(ctx)
+        .to_vec();
+
+    Ok(Some(bytes))
+}
+
+/// get_bytes_array returns the array of bytes of the argument at the index.
+#[cfg(feature = "node")]
+pub fn get_bytes_array(ctx: &mut FunctionContext, index: i32) -> NeonResult<Vec<Vec<u8>>> {
+    let values = ctx.argument::<JsArray>(index)?.to_vec(ctx)?;
+    values
+        .iter()
+        .map(|value| {
+            Ok(value
+                .downcast_or_throw::<JsTypedArray<u8>, _>(ctx)?
+                .as_slice(ctx)
+                .to_vec())
+        })
+        .collect()
+}
+
+/// values_to_js_array converts the values to the array passed to JS, where the missing value is undefined.
+#[cfg(feature = "node")]
+pub fn values_to_js_array<'a, C: Context<'a>>(
+    ctx: &mut C,
+    values: Vec<VecOption>,
+) -> NeonResult<Handle<'a, JsArray>> {
+    let result = ctx.empty_array();
+    for (i, value) in values.into_iter().enumerate() {
+        let value: Handle<JsValue> = match value {
+            Some(value) => JsBuffer::external(ctx, value).upcast(),
+            None => ctx.undefined().upcast(),
+        };
+        result.set(ctx, i as u32, value)?;
+    }
+
+    Ok(result)
+}
+
+/// bools_to_js_array converts the booleans to the array passed to JS.
+#[cfg(feature = "node")]
+pub fn bools_to_js_array<'a, C: Context<'a>>(
+    ctx: &mut C,
+    values: &[bool],
+) -> NeonResult<Handle<'a, JsArray>> {
+    let result = ctx.empty_array();
+    for (i, value) in values.iter().enumerate() {
+        let value = ctx.boolean(*value);
+        result.set(ctx, i as u32, value)?;
     }
+
+    Ok(result)
 }
 
-pub fn cache_to_js_array<'a, C: Context<'a>>(
+#[cfg(feature = "node")]
+pub fn pairs_to_js_array<'a, C: Context<'a>>(
     ctx: &mut C,
-    values: &Cache,
+    pairs: &[KVPair],
+    fields: ItemFields,
 ) -> NeonResult<Handle<'a, JsArray>> {
     let res_values = ctx.empty_array();
-    for (i, kv) in values.iter().enumerate() {
-        let temp_pair = KVPair::new(kv.0, kv.1);
-        let object = pair_to_js_object(ctx, &temp_pair)?;
+    for (i, pair) in pairs.iter().enumerate() {
+        let object = iteration_item_to_js_object(ctx, pair, fields)?;
         res_values.set(ctx, i as u32, object)?;
     }
 
     Ok(res_values)
 }
 
+/// range_prefix returns the prefix of all the keys in the range, which is the prefix followed by the prefix option.
+fn range_prefix(options: &options::IterationOption, prefix: &[u8]) -> Vec<u8> {
+    match &options.prefix {
+        Some(key_prefix) => [prefix, key_prefix].concat(),
+        None => prefix.to_vec(),
+    }
+}
+
+/// has_range_prefix returns true if the key starts with the range prefix.
+fn has_range_prefix(options: &options::IterationOption, key: &[u8], prefix: &[u8]) -> bool {
+    if !key.starts_with(prefix) {
+        return false;
+    }
+    match &options.prefix {
+        Some(key_prefix) => key[prefix.len()..].starts_with(key_prefix),
+        None => true,
+    }
+}
+
+/// get_iteration_mode returns the mode to start the iteration.
+/// When either side of the range is not specified, the range is open to the side.
+/// With prefix, the range is given without the prefix, and the open range is bounded to the namespace of the prefix.
+/// Empty prefix is the whole database. The prefix option bounds the range the same way as the namespace.
+/// For the whole database, the missing side defaults to the bound of with_default_bound.
 pub fn get_iteration_mode<'a>(
     options: &options::IterationOption,
     opt: &'a mut Vec<u8>,
-    has_prefix: bool,
+    prefix: &[u8],
 ) -> rocksdb::IteratorMode<'a> {
-    let no_range = options.gte.is_none() && options.lte.is_none();
-    if no_range {
-        if options.reverse {
-            rocksdb::IteratorMode::End
-        } else {
-            rocksdb::IteratorMode::Start
-        }
-    } else if options.reverse {
-        let lte = options
-            .lte
-            .clone()
-            .unwrap_or_else(|| vec![255; options.gte.as_ref().unwrap().len()]);
-        *opt = if has_prefix {
-            [Prefix::STATE, lte.as_slice()].concat()
-        } else {
-            lte
-        };
+    let prefix = range_prefix(options, prefix);
+    let prefix = prefix.as_slice();
+    if options.reverse {
+        match (&options.lte, &options.gte) {
+            (Some(lte), _) => *opt = [prefix, lte.as_slice()].concat(),
+            (None, Some(gte)) if prefix.is_empty() => *opt = vec![255; gte.len()],
+            // reverse iteration starts from the last key smaller than the next namespace
+            (None, _) => {
+                *opt = next_prefix(prefix);
+                // no namespace follows the prefix of all 0xff
+                if opt.is_empty() {
+                    return rocksdb::IteratorMode::End;
+                }
+            },
+        }
         rocksdb::IteratorMode::From(opt, rocksdb::Direction::Reverse)
     } else {
-        let gte = options
-            .gte
-            .clone()
-            .unwrap_or_else(|| vec![0; options.lte.as_ref().unwrap().len()]);
-        *opt = if has_prefix {
-            [Prefix::STATE, gte.as_slice()].concat()
-        } else {
-            gte
-        };
+        match (&options.gte, &options.lte) {
+            (Some(gte), _) => *opt = [prefix, gte.as_slice()].concat(),
+            (None, Some(lte)) if prefix.is_empty() => *opt = vec![0; lte.len()],
+            (None, _) if prefix.is_empty() => return rocksdb::IteratorMode::Start,
+            (None, _) => *opt = prefix.to_vec(),
+        }
         rocksdb::IteratorMode::From(opt, rocksdb::Direction::Forward)
     }
 }
 
+/// with_default_bound returns the options with the missing side of the range filled for the whole database.
+/// Without the prefix option, missing lte defaults to 0xff and missing gte defaults to 0x00 with the length of the other side.
+/// It keeps the bound of Database and InMemoryDatabase which existing iterations rely on.
+pub fn with_default_bound(options: &options::IterationOption) -> options::IterationOption {
+    let mut options = options.clone();
+    if options.prefix.is_some() {
+        return options;
+    }
+    match (&options.gte, &options.lte) {
+        (Some(gte), None) => options.lte = Some(vec![255; gte.len()]),
+        (None, Some(lte)) => options.gte = Some(vec![0; lte.len()]),
+        _ => {},
+    }
+    options
+}
+
+/// get_read_options returns the read options for the iteration.
+/// Keys only iteration does not fill the block cache, since the values read are not used.
+/// The iteration is in total order regardless of the prefix extractor.
+pub fn get_read_options(options: &options::IterationOption) -> rocksdb::ReadOptions {
+    let mut read_options = prefix_filter::total_order_read_options();
+    if options.keys_only {
+        read_options.fill_cache(false);
+    }
+    read_options
+}
+
+/// next_prefix returns the smallest key which is greater than all the keys with the prefix.
+/// It is empty if no such key exists, which is the case of the empty prefix or the prefix of all 0xff.
+pub fn next_prefix(prefix: &[u8]) -> Vec<u8> {
+    let mut next = prefix.to_vec();
+    while let Some(last) = next.pop() {
+        if last != 255 {
+            next.push(last + 1);
+            break;
+        }
+    }
+    next
+}
+
+/// is_key_after_prefix returns true if the key is the start of the reverse iteration over the next namespace.
+/// Reverse iteration without lte starts from the next namespace of the prefix, which is skipped instead of stopping the iteration.
+pub fn is_key_after_prefix(options: &options::IterationOption, key: &[u8], prefix: &[u8]) -> bool {
+    options.reverse
+        && options.lte.is_none()
+        && !has_range_prefix(options, key, prefix)
+        && compare(key, &range_prefix(options, prefix)) == cmp::Ordering::Greater
+}
+
+/// is_key_out_of_range returns true if the iteration should be stopped at the key.
+/// With prefix, key out of the namespace of the prefix is out of range, and so is the key without the prefix option.
 pub fn is_key_out_of_range(
     options: &options::IterationOption,
     key: &[u8],
     counter: i64,
-    has_prefix: bool,
+    prefix: &[u8],
 ) -> bool {
     if options.limit != -1 && counter >= options.limit {
         return true;
     }
+    if !has_range_prefix(options, key, prefix) {
+        return true;
+    }
     if options.reverse {
         if let Some(gte) = &options.gte {
-            let cmp = if has_prefix {
-                [Prefix::STATE, gte].concat()
-            } else {
-                gte.to_vec()
-            };
+            let cmp = [prefix, gte].concat();
             if compare(key, &cmp) == cmp::Ordering::Less {
                 return true;
             }
         }
     } else if let Some(lte) = &options.lte {
-        let cmp = if has_prefix {
-            [Prefix::STATE, lte].concat()
-        } else {
-            lte.to_vec()
-        };
+        let cmp = [prefix, lte].concat();
         if compare(key, &cmp) == cmp::Ordering::Greater {
             return true;
         }
@@ -122,3 +315,714 @@ pub fn is_key_out_of_range(
 
     false
 }
+
+type IterItem = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>;
+
+/// next_chunk reads up to chunk_size pairs in the range from the iterator.
+/// With prefix, the prefix is removed from the keys. Values are not copied with keys_only, and the values of the state are decompressed.
+/// The pairs not reported by min_value_size are skipped, and they are not counted for the limit.
+/// It returns true with the pairs if the iteration reached the end of the range.
+pub fn next_chunk(
+    iter: &mut impl Iterator<Item = IterItem>,
+    options: &options::IterationOption,
+    counter: &mut i64,
+    chunk_size: usize,
+    prefix: &[u8],
+) -> Result<(Vec<KVPair>, bool), rocksdb::Error> {
+    let mut pairs = Vec::with_capacity(chunk_size);
+    while pairs.len() < chunk_size {
+        let (key, value) = match iter.next() {
+            Some(key_val) => key_val?,
+            None => return Ok((pairs, true)),
+        };
+        if is_key_after_prefix(options, &key, prefix) {
+            continue;
+        }
+        if is_key_out_of_range(options, &key, *counter, prefix) {
+            return Ok((pairs, true));
+        }
+        // values of the state are decompressed, so that the sizes reported are of the values
+        let value = if prefix == Prefix::STATE && !options.keys_only {
+            value_compression::decode_vec(value.into_vec())
+        } else {
+            value.into_vec()
+        };
+        if !options.is_reported(&value) {
+            continue;
+        }
+        *counter += 1;
+        let key = if prefix.is_empty() {
+            key.into_vec()
+        } else {
+            key[prefix.len()..].to_vec()
+        };
+        // buffers returned by the iterator are moved into the pair without copying
+        let value = if options.keys_only { vec![] } else { value };
+        pairs.push(KVPair(key, value));
+    }
+    Ok((pairs, false))
+}
+
+/// ResultSize accumulates the bytes of the keys and the values returned by a single iteration up to the limit.
+/// The first pair is always returned, so that the caller paginating from the last key proceeds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResultSize {
+    max: Option<usize>,
+    size: usize,
+}
+
+impl ResultSize {
+    /// new returns the accumulator of the limit. Unlimited if None.
+    pub fn new(max: Option<usize>) -> Self {
+        Self { max, size: 0 }
+    }
+
+    /// fits adds the pair to the result, and returns false without adding it if the result exceeds the limit.
+    pub fn fits(&mut self, pair: &KVPair) -> bool {
+        let len = pair.key().len() + pair.value().len();
+        if let Some(max) = self.max {
+            if self.size > 0 && self.size + len > max {
+                return false;
+            }
+        }
+        self.size += len;
+        true
+    }
+
+    /// truncate removes the pairs over the limit, and returns true if any pair is removed.
+    pub fn truncate(&mut self, pairs: &mut Vec<KVPair>) -> bool {
+        match pairs.iter().position(|pair| !self.fits(pair)) {
+            Some(index) => {
+                pairs.truncate(index);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// count_in_range counts the keys of the iterator in the range, starting from the counter.
+/// Counting stops once the counter reaches the limit, so that the cost is bounded by the limit.
+/// Key is not counted if skip returns true for the key. With prefix, skip is called with the key without the prefix.
+pub fn count_in_range(
+    iter: impl Iterator<Item = IterItem>,
+    options: &options::IterationOption,
+    mut counter: i64,
+    prefix: &[u8],
+    mut skip: impl FnMut(&[u8]) -> bool,
+) -> Result<i64, rocksdb::Error> {
+    for key_val in iter {
+        let (key, _) = key_val?;
+        if is_key_after_prefix(options, &key, prefix) {
+            continue;
+        }
+        if is_key_out_of_range(options, &key, counter, prefix) {
+            break;
+        }
+        if !skip(&key[prefix.len()..]) {
+            counter += 1;
+        }
+    }
+    Ok(counter)
+}
+
+/// send_count calls the callback with the result of count_in_range.
+#[cfg(feature = "node")]
+pub fn send_count<E: Send + 'static>(
+    channel: &Channel,
+    callback: Root<JsFunction>,
+    result: Result<i64, E>,
+) where
+    DbError: From<E>,
+{
+    channel.send(move |mut ctx| {
+        let callback = callback.into_inner(&mut ctx);
+        let this = ctx.undefined();
+        let args: Vec<Handle<JsValue>> = match result {
+            Ok(count) => vec![ctx.null().upcast(), ctx.number(count as f64).upcast()],
+            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+        };
+        callback.call(&mut ctx, this, args)?;
+
+        Ok(())
+    });
+}
+
+/// send_bool calls the callback with the boolean result, such as of the existence.
+#[cfg(feature = "node")]
+pub fn send_bool<E: Send + 'static>(
+    channel: &Channel,
+    callback: Root<JsFunction>,
+    result: Result<bool, E>,
+) where
+    DbError: From<E>,
+{
+    channel.send(move |mut ctx| {
+        let callback = callback.into_inner(&mut ctx);
+        let this = ctx.undefined();
+        let args: Vec<Handle<JsValue>> = match result {
+            Ok(value) => vec![ctx.null().upcast(), ctx.boolean(value).upcast()],
+            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+        };
+        callback.call(&mut ctx, this, args)?;
+
+        Ok(())
+    });
+}
+
+/// ChunkDecoder converts the pairs of the chunked iteration to the objects passed to JS.
+/// The pairs are decoded on the database thread, and the items are converted on the JS thread.
+#[cfg(feature = "node")]
+pub trait ChunkDecoder: Send + Sync + 'static {
+    type Item: Send + 'static;
+
+    fn decode(&self, pair: KVPair) -> Result<Self::Item, DbError>;
+
+    fn to_js_object<'a>(
+        &self,
+        ctx: &mut TaskContext<'a>,
+        item: Self::Item,
+    ) -> NeonResult<Handle<'a, JsObject>>;
+}
+
+/// RawPairs passes the pairs to JS as they are stored.
+#[cfg(feature = "node")]
+struct RawPairs {
+    fields: ItemFields,
+    zero_copy: bool,
+}
+
+#[cfg(feature = "node")]
+impl ChunkDecoder for RawPairs {
+    type Item = KVPair;
+
+    fn decode(&self, pair: KVPair) -> Result<KVPair, DbError> {
+        Ok(pair)
+    }
+
+    fn to_js_object<'a>(
+        &self,
+        ctx: &mut TaskContext<'a>,
+        item: KVPair,
+    ) -> NeonResult<Handle<'a, JsObject>> {
+        if self.zero_copy {
+            iteration_item_into_js_object(ctx, item, self.fields)
+        } else {
+            iteration_item_to_js_object(ctx, &item, self.fields)
+        }
+    }
+}
+
+/// ChunkCallbacks holds the callback called for each item and the callback called at the end of the iteration.
+#[cfg(feature = "node")]
+pub struct ChunkCallbacks {
+    pub on_data: Root<JsFunction>,
+    pub done: Root<JsFunction>,
+}
+
+/// send_in_chunks calls callback_on_data for each pair of the iterator, and callback_done at the end.
+/// Next chunk is read after JS handled the previous one, so that the pending pairs are bounded by the chunk size.
+/// The iteration stops early when the pairs exceed max_result_size, and callback_done is called with truncated.
+/// The iteration stops when reading fails or the callback throws, and callback_done is not called in that case.
+/// When the handle is closed before the next chunk, callback_done is called once with the closed error.
+#[cfg(feature = "node")]
+pub fn send_in_chunks(
+    channel: &Channel,
+    iter: impl Iterator<Item = IterItem>,
+    options: &options::ChunkedIterationOption,
+    prefix: &[u8],
+    closed: &ClosedFlag,
+    callback_on_data: Root<JsFunction>,
+    callback_done: Root<JsFunction>,
+) {
+    let decoder = RawPairs {
+        fields: options.iteration.item_fields(),
+        zero_copy: options.zero_copy,
+    };
+    let callbacks = ChunkCallbacks {
+        on_data: callback_on_data,
+        done: callback_done,
+    };
+    send_decoded_in_chunks(channel, iter, options, prefix, closed, callbacks, decoder);
+}
+
+/// send_decoded_in_chunks is send_in_chunks of the items decoded from the pairs by the decoder.
+/// The pair which cannot be decoded fails the iteration the same as the read error.
+#[cfg(feature = "node")]
+pub fn send_decoded_in_chunks<D: ChunkDecoder>(
+    channel: &Channel,
+    mut iter: impl Iterator<Item = IterItem>,
+    options: &options::ChunkedIterationOption,
+    prefix: &[u8],
+    closed: &ClosedFlag,
+    callbacks: ChunkCallbacks,
+    decoder: D,
+) {
+    let callback_on_data = Arc::new(callbacks.on_data);
+    let decoder = Arc::new(decoder);
+    let mut counter = 0;
+    let mut result_size = ResultSize::new(options.max_result_size);
+    let mut truncated = false;
+    let mut result_done = Ok(());
+    loop {
+        if let Err(err) = closed.check_open() {
+            result_done = Err(err);
+            break;
+        }
+        let result = next_chunk(
+            &mut iter,
+            &options.iteration,
+            &mut counter,
+            options.chunk_size,
+            prefix,
+        )
+        .map_err(DbError::from)
+        .and_then(|(mut pairs, completed)| {
+            truncated = result_size.truncate(&mut pairs);
+            let completed = completed || truncated;
+            let items = pairs
+                .into_iter()
+                .map(|pair| decoder.decode(pair))
+                .collect::<Result<Vec<D::Item>, DbError>>()?;
+            Ok((items, completed))
+        });
+        let completed = matches!(result, Ok((_, true)));
+        let failed = result.is_err();
+        let callback_on_data = Arc::clone(&callback_on_data);
+        let decoder = Arc::clone(&decoder);
+        let handled = channel
+            .send(move |mut ctx| {
+                let callback = callback_on_data.to_inner(&mut ctx);
+                let this = ctx.undefined();
+                match result {
+                    Ok((items, _)) => {
+                        for item in items {
+                            let obj = decoder.to_js_object(&mut ctx, item)?;
+                            let args: Vec<Handle<JsValue>> =
+                                vec![ctx.null().upcast(), obj.upcast()];
+                            callback.call(&mut ctx, this, args)?;
+                        }
+                    },
+                    Err(err) => {
+                        let args: Vec<Handle<JsValue>> = vec![err.to_js(&mut ctx)?.upcast()];
+                        callback.call(&mut ctx, this, args)?;
+                    },
+                }
+                Ok(())
+            })
+            .join();
+        if handled.is_err() || failed {
+            return;
+        }
+        if completed {
+            break;
+        }
+    }
+    let callback_done = callbacks.done;
+    channel.send(move |mut ctx| {
+        let callback_done = callback_done.into_inner(&mut ctx);
+        let this = ctx.undefined();
+        let args: Vec<Handle<JsValue>> = match result_done {
+            Ok(()) => vec![ctx.null().upcast(), ctx.boolean(truncated).upcast()],
+            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+        };
+        callback_done.call(&mut ctx, this, args)?;
+
+        Ok(())
+    });
+}
+
+/// is_key_in_range returns true if the key is within gte and lte of the options, and starts with the prefix option.
+pub fn is_key_in_range(options: &options::IterationOption, key: &[u8]) -> bool {
+    if let Some(prefix) = &options.prefix {
+        if !key.starts_with(prefix) {
+            return false;
+        }
+    }
+    if let Some(gte) = &options.gte {
+        if compare(key, gte) == cmp::Ordering::Less {
+            return false;
+        }
+    }
+    if let Some(lte) = &options.lte {
+        if compare(key, lte) == cmp::Ordering::Greater {
+            return false;
+        }
+    }
+    true
+}
+
+/// sort_and_limit orders the pairs by the key with the direction, and apply the limit.
+pub fn sort_and_limit(pairs: &mut Vec<KVPair>, options: &options::IterationOption) {
+    if options.reverse {
+        pairs.sort_by(|a, b| b.key().cmp(a.key()));
+    } else {
+        pairs.sort_by(|a, b| a.key().cmp(b.key()));
+    }
+    if options.limit != -1 && pairs.len() > options.limit as usize {
+        pairs.truncate(options.limit as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(keys: &[&[u8]]) -> Vec<IterItem> {
+        keys.iter()
+            .map(|key| Ok((key.to_vec().into_boxed_slice(), vec![1].into_boxed_slice())))
+            .collect()
+    }
+
+    fn options(limit: i64, lte: Option<&[u8]>) -> options::IterationOption {
+        options::IterationOption {
+            limit,
+            reverse: false,
+            gte: None,
+            lte: lte.map(|v| v.to_vec()),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        }
+    }
+
+    #[test]
+    fn test_next_chunk_min_value_size() {
+        let items: Vec<IterItem> = (1..=5)
+            .map(|size| {
+                Ok((
+                    vec![size].into_boxed_slice(),
+                    vec![0; size as usize].into_boxed_slice(),
+                ))
+            })
+            .collect();
+        let inventory = |limit: i64| options::IterationOption {
+            inventory: true,
+            min_value_size: Some(3),
+            ..options(limit, None)
+        };
+
+        // the value of min_value_size is reported, and the shorter values are not
+        let (pairs, completed) =
+            next_chunk(&mut items.into_iter(), &inventory(-1), &mut 0, 10, &[]).unwrap();
+        let sizes: Vec<usize> = pairs.iter().map(|pair| pair.value().len()).collect();
+        assert_eq!(sizes, vec![3, 4, 5]);
+        assert!(completed);
+
+        // skipped pairs are not counted for the limit
+        let items: Vec<IterItem> = (1..=5)
+            .map(|size| {
+                Ok((
+                    vec![size].into_boxed_slice(),
+                    vec![0; size as usize].into_boxed_slice(),
+                ))
+            })
+            .collect();
+        let mut counter = 0;
+        let (pairs, completed) =
+            next_chunk(&mut items.into_iter(), &inventory(2), &mut counter, 10, &[]).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].key(), &[4]);
+        assert!(completed);
+        assert_eq!(counter, 2);
+    }
+
+    #[test]
+    fn test_result_size() {
+        let mut pairs: Vec<KVPair> = (1..=5_u8).map(|i| KVPair::new(&[i], &[i; 9])).collect();
+        // unlimited
+        assert!(!ResultSize::new(None).truncate(&mut pairs.clone()));
+
+        let mut result_size = ResultSize::new(Some(25));
+        assert!(result_size.truncate(&mut pairs));
+        assert_eq!(pairs.len(), 2);
+        // the size is accumulated over the chunks
+        let mut next = vec![KVPair::new(&[6], &[6; 9])];
+        assert!(result_size.truncate(&mut next));
+        assert!(next.is_empty());
+
+        // the first pair is returned even if it is over the limit
+        let mut pairs = vec![KVPair::new(&[1], &[1; 100]), KVPair::new(&[2], &[2])];
+        assert!(ResultSize::new(Some(10)).truncate(&mut pairs));
+        assert_eq!(pairs.len(), 1);
+    }
+
+    #[test]
+    fn test_next_chunk() {
+        let keys: Vec<&[u8]> = vec![&[0, 1], &[0, 2], &[0, 3], &[0, 4], &[0, 5]];
+        let mut iter = items(&keys).into_iter();
+        let mut counter = 0;
+        let (pairs, completed) = next_chunk(
+            &mut iter,
+            &options(-1, None),
+            &mut counter,
+            2,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(
+            pairs,
+            vec![KVPair::new(&[1], &[1]), KVPair::new(&[2], &[1])]
+        );
+        assert!(!completed);
+        let (pairs, completed) = next_chunk(
+            &mut iter,
+            &options(-1, None),
+            &mut counter,
+            2,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(
+            pairs,
+            vec![KVPair::new(&[3], &[1]), KVPair::new(&[4], &[1])]
+        );
+        assert!(!completed);
+        let (pairs, completed) = next_chunk(
+            &mut iter,
+            &options(-1, None),
+            &mut counter,
+            2,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(pairs, vec![KVPair::new(&[5], &[1])]);
+        assert!(completed);
+        assert_eq!(counter, 5);
+
+        // limit is counted over the chunks
+        let mut iter = items(&keys).into_iter();
+        let mut counter = 0;
+        let (pairs, _) = next_chunk(&mut iter, &options(3, None), &mut counter, 2, &[]).unwrap();
+        assert_eq!(pairs.len(), 2);
+        let (pairs, completed) =
+            next_chunk(&mut iter, &options(3, None), &mut counter, 2, &[]).unwrap();
+        assert_eq!(pairs, vec![KVPair::new(&[0, 3], &[1])]);
+        assert!(completed);
+
+        let mut iter = items(&keys).into_iter();
+        let (pairs, completed) = next_chunk(
+            &mut iter,
+            &options(-1, Some(&[2])),
+            &mut 0,
+            10,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert!(completed);
+
+        // values are not copied with keys_only
+        let mut iter = items(&keys).into_iter();
+        let keys_only = options::IterationOption {
+            keys_only: true,
+            ..options(2, None)
+        };
+        let (pairs, _) = next_chunk(&mut iter, &keys_only, &mut 0, 10, Prefix::STATE).unwrap();
+        assert_eq!(pairs, vec![KVPair::new(&[1], &[]), KVPair::new(&[2], &[])]);
+    }
+
+    #[test]
+    fn test_count_in_range() {
+        let keys: Vec<&[u8]> = vec![&[0, 1], &[0, 2], &[0, 3], &[0, 4], &[0, 5]];
+        let count = |options, counter, skip: &dyn Fn(&[u8]) -> bool| {
+            count_in_range(
+                items(&keys).into_iter(),
+                &options,
+                counter,
+                Prefix::STATE,
+                skip,
+            )
+            .unwrap()
+        };
+        assert_eq!(count(options(-1, None), 0, &|_| false), 5);
+        assert_eq!(count(options(-1, Some(&[3])), 0, &|_| false), 3);
+        // counting stops at the limit
+        assert_eq!(count(options(2, None), 0, &|_| false), 2);
+        assert_eq!(count(options(3, None), 3, &|_| false), 3);
+        // skipped key is called without prefix, and it is not counted
+        assert_eq!(count(options(-1, None), 1, &|key| key == [2]), 5);
+        assert_eq!(count(options(4, None), 0, &|key| key < &[3][..]), 3);
+    }
+
+    #[test]
+    fn test_prefix_iteration_bounds() {
+        assert_eq!(next_prefix(&[5, 255]), vec![6]);
+        assert!(next_prefix(&[255, 255]).is_empty());
+        assert!(next_prefix(&[]).is_empty());
+
+        let reverse = options::IterationOption {
+            reverse: true,
+            ..options(-1, None)
+        };
+        let mut opt = vec![];
+        assert!(matches!(
+            get_iteration_mode(&reverse, &mut opt, &[5, 255]),
+            rocksdb::IteratorMode::From(&[6], rocksdb::Direction::Reverse)
+        ));
+        assert!(matches!(
+            get_iteration_mode(&reverse, &mut opt, &[255]),
+            rocksdb::IteratorMode::End
+        ));
+        assert!(matches!(
+            get_iteration_mode(&options(-1, None), &mut opt, &[5, 255]),
+            rocksdb::IteratorMode::From(&[5, 255], rocksdb::Direction::Forward)
+        ));
+
+        // reverse iteration starting from the next namespace skips it, and stops before the previous one
+        let keys: Vec<&[u8]> = vec![&[6], &[5, 255, 255], &[5, 255, 0], &[5, 254, 255]];
+        let mut iter = items(&keys).into_iter();
+        let (pairs, completed) = next_chunk(&mut iter, &reverse, &mut 0, 10, &[5, 255]).unwrap();
+        assert_eq!(
+            pairs,
+            vec![KVPair::new(&[255], &[1]), KVPair::new(&[0], &[1])]
+        );
+        assert!(completed);
+        assert_eq!(
+            count_in_range(items(&keys).into_iter(), &reverse, 0, &[5, 255], |_| false).unwrap(),
+            2
+        );
+
+        // forward iteration stops at the next namespace
+        let keys: Vec<&[u8]> = vec![&[5, 255], &[5, 255, 1], &[6], &[6, 0]];
+        let mut iter = items(&keys).into_iter();
+        let (pairs, completed) =
+            next_chunk(&mut iter, &options(-1, None), &mut 0, 10, &[5, 255]).unwrap();
+        assert_eq!(pairs, vec![KVPair::new(&[], &[1]), KVPair::new(&[1], &[1])]);
+        assert!(completed);
+    }
+
+    #[test]
+    fn test_prefix_option() {
+        let with_prefix = |prefix: &[u8], reverse: bool| options::IterationOption {
+            prefix: Some(prefix.to_vec()),
+            reverse,
+            ..options(-1, None)
+        };
+        let mut opt = vec![];
+        assert!(matches!(
+            get_iteration_mode(&with_prefix(&[5, 255], true), &mut opt, Prefix::STATE),
+            rocksdb::IteratorMode::From(&[0, 6], rocksdb::Direction::Reverse)
+        ));
+        assert!(matches!(
+            get_iteration_mode(&with_prefix(&[5, 255], false), &mut opt, Prefix::STATE),
+            rocksdb::IteratorMode::From(&[0, 5, 255], rocksdb::Direction::Forward)
+        ));
+        // whole database without the prefix option keeps the default bound
+        let gte_only = options::IterationOption {
+            gte: Some(vec![1, 2]),
+            reverse: true,
+            ..options(-1, None)
+        };
+        assert!(matches!(
+            get_iteration_mode(&gte_only, &mut opt, &[]),
+            rocksdb::IteratorMode::From(&[255, 255], rocksdb::Direction::Reverse)
+        ));
+        assert!(matches!(
+            get_iteration_mode(&options(-1, Some(&[1, 2, 3])), &mut opt, &[]),
+            rocksdb::IteratorMode::From(&[0, 0, 0], rocksdb::Direction::Forward)
+        ));
+        assert!(matches!(
+            get_iteration_mode(&gte_only, &mut opt, Prefix::STATE),
+            rocksdb::IteratorMode::From(&[1], rocksdb::Direction::Reverse)
+        ));
+        assert_eq!(with_default_bound(&gte_only).lte, Some(vec![255, 255]));
+        assert_eq!(with_default_bound(&with_prefix(&[5], true)).lte, None);
+        // prefix of all 0xff has no upper bound
+        assert!(matches!(
+            get_iteration_mode(&with_prefix(&[255, 255], true), &mut opt, &[]),
+            rocksdb::IteratorMode::End
+        ));
+
+        // keys keep the prefix option, and only the namespace is removed
+        let keys: Vec<&[u8]> = vec![&[0, 5, 254], &[0, 5, 255], &[0, 5, 255, 0], &[0, 6]];
+        let mut iter = items(&keys[1..]).into_iter();
+        let (pairs, completed) = next_chunk(
+            &mut iter,
+            &with_prefix(&[5, 255], false),
+            &mut 0,
+            10,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                KVPair::new(&[5, 255], &[1]),
+                KVPair::new(&[5, 255, 0], &[1])
+            ]
+        );
+        assert!(completed);
+        let reversed: Vec<&[u8]> = keys.iter().rev().copied().collect();
+        let mut iter = items(&reversed).into_iter();
+        let (pairs, _) = next_chunk(
+            &mut iter,
+            &with_prefix(&[5, 255], true),
+            &mut 0,
+            10,
+            Prefix::STATE,
+        )
+        .unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                KVPair::new(&[5, 255, 0], &[1]),
+                KVPair::new(&[5, 255], &[1])
+            ]
+        );
+
+        // all 0xff prefix iterates to the end of the namespace
+        let keys: Vec<&[u8]> = vec![&[0, 255, 254], &[0, 255, 255], &[0, 255, 255, 255]];
+        assert_eq!(
+            count_in_range(
+                items(&keys).into_iter(),
+                &with_prefix(&[255, 255], false),
+                0,
+                Prefix::STATE,
+                |_| false
+            )
+            .unwrap(),
+            0
+        );
+        assert_eq!(
+            count_in_range(
+                items(&keys[1..]).into_iter(),
+                &with_prefix(&[255, 255], false),
+                0,
+                Prefix::STATE,
+                |_| false
+            )
+            .unwrap(),
+            2
+        );
+
+        assert!(is_key_in_range(
+            &with_prefix(&[5, 255], false),
+            &[5, 255, 1]
+        ));
+        assert!(!is_key_in_range(&with_prefix(&[5, 255], false), &[5, 254]));
+    }
+
+    #[test]
+    fn test_next_chunk_stops_on_error() {
+        let mut items = items(&[&[0, 1], &[0, 2]]);
+        let temp_dir = tempdir::TempDir::new("test_next_chunk_error").unwrap();
+        // opening the same database twice fails with the lock error
+        let _db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let err = rocksdb::DB::open_default(&temp_dir).err().unwrap();
+        items.insert(1, Err(err));
+        let mut iter = items.into_iter();
+        let mut counter = 0;
+        assert!(next_chunk(
+            &mut iter,
+            &options(-1, None),
+            &mut counter,
+            10,
+            Prefix::STATE
+        )
+        .is_err());
+        // the pair after the error is not read
+        assert_eq!(iter.len(), 1);
+    }
+}
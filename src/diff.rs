@@ -1,13 +1,14 @@
 /// diff provides data structure to revert the state for StateDB.
 use crate::batch;
 use crate::codec;
-use crate::types::{Cache, HashKind, HashWithKind, KVPair, KVPairCodec, NestedVec};
+use crate::types::{Cache, HashKind, HashWithKind, KVPair, KVPairCodec, NestedVec, SharedKVPair};
 
 /// Diff maintains difference between each state changes, and it is used when reverting the state.
 /// When updating state to next state, it maintains:
 /// - newly created keys.
 /// - updated keys and corresponding original values
 /// - deleted keys and corresponding original values
+///
 /// When reverting the state,
 /// - Remove created keys
 /// - Update updated to the value
@@ -19,11 +20,131 @@ pub struct Diff {
     deleted: Vec<KVPair>,
 }
 
+/// DiffValue is the original value of the updated or deleted key in the stored diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffValue {
+    Inline(Vec<u8>),
+    /// Ref is the hash of the value kept in the old-value store instead of the value.
+    Ref(Vec<u8>),
+}
+
+/// StoredPair is the updated or deleted pair as it is stored in the diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredPair {
+    pub key: Vec<u8>,
+    pub value: DiffValue,
+}
+
+/// StoredDiff is the diff as it is stored, whose values can be the references to the old-value store.
+/// It is resolved to Diff with the values of the references.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredDiff {
+    created: NestedVec,
+    updated: Vec<StoredPair>,
+    deleted: Vec<StoredPair>,
+}
+
+/// DiffVersion is the format of the encoded diff.
+/// Diffs are stored until they are pruned, so that all the versions must be kept decodable.
+/// New format must be added as a new variant with a version byte which is not a field tag of V0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffVersion {
+    /// V0 is the headerless format written before the version header was introduced.
+    V0,
+    /// V1 is the version header followed by the same fields as V0.
+    V1,
+    /// V2 is the version header followed by the fields of V0, whose pairs hold either the value or the hash of the value.
+    V2,
+}
+
+impl DiffVersion {
+    /// CURRENT is the version used to encode the new diffs.
+    pub const CURRENT: Self = Self::V1;
+
+    /// V0_TAGS are the tags of the fields 1 to 3, one of which starts the non-empty V0 diff.
+    const V0_TAGS: [u8; 3] = [0x0a, 0x12, 0x1a];
+
+    /// header returns the version byte written before the fields. V0 does not have the header.
+    fn header(&self) -> Option<u8> {
+        match self {
+            Self::V0 => None,
+            Self::V1 => Some(1),
+            Self::V2 => Some(2),
+        }
+    }
+
+    /// sniff returns the version of the encoded diff and the bytes after the header.
+    /// Empty bytes or bytes starting with the field tag are V0.
+    fn sniff(val: &[u8]) -> Result<(Self, &[u8]), codec::CodecError> {
+        match val.first() {
+            None => Ok((Self::V0, val)),
+            Some(first) if Self::V0_TAGS.contains(first) => Ok((Self::V0, val)),
+            Some(1) => Ok((Self::V1, &val[1..])),
+            Some(2) => Ok((Self::V2, &val[1..])),
+            Some(version) => Err(codec::CodecError::UnsupportedVersion(*version)),
+        }
+    }
+
+    /// decode_pair decodes the updated or deleted pair in the diff of the version.
+    /// V2 pair has the value in the field 2, or the hash of the value in the field 3.
+    fn decode_pair(&self, val: &[u8]) -> Result<StoredPair, codec::CodecError> {
+        match self {
+            Self::V0 | Self::V1 => KVPair::decode(val).map(|pair| StoredPair {
+                key: pair.key_as_vec(),
+                value: DiffValue::Inline(pair.value_as_vec()),
+            }),
+            Self::V2 => {
+                let mut reader = codec::Reader::new(val);
+                let key = reader.read_bytes(1)?;
+                let value = reader.read_bytes(2)?;
+                let hash = reader.read_bytes(3)?;
+                reader.check_end()?;
+                let value = if hash.is_empty() {
+                    DiffValue::Inline(value)
+                } else if value.is_empty() {
+                    DiffValue::Ref(hash)
+                } else {
+                    // pair must not have both the value and the hash
+                    return Err(codec::CodecError::InvalidBytesLength);
+                };
+                Ok(StoredPair { key, value })
+            },
+        }
+    }
+
+    /// encode_pair encodes the updated or deleted pair in the diff of the version.
+    /// Only V2 can hold the reference, and the other versions are encoded from the inline values of Diff.
+    fn encode_pair(&self, pair: &StoredPair) -> Vec<u8> {
+        let mut writer = codec::Writer::new();
+        writer.write_bytes(1, &pair.key);
+        match (self, &pair.value) {
+            (_, DiffValue::Inline(value)) => writer.write_bytes(2, value),
+            (Self::V2, DiffValue::Ref(hash)) => writer.write_bytes(3, hash),
+            (Self::V0 | Self::V1, DiffValue::Ref(_)) => {
+                unreachable!("reference is only encoded in V2")
+            },
+        }
+        writer.result().to_vec()
+    }
+}
+
+/// SNIPPET_LENGTH is the maximum number of bytes shown in the error of the corrupted diff.
+const SNIPPET_LENGTH: usize = 32;
+
+/// hex_snippet returns the hex of the leading bytes, with "..." if the bytes are truncated.
+pub fn hex_snippet(bytes: &[u8]) -> String {
+    if bytes.len() <= SNIPPET_LENGTH {
+        return hex::encode(bytes);
+    }
+    format!("{}...", hex::encode(&bytes[..SNIPPET_LENGTH]))
+}
+
 impl KVPairCodec for KVPair {
     fn decode(val: &[u8]) -> Result<Self, codec::CodecError> {
         let mut reader = codec::Reader::new(val);
         let key = reader.read_bytes(1)?;
         let value = reader.read_bytes(2)?;
+        reader.check_end()?;
         Ok(Self::new(&key, &value))
     }
 
@@ -43,39 +164,82 @@ impl Diff {
             deleted,
         }
     }
+
+    /// created returns the keys newly created by the state change.
+    pub fn created(&self) -> &NestedVec {
+        &self.created
+    }
+
+    /// into_created returns the created keys without copying them, when the rest of the diff is not used.
+    pub fn into_created(self) -> NestedVec {
+        self.created
+    }
+
+    /// updated returns the pairs changed by the state change with the value before the change.
+    pub fn updated(&self) -> &[KVPair] {
+        &self.updated
+    }
+
+    /// deleted returns the pairs removed by the state change with the value before the change.
+    pub fn deleted(&self) -> &[KVPair] {
+        &self.deleted
+    }
+
     /// decode bytes to diff struct.
-    /// decoding uses lisk-codec protocol.
+    /// decoding uses lisk-codec protocol, and it fails if any byte is not consumed.
+    /// The version is sniffed from the first byte, so that the headerless V0 diff is also decoded.
+    /// The diff with the references fails with MissingValue, and it must be decoded with StoredDiff.
     pub fn decode(val: &[u8]) -> Result<Self, codec::CodecError> {
-        let mut reader = codec::Reader::new(val);
-        let created = reader.read_bytes_slice(1)?;
-        let updated_bytes = reader.read_bytes_slice(2)?;
-        let updated: Vec<KVPair> = updated_bytes
-            .iter()
-            .map(|value| KVPair::decode(value).unwrap())
-            .collect();
-        let deleted_bytes = reader.read_bytes_slice(3)?;
-        let deleted: Vec<KVPair> = deleted_bytes
-            .iter()
-            .map(|value| KVPair::decode(value).unwrap())
-            .collect();
-        Ok(Self {
-            created,
-            updated,
-            deleted,
-        })
+        StoredDiff::decode(val)?
+            .resolve(|hash| Err(codec::CodecError::MissingValue(hex_snippet(hash))))
     }
 
-    /// encode diff to bytes.
+    /// encode diff to bytes with the current version.
     /// encoding uses lisk-codec protocol.
     pub fn encode(&self) -> Vec<u8> {
-        let mut writer = codec::Writer::new();
-        writer.write_bytes_slice(1, &self.created);
-        let updated: NestedVec = self.updated.iter().map(|v| v.encode()).collect();
-        writer.write_bytes_slice(2, &updated);
-        let deleted: NestedVec = self.deleted.iter().map(|v| v.encode()).collect();
-        writer.write_bytes_slice(3, &deleted);
+        self.encode_with_version(DiffVersion::CURRENT)
+    }
 
-        writer.result().to_vec()
+    /// encode_with_version encodes diff to bytes with the version header.
+    pub fn encode_with_version(&self, version: DiffVersion) -> Vec<u8> {
+        self.to_stored(None).encode_with_version(version)
+    }
+
+    /// encode_with_refs encodes diff to bytes in V2, where the values of at least threshold bytes are replaced by the hashes.
+    /// The referenced values are returned as the pairs of the hash and the value to be kept in the old-value store.
+    pub fn encode_with_refs(&self, threshold: usize) -> (Vec<u8>, Vec<KVPair>) {
+        let stored = self.to_stored(Some(threshold));
+        let referenced = self
+            .updated
+            .iter()
+            .chain(self.deleted.iter())
+            .zip(stored.updated.iter().chain(stored.deleted.iter()))
+            .filter_map(|(pair, stored_pair)| match &stored_pair.value {
+                DiffValue::Ref(hash) => Some(KVPair::new(hash, pair.value())),
+                DiffValue::Inline(_) => None,
+            })
+            .collect();
+
+        (stored.encode_with_version(DiffVersion::V2), referenced)
+    }
+
+    /// to_stored returns the stored diff where the values of at least threshold bytes are replaced by the hashes.
+    /// All the values are kept inline if threshold is None.
+    fn to_stored(&self, threshold: Option<usize>) -> StoredDiff {
+        let store_pair = |pair: &KVPair| StoredPair {
+            key: pair.key_as_vec(),
+            value: match threshold {
+                Some(threshold) if pair.value().len() >= threshold => {
+                    DiffValue::Ref(pair.value().hash_with_kind(HashKind::Value))
+                },
+                _ => DiffValue::Inline(pair.value_as_vec()),
+            },
+        };
+        StoredDiff {
+            created: self.created.clone(),
+            updated: self.updated.iter().map(store_pair).collect(),
+            deleted: self.deleted.iter().map(store_pair).collect(),
+        }
     }
 
     /// revert_hashed_update returns cache value with original data.
@@ -84,29 +248,26 @@ impl Diff {
         let mut result = Cache::new();
         for kv in self.updated.iter() {
             result.insert(
-                kv.key_as_vec().hash_with_kind(HashKind::Key),
-                kv.value_as_vec().hash_with_kind(HashKind::Value),
+                kv.key().hash_with_kind(HashKind::Key),
+                kv.value().hash_with_kind(HashKind::Value),
             );
         }
         for kv in self.deleted.iter() {
             result.insert(
-                kv.key_as_vec().hash_with_kind(HashKind::Key),
-                kv.value_as_vec().hash_with_kind(HashKind::Value),
+                kv.key().hash_with_kind(HashKind::Key),
+                kv.value().hash_with_kind(HashKind::Value),
             );
         }
         for key in self.created.iter() {
-            result.insert(key.to_vec().hash_with_kind(HashKind::Key), vec![]);
+            result.insert(key.hash_with_kind(HashKind::Key), vec![]);
         }
         result
     }
 
     /// revert_commit updates batch to revert the states.
     pub fn revert_commit(&self, batch: &mut impl batch::BatchWriter) {
-        for kv in self.updated.iter() {
-            batch.put(kv);
-        }
-        for kv in self.deleted.iter() {
-            batch.put(kv);
+        for kv in self.updated.iter().chain(self.deleted.iter()) {
+            batch.put(&SharedKVPair::new(kv.key(), kv.value()));
         }
         for key in self.created.iter() {
             batch.delete(key);
@@ -114,6 +275,103 @@ impl Diff {
     }
 }
 
+impl StoredDiff {
+    /// decode decodes the diff of any version without resolving the references.
+    pub fn decode(val: &[u8]) -> Result<Self, codec::CodecError> {
+        let (version, body) = DiffVersion::sniff(val)?;
+        let mut reader = codec::Reader::new(body);
+        let created = reader.read_bytes_slice(1)?;
+        let updated_bytes = reader.read_bytes_slice(2)?;
+        let updated = updated_bytes
+            .iter()
+            .map(|value| version.decode_pair(value))
+            .collect::<Result<Vec<StoredPair>, codec::CodecError>>()?;
+        let deleted_bytes = reader.read_bytes_slice(3)?;
+        let deleted = deleted_bytes
+            .iter()
+            .map(|value| version.decode_pair(value))
+            .collect::<Result<Vec<StoredPair>, codec::CodecError>>()?;
+        reader.check_end()?;
+        Ok(Self {
+            created,
+            updated,
+            deleted,
+        })
+    }
+
+    fn encode_with_version(&self, version: DiffVersion) -> Vec<u8> {
+        let mut writer = codec::Writer::new();
+        writer.write_bytes_slice(1, &self.created);
+        let updated: NestedVec = self
+            .updated
+            .iter()
+            .map(|v| version.encode_pair(v))
+            .collect();
+        writer.write_bytes_slice(2, &updated);
+        let deleted: NestedVec = self
+            .deleted
+            .iter()
+            .map(|v| version.encode_pair(v))
+            .collect();
+        writer.write_bytes_slice(3, &deleted);
+
+        match version.header() {
+            Some(header) => [&[header], writer.result().as_slice()].concat(),
+            None => writer.result().to_vec(),
+        }
+    }
+
+    /// created returns the keys newly created by the state change.
+    pub fn created(&self) -> &NestedVec {
+        &self.created
+    }
+
+    /// updated returns the pairs changed by the state change with the value before the change.
+    pub fn updated(&self) -> &[StoredPair] {
+        &self.updated
+    }
+
+    /// deleted returns the pairs removed by the state change with the value before the change.
+    pub fn deleted(&self) -> &[StoredPair] {
+        &self.deleted
+    }
+
+    /// refs returns the hashes of the values kept in the old-value store, once for each reference.
+    pub fn refs(&self) -> Vec<&[u8]> {
+        self.updated
+            .iter()
+            .chain(self.deleted.iter())
+            .filter_map(|pair| match &pair.value {
+                DiffValue::Ref(hash) => Some(hash.as_slice()),
+                DiffValue::Inline(_) => None,
+            })
+            .collect()
+    }
+
+    /// resolve returns the diff with the values of the references returned by lookup.
+    pub fn resolve<E>(
+        self,
+        mut lookup: impl FnMut(&[u8]) -> Result<Vec<u8>, E>,
+    ) -> Result<Diff, E> {
+        let mut resolve_pairs = |pairs: Vec<StoredPair>| {
+            pairs
+                .into_iter()
+                .map(|pair| match pair.value {
+                    DiffValue::Inline(value) => Ok(KVPair(pair.key, value)),
+                    DiffValue::Ref(hash) => lookup(&hash).map(|value| KVPair(pair.key, value)),
+                })
+                .collect::<Result<Vec<KVPair>, E>>()
+        };
+        let updated = resolve_pairs(self.updated)?;
+        let deleted = resolve_pairs(self.deleted)?;
+        Ok(Diff {
+            created: self.created,
+            updated,
+            deleted,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +412,125 @@ mod tests {
         assert_eq!(diff, decoded);
     }
 
+    #[test]
+    fn test_diff_encode_decode_versions() {
+        let created = vec![b"test_key".to_vec()];
+        let updated = vec![KVPair::new(b"test_key", b"test_value")];
+        let deleted = vec![KVPair::new(b"test_key_deleted", b"test_value_deleted")];
+        let diffs = [
+            Diff::new(created.clone(), updated.clone(), deleted.clone()),
+            Diff::new(vec![], updated.clone(), deleted.clone()),
+            Diff::new(vec![], vec![], deleted),
+            Diff::new(vec![], vec![], vec![]),
+        ];
+        for diff in diffs.iter() {
+            let v0 = diff.encode_with_version(DiffVersion::V0);
+            let v1 = diff.encode_with_version(DiffVersion::V1);
+            assert_eq!(v1, [&[1], v0.as_slice()].concat());
+            assert_eq!(diff.encode(), v1);
+            assert_eq!(DiffVersion::sniff(&v0).unwrap().0, DiffVersion::V0);
+            assert_eq!(DiffVersion::sniff(&v1).unwrap().0, DiffVersion::V1);
+            assert_eq!(&Diff::decode(&v0).unwrap(), diff);
+            assert_eq!(&Diff::decode(&v1).unwrap(), diff);
+        }
+
+        // v0 diff encoded before the version header is still decoded
+        let v0 =
+            hex::decode("0a08746573745f6b657912160a08746573745f6b6579120a746573745f76616c7565")
+                .unwrap();
+        assert_eq!(
+            Diff::decode(&v0).unwrap(),
+            Diff::new(created, updated, vec![])
+        );
+
+        assert!(matches!(
+            Diff::decode(&[3, 0x0a, 0x00]),
+            Err(codec::CodecError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn test_diff_encode_with_refs() {
+        let created = vec![b"test_key".to_vec()];
+        let updated = vec![
+            KVPair::new(b"test_key", b"test_value"),
+            KVPair::new(b"test_key_large", &[7; 64]),
+        ];
+        let deleted = vec![KVPair::new(b"test_key_deleted", &[7; 64])];
+        let diff = Diff::new(created, updated, deleted);
+
+        let (encoded, referenced) = diff.encode_with_refs(64);
+        assert_eq!(DiffVersion::sniff(&encoded).unwrap().0, DiffVersion::V2);
+        let hash = vec![7_u8; 64].hash_with_kind(HashKind::Value);
+        assert_eq!(
+            referenced,
+            vec![KVPair::new(&hash, &[7; 64]), KVPair::new(&hash, &[7; 64])]
+        );
+
+        let stored = StoredDiff::decode(&encoded).unwrap();
+        assert_eq!(stored.refs(), vec![hash.as_slice(), hash.as_slice()]);
+        assert_eq!(
+            stored.updated()[0].value,
+            DiffValue::Inline(b"test_value".to_vec())
+        );
+        // references cannot be resolved without the old-value store
+        assert!(matches!(
+            Diff::decode(&encoded),
+            Err(codec::CodecError::MissingValue(_))
+        ));
+        let resolved = stored
+            .resolve(|key| {
+                assert_eq!(key, hash.as_slice());
+                Ok::<_, codec::CodecError>(vec![7; 64])
+            })
+            .unwrap();
+        assert_eq!(resolved, diff);
+
+        // inline diffs are decoded as the stored diff without the references
+        let stored = StoredDiff::decode(&diff.encode()).unwrap();
+        assert!(stored.refs().is_empty());
+        assert_eq!(
+            stored
+                .resolve(|_| Err(codec::CodecError::MissingValue(String::new())))
+                .unwrap(),
+            diff
+        );
+        // the diff without the large values has no reference
+        let (encoded, referenced) = diff.encode_with_refs(65);
+        assert!(referenced.is_empty());
+        assert_eq!(Diff::decode(&encoded).unwrap(), diff);
+    }
+
+    #[test]
+    fn test_diff_decode_corrupted() {
+        let created = vec![b"test_key".to_vec()];
+        let updated = vec![KVPair::new(b"test_key", b"test_value")];
+        let deleted = vec![KVPair::new(b"test_key_deleted", b"test_value_deleted")];
+        let encoded = Diff::new(created, updated, deleted).encode();
+
+        // length of the updated pair is flipped
+        let mut corrupted = encoded.clone();
+        corrupted[12] ^= 0xff;
+        assert!(Diff::decode(&corrupted).is_err());
+        // truncation does not panic, and it fails unless truncated at the field boundary
+        for len in 1..encoded.len() {
+            let _ = Diff::decode(&encoded[..len]);
+        }
+        assert!(Diff::decode(&encoded[..encoded.len() - 1]).is_err());
+        let trailing = [encoded.as_slice(), &[0x22, 0x00]].concat();
+        assert!(matches!(
+            Diff::decode(&trailing),
+            Err(codec::CodecError::UnexpectedBytes(_))
+        ));
+    }
+
+    #[test]
+    fn test_hex_snippet() {
+        assert_eq!(hex_snippet(&[1, 2, 255]), "0102ff");
+        let snippet = hex_snippet(&[0xab; 40]);
+        assert_eq!(snippet, format!("{}...", "ab".repeat(32)));
+    }
+
     #[test]
     fn test_diff_revert_hashed_update() {
         let created = vec![b"test_key".to_vec()];
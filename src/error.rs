@@ -0,0 +1,476 @@
+/// error provides the crate-wide error, which is surfaced to JS with a stable code.
+/// JS can match the code instead of the message, so the codes must not be changed.
+use std::io;
+use std::sync::mpsc;
+
+#[cfg(feature = "node")]
+use neon::context::Context;
+#[cfg(feature = "node")]
+use neon::object::Object;
+#[cfg(feature = "node")]
+use neon::result::{JsResult, NeonResult};
+#[cfg(feature = "node")]
+use neon::types::JsError;
+use thiserror::Error;
+
+use crate::batch::BatchConsumed;
+use crate::codec::CodecError;
+#[cfg(feature = "node")]
+use crate::database::checkpoint_group::CheckpointGroupError;
+use crate::database::chunked_write::ChunkedWriteError;
+use crate::database::conditional_write::ConditionalWriteError;
+use crate::database::in_memory::snapshot::SnapshotError;
+use crate::database::key_layout::KeyLayoutError;
+use crate::database::prefix_filter::PrefixFilterError;
+use crate::database::reader_writer::open_readers::TooManyReaders;
+use crate::database::traits::HandleClosed;
+use crate::database::types::{InvalidKeyLength, SizeLimitError};
+use crate::sparse_merkle_tree::smt::SMTError;
+use crate::state::current_state::CurrentStateError;
+use crate::state::migrations::MigrationError;
+use crate::state::smt_sync::SmtSyncError;
+use crate::state::snapshot::StateSnapshotError;
+use crate::state::spill::SpillError;
+use crate::state::state_writer::StateWriterError;
+use crate::state::store::DataStoreError;
+
+pub const ERR_NOT_FOUND: &str = "ERR_NOT_FOUND";
+pub const ERR_CLOSED: &str = "ERR_CLOSED";
+pub const ERR_INVALID_KEY_LENGTH: &str = "ERR_INVALID_KEY_LENGTH";
+pub const ERR_CODEC: &str = "ERR_CODEC";
+pub const ERR_ROCKSDB: &str = "ERR_ROCKSDB";
+pub const ERR_IO: &str = "ERR_IO";
+pub const ERR_BUSY: &str = "ERR_BUSY";
+pub const ERR_INVALID_USAGE: &str = "ERR_INVALID_USAGE";
+pub const ERR_INVALID_ARGUMENT: &str = "ERR_INVALID_ARGUMENT";
+pub const ERR_READONLY: &str = "ERR_READONLY";
+pub const ERR_INVALID_ROOT: &str = "ERR_INVALID_ROOT";
+pub const ERR_NOT_RETAINED: &str = "ERR_NOT_RETAINED";
+pub const ERR_CORRUPTED: &str = "ERR_CORRUPTED";
+pub const ERR_INTERRUPTED: &str = "ERR_INTERRUPTED";
+pub const ERR_CANCELLED: &str = "ERR_CANCELLED";
+pub const ERR_SIZE_LIMIT: &str = "ERR_SIZE_LIMIT";
+pub const ERR_DB_LOCKED: &str = "ERR_DB_LOCKED";
+pub const ERR_TIMEOUT: &str = "ERR_TIMEOUT";
+pub const ERR_WRITER_CONSUMED: &str = "ERR_WRITER_CONSUMED";
+pub const ERR_BATCH_CONSUMED: &str = "ERR_BATCH_CONSUMED";
+pub const ERR_EMPTY_VALUE: &str = "ERR_EMPTY_VALUE";
+pub const ERR_COUNTER_OVERFLOW: &str = "ERR_COUNTER_OVERFLOW";
+pub const ERR_NEEDS_MIGRATION: &str = "ERR_NEEDS_MIGRATION";
+pub const ERR_CONDITION_FAILED: &str = "ERR_CONDITION_FAILED";
+pub const ERR_BULK_LOADED: &str = "ERR_BULK_LOADED";
+pub const ERR_TOO_MANY_READERS: &str = "ERR_TOO_MANY_READERS";
+pub const ERR_UNKNOWN: &str = "ERR_UNKNOWN";
+
+/// DbError is the error returned to JS. The messages of the wrapped errors are kept as they are.
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error("No data")]
+    NotFound,
+    #[error(transparent)]
+    Closed(#[from] HandleClosed),
+    /// Disconnected is the error of sending the operation after the database thread is stopped.
+    #[error("{0}")]
+    Disconnected(String),
+    /// Locked is the error of opening the database whose LOCK file is held by another instance.
+    #[error("database at `{0}` is locked by another instance")]
+    Locked(String),
+    #[error(transparent)]
+    InvalidKeyLength(#[from] InvalidKeyLength),
+    #[error(transparent)]
+    SizeLimit(#[from] SizeLimitError),
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+    #[error(transparent)]
+    RocksDb(#[from] rocksdb::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("backpressure: {0} operations are pending")]
+    Busy(usize),
+    #[error(transparent)]
+    InvalidUsage(#[from] StateWriterError),
+    #[error(transparent)]
+    BatchConsumed(#[from] BatchConsumed),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error(transparent)]
+    Smt(#[from] SMTError),
+    #[error(transparent)]
+    DataStore(#[from] DataStoreError),
+    #[error(transparent)]
+    CurrentState(#[from] CurrentStateError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    StateSnapshot(#[from] StateSnapshotError),
+    #[error(transparent)]
+    ChunkedWrite(#[from] ChunkedWriteError),
+    #[error(transparent)]
+    ConditionalWrite(#[from] ConditionalWriteError),
+    #[error(transparent)]
+    Spill(#[from] SpillError),
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+    #[error(transparent)]
+    PrefixFilter(#[from] PrefixFilterError),
+    #[error(transparent)]
+    SmtSync(#[from] SmtSyncError),
+    #[error(transparent)]
+    KeyLayout(#[from] KeyLayoutError),
+    #[error(transparent)]
+    TooManyReaders(#[from] TooManyReaders),
+    #[cfg(feature = "node")]
+    #[error(transparent)]
+    CheckpointGroup(#[from] CheckpointGroupError),
+    /// Corrupted is the error of the data which is read but inconsistent.
+    #[error("{0}")]
+    Corrupted(String),
+    /// Cancelled is the error of the operation cancelled from JS before writing anything.
+    #[error("{0} is cancelled")]
+    Cancelled(&'static str),
+    /// Timeout is the error of the operation which did not start on the database thread within the timeout in ms.
+    #[error("operation did not start within {0}ms")]
+    Timeout(u64),
+    #[error("{0}")]
+    Unknown(String),
+}
+
+impl<T> From<mpsc::SendError<T>> for DbError {
+    fn from(err: mpsc::SendError<T>) -> Self {
+        Self::Disconnected(err.to_string())
+    }
+}
+
+impl From<mpsc::RecvError> for DbError {
+    fn from(err: mpsc::RecvError) -> Self {
+        Self::Disconnected(err.to_string())
+    }
+}
+
+impl From<String> for DbError {
+    fn from(err: String) -> Self {
+        Self::Unknown(err)
+    }
+}
+
+impl DbError {
+    /// code returns the stable code of the error set to the JS error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => ERR_NOT_FOUND,
+            Self::Closed(_) | Self::Disconnected(_) => ERR_CLOSED,
+            Self::Locked(_) => ERR_DB_LOCKED,
+            Self::InvalidKeyLength(_) => ERR_INVALID_KEY_LENGTH,
+            Self::SizeLimit(_) => ERR_SIZE_LIMIT,
+            Self::Codec(_) => ERR_CODEC,
+            Self::RocksDb(_) => ERR_ROCKSDB,
+            Self::Io(_) => ERR_IO,
+            Self::Busy(_) => ERR_BUSY,
+            Self::InvalidUsage(StateWriterError::Consumed) => ERR_WRITER_CONSUMED,
+            Self::InvalidUsage(StateWriterError::EmptyValue) => ERR_EMPTY_VALUE,
+            Self::InvalidUsage(StateWriterError::CounterOverflow(_)) => ERR_COUNTER_OVERFLOW,
+            Self::InvalidUsage(StateWriterError::Spill(err)) | Self::Spill(err) => match err {
+                SpillError::Io(_) => ERR_IO,
+                SpillError::Database(_) => ERR_ROCKSDB,
+            },
+            Self::InvalidUsage(_) => ERR_INVALID_USAGE,
+            Self::BatchConsumed(_) => ERR_BATCH_CONSUMED,
+            Self::InvalidArgument(_) => ERR_INVALID_ARGUMENT,
+            Self::Smt(err) => match err {
+                SMTError::InvalidKeyLength { .. } => ERR_INVALID_KEY_LENGTH,
+                SMTError::InvalidRoot(_) => ERR_INVALID_ROOT,
+                SMTError::NotFound(_) => ERR_NOT_FOUND,
+                SMTError::InvalidBitmapLen
+                | SMTError::InvalidInput(_)
+                | SMTError::InvalidValue(_)
+                | SMTError::EmptyValue(_) => ERR_INVALID_ARGUMENT,
+                SMTError::Unknown(_) => ERR_UNKNOWN,
+            },
+            Self::DataStore(err) => match err {
+                DataStoreError::Readonly => ERR_READONLY,
+                DataStoreError::InvalidRoot(_) => ERR_INVALID_ROOT,
+                DataStoreError::DiffNotFound(_)
+                | DataStoreError::DiffNotAvailable(_)
+                | DataStoreError::HeightNotRetained(_) => ERR_NOT_RETAINED,
+                DataStoreError::DiffCorrupted(_) => ERR_CORRUPTED,
+                DataStoreError::EmptyValue => ERR_EMPTY_VALUE,
+                DataStoreError::BulkLoaded { .. } => ERR_BULK_LOADED,
+                DataStoreError::Unknown(_) => ERR_UNKNOWN,
+            },
+            Self::CurrentState(err) => current_state_code(err),
+            Self::Snapshot(err) => match err {
+                SnapshotError::Io(_) => ERR_IO,
+                _ => ERR_CORRUPTED,
+            },
+            Self::StateSnapshot(err) => match err {
+                StateSnapshotError::Io(_) => ERR_IO,
+                StateSnapshotError::Database(_) => ERR_ROCKSDB,
+                StateSnapshotError::HeightNotCurrent { .. } | StateSnapshotError::NotEmpty => {
+                    ERR_INVALID_ARGUMENT
+                },
+                _ => ERR_CORRUPTED,
+            },
+            Self::ChunkedWrite(err) => match err {
+                ChunkedWriteError::RocksDB(_) => ERR_ROCKSDB,
+                ChunkedWriteError::JournalMismatch => ERR_INVALID_ARGUMENT,
+                ChunkedWriteError::Interrupted(_) => ERR_INTERRUPTED,
+                ChunkedWriteError::InvalidBatch(_) => ERR_INVALID_ARGUMENT,
+            },
+            Self::ConditionalWrite(err) => match err {
+                ConditionalWriteError::Database(_) => ERR_ROCKSDB,
+                ConditionalWriteError::Failed(_) => ERR_CONDITION_FAILED,
+            },
+            Self::Migration(err) => match err {
+                MigrationError::Database(_) => ERR_ROCKSDB,
+                MigrationError::Unsupported { .. } => ERR_INVALID_ARGUMENT,
+                MigrationError::NeedsMigration(_) => ERR_NEEDS_MIGRATION,
+                MigrationError::InvalidVersion(_) | MigrationError::Step { .. } => ERR_CORRUPTED,
+                MigrationError::CurrentState(err) => current_state_code(err),
+            },
+            Self::PrefixFilter(err) => match err {
+                PrefixFilterError::Database(_) => ERR_ROCKSDB,
+                PrefixFilterError::Mismatch { .. } => ERR_INVALID_ARGUMENT,
+                PrefixFilterError::InvalidLength(_) => ERR_CORRUPTED,
+            },
+            Self::SmtSync(err) => match err {
+                SmtSyncError::Disabled => ERR_INVALID_USAGE,
+                SmtSyncError::NodeMismatch(_) => ERR_INVALID_ARGUMENT,
+                SmtSyncError::Incomplete { .. } => ERR_INVALID_ROOT,
+                SmtSyncError::Smt(_) => ERR_CORRUPTED,
+                SmtSyncError::CurrentState(_) | SmtSyncError::Database(_) => ERR_ROCKSDB,
+            },
+            Self::KeyLayout(err) => match err {
+                KeyLayoutError::InvalidPart { .. } | KeyLayoutError::TooShort { .. } => {
+                    ERR_INVALID_KEY_LENGTH
+                },
+                KeyLayoutError::UnsupportedKind(_) | KeyLayoutError::PrefixMismatch => {
+                    ERR_INVALID_ARGUMENT
+                },
+            },
+            Self::TooManyReaders(_) => ERR_TOO_MANY_READERS,
+            #[cfg(feature = "node")]
+            Self::CheckpointGroup(err) => match err {
+                CheckpointGroupError::Io(_) => ERR_IO,
+                CheckpointGroupError::UnknownGroup(_)
+                | CheckpointGroupError::ManifestExists(_)
+                | CheckpointGroupError::DuplicateMember(_) => ERR_INVALID_ARGUMENT,
+                CheckpointGroupError::Incomplete
+                | CheckpointGroupError::InvalidManifest(_)
+                | CheckpointGroupError::RootMismatch(_) => ERR_CORRUPTED,
+            },
+            Self::Corrupted(_) => ERR_CORRUPTED,
+            Self::Cancelled(_) => ERR_CANCELLED,
+            Self::Timeout(_) => ERR_TIMEOUT,
+            Self::Unknown(_) => ERR_UNKNOWN,
+        }
+    }
+
+    /// to_js creates the JS error with the message and the code.
+    #[cfg(feature = "node")]
+    pub fn to_js<'a, C: Context<'a>>(&self, ctx: &mut C) -> JsResult<'a, JsError> {
+        let error = ctx.error(self.to_string())?;
+        let code = ctx.string(self.code());
+        error.set(ctx, "code", code)?;
+        Ok(error)
+    }
+
+    /// throw throws the JS error with the message and the code.
+    #[cfg(feature = "node")]
+    pub fn throw<'a, C: Context<'a>, T>(&self, ctx: &mut C) -> NeonResult<T> {
+        let error = self.to_js(ctx)?;
+        ctx.throw(error)
+    }
+}
+
+/// current_state_code returns the code of the error of the current state, which is also returned on open.
+fn current_state_code(err: &CurrentStateError) -> &'static str {
+    match err {
+        CurrentStateError::Database(_) => ERR_ROCKSDB,
+        CurrentStateError::HasherMismatch { .. } => ERR_INVALID_ARGUMENT,
+        _ => ERR_CORRUPTED,
+    }
+}
+
+/// invalid_argument returns InvalidArgument error with the message.
+pub fn invalid_argument(message: impl Into<String>) -> DbError {
+    DbError::InvalidArgument(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// codes are matched by the users, so that changing one must fail this test.
+    #[test]
+    fn test_error_codes() {
+        let (tx, rx) = mpsc::channel::<()>();
+        drop(rx);
+        let mut cases: Vec<(DbError, &str)> = vec![
+            (DbError::NotFound, "ERR_NOT_FOUND"),
+            (DbError::from(HandleClosed("Database")), "ERR_CLOSED"),
+            (DbError::from(tx.send(()).unwrap_err()), "ERR_CLOSED"),
+            (DbError::Locked(String::from("/tmp/db")), "ERR_DB_LOCKED"),
+            (
+                DbError::from(InvalidKeyLength {
+                    expected: 38,
+                    got: 2,
+                }),
+                "ERR_INVALID_KEY_LENGTH",
+            ),
+            (
+                DbError::from(SMTError::InvalidKeyLength {
+                    expected: 38,
+                    got: 2,
+                }),
+                "ERR_INVALID_KEY_LENGTH",
+            ),
+            (DbError::from(SizeLimitError::EmptyKey), "ERR_SIZE_LIMIT"),
+            (DbError::from(CodecError::OutOfRange), "ERR_CODEC"),
+            (
+                DbError::from(io::Error::new(io::ErrorKind::Other, "io")),
+                "ERR_IO",
+            ),
+            (DbError::Busy(2), "ERR_BUSY"),
+            (
+                DbError::from(StateWriterError::InvalidUsage),
+                "ERR_INVALID_USAGE",
+            ),
+            (
+                DbError::from(StateWriterError::Consumed),
+                "ERR_WRITER_CONSUMED",
+            ),
+            (DbError::from(BatchConsumed), "ERR_BATCH_CONSUMED"),
+            (
+                DbError::from(StateWriterError::EmptyValue),
+                "ERR_EMPTY_VALUE",
+            ),
+            (
+                DbError::from(StateWriterError::CounterOverflow(String::new())),
+                "ERR_COUNTER_OVERFLOW",
+            ),
+            (
+                DbError::from(StateWriterError::from(SpillError::from(io::Error::new(
+                    io::ErrorKind::Other,
+                    "spill",
+                )))),
+                "ERR_IO",
+            ),
+            (invalid_argument("height"), "ERR_INVALID_ARGUMENT"),
+            (DbError::from(DataStoreError::Readonly), "ERR_READONLY"),
+            (
+                DbError::from(SMTError::InvalidRoot(String::new())),
+                "ERR_INVALID_ROOT",
+            ),
+            (
+                DbError::from(DataStoreError::InvalidRoot(String::new())),
+                "ERR_INVALID_ROOT",
+            ),
+            (
+                DbError::from(DataStoreError::HeightNotRetained(1)),
+                "ERR_NOT_RETAINED",
+            ),
+            (
+                DbError::from(DataStoreError::DiffNotAvailable(1)),
+                "ERR_NOT_RETAINED",
+            ),
+            (
+                DbError::from(DataStoreError::DiffCorrupted(String::new())),
+                "ERR_CORRUPTED",
+            ),
+            (
+                DbError::from(DataStoreError::BulkLoaded {
+                    height: 2,
+                    start: 1,
+                    end: 3,
+                }),
+                "ERR_BULK_LOADED",
+            ),
+            (
+                DbError::from(ChunkedWriteError::Interrupted(1)),
+                "ERR_INTERRUPTED",
+            ),
+            (
+                DbError::from(ChunkedWriteError::InvalidBatch(String::new())),
+                "ERR_INVALID_ARGUMENT",
+            ),
+            (
+                DbError::from(MigrationError::NeedsMigration(String::new())),
+                "ERR_NEEDS_MIGRATION",
+            ),
+            (
+                DbError::from(ConditionalWriteError::Failed(vec![1])),
+                "ERR_CONDITION_FAILED",
+            ),
+            (
+                DbError::from(CurrentStateError::HasherMismatch {
+                    recorded: String::from("blake3"),
+                    configured: String::from("sha256"),
+                }),
+                "ERR_INVALID_ARGUMENT",
+            ),
+            (
+                DbError::from(MigrationError::from(CurrentStateError::HasherMismatch {
+                    recorded: String::from("blake3"),
+                    configured: String::from("sha256"),
+                })),
+                "ERR_INVALID_ARGUMENT",
+            ),
+            (
+                DbError::from(PrefixFilterError::Mismatch {
+                    recorded: 6,
+                    configured: 7,
+                }),
+                "ERR_INVALID_ARGUMENT",
+            ),
+            (DbError::from(SmtSyncError::Disabled), "ERR_INVALID_USAGE"),
+            (
+                DbError::from(SmtSyncError::Incomplete {
+                    root: String::new(),
+                    node: String::new(),
+                }),
+                "ERR_INVALID_ROOT",
+            ),
+            (
+                DbError::from(KeyLayoutError::TooShort { min: 7, got: 6 }),
+                "ERR_INVALID_KEY_LENGTH",
+            ),
+            (
+                DbError::from(KeyLayoutError::PrefixMismatch),
+                "ERR_INVALID_ARGUMENT",
+            ),
+            (
+                DbError::from(TooManyReaders { max: 2 }),
+                "ERR_TOO_MANY_READERS",
+            ),
+            (DbError::Corrupted(String::new()), "ERR_CORRUPTED"),
+            (DbError::Cancelled("Commit"), "ERR_CANCELLED"),
+            (DbError::Timeout(100), "ERR_TIMEOUT"),
+            (DbError::from(String::from("unknown")), "ERR_UNKNOWN"),
+        ];
+        #[cfg(feature = "node")]
+        cases.push((
+            DbError::from(CheckpointGroupError::Incomplete),
+            "ERR_CORRUPTED",
+        ));
+        for (err, code) in cases {
+            assert_eq!(err.code(), code, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_error_message_is_kept() {
+        assert_eq!(DbError::NotFound.to_string(), "No data");
+        assert_eq!(
+            DbError::from(HandleClosed("StateDB")).to_string(),
+            "StateDB is closed"
+        );
+        assert_eq!(
+            DbError::Busy(2).to_string(),
+            "backpressure: 2 operations are pending"
+        );
+        assert_eq!(
+            DbError::from(StateWriterError::InvalidUsage).to_string(),
+            "Invalid usage"
+        );
+    }
+}
@@ -1,35 +1,71 @@
+#[cfg(feature = "node")]
 use neon::prelude::*;
 
+#[cfg(feature = "node")]
+use crate::database::checkpoint_group;
+#[cfg(feature = "node")]
 use crate::database::db;
+#[cfg(feature = "node")]
 use crate::database::in_memory::in_memory_db;
+#[cfg(feature = "node")]
+use crate::database::key_layout;
+#[cfg(feature = "node")]
+use crate::database::prefixed_db::PrefixedDB;
+#[cfg(feature = "node")]
+use crate::database::reader_writer::range_cursor::RangeCursorHandle;
+#[cfg(feature = "node")]
 use crate::database::reader_writer::read_writer_db;
+#[cfg(feature = "node")]
 use crate::database::reader_writer::reader_db;
+#[cfg(feature = "node")]
+use crate::database::registry;
+#[cfg(feature = "node")]
 use crate::database::traits::{JsNewWithArcMutex, JsNewWithBoxRef};
+#[cfg(feature = "node")]
 use crate::database::types::DbOptions;
+#[cfg(feature = "node")]
 use crate::sparse_merkle_tree::in_memory_smt;
+#[cfg(feature = "node")]
+use crate::state::in_memory_state_db::InMemoryStateDB;
+#[cfg(feature = "node")]
 use crate::state::state_db;
+#[cfg(feature = "node")]
 use crate::state::state_writer;
 
+/// api exposes the plain Rust types to use the storage without neon.
+#[cfg(feature = "rust-api")]
+pub mod api;
 pub mod batch;
 pub mod consts;
 pub mod database;
+pub mod error;
 pub mod sparse_merkle_tree;
 pub mod state;
 pub mod types;
 
+#[cfg(all(feature = "node", any(test, feature = "testing")))]
+pub mod conformance;
+
 mod codec;
 mod diff;
 mod utils;
 
+#[cfg(feature = "node")]
 use batch::WriteBatch;
+#[cfg(feature = "node")]
 use db::Database;
+#[cfg(feature = "node")]
 use in_memory_smt::InMemorySMT;
+#[cfg(feature = "node")]
 use read_writer_db::ReadWriter;
+#[cfg(feature = "node")]
 use state_db::StateDB;
+#[cfg(feature = "node")]
 use state_writer::StateWriter;
 
 /// main registers functions for JS ffi
 
+#[cfg(feature = "node")]
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     let db_new = Database::js_new_with_box_ref::<DbOptions, Database>;
@@ -38,50 +74,165 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("db_close", Database::js_close)?;
     cx.export_function("db_get", Database::js_get)?;
     cx.export_function("db_exists", Database::js_exists)?;
+    cx.export_function("db_exists_prefix", Database::js_exists_prefix)?;
     cx.export_function("db_set", Database::js_set)?;
     cx.export_function("db_del", Database::js_del)?;
     cx.export_function("db_write", Database::js_write)?;
+    cx.export_function("db_write_prepared", Database::js_write_prepared)?;
+    cx.export_function("db_conditional_write", Database::js_conditional_write)?;
+    cx.export_function("db_recover_staged", Database::js_recover_staged)?;
     cx.export_function("db_iterate", Database::js_iterate)?;
     cx.export_function("db_checkpoint", Database::js_checkpoint)?;
+    cx.export_function(
+        "db_checkpoint_with_group",
+        Database::js_checkpoint_with_group,
+    )?;
+    cx.export_function("db_compact", Database::js_compact)?;
+    cx.export_function("db_flush", Database::js_flush)?;
+    cx.export_function("db_delete_range", Database::js_delete_range)?;
+    cx.export_function("db_stats", Database::js_stats)?;
+    cx.export_function("db_approximate_size", Database::js_approximate_size)?;
+    cx.export_function("db_count_range", Database::js_count_range)?;
+    cx.export_function("db_pending_operations", Database::js_pending_operations)?;
+    cx.export_function("db_get_metrics", Database::js_get_metrics)?;
+    cx.export_function("db_with_prefix", PrefixedDB::js_new)?;
+    cx.export_function("prefixed_db_get", PrefixedDB::js_get)?;
+    cx.export_function("prefixed_db_exists", PrefixedDB::js_exists)?;
+    cx.export_function("prefixed_db_set", PrefixedDB::js_set)?;
+    cx.export_function("prefixed_db_del", PrefixedDB::js_del)?;
+    cx.export_function("prefixed_db_write", PrefixedDB::js_write)?;
+    cx.export_function("prefixed_db_iterate", PrefixedDB::js_iterate)?;
 
     cx.export_function("state_db_reader_new", reader_db::Reader::js_new)?;
     cx.export_function("state_db_reader_close", reader_db::Reader::js_close)?;
     cx.export_function("state_db_reader_get", reader_db::Reader::js_get)?;
     cx.export_function("state_db_reader_exists", reader_db::Reader::js_exists)?;
+    cx.export_function("state_db_reader_get_many", reader_db::Reader::js_get_many)?;
+    let reader_exists_many = reader_db::Reader::js_exists_many;
+    cx.export_function("state_db_reader_exists_many", reader_exists_many)?;
     cx.export_function("state_db_reader_iterate", reader_db::Reader::js_iterate)?;
+    let reader_count_range = reader_db::Reader::js_count_range;
+    cx.export_function("state_db_reader_count_range", reader_count_range)?;
+    cx.export_function("state_db_reader_prove", reader_db::Reader::js_prove)?;
+    let reader_exists_prefix = reader_db::Reader::js_exists_prefix;
+    cx.export_function("state_db_reader_exists_prefix", reader_exists_prefix)?;
 
     cx.export_function("state_db_read_writer_new", ReadWriter::js_new)?;
     cx.export_function("state_db_read_writer_close", ReadWriter::js_close)?;
     cx.export_function("state_db_read_writer_upsert_key", ReadWriter::js_upsert_key)?;
     cx.export_function("state_db_read_writer_get_key", ReadWriter::js_get_key)?;
+    let read_writer_get_key_peek = ReadWriter::js_get_key_peek;
+    cx.export_function(
+        "state_db_read_writer_get_key_peek",
+        read_writer_get_key_peek,
+    )?;
     cx.export_function("state_db_read_writer_delete", ReadWriter::js_delete_key)?;
+    cx.export_function("state_db_read_writer_increment", ReadWriter::js_increment)?;
     cx.export_function("state_db_read_writer_range", ReadWriter::js_range)?;
+    let read_writer_range_pending = ReadWriter::js_range_pending;
+    cx.export_function(
+        "state_db_read_writer_range_pending",
+        read_writer_range_pending,
+    )?;
+    cx.export_function("state_db_read_writer_get_many", ReadWriter::js_get_many)?;
+    let read_writer_exists_many = ReadWriter::js_exists_many;
+    cx.export_function("state_db_read_writer_exists_many", read_writer_exists_many)?;
+    let read_writer_count_range = ReadWriter::js_count_range_with_writer;
+    cx.export_function("state_db_read_writer_count_range", read_writer_count_range)?;
+    let read_writer_exists_prefix = ReadWriter::js_exists_prefix;
+    cx.export_function(
+        "state_db_read_writer_exists_prefix",
+        read_writer_exists_prefix,
+    )?;
+    let read_writer_delete_range = ReadWriter::js_delete_range;
+    cx.export_function(
+        "state_db_read_writer_delete_range",
+        read_writer_delete_range,
+    )?;
+    let read_writer_range_open = RangeCursorHandle::js_open;
+    cx.export_function("state_db_read_writer_range_open", read_writer_range_open)?;
+    let read_writer_range_next = RangeCursorHandle::js_next;
+    cx.export_function("state_db_read_writer_range_next", read_writer_range_next)?;
+    let read_writer_range_close = RangeCursorHandle::js_close;
+    cx.export_function("state_db_read_writer_range_close", read_writer_range_close)?;
 
     cx.export_function("batch_new", WriteBatch::js_new_with_arc_mutex::<WriteBatch>)?;
     cx.export_function("batch_set", WriteBatch::js_set)?;
     cx.export_function("batch_del", WriteBatch::js_del)?;
+    cx.export_function("batch_reset", WriteBatch::js_reset)?;
+    cx.export_function("batch_close", WriteBatch::js_close)?;
 
     let state_db_new = StateDB::js_new_with_box_ref::<DbOptions, StateDB>;
     cx.export_function("state_db_new", state_db_new)?;
+    cx.export_function("state_db_migrate", StateDB::js_migrate)?;
     cx.export_function("state_db_get_current_state", StateDB::js_get_current_state)?;
     cx.export_function("state_db_close", StateDB::js_close)?;
     cx.export_function("state_db_get", StateDB::js_get)?;
     cx.export_function("state_db_exists", StateDB::js_exists)?;
     cx.export_function("state_db_iterate", StateDB::js_iterate)?;
+    cx.export_function("state_db_iterate_kind", StateDB::js_iterate_kind)?;
     cx.export_function("state_db_revert", StateDB::js_revert)?;
     cx.export_function("state_db_commit", StateDB::js_commit)?;
+    cx.export_function("state_db_commit_cancel", StateDB::js_commit_cancel)?;
+    cx.export_function("state_db_compute_root", StateDB::js_compute_root)?;
+    cx.export_function("state_db_write", StateDB::js_write)?;
     cx.export_function("state_db_prove", StateDB::js_prove)?;
+    cx.export_function("state_db_prove_pending", StateDB::js_prove_pending)?;
     cx.export_function("state_db_verify", StateDB::js_verify)?;
     cx.export_function("state_db_clean_diff_until", StateDB::js_clean_diff_until)?;
+    cx.export_function("state_db_set_bulk_load", StateDB::js_set_bulk_load)?;
+    cx.export_function("state_db_get_root_at", StateDB::js_get_root_at)?;
+    let state_db_get_latest_heights = StateDB::js_get_latest_heights;
+    cx.export_function("state_db_get_latest_heights", state_db_get_latest_heights)?;
+    let state_db_pending_operations = StateDB::js_pending_operations;
+    cx.export_function("state_db_pending_operations", state_db_pending_operations)?;
+    cx.export_function("state_db_check_diffs", StateDB::js_check_diffs)?;
+    cx.export_function("state_db_diff_stats", StateDB::js_diff_stats)?;
+    let state_db_check_consistency = StateDB::js_check_consistency;
+    cx.export_function("state_db_check_consistency", state_db_check_consistency)?;
     cx.export_function("state_db_checkpoint", StateDB::js_checkpoint)?;
+    cx.export_function("state_db_verify_checkpoint", StateDB::js_verify_checkpoint)?;
+    cx.export_function(
+        "state_db_checkpoint_with_group",
+        StateDB::js_checkpoint_with_group,
+    )?;
     cx.export_function("state_db_calculate_root", StateDB::js_calculate_root)?;
+    cx.export_function("state_db_compact", StateDB::js_compact)?;
+    cx.export_function("state_db_flush", StateDB::js_flush)?;
+    cx.export_function("state_db_stats", StateDB::js_stats)?;
+    cx.export_function("state_db_approximate_size", StateDB::js_approximate_size)?;
+    cx.export_function("state_db_approximate_sizes", StateDB::js_approximate_sizes)?;
+    cx.export_function("state_db_bloom_stats", StateDB::js_bloom_stats)?;
+    let negative_cache_stats = StateDB::js_negative_cache_stats;
+    cx.export_function("state_db_negative_cache_stats", negative_cache_stats)?;
+    cx.export_function("state_db_key_constants", StateDB::js_key_constants)?;
+    cx.export_function("state_db_list_readers", StateDB::js_list_readers)?;
+    cx.export_function("state_db_get_metrics", StateDB::js_get_metrics)?;
+    cx.export_function("state_db_profile_range", StateDB::js_profile_range)?;
+    cx.export_function("state_db_verify_nodes", StateDB::js_verify_nodes)?;
+    cx.export_function("state_db_check_integrity", StateDB::js_check_integrity)?;
+    cx.export_function(
+        "state_db_check_integrity_cancel",
+        StateDB::js_check_integrity_cancel,
+    )?;
+    cx.export_function("state_db_prune_smt", StateDB::js_prune_smt)?;
+    cx.export_function("state_db_rebuild_smt", StateDB::js_rebuild_smt)?;
+    cx.export_function("state_db_get_smt_node", StateDB::js_get_smt_node)?;
+    cx.export_function("state_db_get_smt_children", StateDB::js_get_smt_children)?;
+    cx.export_function("state_db_put_smt_nodes", StateDB::js_put_smt_nodes)?;
+    cx.export_function("state_db_finalize_sync", StateDB::js_finalize_sync)?;
+    cx.export_function("state_db_export_snapshot", StateDB::js_export_snapshot)?;
+    cx.export_function("state_db_import_snapshot", StateDB::js_import_snapshot)?;
 
-    let state_writer_new = StateWriter::js_new_with_arc_mutex::<StateWriter>;
     let restore_snapshot = StateWriter::js_restore_snapshot;
-    cx.export_function("state_writer_new", state_writer_new)?;
+    cx.export_function("state_writer_new", StateWriter::js_new)?;
     cx.export_function("state_writer_close", StateWriter::js_close)?;
     cx.export_function("state_writer_snapshot", StateWriter::js_snapshot)?;
     cx.export_function("state_writer_restore_snapshot", restore_snapshot)?;
+    let release_snapshot = StateWriter::js_release_snapshot;
+    cx.export_function("state_writer_release_snapshot", release_snapshot)?;
+    cx.export_function("state_writer_reset", StateWriter::js_reset)?;
+    cx.export_function("state_writer_size", StateWriter::js_size)?;
 
     cx.export_function("in_memory_db_new", in_memory_db::Database::js_new)?;
     cx.export_function("in_memory_db_clone", in_memory_db::Database::js_clone)?;
@@ -91,16 +242,52 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("in_memory_db_clear", in_memory_db::Database::js_clear)?;
     cx.export_function("in_memory_db_write", in_memory_db::Database::js_write)?;
     cx.export_function("in_memory_db_iterate", in_memory_db::Database::js_iterate)?;
+    cx.export_function("in_memory_db_snapshot", in_memory_db::Database::js_snapshot)?;
+    let in_memory_db_restore_snapshot = in_memory_db::Database::js_restore_snapshot;
+    cx.export_function(
+        "in_memory_db_restore_snapshot",
+        in_memory_db_restore_snapshot,
+    )?;
+    let in_memory_db_snapshot_to_file = in_memory_db::Database::js_snapshot_to_file;
+    let in_memory_db_load_from_file = in_memory_db::Database::js_load_from_file;
+    cx.export_function(
+        "in_memory_db_snapshot_to_file",
+        in_memory_db_snapshot_to_file,
+    )?;
+    cx.export_function("in_memory_db_load_from_file", in_memory_db_load_from_file)?;
+
+    cx.export_function("in_memory_state_db_new", InMemoryStateDB::js_new)?;
+    cx.export_function("in_memory_state_db_commit", InMemoryStateDB::js_commit)?;
+    cx.export_function("in_memory_state_db_revert", InMemoryStateDB::js_revert)?;
+    cx.export_function("in_memory_state_db_prove", InMemoryStateDB::js_prove)?;
+    cx.export_function("in_memory_state_db_verify", InMemoryStateDB::js_verify)?;
+    cx.export_function("in_memory_state_db_get", InMemoryStateDB::js_get)?;
+    cx.export_function("in_memory_state_db_iterate", InMemoryStateDB::js_iterate)?;
 
-    let in_memory_smt_new = InMemorySMT::js_new_with_arc_mutex::<InMemorySMT>;
     let in_memory_smt_calculate_root = InMemorySMT::js_calculate_root;
+    let in_memory_smt_calculate_root_from_data = InMemorySMT::js_calculate_root_from_data;
     let remove_keys_proof = InMemorySMT::js_remove_keys_from_proof;
-    cx.export_function("in_memory_smt_new", in_memory_smt_new)?;
+    cx.export_function("in_memory_smt_new", InMemorySMT::js_new)?;
     cx.export_function("in_memory_smt_update", InMemorySMT::js_update)?;
     cx.export_function("in_memory_smt_prove", InMemorySMT::js_prove)?;
     cx.export_function("in_memory_smt_verify", InMemorySMT::js_verify)?;
+    cx.export_function("in_memory_smt_verify_batch", InMemorySMT::js_verify_batch)?;
     cx.export_function("in_memory_smt_calculate_root", in_memory_smt_calculate_root)?;
+    cx.export_function(
+        "in_memory_smt_calculate_root_from_data",
+        in_memory_smt_calculate_root_from_data,
+    )?;
     cx.export_function("in_memory_smt_remove_keys_from_proof", remove_keys_proof)?;
+    cx.export_function("in_memory_smt_export_nodes", InMemorySMT::js_export_nodes)?;
+    cx.export_function("in_memory_smt_import_nodes", InMemorySMT::js_import_nodes)?;
+    cx.export_function("in_memory_smt_run_vectors", InMemorySMT::js_run_vectors)?;
+
+    cx.export_function("shutdown_all", registry::js_shutdown_all)?;
+    cx.export_function("key_encode", key_layout::js_key_encode)?;
+    cx.export_function("key_decode", key_layout::js_key_decode)?;
+    cx.export_function("key_constants", key_layout::js_key_constants)?;
+    cx.export_function("checkpoint_group_new", checkpoint_group::js_new)?;
+    cx.export_function("checkpoint_group_verify", checkpoint_group::js_verify)?;
 
     Ok(())
 }
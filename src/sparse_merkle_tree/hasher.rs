@@ -0,0 +1,164 @@
+/// hasher provides the hash functions of the tree nodes, so that the tree can follow the spec of the other chains.
+/// Sha256 of [LIP-0039](https://github.com/LiskHQ/lips/blob/main/proposals/lip-0039.md) is the default,
+/// and the other hash functions are enabled with the cargo features.
+use sha2::{Digest, Sha256};
+
+use crate::consts::{PREFIX_BRANCH_HASH, PREFIX_LEAF_HASH};
+use crate::sparse_merkle_tree::smt::EMPTY_HASH;
+
+/// TreeHasher hashes the nodes of the tree.
+/// The hash must be 32 bytes, which is the size of the node hash in the encoded subtree.
+pub trait TreeHasher {
+    /// digest returns the hash of the concatenated data.
+    fn digest(&self, data: &[&[u8]]) -> Vec<u8>;
+
+    /// hash_leaf returns the hash of the leaf node with the key and the value hash.
+    fn hash_leaf(&self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        self.digest(&[PREFIX_LEAF_HASH, key, value])
+    }
+
+    /// hash_branch returns the hash of the branch node with the hashes of the children.
+    fn hash_branch(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.digest(&[PREFIX_BRANCH_HASH, left, right])
+    }
+
+    /// empty_hash returns the hash of the empty node at the height.
+    /// The tree collapses the empty subtrees to a single empty node, so it must be the same at all the heights.
+    fn empty_hash(&self, _height: usize) -> Vec<u8> {
+        self.digest(&[])
+    }
+
+    /// is_empty_hash returns true if the hash is the hash of the empty node.
+    fn is_empty_hash(&self, hash: &[u8]) -> bool {
+        hash == self.empty_hash(0).as_slice()
+    }
+}
+
+/// Sha256Hasher is the hasher of LIP-0039.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256Hasher;
+
+impl TreeHasher for Sha256Hasher {
+    fn digest(&self, data: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for chunk in data {
+            hasher.update(chunk);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    fn empty_hash(&self, _height: usize) -> Vec<u8> {
+        EMPTY_HASH.to_vec()
+    }
+}
+
+/// Blake3Hasher uses BLAKE3 with the same domain separation of the nodes as LIP-0039.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl TreeHasher for Blake3Hasher {
+    fn digest(&self, data: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new();
+        for chunk in data {
+            hasher.update(chunk);
+        }
+        hasher.finalize().as_bytes().to_vec()
+    }
+}
+
+/// HasherKind selects the hasher of the tree. It is given by the name from JS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HasherKind {
+    #[default]
+    Sha256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl HasherKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    /// from_name returns the hasher of the name. None if the name is unknown or the feature of the hasher is not enabled.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Self::Sha256),
+            #[cfg(feature = "blake3")]
+            "blake3" => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+}
+
+impl TreeHasher for HasherKind {
+    fn digest(&self, data: &[&[u8]]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256Hasher.digest(data),
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => Blake3Hasher.digest(data),
+        }
+    }
+
+    fn empty_hash(&self, height: usize) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256Hasher.empty_hash(height),
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => Blake3Hasher.empty_hash(height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HashKind, HashWithKind};
+
+    #[test]
+    fn test_sha256_hasher() {
+        let left = vec![1; 32];
+        let right = vec![2; 32];
+        assert_eq!(
+            HasherKind::Sha256.hash_branch(&left, &right),
+            [left.as_slice(), right.as_slice()]
+                .concat()
+                .hash_with_kind(HashKind::Branch)
+        );
+        assert_eq!(HasherKind::Sha256.empty_hash(0), Sha256Hasher.digest(&[]));
+        assert!(HasherKind::Sha256.is_empty_hash(&EMPTY_HASH));
+    }
+
+    #[test]
+    fn test_hasher_kind_from_name() {
+        assert_eq!(HasherKind::from_name("sha256"), Some(HasherKind::Sha256));
+        assert_eq!(HasherKind::from_name("keccak"), None);
+        assert_eq!(
+            HasherKind::from_name(HasherKind::default().name()),
+            Some(HasherKind::Sha256)
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(HasherKind::from_name("blake3"), Some(HasherKind::Blake3));
+        #[cfg(not(feature = "blake3"))]
+        assert_eq!(HasherKind::from_name("blake3"), None);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_hasher() {
+        // BLAKE3 test vector of the empty input
+        assert_eq!(
+            hex::encode(HasherKind::Blake3.empty_hash(0)),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_ne!(
+            HasherKind::Blake3.hash_leaf(&[1; 32], &[2; 32]),
+            HasherKind::Sha256.hash_leaf(&[1; 32], &[2; 32])
+        );
+    }
+}
@@ -1,21 +1,35 @@
 // in_memory_smt provides in memory SMT computation without a physical storage.
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
 
 use crate::consts;
-use crate::database::traits::{DatabaseKind, JsNewWithArcMutex, NewDBWithKeyLength};
+use crate::database::options;
+use crate::database::traits::{DatabaseKind, NewDBWithKeyLength};
 use crate::database::types::{JsArcMutex, Kind as DBKind};
-use crate::sparse_merkle_tree::smt::{QueryProofWithProof, SMTError};
+use crate::error::{self, DbError};
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt::{
+    validate_key_lengths, NodeEntry, NodeEntryKind, QueryProofWithProof, SMTError, VerifyEntry,
+};
 use crate::sparse_merkle_tree::smt_db;
+use crate::sparse_merkle_tree::test_vectors;
 use crate::sparse_merkle_tree::{Proof, QueryProof, SparseMerkleTree, UpdateData};
 use crate::types::{ArcMutex, Cache, KVPair, KeyLength, NestedVec};
 
 type SharedInMemorySMT = JsArcMutex<InMemorySMT>;
 type DatabaseParameters = (ArcMutex<InMemorySMT>, Vec<u8>, Root<JsFunction>);
-type VerifyParameters = (Vec<u8>, NestedVec, Proof, KeyLength, Root<JsFunction>);
+type VerifyParameters = (
+    Vec<u8>,
+    NestedVec,
+    Proof,
+    KeyLength,
+    HasherKind,
+    Root<JsFunction>,
+);
 type RemovedKeysParameters = (Proof, NestedVec, Root<JsFunction>);
 
 struct JsFunctionContext<'a> {
@@ -25,6 +39,7 @@ struct JsFunctionContext<'a> {
 pub struct InMemorySMT {
     db: smt_db::InMemorySmtDB,
     key_length: KeyLength,
+    hasher: HasherKind,
 }
 
 impl NewDBWithKeyLength for InMemorySMT {
@@ -32,6 +47,7 @@ impl NewDBWithKeyLength for InMemorySMT {
         Self {
             db: smt_db::InMemorySmtDB::default(),
             key_length: len.expect("The key_length should have a value"),
+            hasher: HasherKind::default(),
         }
     }
 }
@@ -42,10 +58,171 @@ impl DatabaseKind for InMemorySMT {
     }
 }
 
-impl JsNewWithArcMutex for InMemorySMT {}
 impl Finalize for InMemorySMT {}
 
+fn node_entries_to_js_array<'a, C: Context<'a>>(
+    ctx: &mut C,
+    entries: &[NodeEntry],
+) -> JsResult<'a, JsArray> {
+    let result = ctx.empty_array();
+    for (i, entry) in entries.iter().enumerate() {
+        let obj = ctx.empty_object();
+        let node_hash = JsBuffer::external(ctx, entry.node_hash.clone());
+        obj.set(ctx, "nodeHash", node_hash)?;
+        let kind = ctx.string(entry.kind.name());
+        obj.set(ctx, "kind", kind)?;
+        let height = ctx.number(entry.height);
+        obj.set(ctx, "height", height)?;
+        if let Some(key) = &entry.key {
+            let key = JsBuffer::external(ctx, key.clone());
+            obj.set(ctx, "key", key)?;
+        }
+        if let Some(value) = &entry.value {
+            let value = JsBuffer::external(ctx, value.clone());
+            obj.set(ctx, "value", value)?;
+        }
+        result.set(ctx, i as u32, obj)?;
+    }
+    Ok(result)
+}
+
+/// proof_from_js_object converts the proof object { siblingHashes, queries } given from JS.
+fn proof_from_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    raw_proof: Handle<JsObject>,
+) -> NeonResult<Proof> {
+    let mut sibling_hashes = NestedVec::new();
+    let raw_sibling_hashes = raw_proof
+        .get::<JsArray, _, _>(ctx, "siblingHashes")?
+        .to_vec(ctx)?;
+    for raw_sibling_hash in raw_sibling_hashes.iter() {
+        let sibling_hash = raw_sibling_hash
+            .downcast_or_throw::<JsTypedArray<u8>, _>(ctx)?
+            .as_slice(ctx)
+            .to_vec();
+        sibling_hashes.push(sibling_hash);
+    }
+    let raw_queries = raw_proof
+        .get::<JsArray, _, _>(ctx, "queries")?
+        .to_vec(ctx)?;
+    let mut queries: Vec<QueryProof> = Vec::with_capacity(raw_queries.len());
+    for key in raw_queries.iter() {
+        let obj = key.downcast_or_throw::<JsObject, _>(ctx)?;
+        let key = obj
+            .get::<JsTypedArray<u8>, _, _>(ctx, "key")?
+            .as_slice(ctx)
+            .to_vec();
+        let value = obj
+            .get::<JsTypedArray<u8>, _, _>(ctx, "value")?
+            .as_slice(ctx)
+            .to_vec();
+        let bitmap = obj
+            .get::<JsTypedArray<u8>, _, _>(ctx, "bitmap")?
+            .as_slice(ctx)
+            .to_vec();
+        queries.push(QueryProof {
+            pair: Arc::new(KVPair::new(&key, &value)),
+            bitmap: Arc::new(bitmap),
+        });
+    }
+
+    Ok(Proof {
+        queries,
+        sibling_hashes,
+    })
+}
+
+/// verify_entry_from_js_value converts the entry { root, proof, queryKeys } of the batch verification given from JS.
+fn verify_entry_from_js_value<'a, C: Context<'a>>(
+    ctx: &mut C,
+    raw_entry: Handle<JsValue>,
+) -> NeonResult<VerifyEntry> {
+    let raw_entry = raw_entry.downcast_or_throw::<JsObject, _>(ctx)?;
+    let root = raw_entry
+        .get::<JsTypedArray<u8>, _, _>(ctx, "root")?
+        .as_slice(ctx)
+        .to_vec();
+    let raw_proof = raw_entry.get::<JsObject, _, _>(ctx, "proof")?;
+    let proof = proof_from_js_object(ctx, raw_proof)?;
+    let raw_query_keys = raw_entry
+        .get::<JsArray, _, _>(ctx, "queryKeys")?
+        .to_vec(ctx)?;
+    let mut query_keys = NestedVec::new();
+    for key in raw_query_keys.iter() {
+        let key = key
+            .downcast_or_throw::<JsTypedArray<u8>, _>(ctx)?
+            .as_slice(ctx)
+            .to_vec();
+        query_keys.push(key);
+    }
+
+    Ok(VerifyEntry {
+        root,
+        query_keys,
+        proof,
+    })
+}
+
 impl JsFunctionContext<'_> {
+    /// get_hasher returns the hasher of the InMemorySMT of "this".
+    fn get_hasher(&mut self) -> NeonResult<HasherKind> {
+        let in_memory_smt = self
+            .context
+            .this()
+            .downcast_or_throw::<SharedInMemorySMT, _>(&mut self.context)?;
+        let hasher = in_memory_smt.borrow().lock().unwrap().hasher;
+        Ok(hasher)
+    }
+
+    fn get_node_entries(&mut self, pos: u8) -> NeonResult<Vec<NodeEntry>> {
+        let input = self
+            .context
+            .argument::<JsArray>(pos.into())?
+            .to_vec(&mut self.context)?;
+        let mut entries = Vec::with_capacity(input.len());
+        for item in input.iter() {
+            let obj = item.downcast_or_throw::<JsObject, _>(&mut self.context)?;
+            let node_hash = obj
+                .get::<JsTypedArray<u8>, _, _>(&mut self.context, "nodeHash")?
+                .as_slice(&self.context)
+                .to_vec();
+            let kind = obj
+                .get::<JsString, _, _>(&mut self.context, "kind")?
+                .value(&mut self.context);
+            let kind = match NodeEntryKind::from_name(&kind) {
+                Some(kind) => kind,
+                None => {
+                    return error::invalid_argument(format!(
+                        "kind must be one of leaf, branch or empty, got {}",
+                        kind
+                    ))
+                    .throw(&mut self.context)
+                },
+            };
+            let height = obj
+                .get::<JsNumber, _, _>(&mut self.context, "height")?
+                .value(&mut self.context);
+            if height < 0.0 || height > u32::MAX as f64 || height.fract() != 0.0 {
+                return error::invalid_argument("height must be a non-negative integer")
+                    .throw(&mut self.context);
+            }
+            let key = obj
+                .get_opt::<JsTypedArray<u8>, _, _>(&mut self.context, "key")?
+                .map(|key| key.as_slice(&self.context).to_vec());
+            let value = obj
+                .get_opt::<JsTypedArray<u8>, _, _>(&mut self.context, "value")?
+                .map(|value| value.as_slice(&self.context).to_vec());
+            entries.push(NodeEntry {
+                node_hash,
+                kind,
+                height: height as u32,
+                key,
+                value,
+            });
+        }
+        Ok(entries)
+    }
+
     fn get_database_parameters(&mut self) -> NeonResult<DatabaseParameters> {
         let in_memory_smt = self
             .context
@@ -67,9 +244,15 @@ impl JsFunctionContext<'_> {
     }
 
     fn get_key_value_pairs(&mut self) -> NeonResult<Cache> {
+        self.get_key_value_pairs_at(1)
+    }
+
+    /// get_key_value_pairs_at reads the array of { key, value } at the position.
+    /// The duplicated key keeps the value of its last occurrence, see UpdateData.
+    fn get_key_value_pairs_at(&mut self, pos: u8) -> NeonResult<Cache> {
         let input = self
             .context
-            .argument::<JsArray>(1)?
+            .argument::<JsArray>(pos.into())?
             .to_vec(&mut self.context)?;
 
         let mut data = Cache::new();
@@ -96,8 +279,12 @@ impl JsFunctionContext<'_> {
             let update_data = UpdateData::new_from(data);
             let mut inner_smt = in_memory_smt.lock().unwrap();
 
-            let mut tree =
-                SparseMerkleTree::new(&state_root, inner_smt.key_length, consts::SUBTREE_HEIGHT);
+            let mut tree = SparseMerkleTree::new_with_hasher(
+                &state_root,
+                inner_smt.key_length,
+                consts::SUBTREE_HEIGHT,
+                inner_smt.hasher,
+            );
 
             let result = tree.commit(&mut inner_smt.db, &update_data);
 
@@ -109,7 +296,7 @@ impl JsFunctionContext<'_> {
                         let buffer = JsBuffer::external(&mut ctx, (**val.lock().unwrap()).clone());
                         vec![ctx.null().upcast(), buffer.upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -143,8 +330,12 @@ impl JsFunctionContext<'_> {
 
         thread::spawn(move || {
             let mut inner_smt = in_memory_smt.lock().unwrap();
-            let mut tree =
-                SparseMerkleTree::new(&state_root, inner_smt.key_length, consts::SUBTREE_HEIGHT);
+            let mut tree = SparseMerkleTree::new_with_hasher(
+                &state_root,
+                inner_smt.key_length,
+                consts::SUBTREE_HEIGHT,
+                inner_smt.hasher,
+            );
 
             let result = tree.prove(&mut inner_smt.db, &data);
 
@@ -175,7 +366,7 @@ impl JsFunctionContext<'_> {
                         }
                         vec![ctx.null().upcast(), obj.upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -188,46 +379,7 @@ impl JsFunctionContext<'_> {
 
     fn get_proof(&mut self, pos: u8) -> NeonResult<Proof> {
         let raw_proof = self.context.argument::<JsObject>(pos.into())?;
-        let mut sibling_hashes = NestedVec::new();
-        let raw_sibling_hashes = raw_proof
-            .get::<JsArray, _, _>(&mut self.context, "siblingHashes")?
-            .to_vec(&mut self.context)?;
-        for raw_sibling_hash in raw_sibling_hashes.iter() {
-            let sibling_hash = raw_sibling_hash
-                .downcast_or_throw::<JsTypedArray<u8>, _>(&mut self.context)?
-                .as_slice(&self.context)
-                .to_vec();
-            sibling_hashes.push(sibling_hash);
-        }
-        let raw_queries = raw_proof
-            .get::<JsArray, _, _>(&mut self.context, "queries")?
-            .to_vec(&mut self.context)?;
-        let mut queries: Vec<QueryProof> = Vec::with_capacity(raw_queries.len());
-        for key in raw_queries.iter() {
-            let obj = key.downcast_or_throw::<JsObject, _>(&mut self.context)?;
-            let key = obj
-                .get::<JsTypedArray<u8>, _, _>(&mut self.context, "key")?
-                .as_slice(&self.context)
-                .to_vec();
-            let value = obj
-                .get::<JsTypedArray<u8>, _, _>(&mut self.context, "value")?
-                .as_slice(&self.context)
-                .to_vec();
-            let bitmap = obj
-                .get::<JsTypedArray<u8>, _, _>(&mut self.context, "bitmap")?
-                .as_slice(&self.context)
-                .to_vec();
-            queries.push(QueryProof {
-                pair: Arc::new(KVPair::new(&key, &value)),
-                bitmap: Arc::new(bitmap),
-            });
-        }
-        let proof = Proof {
-            queries,
-            sibling_hashes,
-        };
-
-        Ok(proof)
+        proof_from_js_object(&mut self.context, raw_proof)
     }
 
     fn get_removed_keys_parameters(&mut self) -> NeonResult<RemovedKeysParameters> {
@@ -275,6 +427,7 @@ impl JsFunctionContext<'_> {
         }
 
         let proof = self.get_proof(2)?;
+        let hasher = self.get_hasher()?;
 
         let key_length = self
             .context
@@ -286,11 +439,38 @@ impl JsFunctionContext<'_> {
             .argument::<JsFunction>(4)?
             .root(&mut self.context);
 
-        Ok((state_root, parsed_query_keys, proof, key_length, callback))
+        Ok((
+            state_root,
+            parsed_query_keys,
+            proof,
+            key_length,
+            hasher,
+            callback,
+        ))
     }
 }
 
 impl InMemorySMT {
+    /// js_new is handler for JS ffi.
+    /// - @params(0) - key length of the tree.
+    /// - @params(1) - name of the hasher of the tree. SHA-256 is used if it is undefined.
+    /// - @returns - InMemorySMT without any node.
+    pub fn js_new(mut ctx: FunctionContext) -> JsResult<SharedInMemorySMT> {
+        let key_length = ctx.argument::<JsNumber>(0)?.value(&mut ctx).into();
+        let hasher = match ctx.argument_opt(1) {
+            Some(name) if !name.is_a::<JsUndefined, _>(&mut ctx) => {
+                let name = name
+                    .downcast_or_throw::<JsString, _>(&mut ctx)?
+                    .value(&mut ctx);
+                options::hasher_from_name(&mut ctx, &name)?
+            },
+            _ => HasherKind::default(),
+        };
+        let mut in_memory_smt = Self::new_db_with_key_length(Some(key_length));
+        in_memory_smt.hasher = hasher;
+        Ok(ctx.boxed(RefCell::new(Arc::new(Mutex::new(in_memory_smt)))))
+    }
+
     /// js_update is handler for JS ffi.
     /// it is the similar to StateDB commit, but it uses in memory database.
     pub fn js_update(ctx: FunctionContext) -> JsResult<JsUndefined> {
@@ -318,13 +498,20 @@ impl InMemorySMT {
     pub fn js_verify(ctx: FunctionContext) -> JsResult<JsUndefined> {
         let mut js_context = JsFunctionContext { context: ctx };
 
-        let (state_root, parsed_query_keys, proof, key_length, callback) =
+        let (state_root, parsed_query_keys, proof, key_length, hasher, callback) =
             js_context.get_verify_parameters()?;
         let channel = js_context.context.channel();
 
         thread::spawn(move || {
-            let result =
-                SparseMerkleTree::verify(&parsed_query_keys, &proof, &state_root, key_length);
+            let result = validate_key_lengths(&parsed_query_keys, key_length).and_then(|_| {
+                SparseMerkleTree::verify_with_hasher(
+                    &parsed_query_keys,
+                    &proof,
+                    &state_root,
+                    key_length,
+                    hasher,
+                )
+            });
 
             channel.send(move |mut ctx| {
                 let callback = callback.into_inner(&mut ctx);
@@ -333,7 +520,7 @@ impl InMemorySMT {
                     Ok(val) => {
                         vec![ctx.null().upcast(), JsBoolean::new(&mut ctx, val).upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -344,12 +531,61 @@ impl InMemorySMT {
         Ok(js_context.context.undefined())
     }
 
-    /// js_calculate_root is handler for JS ffi.
+    /// js_verify_batch is handler for JS ffi.
+    /// It verifies the proofs against the different roots in a single call, split to the worker threads.
+    /// js "this" - InMemorySMT.
+    /// - @params(0) - entries { root: &[u8], proof: Proof, queryKeys: &[u8][] }[].
+    /// - @params(1) - key length of the tree.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool[] in the order of the entries. Malformed entry is false without failing the others.
+    pub fn js_verify_batch(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let hasher = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemorySMT, _>(&mut ctx)?
+            .borrow()
+            .lock()
+            .unwrap()
+            .hasher;
+        let raw_entries = ctx.argument::<JsArray>(0)?.to_vec(&mut ctx)?;
+        let key_length: KeyLength = ctx.argument::<JsNumber>(1)?.value(&mut ctx).into();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let mut entries = Vec::with_capacity(raw_entries.len());
+        for raw_entry in raw_entries {
+            let entry = ctx
+                .try_catch(|ctx| verify_entry_from_js_value(ctx, raw_entry))
+                .ok();
+            entries.push(entry);
+        }
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = SparseMerkleTree::verify_batch_with_hasher(&entries, key_length, hasher);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let verified = ctx.empty_array();
+                for (i, val) in result.into_iter().enumerate() {
+                    let val = ctx.boolean(val);
+                    verified.set(&mut ctx, i as u32, val)?;
+                }
+                let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), verified.upcast()];
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(ctx.undefined())
+    }
+
     /// it calculate and returns the root hash of the in memory database.
     pub fn js_calculate_root(ctx: FunctionContext) -> JsResult<JsUndefined> {
         let mut js_context = JsFunctionContext { context: ctx };
 
         let proof = js_context.get_proof(0)?;
+        let hasher = js_context.get_hasher()?;
         let callback = js_context
             .context
             .argument::<JsFunction>(1)?
@@ -358,15 +594,17 @@ impl InMemorySMT {
 
         thread::spawn(move || {
             let result: Result<Vec<u8>, SMTError> =
-                match SparseMerkleTree::prepare_queries_with_proof_map(&proof) {
+                match SparseMerkleTree::prepare_queries_with_proof_map_with_hasher(&proof, hasher)
+                {
                     Ok(filter_map) => {
                         let mut filtered_proof = filter_map
                             .values()
                             .cloned()
                             .collect::<Vec<QueryProofWithProof>>();
-                        SparseMerkleTree::calculate_root(
+                        SparseMerkleTree::calculate_root_with_hasher(
                             &proof.sibling_hashes,
                             &mut filtered_proof,
+                            hasher,
                         )
                     },
                     Err(err) => Err(err),
@@ -382,7 +620,56 @@ impl InMemorySMT {
                             JsBuffer::external(&mut ctx, val).upcast(),
                         ]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(js_context.context.undefined())
+    }
+
+    /// js_calculate_root_from_data is handler for JS ffi.
+    /// it calculates the root hash of the tree which has exactly the key-value pairs without storing the nodes.
+    pub fn js_calculate_root_from_data(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let mut js_context = JsFunctionContext { context: ctx };
+
+        let in_memory_smt = js_context
+            .context
+            .this()
+            .downcast_or_throw::<SharedInMemorySMT, _>(&mut js_context.context)?;
+        let (key_length, hasher) = {
+            let inner_smt = in_memory_smt.borrow();
+            let inner_smt = inner_smt.lock().unwrap();
+            (inner_smt.key_length, inner_smt.hasher)
+        };
+        let data = js_context.get_key_value_pairs_at(0)?;
+        let callback = js_context
+            .context
+            .argument::<JsFunction>(1)?
+            .root(&mut js_context.context);
+        let channel = js_context.context.channel();
+
+        thread::spawn(move || {
+            let result = SparseMerkleTree::calculate_root_from_data_with_hasher(
+                &UpdateData::new_from(data),
+                key_length,
+                hasher,
+            );
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(val) => {
+                        vec![
+                            ctx.null().upcast(),
+                            JsBuffer::external(&mut ctx, val).upcast(),
+                        ]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -393,18 +680,50 @@ impl InMemorySMT {
         Ok(js_context.context.undefined())
     }
 
+    /// js_run_vectors is handler for JS ffi.
+    /// It does not use "this", as the key length and the hasher are given in the config of the vectors.
+    /// - @params(0) - JSON string of the test vectors in the schema of the lisk-tree fixtures.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - JSON string of the test vectors with the output computed.
+    pub fn js_run_vectors(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let input = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = test_vectors::run_vectors(&input);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(val) => vec![ctx.null().upcast(), ctx.string(val).upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(ctx.undefined())
+    }
+
     pub fn js_remove_keys_from_proof(ctx: FunctionContext) -> JsResult<JsUndefined> {
         let mut js_context = JsFunctionContext { context: ctx };
 
         let (proof, parsed_removed_keys, callback) = js_context.get_removed_keys_parameters()?;
+        let hasher = js_context.get_hasher()?;
         let channel = js_context.context.channel();
         thread::spawn(move || {
-            let result = SparseMerkleTree::remove_keys_from_proof(
+            let result = SparseMerkleTree::remove_keys_from_proof_with_hasher(
                 &proof,
                 &parsed_removed_keys
                     .iter()
                     .map(|x| x.as_slice())
                     .collect::<Vec<_>>(),
+                hasher,
             );
 
             channel.send(move |mut ctx| {
@@ -434,7 +753,120 @@ impl InMemorySMT {
                         }
                         vec![ctx.null().upcast(), obj.upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(js_context.context.undefined())
+    }
+
+    /// js_export_nodes is handler for JS ffi.
+    /// js "this" - InMemorySMT.
+    /// - @params(0) - root of the tree to export.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { nodeHash: &[u8]; kind: "leaf" | "branch" | "empty"; height: u32; key?: &[u8]; value?: &[u8]; }[] in depth-first order.
+    pub fn js_export_nodes(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let mut js_context = JsFunctionContext { context: ctx };
+
+        let in_memory_smt = js_context
+            .context
+            .this()
+            .downcast_or_throw::<SharedInMemorySMT, _>(&mut js_context.context)?;
+        let in_memory_smt = Arc::clone(&in_memory_smt.borrow());
+        let root = js_context
+            .context
+            .argument::<JsTypedArray<u8>>(0)?
+            .as_slice(&js_context.context)
+            .to_vec();
+        let callback = js_context
+            .context
+            .argument::<JsFunction>(1)?
+            .root(&mut js_context.context);
+        let channel = js_context.context.channel();
+
+        thread::spawn(move || {
+            let inner_smt = in_memory_smt.lock().unwrap();
+            let tree = SparseMerkleTree::new_with_hasher(
+                &root,
+                inner_smt.key_length,
+                consts::SUBTREE_HEIGHT,
+                inner_smt.hasher,
+            );
+            let result = tree.export_nodes(&inner_smt.db);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(entries) => {
+                        let entries = node_entries_to_js_array(&mut ctx, &entries)?;
+                        vec![ctx.null().upcast(), entries.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            })
+        });
+
+        Ok(js_context.context.undefined())
+    }
+
+    /// js_import_nodes is handler for JS ffi.
+    /// It rebuilds the tree from the exported nodes, and fails if any of the nodes does not match.
+    /// js "this" - InMemorySMT.
+    /// - @params(0) - exported nodes in the format of the result of js_export_nodes.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - &[u8] root of the imported tree.
+    pub fn js_import_nodes(ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let mut js_context = JsFunctionContext { context: ctx };
+
+        let in_memory_smt = js_context
+            .context
+            .this()
+            .downcast_or_throw::<SharedInMemorySMT, _>(&mut js_context.context)?;
+        let in_memory_smt = Arc::clone(&in_memory_smt.borrow());
+        let entries = js_context.get_node_entries(0)?;
+        let callback = js_context
+            .context
+            .argument::<JsFunction>(1)?
+            .root(&mut js_context.context);
+        let channel = js_context.context.channel();
+
+        thread::spawn(move || {
+            let mut inner_smt = in_memory_smt.lock().unwrap();
+            let key_length = inner_smt.key_length;
+            // nodes are written only if all of them match, so the current trees are kept on error
+            let mut db = smt_db::InMemorySmtDB::default();
+            let result = SparseMerkleTree::import_nodes_with_hasher(
+                &mut db,
+                &entries,
+                key_length,
+                consts::SUBTREE_HEIGHT,
+                inner_smt.hasher,
+            );
+            if result.is_ok() {
+                inner_smt.db.merge(db);
+            }
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(root) => {
+                        vec![
+                            ctx.null().upcast(),
+                            JsBuffer::external(&mut ctx, root).upcast(),
+                        ]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -1,5 +1,10 @@
+pub mod hasher;
+#[cfg(feature = "node")]
 pub mod in_memory_smt;
 pub mod smt;
 pub mod smt_db;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
 
+pub use hasher::{HasherKind, TreeHasher};
 pub use smt::{Proof, QueryProof, QueryProofWithProof, SparseMerkleTree, UpdateData};
@@ -1,19 +1,24 @@
 /// SparseMerkleTree is optimized sparse merkle tree implementation based on [LIP-0039](https://github.com/LiskHQ/lips/blob/main/proposals/lip-0039.md).
 use std::cmp;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
+use std::thread;
 
-use sha2::{Digest, Sha256};
 use thiserror::Error;
 
-use crate::consts::{PREFIX_EMPTY, PREFIX_LEAF_HASH};
-use crate::database::traits::Actions;
+use crate::codec;
+use crate::consts::PREFIX_EMPTY;
+use crate::database::traits::{Actions, NodeReader};
+use crate::sparse_merkle_tree::hasher::{HasherKind, Sha256Hasher, TreeHasher};
 use crate::types::{
     ArcMutex, Cache, Hash256, HashKind, HashWithKind, Height, KVPair, KeyLength, NestedVec,
-    NestedVecOfSlices, SharedKVPair, SharedNestedVec, SharedVec, StructurePosition, SubtreeHeight,
+    NestedVecOfSlices, SharedKVPair, SharedNestedVec, SharedVec, SubtreeHeight, VecOption,
 };
 use crate::utils;
 
+/// MAX_DEFAULT_THREADS is the maximum number of the threads updating the tree by default.
+const MAX_DEFAULT_THREADS: usize = 4;
 /// PREFIX_SUB_TREE_LEAF is for leaf prefix for sub tree.
 const PREFIX_SUB_TREE_LEAF: u8 = 0;
 /// PREFIX_SUB_TREE_BRANCH is for branch prefix for sub tree.
@@ -40,14 +45,46 @@ pub enum SMTError {
     InvalidBitmapLen,
     #[error("Invalid input: `{0}`")]
     InvalidInput(String),
+    #[error("invalid key length: expected {expected}, got {got}")]
+    InvalidKeyLength { expected: usize, got: usize },
     #[error("unknown data not found error `{0}`")]
     NotFound(String),
+    #[error("Value does not match the value hash of the query `{0}`")]
+    InvalidValue(usize),
     #[error("Invalid state root `{0}`")]
     InvalidRoot(String),
+    #[error("Empty value in the state for the key `{0}`")]
+    EmptyValue(String),
     #[error("unknown data store error `{0}`")]
     Unknown(String),
 }
 
+/// check_empty_values returns an error with the first query key which has the inclusion proof of an empty value.
+/// Empty value in the state is illegal, and the leaf with the hash of an empty value is treated as corrupted.
+pub fn check_empty_values(query_keys: &[Vec<u8>], queries: &[QueryProof]) -> Result<(), SMTError> {
+    match query_keys.iter().zip(queries.iter()).find(|(key, query)| {
+        utils::is_bytes_equal(key, query.key()) && utils::is_empty_hash(query.value())
+    }) {
+        Some((key, _)) => Err(SMTError::EmptyValue(hex::encode(key))),
+        None => Ok(()),
+    }
+}
+
+/// validate_key_lengths returns an error with the first key which does not have the key length.
+pub fn validate_key_lengths<K: AsRef<[u8]>>(
+    keys: &[K],
+    key_length: KeyLength,
+) -> Result<(), SMTError> {
+    let expected: usize = key_length.into();
+    match keys.iter().find(|key| key.as_ref().len() != expected) {
+        Some(key) => Err(SMTError::InvalidKeyLength {
+            expected,
+            got: key.as_ref().len(),
+        }),
+        None => Ok(()),
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 enum NodeKind {
     Empty,
@@ -57,12 +94,16 @@ enum NodeKind {
 }
 
 /// UpdateData holds key-value pairs to update the SMT.
+/// Each key is held once. Inserting the key again replaces the value, so that a duplicated key in the input
+/// keeps its last occurrence, and the root does not depend on the iteration order of the pairs.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UpdateData {
     data: Cache,
 }
 
 /// Proof holds SMT proof.
+/// Sibling hashes of the empty nodes are not included, and the bitmap of the query marks them
+/// as [LIP-0039](https://github.com/LiskHQ/lips/blob/main/proposals/lip-0039.md#proof-construction) specifies.
 #[derive(Clone, Debug)]
 pub struct Proof {
     pub sibling_hashes: NestedVec,
@@ -76,6 +117,14 @@ pub struct QueryProof {
     pub bitmap: Arc<Vec<u8>>,
 }
 
+/// VerifyEntry is the proof of the query keys against the root verified by verify_batch.
+#[derive(Clone, Debug)]
+pub struct VerifyEntry {
+    pub root: Vec<u8>,
+    pub query_keys: NestedVec,
+    pub proof: Proof,
+}
+
 #[derive(Clone, Debug)]
 pub struct QueryProofWithProof {
     query_proof: QueryProof,
@@ -86,6 +135,39 @@ pub struct QueryProofWithProof {
     is_removed: bool,
 }
 
+/// NodeEntryKind is the kind of the exported node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeEntryKind {
+    Leaf,
+    Branch,
+    Empty,
+}
+
+/// NodeEntry is a node of the tree exported for the debugging and the audit.
+/// height is the depth of the node from the root, and key and value are set only for the leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeEntry {
+    pub node_hash: Vec<u8>,
+    pub kind: NodeEntryKind,
+    pub height: u32,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// SubtreeCheck is the result of the check of a stored subtree reached from the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubtreeCheck {
+    /// Valid holds the leaves of the subtree as the pairs of the hashed key and the value hash.
+    Valid {
+        node_hash: Vec<u8>,
+        leaves: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+    /// Missing is the subtree which is not stored.
+    Missing(Vec<u8>),
+    /// Mismatched is the subtree whose encoding does not hash to the key, or cannot be decoded.
+    Mismatched(Vec<u8>),
+}
+
 #[derive(Clone, Debug)]
 struct Node {
     kind: NodeKind,
@@ -140,8 +222,19 @@ struct UpdateNodeInfo<'a> {
     length_bins: &'a [u32],
     current_node: SharedNode,
     length_base: u32,
-    height: Height,
-    structure_pos: StructurePosition,
+    /// height is the depth of the root of the subtree in bits.
+    height: usize,
+    /// structure_pos is the depth of the node in the subtree.
+    structure_pos: u8,
+}
+
+/// BinLocator locates the bin of a key in the subtree at a height with the bit operations.
+/// It is computed once per subtree, so that each key is binned with a shift and a mask.
+#[derive(Clone, Copy)]
+struct BinLocator {
+    byte: usize,
+    shift: u8,
+    mask: u8,
 }
 
 struct Bins<'a> {
@@ -155,6 +248,17 @@ struct UpdatedInfo {
     bin_offset: usize,
 }
 
+/// WorkerNodes records the node changes of a thread updating the subtrees in parallel.
+/// The changes are applied to the database in the order of the nodes after all the threads finish,
+/// so that the database is changed the same as the sequential update regardless of the scheduling.
+struct WorkerNodes<'a> {
+    reader: &'a dyn NodeReader,
+    changes: Vec<(Vec<u8>, VecOption)>,
+}
+
+/// WorkerResult is the updated nodes and their structures with the node changes of a thread.
+type WorkerResult = (Vec<SharedNode>, Vec<u8>, Vec<(Vec<u8>, VecOption)>);
+
 /// SparseMerkleTree is optimized sparse merkle tree implementation based on [LIP-0039](https://github.com/LiskHQ/lips/blob/main/proposals/lip-0039.md).
 pub struct SparseMerkleTree {
     root: SharedVec,
@@ -163,6 +267,10 @@ pub struct SparseMerkleTree {
     subtree_height: SubtreeHeight,
     /// height of the sub tree. Increase in the subtree height will increase number of hashes used while it decreases call to the storage.
     max_number_of_nodes: usize,
+    /// hasher hashes the nodes of the tree. All the trees sharing the nodes must use the same hasher.
+    hasher: HasherKind,
+    /// threads is the number of the threads updating the subtrees below the root in parallel on commit.
+    threads: usize,
 }
 
 #[derive(Clone)]
@@ -170,16 +278,48 @@ struct Hasher {
     node_hashes: Vec<Arc<Vec<u8>>>,
     structure: Vec<u8>,
     height: Height,
+    hasher: HasherKind,
+}
+
+impl Actions for WorkerNodes<'_> {
+    fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        self.reader.read_node(key)
+    }
+
+    fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+        self.changes
+            .push((pair.key_as_vec(), Some(pair.value_as_vec())));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.changes.push((key.to_vec(), None));
+        Ok(())
+    }
+}
+
+/// split_by_keys splits the nodes to at most parts contiguous ranges with about the same number of the keys.
+fn split_by_keys(counts: &[usize], parts: usize) -> Vec<Range<usize>> {
+    let total: usize = counts.iter().sum();
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut sum = 0;
+    for (i, count) in counts.iter().enumerate() {
+        sum += count;
+        if chunks.len() + 1 < parts && sum * parts >= total * (chunks.len() + 1) {
+            chunks.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < counts.len() {
+        chunks.push(start..counts.len());
+    }
+    chunks
 }
 
 impl Hash256 for KVPair {
     fn hash(&self) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(PREFIX_LEAF_HASH);
-        hasher.update(self.key());
-        hasher.update(self.value());
-        let result = hasher.finalize();
-        result.to_vec()
+        Sha256Hasher.hash_leaf(self.key(), self.value())
     }
 }
 
@@ -232,6 +372,7 @@ fn calculate_subtree(
     layer_structure: &[u8],
     height: Height,
     tree_map: &mut VecDeque<(Vec<SharedNode>, Vec<u8>)>,
+    hasher: HasherKind,
 ) -> Result<SubTree, SMTError> {
     let mut layer_nodes = layer_nodes.to_vec();
     let mut layer_structure = layer_structure.to_vec();
@@ -260,23 +401,32 @@ fn calculate_subtree(
                 let (nodes, structure) = tree_map.pop_front().ok_or_else(|| {
                     SMTError::Unknown(String::from("Subtree must exist for stub"))
                 })?;
-                return SubTree::from_data(&structure, &nodes);
+                return SubTree::from_data(&structure, &nodes, hasher);
             }
-            return SubTree::from_data(&[0], &next_layer_nodes);
+            return SubTree::from_data(&[0], &next_layer_nodes, hasher);
         }
         layer_nodes = next_layer_nodes;
         layer_structure = next_layer_structure;
         height = height - Height(1);
     }
 
-    SubTree::from_data(&[0], &layer_nodes)
+    SubTree::from_data(&[0], &layer_nodes, hasher)
 }
 
-fn calculate_next_info(info: &mut QueryHashesInfo, next_info: &mut NextQueryHashesInfo, i: usize) {
+fn calculate_next_info(
+    info: &mut QueryHashesInfo,
+    next_info: &mut NextQueryHashesInfo,
+    i: usize,
+    hasher: HasherKind,
+) {
     let layer_node = info.layer_nodes[i].lock().unwrap();
     let layer_node_next = info.layer_nodes[i + 1].lock().unwrap();
 
-    let mut parent_node = Node::new_branch(layer_node.hash.value(), layer_node_next.hash.value());
+    let mut parent_node = Node::new_branch(
+        layer_node.hash.value(),
+        layer_node_next.hash.value(),
+        hasher,
+    );
     parent_node.index = info.extra.max_index + i;
     let parent_node_index = parent_node.index;
     let parent_node_hash = parent_node.hash.value_as_vec();
@@ -314,7 +464,7 @@ fn calculate_next_info(info: &mut QueryHashesInfo, next_info: &mut NextQueryHash
     }
 }
 
-fn calculate_query_hashes(mut info: QueryHashesInfo) {
+fn calculate_query_hashes(mut info: QueryHashesInfo, hasher: HasherKind) {
     let mut is_extra_height_zero = info.extra.height.is_equal_to(0);
     while !is_extra_height_zero {
         let mut next_info = NextQueryHashesInfo::new(info.extra.target_id);
@@ -325,7 +475,7 @@ fn calculate_query_hashes(mut info: QueryHashesInfo) {
                 i += 1;
                 continue;
             }
-            calculate_next_info(&mut info, &mut next_info, i);
+            calculate_next_info(&mut info, &mut next_info, i, hasher);
             i += 2;
         }
         let new_extra = QueryHashesExtraInfo::new(
@@ -366,6 +516,63 @@ fn insert_and_filter_queries(q: QueryProofWithProof, queries: &mut VecDeque<Quer
     }
 }
 
+/// QueryQueue holds the queries in the order of insert_and_filter_queries, sorted by the height in descending order and then by the key.
+/// It is indexed in the ordered map, so that the query is inserted in O(log n) instead of shifting the queue.
+/// The sequence keeps the queries with the same height and key in the insertion order.
+#[derive(Default)]
+struct QueryQueue {
+    queries: BTreeMap<(cmp::Reverse<usize>, Vec<u8>, usize), QueryProofWithProof>,
+    sequence: usize,
+}
+
+impl QueryQueue {
+    /// new creates the queue from the queries sorted by sort_descending.
+    fn new(sorted_queries: &[QueryProofWithProof]) -> Self {
+        let mut queue = Self::default();
+        for query in sorted_queries {
+            queue.push(query.clone());
+        }
+        queue
+    }
+
+    fn push(&mut self, query: QueryProofWithProof) {
+        let index = (
+            cmp::Reverse(query.height()),
+            query.query_proof.key().to_vec(),
+            self.sequence,
+        );
+        self.sequence += 1;
+        self.queries.insert(index, query);
+    }
+
+    fn front(&self) -> Option<&QueryProofWithProof> {
+        self.queries.values().next()
+    }
+
+    fn pop_front(&mut self) -> Option<QueryProofWithProof> {
+        self.queries.pop_first().map(|(_, query)| query)
+    }
+
+    /// insert_and_filter inserts the query unless the query right after it has the same binary path,
+    /// which is the same as insert_and_filter_queries.
+    fn insert_and_filter(&mut self, query: QueryProofWithProof) {
+        let after = (
+            cmp::Reverse(query.height()),
+            query.query_proof.key().to_vec(),
+            usize::MAX,
+        );
+        if let Some((_, next)) = self.queries.range(after..).next() {
+            if utils::array_equal_bool(&query.binary_path(), &next.binary_path()) {
+                return;
+            }
+        }
+        self.push(query);
+    }
+}
+
+/// calculate_sibling_hashes collects the sibling hashes of all the queries in the order of the multi-proof.
+/// The queries sharing the subtrees share the sibling hashes, so that each hash is included once,
+/// and the hash of a node on the path of another query is not included as it is computed by the verifier.
 fn calculate_sibling_hashes(
     query_with_proofs: &mut VecDeque<QueryProofWithProof>,
     ancestor_hashes: &[Vec<u8>],
@@ -374,6 +581,8 @@ fn calculate_sibling_hashes(
     if query_with_proofs.is_empty() {
         return;
     }
+    let ancestor_hashes: HashSet<&[u8]> = ancestor_hashes.iter().map(Vec::as_slice).collect();
+    let mut included: HashSet<Vec<u8>> = sibling_hashes.iter().cloned().collect();
     while !query_with_proofs.is_empty() {
         let mut query = query_with_proofs.pop_front().unwrap();
         if query.is_zero_height() {
@@ -381,8 +590,8 @@ fn calculate_sibling_hashes(
         }
         if query.binary_bitmap[0] {
             let node_hash = query.sibling_hashes.pop().unwrap();
-            if !utils::bytes_in(ancestor_hashes, &node_hash)
-                && !utils::bytes_in(sibling_hashes, &node_hash)
+            if !ancestor_hashes.contains(node_hash.as_slice())
+                && included.insert(node_hash.clone())
             {
                 sibling_hashes.push(node_hash);
             }
@@ -424,11 +633,17 @@ impl rocksdb::WriteBatchIterator for UpdateData {
 }
 
 impl Hasher {
-    fn new(node_hashes: &[Arc<Vec<u8>>], structure: &[u8], height: Height) -> Self {
+    fn new(
+        node_hashes: &[Arc<Vec<u8>>],
+        structure: &[u8],
+        height: Height,
+        hasher: HasherKind,
+    ) -> Self {
         Self {
             node_hashes: node_hashes.to_vec(),
             structure: structure.to_vec(),
             height,
+            hasher,
         }
     }
 
@@ -440,13 +655,10 @@ impl Hasher {
 
             while i < self.node_hashes.len() {
                 if self.structure[i] == self.height.into() {
-                    let branch = [
-                        (*self.node_hashes[i]).as_slice(),
-                        (*self.node_hashes[i + 1]).as_slice(),
-                    ]
-                    .concat();
-                    let hash = branch.hash_with_kind(HashKind::Branch);
-                    next_hashes.push(Arc::new(hash.to_vec()));
+                    let hash = self
+                        .hasher
+                        .hash_branch(&self.node_hashes[i], &self.node_hashes[i + 1]);
+                    next_hashes.push(Arc::new(hash));
                     next_structure.push(self.structure[i] - 1);
                     i += 1;
                 } else {
@@ -469,6 +681,43 @@ impl Hasher {
     }
 }
 
+/// calculate_sorted_root returns the hash of the sub tree at the height holding the sorted keys sharing the same prefix.
+fn calculate_sorted_root(
+    keys: &[&[u8]],
+    values: &[&[u8]],
+    height: usize,
+    hasher: HasherKind,
+) -> Vec<u8> {
+    match keys.len() {
+        0 => hasher.empty_hash(height),
+        1 => hasher.hash_leaf(keys[0], values[0]),
+        _ => {
+            let split = keys.partition_point(|key| !utils::is_bit_set(key, height));
+            let left = calculate_sorted_root(&keys[..split], &values[..split], height + 1, hasher);
+            let right =
+                calculate_sorted_root(&keys[split..], &values[split..], height + 1, hasher);
+            hasher.hash_branch(&left, &right)
+        },
+    }
+}
+
+impl Proof {
+    /// encode returns the proof encoded by lisk-codec with the proof schema of lisk-tree,
+    /// { siblingHashes: bytes[] = 1; queries: { key: bytes = 1; value: bytes = 2; bitmap: bytes = 3 }[] = 2 }.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = codec::Writer::new();
+        writer.write_bytes_slice(1, &self.sibling_hashes);
+        for query in self.queries.iter() {
+            let mut query_writer = codec::Writer::new();
+            query_writer.write_bytes(1, query.key());
+            query_writer.write_bytes(2, query.value());
+            query_writer.write_bytes(3, &query.bitmap);
+            writer.write_bytes(2, query_writer.result());
+        }
+        writer.result().to_vec()
+    }
+}
+
 impl QueryProof {
     #[inline]
     pub fn new_with_binary_bitmap(pair: Arc<KVPair>, binary_bitmap: &[bool]) -> Self {
@@ -508,7 +757,7 @@ impl UpdateData {
         self.data.insert(kv.key_as_vec(), kv.value_as_vec());
     }
 
-    pub fn entries(&self) -> (SharedNestedVec, SharedNestedVec) {
+    pub fn entries(&self) -> (SharedNestedVec<'_>, SharedNestedVec<'_>) {
         let mut kv_pair: Vec<SharedKVPair> =
             self.data.iter().map(|(k, v)| SharedKVPair(k, v)).collect();
         kv_pair.sort_by(|a, b| a.0.cmp(b.0));
@@ -532,11 +781,12 @@ impl QueryProofWithProof {
         binary_bitmap: &[bool],
         ancestor_hashes: &[Vec<u8>],
         sibling_hashes: &[Vec<u8>],
+        hasher: HasherKind,
     ) -> Self {
         let hashed_key = if pair.is_empty_value() {
-            EMPTY_HASH.to_vec()
+            hasher.empty_hash(binary_bitmap.len())
         } else {
-            pair.hash()
+            hasher.hash_leaf(pair.key(), pair.value())
         };
         Self {
             query_proof: QueryProof::new_with_binary_bitmap(pair, binary_bitmap),
@@ -593,6 +843,37 @@ impl QueryProofWithProof {
     }
 }
 
+impl NodeEntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Leaf => "leaf",
+            Self::Branch => "branch",
+            Self::Empty => "empty",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "leaf" => Some(Self::Leaf),
+            "branch" => Some(Self::Branch),
+            "empty" => Some(Self::Empty),
+            _ => None,
+        }
+    }
+}
+
+impl NodeEntry {
+    fn new(node_hash: Vec<u8>, kind: NodeEntryKind, height: u32) -> Self {
+        Self {
+            node_hash,
+            kind,
+            height,
+            key: None,
+            value: None,
+        }
+    }
+}
+
 impl Node {
     fn new_temp() -> Self {
         Self {
@@ -613,10 +894,9 @@ impl Node {
         }
     }
 
-    fn new_branch(left_hash: &[u8], right_hash: &[u8]) -> Self {
-        let combined = [left_hash, right_hash].concat();
+    fn new_branch(left_hash: &[u8], right_hash: &[u8], hasher: HasherKind) -> Self {
         let data = [&[PREFIX_SUB_TREE_BRANCH], left_hash, right_hash].concat();
-        let hashed = combined.hash_with_kind(HashKind::Branch);
+        let hashed = hasher.hash_branch(left_hash, right_hash);
         Self {
             kind: NodeKind::Stub,
             hash: KVPair::new(&data, &hashed),
@@ -625,8 +905,8 @@ impl Node {
         }
     }
 
-    fn new_leaf(pair: &KVPair) -> Self {
-        let h = pair.hash();
+    fn new_leaf(pair: &KVPair, hasher: HasherKind) -> Self {
+        let h = hasher.hash_leaf(pair.key(), pair.value());
         let data = [&[PREFIX_SUB_TREE_LEAF], pair.key(), pair.value()].concat();
         Self {
             kind: NodeKind::Leaf,
@@ -636,11 +916,11 @@ impl Node {
         }
     }
 
-    fn new_empty() -> Self {
+    fn new_empty(hasher: HasherKind) -> Self {
         let data = [PREFIX_EMPTY].concat();
         Self {
             kind: NodeKind::Empty,
-            hash: KVPair::new(&data, &EMPTY_HASH),
+            hash: KVPair::new(&data, &hasher.empty_hash(0)),
             key: vec![],
             index: 0,
         }
@@ -649,7 +929,7 @@ impl Node {
 
 impl SubTree {
     /// new returns decoded SubTree using the encoded data.
-    pub fn new(data: &[u8], key_length: KeyLength) -> Result<Self, SMTError> {
+    pub fn new(data: &[u8], key_length: KeyLength, hasher: HasherKind) -> Result<Self, SMTError> {
         if data.is_empty() {
             return Err(SMTError::InvalidInput(String::from("keys length is zero")));
         }
@@ -669,7 +949,7 @@ impl SubTree {
                         &node_data[idx + [PREFIX_SUB_TREE_LEAF].len() + key_length
                             ..idx + [PREFIX_SUB_TREE_LEAF].len() + key_length + HASH_SIZE],
                     );
-                    let node = Node::new_leaf(&kv);
+                    let node = Node::new_leaf(&kv, hasher);
                     nodes.push(Arc::new(Mutex::new(node)));
                     idx += [PREFIX_SUB_TREE_LEAF].len() + key_length + HASH_SIZE;
                 },
@@ -680,7 +960,7 @@ impl SubTree {
                     idx += [PREFIX_SUB_TREE_BRANCH].len() + HASH_SIZE;
                 },
                 PREFIX_SUB_TREE_EMPTY => {
-                    nodes.push(Arc::new(Mutex::new(Node::new_empty())));
+                    nodes.push(Arc::new(Mutex::new(Node::new_empty(hasher))));
                     idx += PREFIX_EMPTY.len();
                 },
                 _ => {
@@ -691,11 +971,15 @@ impl SubTree {
             }
         }
 
-        SubTree::from_data(structure, &nodes)
+        SubTree::from_data(structure, &nodes, hasher)
     }
 
     /// from_data creates SubTree from structure and nodes information.
-    pub fn from_data(structure: &[u8], nodes: &[SharedNode]) -> Result<Self, SMTError> {
+    pub fn from_data(
+        structure: &[u8],
+        nodes: &[SharedNode],
+        hasher: HasherKind,
+    ) -> Result<Self, SMTError> {
         let height: Height = structure
             .iter()
             .max()
@@ -706,8 +990,7 @@ impl SubTree {
             .iter()
             .map(|n| Arc::new(n.lock().unwrap().hash.value_as_vec()))
             .collect::<Vec<Arc<Vec<u8>>>>();
-        let mut hasher = Hasher::new(&node_hashes, structure, height);
-        let calculated = hasher.execute();
+        let calculated = Hasher::new(&node_hashes, structure, height, hasher).execute();
 
         Ok(Self {
             structure: structure.to_vec(),
@@ -717,10 +1000,10 @@ impl SubTree {
     }
 
     /// new_empty returns empty SubTree.
-    pub fn new_empty() -> Self {
+    pub fn new_empty(hasher: HasherKind) -> Self {
         let structure = vec![0];
-        let empty = Node::new_empty();
-        let node_hashes = vec![Arc::new(Mutex::new(Node::new_empty()))];
+        let empty = Node::new_empty(hasher);
+        let node_hashes = vec![Arc::new(Mutex::new(Node::new_empty(hasher)))];
 
         Self {
             structure,
@@ -746,6 +1029,96 @@ impl SubTree {
     }
 }
 
+/// calculate_node_hash recomputes the hash of the encoded SubTree from its leaf and branch data.
+/// Unlike SubTree::new, it does not trust the encoding, and malformed data is returned as an error.
+pub fn calculate_node_hash(data: &[u8], key_length: KeyLength) -> Result<Vec<u8>, SMTError> {
+    calculate_node_hash_with_hasher(data, key_length, HasherKind::Sha256)
+}
+
+/// calculate_node_hash_with_hasher is calculate_node_hash of the tree hashed by the hasher.
+pub fn calculate_node_hash_with_hasher(
+    data: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<Vec<u8>, SMTError> {
+    let invalid = || SMTError::InvalidInput(String::from("Invalid encoded node"));
+    let (count, rest) = data.split_first().ok_or_else(invalid)?;
+    let node_length = *count as usize + 1;
+    if rest.len() < node_length {
+        return Err(invalid());
+    }
+    let (structure, mut node_data) = rest.split_at(node_length);
+    let key_length: usize = key_length.into();
+    let leaf_length = [PREFIX_SUB_TREE_LEAF].len() + key_length + HASH_SIZE;
+    let branch_length = [PREFIX_SUB_TREE_BRANCH].len() + HASH_SIZE;
+    // pending nodes with the height, adjacent nodes at the same height are merged into the parent
+    let mut stack: Vec<(Vec<u8>, u8)> = Vec::with_capacity(structure.len());
+    for height in structure {
+        let hash = match node_data.first() {
+            Some(&PREFIX_SUB_TREE_LEAF) if node_data.len() >= leaf_length => {
+                let kv = KVPair::new(
+                    &node_data[[PREFIX_SUB_TREE_LEAF].len()..leaf_length - HASH_SIZE],
+                    &node_data[leaf_length - HASH_SIZE..leaf_length],
+                );
+                node_data = &node_data[leaf_length..];
+                hasher.hash_leaf(kv.key(), kv.value())
+            },
+            Some(&PREFIX_SUB_TREE_BRANCH) if node_data.len() >= branch_length => {
+                let hash = node_data[[PREFIX_SUB_TREE_BRANCH].len()..branch_length].to_vec();
+                node_data = &node_data[branch_length..];
+                hash
+            },
+            Some(&PREFIX_SUB_TREE_EMPTY) => {
+                node_data = &node_data[PREFIX_EMPTY.len()..];
+                hasher.empty_hash(*height as usize)
+            },
+            _ => return Err(invalid()),
+        };
+        stack.push((hash, *height));
+        while stack.len() >= 2 {
+            let (right, right_height) = &stack[stack.len() - 1];
+            let (left, left_height) = &stack[stack.len() - 2];
+            if left_height != right_height || *right_height == 0 {
+                break;
+            }
+            let parent = hasher.hash_branch(left, right);
+            let parent_height = right_height - 1;
+            stack.truncate(stack.len() - 2);
+            stack.push((parent, parent_height));
+        }
+    }
+    match stack.as_slice() {
+        [(hash, 0)] if node_data.is_empty() => Ok(hash.clone()),
+        _ => Err(invalid()),
+    }
+}
+
+/// child_node_hashes returns the hashes of the child subtrees referenced by the encoded SubTree, in the order of the nodes.
+/// The encoding is checked as calculate_node_hash, and malformed data is returned as an error.
+pub fn child_node_hashes(data: &[u8], key_length: KeyLength) -> Result<Vec<Vec<u8>>, SMTError> {
+    child_node_hashes_with_hasher(data, key_length, HasherKind::Sha256)
+}
+
+/// child_node_hashes_with_hasher is child_node_hashes of the tree hashed by the hasher.
+pub fn child_node_hashes_with_hasher(
+    data: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<Vec<Vec<u8>>, SMTError> {
+    calculate_node_hash_with_hasher(data, key_length, hasher)?;
+    let subtree = SubTree::new(data, key_length, hasher)?;
+    let hashes = subtree
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let node = node.lock().unwrap();
+            // stub is the root of the child subtree
+            (node.kind == NodeKind::Stub).then(|| node.hash.value_as_vec())
+        })
+        .collect();
+    Ok(hashes)
+}
+
 impl QueryHashesExtraInfo {
     fn new(height: Height, target_id: usize, max_index: usize) -> Self {
         Self {
@@ -798,8 +1171,8 @@ impl<'a> UpdateNodeInfo<'a> {
         length_bins: &'a [u32],
         current_node: SharedNode,
         length_base: u32,
-        height: Height,
-        structure_pos: StructurePosition,
+        height: usize,
+        structure_pos: u8,
     ) -> Self {
         Self {
             key_bins,
@@ -813,6 +1186,35 @@ impl<'a> UpdateNodeInfo<'a> {
     }
 }
 
+impl BinLocator {
+    fn new(height: usize, subtree_height: SubtreeHeight) -> Result<Self, SMTError> {
+        let byte = height >> 3;
+        // when subtree_height is 8, the bin is the byte itself
+        if !subtree_height.is_four() {
+            return Ok(Self {
+                byte,
+                shift: 0,
+                mask: 0xff,
+            });
+        }
+        let shift = match height & 7 {
+            0 => 4,
+            4 => 0,
+            _ => return Err(SMTError::Unknown(String::from("Invalid bin index"))),
+        };
+        Ok(Self {
+            byte,
+            shift,
+            mask: 0x0f,
+        })
+    }
+
+    #[inline]
+    fn index(&self, key: &[u8]) -> usize {
+        ((key[self.byte] >> self.shift) & self.mask) as usize
+    }
+}
+
 impl SparseMerkleTree {
     fn get_proof_queries(&self, query_with_proofs: &[QueryProofWithProof]) -> Vec<QueryProof> {
         let proof_queries: Vec<QueryProof> = query_with_proofs
@@ -848,6 +1250,7 @@ impl SparseMerkleTree {
         proof: &Proof,
         query_keys: &[Vec<u8>],
         key_length: KeyLength,
+        hasher: HasherKind,
     ) -> Result<Vec<QueryProofWithProof>, SMTError> {
         if query_keys.len() != proof.queries.len() {
             return Err(SMTError::InvalidInput(String::from(
@@ -866,7 +1269,7 @@ impl SparseMerkleTree {
             }
 
             let query = &proof.queries[i];
-            if query.bitmap.len() > 0 && query.bitmap[0] == 0 {
+            if !query.bitmap.is_empty() && query.bitmap[0] == 0 {
                 return Err(SMTError::InvalidBitmapLen);
             }
 
@@ -911,6 +1314,7 @@ impl SparseMerkleTree {
                     &binary_bitmap,
                     &[],
                     &[],
+                    hasher,
                 ));
             }
         }
@@ -922,11 +1326,11 @@ impl SparseMerkleTree {
     /// if node_has is empty or equals to the empty hash, it returns empty SubTree.
     fn get_subtree(&self, db: &impl Actions, node_hash: &[u8]) -> Result<SubTree, SMTError> {
         if node_hash.is_empty() {
-            return Ok(SubTree::new_empty());
+            return Ok(SubTree::new_empty(self.hasher));
         }
 
-        if utils::is_empty_hash(node_hash) {
-            return Ok(SubTree::new_empty());
+        if self.hasher.is_empty_hash(node_hash) {
+            return Ok(SubTree::new_empty(self.hasher));
         }
 
         let value = db
@@ -934,59 +1338,102 @@ impl SparseMerkleTree {
             .map_err(|err| SMTError::Unknown(err.to_string()))?
             .ok_or_else(|| SMTError::NotFound(String::from("node_hash does not exist")))?;
 
-        SubTree::new(&value, self.key_length)
+        SubTree::new(&value, self.key_length, self.hasher)
     }
 
     fn calculate_bins<'a>(
         &mut self,
         key_bin: &'a [&'a [u8]],
         value_bin: &'a [&'a [u8]],
-        height: Height,
+        height: usize,
     ) -> Result<Bins<'a>, SMTError> {
         let mut keys: NestedVecOfSlices = vec![vec![]; self.max_number_of_nodes];
         let mut values: NestedVecOfSlices = vec![vec![]; self.max_number_of_nodes];
 
-        let b = height.div_to_usize(8);
-        for i in 0..key_bin.len() {
-            let k = key_bin[i];
-            let v = value_bin[i];
-            let bin_idx = if self.subtree_height.is_four() {
-                match height.mod_to_u8(8) {
-                    0 => Ok(k[b] >> 4),
-                    4 => Ok(k[b] & 15),
-                    _ => Err(SMTError::Unknown(String::from("Invalid bin index"))),
-                }?
-            // when subtree_height is 8
-            } else {
-                k[b]
-            };
-            keys[bin_idx as usize].push(k);
-            values[bin_idx as usize].push(v);
+        let locator = BinLocator::new(height, self.subtree_height)?;
+        for (k, v) in key_bin.iter().zip(value_bin) {
+            let bin_idx = locator.index(k);
+            keys[bin_idx].push(*k);
+            values[bin_idx].push(*v);
         }
 
         Ok(Bins { keys, values })
     }
 
     /// calculate_updated_info computes the update of SMT using key value pairs.
+    /// The nodes of the root subtree are updated in parallel if the tree has the threads and the database has the node reader.
     fn calculate_updated_info<'a>(
         &mut self,
         db: &mut impl Actions,
         current_subtree: &SubTree,
         key_bin: &'a [&'a [u8]],
         value_bin: &'a [&'a [u8]],
-        height: Height,
+        height: usize,
     ) -> Result<UpdatedInfo, SMTError> {
         let bins = self.calculate_bins(key_bin, value_bin, height)?;
-        let mut nodes: Vec<SharedNode> = vec![];
-        let mut structures: Vec<u8> = vec![];
+        let mut ranges = Vec::with_capacity(current_subtree.nodes.len());
         let mut bin_offset = 0;
-        for i in 0..current_subtree.nodes.len() {
-            let pos = current_subtree.structure[i];
-            let current_node = Arc::clone(&current_subtree.nodes[i]);
-            let new_offset = 1 << self.subtree_height.sub_to_usize(pos);
+        for pos in current_subtree
+            .structure
+            .iter()
+            .take(current_subtree.nodes.len())
+        {
+            let new_offset = 1 << self.subtree_height.sub_to_usize(*pos);
+            if bin_offset + new_offset > self.max_number_of_nodes {
+                return Err(SMTError::Unknown(String::from("Invalid structure")));
+            }
+            ranges.push(bin_offset..bin_offset + new_offset);
+            bin_offset += new_offset;
+        }
+
+        let reader = db
+            .node_reader()
+            .filter(|_| height == 0 && self.threads > 1 && ranges.len() > 1);
+        let (nodes, structures) = match reader {
+            Some(reader) => {
+                let (nodes, structures, changes) =
+                    self.update_nodes_in_parallel(reader, current_subtree, &bins, &ranges)?;
+                for (key, value) in changes {
+                    match value {
+                        Some(value) => db.set(&KVPair::new(&key, &value)),
+                        None => db.del(&key),
+                    }
+                    .map_err(|err| SMTError::Unknown(err.to_string()))?;
+                }
+                (nodes, structures)
+            },
+            None => self.update_nodes(
+                db,
+                &current_subtree.structure,
+                &current_subtree.nodes,
+                &bins,
+                &ranges,
+                height,
+            )?,
+        };
+
+        Ok(UpdatedInfo {
+            nodes,
+            structures,
+            bin_offset,
+        })
+    }
 
-            let slice_keys = &bins.keys[bin_offset..bin_offset + new_offset];
-            let slice_values = &bins.values[bin_offset..bin_offset + new_offset];
+    /// update_nodes updates the nodes of the subtree with the keys and values in the bins of the ranges.
+    fn update_nodes(
+        &mut self,
+        db: &mut impl Actions,
+        structure: &[u8],
+        current_nodes: &[SharedNode],
+        bins: &Bins,
+        ranges: &[Range<usize>],
+        height: usize,
+    ) -> Result<(Vec<SharedNode>, Vec<u8>), SMTError> {
+        let mut nodes: Vec<SharedNode> = vec![];
+        let mut structures: Vec<u8> = vec![];
+        for (i, range) in ranges.iter().enumerate() {
+            let slice_keys = &bins.keys[range.clone()];
+            let slice_values = &bins.values[range.clone()];
             let mut sum = 0;
             let base_length: Vec<u32> = slice_keys
                 .iter()
@@ -1000,26 +1447,76 @@ impl SparseMerkleTree {
                 slice_keys,
                 slice_values,
                 &base_length,
-                Arc::clone(&current_node),
+                Arc::clone(&current_nodes[i]),
                 0,
                 height,
-                pos.into(),
+                structure[i],
             );
             let (updated_nodes, heights) = self.update_node(db, info)?;
 
-            for node in updated_nodes {
-                nodes.push(node);
-            }
-
+            nodes.extend(updated_nodes);
             structures.extend(heights);
-            bin_offset += new_offset;
         }
 
-        Ok(UpdatedInfo {
-            nodes,
-            structures,
-            bin_offset,
-        })
+        Ok((nodes, structures))
+    }
+
+    /// update_nodes_in_parallel updates the nodes of the root subtree split to the threads by the number of the keys.
+    /// The subtrees below the nodes are independent, and the results are joined in the order of the nodes,
+    /// so that the nodes and the changes are the same as update_nodes.
+    fn update_nodes_in_parallel(
+        &self,
+        reader: &dyn NodeReader,
+        current_subtree: &SubTree,
+        bins: &Bins,
+        ranges: &[Range<usize>],
+    ) -> Result<WorkerResult, SMTError> {
+        let counts: Vec<usize> = ranges
+            .iter()
+            .map(|range| bins.keys[range.clone()].iter().map(Vec::len).sum())
+            .collect();
+        let chunks = split_by_keys(&counts, self.threads);
+        let results: Vec<Result<WorkerResult, SMTError>> = thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    let mut worker = self.worker();
+                    scope.spawn(move || -> Result<WorkerResult, SMTError> {
+                        let mut db = WorkerNodes {
+                            reader,
+                            changes: vec![],
+                        };
+                        let (nodes, structures) = worker.update_nodes(
+                            &mut db,
+                            &current_subtree.structure[chunk.clone()],
+                            &current_subtree.nodes[chunk.clone()],
+                            bins,
+                            &ranges[chunk],
+                            0,
+                        )?;
+                        Ok((nodes, structures, db.changes))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let mut updated: WorkerResult = (vec![], vec![], vec![]);
+        for result in results {
+            let (nodes, structures, changes) = result?;
+            updated.0.extend(nodes);
+            updated.1.extend(structures);
+            updated.2.extend(changes);
+        }
+        Ok(updated)
+    }
+
+    /// worker returns the tree of the same options updating the subtrees sequentially on a thread.
+    fn worker(&self) -> Self {
+        Self::new_with_hasher(&[], self.key_length, self.subtree_height, self.hasher)
     }
 
     /// update_subtree updates the SubTree based on the keys and values.
@@ -1029,7 +1526,7 @@ impl SparseMerkleTree {
         key_bin: &'a [&'a [u8]],
         value_bin: &'a [&'a [u8]],
         current_subtree: &SubTree,
-        height: Height,
+        height: usize,
     ) -> Result<SubTree, SMTError> {
         if key_bin.is_empty() {
             return Ok(current_subtree.clone());
@@ -1055,6 +1552,7 @@ impl SparseMerkleTree {
             &updated.structures,
             max_structure.into(),
             &mut tree_map,
+            self.hasher,
         )?;
         let value = new_subtree.encode();
         db.set(&KVPair::new(&new_subtree.root, &value))
@@ -1067,7 +1565,7 @@ impl SparseMerkleTree {
     fn update_single_node(
         &self,
         info: &UpdateNodeInfo,
-    ) -> Result<Option<(SharedNode, StructurePosition)>, SMTError> {
+    ) -> Result<Option<(SharedNode, u8)>, SMTError> {
         let idx = info
             .length_bins
             .iter()
@@ -1078,8 +1576,10 @@ impl SparseMerkleTree {
 
         if current_node.kind == NodeKind::Empty {
             if !info.value_bins[idx][0].is_empty() {
-                let new_leaf =
-                    Node::new_leaf(&KVPair::new(info.key_bins[idx][0], info.value_bins[idx][0]));
+                let new_leaf = Node::new_leaf(
+                    &KVPair::new(info.key_bins[idx][0], info.value_bins[idx][0]),
+                    self.hasher,
+                );
                 return Ok(Some((Arc::new(Mutex::new(new_leaf)), info.structure_pos)));
             }
             return Ok(Some((Arc::clone(&info.current_node), info.structure_pos)));
@@ -1089,12 +1589,14 @@ impl SparseMerkleTree {
             && utils::is_bytes_equal(&current_node.key, info.key_bins[idx][0])
         {
             if !info.value_bins[idx][0].is_empty() {
-                let new_leaf =
-                    Node::new_leaf(&KVPair::new(info.key_bins[idx][0], info.value_bins[idx][0]));
+                let new_leaf = Node::new_leaf(
+                    &KVPair::new(info.key_bins[idx][0], info.value_bins[idx][0]),
+                    self.hasher,
+                );
                 return Ok(Some((Arc::new(Mutex::new(new_leaf)), info.structure_pos)));
             }
             return Ok(Some((
-                Arc::new(Mutex::new(Node::new_empty())),
+                Arc::new(Mutex::new(Node::new_empty(self.hasher))),
                 info.structure_pos,
             )));
         }
@@ -1106,7 +1608,7 @@ impl SparseMerkleTree {
         &mut self,
         db: &mut impl Actions,
         info: &UpdateNodeInfo,
-    ) -> Result<(SharedNode, StructurePosition), SMTError> {
+    ) -> Result<(SharedNode, u8), SMTError> {
         let current_node_kind = info.current_node.lock().unwrap().kind.clone();
         let btm_subtree = match current_node_kind {
             NodeKind::Stub => {
@@ -1119,7 +1621,9 @@ impl SparseMerkleTree {
             NodeKind::Empty => {
                 self.get_subtree(db, info.current_node.lock().unwrap().hash.value())?
             },
-            NodeKind::Leaf => SubTree::from_data(&[0], &[Arc::clone(&info.current_node)])?,
+            NodeKind::Leaf => {
+                SubTree::from_data(&[0], &[Arc::clone(&info.current_node)], self.hasher)?
+            },
             _ => {
                 return Err(SMTError::Unknown(String::from("invalid node type")));
             },
@@ -1132,7 +1636,7 @@ impl SparseMerkleTree {
             &info.key_bins[0],
             &info.value_bins[0],
             &btm_subtree,
-            info.height + info.structure_pos.into(),
+            info.height + info.structure_pos as usize,
         )?;
         if new_subtree.nodes.len() == 1 {
             return Ok((Arc::clone(&new_subtree.nodes[0]), info.structure_pos));
@@ -1150,22 +1654,20 @@ impl SparseMerkleTree {
 
         match current_node.kind {
             NodeKind::Empty => Ok((
-                Arc::new(Mutex::new(Node::new_empty())),
-                Arc::new(Mutex::new(Node::new_empty())),
+                Arc::new(Mutex::new(Node::new_empty(self.hasher))),
+                Arc::new(Mutex::new(Node::new_empty(self.hasher))),
             )),
             NodeKind::Leaf => {
-                if utils::is_bit_set(
-                    &current_node.key,
-                    (info.height + info.structure_pos.into()).into(),
-                ) {
+                if utils::is_bit_set(&current_node.key, info.height + info.structure_pos as usize)
+                {
                     Ok((
-                        Arc::new(Mutex::new(Node::new_empty())),
+                        Arc::new(Mutex::new(Node::new_empty(self.hasher))),
                         Arc::clone(&info.current_node),
                     ))
                 } else {
                     Ok((
                         Arc::clone(&info.current_node),
-                        Arc::new(Mutex::new(Node::new_empty())),
+                        Arc::new(Mutex::new(Node::new_empty(self.hasher))),
                     ))
                 }
             },
@@ -1184,19 +1686,19 @@ impl SparseMerkleTree {
         if total_data == 0 {
             return Ok((
                 vec![Arc::clone(&info.current_node)],
-                vec![info.structure_pos.into()],
+                vec![info.structure_pos],
             ));
         }
         // remaining data only has one side. Update the node and complete
         if total_data == 1 {
             if let Some((node, structure)) = self.update_single_node(&info)? {
-                return Ok((vec![node], vec![structure.into()]));
+                return Ok((vec![node], vec![structure]));
             }
         }
 
-        if info.structure_pos == self.subtree_height.into() {
+        if info.structure_pos as u16 == self.subtree_height.u16() {
             let (node, structure) = self.update_same_height(db, &info)?;
-            return Ok((vec![node], vec![structure.into()]));
+            return Ok((vec![node], vec![structure]));
         }
 
         // Update left side of the node recursively
@@ -1209,7 +1711,7 @@ impl SparseMerkleTree {
             left_node,
             info.length_base,
             info.height,
-            info.structure_pos + StructurePosition(1),
+            info.structure_pos + 1,
         );
         let (mut left_nodes, mut left_heights) = self.update_node(db, left_info)?;
         // Update right side of the node recursively
@@ -1220,7 +1722,7 @@ impl SparseMerkleTree {
             right_node,
             info.length_bins[idx - 1],
             info.height,
-            info.structure_pos + StructurePosition(1),
+            info.structure_pos + 1,
         );
         let (right_nodes, right_heights) = self.update_node(db, right_info)?;
 
@@ -1230,18 +1732,9 @@ impl SparseMerkleTree {
         Ok((left_nodes, left_heights))
     }
 
-    fn find_index(&mut self, query_key: &[u8], height: Height) -> Result<u8, SMTError> {
-        let b = height.div_to_usize(8);
-        if self.subtree_height.is_four() {
-            match height.mod_to_u8(8) {
-                0 => Ok(query_key[b] >> 4),
-                4 => Ok(query_key[b] & 15),
-                _ => Err(SMTError::Unknown(String::from("Invalid bin index"))),
-            }
-        // when subtree_height is 8
-        } else {
-            Ok(query_key[b])
-        }
+    fn find_index(&mut self, query_key: &[u8], height: Height) -> Result<usize, SMTError> {
+        let locator = BinLocator::new(height.0 as usize, self.subtree_height)?;
+        Ok(locator.index(query_key))
     }
 
     fn find_current_node(
@@ -1252,7 +1745,7 @@ impl SparseMerkleTree {
     ) -> Result<(SharedNode, Height), SMTError> {
         let mut bin_offset: usize = 0;
         let mut current_node: Option<SharedNode> = None;
-        let bin_idx: usize = self.find_index(query_key, height)?.into();
+        let bin_idx = self.find_index(query_key, height)?;
         let mut h = 0;
         for i in 0..current_subtree.nodes.len() {
             h = current_subtree.structure[i];
@@ -1282,6 +1775,7 @@ impl SparseMerkleTree {
                 d.query_hashes.binary_bitmap,
                 &Vec::from(ancestor_hashes),
                 &(sibling_hashes),
+                self.hasher,
             ));
         }
 
@@ -1298,6 +1792,7 @@ impl SparseMerkleTree {
                 &binary_bitmap,
                 &Vec::from(ancestor_hashes),
                 &sibling_hashes,
+                self.hasher,
             ));
         }
 
@@ -1319,6 +1814,7 @@ impl SparseMerkleTree {
             ]
             .concat(),
             &[sibling_hashes, lower_query_proof.sibling_hashes].concat(),
+            self.hasher,
         ))
     }
 
@@ -1376,7 +1872,7 @@ impl SparseMerkleTree {
             },
             extra,
         );
-        calculate_query_hashes(info);
+        calculate_query_hashes(info, self.hasher);
         let data = GenerateResultData {
             query_key,
             current_node: &current_node.lock().unwrap(),
@@ -1391,17 +1887,17 @@ impl SparseMerkleTree {
         self.calculate_query_proof_from_result(db, &data)
     }
 
-    fn next_query(query: &QueryProofWithProof, sibling_hash: &[u8]) -> QueryProofWithProof {
+    fn next_query(
+        query: &QueryProofWithProof,
+        sibling_hash: &[u8],
+        hasher: HasherKind,
+    ) -> QueryProofWithProof {
         let d = query.binary_key()[query.height() - 1];
         let mut next_query = query.clone();
         if !d {
-            next_query.hash = [query.hash.as_slice(), sibling_hash]
-                .concat()
-                .hash_with_kind(HashKind::Branch);
+            next_query.hash = hasher.hash_branch(&query.hash, sibling_hash);
         } else {
-            next_query.hash = [sibling_hash, query.hash.as_slice()]
-                .concat()
-                .hash_with_kind(HashKind::Branch);
+            next_query.hash = hasher.hash_branch(sibling_hash, &query.hash);
         }
         next_query.slice_bitmap();
 
@@ -1411,8 +1907,9 @@ impl SparseMerkleTree {
     fn is_bitmap_valid(
         sibling: &QueryProofWithProof,
         query: &QueryProofWithProof,
+        hasher: HasherKind,
     ) -> Result<bool, SMTError> {
-        let is_sibling_empty = utils::is_empty_hash(&sibling.hash);
+        let is_sibling_empty = hasher.is_empty_hash(&sibling.hash);
         if (is_sibling_empty && query.binary_bitmap[0])
             || (!is_sibling_empty && !query.binary_bitmap[0])
         {
@@ -1420,7 +1917,7 @@ impl SparseMerkleTree {
                 "bitmap is not consistent with the nodes type",
             )));
         }
-        let is_query_empty = utils::is_empty_hash(&query.hash);
+        let is_query_empty = hasher.is_empty_hash(&query.hash);
         if (is_query_empty && sibling.binary_bitmap[0])
             || (!is_query_empty && !sibling.binary_bitmap[0])
         {
@@ -1495,6 +1992,14 @@ impl SparseMerkleTree {
 
     pub fn prepare_queries_with_proof_map(
         proof: &Proof,
+    ) -> Result<HashMap<Vec<bool>, QueryProofWithProof>, SMTError> {
+        Self::prepare_queries_with_proof_map_with_hasher(proof, HasherKind::Sha256)
+    }
+
+    /// prepare_queries_with_proof_map_with_hasher is prepare_queries_with_proof_map of the tree hashed by the hasher.
+    pub fn prepare_queries_with_proof_map_with_hasher(
+        proof: &Proof,
+        hasher: HasherKind,
     ) -> Result<HashMap<Vec<bool>, QueryProofWithProof>, SMTError> {
         let mut queries_with_proof: HashMap<Vec<bool>, QueryProofWithProof> = HashMap::new();
         for query in &proof.queries {
@@ -1513,6 +2018,7 @@ impl SparseMerkleTree {
                     &binary_bitmap,
                     &[],
                     &[],
+                    hasher,
                 ),
             );
         }
@@ -1524,14 +2030,23 @@ impl SparseMerkleTree {
     pub fn calculate_root(
         sibling_hashes: &[Vec<u8>],
         queries: &mut [QueryProofWithProof],
+    ) -> Result<Vec<u8>, SMTError> {
+        Self::calculate_root_with_hasher(sibling_hashes, queries, HasherKind::Sha256)
+    }
+
+    /// calculate_root_with_hasher is calculate_root of the tree hashed by the hasher.
+    /// The queries must be prepared with the same hasher.
+    pub fn calculate_root_with_hasher(
+        sibling_hashes: &[Vec<u8>],
+        queries: &mut [QueryProofWithProof],
+        hasher: HasherKind,
     ) -> Result<Vec<u8>, SMTError> {
         queries.sort_descending();
 
-        let mut sorted_queries = VecDeque::from(queries.to_vec());
+        let mut sorted_queries = QueryQueue::new(queries);
         let mut next_sibling_hash = 0;
 
-        while !sorted_queries.is_empty() {
-            let query = &sorted_queries.pop_front().unwrap();
+        while let Some(query) = sorted_queries.pop_front() {
             if query.is_zero_height() {
                 if next_sibling_hash != sibling_hashes.len() {
                     return Err(SMTError::InvalidInput(String::from(
@@ -1542,15 +2057,18 @@ impl SparseMerkleTree {
             }
 
             let mut sibling_hash: Vec<u8> = vec![];
-            if !sorted_queries.is_empty() && query.is_sibling_of(&sorted_queries[0]) {
+            if sorted_queries
+                .front()
+                .map_or(false, |next| query.is_sibling_of(next))
+            {
                 let sibling = sorted_queries.pop_front().unwrap();
                 // We are merging two branches.
                 // Check that the bitmap at the merging point is consistent with the nodes type.
-                if Self::is_bitmap_valid(&sibling, query)? {
+                if Self::is_bitmap_valid(&sibling, &query, hasher)? {
                     sibling_hash = sibling.hash;
                 }
             } else if !query.binary_bitmap[0] {
-                sibling_hash = EMPTY_HASH.to_vec();
+                sibling_hash = hasher.empty_hash(query.height());
             } else if query.binary_bitmap[0] {
                 if sibling_hashes.len() == next_sibling_hash {
                     return Err(SMTError::InvalidInput(String::from(
@@ -1560,17 +2078,248 @@ impl SparseMerkleTree {
                 sibling_hash = sibling_hashes[next_sibling_hash].clone();
                 next_sibling_hash += 1;
             }
-            insert_and_filter_queries(Self::next_query(query, &sibling_hash), &mut sorted_queries);
+            sorted_queries.insert_and_filter(Self::next_query(&query, &sibling_hash, hasher));
         }
 
         Ok(vec![])
     }
 
-    /// new creates a new SparseMerkleTree.
+    /// calculate_root_from_data calculates the merkle root of the tree which has exactly the key-value pairs,
+    /// bottom-up from the sorted keys without creating the nodes.
+    /// The root is the same as committing the data to an empty tree; pairs with an empty value are not in the tree.
+    pub fn calculate_root_from_data(
+        data: &UpdateData,
+        key_length: KeyLength,
+    ) -> Result<Vec<u8>, SMTError> {
+        Self::calculate_root_from_data_with_hasher(data, key_length, HasherKind::Sha256)
+    }
+
+    /// calculate_root_from_data_with_hasher is calculate_root_from_data of the tree hashed by the hasher.
+    pub fn calculate_root_from_data_with_hasher(
+        data: &UpdateData,
+        key_length: KeyLength,
+        hasher: HasherKind,
+    ) -> Result<Vec<u8>, SMTError> {
+        let (keys, values) = data.entries();
+        validate_key_lengths(&keys, key_length)?;
+        let (keys, values): (Vec<&[u8]>, Vec<&[u8]>) = keys
+            .into_iter()
+            .zip(values)
+            .filter(|(_, value)| !value.is_empty())
+            .unzip();
+
+        Ok(calculate_sorted_root(&keys, &values, 0, hasher))
+    }
+
+    /// export_nodes returns all the nodes of the tree in depth-first order, where the children come before the parent.
+    /// The order only depends on the tree, so the exports of the same root can be compared.
+    pub fn export_nodes(&self, db: &impl Actions) -> Result<Vec<NodeEntry>, SMTError> {
+        let root = Arc::clone(&self.root.lock().unwrap());
+        let mut entries = vec![];
+        self.export_subtree(db, &root, 0, &mut entries)?;
+        Ok(entries)
+    }
+
+    /// export_subtree appends the nodes of the subtree and its children.
+    /// offset is the depth of the root of the subtree.
+    fn export_subtree(
+        &self,
+        db: &impl Actions,
+        node_hash: &[u8],
+        offset: u32,
+        entries: &mut Vec<NodeEntry>,
+    ) -> Result<(), SMTError> {
+        let subtree = self.get_subtree(db, node_hash)?;
+        // pending nodes with the height, adjacent nodes at the same height are merged into the parent
+        let mut stack: Vec<(Vec<u8>, u8)> = Vec::with_capacity(subtree.structure.len());
+        for (node, height) in subtree.nodes.iter().zip(subtree.structure.iter()) {
+            let (kind, hash, data, key) = {
+                let node = node.lock().unwrap();
+                (
+                    node.kind.clone(),
+                    node.hash.value_as_vec(),
+                    node.hash.key_as_vec(),
+                    node.key.clone(),
+                )
+            };
+            let depth = offset + *height as u32;
+            match kind {
+                NodeKind::Leaf => {
+                    let mut entry = NodeEntry::new(hash.clone(), NodeEntryKind::Leaf, depth);
+                    entry.value = Some(data[[PREFIX_SUB_TREE_LEAF].len() + key.len()..].to_vec());
+                    entry.key = Some(key);
+                    entries.push(entry);
+                },
+                NodeKind::Empty => {
+                    entries.push(NodeEntry::new(hash.clone(), NodeEntryKind::Empty, depth))
+                },
+                // stub is the root of the child subtree
+                _ => self.export_subtree(db, &hash, depth, entries)?,
+            }
+            stack.push((hash, *height));
+            while stack.len() >= 2 {
+                let (right, right_height) = &stack[stack.len() - 1];
+                let (left, left_height) = &stack[stack.len() - 2];
+                if left_height != right_height || *right_height == 0 {
+                    break;
+                }
+                let parent = self.hasher.hash_branch(left, right);
+                let parent_height = right_height - 1;
+                entries.push(NodeEntry::new(
+                    parent.clone(),
+                    NodeEntryKind::Branch,
+                    offset + parent_height as u32,
+                ));
+                stack.truncate(stack.len() - 2);
+                stack.push((parent, parent_height));
+            }
+        }
+        Ok(())
+    }
+
+    /// collect_node_keys inserts the keys of the stored subtrees of the tree into keys.
+    /// Subtrees already in keys are not visited again, so the trees sharing the nodes are walked once.
+    pub fn collect_node_keys(
+        &self,
+        db: &impl Actions,
+        keys: &mut HashSet<Vec<u8>>,
+    ) -> Result<(), SMTError> {
+        let root = Arc::clone(&self.root.lock().unwrap());
+        let mut pending = vec![root.to_vec()];
+        while let Some(node_hash) = pending.pop() {
+            if self.hasher.is_empty_hash(&node_hash) || keys.contains(&node_hash) {
+                continue;
+            }
+            let subtree = self.get_subtree(db, &node_hash)?;
+            keys.insert(node_hash);
+            for node in subtree.nodes.iter() {
+                let node = node.lock().unwrap();
+                // stub is the root of the child subtree
+                if node.kind == NodeKind::Stub {
+                    pending.push(node.hash.value_as_vec());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// check_subtrees walks the stored subtrees reachable from the root, and calls visit with the check of each of them.
+    /// The hash of each subtree is recomputed from its encoding, and the children of the subtree which is missing
+    /// or mismatched are not walked. The walk stops when visit returns false.
+    pub fn check_subtrees(
+        &self,
+        db: &impl Actions,
+        mut visit: impl FnMut(SubtreeCheck) -> bool,
+    ) -> Result<(), SMTError> {
+        let root = Arc::clone(&self.root.lock().unwrap());
+        let mut pending = vec![root.to_vec()];
+        while let Some(node_hash) = pending.pop() {
+            if self.hasher.is_empty_hash(&node_hash) {
+                continue;
+            }
+            let data = match db
+                .get(&node_hash)
+                .map_err(|err| SMTError::Unknown(err.to_string()))?
+            {
+                Some(data) => data,
+                None => {
+                    if !visit(SubtreeCheck::Missing(node_hash)) {
+                        return Ok(());
+                    }
+                    continue;
+                },
+            };
+            let matched = calculate_node_hash_with_hasher(&data, self.key_length, self.hasher)
+                .map(|hash| hash == node_hash)
+                .unwrap_or(false);
+            if !matched {
+                if !visit(SubtreeCheck::Mismatched(node_hash)) {
+                    return Ok(());
+                }
+                continue;
+            }
+            let subtree = SubTree::new(&data, self.key_length, self.hasher)?;
+            let mut leaves = vec![];
+            for node in subtree.nodes.iter() {
+                let node = node.lock().unwrap();
+                match node.kind {
+                    // stub is the root of the child subtree
+                    NodeKind::Stub => pending.push(node.hash.value_as_vec()),
+                    NodeKind::Leaf => {
+                        let value_hash = node.hash.key()
+                            [[PREFIX_SUB_TREE_LEAF].len() + node.key.len()..]
+                            .to_vec();
+                        leaves.push((node.key.clone(), value_hash));
+                    },
+                    _ => {},
+                }
+            }
+            if !visit(SubtreeCheck::Valid { node_hash, leaves }) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// import_nodes rebuilds the tree from the leaves of the exported nodes, and returns the root.
+    /// All the nodes of the rebuilt tree must match with the exported nodes, so that the dump is verified.
+    pub fn import_nodes(
+        db: &mut impl Actions,
+        entries: &[NodeEntry],
+        key_length: KeyLength,
+        subtree_height: SubtreeHeight,
+    ) -> Result<Vec<u8>, SMTError> {
+        Self::import_nodes_with_hasher(db, entries, key_length, subtree_height, HasherKind::Sha256)
+    }
+
+    /// import_nodes_with_hasher is import_nodes of the tree hashed by the hasher.
+    pub fn import_nodes_with_hasher(
+        db: &mut impl Actions,
+        entries: &[NodeEntry],
+        key_length: KeyLength,
+        subtree_height: SubtreeHeight,
+        hasher: HasherKind,
+    ) -> Result<Vec<u8>, SMTError> {
+        let mut data = Cache::new();
+        for entry in entries.iter().filter(|e| e.kind == NodeEntryKind::Leaf) {
+            match (&entry.key, &entry.value) {
+                (Some(key), Some(value)) if !value.is_empty() => {
+                    data.insert(key.clone(), value.clone());
+                },
+                _ => {
+                    return Err(SMTError::InvalidInput(String::from(
+                        "leaf must have the key and the value",
+                    )))
+                },
+            }
+        }
+        let mut tree = SparseMerkleTree::new_with_hasher(&[], key_length, subtree_height, hasher);
+        tree.commit(db, &UpdateData::new_from(data))?;
+        if tree.export_nodes(db)? != entries {
+            return Err(SMTError::InvalidRoot(String::from(
+                "Not matching with the exported nodes",
+            )));
+        }
+        let root = (**tree.root.lock().unwrap()).clone();
+        Ok(root)
+    }
+
+    /// new creates a new SparseMerkleTree hashed by SHA-256 of LIP-0039.
     pub fn new(root: &[u8], key_length: KeyLength, subtree_height: SubtreeHeight) -> Self {
+        Self::new_with_hasher(root, key_length, subtree_height, HasherKind::Sha256)
+    }
+
+    /// new_with_hasher creates a new SparseMerkleTree hashed by the hasher.
+    /// The empty root is the empty hash of the hasher.
+    pub fn new_with_hasher(
+        root: &[u8],
+        key_length: KeyLength,
+        subtree_height: SubtreeHeight,
+        hasher: HasherKind,
+    ) -> Self {
         let max_number_of_nodes = 1 << subtree_height.u16();
         let r = if root.is_empty() {
-            EMPTY_HASH.to_vec()
+            hasher.empty_hash(0)
         } else {
             root.to_vec()
         };
@@ -1579,9 +2328,25 @@ impl SparseMerkleTree {
             key_length,
             subtree_height,
             max_number_of_nodes,
+            hasher,
+            threads: 1,
         }
     }
 
+    /// with_threads sets the number of the threads updating the subtrees below the root in parallel on commit.
+    /// The tree is updated sequentially with 1, or if the database does not provide the node reader.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// default_threads returns the number of the CPUs up to MAX_DEFAULT_THREADS, which is the default number of the threads.
+    pub fn default_threads() -> usize {
+        thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_DEFAULT_THREADS)
+    }
+
     /// commit updates the db with key-value pairs based on [LIP-0039](https://github.com/LiskHQ/lips/blob/main/proposals/lip-0039.md#root-hash-calculation) with SubTree optimization.
     /// Nodes are batched to "SubTree" for defined height N (4 or 8) to reduce DB call with trade-off of # of hashes.
     /// all the keys for the data must be unique and have the same length.
@@ -1595,15 +2360,11 @@ impl SparseMerkleTree {
         }
         let (update_keys, update_values) = data.entries();
         // check if all keys have the same length
-        if !utils::have_all_arrays_same_length(&update_keys, self.key_length.into()) {
-            return Err(SMTError::InvalidInput(String::from(
-                "all keys must have the same length",
-            )));
-        }
+        validate_key_lengths(&update_keys, self.key_length)?;
         // get the root subtree
         let root = self.get_subtree(db, &self.root.lock().unwrap())?;
         // update using the key-value pairs starting from the root (height: 0).
-        let new_root = self.update_subtree(db, &update_keys, &update_values, &root, Height(0))?;
+        let new_root = self.update_subtree(db, &update_keys, &update_values, &root, 0)?;
         self.root = Arc::new(Mutex::new(new_root.root));
         Ok(Arc::clone(&self.root))
     }
@@ -1621,8 +2382,10 @@ impl SparseMerkleTree {
                 sibling_hashes: vec![],
             });
         }
+        validate_key_lengths(queries, self.key_length)?;
         let (mut query_with_proofs, ancestor_hashes) = self.generate_sibling_data(db, queries)?;
         let proof_queries = self.get_proof_queries(&query_with_proofs);
+        check_empty_values(queries, &proof_queries)?;
 
         query_with_proofs.sort_descending();
 
@@ -1648,24 +2411,94 @@ impl SparseMerkleTree {
         root: &[u8],
         key_length: KeyLength,
     ) -> Result<bool, SMTError> {
+        Self::verify_with_hasher(query_keys, proof, root, key_length, HasherKind::Sha256)
+    }
+
+    /// verify_with_hasher is verify of the tree hashed by the hasher.
+    pub fn verify_with_hasher(
+        query_keys: &[Vec<u8>],
+        proof: &Proof,
+        root: &[u8],
+        key_length: KeyLength,
+        hasher: HasherKind,
+    ) -> Result<bool, SMTError> {
+        check_empty_values(query_keys, &proof.queries)?;
         let mut filtered_proof =
-            match Self::verify_and_prepare_proof_map(proof, query_keys, key_length) {
+            match Self::verify_and_prepare_proof_map(proof, query_keys, key_length, hasher) {
                 Ok(v) => v,
                 Err(_) => return Ok(false),
             };
 
-        match SparseMerkleTree::calculate_root(&proof.sibling_hashes, &mut filtered_proof) {
+        match Self::calculate_root_with_hasher(&proof.sibling_hashes, &mut filtered_proof, hasher)
+        {
             Ok(computed_root) => Ok(utils::is_bytes_equal(root, &computed_root)),
             Err(_) => Ok(false),
         }
     }
 
+    /// verify_batch verifies the entries independently, split to the worker threads, and returns the results in the order of the entries.
+    /// None is the entry which could not be decoded. It is false the same as the entry which cannot be verified,
+    /// such as the query keys with the wrong length, so that a malformed entry does not fail the others.
+    pub fn verify_batch(entries: &[Option<VerifyEntry>], key_length: KeyLength) -> Vec<bool> {
+        Self::verify_batch_with_hasher(entries, key_length, HasherKind::Sha256)
+    }
+
+    /// verify_batch_with_hasher is verify_batch of the trees hashed by the hasher.
+    pub fn verify_batch_with_hasher(
+        entries: &[Option<VerifyEntry>],
+        key_length: KeyLength,
+        hasher: HasherKind,
+    ) -> Vec<bool> {
+        if entries.is_empty() {
+            return vec![];
+        }
+        let workers = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(entries.len());
+        let chunk_size = (entries.len() + workers - 1) / workers;
+        let verify_entry = |entry: &Option<VerifyEntry>| match entry {
+            Some(entry) => validate_key_lengths(&entry.query_keys, key_length)
+                .and_then(|_| {
+                    Self::verify_with_hasher(
+                        &entry.query_keys,
+                        &entry.proof,
+                        &entry.root,
+                        key_length,
+                        hasher,
+                    )
+                })
+                .unwrap_or(false),
+            None => false,
+        };
+        thread::scope(|scope| {
+            let handles: Vec<_> = entries
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || chunk.iter().map(verify_entry).collect::<Vec<_>>())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
     // remove_keys_from_proof removes keys from proof and returns a new proof without them.
     pub fn remove_keys_from_proof(
         proof: &Proof,
         removed_keys: &[&[u8]],
     ) -> Result<Proof, SMTError> {
-        let filter_map = Self::prepare_queries_with_proof_map(proof)?;
+        Self::remove_keys_from_proof_with_hasher(proof, removed_keys, HasherKind::Sha256)
+    }
+
+    // remove_keys_from_proof_with_hasher is remove_keys_from_proof of the tree hashed by the hasher.
+    pub fn remove_keys_from_proof_with_hasher(
+        proof: &Proof,
+        removed_keys: &[&[u8]],
+        hasher: HasherKind,
+    ) -> Result<Proof, SMTError> {
+        let filter_map = Self::prepare_queries_with_proof_map_with_hasher(proof, hasher)?;
         let mut filtered_proof = filter_map
             .values()
             .cloned()
@@ -1704,7 +2537,7 @@ impl SparseMerkleTree {
                 let sibling = sorted_queries.pop_front().unwrap();
                 // We are merging two branches.
                 // Check that the bitmap at the merging point is consistent with the nodes type.
-                if Self::is_bitmap_valid(&sibling, query)? {
+                if Self::is_bitmap_valid(&sibling, query, hasher)? {
                     sibling_hash = sibling.hash.clone();
                 }
                 // if the branch is being removed, we need to add the sibling hash to the list of hashes to be added.
@@ -1718,7 +2551,7 @@ impl SparseMerkleTree {
                 }
             } else if !query.binary_bitmap[0] {
                 // #2. sibling hash is a default empty node.
-                sibling_hash = EMPTY_HASH.to_vec();
+                sibling_hash = hasher.empty_hash(query.height());
             } else if query.binary_bitmap[0] {
                 // #3. sibling hash comes from proof.sibling_hashes.
                 if proof.sibling_hashes.len() == next_sibling_hash_index {
@@ -1733,47 +2566,380 @@ impl SparseMerkleTree {
                     removed_sibling_hashes.push(sibling_hash.clone());
                 }
             }
-            insert_and_filter_queries(Self::next_query(query, &sibling_hash), &mut sorted_queries);
+            insert_and_filter_queries(
+                Self::next_query(query, &sibling_hash, hasher),
+                &mut sorted_queries,
+            );
+        }
+
+        Err(SMTError::InvalidInput(String::from("Empty")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+
+    use super::*;
+    use crate::sparse_merkle_tree::smt_db;
+    use crate::types::SubtreeHeightKind;
+
+    #[test]
+    fn test_subtree() {
+        let test_data = vec![
+            ("05030302020303001f930f4f669738b026406a872c24db29238731868957ae1de0e5a68bb0cf7da633e508533a13da9c33fc64eb78b18bd0646c82d6316697dece0aee5a3a92e45700082e6af17a61852d01dfc18e859c20b0b974472bf6169295c36ce1380c2550e16c16babfe7d3204f61852d100f553276ad154921988de3797622091f0581884b008b647996849b70889d2a382d8fa2f42405c3bca57189de0be52c92bbc03f0cd21194ddd776cf387a81d0117b6288e6a724ec14a58cdde3c196292191da360da800ec66ad4b484153de040869f8833a30a8fcde4fdf8fcbd78d33c2fb2182dd8ffa3b311d3a72a9aec8560c56c68d665ad54c5644d40ea4fc7ed914d4eea5da3c0400e93bd78ce150412056a9076cf58977ff1a697b1932abdd52d7b978fce69186d3a9cb7274eceac6b0807ce4db0763dc596cd00e59177172de6b5dd1593b33a78500c8c4673053da259999cbc9502aef75c3c0b84bce42b1d1a2d437df88d32b737bd36e7a6410939ac431914de947353f06bbbfc31c86609ec291ed9e13b665f86a", "d6b9f2888480a4fa33fc1d0e0daaef702f0ab41dd8875eee80b7c312011e5191", vec![3, 3, 2, 2, 3, 3]),
+            ("02010202020049720db77a5ca853713493d4e11926b417af0cae746a305a52f555738eed47cad58c7809f5cf4119cc0f25c224f7124d15b5d62ba93bc3d948db32871026f068018dfe7dfa8fb4a5a268168638c8cce0e26f87a227320aee691f8872ed6a3aba0e", "0989d2ac315f25669f69e9bef067b9c41310964d76565196ac4fb92452c68955", vec![1,2,2]),
+            ("0f0404040404040404040404040404040401bbacc7102a28f2eecd0e4de3c130064e653d0118b1dc4129095901f190e70034019dcb747007aca526d4b0782ed20a88a5d48a4ab6276378bada201ab5b6e4d75b01e89b7270dd0ad80207e11422bfc28f8cda8932d59b1082486fa1bf5626ea0aba01858c61150861b89516244e07cfd9d3ebcb12b2d44c2de4e7e2faed96717202eb01f9437e84b231d85f7fc2690ed54b09e85c2e0fc98b26430f10418065374e40bf0189ae2184c9a2e70656ce37c89c903b258198ad6e9db66f135780f66d8613a6fd01058c3bef2957b130622e752f0a81ee8dcf60b4685675eb88e39d5150c954fe220161543e80c5356f580f8e7e4548576486ee754ffe22f4dd122ef48e41bffc7adc01f55a1089a16835a4cbe8b5e12227575ecfd99cd951e34b409f9b2ace6f25a49701e5dfbf3ecaf909728248a751e1a75f3b626777094fe1aab03ae6f526ddac799a01f88ad8cd4aec6cc4f8d2c2bc4a5f368fc9b877685eb55673baa01d652fa4c82b0182f8fb577797274de4f48d8bd7cc5a77068ea3c60477e8552b38c926466eba1101c149d0c79bc1355d763d01690139fd187a84488d534e7e38e4772279c3826b9b01006afab486675b0e3f9b6b06283da947df6749269fb8621afe843d5df942bce7011ead1b569f80edffa2044bf9d8b8703b970ca741b821127d6da69da83b52294f01c1a9d57b050c3ba96aca78a26c5eebc76bb51acab78ce70ed3bdea1ca9143cd8", "2c76277c959e70205fff49ef8732047516cf07e18758345ca56005e732ca2d17", vec![4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]),
+        ];
+
+        for (data, hash, structure) in test_data {
+            let decoded_data = hex::decode(data).unwrap();
+            let tree = SubTree::new(&decoded_data, KeyLength(32), HasherKind::Sha256).unwrap();
+            let decoded_hash = hex::decode(hash).unwrap();
+            assert_eq!(tree.structure, structure);
+            assert_eq!(*tree.root, decoded_hash);
+        }
+    }
+    #[test]
+    fn test_subtree_encode() {
+        let test_data = vec![
+            ("05030302020303001f930f4f669738b026406a872c24db29238731868957ae1de0e5a68bb0cf7da633e508533a13da9c33fc64eb78b18bd0646c82d6316697dece0aee5a3a92e45700082e6af17a61852d01dfc18e859c20b0b974472bf6169295c36ce1380c2550e16c16babfe7d3204f61852d100f553276ad154921988de3797622091f0581884b008b647996849b70889d2a382d8fa2f42405c3bca57189de0be52c92bbc03f0cd21194ddd776cf387a81d0117b6288e6a724ec14a58cdde3c196292191da360da800ec66ad4b484153de040869f8833a30a8fcde4fdf8fcbd78d33c2fb2182dd8ffa3b311d3a72a9aec8560c56c68d665ad54c5644d40ea4fc7ed914d4eea5da3c0400e93bd78ce150412056a9076cf58977ff1a697b1932abdd52d7b978fce69186d3a9cb7274eceac6b0807ce4db0763dc596cd00e59177172de6b5dd1593b33a78500c8c4673053da259999cbc9502aef75c3c0b84bce42b1d1a2d437df88d32b737bd36e7a6410939ac431914de947353f06bbbfc31c86609ec291ed9e13b665f86a", "7a208dc2a21cb829e5fa4dc7d876bef8e52ddd23ae5ea24c2567b264bcd91a23", vec![3, 3, 2, 2, 3, 3]),
+            ("02010202020049720db77a5ca853713493d4e11926b417af0cae746a305a52f555738eed47cad58c7809f5cf4119cc0f25c224f7124d15b5d62ba93bc3d948db32871026f068018dfe7dfa8fb4a5a268168638c8cce0e26f87a227320aee691f8872ed6a3aba0e", "c0fcf4b2571622905dde0884ef56d494ad3481d28fa167466f970f2c633e2925", vec![1,2,2]),
+            ("0f0404040404040404040404040404040401bbacc7102a28f2eecd0e4de3c130064e653d0118b1dc4129095901f190e70034019dcb747007aca526d4b0782ed20a88a5d48a4ab6276378bada201ab5b6e4d75b01e89b7270dd0ad80207e11422bfc28f8cda8932d59b1082486fa1bf5626ea0aba01858c61150861b89516244e07cfd9d3ebcb12b2d44c2de4e7e2faed96717202eb01f9437e84b231d85f7fc2690ed54b09e85c2e0fc98b26430f10418065374e40bf0189ae2184c9a2e70656ce37c89c903b258198ad6e9db66f135780f66d8613a6fd01058c3bef2957b130622e752f0a81ee8dcf60b4685675eb88e39d5150c954fe220161543e80c5356f580f8e7e4548576486ee754ffe22f4dd122ef48e41bffc7adc01f55a1089a16835a4cbe8b5e12227575ecfd99cd951e34b409f9b2ace6f25a49701e5dfbf3ecaf909728248a751e1a75f3b626777094fe1aab03ae6f526ddac799a01f88ad8cd4aec6cc4f8d2c2bc4a5f368fc9b877685eb55673baa01d652fa4c82b0182f8fb577797274de4f48d8bd7cc5a77068ea3c60477e8552b38c926466eba1101c149d0c79bc1355d763d01690139fd187a84488d534e7e38e4772279c3826b9b01006afab486675b0e3f9b6b06283da947df6749269fb8621afe843d5df942bce7011ead1b569f80edffa2044bf9d8b8703b970ca741b821127d6da69da83b52294f01c1a9d57b050c3ba96aca78a26c5eebc76bb51acab78ce70ed3bdea1ca9143cd8", "5a2f1f740cbea0944d5182fe8ef9190d7a07e8601d0b9fc1137d48b94ce73407", vec![4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]),
+        ];
+
+        for (data, _, _) in test_data {
+            let decoded_data = hex::decode(data).unwrap();
+            let tree = SubTree::new(&decoded_data, KeyLength(32), HasherKind::Sha256).unwrap();
+            assert_eq!(tree.encode(), decoded_data);
+        }
+    }
+
+    #[test]
+    fn test_calculate_root_from_data() {
+        // the seed is fixed, so that a failure is reproduced by running the test again
+        let mut rng = StdRng::seed_from_u64(0);
+        for round in 0..20 {
+            let mut data = Cache::new();
+            for _ in 0..rng.gen_range(0..300) {
+                let mut key = vec![0; 32];
+                rng.fill_bytes(&mut key);
+                // keys sharing the long prefix make the deep branches
+                if round % 2 == 1 {
+                    key[..31].fill(7);
+                }
+                let mut value = vec![0; 32];
+                rng.fill_bytes(&mut value);
+                data.insert(key, value);
+            }
+
+            // incremental path with batches, some keys are deleted in the later batch
+            let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+            let mut db = smt_db::InMemorySmtDB::default();
+            let mut expected = data.clone();
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = data.into_iter().collect();
+            for batch in entries.chunks(rng.gen_range(1..50)) {
+                let mut update = Cache::new();
+                for (key, value) in batch {
+                    update.insert(key.clone(), value.clone());
+                }
+                tree.commit(&mut db, &UpdateData::new_from(update)).unwrap();
+            }
+            let mut deletes = Cache::new();
+            for (key, _) in entries.iter().step_by(3) {
+                deletes.insert(key.clone(), vec![]);
+                expected.remove(key);
+            }
+            let root = tree
+                .commit(&mut db, &UpdateData::new_from(deletes.clone()))
+                .unwrap();
+
+            // deleted keys are not in the tree
+            let mut with_deletes = expected.clone();
+            with_deletes.extend(deletes);
+            for input in [expected, with_deletes] {
+                assert_eq!(
+                    SparseMerkleTree::calculate_root_from_data(
+                        &UpdateData::new_from(input),
+                        KeyLength(32)
+                    )
+                    .unwrap(),
+                    **root.lock().unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bin_locator() {
+        let mut rng = rand::thread_rng();
+        let mut key = vec![0; 32];
+        rng.fill_bytes(&mut key);
+        let four = SubtreeHeight(SubtreeHeightKind::Four);
+        for height in (0..256).step_by(4) {
+            let expected = if height % 8 == 0 {
+                key[height / 8] >> 4
+            } else {
+                key[height / 8] & 15
+            };
+            let locator = BinLocator::new(height, four).unwrap();
+            assert_eq!(locator.index(&key), expected as usize);
+        }
+        let eight = SubtreeHeight(SubtreeHeightKind::Eight);
+        for height in (0..256).step_by(8) {
+            let locator = BinLocator::new(height, eight).unwrap();
+            assert_eq!(locator.index(&key), key[height / 8] as usize);
+        }
+        assert!(BinLocator::new(2, four).is_err());
+    }
+
+    fn committed_tree(
+        data: &Cache,
+        batch_size: usize,
+    ) -> (SparseMerkleTree, smt_db::InMemorySmtDB) {
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        let mut db = smt_db::InMemorySmtDB::default();
+        let entries: Vec<(&Vec<u8>, &Vec<u8>)> = data.iter().collect();
+        for batch in entries.chunks(batch_size) {
+            let mut update = Cache::new();
+            for (key, value) in batch {
+                update.insert(key.to_vec(), value.to_vec());
+            }
+            tree.commit(&mut db, &UpdateData::new_from(update)).unwrap();
+        }
+        (tree, db)
+    }
+
+    #[test]
+    fn test_export_nodes() {
+        let mut rng = rand::thread_rng();
+        let mut data = Cache::new();
+        for _ in 0..300 {
+            let mut key = vec![0; 32];
+            rng.fill_bytes(&mut key);
+            let mut value = vec![0; 32];
+            rng.fill_bytes(&mut value);
+            data.insert(key, value);
+        }
+        let (tree, db) = committed_tree(&data, 300);
+        let entries = tree.export_nodes(&db).unwrap();
+
+        let root = entries.last().unwrap();
+        assert_eq!(root.node_hash, **tree.root.lock().unwrap());
+        assert_eq!((root.kind, root.height), (NodeEntryKind::Branch, 0));
+        let leaves: Vec<&NodeEntry> = entries
+            .iter()
+            .filter(|e| e.kind == NodeEntryKind::Leaf)
+            .collect();
+        assert_eq!(leaves.len(), data.len());
+        // leaves are visited from left to right
+        let mut keys: NestedVec = data.keys().cloned().collect();
+        keys.sort();
+        for (leaf, key) in leaves.iter().zip(keys.iter()) {
+            assert_eq!(leaf.key.as_ref(), Some(key));
+            assert_eq!(leaf.value.as_ref(), data.get(key));
+        }
+
+        // the order does not depend on how the tree is built
+        let (other, other_db) = committed_tree(&data, 7);
+        assert_eq!(other.export_nodes(&other_db).unwrap(), entries);
+
+        let empty = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        assert_eq!(
+            empty.export_nodes(&db).unwrap(),
+            vec![NodeEntry::new(EMPTY_HASH.to_vec(), NodeEntryKind::Empty, 0)]
+        );
+    }
+
+    #[test]
+    fn test_check_subtrees() {
+        let mut rng = rand::thread_rng();
+        let mut data = Cache::new();
+        for _ in 0..300 {
+            let mut key = vec![0; 32];
+            rng.fill_bytes(&mut key);
+            let mut value = vec![0; 32];
+            rng.fill_bytes(&mut value);
+            data.insert(key, value);
+        }
+        let (tree, mut db) = committed_tree(&data, 300);
+        let mut checks = vec![];
+        tree.check_subtrees(&db, |check| {
+            checks.push(check);
+            true
+        })
+        .unwrap();
+        let mut subtrees = vec![];
+        let mut leaves = Cache::new();
+        for check in checks {
+            match check {
+                SubtreeCheck::Valid {
+                    node_hash,
+                    leaves: subtree_leaves,
+                } => {
+                    subtrees.push(node_hash);
+                    leaves.extend(subtree_leaves);
+                },
+                _ => panic!("unexpected check {:?}", check),
+            }
         }
-
-        Err(SMTError::InvalidInput(String::from("Empty")))
+        assert_eq!(leaves, data);
+        assert_eq!(subtrees[0], **tree.root.lock().unwrap());
+
+        // children of the missing and the mismatched subtrees are not walked
+        let collect = |db: &smt_db::InMemorySmtDB| {
+            let mut checks = vec![];
+            tree.check_subtrees(db, |check| {
+                checks.push(check);
+                true
+            })
+            .unwrap();
+            checks
+        };
+        let encoded = db.get(&subtrees[1]).unwrap().unwrap();
+        db.del(&subtrees[1]).unwrap();
+        let checks = collect(&db);
+        assert!(checks.contains(&SubtreeCheck::Missing(subtrees[1].clone())));
+        assert!(checks.len() <= subtrees.len());
+        let mut flipped = encoded.clone();
+        let index = flipped.len() - 1;
+        flipped[index] ^= 0xff;
+        db.set(&KVPair::new(&subtrees[1], &flipped)).unwrap();
+        let checks = collect(&db);
+        assert!(checks.contains(&SubtreeCheck::Mismatched(subtrees[1].clone())));
+        db.set(&KVPair::new(&subtrees[1], &encoded)).unwrap();
+        assert_eq!(collect(&db).len(), subtrees.len());
+
+        // walk stops when visit returns false
+        let mut visited = 0;
+        tree.check_subtrees(&db, |_| {
+            visited += 1;
+            false
+        })
+        .unwrap();
+        assert_eq!(visited, 1);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::sparse_merkle_tree::smt_db;
 
     #[test]
-    fn test_subtree() {
-        let test_data = vec![
-            ("05030302020303001f930f4f669738b026406a872c24db29238731868957ae1de0e5a68bb0cf7da633e508533a13da9c33fc64eb78b18bd0646c82d6316697dece0aee5a3a92e45700082e6af17a61852d01dfc18e859c20b0b974472bf6169295c36ce1380c2550e16c16babfe7d3204f61852d100f553276ad154921988de3797622091f0581884b008b647996849b70889d2a382d8fa2f42405c3bca57189de0be52c92bbc03f0cd21194ddd776cf387a81d0117b6288e6a724ec14a58cdde3c196292191da360da800ec66ad4b484153de040869f8833a30a8fcde4fdf8fcbd78d33c2fb2182dd8ffa3b311d3a72a9aec8560c56c68d665ad54c5644d40ea4fc7ed914d4eea5da3c0400e93bd78ce150412056a9076cf58977ff1a697b1932abdd52d7b978fce69186d3a9cb7274eceac6b0807ce4db0763dc596cd00e59177172de6b5dd1593b33a78500c8c4673053da259999cbc9502aef75c3c0b84bce42b1d1a2d437df88d32b737bd36e7a6410939ac431914de947353f06bbbfc31c86609ec291ed9e13b665f86a", "d6b9f2888480a4fa33fc1d0e0daaef702f0ab41dd8875eee80b7c312011e5191", vec![3, 3, 2, 2, 3, 3]),
-            ("02010202020049720db77a5ca853713493d4e11926b417af0cae746a305a52f555738eed47cad58c7809f5cf4119cc0f25c224f7124d15b5d62ba93bc3d948db32871026f068018dfe7dfa8fb4a5a268168638c8cce0e26f87a227320aee691f8872ed6a3aba0e", "0989d2ac315f25669f69e9bef067b9c41310964d76565196ac4fb92452c68955", vec![1,2,2]),
-            ("0f0404040404040404040404040404040401bbacc7102a28f2eecd0e4de3c130064e653d0118b1dc4129095901f190e70034019dcb747007aca526d4b0782ed20a88a5d48a4ab6276378bada201ab5b6e4d75b01e89b7270dd0ad80207e11422bfc28f8cda8932d59b1082486fa1bf5626ea0aba01858c61150861b89516244e07cfd9d3ebcb12b2d44c2de4e7e2faed96717202eb01f9437e84b231d85f7fc2690ed54b09e85c2e0fc98b26430f10418065374e40bf0189ae2184c9a2e70656ce37c89c903b258198ad6e9db66f135780f66d8613a6fd01058c3bef2957b130622e752f0a81ee8dcf60b4685675eb88e39d5150c954fe220161543e80c5356f580f8e7e4548576486ee754ffe22f4dd122ef48e41bffc7adc01f55a1089a16835a4cbe8b5e12227575ecfd99cd951e34b409f9b2ace6f25a49701e5dfbf3ecaf909728248a751e1a75f3b626777094fe1aab03ae6f526ddac799a01f88ad8cd4aec6cc4f8d2c2bc4a5f368fc9b877685eb55673baa01d652fa4c82b0182f8fb577797274de4f48d8bd7cc5a77068ea3c60477e8552b38c926466eba1101c149d0c79bc1355d763d01690139fd187a84488d534e7e38e4772279c3826b9b01006afab486675b0e3f9b6b06283da947df6749269fb8621afe843d5df942bce7011ead1b569f80edffa2044bf9d8b8703b970ca741b821127d6da69da83b52294f01c1a9d57b050c3ba96aca78a26c5eebc76bb51acab78ce70ed3bdea1ca9143cd8", "2c76277c959e70205fff49ef8732047516cf07e18758345ca56005e732ca2d17", vec![4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]),
-        ];
+    fn test_import_nodes() {
+        let mut data = Cache::new();
+        for i in 0..100_u8 {
+            data.insert(vec![i; 32], vec![i; 32].hash_with_kind(HashKind::Value));
+        }
+        let (tree, db) = committed_tree(&data, 100);
+        let entries = tree.export_nodes(&db).unwrap();
 
-        for (data, hash, structure) in test_data {
-            let decoded_data = hex::decode(data).unwrap();
-            let tree = SubTree::new(&decoded_data, KeyLength(32)).unwrap();
-            let decoded_hash = hex::decode(hash).unwrap();
-            assert_eq!(tree.structure, structure);
-            assert_eq!(*tree.root, decoded_hash);
+        let mut imported_db = smt_db::InMemorySmtDB::default();
+        let root = SparseMerkleTree::import_nodes(
+            &mut imported_db,
+            &entries,
+            KeyLength(32),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(root, **tree.root.lock().unwrap());
+        assert_eq!(imported_db, db);
+
+        let leaf = entries
+            .iter()
+            .position(|e| e.kind == NodeEntryKind::Leaf)
+            .unwrap();
+        let branch = entries
+            .iter()
+            .position(|e| e.kind == NodeEntryKind::Branch)
+            .unwrap();
+        let mut tampered_value = entries.clone();
+        tampered_value[leaf].value = Some(vec![9; 32]);
+        let mut tampered_height = entries.clone();
+        tampered_height[branch].height += 1;
+        let mut missing = entries.clone();
+        missing.remove(branch);
+        for tampered in [tampered_value, tampered_height, missing] {
+            assert_eq!(
+                SparseMerkleTree::import_nodes(
+                    &mut smt_db::InMemorySmtDB::default(),
+                    &tampered,
+                    KeyLength(32),
+                    Default::default(),
+                ),
+                Err(SMTError::InvalidRoot(String::from(
+                    "Not matching with the exported nodes"
+                )))
+            );
         }
+
+        let mut without_value = entries;
+        without_value[leaf].value = None;
+        assert!(matches!(
+            SparseMerkleTree::import_nodes(
+                &mut smt_db::InMemorySmtDB::default(),
+                &without_value,
+                KeyLength(32),
+                Default::default(),
+            ),
+            Err(SMTError::InvalidInput(_))
+        ));
     }
+
     #[test]
-    fn test_subtree_encode() {
-        let test_data = vec![
-            ("05030302020303001f930f4f669738b026406a872c24db29238731868957ae1de0e5a68bb0cf7da633e508533a13da9c33fc64eb78b18bd0646c82d6316697dece0aee5a3a92e45700082e6af17a61852d01dfc18e859c20b0b974472bf6169295c36ce1380c2550e16c16babfe7d3204f61852d100f553276ad154921988de3797622091f0581884b008b647996849b70889d2a382d8fa2f42405c3bca57189de0be52c92bbc03f0cd21194ddd776cf387a81d0117b6288e6a724ec14a58cdde3c196292191da360da800ec66ad4b484153de040869f8833a30a8fcde4fdf8fcbd78d33c2fb2182dd8ffa3b311d3a72a9aec8560c56c68d665ad54c5644d40ea4fc7ed914d4eea5da3c0400e93bd78ce150412056a9076cf58977ff1a697b1932abdd52d7b978fce69186d3a9cb7274eceac6b0807ce4db0763dc596cd00e59177172de6b5dd1593b33a78500c8c4673053da259999cbc9502aef75c3c0b84bce42b1d1a2d437df88d32b737bd36e7a6410939ac431914de947353f06bbbfc31c86609ec291ed9e13b665f86a", "7a208dc2a21cb829e5fa4dc7d876bef8e52ddd23ae5ea24c2567b264bcd91a23", vec![3, 3, 2, 2, 3, 3]),
-            ("02010202020049720db77a5ca853713493d4e11926b417af0cae746a305a52f555738eed47cad58c7809f5cf4119cc0f25c224f7124d15b5d62ba93bc3d948db32871026f068018dfe7dfa8fb4a5a268168638c8cce0e26f87a227320aee691f8872ed6a3aba0e", "c0fcf4b2571622905dde0884ef56d494ad3481d28fa167466f970f2c633e2925", vec![1,2,2]),
-            ("0f0404040404040404040404040404040401bbacc7102a28f2eecd0e4de3c130064e653d0118b1dc4129095901f190e70034019dcb747007aca526d4b0782ed20a88a5d48a4ab6276378bada201ab5b6e4d75b01e89b7270dd0ad80207e11422bfc28f8cda8932d59b1082486fa1bf5626ea0aba01858c61150861b89516244e07cfd9d3ebcb12b2d44c2de4e7e2faed96717202eb01f9437e84b231d85f7fc2690ed54b09e85c2e0fc98b26430f10418065374e40bf0189ae2184c9a2e70656ce37c89c903b258198ad6e9db66f135780f66d8613a6fd01058c3bef2957b130622e752f0a81ee8dcf60b4685675eb88e39d5150c954fe220161543e80c5356f580f8e7e4548576486ee754ffe22f4dd122ef48e41bffc7adc01f55a1089a16835a4cbe8b5e12227575ecfd99cd951e34b409f9b2ace6f25a49701e5dfbf3ecaf909728248a751e1a75f3b626777094fe1aab03ae6f526ddac799a01f88ad8cd4aec6cc4f8d2c2bc4a5f368fc9b877685eb55673baa01d652fa4c82b0182f8fb577797274de4f48d8bd7cc5a77068ea3c60477e8552b38c926466eba1101c149d0c79bc1355d763d01690139fd187a84488d534e7e38e4772279c3826b9b01006afab486675b0e3f9b6b06283da947df6749269fb8621afe843d5df942bce7011ead1b569f80edffa2044bf9d8b8703b970ca741b821127d6da69da83b52294f01c1a9d57b050c3ba96aca78a26c5eebc76bb51acab78ce70ed3bdea1ca9143cd8", "5a2f1f740cbea0944d5182fe8ef9190d7a07e8601d0b9fc1137d48b94ce73407", vec![4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]),
-        ];
+    fn test_calculate_root_from_data_invalid_key_length() {
+        let mut data = Cache::new();
+        data.insert(vec![1; 32], vec![1]);
+        data.insert(vec![2; 31], vec![2]);
+        assert!(matches!(
+            SparseMerkleTree::calculate_root_from_data(&UpdateData::new_from(data), KeyLength(32)),
+            Err(SMTError::InvalidKeyLength {
+                expected: 32,
+                got: 31
+            })
+        ));
+        assert_eq!(
+            SparseMerkleTree::calculate_root_from_data(
+                &UpdateData::new_from(Cache::new()),
+                KeyLength(32)
+            )
+            .unwrap(),
+            EMPTY_HASH.to_vec()
+        );
+    }
 
-        for (data, _, _) in test_data {
-            let decoded_data = hex::decode(data).unwrap();
-            let tree = SubTree::new(&decoded_data, KeyLength(32)).unwrap();
-            assert_eq!(tree.encode(), decoded_data);
+    #[test]
+    fn test_calculate_node_hash() {
+        let data = hex::decode("02010202020049720db77a5ca853713493d4e11926b417af0cae746a305a52f555738eed47cad58c7809f5cf4119cc0f25c224f7124d15b5d62ba93bc3d948db32871026f068018dfe7dfa8fb4a5a268168638c8cce0e26f87a227320aee691f8872ed6a3aba0e").unwrap();
+        let tree = SubTree::new(&data, KeyLength(32), HasherKind::Sha256).unwrap();
+        assert_eq!(
+            calculate_node_hash(&data, KeyLength(32)).unwrap(),
+            *tree.root
+        );
+        assert_eq!(
+            calculate_node_hash(
+                &SubTree::new_empty(HasherKind::Sha256).encode(),
+                KeyLength(32)
+            )
+            .unwrap(),
+            EMPTY_HASH.to_vec()
+        );
+
+        // malformed data is an error instead of a panic
+        for len in 0..data.len() {
+            assert!(calculate_node_hash(&data[..len], KeyLength(32)).is_err());
         }
+        let mut trailing = data.clone();
+        trailing.push(2);
+        assert!(calculate_node_hash(&trailing, KeyLength(32)).is_err());
+        let mut invalid_structure = data.clone();
+        invalid_structure[1] = 2;
+        assert!(calculate_node_hash(&invalid_structure, KeyLength(32)).is_err());
+
+        // flipped byte in the leaf changes the hash
+        let mut flipped = data;
+        let index = flipped.len() - 1;
+        flipped[index] ^= 1;
+        assert_ne!(
+            calculate_node_hash(&flipped, KeyLength(32)).unwrap(),
+            *tree.root
+        );
     }
 
     #[test]
@@ -1790,6 +2956,171 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_proof_omits_empty_sibling_hashes() {
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        let mut data = UpdateData { data: Cache::new() };
+        for i in 0..20u8 {
+            data.data.insert(vec![i * 7; 32], vec![i; 4]);
+        }
+        let mut db = smt_db::InMemorySmtDB::default();
+        let root = tree.commit(&mut db, &data).unwrap();
+        let root = root.lock().unwrap().to_vec();
+
+        // non-existing keys are included so that the queries end with the empty nodes
+        let query_keys: NestedVec = vec![vec![0; 32], vec![7; 32], vec![1; 32], vec![255; 32]];
+        let proof = tree.prove(&mut db, &query_keys).unwrap();
+        assert!(!proof.sibling_hashes.is_empty());
+        assert!(proof
+            .sibling_hashes
+            .iter()
+            .all(|hash| hash.as_slice() != EMPTY_HASH));
+        assert!(SparseMerkleTree::verify(&query_keys, &proof, &root, KeyLength(32)).unwrap());
+    }
+
+    #[test]
+    fn test_proof_encode() {
+        let proof = Proof {
+            sibling_hashes: vec![vec![1; 32]],
+            queries: vec![QueryProof {
+                pair: Arc::new(KVPair::new(&[2; 32], &[3; 32])),
+                bitmap: Arc::new(vec![1]),
+            }],
+        };
+        let encoded = proof.encode();
+        let query = [
+            vec![0x0a, 32],
+            vec![2; 32],
+            vec![0x12, 32],
+            vec![3; 32],
+            vec![0x1a, 1, 1],
+        ]
+        .concat();
+        let expected = [
+            vec![0x0a, 32],
+            vec![1; 32],
+            vec![0x12, query.len() as u8],
+            query,
+        ]
+        .concat();
+        assert_eq!(encoded, expected);
+        assert_eq!(encoded.len(), 107);
+
+        let empty = Proof {
+            sibling_hashes: vec![],
+            queries: vec![],
+        };
+        assert!(empty.encode().is_empty());
+    }
+
+    #[test]
+    fn test_query_queue_same_order_as_insert_and_filter_queries() {
+        let mut rng = rand::thread_rng();
+        let new_query = |key: Vec<u8>, height: usize| {
+            let bitmap = vec![true; height];
+            QueryProofWithProof::new_with_pair(
+                Arc::new(KVPair(key, vec![1])),
+                &bitmap,
+                &[],
+                &[],
+                HasherKind::Sha256,
+            )
+        };
+        let mut initial: Vec<QueryProofWithProof> = (0..50)
+            .map(|_| new_query(vec![rng.gen_range(0..16) << 4], rng.gen_range(1..8)))
+            .collect();
+        initial.sort_descending();
+        let mut queue = QueryQueue::new(&initial);
+        let mut expected = VecDeque::from(initial);
+        // small keys and heights result in the same keys and binary paths
+        for _ in 0..500 {
+            let query = new_query(vec![rng.gen_range(0..16) << 4], rng.gen_range(1..8));
+            queue.insert_and_filter(query.clone());
+            insert_and_filter_queries(query, &mut expected);
+        }
+        let mut actual = vec![];
+        while let Some(query) = queue.pop_front() {
+            actual.push((query.height(), query.query_proof.key().to_vec()));
+        }
+        let expected: Vec<(usize, Vec<u8>)> = expected
+            .iter()
+            .map(|query| (query.height(), query.query_proof.key().to_vec()))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_verify_many_queries() {
+        let mut rng = rand::thread_rng();
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        let mut data = UpdateData { data: Cache::new() };
+        for _ in 0..2000 {
+            let mut key = vec![0; 32];
+            rng.fill_bytes(&mut key);
+            data.data.insert(key, vec![1; 4]);
+        }
+        let mut db = smt_db::InMemorySmtDB::default();
+        let root = tree.commit(&mut db, &data).unwrap();
+        let root = root.lock().unwrap().to_vec();
+
+        // inclusion and non-inclusion queries are mixed
+        let mut query_keys: NestedVec = data.data.keys().take(300).cloned().collect();
+        for _ in 0..300 {
+            let mut key = vec![0; 32];
+            rng.fill_bytes(&mut key);
+            query_keys.push(key);
+        }
+        let proof = tree.prove(&mut db, &query_keys).unwrap();
+        assert!(SparseMerkleTree::verify(&query_keys, &proof, &root, KeyLength(32)).unwrap());
+
+        let mut invalid_proof = proof.clone();
+        let last = invalid_proof.sibling_hashes.len() - 1;
+        invalid_proof.sibling_hashes[last] = vec![0; 32];
+        assert!(
+            !SparseMerkleTree::verify(&query_keys, &invalid_proof, &root, KeyLength(32)).unwrap()
+        );
+        let mut invalid_proof = proof;
+        invalid_proof.sibling_hashes.pop();
+        assert!(
+            !SparseMerkleTree::verify(&query_keys, &invalid_proof, &root, KeyLength(32)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prove_shares_sibling_hashes() {
+        let mut rng = rand::thread_rng();
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(38), Default::default());
+        let mut data = UpdateData { data: Cache::new() };
+        // keys of the same module prefix share the subtree, and the other keys are spread over the tree
+        let mut module_keys: NestedVec = vec![];
+        for i in 0..1000 {
+            let mut key = vec![0; 38];
+            rng.fill_bytes(&mut key);
+            if i < 200 {
+                key[..6].copy_from_slice(&[0, 0, 0, 1, 0, 0]);
+                module_keys.push(key.clone());
+            }
+            data.data.insert(key, vec![1; 4]);
+        }
+        let mut db = smt_db::InMemorySmtDB::default();
+        let root = tree.commit(&mut db, &data).unwrap();
+        let root = root.lock().unwrap().to_vec();
+
+        let proof = tree.prove(&mut db, &module_keys).unwrap();
+        assert!(SparseMerkleTree::verify(&module_keys, &proof, &root, KeyLength(38)).unwrap());
+        let unique: HashSet<&Vec<u8>> = proof.sibling_hashes.iter().collect();
+        assert_eq!(unique.len(), proof.sibling_hashes.len());
+
+        let mut independent = 0;
+        for key in module_keys.iter() {
+            let keys = vec![key.clone()];
+            let single = tree.prove(&mut db, &keys).unwrap();
+            assert!(SparseMerkleTree::verify(&keys, &single, &root, KeyLength(38)).unwrap());
+            independent += single.sibling_hashes.len();
+        }
+        assert!(proof.sibling_hashes.len() < independent);
+    }
+
     #[test]
     fn test_key_length_invalid_size() {
         let test_data = vec![
@@ -1827,12 +3158,10 @@ mod tests {
             let mut db = smt_db::InMemorySmtDB::default();
             let result = tree.commit(&mut db, &data);
 
-            assert_eq!(
+            assert!(matches!(
                 result.err(),
-                Some(SMTError::InvalidInput(String::from(
-                    "all keys must have the same length"
-                )))
-            );
+                Some(SMTError::InvalidKeyLength { expected: 32, .. })
+            ));
         }
     }
 
@@ -1968,22 +3297,259 @@ mod tests {
         )];
 
         for (keys, values, root) in test_data {
-            let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
-            let mut data = UpdateData { data: Cache::new() };
-            for idx in 0..keys.len() {
-                data.data.insert(
-                    hex::decode(keys[idx]).unwrap(),
-                    hex::decode(values[idx]).unwrap(),
+            // the root is the same with the subtrees updated in parallel
+            for threads in [1, 4] {
+                let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default())
+                    .with_threads(threads);
+                let mut data = UpdateData { data: Cache::new() };
+                for idx in 0..keys.len() {
+                    data.data.insert(
+                        hex::decode(keys[idx]).unwrap(),
+                        hex::decode(values[idx]).unwrap(),
+                    );
+                }
+                let mut db = smt_db::InMemorySmtDB::default();
+                let result = tree.commit(&mut db, &data);
+
+                assert_eq!(
+                    **result.unwrap().lock().unwrap(),
+                    hex::decode(root).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_commit_with_threads() {
+        // the seed is fixed, so that a failure is reproduced by running the test again
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut batches = vec![];
+        let mut keys = vec![];
+        for round in 0..5 {
+            let mut data = Cache::new();
+            for _ in 0..rng.gen_range(1..2000) {
+                let mut key = vec![0; 32];
+                rng.fill_bytes(&mut key);
+                data.insert(key.clone(), vec![round; 32]);
+                keys.push(key);
+            }
+            // some keys are updated or deleted in the later batches
+            for key in keys.iter().step_by(rng.gen_range(2..10)) {
+                data.insert(
+                    key.clone(),
+                    if round % 2 == 0 {
+                        vec![]
+                    } else {
+                        vec![round; 8]
+                    },
                 );
             }
+            batches.push(data);
+        }
+
+        let mut sequential = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        let mut sequential_db = smt_db::InMemorySmtDB::default();
+        let mut parallel =
+            SparseMerkleTree::new(&[], KeyLength(32), Default::default()).with_threads(4);
+        let mut parallel_db = smt_db::InMemorySmtDB::default();
+        for data in batches {
+            let data = UpdateData::new_from(data);
+            let expected = sequential.commit(&mut sequential_db, &data).unwrap();
+            let root = parallel.commit(&mut parallel_db, &data).unwrap();
+            assert_eq!(**root.lock().unwrap(), **expected.lock().unwrap());
+            assert_eq!(parallel_db, sequential_db);
+        }
+    }
+
+    #[test]
+    fn test_split_by_keys() {
+        assert_eq!(split_by_keys(&[1, 1, 1, 1], 2), vec![0..2, 2..4]);
+        assert_eq!(split_by_keys(&[5, 0, 0, 1, 1, 1], 2), vec![0..1, 1..6]);
+        assert_eq!(split_by_keys(&[1, 2, 3], 4), vec![0..2, 2..3]);
+        assert_eq!(split_by_keys(&[0, 3, 0], 1), vec![0..3]);
+        assert!(split_by_keys(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_validate_key_lengths() {
+        assert!(validate_key_lengths::<Vec<u8>>(&[], KeyLength(38)).is_ok());
+        assert!(validate_key_lengths(&[vec![0; 38], vec![1; 38]], KeyLength(38)).is_ok());
+        let test_data: Vec<(Vec<u8>, usize)> =
+            vec![(vec![], 0), (vec![0; 32], 32), (vec![0; 39], 39)];
+        for (key, got) in test_data {
+            let err = validate_key_lengths(&[vec![0; 38], key], KeyLength(38)).unwrap_err();
+            assert_eq!(err, SMTError::InvalidKeyLength { expected: 38, got });
+            assert_eq!(
+                err.to_string(),
+                format!("invalid key length: expected 38, got {}", got)
+            );
+        }
+    }
+
+    #[test]
+    fn test_prove_and_commit_invalid_key_length() {
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(38), Default::default());
+        let mut db = smt_db::InMemorySmtDB::default();
+        let mut data = UpdateData { data: Cache::new() };
+        data.data.insert(vec![1; 38], vec![1, 2, 3]);
+        let root = tree.commit(&mut db, &data).unwrap();
+        let root = (**root.lock().unwrap()).clone();
+
+        for key in [vec![], vec![1; 32], vec![1; 39]] {
+            let mut tree = SparseMerkleTree::new(&root, KeyLength(38), Default::default());
+            let err = tree
+                .prove(&mut db, &[vec![1; 38], key.clone()])
+                .unwrap_err();
+            assert_eq!(
+                err,
+                SMTError::InvalidKeyLength {
+                    expected: 38,
+                    got: key.len()
+                }
+            );
+
+            let mut data = UpdateData { data: Cache::new() };
+            data.data.insert(key.clone(), vec![4, 5, 6]);
+            let err = tree.commit(&mut db, &data).unwrap_err();
+            assert_eq!(
+                err,
+                SMTError::InvalidKeyLength {
+                    expected: 38,
+                    got: key.len()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let key_length = KeyLength(32);
+        let mut db = smt_db::InMemorySmtDB::default();
+        let mut entries = vec![];
+        for i in 0..20_u8 {
+            let mut data = UpdateData { data: Cache::new() };
+            for j in 0..5_u8 {
+                data.data
+                    .insert(vec![j; 32].hash_with_kind(HashKind::Key), vec![i, j]);
+            }
+            let mut tree = SparseMerkleTree::new(&[], key_length, Default::default());
+            let root = (**tree.commit(&mut db, &data).unwrap().lock().unwrap()).clone();
+            let query_keys = vec![vec![i % 5; 32].hash_with_kind(HashKind::Key)];
+            let proof = tree.prove(&mut db, &query_keys).unwrap();
+            entries.push(Some(VerifyEntry {
+                root,
+                query_keys,
+                proof,
+            }));
+        }
+        // proof of the other root
+        let mut swapped = entries[1].clone().unwrap();
+        swapped.root = entries[2].as_ref().unwrap().root.clone();
+        entries[1] = Some(swapped);
+        // query key with the wrong length
+        let mut short_key = entries[3].clone().unwrap();
+        short_key.query_keys = vec![vec![1; 20]];
+        entries[3] = Some(short_key);
+        // entry which could not be decoded
+        entries[4] = None;
+
+        let result = SparseMerkleTree::verify_batch(&entries, key_length);
+
+        let expected: Vec<bool> = (0..20).map(|i| !(1..=4).contains(&i)).collect();
+        assert_eq!(result, expected);
+        assert!(SparseMerkleTree::verify_batch(&[], key_length).is_empty());
+    }
+
+    /// hasher_test_data returns the single leaf and the three leaves with the roots by the hasher.
+    fn hasher_test_data() -> Vec<(Cache, &'static str, &'static str)> {
+        let single: Cache = vec![(vec![1; 32], vec![2; 32])].into_iter().collect();
+        let three: Cache = vec![0x10, 0x20, 0x80]
+            .into_iter()
+            .map(|i: u8| (vec![i; 32], vec![i + 1; 32]))
+            .collect();
+        vec![
+            (
+                single,
+                "90d6bfb0687fbe85379bf5390e2e7c65d766c87ae563f8e75b98a614301073e7",
+                "2326002e1dee123b2ee99d67e072e2aa5caeb402cb14949b157b5ebe926340c2",
+            ),
+            (
+                three,
+                "032ceca555d189aa83945630ad794659dfabf57de1323ded8bb39a95d02575ec",
+                "f1d4b538b4e9363ef5904e6dd8b1e0bb9df85b9d3f808eaa1cfa38cabb7bfba4",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_hasher_sha256() {
+        for (data, sha256_root, _) in hasher_test_data() {
+            let mut tree = SparseMerkleTree::new_with_hasher(
+                &[],
+                KeyLength(32),
+                Default::default(),
+                HasherKind::Sha256,
+            );
             let mut db = smt_db::InMemorySmtDB::default();
-            let result = tree.commit(&mut db, &data);
+            let root = tree.commit(&mut db, &UpdateData::new_from(data)).unwrap();
+            assert_eq!(hex::encode(&**root.lock().unwrap()), sha256_root);
+        }
+    }
 
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_hasher_blake3() {
+        let hasher = HasherKind::Blake3;
+        for (data, sha256_root, blake3_root) in hasher_test_data() {
+            let update = UpdateData::new_from(data.clone());
+            let mut tree =
+                SparseMerkleTree::new_with_hasher(&[], KeyLength(32), Default::default(), hasher);
+            let mut db = smt_db::InMemorySmtDB::default();
+            let root = (**tree.commit(&mut db, &update).unwrap().lock().unwrap()).clone();
+            assert_eq!(hex::encode(&root), blake3_root);
             assert_eq!(
-                **result.unwrap().lock().unwrap(),
-                hex::decode(root).unwrap()
+                SparseMerkleTree::calculate_root_from_data_with_hasher(
+                    &update,
+                    KeyLength(32),
+                    hasher
+                )
+                .unwrap(),
+                root
             );
+
+            let query_keys: Vec<Vec<u8>> = data.keys().cloned().chain([vec![0; 32]]).collect();
+            let proof = tree.prove(&mut db, &query_keys).unwrap();
+            assert!(SparseMerkleTree::verify_with_hasher(
+                &query_keys,
+                &proof,
+                &root,
+                KeyLength(32),
+                hasher
+            )
+            .unwrap());
+            // the proof does not verify with the other hasher
+            assert!(!SparseMerkleTree::verify(&query_keys, &proof, &root, KeyLength(32)).unwrap());
+            assert!(!SparseMerkleTree::verify(
+                &query_keys,
+                &proof,
+                &hex::decode(sha256_root).unwrap(),
+                KeyLength(32)
+            )
+            .unwrap());
         }
+
+        // removing all the keys results in the empty hash of the hasher
+        let mut tree =
+            SparseMerkleTree::new_with_hasher(&[], KeyLength(32), Default::default(), hasher);
+        let mut db = smt_db::InMemorySmtDB::default();
+        let (data, _, _) = hasher_test_data().remove(1);
+        tree.commit(&mut db, &UpdateData::new_from(data.clone()))
+            .unwrap();
+        let deletes = data.into_keys().map(|key| (key, vec![])).collect();
+        let root = tree
+            .commit(&mut db, &UpdateData::new_from(deletes))
+            .unwrap();
+        assert_eq!(**root.lock().unwrap(), hasher.empty_hash(0));
     }
 
     #[test]
@@ -3154,6 +4720,43 @@ mod tests {
         assert_eq!(data.data.get(&vec![7, 8, 9]).unwrap(), &vec![10, 11, 12]);
     }
 
+    #[test]
+    fn test_update_data_duplicated_keys() {
+        let key = vec![1; 32];
+        let other = vec![2; 32];
+        let root_of = |pairs: &[(&Vec<u8>, Vec<u8>)]| {
+            let mut data = UpdateData::new_from(Cache::new());
+            for (key, value) in pairs {
+                data.insert(SharedKVPair(key, value));
+            }
+            let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+            let mut db = smt_db::InMemorySmtDB::default();
+            let root = tree.commit(&mut db, &data).unwrap();
+            let root = root.lock().unwrap();
+            root.clone()
+        };
+
+        let expected = root_of(&[(&other, vec![2; 32]), (&key, vec![3; 32])]);
+        for _ in 0..10 {
+            let root = root_of(&[
+                (&key, vec![1; 32]),
+                (&other, vec![2; 32]),
+                (&key, vec![3; 32]),
+            ]);
+            assert_eq!(root, expected);
+        }
+        let root = root_of(&[
+            (&key, vec![3; 32]),
+            (&other, vec![2; 32]),
+            (&key, vec![1; 32]),
+        ]);
+        assert_ne!(root, expected);
+
+        // the deletion after the update removes the key
+        let root = root_of(&[(&key, vec![1; 32]), (&other, vec![2; 32]), (&key, vec![])]);
+        assert_eq!(root, root_of(&[(&other, vec![2; 32])]));
+    }
+
     #[test]
     fn test_query_proof_with_proof() {
         let pair = Arc::new(KVPair(
@@ -3180,6 +4783,7 @@ mod tests {
             &binary_bitmap,
             &ancestor_hashes,
             &sibling_hashes,
+            HasherKind::Sha256,
         );
 
         assert_eq!(proof.binary_bitmap, binary_bitmap);
@@ -3223,6 +4827,7 @@ mod tests {
             &binary_bitmap,
             &ancestor_hashes,
             &sibling_hashes,
+            HasherKind::Sha256,
         );
 
         proof.slice_bitmap();
@@ -3280,6 +4885,7 @@ mod tests {
             &binary_bitmap,
             &ancestor_hashes,
             &sibling_hashes,
+            HasherKind::Sha256,
         );
 
         let path = proof.binary_path();
@@ -3344,6 +4950,7 @@ mod tests {
                 .map(|k| hex::decode(k).unwrap())
                 .collect::<NestedVec>(),
             KeyLength(32),
+            HasherKind::Sha256,
         );
         assert_eq!(res.unwrap_err(), SMTError::InvalidBitmapLen);
     }
@@ -3668,7 +5275,8 @@ mod tests {
                 SparseMerkleTree::verify_and_prepare_proof_map(
                     &proof,
                     &invalid_query_keys,
-                    KeyLength(32)
+                    KeyLength(32),
+                    HasherKind::Sha256
                 )
                 .unwrap_err(),
                 SMTError::InvalidInput(String::from("The length of the key is invalid",))
@@ -3681,8 +5289,13 @@ mod tests {
             ));
             proof.queries[0] = invalid_query_with_proof;
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidInput(String::from("The length of the key is invalid",))
             );
 
@@ -3692,8 +5305,13 @@ mod tests {
             invalid_bitmap[0] = 0;
             proof.queries[0].bitmap = Arc::new(invalid_bitmap);
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidBitmapLen
             );
             // empty proof value when query key and proof key differs (invalid proof)
@@ -3703,8 +5321,13 @@ mod tests {
                 &hex::decode(vec![]).unwrap(),
             ));
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidInput(String::from(
                     "Proof for which query key and proof key differs, must have a non-empty value",
                 ))
@@ -3713,15 +5336,25 @@ mod tests {
             proof.queries[0] = valid_query_proof.clone();
             proof.queries[0].bitmap = Arc::new(vec![30; 33]);
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidBitmapLen
             );
             // invalid bitmap length with common_prefix
             proof.queries[0].bitmap = Arc::new(vec![31; 2]);
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidBitmapLen
             );
 
@@ -3731,8 +5364,13 @@ mod tests {
             let valid_bitmap = proof.queries[3].bitmap.clone();
             proof.queries[3].bitmap = Arc::new(vec![30]);
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidInput(String::from("Mismatched values or bitmap",))
             );
             // 2. mismatched values
@@ -3742,8 +5380,13 @@ mod tests {
                 &hex::decode(values[3]).unwrap(),
             ));
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidInput(String::from("Mismatched values or bitmap",))
             );
             // 3. mismatched keys
@@ -3752,16 +5395,26 @@ mod tests {
                 &hex::decode(values[3]).unwrap(),
             ));
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidBitmapLen
             );
 
             // mismatched length of keys and the queries
             proof.queries.clear();
             assert_eq!(
-                SparseMerkleTree::verify_and_prepare_proof_map(&proof, &query_keys, KeyLength(32))
-                    .unwrap_err(),
+                SparseMerkleTree::verify_and_prepare_proof_map(
+                    &proof,
+                    &query_keys,
+                    KeyLength(32),
+                    HasherKind::Sha256
+                )
+                .unwrap_err(),
                 SMTError::InvalidInput(String::from(
                     "Mismatched length of keys and the queries of the proof",
                 ))
@@ -3798,7 +5451,7 @@ mod tests {
 
     #[test]
     fn test_node_new_branch() {
-        let node = Node::new_branch(&EMPTY_HASH, &EMPTY_HASH);
+        let node = Node::new_branch(&EMPTY_HASH, &EMPTY_HASH, HasherKind::Sha256);
         assert_eq!(node.kind, NodeKind::Stub);
         assert_eq!(
             node.hash,
@@ -3821,10 +5474,10 @@ mod tests {
 
     #[test]
     fn test_node_new_leaf() {
-        let node = Node::new_leaf(&KVPair(
-            vec![10, 11, 12, 13, 14, 15],
-            vec![16, 17, 18, 19, 20],
-        ));
+        let node = Node::new_leaf(
+            &KVPair(vec![10, 11, 12, 13, 14, 15], vec![16, 17, 18, 19, 20]),
+            HasherKind::Sha256,
+        );
         assert_eq!(node.kind, NodeKind::Leaf);
         assert_eq!(
             node.hash,
@@ -3842,7 +5495,7 @@ mod tests {
 
     #[test]
     fn test_node_new_empty() {
-        let node = Node::new_empty();
+        let node = Node::new_empty(HasherKind::Sha256);
         assert_eq!(node.kind, NodeKind::Empty);
         assert_eq!(node.hash, KVPair(vec![2], EMPTY_HASH.to_vec()));
         assert_eq!(node.key, vec![]);
@@ -1,23 +1,48 @@
 // smt_db provides in memory interface for in memory SMT computation.
+use std::collections::HashMap;
+
 use crate::consts;
-use crate::database::traits::Actions;
-use crate::database::DB;
+use crate::database::column_families::{self, StateSnapshot};
+use crate::database::traits::{Actions, NodeReader};
 use crate::types::{Cache, KVPair, VecOption};
 
 pub struct SmtDB<'a> {
-    db: &'a DB,
+    db: &'a rocksdb::DB,
     pub batch: rocksdb::WriteBatch,
 }
 
-#[derive(Default)]
+#[derive(Default, Debug, PartialEq, Eq)]
 pub struct InMemorySmtDB {
     cache: Cache,
 }
 
+/// OverlaySmtDB reads the nodes from the database, and keeps the changes in memory.
+/// It is used to rebuild the tree of a past root without writing to the database.
+pub struct OverlaySmtDB<'a> {
+    db: &'a rocksdb::DB,
+    /// overlay holds the changed nodes. None if the node is deleted.
+    overlay: HashMap<Vec<u8>, VecOption>,
+}
+
+/// SnapshotSmtDB reads the nodes from the snapshot, and keeps the changes in memory.
+/// It is used to prove the root of the snapshot while the commits update the database.
+pub struct SnapshotSmtDB<'a> {
+    snapshot: &'a StateSnapshot<'a>,
+    /// overlay holds the changed nodes. None if the node is deleted.
+    overlay: HashMap<Vec<u8>, VecOption>,
+}
+
+/// The database reads the nodes stored with the SMT prefix.
+/// The batch of SmtDB cannot be shared by the threads, so that the threads read the nodes from the database directly.
+impl NodeReader for rocksdb::DB {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        column_families::get(self, &[consts::Prefix::SMT, key].concat())
+    }
+}
+
 impl Actions for SmtDB<'_> {
     fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
-        let result = self.db.get(&[consts::Prefix::SMT, key].concat())?;
-        Ok(result)
+        self.db.read_node(key)
     }
 
     fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
@@ -29,10 +54,14 @@ impl Actions for SmtDB<'_> {
         self.batch.delete(key);
         Ok(())
     }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self.db)
+    }
 }
 
 impl<'a> SmtDB<'a> {
-    pub fn new(db: &'a DB) -> Self {
+    pub fn new(db: &'a rocksdb::DB) -> Self {
         Self {
             db,
             batch: rocksdb::WriteBatch::default(),
@@ -40,13 +69,119 @@ impl<'a> SmtDB<'a> {
     }
 }
 
-impl Actions for InMemorySmtDB {
+impl Actions for OverlaySmtDB<'_> {
+    fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        self.read_node(key)
+    }
+
+    fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+        self.overlay
+            .insert(pair.key_as_vec(), Some(pair.value_as_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.overlay.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self)
+    }
+}
+
+impl NodeReader for OverlaySmtDB<'_> {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        match self.overlay.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.db.read_node(key),
+        }
+    }
+}
+
+impl<'a> OverlaySmtDB<'a> {
+    pub fn new(db: &'a rocksdb::DB) -> Self {
+        Self {
+            db,
+            overlay: HashMap::new(),
+        }
+    }
+
+    /// into_batch returns the batch of the changed nodes ordered by the key, so that the same changes result in the same batch.
+    pub fn into_batch(self) -> rocksdb::WriteBatch {
+        let mut changes: Vec<(Vec<u8>, VecOption)> = self.overlay.into_iter().collect();
+        changes.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, value) in changes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        batch
+    }
+}
+
+impl Actions for SnapshotSmtDB<'_> {
     fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
-        let result = self.cache.get(key);
-        if let Some(value) = result {
-            return Ok(Some(value.clone()));
+        self.read_node(key)
+    }
+
+    fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+        self.overlay
+            .insert(pair.key_as_vec(), Some(pair.value_as_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.overlay.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self)
+    }
+}
+
+/// The snapshot is shared by the threads, as it reads the same nodes from any thread.
+impl NodeReader for SnapshotSmtDB<'_> {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        match self.overlay.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.snapshot.get(&[consts::Prefix::SMT, key].concat()),
         }
-        Ok(None)
+    }
+}
+
+impl<'a> SnapshotSmtDB<'a> {
+    pub fn new(snapshot: &'a StateSnapshot<'a>) -> Self {
+        Self::with_overlay(snapshot, HashMap::new())
+    }
+
+    /// with_overlay continues from the changes returned by into_overlay on the same snapshot.
+    pub fn with_overlay(
+        snapshot: &'a StateSnapshot<'a>,
+        overlay: HashMap<Vec<u8>, VecOption>,
+    ) -> Self {
+        Self { snapshot, overlay }
+    }
+
+    /// into_overlay returns the changed nodes.
+    pub fn into_overlay(self) -> HashMap<Vec<u8>, VecOption> {
+        self.overlay
+    }
+}
+
+impl InMemorySmtDB {
+    /// merge moves the nodes of the other database into this one.
+    pub fn merge(&mut self, other: InMemorySmtDB) {
+        self.cache.extend(other.cache);
+    }
+}
+
+impl Actions for InMemorySmtDB {
+    fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        self.read_node(key)
     }
 
     fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
@@ -58,24 +193,31 @@ impl Actions for InMemorySmtDB {
         self.cache.remove(key);
         Ok(())
     }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self)
+    }
+}
+
+impl NodeReader for InMemorySmtDB {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        Ok(self.cache.get(key).cloned())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::mpsc;
     use tempdir::TempDir;
 
     use crate::batch::PrefixWriteBatch;
-    use crate::database::types::{DbMessage, Kind};
-    use crate::database::DB;
+    use crate::sparse_merkle_tree::smt::{SparseMerkleTree, UpdateData};
+    use crate::types::{HashKind, HashWithKind, KeyLength};
 
     use super::*;
 
-    fn temp_db() -> (DB, TempDir) {
+    fn temp_db() -> (rocksdb::DB, TempDir) {
         let temp_dir = TempDir::new("test_smt_db").unwrap();
-        let rocks_db = rocksdb::DB::open_default(&temp_dir).unwrap();
-        let (tx, _) = mpsc::channel::<DbMessage>();
-        (DB::new(rocks_db, tx, Kind::Normal), temp_dir)
+        (rocksdb::DB::open_default(&temp_dir).unwrap(), temp_dir)
     }
 
     #[test]
@@ -133,6 +275,93 @@ mod tests {
         assert_eq!(smt_db.batch.len(), 2);
     }
 
+    #[test]
+    fn test_overlay_smt_db() {
+        let (db, _) = temp_db();
+        db.put([consts::Prefix::SMT, b"stored"].concat(), b"value")
+            .unwrap();
+        let mut overlay = OverlaySmtDB::new(&db);
+        assert_eq!(overlay.get(b"stored").unwrap(), Some(b"value".to_vec()));
+
+        overlay.set(&KVPair::new(b"new", b"new_value")).unwrap();
+        overlay.del(b"stored").unwrap();
+        assert_eq!(overlay.get(b"new").unwrap(), Some(b"new_value".to_vec()));
+        assert_eq!(overlay.get(b"stored").unwrap(), None);
+        // database is not changed
+        assert_eq!(
+            db.get([consts::Prefix::SMT, b"stored"].concat()).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            db.get([consts::Prefix::SMT, b"new"].concat()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_overlay_smt_db_into_batch() {
+        let (db, _) = temp_db();
+        db.put([consts::Prefix::SMT, b"stored"].concat(), b"value")
+            .unwrap();
+        let mut overlay = OverlaySmtDB::new(&db);
+        overlay.set(&KVPair::new(b"new", b"new_value")).unwrap();
+        overlay.set(&KVPair::new(b"temp", b"temp_value")).unwrap();
+        overlay.del(b"temp").unwrap();
+        overlay.del(b"stored").unwrap();
+
+        let mut smt_db = SmtDB::new(&db);
+        smt_db.batch = overlay.into_batch();
+        assert_eq!(smt_db.batch.len(), 3);
+        let mut write_batch = PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::SMT);
+        smt_db.batch.iterate(&mut write_batch);
+        db.write(write_batch.batch).unwrap();
+        assert_eq!(smt_db.get(b"new").unwrap(), Some(b"new_value".to_vec()));
+        assert_eq!(smt_db.get(b"stored").unwrap(), None);
+        assert_eq!(smt_db.get(b"temp").unwrap(), None);
+    }
+
+    /// the batch of the tree updated in parallel has the same bytes as the batch of the sequential update.
+    #[test]
+    fn test_smt_db_batch_with_threads() {
+        let (db, _) = temp_db();
+        let data = |keys: std::ops::Range<u32>, value: Vec<u8>| -> Cache {
+            keys.map(|i| {
+                let key = i.to_be_bytes().to_vec().hash_with_kind(HashKind::Key);
+                (key, value.clone())
+            })
+            .collect()
+        };
+        let mut tree = SparseMerkleTree::new(&[], KeyLength(32), Default::default());
+        let mut smt_db = SmtDB::new(&db);
+        let root = tree
+            .commit(&mut smt_db, &UpdateData::new_from(data(0..500, vec![1; 8])))
+            .unwrap();
+        let mut write_batch = PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::SMT);
+        smt_db.batch.iterate(&mut write_batch);
+        db.write(write_batch.batch).unwrap();
+
+        // the stored subtrees are read and deleted by the update, and some keys are deleted
+        let mut batches = vec![];
+        for threads in [1, 4] {
+            let mut tree =
+                SparseMerkleTree::new(&root.lock().unwrap(), KeyLength(32), Default::default())
+                    .with_threads(threads);
+            let mut smt_db = SmtDB::new(&db);
+            let mut update = data(250..750, vec![2; 8]);
+            update.extend(data(0..50, vec![]));
+            let root = tree
+                .commit(&mut smt_db, &UpdateData::new_from(update))
+                .unwrap();
+            batches.push((
+                (**root.lock().unwrap()).clone(),
+                smt_db.batch.data().to_vec(),
+            ));
+        }
+        assert_eq!(batches[0], batches[1]);
+    }
+
     #[test]
     fn test_in_memory_smt_db_get() {
         let mut db = InMemorySmtDB::default();
@@ -0,0 +1,236 @@
+/// test_vectors runs the SMT test vectors in the schema of the lisk-tree fixtures.
+/// The input is { title, summary, config, runner, handler, testCases } with the hex encoded keys and values,
+/// and the output is the same object with the output of each test case computed by the SMT.
+/// The update and the proof use the same code path as in_memory_smt_update and in_memory_smt_prove.
+use serde_json::{json, Map, Value};
+
+use crate::consts;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt::{Proof, SMTError, SparseMerkleTree, UpdateData};
+use crate::sparse_merkle_tree::smt_db::InMemorySmtDB;
+use crate::types::{Cache, KeyLength, NestedVec};
+
+/// DEFAULT_KEY_LENGTH is the key length of the lisk-tree fixtures, used if config.keyLength is not given.
+const DEFAULT_KEY_LENGTH: usize = 32;
+
+fn invalid(message: String) -> SMTError {
+    SMTError::InvalidInput(format!("invalid test vectors: {}", message))
+}
+
+fn hex_array(value: Option<&Value>, name: &str) -> Result<NestedVec, SMTError> {
+    let items = match value {
+        Some(value) => value
+            .as_array()
+            .ok_or_else(|| invalid(format!("{} must be an array", name)))?,
+        None => return Ok(vec![]),
+    };
+    items
+        .iter()
+        .map(|item| {
+            item.as_str()
+                .and_then(|s| hex::decode(s).ok())
+                .ok_or_else(|| invalid(format!("{} must contain hex strings", name)))
+        })
+        .collect()
+}
+
+/// parse_batch returns the changes of { keys, values, deleteKeys }.
+/// The deleted keys are applied after the inserted ones, as the empty values.
+fn parse_batch(batch: &Value) -> Result<Cache, SMTError> {
+    let keys = hex_array(batch.get("keys"), "keys")?;
+    let values = hex_array(batch.get("values"), "values")?;
+    if keys.len() != values.len() {
+        return Err(invalid(String::from(
+            "keys and values must have the same length",
+        )));
+    }
+    let mut data = Cache::new();
+    for (key, value) in keys.into_iter().zip(values) {
+        data.insert(key, value);
+    }
+    for key in hex_array(batch.get("deleteKeys"), "deleteKeys")? {
+        data.insert(key, vec![]);
+    }
+    Ok(data)
+}
+
+fn proof_to_json(proof: &Proof) -> Value {
+    let queries: Vec<Value> = proof
+        .queries
+        .iter()
+        .map(|query| {
+            json!({
+                "bitmap": hex::encode(query.bitmap.as_ref()),
+                "key": hex::encode(query.key()),
+                "value": hex::encode(query.value()),
+            })
+        })
+        .collect();
+    json!({
+        "siblingHashes": proof.sibling_hashes.iter().map(hex::encode).collect::<Vec<_>>(),
+        "queries": queries,
+    })
+}
+
+/// run_case returns the output of the test case.
+/// input.batches of [{ keys, values, deleteKeys }] are committed in order, and the root after each batch is returned in roots.
+/// Without batches, the input itself is committed as a single batch as the lisk-tree fixtures.
+fn run_case(case: &Value, key_length: KeyLength, hasher: HasherKind) -> Result<Value, SMTError> {
+    let input = case
+        .get("input")
+        .ok_or_else(|| invalid(String::from("input must exist in the test case")))?;
+    let batches = match input.get("batches") {
+        Some(batches) => batches
+            .as_array()
+            .ok_or_else(|| invalid(String::from("batches must be an array")))?
+            .iter()
+            .map(parse_batch)
+            .collect::<Result<Vec<Cache>, SMTError>>()?,
+        None => vec![parse_batch(input)?],
+    };
+    let query_keys = hex_array(input.get("queryKeys"), "queryKeys")?;
+
+    let mut db = InMemorySmtDB::default();
+    let mut root = vec![];
+    let mut roots = vec![];
+    for batch in batches {
+        let mut tree =
+            SparseMerkleTree::new_with_hasher(&root, key_length, consts::SUBTREE_HEIGHT, hasher);
+        let result = tree.commit(&mut db, &UpdateData::new_from(batch))?;
+        root = (**result.lock().unwrap()).clone();
+        roots.push(Value::String(hex::encode(&root)));
+    }
+    let mut tree =
+        SparseMerkleTree::new_with_hasher(&root, key_length, consts::SUBTREE_HEIGHT, hasher);
+    let proof = tree.prove(&mut db, &query_keys)?;
+
+    let mut output = Map::new();
+    output.insert(
+        String::from("merkleRoot"),
+        Value::String(hex::encode(&root)),
+    );
+    output.insert(String::from("proof"), proof_to_json(&proof));
+    if input.get("batches").is_some() {
+        output.insert(String::from("roots"), Value::Array(roots));
+    }
+    let mut result = case
+        .as_object()
+        .cloned()
+        .ok_or_else(|| invalid(String::from("test case must be an object")))?;
+    result.insert(String::from("output"), Value::Object(output));
+    Ok(Value::Object(result))
+}
+
+/// run_vectors returns the JSON of the test vectors with the output computed for each test case.
+/// config.keyLength and config.hasher select the key length and the hasher, which are 32 and sha256 by default.
+pub fn run_vectors(input: &str) -> Result<String, SMTError> {
+    let vectors: Value = serde_json::from_str(input).map_err(|err| invalid(err.to_string()))?;
+    let mut result = vectors
+        .as_object()
+        .cloned()
+        .ok_or_else(|| invalid(String::from("test vectors must be an object")))?;
+    let config = vectors.get("config");
+    let key_length = match config.and_then(|c| c.get("keyLength")) {
+        Some(len) => len
+            .as_u64()
+            .filter(|len| *len > 0)
+            .ok_or_else(|| invalid(String::from("keyLength must be a positive integer")))?
+            as usize,
+        None => DEFAULT_KEY_LENGTH,
+    };
+    let hasher = match config.and_then(|c| c.get("hasher")) {
+        Some(name) => name
+            .as_str()
+            .and_then(HasherKind::from_name)
+            .ok_or_else(|| invalid(format!("unknown hasher {}", name)))?,
+        None => HasherKind::default(),
+    };
+    let cases = vectors
+        .get("testCases")
+        .and_then(|cases| cases.as_array())
+        .ok_or_else(|| invalid(String::from("testCases must be an array")))?;
+    let outputs = cases
+        .iter()
+        .map(|case| run_case(case, key_length.into(), hasher))
+        .collect::<Result<Vec<Value>, SMTError>>()?;
+    result.insert(String::from("testCases"), Value::Array(outputs));
+
+    serde_json::to_string_pretty(&result).map_err(|err| invalid(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(output: &str) -> Value {
+        serde_json::from_str(output).unwrap()
+    }
+
+    #[test]
+    fn test_run_vectors_fixtures() {
+        for path in [
+            "test/fixtures/fixtures_no_delete_inclusion_proof.json",
+            "test/fixtures/fixtures_delete_non_inclusion_proof.json",
+        ] {
+            let input = std::fs::read_to_string(path).unwrap();
+            let mut vectors = parse(&input);
+            // the fixtures are large, so that only the first cases are run
+            let cases = vectors["testCases"].as_array_mut().unwrap();
+            cases.truncate(3);
+            let output = run_vectors(&vectors.to_string()).unwrap();
+            assert_eq!(parse(&output), vectors);
+        }
+    }
+
+    #[test]
+    fn test_run_vectors_batches() {
+        let input = json!({
+            "title": "batches",
+            "config": {},
+            "testCases": [{
+                "description": "insert and delete",
+                "input": {
+                    "batches": [
+                        { "keys": ["01".repeat(32), "02".repeat(32)], "values": ["aa", "bb"] },
+                        { "keys": [], "values": [], "deleteKeys": ["01".repeat(32)] },
+                    ],
+                    "queryKeys": ["01".repeat(32), "02".repeat(32)],
+                },
+            }],
+        });
+        let output = parse(&run_vectors(&input.to_string()).unwrap());
+        let case_output = &output["testCases"][0]["output"];
+        let roots = case_output["roots"].as_array().unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_ne!(roots[0], roots[1]);
+        assert_eq!(case_output["merkleRoot"], roots[1]);
+        assert_eq!(output["title"], "batches");
+
+        // the same changes in a single batch result in the same root
+        let single = json!({
+            "testCases": [{
+                "input": {
+                    "keys": ["02".repeat(32)],
+                    "values": ["bb"],
+                    "queryKeys": [],
+                },
+            }],
+        });
+        let single = parse(&run_vectors(&single.to_string()).unwrap());
+        assert_eq!(single["testCases"][0]["output"]["merkleRoot"], roots[1]);
+        assert!(single["testCases"][0]["output"].get("roots").is_none());
+    }
+
+    #[test]
+    fn test_run_vectors_invalid() {
+        assert!(run_vectors("[]").is_err());
+        assert!(
+            run_vectors(r#"{"testCases": [{"input": {"keys": ["zz"], "values": ["00"]}}]}"#)
+                .is_err()
+        );
+        assert!(
+            run_vectors(r#"{"testCases": [{"input": {"keys": ["00"], "values": []}}]}"#).is_err()
+        );
+        assert!(run_vectors(r#"{"config": {"hasher": "keccak"}, "testCases": []}"#).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+/// bloom provides in-memory bloom filter over the keys in the state namespace.
+/// It is advisory only, and it answers either "maybe exist" or "definitely not exist".
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::consts;
+use crate::database::column_families::{self, StateSnapshot};
+
+/// MIN_CAPACITY is the minimum number of keys the filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+
+/// BloomFilter is a fixed size bloom filter using double hashing.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    capacity: usize,
+    len: usize,
+}
+
+/// BloomStats holds counters to measure effectiveness of the filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BloomStats {
+    /// checks is the number of lookups consulted the filter.
+    pub checks: u64,
+    /// definite_misses is the number of lookups answered without reading the database.
+    pub definite_misses: u64,
+    /// false_positives is the number of lookups the filter answered "maybe" for non-existing key.
+    pub false_positives: u64,
+    /// rebuilds is the number of times the filter is built from the database.
+    pub rebuilds: u64,
+}
+
+/// StateBloom maintains the filter for StateDB.
+/// The filter is built lazily from a snapshot of the database on first use, and after it gets stale.
+/// The build runs off the owner thread, and the lookups until it is installed are not answered by the filter.
+pub struct StateBloom {
+    filter: Option<BloomFilter>,
+    false_positive_rate: f64,
+    stats: BloomStats,
+    /// building holds the keys created since the build is started, which may not be in its snapshot.
+    building: Option<Vec<Vec<u8>>>,
+    /// generation is incremented on invalidation, so that the filter of the build started before is discarded.
+    generation: u64,
+}
+
+/// BloomBuild is the build of the filter started by StateBloom::start_build.
+pub struct BloomBuild {
+    generation: u64,
+    false_positive_rate: f64,
+}
+
+impl BloomFilter {
+    /// new creates filter sized for the capacity with the false positive rate.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let num_bits = num_bits.max(64);
+        let num_hashes = ((num_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; (num_bits / 64 + 1) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    fn hashes(key: &[u8]) -> (u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0xa5_u8.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        (h1, h2)
+    }
+
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let (h1, h2) = Self::hashes(key);
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        let positions: Vec<u64> = self.positions(key).collect();
+        for pos in positions {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+        self.len += 1;
+    }
+
+    /// may_contain returns false only if the key was never inserted.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    /// is_full returns true when more keys than the capacity are inserted,
+    /// and the false positive rate is no longer guaranteed.
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// fill inserts all the keys in the state namespace of the snapshot without the prefix.
+fn fill(snapshot: &StateSnapshot, filter: &mut BloomFilter) -> Result<(), rocksdb::Error> {
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let iter = snapshot.iterator_opt(
+        consts::Prefix::STATE,
+        rocksdb::IteratorMode::From(consts::Prefix::STATE, rocksdb::Direction::Forward),
+        read_options,
+    );
+    for kv in iter {
+        let (key, _) = kv?;
+        if !key.starts_with(consts::Prefix::STATE) {
+            break;
+        }
+        filter.insert(&key[consts::Prefix::STATE.len()..]);
+    }
+
+    Ok(())
+}
+
+impl BloomBuild {
+    /// run creates the filter from all the keys in the state namespace of a snapshot of the database.
+    /// It reads the whole namespace, and it must run on the DB thread.
+    pub fn run(&self, conn: &rocksdb::DB) -> Result<BloomFilter, rocksdb::Error> {
+        let estimated = match column_families::handle(conn, consts::Prefix::STATE) {
+            Some(cf) => conn.property_int_value_cf(cf, "rocksdb.estimate-num-keys")?,
+            None => conn.property_int_value("rocksdb.estimate-num-keys")?,
+        };
+        let estimated = estimated.unwrap_or(0) as usize;
+        let snapshot = StateSnapshot::new(conn);
+        let mut filter = BloomFilter::new(estimated * 2, self.false_positive_rate);
+        fill(&snapshot, &mut filter)?;
+        // size again if the estimation was too small
+        if filter.is_full() {
+            filter = BloomFilter::new(filter.len() * 2, self.false_positive_rate);
+            fill(&snapshot, &mut filter)?;
+        }
+
+        Ok(filter)
+    }
+}
+
+impl StateBloom {
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            filter: None,
+            false_positive_rate,
+            stats: BloomStats::default(),
+            building: None,
+            generation: 0,
+        }
+    }
+
+    /// needs_build returns true if the filter is neither built nor being built.
+    pub fn needs_build(&self) -> bool {
+        self.filter.is_none() && self.building.is_none()
+    }
+
+    /// start_build returns the build to run if the filter is neither built nor being built.
+    /// The keys inserted until the build is finished are added to the built filter.
+    pub fn start_build(&mut self) -> Option<BloomBuild> {
+        if !self.needs_build() {
+            return None;
+        }
+        self.building = Some(vec![]);
+        Some(BloomBuild {
+            generation: self.generation,
+            false_positive_rate: self.false_positive_rate,
+        })
+    }
+
+    /// finish_build installs the filter of the build. The filter is discarded if it is invalidated after the start,
+    /// and the failed build is started again on the next start_build.
+    pub fn finish_build(
+        &mut self,
+        build: BloomBuild,
+        result: Result<BloomFilter, rocksdb::Error>,
+    ) {
+        if build.generation != self.generation {
+            return;
+        }
+        let created = match self.building.take() {
+            Some(created) => created,
+            None => return,
+        };
+        if let Ok(filter) = result {
+            self.filter = Some(filter);
+            self.stats.rebuilds += 1;
+            self.insert(created.iter());
+        }
+    }
+
+    /// may_exist returns false if the key definitely does not exist in the state,
+    /// or None if the filter is not built yet. Key should not include the state prefix.
+    pub fn may_exist(&mut self, key: &[u8]) -> Option<bool> {
+        let filter = self.filter.as_ref()?;
+        self.stats.checks += 1;
+        let result = filter.may_contain(key);
+        if !result {
+            self.stats.definite_misses += 1;
+        }
+        Some(result)
+    }
+
+    /// record_false_positive records that the filter answered "maybe" for non-existing key.
+    pub fn record_false_positive(&mut self) {
+        self.stats.false_positives += 1;
+    }
+
+    /// insert adds newly created keys in the state to the filter.
+    /// When the filter is being built, the keys are added once it is built,
+    /// and when it is not built yet, the keys will be included in the next build.
+    pub fn insert<'a>(&mut self, keys: impl Iterator<Item = &'a Vec<u8>>) {
+        if let Some(created) = self.building.as_mut() {
+            created.extend(keys.cloned());
+            return;
+        }
+        if let Some(filter) = self.filter.as_mut() {
+            for key in keys {
+                filter.insert(key);
+            }
+            if filter.is_full() {
+                self.filter = None;
+            }
+        }
+    }
+
+    /// invalidate marks the filter stale, including the one being built. It is rebuilt on next use.
+    pub fn invalidate(&mut self) {
+        self.filter = None;
+        self.building = None;
+        self.generation += 1;
+    }
+
+    pub fn stats(&self) -> BloomStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::batch;
+    use crate::state::state_writer::StateWriter;
+    use crate::types::SharedKVPair;
+
+    #[test]
+    fn test_bloom_filter_no_false_negative() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        assert!(filter.is_empty());
+        let keys: Vec<Vec<u8>> = (0..10_000_u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        for key in keys.iter() {
+            filter.insert(key);
+        }
+        assert_eq!(filter.len(), 10_000);
+        assert!(!filter.is_full());
+        for key in keys.iter() {
+            assert!(filter.may_contain(key));
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000_u32 {
+            filter.insert(&i.to_be_bytes());
+        }
+        let false_positives = (10_000..110_000_u32)
+            .filter(|i| filter.may_contain(&i.to_be_bytes()))
+            .count();
+        // expected around 1,000 for 100,000 lookups
+        assert!(false_positives < 2_000, "{}", false_positives);
+    }
+
+    /// build builds the filter as StateDB does on the DB thread.
+    fn build(bloom: &mut StateBloom, db: &rocksdb::DB) {
+        let build = bloom.start_build().unwrap();
+        let result = build.run(db);
+        bloom.finish_build(build, result);
+    }
+
+    #[test]
+    fn test_state_bloom() {
+        let temp_dir = TempDir::new("test_state_bloom").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 0..100_u8 {
+            db.put([consts::Prefix::STATE, &[i]].concat(), [i]).unwrap();
+            // keys in other namespace are not included
+            db.put([consts::Prefix::SMT, &[i, i]].concat(), [i])
+                .unwrap();
+        }
+
+        let mut bloom = StateBloom::new(0.01);
+        // not answered until the filter is built
+        assert_eq!(bloom.may_exist(&[0]), None);
+        build(&mut bloom, &db);
+        assert!(bloom.start_build().is_none());
+        for i in 0..100_u8 {
+            assert_eq!(bloom.may_exist(&[i]), Some(true));
+        }
+        assert_eq!(bloom.stats().rebuilds, 1);
+        assert_eq!(bloom.stats().checks, 100);
+
+        let misses = (0..100_u8)
+            .filter(|i| bloom.may_exist(&[*i, *i]) == Some(false))
+            .count() as u64;
+        assert!(misses > 90);
+        assert_eq!(bloom.stats().definite_misses, misses);
+
+        bloom.insert([vec![200, 200]].iter());
+        assert_eq!(bloom.may_exist(&[200, 200]), Some(true));
+
+        // after invalidation, built from the database again
+        db.put([consts::Prefix::STATE, &[201, 201]].concat(), [1])
+            .unwrap();
+        bloom.invalidate();
+        assert_eq!(bloom.may_exist(&[201, 201]), None);
+        build(&mut bloom, &db);
+        assert_eq!(bloom.may_exist(&[201, 201]), Some(true));
+        assert_eq!(bloom.stats().rebuilds, 2);
+    }
+
+    #[test]
+    fn test_state_bloom_changed_while_building() {
+        let temp_dir = TempDir::new("test_state_bloom_building").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        db.put([consts::Prefix::STATE, &[1, 1]].concat(), [1])
+            .unwrap();
+
+        // keys created after the snapshot of the build are added once it is built
+        let mut bloom = StateBloom::new(0.01);
+        let build = bloom.start_build().unwrap();
+        assert!(bloom.start_build().is_none());
+        let result = build.run(&db);
+        db.put([consts::Prefix::STATE, &[2, 2]].concat(), [2])
+            .unwrap();
+        bloom.insert([vec![2, 2]].iter());
+        assert_eq!(bloom.may_exist(&[2, 2]), None);
+        bloom.finish_build(build, result);
+        assert_eq!(bloom.may_exist(&[1, 1]), Some(true));
+        assert_eq!(bloom.may_exist(&[2, 2]), Some(true));
+
+        // filter of the build started before the invalidation is discarded
+        bloom.invalidate();
+        let stale = bloom.start_build().unwrap();
+        let result = stale.run(&db);
+        bloom.invalidate();
+        bloom.finish_build(stale, result);
+        assert_eq!(bloom.may_exist(&[1, 1]), None);
+        assert_eq!(bloom.stats().rebuilds, 1);
+        build(&mut bloom, &db);
+        assert_eq!(bloom.stats().rebuilds, 2);
+    }
+
+    #[test]
+    fn test_state_bloom_commit_and_revert() {
+        let temp_dir = TempDir::new("test_state_bloom_revert").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        db.put([consts::Prefix::STATE, &[1, 1]].concat(), [1])
+            .unwrap();
+
+        let mut bloom = StateBloom::new(0.01);
+        build(&mut bloom, &db);
+        assert_eq!(bloom.may_exist(&[1, 1]), Some(true));
+
+        // commit which creates [2, 2] and deletes [1, 1]
+        let mut writer = StateWriter::default();
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 1], &[1]))
+            .unwrap();
+        writer.delete(&[1, 1]).unwrap();
+        writer.cache_new(&SharedKVPair::new(&[2, 2], &[2])).unwrap();
+        let mut write_batch = batch::PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::STATE);
+        let diff = writer.commit(&mut write_batch).unwrap();
+        db.write(write_batch.batch).unwrap();
+        bloom.insert(diff.created().iter());
+        assert_eq!(bloom.may_exist(&[2, 2]), Some(true));
+
+        // filter built after the deletion does not include [1, 1]
+        bloom.invalidate();
+        build(&mut bloom, &db);
+        assert_eq!(bloom.may_exist(&[2, 2]), Some(true));
+
+        // revert restores [1, 1], so the filter must be rebuilt
+        let mut write_batch = batch::PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::STATE);
+        diff.revert_commit(&mut write_batch);
+        db.write(write_batch.batch).unwrap();
+        bloom.invalidate();
+        build(&mut bloom, &db);
+        assert_eq!(bloom.may_exist(&[1, 1]), Some(true));
+        assert_eq!(bloom.stats().rebuilds, 3);
+    }
+}
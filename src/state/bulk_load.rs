@@ -0,0 +1,70 @@
+/// bulk_load provides the rocksdb options of the bulk load of the initial sync.
+/// The commits in the bulk load write no diff, and the larger memtables without the automatic compaction
+/// reduce the write amplification until the bulk load is turned off and the database is compacted.
+use crate::consts;
+use crate::database::column_families;
+use crate::database::types::TuningOptions;
+
+/// options returns the mutable rocksdb options of the mode. Turning it off restores the configured options.
+pub fn options(enabled: bool, tuning: &TuningOptions) -> Vec<(&'static str, String)> {
+    let write_buffer_size = if enabled {
+        consts::BULK_LOAD_WRITE_BUFFER_SIZE
+    } else {
+        tuning
+            .write_buffer_size
+            .unwrap_or(consts::DEFAULT_WRITE_BUFFER_SIZE)
+    };
+    vec![
+        ("write_buffer_size", write_buffer_size.to_string()),
+        ("disable_auto_compactions", enabled.to_string()),
+    ]
+}
+
+/// switch sets the options of the mode on all the column families.
+/// Turning it off compacts the database, so that the files written without the automatic compaction are merged.
+pub fn switch(
+    conn: &rocksdb::DB,
+    enabled: bool,
+    tuning: &TuningOptions,
+) -> Result<(), rocksdb::Error> {
+    let options = options(enabled, tuning);
+    let options: Vec<(&str, &str)> = options
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    column_families::set_options(conn, &options)?;
+    if !enabled {
+        column_families::compact_range(conn, None, None);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulk_load_options() {
+        let tuning = TuningOptions::default();
+        assert_eq!(
+            options(true, &tuning),
+            vec![
+                ("write_buffer_size", String::from("268435456")),
+                ("disable_auto_compactions", String::from("true")),
+            ]
+        );
+        assert_eq!(
+            options(false, &tuning),
+            vec![
+                ("write_buffer_size", String::from("67108864")),
+                ("disable_auto_compactions", String::from("false")),
+            ]
+        );
+        // configured size is restored
+        let tuning = TuningOptions {
+            write_buffer_size: Some(4 * 1024 * 1024),
+            ..Default::default()
+        };
+        assert_eq!(options(false, &tuning)[0].1, "4194304");
+    }
+}
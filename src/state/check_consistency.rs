@@ -0,0 +1,259 @@
+/// check_consistency provides the check of the latest commit against the tree, the diffs and the root history.
+/// State, SMT nodes, diff and current state of a commit are written in a single batch,
+/// but the databases written by the older releases may have them out of sync after a crash.
+use std::convert::TryInto;
+
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt;
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::state::root_history;
+use crate::types::{BlockHeight, KeyLength};
+use crate::utils;
+
+/// ConsistencyReport holds the result of the check of the latest commit.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// height is the version of the current state.
+    pub height: u32,
+    /// root is the root of the current state.
+    pub root: Vec<u8>,
+    /// root_in_tree is true if the root node is stored with the matching hash, or the root is the empty tree.
+    pub root_in_tree: bool,
+    /// diff_exists is true if the diff of the height is stored.
+    pub diff_exists: bool,
+    /// recorded_root is the root recorded at the height. None if the height is not recorded.
+    pub recorded_root: Option<Vec<u8>>,
+    /// pending_commit is true if the commit written in chunks is interrupted.
+    pub pending_commit: bool,
+    /// issues describes each inconsistency found.
+    pub issues: Vec<String>,
+}
+
+impl ConsistencyReport {
+    /// is_consistent returns true if no issue is found.
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// RootNode is the state of the stored node of the root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootNode {
+    /// Empty is the root of the empty tree, which has no node.
+    Empty,
+    Matched,
+    /// Mismatched is the node whose hash does not match the root, or which cannot be decoded.
+    Mismatched,
+    Missing,
+}
+
+/// diff_key returns the key of the diff stored for the height.
+pub fn diff_key(height: u32) -> Vec<u8> {
+    [Prefix::DIFF, &height.to_be_bytes()].concat()
+}
+
+/// current_state returns the current state on the snapshot, or the empty state at height 0 if nothing is committed.
+/// The root of the empty state is the empty hash of the hasher.
+pub fn current_state(
+    snapshot: &StateSnapshot,
+    hasher: HasherKind,
+) -> Result<CurrentState, CurrentStateError> {
+    match snapshot.get(Prefix::CURRENT_STATE)? {
+        Some(bytes) => Ok(CurrentState::from_bytes(&bytes)?.0),
+        None => Ok(CurrentState::new(&hasher.empty_hash(0), BlockHeight(0))),
+    }
+}
+
+/// root_node checks the stored node of the root against its hash by the hasher.
+pub fn root_node(
+    snapshot: &StateSnapshot,
+    root: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<RootNode, rocksdb::Error> {
+    if hasher.is_empty_hash(root) {
+        return Ok(RootNode::Empty);
+    }
+    let node = match snapshot.get(&[Prefix::SMT, root].concat())? {
+        Some(node) => node,
+        None => return Ok(RootNode::Missing),
+    };
+    let matched = smt::calculate_node_hash_with_hasher(&node, key_length, hasher)
+        .map(|hash| hash == root)
+        .unwrap_or(false);
+    Ok(if matched {
+        RootNode::Matched
+    } else {
+        RootNode::Mismatched
+    })
+}
+
+/// has_diff_below returns true if any diff lower than the height is stored.
+fn has_diff_below(snapshot: &StateSnapshot, height: u32) -> Result<bool, rocksdb::Error> {
+    let end = diff_key(height);
+    let mode = rocksdb::IteratorMode::From(&end, rocksdb::Direction::Reverse);
+    for key_val in snapshot.iterator(Prefix::DIFF, mode) {
+        let (key, _) = key_val?;
+        if !key.starts_with(Prefix::DIFF) {
+            break;
+        }
+        let stored: [u8; 4] = match key[Prefix::DIFF.len()..].try_into() {
+            Ok(stored) => stored,
+            Err(_) => continue,
+        };
+        if u32::from_be_bytes(stored) < height {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// check_consistency checks the current state on the snapshot of the database.
+/// - the root node must be stored, and its hash must match the root.
+/// - the diff of the height must be stored if the diffs of the lower heights are stored.
+///   State imported from the snapshot and the height committed in the bulk load have no diff, so that they are not reported.
+/// - the root recorded at the height must match the root.
+/// - no commit written in chunks is interrupted.
+pub fn check_consistency(
+    conn: &rocksdb::DB,
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<ConsistencyReport, CurrentStateError> {
+    let snapshot = StateSnapshot::new(conn);
+    let current_state = current_state(&snapshot, hasher)?;
+    let height: u32 = current_state.version().into();
+    let root = current_state.root().to_vec();
+
+    let mut report = ConsistencyReport {
+        height,
+        root: root.clone(),
+        ..Default::default()
+    };
+
+    report.root_in_tree = matches!(
+        root_node(&snapshot, &root, key_length, hasher)?,
+        RootNode::Empty | RootNode::Matched
+    );
+    if !report.root_in_tree {
+        report.issues.push(format!(
+            "root of height {} is not found in the tree",
+            height
+        ));
+    }
+
+    report.diff_exists = snapshot.get(&diff_key(height))?.is_some();
+    if height > 0
+        && !report.diff_exists
+        && has_diff_below(&snapshot, height)?
+        && root_history::bulk_loaded(conn, BlockHeight(height))?.is_none()
+    {
+        report
+            .issues
+            .push(format!("diff of height {} is missing", height));
+    }
+
+    report.recorded_root = snapshot.get(&root_history::key(BlockHeight(height)))?;
+    if let Some(recorded_root) = report.recorded_root.as_ref() {
+        if !utils::is_bytes_equal(recorded_root, &root) {
+            report.issues.push(format!(
+                "root recorded at height {} does not match the current state",
+                height
+            ));
+        }
+    }
+
+    report.pending_commit = snapshot.get(Prefix::JOURNAL)?.is_some();
+    if report.pending_commit {
+        report
+            .issues
+            .push(String::from("commit written in chunks is interrupted"));
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::database::column_families;
+    use crate::state::state_writer::StateWriter;
+    use crate::state::test_utils::{open, state_key};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    #[test]
+    fn test_check_consistency() {
+        let temp_dir = TempDir::new("test_check_consistency").unwrap();
+        let store = open(&temp_dir, false);
+        let report = check_consistency(store.conn(), KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.height, 0);
+
+        let mut root = smt::EMPTY_HASH.to_vec();
+        for height in 1..=3 {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(height), &[height; 4])
+                .unwrap();
+            root = store
+                .commit(&writer, BlockHeight(height.into()), &root)
+                .unwrap();
+        }
+        let report = check_consistency(store.conn(), KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.height, 3);
+        assert_eq!(report.root, root);
+        assert!(report.root_in_tree);
+        assert!(report.diff_exists);
+        assert_eq!(report.recorded_root, Some(root.clone()));
+
+        // diff of the latest height is lost by the crash
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete(diff_key(3));
+        batch.put(root_history::key(BlockHeight(3)), [1; 32]);
+        column_families::write(store.conn(), batch).unwrap();
+        let report = check_consistency(store.conn(), KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert!(!report.diff_exists);
+        assert_eq!(
+            report.issues,
+            vec![
+                String::from("diff of height 3 is missing"),
+                String::from("root recorded at height 3 does not match the current state"),
+            ]
+        );
+
+        // root node is not written
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete([Prefix::SMT, &root].concat());
+        column_families::write(store.conn(), batch).unwrap();
+        let report = check_consistency(store.conn(), KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert!(!report.root_in_tree);
+        assert_eq!(report.issues.len(), 3);
+    }
+
+    #[test]
+    fn test_check_consistency_without_diffs() {
+        let temp_dir = TempDir::new("test_check_consistency_without_diffs").unwrap();
+        let store = open(&temp_dir, false);
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(1), &[1; 4]).unwrap();
+        let root = store
+            .commit(&writer, BlockHeight(5), &smt::EMPTY_HASH)
+            .unwrap();
+
+        // state imported from the snapshot has no diff and no root history
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete(diff_key(5));
+        batch.delete(root_history::key(BlockHeight(5)));
+        column_families::write(store.conn(), batch).unwrap();
+        let report = check_consistency(store.conn(), KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.root, root);
+        assert!(!report.diff_exists);
+        assert_eq!(report.recorded_root, None);
+    }
+}
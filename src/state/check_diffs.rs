@@ -0,0 +1,271 @@
+/// check_diffs provides the scan of the stored diffs to find the ones which cannot be decoded.
+/// The scan only reads the diffs, so it can be used to assess the damage before reverting.
+use std::convert::TryInto;
+
+use crate::codec::CodecError;
+use crate::consts::Prefix;
+use crate::database::column_families::{self, StateSnapshot};
+use crate::diff::{self, Diff, StoredDiff, StoredPair};
+use crate::state::diff_values;
+use crate::state::store::DataStoreError;
+use crate::types::KVPair;
+
+/// CorruptedDiff holds the height of the diff which cannot be decoded and the reason.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptedDiff {
+    pub height: u32,
+    pub error: String,
+}
+
+/// DiffsReport holds the result of the scan.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffsReport {
+    /// checked is the number of the diffs checked.
+    pub checked: u64,
+    pub corrupted: Vec<CorruptedDiff>,
+}
+
+/// SectionStats holds the sizes of the pairs in a section of the diff.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SectionStats {
+    pub count: u64,
+    pub key_bytes: u64,
+    /// value_bytes is the size of the resolved values, including the referenced ones.
+    pub value_bytes: u64,
+    /// referenced is the number of the values stored in the old-value store.
+    pub referenced: u64,
+}
+
+/// DiffStats holds the sizes of the diff stored for the height.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffStats {
+    pub height: u32,
+    /// encoded_bytes is the size of the stored diff, excluding the referenced values.
+    pub encoded_bytes: u64,
+    pub created: SectionStats,
+    pub updated: SectionStats,
+    pub deleted: SectionStats,
+}
+
+fn corrupted(height: u32, err: CodecError, bytes: &[u8]) -> String {
+    format!(
+        "diff at height {} is corrupted: {} (bytes: {})",
+        height,
+        err,
+        diff::hex_snippet(bytes)
+    )
+}
+
+/// decode_diff decodes the diff stored for the height without resolving the referenced values.
+/// The error message includes the height and the hex snippet of the stored bytes.
+pub fn decode_diff(height: u32, bytes: &[u8]) -> Result<StoredDiff, String> {
+    StoredDiff::decode(bytes).map_err(|err| corrupted(height, err, bytes))
+}
+
+/// read_diff decodes the diff stored for the height, and resolves the referenced values from the old-value store.
+/// The diff which cannot be decoded or whose value is missing is DiffCorrupted.
+pub fn read_diff(conn: &rocksdb::DB, height: u32, bytes: &[u8]) -> Result<Diff, DataStoreError> {
+    resolve_diff(height, bytes, |hash| diff_values::get(conn, hash))
+}
+
+/// read_snapshot_diff decodes the diff as read_diff, and resolves the referenced values from the snapshot.
+pub fn read_snapshot_diff(
+    snapshot: &StateSnapshot,
+    height: u32,
+    bytes: &[u8],
+) -> Result<Diff, DataStoreError> {
+    resolve_diff(height, bytes, |hash| {
+        diff_values::get_snapshot(snapshot, hash)
+    })
+}
+
+fn resolve_diff(
+    height: u32,
+    bytes: &[u8],
+    get_value: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error>,
+) -> Result<Diff, DataStoreError> {
+    decode_diff(height, bytes)
+        .map_err(DataStoreError::DiffCorrupted)?
+        .resolve(|hash| match get_value(hash) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(DataStoreError::DiffCorrupted(corrupted(
+                height,
+                CodecError::MissingValue(diff::hex_snippet(hash)),
+                bytes,
+            ))),
+            Err(err) => Err(DataStoreError::Unknown(err.to_string())),
+        })
+}
+
+fn section_stats(stored: &[StoredPair], resolved: &[KVPair]) -> SectionStats {
+    SectionStats {
+        count: resolved.len() as u64,
+        key_bytes: resolved.iter().map(|pair| pair.key().len() as u64).sum(),
+        value_bytes: resolved.iter().map(|pair| pair.value().len() as u64).sum(),
+        referenced: stored
+            .iter()
+            .filter(|pair| matches!(pair.value, diff::DiffValue::Ref(_)))
+            .count() as u64,
+    }
+}
+
+/// diff_stats returns the sizes of the diff stored for the height.
+pub fn diff_stats(conn: &rocksdb::DB, height: u32) -> Result<DiffStats, DataStoreError> {
+    let bytes = column_families::get(conn, &[Prefix::DIFF, &height.to_be_bytes()].concat())
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+        .ok_or(DataStoreError::DiffNotFound(height as usize))?;
+    let stored = decode_diff(height, &bytes).map_err(DataStoreError::DiffCorrupted)?;
+    let diff = read_diff(conn, height, &bytes)?;
+    let created_keys = diff.created();
+
+    Ok(DiffStats {
+        height,
+        encoded_bytes: bytes.len() as u64,
+        created: SectionStats {
+            count: created_keys.len() as u64,
+            key_bytes: created_keys.iter().map(|key| key.len() as u64).sum(),
+            ..SectionStats::default()
+        },
+        updated: section_stats(stored.updated(), diff.updated()),
+        deleted: section_stats(stored.deleted(), diff.deleted()),
+    })
+}
+
+/// read_diff_entry decodes the diff stored with the key, which is the height without the diff prefix.
+/// The key which is not 4 bytes height is DiffCorrupted, as it is not written by the commit.
+pub fn read_diff_entry(
+    conn: &rocksdb::DB,
+    key: &[u8],
+    bytes: &[u8],
+) -> Result<(u32, Diff), DataStoreError> {
+    let height: [u8; 4] = key.try_into().map_err(|_| {
+        DataStoreError::DiffCorrupted(format!(
+            "diff key {} is not a height",
+            diff::hex_snippet(key)
+        ))
+    })?;
+    let height = u32::from_be_bytes(height);
+    Ok((height, read_diff(conn, height, bytes)?))
+}
+
+/// check_diffs decodes all the diffs in the diff prefix on the snapshot of the database.
+/// Keys which are not 4 bytes height are not written by the commit, and they are skipped.
+pub fn check_diffs(conn: &rocksdb::DB) -> Result<DiffsReport, rocksdb::Error> {
+    let snapshot = StateSnapshot::new(conn);
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mode = rocksdb::IteratorMode::From(Prefix::DIFF, rocksdb::Direction::Forward);
+
+    let mut report = DiffsReport::default();
+    for key_val in snapshot.iterator_opt(Prefix::DIFF, mode, read_options) {
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::DIFF) {
+            break;
+        }
+        let height: [u8; 4] = match key[Prefix::DIFF.len()..].try_into() {
+            Ok(height) => height,
+            Err(_) => continue,
+        };
+        let height = u32::from_be_bytes(height);
+        report.checked += 1;
+        let diff = match decode_diff(height, &value) {
+            Ok(diff) => diff,
+            Err(error) => {
+                report.corrupted.push(CorruptedDiff { height, error });
+                continue;
+            },
+        };
+        // the referenced values must be stored until the diff is removed
+        for hash in diff.refs() {
+            if snapshot.get(&diff_values::key(hash))?.is_none() {
+                let error = corrupted(
+                    height,
+                    CodecError::MissingValue(diff::hex_snippet(hash)),
+                    &value,
+                );
+                report.corrupted.push(CorruptedDiff { height, error });
+                break;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn diff_key(height: u32) -> Vec<u8> {
+        [Prefix::DIFF, &height.to_be_bytes()].concat()
+    }
+
+    #[test]
+    fn test_check_diffs() {
+        let temp_dir = TempDir::new("test_check_diffs").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for height in 1..=5 {
+            let diff = Diff::new(
+                vec![vec![height as u8; 10]],
+                vec![KVPair::new(&[1; 10], &[height as u8; 5])],
+                vec![],
+            );
+            db.put(diff_key(height), diff.encode()).unwrap();
+        }
+        // keys out of the diff prefix are not checked
+        db.put([Prefix::SMT, &[9; 4]].concat(), [1]).unwrap();
+        db.put([Prefix::CURRENT_STATE, &[9; 4]].concat(), [1])
+            .unwrap();
+
+        let report = check_diffs(&db).unwrap();
+        assert_eq!(report.checked, 5);
+        assert!(report.corrupted.is_empty());
+
+        let mut value = db.get(diff_key(3)).unwrap().unwrap();
+        value[1] ^= 0xff;
+        db.put(diff_key(3), &value).unwrap();
+        db.put(diff_key(5), [10, 10, 1]).unwrap();
+
+        let report = check_diffs(&db).unwrap();
+        assert_eq!(report.checked, 5);
+        assert_eq!(
+            report
+                .corrupted
+                .iter()
+                .map(|diff| diff.height)
+                .collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+        assert_eq!(
+            report.corrupted[1].error,
+            "diff at height 5 is corrupted: Invalid bytes length (bytes: 0a0a01)"
+        );
+    }
+
+    #[test]
+    fn test_read_diff_entry() {
+        let temp_dir = TempDir::new("test_read_diff_entry").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let diff = Diff::new(
+            vec![vec![1; 10]],
+            vec![KVPair::new(&[2; 10], &[3; 5])],
+            vec![KVPair::new(&[4; 10], &[5; 5])],
+        );
+        let encoded = diff.encode();
+
+        assert_eq!(
+            read_diff_entry(&db, &7_u32.to_be_bytes(), &encoded).unwrap(),
+            (7, diff)
+        );
+        assert!(matches!(
+            read_diff_entry(&db, &[7], &encoded),
+            Err(DataStoreError::DiffCorrupted(_))
+        ));
+        assert!(matches!(
+            read_diff_entry(&db, &7_u32.to_be_bytes(), &[10, 10, 1]),
+            Err(DataStoreError::DiffCorrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+/// check_integrity provides the integrity scan of the state at the configurable depth, which is run on the startup.
+/// Quick level checks the records of the latest commit, and full level walks the whole tree against the state keys.
+/// The scan only reads the snapshot of the database, and the problems found are reported instead of returned as the error.
+use std::collections::HashMap;
+
+use crate::consts::{self, Prefix};
+use crate::database::column_families::StateSnapshot;
+use crate::database::options::VerifyNodesOption;
+use crate::error::DbError;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt::{SparseMerkleTree, SubtreeCheck};
+use crate::sparse_merkle_tree::smt_db::SnapshotSmtDB;
+use crate::state::check_consistency::{self, RootNode};
+use crate::state::check_diffs;
+use crate::state::current_state::CurrentStateError;
+use crate::state::value_compression;
+use crate::types::{HashKind, HashWithKind, KeyLength};
+
+/// IntegrityLevel is the depth of the integrity scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegrityLevel {
+    /// Quick checks that the current state decodes, the root node is stored, and the latest diff decodes.
+    Quick,
+    /// Full additionally checks every node reachable from the root, and the leaf of every state key.
+    Full,
+}
+
+impl IntegrityLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Quick => "quick",
+            Self::Full => "full",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quick" => Some(Self::Quick),
+            "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+}
+
+/// IntegrityIssue describes a problem found by the scan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityIssue {
+    pub message: String,
+    /// key is the node hash, the state key or the hashed key of the leaf. None if the problem is not of a key.
+    pub key: Option<Vec<u8>>,
+    /// height is the height of the record. None if the problem is not of a height.
+    pub height: Option<u32>,
+}
+
+/// IntegrityReport holds the result of the scan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub level: IntegrityLevel,
+    /// height is the version of the current state.
+    pub height: u32,
+    /// root is the root of the current state. Empty if the current state cannot be decoded.
+    pub root: Vec<u8>,
+    /// checked_nodes is the number of the SMT nodes checked by the full scan.
+    pub checked_nodes: u64,
+    /// checked_keys is the number of the state keys checked by the full scan.
+    pub checked_keys: u64,
+    /// error_count is the number of the problems found.
+    pub error_count: u64,
+    /// errors holds the problems found, up to the max errors.
+    pub errors: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    fn new(level: IntegrityLevel) -> Self {
+        Self {
+            level,
+            height: 0,
+            root: vec![],
+            checked_nodes: 0,
+            checked_keys: 0,
+            error_count: 0,
+            errors: vec![],
+        }
+    }
+
+    /// ok returns true if no problem is found.
+    pub fn ok(&self) -> bool {
+        self.error_count == 0
+    }
+
+    /// truncated returns true if more problems are found than the errors reported.
+    pub fn truncated(&self) -> bool {
+        self.error_count > self.errors.len() as u64
+    }
+
+    fn checked(&self) -> u64 {
+        self.checked_nodes + self.checked_keys
+    }
+}
+
+/// Scan keeps the report with the limits of the scan.
+struct Scan<'a, P: FnMut(&IntegrityReport), C: Fn() -> bool> {
+    report: IntegrityReport,
+    options: &'a VerifyNodesOption,
+    on_progress: P,
+    is_cancelled: C,
+}
+
+impl<P: FnMut(&IntegrityReport), C: Fn() -> bool> Scan<'_, P, C> {
+    fn issue(&mut self, message: String, key: Option<&Vec<u8>>, height: Option<u32>) {
+        self.report.error_count += 1;
+        if self.report.errors.len() < self.options.max_errors {
+            self.report.errors.push(IntegrityIssue {
+                message,
+                key: key.cloned(),
+                height,
+            });
+        }
+    }
+
+    /// step is called after each node or key, and reports the progress at every progress interval.
+    fn step(&mut self) -> Result<(), DbError> {
+        if (self.is_cancelled)() {
+            return Err(DbError::Cancelled("Integrity check"));
+        }
+        let checked = self.report.checked();
+        if self.options.progress_interval > 0 && checked % self.options.progress_interval == 0 {
+            (self.on_progress)(&self.report);
+        }
+        Ok(())
+    }
+}
+
+/// check_quick checks the records of the latest commit. It returns true if the tree can be walked from the root,
+/// which is not the case if the current state cannot be decoded or the root node is broken.
+fn check_quick<P: FnMut(&IntegrityReport), C: Fn() -> bool>(
+    conn: &rocksdb::DB,
+    snapshot: &StateSnapshot,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    scan: &mut Scan<P, C>,
+) -> Result<bool, DbError> {
+    let current_state = match check_consistency::current_state(snapshot, hasher) {
+        Ok(current_state) => current_state,
+        Err(CurrentStateError::Database(err)) => return Err(err.into()),
+        Err(err) => {
+            scan.issue(
+                format!("current state cannot be decoded: {}", err),
+                Some(&Prefix::CURRENT_STATE.to_vec()),
+                None,
+            );
+            return Ok(false);
+        },
+    };
+    let height: u32 = current_state.version().into();
+    let root = current_state.root().to_vec();
+    scan.report.height = height;
+    scan.report.root = root.clone();
+
+    let root_node = check_consistency::root_node(snapshot, &root, key_length, hasher)?;
+    match root_node {
+        RootNode::Empty | RootNode::Matched => {},
+        RootNode::Mismatched => scan.issue(
+            format!("root node of height {} does not match its hash", height),
+            Some(&root),
+            Some(height),
+        ),
+        RootNode::Missing => scan.issue(
+            format!("root node of height {} is not stored", height),
+            Some(&root),
+            Some(height),
+        ),
+    }
+
+    // state imported from the snapshot has no diff
+    if let Some(bytes) = snapshot.get(&check_consistency::diff_key(height))? {
+        if let Err(err) = check_diffs::read_diff(conn, height, &bytes) {
+            scan.issue(err.to_string(), None, Some(height));
+        }
+    }
+
+    Ok(matches!(root_node, RootNode::Empty | RootNode::Matched))
+}
+
+/// check_full walks the tree from the root, and checks the leaves against the state keys.
+/// Leaves are collected in memory as the pairs of the hashed key and the value hash before the state keys are read.
+fn check_full<P: FnMut(&IntegrityReport), C: Fn() -> bool>(
+    snapshot: &StateSnapshot,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    scan: &mut Scan<P, C>,
+) -> Result<(), DbError> {
+    let smt_db = SnapshotSmtDB::new(snapshot);
+    let tree = SparseMerkleTree::new_with_hasher(
+        &scan.report.root,
+        key_length,
+        consts::SUBTREE_HEIGHT,
+        hasher,
+    );
+    let mut leaves = HashMap::new();
+    let mut result = Ok(());
+    tree.check_subtrees(&smt_db, |check| {
+        match check {
+            SubtreeCheck::Valid {
+                leaves: subtree_leaves,
+                ..
+            } => leaves.extend(subtree_leaves),
+            SubtreeCheck::Missing(node_hash) => {
+                scan.issue(String::from("node is not stored"), Some(&node_hash), None)
+            },
+            SubtreeCheck::Mismatched(node_hash) => scan.issue(
+                String::from("node does not match its hash"),
+                Some(&node_hash),
+                None,
+            ),
+        }
+        scan.report.checked_nodes += 1;
+        result = scan.step();
+        result.is_ok()
+    })?;
+    result?;
+
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mode = rocksdb::IteratorMode::From(Prefix::STATE, rocksdb::Direction::Forward);
+    for key_val in snapshot.iterator_opt(Prefix::STATE, mode, read_options) {
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::STATE) {
+            break;
+        }
+        let state_key = key[Prefix::STATE.len()..].to_vec();
+        match leaves.remove(&state_key.hash_with_kind(HashKind::Key)) {
+            Some(value_hash)
+                if value_hash
+                    == value_compression::decode(&value).hash_with_kind(HashKind::Value) => {},
+            Some(_) => scan.issue(
+                String::from("state value does not match the leaf"),
+                Some(&state_key),
+                None,
+            ),
+            None => scan.issue(
+                String::from("state key has no leaf"),
+                Some(&state_key),
+                None,
+            ),
+        }
+        scan.report.checked_keys += 1;
+        scan.step()?;
+    }
+    for hashed_key in leaves.keys() {
+        scan.issue(
+            String::from("leaf has no state key"),
+            Some(hashed_key),
+            None,
+        );
+    }
+
+    Ok(())
+}
+
+/// check_integrity scans the snapshot of the database at the level.
+/// on_progress is called with the report at every progress interval of the nodes and the keys checked by the full scan.
+/// is_cancelled is checked after each node and key, and the scan fails with Cancelled when it returns true.
+pub fn check_integrity(
+    conn: &rocksdb::DB,
+    level: IntegrityLevel,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    options: &VerifyNodesOption,
+    on_progress: impl FnMut(&IntegrityReport),
+    is_cancelled: impl Fn() -> bool,
+) -> Result<IntegrityReport, DbError> {
+    let snapshot = StateSnapshot::new(conn);
+    let mut scan = Scan {
+        report: IntegrityReport::new(level),
+        options,
+        on_progress,
+        is_cancelled,
+    };
+    let walkable = check_quick(conn, &snapshot, key_length, hasher, &mut scan)?;
+    if walkable && level == IntegrityLevel::Full {
+        check_full(&snapshot, key_length, hasher, &mut scan)?;
+    }
+
+    Ok(scan.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::database::column_families;
+    use crate::sparse_merkle_tree::smt;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::{commit, open, state_key};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn options(max_errors: usize) -> VerifyNodesOption {
+        VerifyNodesOption {
+            max_errors,
+            progress_interval: 10,
+        }
+    }
+
+    fn check(store: &StateStore, level: IntegrityLevel) -> IntegrityReport {
+        check_integrity(
+            store.conn(),
+            level,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(10),
+            |_| {},
+            || false,
+        )
+        .unwrap()
+    }
+
+    /// committed_store commits 100 keys at height 1 and updates 10 of them at height 2.
+    fn committed_store(temp_dir: &TempDir) -> (StateStore, Vec<u8>) {
+        let store = open(temp_dir, false);
+        let upserts = (0..100).map(|i| (i, vec![i; 4]));
+        let root = commit(&store, 1, &smt::EMPTY_HASH, upserts, []);
+        let upserts = (0..10).map(|i| (i, vec![i; 5]));
+        let root = commit(&store, 2, &root, upserts, []);
+        (store, root)
+    }
+
+    fn write(store: &StateStore, f: impl FnOnce(&mut rocksdb::WriteBatch)) {
+        let mut batch = rocksdb::WriteBatch::default();
+        f(&mut batch);
+        column_families::write(store.conn(), batch).unwrap();
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let temp_dir = TempDir::new("test_check_integrity").unwrap();
+        let store = open(&temp_dir, false);
+        // empty state
+        for level in [IntegrityLevel::Quick, IntegrityLevel::Full] {
+            let report = check(&store, level);
+            assert!(report.ok());
+            assert_eq!(report.height, 0);
+        }
+        drop(store);
+
+        let temp_dir = TempDir::new("test_check_integrity_committed").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let report = check(&store, IntegrityLevel::Quick);
+        assert!(report.ok());
+        assert_eq!((report.height, report.root), (2, root.clone()));
+        assert_eq!((report.checked_nodes, report.checked_keys), (0, 0));
+
+        let mut progress = vec![];
+        let report = check_integrity(
+            store.conn(),
+            IntegrityLevel::Full,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(10),
+            |p| progress.push(p.checked()),
+            || false,
+        )
+        .unwrap();
+        assert!(report.ok());
+        assert_eq!(report.checked_keys, 100);
+        assert!(report.checked_nodes > 0);
+        assert_eq!(progress.len() as u64, report.checked() / 10);
+    }
+
+    #[test]
+    fn test_check_integrity_quick() {
+        let temp_dir = TempDir::new("test_check_integrity_quick").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+
+        write(&store, |batch| {
+            batch.put(check_consistency::diff_key(2), [3, 1]);
+            batch.delete([Prefix::SMT, &root].concat());
+        });
+        let report = check(&store, IntegrityLevel::Quick);
+        assert!(!report.ok());
+        assert_eq!(report.error_count, 2);
+        assert_eq!(
+            report.errors[0],
+            IntegrityIssue {
+                message: String::from("root node of height 2 is not stored"),
+                key: Some(root.clone()),
+                height: Some(2),
+            }
+        );
+        assert_eq!(report.errors[1].height, Some(2));
+        assert!(report.errors[1].message.contains("diff at height 2"));
+        // tree is not walked from the missing root
+        assert_eq!(check(&store, IntegrityLevel::Full).errors, report.errors);
+
+        write(&store, |batch| batch.put(Prefix::CURRENT_STATE, [1, 2, 3]));
+        let report = check(&store, IntegrityLevel::Full);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(report.errors[0].key, Some(Prefix::CURRENT_STATE.to_vec()));
+        assert!(report.root.is_empty());
+    }
+
+    #[test]
+    fn test_check_integrity_full() {
+        let temp_dir = TempDir::new("test_check_integrity_full").unwrap();
+        let (store, _) = committed_store(&temp_dir);
+
+        // state keys changed without the tree
+        write(&store, |batch| {
+            batch.put([Prefix::STATE, &state_key(3)].concat(), [9; 4]);
+            batch.delete([Prefix::STATE, &state_key(4)].concat());
+            batch.put([Prefix::STATE, &state_key(200)].concat(), [1; 4]);
+        });
+        assert!(check(&store, IntegrityLevel::Quick).ok());
+        let report = check(&store, IntegrityLevel::Full);
+        assert_eq!(report.error_count, 3);
+        let messages: Vec<(&str, Option<Vec<u8>>)> = report
+            .errors
+            .iter()
+            .map(|issue| (issue.message.as_str(), issue.key.clone()))
+            .collect();
+        assert!(messages.contains(&("state value does not match the leaf", Some(state_key(3)))));
+        assert!(messages.contains(&("state key has no leaf", Some(state_key(200)))));
+        assert!(messages.contains(&(
+            "leaf has no state key",
+            Some(state_key(4).hash_with_kind(HashKind::Key))
+        )));
+
+        // errors list is bounded
+        let report = check_integrity(
+            store.conn(),
+            IntegrityLevel::Full,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(1),
+            |_| {},
+            || false,
+        )
+        .unwrap();
+        assert_eq!(report.error_count, 3);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.truncated());
+    }
+
+    #[test]
+    fn test_check_integrity_cancelled() {
+        let temp_dir = TempDir::new("test_check_integrity_cancelled").unwrap();
+        let (store, _) = committed_store(&temp_dir);
+
+        let err = check_integrity(
+            store.conn(),
+            IntegrityLevel::Full,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(10),
+            |_| {},
+            || true,
+        )
+        .unwrap_err();
+        assert!(matches!(err, DbError::Cancelled("Integrity check")));
+        // quick check is not cancelled
+        let report = check_integrity(
+            store.conn(),
+            IntegrityLevel::Quick,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(10),
+            |_| {},
+            || true,
+        )
+        .unwrap();
+        assert!(report.ok());
+    }
+}
@@ -0,0 +1,536 @@
+/// current_state provides the versioned record of the latest root and height of the state_db.
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::consts;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::types::BlockHeight;
+
+/// FORMAT_VERSION is the version of the tagged encoding written by this release.
+/// Records of the earlier format versions are decoded with the missing fields as zero.
+pub const FORMAT_VERSION: u32 = 3;
+
+const FIELD_FORMAT_VERSION: u32 = 1;
+const FIELD_ROOT: u32 = 2;
+const FIELD_VERSION: u32 = 3;
+const FIELD_COMMIT_COUNT: u32 = 4;
+const FIELD_TIMESTAMP: u32 = 5;
+const FIELD_REVERTED_FROM: u32 = 6;
+const FIELD_HASHER: u32 = 7;
+
+#[derive(Error, Debug)]
+pub enum CurrentStateError {
+    #[error("invalid current state record: `{0}`")]
+    InvalidFormat(String),
+    #[error("unsupported current state format version: `{0}`")]
+    UnsupportedVersion(u32),
+    #[error("invalid current state encoding: `{0}`")]
+    Codec(#[from] codec::CodecError),
+    #[error("failed to access current state: `{0}`")]
+    Database(#[from] rocksdb::Error),
+    /// HasherMismatch is the hasher which is not the hasher recorded by the commits of the state.
+    /// The stored nodes and the roots are hashed by the recorded hasher.
+    #[error("hasher {configured} does not match the hasher {recorded} of the state")]
+    HasherMismatch {
+        recorded: String,
+        configured: String,
+    },
+}
+
+/// CurrentState holds the root and the version(height) of the latest state.
+/// It is stored under CURRENT_STATE prefix using the lisk-codec protocol with following fields.
+/// - 1: format version of the record.
+/// - 2: state root.
+/// - 3: version of the state.
+/// - 4: number of the commits, which is increased on each commit. (since format version 2)
+/// - 5: timestamp of the last commit or revert in milliseconds. (since format version 2)
+/// - 6: root reverted from, only written after the revert. (since format version 2)
+/// - 7: name of the hasher of the SMT, only written for the hasher other than sha256. (since format version 3)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CurrentState {
+    root: Vec<u8>,
+    version: BlockHeight,
+    commit_count: u64,
+    timestamp: u64,
+    reverted_from: Vec<u8>,
+    hasher: String,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl CurrentState {
+    pub fn new(root: &[u8], version: BlockHeight) -> Self {
+        Self {
+            root: root.to_vec(),
+            version,
+            ..Default::default()
+        }
+    }
+
+    /// committed returns the record after committing the root at the version.
+    /// The commit count is increased from the previous record.
+    pub fn committed(&self, root: &[u8], version: BlockHeight) -> Self {
+        Self {
+            root: root.to_vec(),
+            version,
+            commit_count: self.commit_count + 1,
+            timestamp: now_millis(),
+            reverted_from: vec![],
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// reverted returns the record after reverting reverted_from to the root at the version.
+    /// The commit count is kept, so that it only increases.
+    pub fn reverted(&self, root: &[u8], version: BlockHeight, reverted_from: &[u8]) -> Self {
+        Self {
+            root: root.to_vec(),
+            version,
+            commit_count: self.commit_count,
+            timestamp: now_millis(),
+            reverted_from: reverted_from.to_vec(),
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// with_hasher returns the record of the state hashed by the hasher.
+    pub fn with_hasher(mut self, hasher: HasherKind) -> Self {
+        self.hasher = if hasher == HasherKind::default() {
+            String::new()
+        } else {
+            hasher.name().to_string()
+        };
+        self
+    }
+
+    #[inline]
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    #[inline]
+    pub fn version(&self) -> BlockHeight {
+        self.version
+    }
+
+    #[inline]
+    pub fn commit_count(&self) -> u64 {
+        self.commit_count
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// reverted_from returns the root before the last revert. It is empty if the last update is a commit.
+    #[inline]
+    pub fn reverted_from(&self) -> &[u8] {
+        &self.reverted_from
+    }
+
+    /// hasher returns the name of the hasher of the SMT. Records without the hasher are hashed by the default sha256.
+    #[inline]
+    pub fn hasher(&self) -> &str {
+        if self.hasher.is_empty() {
+            HasherKind::default().name()
+        } else {
+            &self.hasher
+        }
+    }
+
+    /// encode the record to bytes using the current format version.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = codec::Writer::new();
+        writer.write_u32(FIELD_FORMAT_VERSION, FORMAT_VERSION);
+        writer.write_bytes(FIELD_ROOT, &self.root);
+        writer.write_u32(FIELD_VERSION, self.version.into());
+        writer.write_u64(FIELD_COMMIT_COUNT, self.commit_count);
+        writer.write_u64(FIELD_TIMESTAMP, self.timestamp);
+        if !self.reverted_from.is_empty() {
+            writer.write_bytes(FIELD_REVERTED_FROM, &self.reverted_from);
+        }
+        if !self.hasher.is_empty() {
+            writer.write_bytes(FIELD_HASHER, self.hasher.as_bytes());
+        }
+        writer.result().to_vec()
+    }
+
+    /// decode the tagged encoding of the format versions up to FORMAT_VERSION. All bytes must be consumed.
+    pub fn decode(bytes: &[u8]) -> Result<Self, CurrentStateError> {
+        let mut reader = codec::Reader::new(bytes);
+        let format_version = reader.read_u32(FIELD_FORMAT_VERSION)?;
+        let root = reader.read_bytes(FIELD_ROOT)?;
+        let version = reader.read_u32(FIELD_VERSION)?;
+        let commit_count = reader.read_u64(FIELD_COMMIT_COUNT)?;
+        let timestamp = reader.read_u64(FIELD_TIMESTAMP)?;
+        let reverted_from = reader.read_bytes(FIELD_REVERTED_FROM)?;
+        let hasher = reader.read_bytes(FIELD_HASHER)?;
+        if format_version == 0 {
+            return Err(CurrentStateError::InvalidFormat(String::from(
+                "format version is missing",
+            )));
+        }
+        if !reader.is_end() {
+            return Err(CurrentStateError::InvalidFormat(String::from(
+                "unexpected trailing bytes",
+            )));
+        }
+        // structure is valid, but it is written by a newer release
+        if format_version > FORMAT_VERSION {
+            return Err(CurrentStateError::UnsupportedVersion(format_version));
+        }
+        let hasher = String::from_utf8(hasher)
+            .map_err(|_| CurrentStateError::InvalidFormat(String::from("hasher must be utf8")))?;
+
+        Ok(Self {
+            root,
+            version: version.into(),
+            commit_count,
+            timestamp,
+            reverted_from,
+            hasher,
+        })
+    }
+
+    /// decode_legacy decodes the layout used before the versioned encoding,
+    /// which is the root followed by 4 bytes of big endian version.
+    pub fn decode_legacy(bytes: &[u8]) -> Result<Self, CurrentStateError> {
+        if bytes.len() < 4 {
+            return Err(CurrentStateError::InvalidFormat(format!(
+                "legacy record must have at least 4 bytes, got {}",
+                bytes.len()
+            )));
+        }
+        let version_point = bytes.len() - 4;
+        let version = u32::from_be_bytes(bytes[version_point..].try_into().unwrap());
+
+        Ok(Self {
+            root: bytes[..version_point].to_vec(),
+            version: version.into(),
+            ..Default::default()
+        })
+    }
+
+    /// from_bytes decodes the record in either format.
+    /// It returns true as second value if the record is in the legacy layout.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, bool), CurrentStateError> {
+        match Self::decode(bytes) {
+            Ok(state) => Ok((state, false)),
+            Err(CurrentStateError::UnsupportedVersion(v)) => {
+                Err(CurrentStateError::UnsupportedVersion(v))
+            },
+            Err(_) => Ok((Self::decode_legacy(bytes)?, true)),
+        }
+    }
+
+    /// get reads the record from the database. It returns None if the state is never committed.
+    pub fn get(conn: &rocksdb::DB) -> Result<Option<Self>, CurrentStateError> {
+        match conn.get(consts::Prefix::CURRENT_STATE)? {
+            Some(bytes) => Ok(Some(Self::from_bytes(&bytes)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// get_or_empty reads the record from the database, defaulting to the empty tree at version 0.
+    pub fn get_or_empty(conn: &rocksdb::DB, empty_root: &[u8]) -> Result<Self, CurrentStateError> {
+        Ok(Self::get(conn)?.unwrap_or_else(|| Self::new(empty_root, BlockHeight(0))))
+    }
+
+    /// check_hasher returns HasherMismatch if the state is committed with the hasher other than the given one.
+    /// The state which is never committed is not checked, as the hasher is recorded on the first commit.
+    /// Records which cannot be decoded are not checked, and they are reported when reading them.
+    pub fn check_hasher(conn: &rocksdb::DB, hasher: HasherKind) -> Result<(), CurrentStateError> {
+        let state = match Self::get(conn) {
+            Ok(Some(state)) => state,
+            Ok(None) => return Ok(()),
+            Err(CurrentStateError::Database(err)) => return Err(err.into()),
+            Err(_) => return Ok(()),
+        };
+        if state.hasher() != hasher.name() {
+            return Err(CurrentStateError::HasherMismatch {
+                recorded: state.hasher().to_string(),
+                configured: hasher.name().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// put stores the record to the database in the current format.
+    pub fn put(&self, conn: &rocksdb::DB) -> Result<(), CurrentStateError> {
+        self.put_opt(conn, &rocksdb::WriteOptions::default())
+    }
+
+    /// put_batch adds the record to the batch, so that it is written atomically with the state.
+    pub fn put_batch(&self, batch: &mut rocksdb::WriteBatch) {
+        batch.put(consts::Prefix::CURRENT_STATE, self.encode());
+    }
+
+    /// put_opt stores the record with the write options.
+    pub fn put_opt(
+        &self,
+        conn: &rocksdb::DB,
+        write_opts: &rocksdb::WriteOptions,
+    ) -> Result<(), CurrentStateError> {
+        conn.put_opt(consts::Prefix::CURRENT_STATE, self.encode(), write_opts)?;
+        Ok(())
+    }
+
+    /// migrate upgrades the legacy record to the current format.
+    /// The original bytes are kept under CURRENT_STATE_BACKUP prefix.
+    /// It returns true if the record was migrated.
+    pub fn migrate(conn: &rocksdb::DB) -> Result<bool, CurrentStateError> {
+        let bytes = match conn.get(consts::Prefix::CURRENT_STATE)? {
+            Some(bytes) => bytes,
+            None => return Ok(false),
+        };
+        let (state, legacy) = Self::from_bytes(&bytes)?;
+        if !legacy {
+            return Ok(false);
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put(consts::Prefix::CURRENT_STATE_BACKUP, &bytes);
+        batch.put(consts::Prefix::CURRENT_STATE, state.encode());
+        conn.write(batch)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::sparse_merkle_tree::smt::EMPTY_HASH;
+
+    // root 0x0102..20 with height 4660, stored in the legacy layout.
+    const LEGACY_FIXTURE: &str =
+        "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f2000001234";
+
+    fn temp_db() -> (TempDir, rocksdb::DB) {
+        let temp_dir = TempDir::new("test_current_state").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        (temp_dir, db)
+    }
+
+    #[test]
+    fn test_decode_legacy() {
+        let block_zero = BlockHeight(0);
+        let block_ten = BlockHeight(10);
+        let block_hundred = BlockHeight(100);
+        let root = Vec::with_capacity(30);
+
+        let test_data = vec![
+            (
+                CurrentState::new(&[], block_zero),
+                [vec![], block_zero.to_be_bytes().to_vec()].concat(),
+            ),
+            (
+                CurrentState::new(&[1, 2, 3, 4], block_ten),
+                [vec![1, 2, 3, 4], block_ten.to_be_bytes().to_vec()].concat(),
+            ),
+            (
+                CurrentState::new(&root, block_hundred),
+                [root.clone(), block_hundred.to_be_bytes().to_vec()].concat(),
+            ),
+            (
+                CurrentState::new(&EMPTY_HASH, block_zero),
+                [EMPTY_HASH.to_vec(), block_zero.to_be_bytes().to_vec()].concat(),
+            ),
+        ];
+        for (state_as_struct, state_as_bytes) in test_data {
+            assert_eq!(
+                CurrentState::decode_legacy(&state_as_bytes).unwrap(),
+                state_as_struct
+            );
+            assert_eq!(
+                CurrentState::from_bytes(&state_as_bytes).unwrap(),
+                (state_as_struct, true)
+            );
+        }
+
+        let fixture = hex::decode(LEGACY_FIXTURE).unwrap();
+        let (state, legacy) = CurrentState::from_bytes(&fixture).unwrap();
+        assert!(legacy);
+        assert_eq!(state.root(), &fixture[..32]);
+        assert_eq!(state.version(), BlockHeight(0x1234));
+
+        assert!(CurrentState::decode_legacy(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let test_data = vec![
+            CurrentState::new(&[], BlockHeight(0)),
+            CurrentState::new(&EMPTY_HASH, BlockHeight(0)),
+            CurrentState::new(&[1, 2, 3, 4], BlockHeight(10)),
+            CurrentState::new(&[7; 32], BlockHeight(u32::MAX)),
+        ];
+        for state in test_data {
+            let encoded = state.encode();
+            assert_eq!(CurrentState::decode(&encoded).unwrap(), state);
+            assert_eq!(
+                CurrentState::from_bytes(&encoded).unwrap(),
+                (state.clone(), false)
+            );
+            // encoding is stable after decoding
+            assert_eq!(CurrentState::decode(&encoded).unwrap().encode(), encoded);
+        }
+
+        assert_eq!(
+            CurrentState::new(&[1, 2], BlockHeight(300)).encode(),
+            vec![0x08, 0x03, 0x12, 0x02, 0x01, 0x02, 0x18, 0xac, 0x02, 0x20, 0x00, 0x28, 0x00]
+        );
+
+        let committed = CurrentState::new(&[1, 2], BlockHeight(1))
+            .committed(&[3, 4], BlockHeight(2))
+            .committed(&[5, 6], BlockHeight(3));
+        assert_eq!(committed.commit_count(), 2);
+        assert!(committed.timestamp() > 0);
+        assert!(committed.reverted_from().is_empty());
+        let reverted = committed.reverted(&[3, 4], BlockHeight(2), committed.root());
+        assert_eq!(reverted.commit_count(), 2);
+        assert_eq!(reverted.reverted_from(), &[5, 6]);
+        for state in [committed, reverted] {
+            assert_eq!(CurrentState::decode(&state.encode()).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn test_decode_format_version_1() {
+        // record written by the format version 1 without the commit metadata
+        let encoded = vec![0x08, 0x01, 0x12, 0x02, 0x01, 0x02, 0x18, 0xac, 0x02];
+        let (state, legacy) = CurrentState::from_bytes(&encoded).unwrap();
+        assert!(!legacy);
+        assert_eq!(state, CurrentState::new(&[1, 2], BlockHeight(300)));
+        assert_eq!(state.commit_count(), 0);
+        assert_eq!(state.timestamp(), 0);
+
+        // next commit counts from the record
+        assert_eq!(state.committed(&[3], BlockHeight(301)).commit_count(), 1);
+    }
+
+    #[test]
+    fn test_decode_format_version_2() {
+        // record written by the format version 2 without the hasher
+        let encoded = vec![
+            0x08, 0x02, 0x12, 0x02, 0x01, 0x02, 0x18, 0xac, 0x02, 0x20, 0x01, 0x28, 0x00,
+        ];
+        let (state, legacy) = CurrentState::from_bytes(&encoded).unwrap();
+        assert!(!legacy);
+        assert_eq!(state.commit_count(), 1);
+        assert_eq!(state.hasher(), "sha256");
+    }
+
+    #[test]
+    fn test_hasher() {
+        let state = CurrentState::new(&[1, 2], BlockHeight(3));
+        assert_eq!(state.hasher(), "sha256");
+        // default hasher is not written, so that the encoding is the same as without it
+        assert_eq!(
+            state.clone().with_hasher(HasherKind::Sha256).encode(),
+            state.encode()
+        );
+
+        let mut named = state.clone();
+        named.hasher = String::from("blake3");
+        let decoded = CurrentState::decode(&named.encode()).unwrap();
+        assert_eq!(decoded.hasher(), "blake3");
+        // the hasher is kept by the commits and the reverts
+        let committed = decoded.committed(&[4], BlockHeight(4));
+        assert_eq!(committed.hasher(), "blake3");
+        assert_eq!(
+            committed.reverted(&[1, 2], BlockHeight(3), &[4]).hasher(),
+            "blake3"
+        );
+
+        let (_temp_dir, db) = temp_db();
+        // state never committed is not checked
+        CurrentState::check_hasher(&db, HasherKind::Sha256).unwrap();
+        named.put(&db).unwrap();
+        let err = CurrentState::check_hasher(&db, HasherKind::Sha256).unwrap_err();
+        assert!(matches!(
+            err,
+            CurrentStateError::HasherMismatch { ref recorded, ref configured }
+                if recorded == "blake3" && configured == "sha256"
+        ));
+        state.put(&db).unwrap();
+        CurrentState::check_hasher(&db, HasherKind::Sha256).unwrap();
+        // legacy record is hashed by sha256
+        db.put(
+            consts::Prefix::CURRENT_STATE,
+            hex::decode(LEGACY_FIXTURE).unwrap(),
+        )
+        .unwrap();
+        CurrentState::check_hasher(&db, HasherKind::Sha256).unwrap();
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // trailing bytes
+        let mut encoded = CurrentState::new(&[1, 2], BlockHeight(3)).encode();
+        encoded.push(0);
+        assert!(CurrentState::decode(&encoded).is_err());
+
+        // newer format version
+        let mut writer = codec::Writer::new();
+        writer.write_u32(FIELD_FORMAT_VERSION, FORMAT_VERSION + 1);
+        writer.write_bytes(FIELD_ROOT, &[1, 2]);
+        writer.write_u32(FIELD_VERSION, 3);
+        assert!(matches!(
+            CurrentState::from_bytes(writer.result()),
+            Err(CurrentStateError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (_temp_dir, db) = temp_db();
+        assert!(!CurrentState::migrate(&db).unwrap());
+        assert_eq!(CurrentState::get(&db).unwrap(), None);
+        assert_eq!(
+            CurrentState::get_or_empty(&db, &EMPTY_HASH).unwrap(),
+            CurrentState::new(&EMPTY_HASH, BlockHeight(0))
+        );
+
+        let fixture = hex::decode(LEGACY_FIXTURE).unwrap();
+        db.put(consts::Prefix::CURRENT_STATE, &fixture).unwrap();
+        let before = CurrentState::get(&db).unwrap().unwrap();
+
+        assert!(CurrentState::migrate(&db).unwrap());
+        let stored = db.get(consts::Prefix::CURRENT_STATE).unwrap().unwrap();
+        assert_eq!(CurrentState::decode(&stored).unwrap(), before);
+        assert_eq!(
+            db.get(consts::Prefix::CURRENT_STATE_BACKUP)
+                .unwrap()
+                .unwrap(),
+            fixture
+        );
+
+        // migrating again does nothing
+        assert!(!CurrentState::migrate(&db).unwrap());
+        assert_eq!(
+            db.get(consts::Prefix::CURRENT_STATE).unwrap().unwrap(),
+            stored
+        );
+    }
+
+    #[test]
+    fn test_put_get() {
+        let (_temp_dir, db) = temp_db();
+        let state = CurrentState::new(&[3; 32], BlockHeight(42));
+        state.put(&db).unwrap();
+        assert_eq!(CurrentState::get(&db).unwrap().unwrap(), state);
+        let stored = db.get(consts::Prefix::CURRENT_STATE).unwrap().unwrap();
+        assert_eq!(stored, state.encode());
+    }
+}
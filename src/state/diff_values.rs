@@ -0,0 +1,245 @@
+/// diff_values provides the old-value store of the values referenced by the hash from the diffs.
+/// Each value is kept once with the number of the references, and it is removed with the last diff referencing it.
+/// The keys are kept in the default column family.
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::consts::Prefix;
+use crate::database::column_families::{self, StateSnapshot};
+use crate::diff::{self, StoredDiff};
+use crate::state::check_diffs;
+use crate::state::store::DataStoreError;
+
+/// COUNT_SIZE is the bytes of the reference count stored before the value.
+const COUNT_SIZE: usize = 8;
+
+/// key returns the database key of the value of the hash.
+pub fn key(hash: &[u8]) -> Vec<u8> {
+    [Prefix::DIFF_VALUE, hash].concat()
+}
+
+/// decode_entry splits the stored entry to the reference count and the value.
+fn decode_entry(entry: &[u8]) -> Option<(u64, &[u8])> {
+    if entry.len() < COUNT_SIZE {
+        return None;
+    }
+    let count: [u8; COUNT_SIZE] = entry[..COUNT_SIZE].try_into().ok()?;
+    Some((u64::from_be_bytes(count), &entry[COUNT_SIZE..]))
+}
+
+fn encode_entry(count: u64, value: &[u8]) -> Vec<u8> {
+    [&count.to_be_bytes(), value].concat()
+}
+
+/// get returns the value of the hash. None if the value is not stored.
+pub fn get(conn: &rocksdb::DB, hash: &[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+    Ok(conn
+        .get(key(hash))?
+        .and_then(|entry| decode_entry(&entry).map(|(_, value)| value.to_vec())))
+}
+
+/// get_snapshot returns the value of the hash in the snapshot. None if the value is not stored.
+pub fn get_snapshot(
+    snapshot: &StateSnapshot,
+    hash: &[u8],
+) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+    Ok(snapshot
+        .get(&key(hash))?
+        .and_then(|entry| decode_entry(&entry).map(|(_, value)| value.to_vec())))
+}
+
+/// ref_count returns the number of the references of the hash. 0 if the value is not stored.
+pub fn ref_count(conn: &rocksdb::DB, hash: &[u8]) -> Result<u64, rocksdb::Error> {
+    Ok(conn
+        .get(key(hash))?
+        .and_then(|entry| decode_entry(&entry).map(|(count, _)| count))
+        .unwrap_or(0))
+}
+
+/// is_empty returns true if no value is stored, so that the diffs removed have nothing to dereference.
+pub fn is_empty(conn: &rocksdb::DB) -> Result<bool, rocksdb::Error> {
+    let mode = rocksdb::IteratorMode::From(Prefix::DIFF_VALUE, rocksdb::Direction::Forward);
+    match conn.iterator(mode).next() {
+        Some(key_val) => Ok(!key_val?.0.starts_with(Prefix::DIFF_VALUE)),
+        None => Ok(true),
+    }
+}
+
+/// RefChanges accumulates the changes of the reference counts, which are written in the batch of the diffs.
+#[derive(Default)]
+pub struct RefChanges {
+    changes: HashMap<Vec<u8>, (i64, Option<Vec<u8>>)>,
+}
+
+impl RefChanges {
+    /// add adds the reference of the value by the diff written.
+    pub fn add(&mut self, hash: &[u8], value: &[u8]) {
+        let change = self.changes.entry(hash.to_vec()).or_default();
+        change.0 += 1;
+        if change.1.is_none() {
+            change.1 = Some(value.to_vec());
+        }
+    }
+
+    /// remove_diff removes the references of the diff removed.
+    pub fn remove_diff(&mut self, diff: &StoredDiff) {
+        for hash in diff.refs() {
+            self.changes.entry(hash.to_vec()).or_default().0 -= 1;
+        }
+    }
+
+    /// remove_stored_diff removes the references of the diff stored with the key, which is the height with the diff prefix.
+    /// The diff which cannot be decoded is DiffCorrupted, as the values it references would be left.
+    pub fn remove_stored_diff(&mut self, key: &[u8], bytes: &[u8]) -> Result<(), DataStoreError> {
+        let height: [u8; 4] = key
+            .strip_prefix(Prefix::DIFF)
+            .and_then(|height| height.try_into().ok())
+            .ok_or_else(|| {
+                DataStoreError::DiffCorrupted(format!(
+                    "diff key {} is not a height",
+                    diff::hex_snippet(key)
+                ))
+            })?;
+        let stored = check_diffs::decode_diff(u32::from_be_bytes(height), bytes)
+            .map_err(DataStoreError::DiffCorrupted)?;
+        self.remove_diff(&stored);
+        Ok(())
+    }
+
+    /// remove_diffs removes the references of the diffs between start and end, which are removed in the batch.
+    /// Nothing is read if no value is stored. The diff which cannot be decoded is DiffCorrupted.
+    pub fn remove_diffs(
+        &mut self,
+        conn: &rocksdb::DB,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<(), DataStoreError> {
+        let unknown = |err: rocksdb::Error| DataStoreError::Unknown(err.to_string());
+        if is_empty(conn).map_err(unknown)? {
+            return Ok(());
+        }
+        let mode = rocksdb::IteratorMode::From(start, rocksdb::Direction::Forward);
+        for key_val in column_families::iterator(conn, Prefix::DIFF, mode) {
+            let (key, value) = key_val.map_err(unknown)?;
+            if !key.starts_with(Prefix::DIFF) || key.as_ref() >= end {
+                break;
+            }
+            self.remove_stored_diff(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    /// write adds the entries with the updated counts to the batch, and deletes the ones without the reference.
+    /// The counts are read from the connection, so that the batch must be written before the next changes are written.
+    pub fn write(
+        &self,
+        conn: &rocksdb::DB,
+        batch: &mut rocksdb::WriteBatch,
+    ) -> Result<(), rocksdb::Error> {
+        for (hash, (delta, value)) in self.changes.iter() {
+            if *delta == 0 {
+                continue;
+            }
+            let key = key(hash);
+            let stored = conn.get(&key)?;
+            let (count, stored_value) = match stored.as_deref().and_then(decode_entry) {
+                Some((count, value)) => (count, Some(value)),
+                None => (0, None),
+            };
+            let count = (count as i64).saturating_add(*delta);
+            match stored_value.or(value.as_deref()) {
+                Some(value) if count > 0 => batch.put(&key, encode_entry(count as u64, value)),
+                _ => batch.delete(&key),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::diff::Diff;
+    use crate::types::KVPair;
+
+    fn write(db: &rocksdb::DB, changes: &RefChanges) {
+        let mut batch = rocksdb::WriteBatch::default();
+        changes.write(db, &mut batch).unwrap();
+        db.write(batch).unwrap();
+    }
+
+    #[test]
+    fn test_ref_changes() {
+        let temp_dir = TempDir::new("test_diff_values").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        assert!(is_empty(&db).unwrap());
+
+        let diff = Diff::new(
+            vec![],
+            vec![
+                KVPair::new(&[3; 10], &[7; 100]),
+                KVPair::new(&[4; 10], &[7; 100]),
+            ],
+            vec![KVPair::new(&[5; 10], &[8; 10])],
+        );
+        let (encoded, referenced) = diff.encode_with_refs(100);
+        // the small value is kept inline
+        assert_eq!(referenced.len(), 2);
+        let hash = referenced[0].key_as_vec();
+        assert_eq!(referenced[1].key(), hash.as_slice());
+        let mut changes = RefChanges::default();
+        for pair in referenced.iter() {
+            changes.add(pair.key(), pair.value());
+        }
+        write(&db, &changes);
+        assert!(!is_empty(&db).unwrap());
+        assert_eq!(ref_count(&db, &hash).unwrap(), 2);
+        assert_eq!(get(&db, &hash).unwrap(), Some(vec![7; 100]));
+
+        // the value is removed with the last reference
+        let mut changes = RefChanges::default();
+        changes.remove_diff(&StoredDiff::decode(&encoded).unwrap());
+        write(&db, &changes);
+        assert_eq!(ref_count(&db, &hash).unwrap(), 0);
+        assert_eq!(get(&db, &hash).unwrap(), None);
+        assert!(is_empty(&db).unwrap());
+    }
+
+    #[test]
+    fn test_remove_diffs() {
+        let temp_dir = TempDir::new("test_diff_values_remove").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let diff_key = |height: u32| [Prefix::DIFF, &height.to_be_bytes()].concat();
+        let mut changes = RefChanges::default();
+        for height in 1..=3_u32 {
+            let diff = Diff::new(vec![], vec![KVPair::new(&[1; 10], &[9; 64])], vec![]);
+            let (encoded, referenced) = diff.encode_with_refs(64);
+            for pair in referenced.iter() {
+                changes.add(pair.key(), pair.value());
+            }
+            db.put(diff_key(height), encoded).unwrap();
+        }
+        write(&db, &changes);
+        let diff = Diff::new(vec![], vec![KVPair::new(&[1; 10], &[9; 64])], vec![]);
+        let hash = diff.encode_with_refs(64).1[0].key_as_vec();
+        assert_eq!(ref_count(&db, &hash).unwrap(), 3);
+
+        let mut changes = RefChanges::default();
+        changes
+            .remove_diffs(&db, &diff_key(0), &diff_key(3))
+            .unwrap();
+        write(&db, &changes);
+        assert_eq!(ref_count(&db, &hash).unwrap(), 1);
+        assert_eq!(get(&db, &hash).unwrap(), Some(vec![9; 64]));
+
+        // the references of the diff which cannot be decoded are not skipped
+        db.put(diff_key(2), [0xff; 3]).unwrap();
+        let mut changes = RefChanges::default();
+        assert!(matches!(
+            changes.remove_diffs(&db, &diff_key(0), &diff_key(4)),
+            Err(DataStoreError::DiffCorrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,618 @@
+/// in_memory_state_db provides the state storage in memory with the same commit, revert and proofs as StateDB.
+/// It is used to run the full state cycle in the unit tests without a physical storage.
+#[cfg(feature = "node")]
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[cfg(feature = "node")]
+use neon::prelude::*;
+#[cfg(feature = "node")]
+use neon::types::buffer::TypedArray;
+
+use crate::batch;
+use crate::consts;
+use crate::database::options::IterationOption;
+use crate::database::reader_writer::read_writer_db;
+use crate::database::traits::{Actions, NodeReader};
+#[cfg(feature = "node")]
+use crate::database::types::JsBoxRef;
+use crate::database::utils::{is_key_in_range, sort_and_limit};
+use crate::diff::Diff;
+#[cfg(feature = "node")]
+use crate::error::{self, DbError};
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt::{self, SMTError};
+use crate::sparse_merkle_tree::smt_db::InMemorySmtDB;
+#[cfg(feature = "node")]
+use crate::state::state_db::StateDB;
+use crate::state::state_writer::StateWriter;
+use crate::state::store::{self, DataStoreError};
+#[cfg(feature = "node")]
+use crate::types::NestedVec;
+use crate::types::{BlockHeight, Cache, KVPair, KeyLength, SharedKVPair, VecOption};
+
+#[cfg(feature = "node")]
+type SharedInMemoryStateDB = JsBoxRef<InMemoryStateDB>;
+
+/// SmtOverlay keeps the node changes of a commit apart from the tree,
+/// so that a failed commit leaves the tree unchanged like the single batch of StateDB.
+struct SmtOverlay<'a> {
+    base: &'a InMemorySmtDB,
+    changes: HashMap<Vec<u8>, VecOption>,
+}
+
+impl Actions for SmtOverlay<'_> {
+    fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        self.read_node(key)
+    }
+
+    fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+        self.changes
+            .insert(pair.key_as_vec(), Some(pair.value_as_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.changes.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self)
+    }
+}
+
+impl NodeReader for SmtOverlay<'_> {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        match self.changes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.base.get(key),
+        }
+    }
+}
+
+impl<'a> SmtOverlay<'a> {
+    fn new(base: &'a InMemorySmtDB) -> Self {
+        Self {
+            base,
+            changes: HashMap::new(),
+        }
+    }
+
+    fn into_changes(self) -> HashMap<Vec<u8>, VecOption> {
+        self.changes
+    }
+}
+
+/// StateValues holds the committed state values keyed by the state key without the prefix.
+#[derive(Default)]
+struct StateValues {
+    data: Cache,
+}
+
+impl batch::BatchWriter for StateValues {
+    fn put(&mut self, pair: &SharedKVPair) {
+        self.data.insert(pair.key_as_vec(), pair.value_as_vec());
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.data.remove(key);
+    }
+}
+
+/// InMemoryStateDB composes the state values, the diffs and the SMT nodes in memory.
+/// The roots are the same as StateDB for the same sequence of the commits and the reverts.
+pub struct InMemoryStateDB {
+    values: StateValues,
+    smt_db: InMemorySmtDB,
+    /// diffs holds the diff of each committed height until it is reverted.
+    diffs: HashMap<u32, Diff>,
+    key_length: KeyLength,
+}
+
+impl InMemoryStateDB {
+    /// new creates the empty state with the key length of the SMT.
+    pub fn new(key_length: KeyLength) -> Self {
+        Self {
+            values: StateValues::default(),
+            smt_db: InMemorySmtDB::default(),
+            diffs: HashMap::new(),
+            key_length,
+        }
+    }
+
+    /// get returns the committed value of the state key.
+    pub fn get(&self, key: &[u8]) -> VecOption {
+        self.values.data.get(key).cloned()
+    }
+
+    /// get_with_writer returns the value from the writer, or the committed value which is cached to the writer.
+    pub fn get_with_writer(
+        &self,
+        writer: &mut StateWriter,
+        key: &[u8],
+    ) -> Result<VecOption, DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+        };
+        read_writer_db::get_with_writer(writer, key, stored).map_err(DataStoreError::from)
+    }
+
+    /// upsert sets the value of the key in the writer, caching the committed value first.
+    pub fn upsert(
+        &self,
+        writer: &mut StateWriter,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+        };
+        read_writer_db::upsert(writer, key, value, stored).map_err(DataStoreError::from)
+    }
+
+    /// delete marks the key as deleted in the writer, caching the committed value first.
+    pub fn delete(&self, writer: &mut StateWriter, key: &[u8]) -> Result<(), DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+        };
+        read_writer_db::delete_with_writer(writer, key, stored).map_err(DataStoreError::from)
+    }
+
+    /// iterate returns the committed pairs in the range with the order and the limit.
+    pub fn iterate(&self, options: &IterationOption) -> Vec<KVPair> {
+        let mut pairs: Vec<KVPair> = self
+            .values
+            .data
+            .iter()
+            .filter(|(key, _)| is_key_in_range(options, key))
+            .map(|(key, value)| KVPair::new(key, value))
+            .collect();
+        sort_and_limit(&mut pairs, options);
+        pairs
+    }
+
+    /// commit applies the writer as the version on top of prev_root, and returns the new root.
+    /// Nothing is changed if updating the tree fails.
+    pub fn commit(
+        &mut self,
+        writer: &StateWriter,
+        version: BlockHeight,
+        prev_root: &[u8],
+    ) -> Result<Vec<u8>, DataStoreError> {
+        let data = smt::UpdateData::new_from(writer.get_hashed_updated()?);
+        let mut overlay = SmtOverlay::new(&self.smt_db);
+        let mut tree =
+            smt::SparseMerkleTree::new(prev_root, self.key_length, consts::SUBTREE_HEIGHT);
+        let root = tree
+            .commit(&mut overlay, &data)
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        let root = (**root.lock().unwrap()).clone();
+        self.apply_nodes(overlay.into_changes());
+        let diff = writer.commit(&mut self.values)?;
+        self.diffs.insert(u32::from(version), diff);
+
+        Ok(root)
+    }
+
+    /// revert reverts the commit of the version on state_root, and returns the previous root.
+    pub fn revert(
+        &mut self,
+        version: BlockHeight,
+        state_root: &[u8],
+    ) -> Result<Vec<u8>, DataStoreError> {
+        let diff = self
+            .diffs
+            .get(&u32::from(version))
+            .ok_or_else(|| DataStoreError::DiffNotFound(version.into()))?;
+        let mut overlay = SmtOverlay::new(&self.smt_db);
+        let root = store::smt_apply_diff(
+            &mut overlay,
+            state_root,
+            self.key_length,
+            HasherKind::Sha256,
+            diff,
+        )
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        let root = (**root.lock().unwrap()).clone();
+        let changes = overlay.into_changes();
+        let diff = self.diffs.remove(&u32::from(version)).unwrap();
+        self.apply_nodes(changes);
+        diff.revert_commit(&mut self.values);
+
+        Ok(root)
+    }
+
+    /// prove creates the proof of the query keys, which are the hashed state keys, for the root.
+    pub fn prove(&mut self, root: &[u8], query_keys: &[Vec<u8>]) -> Result<smt::Proof, SMTError> {
+        let mut tree = smt::SparseMerkleTree::new(root, self.key_length, consts::SUBTREE_HEIGHT);
+        tree.prove(&mut self.smt_db, query_keys)
+    }
+
+    /// verify checks the proof of the query keys against the root with the key length of the state.
+    pub fn verify(
+        &self,
+        root: &[u8],
+        query_keys: &[Vec<u8>],
+        proof: &smt::Proof,
+    ) -> Result<bool, SMTError> {
+        smt::validate_key_lengths(query_keys, self.key_length)?;
+        smt::SparseMerkleTree::verify(query_keys, proof, root, self.key_length)
+    }
+
+    fn apply_nodes(&mut self, changes: HashMap<Vec<u8>, VecOption>) {
+        for (key, value) in changes {
+            let result = match value {
+                Some(value) => self.smt_db.set(&KVPair::new(&key, &value)),
+                None => self.smt_db.del(&key),
+            };
+            result.expect("in memory SMT does not fail");
+        }
+    }
+}
+
+#[cfg(feature = "node")]
+impl Finalize for InMemoryStateDB {}
+
+#[cfg(feature = "node")]
+fn send_result<'a, T, E: Into<DbError>>(
+    ctx: &mut FunctionContext<'a>,
+    callback: Handle<'a, JsFunction>,
+    result: Result<T, E>,
+    to_js: impl FnOnce(&mut FunctionContext<'a>, T) -> JsResult<'a, JsValue>,
+) -> JsResult<'a, JsUndefined> {
+    let this = ctx.undefined();
+    let args: Vec<Handle<JsValue>> = match result {
+        Ok(val) => vec![ctx.null().upcast(), to_js(ctx, val)?],
+        Err(err) => vec![Into::<DbError>::into(err).to_js(ctx)?.upcast()],
+    };
+    callback.call(ctx, this, args)?;
+
+    Ok(ctx.undefined())
+}
+
+#[cfg(feature = "node")]
+fn parse_keys(ctx: &mut FunctionContext, pos: i32) -> NeonResult<NestedVec> {
+    let input = ctx.argument::<JsArray>(pos)?.to_vec(ctx)?;
+    input
+        .iter()
+        .map(|key| {
+            Ok(key
+                .downcast_or_throw::<JsTypedArray<u8>, _>(ctx)?
+                .as_slice(ctx)
+                .to_vec())
+        })
+        .collect()
+}
+
+#[cfg(feature = "node")]
+fn parse_height(ctx: &mut FunctionContext, pos: i32) -> NeonResult<BlockHeight> {
+    let height = ctx.argument::<JsNumber>(pos)?.value(ctx);
+    if height < 0.0 || height > u32::MAX as f64 || height.fract() != 0.0 {
+        return error::invalid_argument("height must be a non-negative integer").throw(ctx);
+    }
+    Ok(BlockHeight(height as u32))
+}
+
+#[cfg(feature = "node")]
+impl InMemoryStateDB {
+    /// js_new is handler for JS ffi.
+    /// - @params(0) - key length of the SMT.
+    /// - @returns - InMemoryStateDB without any state.
+    pub fn js_new(mut ctx: FunctionContext) -> JsResult<SharedInMemoryStateDB> {
+        let key_length = ctx.argument::<JsNumber>(0)?.value(&mut ctx).into();
+        Ok(ctx.boxed(RefCell::new(Self::new(key_length))))
+    }
+
+    /// js_commit is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - changes of the state in format of { key: &[u8]; value?: &[u8]; }[]. The key is deleted without the value.
+    /// - @params(1) - height of the commit.
+    /// - @params(2) - previous state root.
+    /// - @params(3) - callback to return the new state root.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - [u8]. New state root.
+    pub fn js_commit(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let input = ctx.argument::<JsArray>(0)?.to_vec(&mut ctx)?;
+        let mut changes = Vec::with_capacity(input.len());
+        for item in input.iter() {
+            let obj = item.downcast_or_throw::<JsObject, _>(&mut ctx)?;
+            let key = obj
+                .get::<JsTypedArray<u8>, _, _>(&mut ctx, "key")?
+                .as_slice(&ctx)
+                .to_vec();
+            let value = obj
+                .get_opt::<JsTypedArray<u8>, _, _>(&mut ctx, "value")?
+                .map(|value| value.as_slice(&ctx).to_vec());
+            changes.push((key, value));
+        }
+        let height = parse_height(&mut ctx, 1)?;
+        let prev_root = ctx.argument::<JsTypedArray<u8>>(2)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(3)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let mut db = db.borrow_mut();
+
+        let mut writer = StateWriter::default();
+        let result = changes
+            .iter()
+            .try_for_each(|(key, value)| match value {
+                Some(value) => db.upsert(&mut writer, key, value),
+                None => db.delete(&mut writer, key),
+            })
+            .and_then(|_| db.commit(&writer, height, &prev_root));
+        send_result(&mut ctx, callback, result, |ctx, root| {
+            Ok(JsBuffer::external(ctx, root).upcast())
+        })
+    }
+
+    /// js_revert is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - state root of the height.
+    /// - @params(1) - height to revert.
+    /// - @params(2) - callback to return the previous state root.
+    /// - @callback(0) - Error. ERR_NOT_RETAINED if the height is not committed.
+    /// - @callback(1) - [u8]. Previous state root.
+    pub fn js_revert(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let state_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let height = parse_height(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let result = db.borrow_mut().revert(height, &state_root);
+        send_result(&mut ctx, callback, result, |ctx, root| {
+            Ok(JsBuffer::external(ctx, root).upcast())
+        })
+    }
+
+    /// js_get is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - state key.
+    /// - @params(1) - callback to return the committed value.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
+    /// - @callback(1) - [u8]. Value associated with the key.
+    pub fn js_get(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let result = db.borrow().get(&key).ok_or(DbError::NotFound);
+        send_result(&mut ctx, callback, result, |ctx, value| {
+            Ok(JsBuffer::external(ctx, value).upcast())
+        })
+    }
+
+    /// js_iterate is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], keysOnly: bool}.
+    /// - @params(1) - callback to return all the pairs at once.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { key: &[u8], value: &[u8]}[]. Value is not set with keysOnly.
+    pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let option_inputs = ctx.argument::<JsObject>(0)?;
+        let options = IterationOption::new(&mut ctx, option_inputs)?;
+        let callback = ctx.argument::<JsFunction>(1)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let pairs = db.borrow().iterate(&options);
+        send_result(&mut ctx, callback, Ok::<_, DbError>(pairs), |ctx, pairs| {
+            let arr = JsArray::new(ctx, pairs.len() as u32);
+            for (i, kv) in pairs.iter().enumerate() {
+                let obj = ctx.empty_object();
+                let key = JsBuffer::external(ctx, kv.key_as_vec());
+                obj.set(ctx, "key", key)?;
+                if !options.keys_only {
+                    let value = JsBuffer::external(ctx, kv.value_as_vec());
+                    obj.set(ctx, "value", value)?;
+                }
+                arr.set(ctx, i as u32, obj)?;
+            }
+            Ok(arr.upcast())
+        })
+    }
+
+    /// js_prove is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - state root.
+    /// - @params(1) - queries in format of &[&[u8]]
+    /// - @params(2) - callback to return the proof.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }
+    pub fn js_prove(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let state_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let queries = parse_keys(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let result = db.borrow_mut().prove(&state_root, &queries);
+        send_result(&mut ctx, callback, result, |ctx, proof| {
+            let obj = ctx.empty_object();
+            let sibling_hashes = ctx.empty_array();
+            for (i, h) in proof.sibling_hashes.iter().enumerate() {
+                let hash = JsBuffer::external(ctx, h.to_vec());
+                sibling_hashes.set(ctx, i as u32, hash)?;
+            }
+            obj.set(ctx, "siblingHashes", sibling_hashes)?;
+            let queries = ctx.empty_array();
+            for (i, v) in proof.queries.iter().enumerate() {
+                let query = ctx.empty_object();
+                let key = JsBuffer::external(ctx, v.key_as_vec());
+                query.set(ctx, "key", key)?;
+                let value = JsBuffer::external(ctx, v.value_as_vec());
+                query.set(ctx, "value", value)?;
+                let bitmap = JsBuffer::external(ctx, v.bitmap.to_vec());
+                query.set(ctx, "bitmap", bitmap)?;
+                queries.set(ctx, i as u32, query)?;
+            }
+            obj.set(ctx, "queries", queries)?;
+            Ok(obj.upcast())
+        })
+    }
+
+    /// js_verify is handler for JS ffi.
+    /// js "this" - InMemoryStateDB.
+    /// - @params(0) - state root.
+    /// - @params(1) - queries in format of &[&[u8]]
+    /// - @params(2) - proof { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool represents true if proof is valid.
+    pub fn js_verify(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let state_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let queries = parse_keys(&mut ctx, 1)?;
+        let proof = StateDB::proof(&mut ctx, 2)?;
+        let callback = ctx.argument::<JsFunction>(3)?;
+        let db = ctx
+            .this()
+            .downcast_or_throw::<SharedInMemoryStateDB, _>(&mut ctx)?;
+        let result = db.borrow().verify(&state_root, &queries, &proof);
+        send_result(&mut ctx, callback, result, |ctx, valid| {
+            Ok(ctx.boolean(valid).upcast())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::state::test_utils::{open, state_key};
+    use crate::types::{HashKind, HashWithKind};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    #[test]
+    fn test_in_memory_state_db_commit_revert_and_prove() {
+        let mut db = InMemoryStateDB::new(KEY_LENGTH);
+        let mut writer = StateWriter::default();
+        for i in 0..3 {
+            db.upsert(&mut writer, &state_key(i), &[i; 4]).unwrap();
+        }
+        let root1 = db
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+
+        let mut writer = StateWriter::default();
+        db.upsert(&mut writer, &state_key(0), &[9; 4]).unwrap();
+        db.delete(&mut writer, &state_key(1)).unwrap();
+        let root2 = db.commit(&writer, BlockHeight(2), &root1).unwrap();
+        assert_ne!(root1, root2);
+        assert_eq!(db.get(&state_key(0)), Some(vec![9; 4]));
+        assert_eq!(db.get(&state_key(1)), None);
+        let options = IterationOption {
+            limit: -1,
+            reverse: true,
+            gte: None,
+            lte: None,
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+        let keys: Vec<Vec<u8>> = db
+            .iterate(&options)
+            .iter()
+            .map(|p| p.key_as_vec())
+            .collect();
+        assert_eq!(keys, vec![state_key(2), state_key(0)]);
+
+        let query_keys: Vec<Vec<u8>> = [0, 1, 5]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = db.prove(&root2, &query_keys).unwrap();
+        assert!(db.verify(&root2, &query_keys, &proof).unwrap());
+        assert!(!db.verify(&root1, &query_keys, &proof).unwrap());
+
+        assert_eq!(db.revert(BlockHeight(2), &root2).unwrap(), root1);
+        assert_eq!(db.get(&state_key(0)), Some(vec![0; 4]));
+        assert_eq!(db.get(&state_key(1)), Some(vec![1; 4]));
+        assert!(matches!(
+            db.revert(BlockHeight(2), &root1),
+            Err(DataStoreError::DiffNotFound(2))
+        ));
+    }
+
+    #[test]
+    fn test_in_memory_state_db_failed_commit_keeps_state() {
+        let mut db = InMemoryStateDB::new(KEY_LENGTH);
+        let mut writer = StateWriter::default();
+        db.upsert(&mut writer, &state_key(1), &[1; 4]).unwrap();
+        let root = db
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+
+        let mut writer = StateWriter::default();
+        db.upsert(&mut writer, &state_key(1), &[2; 4]).unwrap();
+        assert!(db.commit(&writer, BlockHeight(2), &[7; 32]).is_err());
+        assert_eq!(db.get(&state_key(1)), Some(vec![1; 4]));
+        let query_keys = vec![state_key(1).hash_with_kind(HashKind::Key)];
+        let proof = db.prove(&root, &query_keys).unwrap();
+        assert!(db.verify(&root, &query_keys, &proof).unwrap());
+    }
+
+    /// the roots must match StateStore for the same random operations at every height, including the reverts.
+    #[test]
+    fn test_in_memory_state_db_matches_state_store() {
+        let temp_dir = TempDir::new("test_in_memory_state_db_matches").unwrap();
+        let store = open(&temp_dir, false);
+        let mut db = InMemoryStateDB::new(KEY_LENGTH);
+        // the seed is fixed, so that a failure is reproduced by running the test again
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        let mut height = 0;
+        for _ in 0..60 {
+            // revert one in four steps once something is committed
+            if height > 0 && rng.gen_range(0..4) == 0 {
+                let root = roots.pop().unwrap();
+                let stored = store.revert(BlockHeight(height), &root).unwrap();
+                let in_memory = db.revert(BlockHeight(height), &root).unwrap();
+                assert_eq!(stored, in_memory, "revert of height {}", height);
+                assert_eq!(&stored, roots.last().unwrap());
+                height -= 1;
+                continue;
+            }
+            height += 1;
+            let mut stored_writer = StateWriter::default();
+            let mut writer = StateWriter::default();
+            for _ in 0..rng.gen_range(1..20) {
+                let key = state_key(rng.gen_range(0..32));
+                if rng.gen_range(0..3) == 0 {
+                    store.delete(&mut stored_writer, &key).unwrap();
+                    db.delete(&mut writer, &key).unwrap();
+                } else {
+                    let mut value = vec![0; rng.gen_range(1..8)];
+                    rng.fill_bytes(&mut value);
+                    store.upsert(&mut stored_writer, &key, &value).unwrap();
+                    db.upsert(&mut writer, &key, &value).unwrap();
+                }
+            }
+            let prev_root = roots.last().unwrap();
+            let stored = store
+                .commit(&stored_writer, BlockHeight(height), prev_root)
+                .unwrap();
+            let in_memory = db.commit(&writer, BlockHeight(height), prev_root).unwrap();
+            assert_eq!(stored, in_memory, "commit of height {}", height);
+            roots.push(stored);
+
+            for i in 0..32 {
+                assert_eq!(store.get(&state_key(i)).unwrap(), db.get(&state_key(i)));
+            }
+        }
+    }
+}
@@ -0,0 +1,471 @@
+/// migrations provides the versioned upgrades of the on-disk format of the state_db.
+/// The format version is written when the database is created, and the database written by an older
+/// format is upgraded by the registered steps in order. Each step is idempotent and the version is
+/// written after the step completes, so that the interrupted migration is run again on the next open.
+use std::convert::TryInto;
+
+use thiserror::Error;
+
+use crate::consts::{self, Prefix};
+use crate::database::column_families;
+use crate::database::types::DbOptions;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt_db::OverlaySmtDB;
+use crate::state::check_diffs;
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::state::root_history;
+use crate::state::store::{self, DataStoreError};
+use crate::types::{BlockHeight, KeyLength};
+
+/// FORMAT_VERSION is the format version written by this version of the state_db.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// MigrationError is the error of checking or upgrading the format version.
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+    /// Unsupported is the database written by a newer format than this version supports.
+    #[error("format version {found} is newer than the supported version {supported}")]
+    Unsupported { found: u32, supported: u32 },
+    #[error("format version must be 4 bytes, got {0} bytes")]
+    InvalidVersion(usize),
+    /// NeedsMigration is the database which is not opened without running the pending steps.
+    #[error(
+        "database needs migration: {0}. Open it with autoMigrate: true to run the migrations"
+    )]
+    NeedsMigration(String),
+    /// Step is the failure of the step, and the version stays at the one before the step.
+    #[error("migration {name} failed: {source}")]
+    Step {
+        name: &'static str,
+        source: DataStoreError,
+    },
+    /// CurrentState is the current state which cannot be opened with the options, such as the mismatched hasher.
+    #[error(transparent)]
+    CurrentState(#[from] CurrentStateError),
+}
+
+/// Progress is called with the number of the records processed by the step so far.
+pub type Progress<'a> = &'a mut dyn FnMut(usize);
+
+/// Migration is a step upgrading the format from the version to the next one.
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    pub name: &'static str,
+    run: fn(&rocksdb::DB, KeyLength, Progress) -> Result<(), DataStoreError>,
+}
+
+impl Migration {
+    /// describe returns the name and the versions of the step, such as root_history (0 -> 1).
+    pub fn describe(&self) -> String {
+        format!("{} ({} -> {})", self.name, self.from, self.to)
+    }
+}
+
+/// MIGRATIONS is the registry of the steps ordered by the version.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        to: 1,
+        name: KEYSPACE_STEP,
+        run: move_keyspace,
+    },
+    Migration {
+        from: 1,
+        to: 2,
+        name: "root_history",
+        run: record_root_history,
+    },
+];
+
+/// KEYSPACE_STEP is the name of the step moving the keys to the column families.
+const KEYSPACE_STEP: &str = "column_families";
+
+fn encode_version(version: u32) -> [u8; 4] {
+    version.to_be_bytes()
+}
+
+/// stored_version returns the format version of the database.
+/// Database without the version is the format 0 if it has the current state, and None if it is created now.
+pub fn stored_version(conn: &rocksdb::DB) -> Result<Option<u32>, MigrationError> {
+    match conn.get(Prefix::FORMAT_VERSION)? {
+        Some(bytes) => {
+            let version: [u8; 4] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| MigrationError::InvalidVersion(bytes.len()))?;
+            Ok(Some(u32::from_be_bytes(version)))
+        },
+        None if conn.get(Prefix::CURRENT_STATE)?.is_some() => Ok(Some(0)),
+        None => Ok(None),
+    }
+}
+
+/// pending returns the steps to run from the version.
+/// The keys written by the legacy-keyspace feature are moved regardless of the version
+/// once the database has the column families.
+pub fn pending(
+    conn: &rocksdb::DB,
+    version: u32,
+) -> Result<Vec<&'static Migration>, MigrationError> {
+    let keyspace_pending = column_families::is_pending(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| m.from >= version || (m.name == KEYSPACE_STEP && keyspace_pending))
+        .collect())
+}
+
+/// migrate runs the pending steps in order, and writes the version after each step.
+/// on_progress is called with the step and the number of the records processed.
+/// It returns the number of the steps run.
+pub fn migrate(
+    conn: &rocksdb::DB,
+    key_length: KeyLength,
+    on_progress: &mut dyn FnMut(&Migration, usize),
+) -> Result<usize, MigrationError> {
+    let mut version = stored_version(conn)?.unwrap_or(0);
+    let steps = pending(conn, version)?;
+    for step in steps.iter() {
+        (step.run)(conn, key_length, &mut |count| on_progress(step, count)).map_err(|source| {
+            MigrationError::Step {
+                name: step.name,
+                source,
+            }
+        })?;
+        // the keyspace step run again on the newer version does not lower it
+        if step.to > version {
+            version = step.to;
+            conn.put(Prefix::FORMAT_VERSION, encode_version(version))?;
+        }
+    }
+
+    Ok(steps.len())
+}
+
+/// prepare checks the format version on open. New database is marked with the current version.
+/// The pending steps are run if auto_migrate is set, and otherwise NeedsMigration is returned with the steps.
+/// Readonly database is only checked, as the steps cannot be written.
+/// on_progress is called with the step and the number of the records processed as migrate.
+pub fn prepare(
+    conn: &rocksdb::DB,
+    options: &DbOptions,
+    on_progress: &mut dyn FnMut(&Migration, usize),
+) -> Result<(), MigrationError> {
+    let version = match stored_version(conn)? {
+        Some(version) => version,
+        None if options.is_readonly() => return Ok(()),
+        None => {
+            // new database has no keys to move, and it is only marked as moved
+            column_families::migrate(conn, &mut |_| {})?;
+            conn.put(Prefix::FORMAT_VERSION, encode_version(FORMAT_VERSION))?;
+            return Ok(());
+        },
+    };
+    if version > FORMAT_VERSION {
+        return Err(MigrationError::Unsupported {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    let steps = pending(conn, version)?;
+    if steps.is_empty() {
+        return Ok(());
+    }
+    if options.is_readonly() || !options.auto_migrate {
+        let names: Vec<String> = steps.iter().map(|step| step.describe()).collect();
+        return Err(MigrationError::NeedsMigration(names.join(", ")));
+    }
+    migrate(conn, options.key_length(), on_progress)?;
+
+    Ok(())
+}
+
+/// move_keyspace moves the keys in the default column family to the column families.
+/// Database without the column families, such as the one opened with legacy-keyspace feature, is kept as it is.
+fn move_keyspace(
+    conn: &rocksdb::DB,
+    _key_length: KeyLength,
+    on_progress: Progress,
+) -> Result<(), DataStoreError> {
+    column_families::migrate(conn, on_progress)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+
+    Ok(())
+}
+
+/// record_root_history records the roots of the heights with the diffs, which are committed before the root history.
+/// The roots are computed by applying the diffs from the current version in reverse, and the recorded ones are kept.
+/// Heights below the pruned height are not recorded, as their roots are removed together with the diffs.
+fn record_root_history(
+    conn: &rocksdb::DB,
+    key_length: KeyLength,
+    on_progress: Progress,
+) -> Result<(), DataStoreError> {
+    let unknown = |err: rocksdb::Error| DataStoreError::Unknown(err.to_string());
+    let current_state = match CurrentState::get(conn) {
+        Ok(Some(current_state)) => current_state,
+        Ok(None) => return Ok(()),
+        Err(err) => return Err(DataStoreError::Unknown(err.to_string())),
+    };
+    let hasher = HasherKind::from_name(current_state.hasher()).ok_or_else(|| {
+        DataStoreError::Unknown(format!("hasher {} is not enabled", current_state.hasher()))
+    })?;
+    let pruned_until = root_history::pruned_until(conn).map_err(unknown)?;
+    let mut smt_db = OverlaySmtDB::new(conn);
+    let mut root = current_state.root().to_vec();
+    let mut height = u32::from(current_state.version());
+    let mut batch = rocksdb::WriteBatch::default();
+    let mut processed = 0;
+    while height >= pruned_until {
+        let diff_key = [Prefix::DIFF, &height.to_be_bytes()].concat();
+        let diff_bytes = match column_families::get(conn, &diff_key).map_err(unknown)? {
+            Some(diff_bytes) => diff_bytes,
+            None => break,
+        };
+        let key = root_history::key(BlockHeight(height));
+        if conn.get(&key).map_err(unknown)?.is_none() {
+            batch.put(&key, &root);
+        }
+        processed += 1;
+        if processed % consts::MIGRATION_BATCH_SIZE == 0 {
+            conn.write(std::mem::take(&mut batch)).map_err(unknown)?;
+            on_progress(processed);
+        }
+        if height == 0 {
+            break;
+        }
+        let diff = check_diffs::read_diff(conn, height, &diff_bytes)?;
+        let prev_root = store::smt_apply_diff(&mut smt_db, &root, key_length, hasher, &diff)
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        root = (**prev_root.lock().unwrap()).clone();
+        height -= 1;
+    }
+    conn.write(batch).map_err(unknown)?;
+    on_progress(processed);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::state::root_history::RootAt;
+    use crate::state::state_writer::StateWriter;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::state_key;
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn options(readonly: bool, auto_migrate: bool) -> DbOptions {
+        let mut options = DbOptions::new(readonly, KEY_LENGTH);
+        options.auto_migrate = auto_migrate;
+        options
+    }
+
+    /// create_old_layout commits the heights and removes the version and the root history,
+    /// which is the layout written before the format version.
+    fn create_old_layout(temp_dir: &TempDir, heights: u8) -> Vec<Vec<u8>> {
+        let store = StateStore::open(temp_dir.path(), options(false, false)).unwrap();
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=heights {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(height), &[height; 4])
+                .unwrap();
+            let root = store
+                .commit(&writer, BlockHeight(height.into()), roots.last().unwrap())
+                .unwrap();
+            roots.push(root);
+        }
+        let conn = store.conn();
+        conn.delete(Prefix::FORMAT_VERSION).unwrap();
+        for height in 0..=heights {
+            conn.delete(root_history::key(BlockHeight(height.into())))
+                .unwrap();
+        }
+        roots
+    }
+
+    /// create_pre_series_layout writes the heights in the layout before the column families and the format version,
+    /// which has all the keys in the default column family and the current state of the root and the height.
+    fn create_pre_series_layout(temp_dir: &TempDir, heights: u8) -> Vec<Vec<u8>> {
+        let source_dir = TempDir::new("test_migrations_pre_series_source").unwrap();
+        let roots = create_old_layout(&source_dir, heights);
+        let source =
+            column_families::open(&rocksdb::Options::default(), source_dir.path(), true).unwrap();
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        let conn = rocksdb::DB::open(&opts, temp_dir.path()).unwrap();
+        for prefix in [Prefix::STATE, Prefix::SMT, Prefix::DIFF] {
+            let iter = match column_families::handle(&source, prefix) {
+                Some(cf) => source.iterator_cf(cf, rocksdb::IteratorMode::Start),
+                None => source.iterator(rocksdb::IteratorMode::From(
+                    prefix,
+                    rocksdb::Direction::Forward,
+                )),
+            };
+            for key_val in iter {
+                let (key, value) = key_val.unwrap();
+                if !key.starts_with(prefix) {
+                    break;
+                }
+                conn.put(&key, &value).unwrap();
+            }
+        }
+        let legacy_state = [
+            roots.last().unwrap().as_slice(),
+            &u32::from(heights).to_be_bytes(),
+        ]
+        .concat();
+        conn.put(Prefix::CURRENT_STATE, legacy_state).unwrap();
+        roots
+    }
+
+    #[test]
+    fn test_new_database_has_current_version() {
+        let temp_dir = TempDir::new("test_migrations_new").unwrap();
+        let store = StateStore::open(temp_dir.path(), options(false, false)).unwrap();
+        assert_eq!(stored_version(store.conn()).unwrap(), Some(FORMAT_VERSION));
+        drop(store);
+        // reopening does not need any migration
+        StateStore::open(temp_dir.path(), options(true, false)).unwrap();
+    }
+
+    #[test]
+    fn test_old_layout_needs_migration() {
+        let temp_dir = TempDir::new("test_migrations_needs").unwrap();
+        create_old_layout(&temp_dir, 3);
+
+        for readonly in [false, true] {
+            let err = StateStore::open(temp_dir.path(), options(readonly, false))
+                .err()
+                .unwrap();
+            match err {
+                MigrationError::NeedsMigration(steps) => {
+                    assert_eq!(steps, "column_families (0 -> 1), root_history (1 -> 2)")
+                },
+                err => panic!("unexpected error {:?}", err),
+            }
+        }
+        // readonly database is not migrated with auto_migrate
+        assert!(StateStore::open(temp_dir.path(), options(true, true)).is_err());
+    }
+
+    #[test]
+    fn test_auto_migrate_records_root_history() {
+        let temp_dir = TempDir::new("test_migrations_auto").unwrap();
+        let roots = create_old_layout(&temp_dir, 3);
+
+        let store = StateStore::open(temp_dir.path(), options(false, true)).unwrap();
+        assert_eq!(stored_version(store.conn()).unwrap(), Some(FORMAT_VERSION));
+        for (height, root) in roots.iter().enumerate().skip(1) {
+            assert_eq!(
+                root_history::get(store.conn(), BlockHeight(height as u32)).unwrap(),
+                RootAt::Recorded(root.clone()),
+            );
+        }
+        // height 0 is not committed
+        assert_eq!(
+            root_history::get(store.conn(), BlockHeight(0)).unwrap(),
+            RootAt::NotRecorded,
+        );
+        // the migrated database is opened without the option
+        drop(store);
+        StateStore::open(temp_dir.path(), options(false, false)).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_reports_progress() {
+        let temp_dir = TempDir::new("test_migrations_progress").unwrap();
+        create_old_layout(&temp_dir, 2);
+        let conn =
+            column_families::open(&rocksdb::Options::default(), temp_dir.path(), false).unwrap();
+        let mut reported = vec![];
+        let count = migrate(&conn, KEY_LENGTH, &mut |step, processed| {
+            reported.push((step.name, processed))
+        })
+        .unwrap();
+        // the keys are already in the column families
+        assert_eq!(count, 2);
+        assert_eq!(reported, vec![("root_history", 2)]);
+        // running it again does nothing
+        assert_eq!(migrate(&conn, KEY_LENGTH, &mut |_, _| {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pre_series_database() {
+        let temp_dir = TempDir::new("test_migrations_pre_series").unwrap();
+        let roots = create_pre_series_layout(&temp_dir, 3);
+
+        // the database is not changed without auto_migrate
+        let err = StateStore::open(temp_dir.path(), options(false, false))
+            .err()
+            .unwrap();
+        match err {
+            MigrationError::NeedsMigration(steps) => {
+                assert_eq!(steps, "column_families (0 -> 1), root_history (1 -> 2)")
+            },
+            err => panic!("unexpected error {:?}", err),
+        }
+        let opts = rocksdb::Options::default();
+        assert_eq!(
+            rocksdb::DB::list_cf(&opts, temp_dir.path()).unwrap(),
+            vec![rocksdb::DEFAULT_COLUMN_FAMILY_NAME]
+        );
+        let legacy = rocksdb::DB::open_for_read_only(&opts, temp_dir.path(), false).unwrap();
+        assert!(legacy.get(Prefix::FORMAT_VERSION).unwrap().is_none());
+        assert!(
+            CurrentState::from_bytes(&legacy.get(Prefix::CURRENT_STATE).unwrap().unwrap())
+                .unwrap()
+                .1
+        );
+        drop(legacy);
+
+        let conn = column_families::open(&opts, temp_dir.path(), false).unwrap();
+        let mut reported = vec![];
+        let count = migrate(&conn, KEY_LENGTH, &mut |step, processed| {
+            reported.push((step.name, processed))
+        })
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            reported.iter().any(|(name, _)| *name == "column_families"),
+            !cfg!(feature = "legacy-keyspace")
+        );
+        assert_eq!(stored_version(&conn).unwrap(), Some(FORMAT_VERSION));
+        drop(conn);
+
+        let store = StateStore::open(temp_dir.path(), options(false, false)).unwrap();
+        for (height, root) in roots.iter().enumerate().skip(1) {
+            assert_eq!(
+                root_history::get(store.conn(), BlockHeight(height as u32)).unwrap(),
+                RootAt::Recorded(root.clone()),
+            );
+            assert_eq!(
+                store.get(&state_key(height as u8)).unwrap(),
+                Some(vec![height as u8; 4])
+            );
+        }
+        let current_state = CurrentState::get(store.conn()).unwrap().unwrap();
+        assert_eq!(current_state.root(), roots[3].as_slice());
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let temp_dir = TempDir::new("test_migrations_newer").unwrap();
+        let store = StateStore::open(temp_dir.path(), options(false, false)).unwrap();
+        store
+            .conn()
+            .put(Prefix::FORMAT_VERSION, encode_version(FORMAT_VERSION + 1))
+            .unwrap();
+        drop(store);
+        assert!(matches!(
+            StateStore::open(temp_dir.path(), options(false, true)),
+            Err(MigrationError::Unsupported { .. })
+        ));
+    }
+}
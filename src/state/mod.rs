@@ -1,4 +1,48 @@
+/// bloom provides in-memory bloom filter over the state keys.
+pub mod bloom;
+/// bulk_load provides the rocksdb options of the bulk load of the initial sync.
+pub mod bulk_load;
+/// check_consistency provides the check of the latest commit against the tree and the diffs.
+pub mod check_consistency;
+/// check_diffs provides the scan of the stored diffs which cannot be decoded.
+pub mod check_diffs;
+/// check_integrity provides the integrity scan of the state at the quick or the full level.
+pub mod check_integrity;
+/// current_state provides the versioned record of the latest state.
+pub mod current_state;
+/// diff_values provides the refcounted store of the old values referenced from the diffs.
+pub mod diff_values;
+/// in_memory_state_db provides the state storage with the diffs and the SMT in memory.
+pub mod in_memory_state_db;
+/// migrations provides the upgrades of the on-disk format version.
+pub mod migrations;
+/// negative_cache provides the LRU cache of the state keys known not to exist.
+pub mod negative_cache;
+/// profile provides read amplification stats for the state range.
+pub mod profile;
+/// prune provides the mark-and-sweep of the SMT nodes which are not reachable from the retained roots.
+pub mod prune;
+/// rebuild_smt provides the recovery of the SMT nodes from the state keys.
+pub mod rebuild_smt;
+/// root_history provides the index of the state root committed at each height.
+pub mod root_history;
+/// smt_sync provides the SMT nodes served and installed by their hash for the state sync.
+pub mod smt_sync;
+/// snapshot provides the export and import of the whole state in Lisk snapshot format.
+pub mod snapshot;
+/// spill provides the temporary on-disk store for the StateWriter cache.
+pub mod spill;
 /// state_db provides authenticated data storage using sparse merkle tree.
+#[cfg(feature = "node")]
 pub mod state_db;
 /// state_writer provides batch writer for the state_db.
 pub mod state_writer;
+/// store provides the state storage on rocksdb without neon.
+pub mod store;
+/// test_utils provides the fixtures shared by the tests of the state storage.
+#[cfg(test)]
+pub(crate) mod test_utils;
+/// value_compression provides the envelope of the state values compressed on commit.
+pub mod value_compression;
+/// verify_nodes provides the integrity check of the stored SMT nodes.
+pub mod verify_nodes;
@@ -0,0 +1,152 @@
+/// negative_cache provides the in-memory LRU cache of the state keys known not to exist, so that the repeated lookups
+/// of the missing keys between the commits are answered without reading the database.
+/// The keys created by a commit are removed from the cache, and the cache is cleared when the state is changed otherwise.
+use std::collections::{BTreeMap, HashMap};
+
+/// NegativeCacheStats holds counters to measure effectiveness of the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NegativeCacheStats {
+    /// hits is the number of lookups answered by the cache without reading the database.
+    pub hits: u64,
+    /// misses is the number of lookups not in the cache.
+    pub misses: u64,
+    /// evictions is the number of keys removed as the least recently used over the capacity.
+    pub evictions: u64,
+    /// entries is the number of keys currently in the cache.
+    pub entries: usize,
+}
+
+/// NegativeCache holds up to the capacity of the missing keys, and evicts the least recently used key over it.
+pub struct NegativeCache {
+    capacity: usize,
+    /// keys holds the tick of the last use of each key.
+    keys: HashMap<Vec<u8>, u64>,
+    /// order holds the keys by the tick of the last use, so that the least recently used key is the first.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    stats: NegativeCacheStats,
+}
+
+impl NegativeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            keys: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            stats: NegativeCacheStats::default(),
+        }
+    }
+
+    /// touch marks the key as the most recently used.
+    fn touch(&mut self, key: &[u8]) -> bool {
+        let tick = match self.keys.get_mut(key) {
+            Some(tick) => tick,
+            None => return false,
+        };
+        self.order.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, key.to_vec());
+        true
+    }
+
+    /// contains returns true if the key is known not to exist, and counts the lookup.
+    pub fn contains(&mut self, key: &[u8]) -> bool {
+        let hit = self.touch(key);
+        if hit {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        hit
+    }
+
+    /// insert records that the key does not exist.
+    pub fn insert(&mut self, key: &[u8]) {
+        if self.touch(key) {
+            return;
+        }
+        if self.keys.len() >= self.capacity {
+            if let Some((_, evicted)) = self.order.pop_first() {
+                self.keys.remove(&evicted);
+                self.stats.evictions += 1;
+            }
+        }
+        self.tick += 1;
+        self.keys.insert(key.to_vec(), self.tick);
+        self.order.insert(self.tick, key.to_vec());
+    }
+
+    /// remove removes the keys created in the state.
+    pub fn remove<'a>(&mut self, keys: impl Iterator<Item = &'a Vec<u8>>) {
+        for key in keys {
+            if let Some(tick) = self.keys.remove(key) {
+                self.order.remove(&tick);
+            }
+        }
+    }
+
+    /// clear removes all the keys when the created keys are not known.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.order.clear();
+    }
+
+    pub fn stats(&self) -> NegativeCacheStats {
+        NegativeCacheStats {
+            entries: self.keys.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negative_cache_lru() {
+        let mut cache = NegativeCache::new(2);
+        assert!(!cache.contains(&[1]));
+        cache.insert(&[1]);
+        cache.insert(&[2]);
+        assert!(cache.contains(&[1]));
+        // [2] is the least recently used
+        cache.insert(&[3]);
+        assert!(!cache.contains(&[2]));
+        assert!(cache.contains(&[1]));
+        assert!(cache.contains(&[3]));
+        assert_eq!(
+            cache.stats(),
+            NegativeCacheStats {
+                hits: 3,
+                misses: 2,
+                evictions: 1,
+                entries: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_negative_cache_remove() {
+        let mut cache = NegativeCache::new(10);
+        for i in 0..5 {
+            cache.insert(&[i]);
+        }
+        cache.remove([vec![1], vec![3], vec![9]].iter());
+        assert!(!cache.contains(&[1]));
+        assert!(!cache.contains(&[3]));
+        assert!(cache.contains(&[4]));
+        assert_eq!(cache.stats().entries, 3);
+
+        cache.clear();
+        assert!(!cache.contains(&[4]));
+        assert_eq!(cache.stats().entries, 0);
+        // removed keys are not evicted
+        for i in 0..10 {
+            cache.insert(&[i]);
+        }
+        assert_eq!(cache.stats().evictions, 0);
+    }
+}
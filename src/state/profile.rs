@@ -0,0 +1,216 @@
+/// profile provides read amplification stats for a range of the state using rocksdb perf context.
+/// Perf context is thread local, so the scan must run on a dedicated thread.
+use rocksdb::perf::{self, PerfContext, PerfMetric, PerfStatsLevel};
+
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
+use crate::database::options::ProfileRangeOption;
+use crate::database::utils::{get_iteration_mode, is_key_after_prefix, is_key_out_of_range};
+
+/// RangeProfile holds the aggregated counters of the scan.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RangeProfile {
+    /// keys is the number of keys returned by the scan.
+    pub keys: u64,
+    /// bytes_returned is the size of keys and values returned by the scan.
+    pub bytes_returned: u64,
+    /// bytes_read is the size of the blocks read from the table files.
+    pub bytes_read: u64,
+    pub block_read_count: u64,
+    pub block_cache_hit_count: u64,
+    /// max_blocks_read_per_key is the largest number of blocks read to return a key.
+    pub max_blocks_read_per_key: u64,
+    pub internal_key_skipped_count: u64,
+    pub internal_delete_skipped_count: u64,
+    pub bloom_sst_hit_count: u64,
+    pub bloom_sst_miss_count: u64,
+    /// truncated is true if the scan stopped by the row cap.
+    pub truncated: bool,
+}
+
+impl RangeProfile {
+    /// blocks_read_per_key returns the average number of blocks read for a key.
+    pub fn blocks_read_per_key(&self) -> f64 {
+        if self.keys == 0 {
+            return 0.0;
+        }
+        self.block_read_count as f64 / self.keys as f64
+    }
+
+    /// bloom_useful_rate returns the rate of the bloom filter checks which avoided reading the file.
+    pub fn bloom_useful_rate(&self) -> f64 {
+        let checks = self.bloom_sst_hit_count + self.bloom_sst_miss_count;
+        if checks == 0 {
+            return 0.0;
+        }
+        self.bloom_sst_miss_count as f64 / checks as f64
+    }
+
+    fn update(&mut self, context: &PerfContext) {
+        self.bytes_read = context.metric(PerfMetric::BlockReadByte);
+        self.block_read_count = context.metric(PerfMetric::BlockReadCount);
+        self.block_cache_hit_count = context.metric(PerfMetric::BlockCacheHitCount);
+        self.internal_key_skipped_count = context.metric(PerfMetric::InternalKeySkippedCount);
+        self.internal_delete_skipped_count =
+            context.metric(PerfMetric::InternalDeleteSkippedCount);
+        self.bloom_sst_hit_count = context.metric(PerfMetric::BloomSstHitCount);
+        self.bloom_sst_miss_count = context.metric(PerfMetric::BloomSstMissCount);
+    }
+}
+
+/// profile_range scans the state range on the snapshot while collecting the perf counters.
+/// The scan does not fill the block cache, so that it does not evict the blocks used by the node.
+/// on_progress is called with the counters at every progress interval.
+pub fn profile_range(
+    snapshot: &StateSnapshot,
+    options: &ProfileRangeOption,
+    mut on_progress: impl FnMut(&RangeProfile),
+) -> Result<RangeProfile, rocksdb::Error> {
+    perf::set_perf_stats(PerfStatsLevel::EnableCount);
+    let mut context = PerfContext::default();
+    context.reset();
+
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mut start = vec![];
+    let mode = get_iteration_mode(&options.iteration, &mut start, Prefix::STATE);
+
+    let mut profile = RangeProfile::default();
+    let mut prev_block_read_count = 0;
+    for key_val in snapshot.iterator_opt(Prefix::STATE, mode, read_options) {
+        let (key, value) = key_val?;
+        if is_key_after_prefix(&options.iteration, &key, Prefix::STATE) {
+            continue;
+        }
+        if is_key_out_of_range(&options.iteration, &key, profile.keys as i64, Prefix::STATE) {
+            break;
+        }
+        if profile.keys >= options.max_rows {
+            profile.truncated = true;
+            break;
+        }
+        profile.keys += 1;
+        profile.bytes_returned += (key.len() + value.len()) as u64;
+        profile.update(&context);
+        profile.max_blocks_read_per_key = profile
+            .max_blocks_read_per_key
+            .max(profile.block_read_count - prev_block_read_count);
+        prev_block_read_count = profile.block_read_count;
+        if options.progress_interval > 0 && profile.keys % options.progress_interval == 0 {
+            on_progress(&profile);
+            // let other threads proceed as the scan is low priority
+            std::thread::yield_now();
+        }
+    }
+    profile.update(&context);
+    perf::set_perf_stats(PerfStatsLevel::Disable);
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::database::options::IterationOption;
+
+    fn options(gte: &[u8], lte: &[u8], max_rows: u64) -> ProfileRangeOption {
+        ProfileRangeOption {
+            iteration: IterationOption {
+                limit: -1,
+                reverse: false,
+                gte: Some(gte.to_vec()),
+                lte: Some(lte.to_vec()),
+                prefix: None,
+                keys_only: false,
+                inventory: false,
+                min_value_size: None,
+            },
+            max_rows,
+            progress_interval: 100,
+        }
+    }
+
+    fn synthetic_db(temp_dir: &TempDir) -> rocksdb::DB {
+        let db = rocksdb::DB::open_default(temp_dir).unwrap();
+        let mut rng = rand::thread_rng();
+        for module in [[0, 0, 0, 1], [0, 0, 0, 2]] {
+            for _ in 0..1000 {
+                let mut suffix = vec![0; 16];
+                rng.fill_bytes(&mut suffix);
+                let mut value = vec![0; 100];
+                rng.fill_bytes(&mut value);
+                let key = [Prefix::STATE, &module, &suffix].concat();
+                db.put(key, value).unwrap();
+            }
+        }
+        db.flush().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_profile_range() {
+        let temp_dir = TempDir::new("test_profile_range").unwrap();
+        let db = synthetic_db(&temp_dir);
+        let snapshot = StateSnapshot::new(&db);
+
+        let mut progress: Vec<RangeProfile> = vec![];
+        let profile = profile_range(
+            &snapshot,
+            &options(&[0, 0, 0, 1], &[0, 0, 0, 2], 10_000),
+            |p| progress.push(p.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(profile.keys, 1000);
+        assert!(!profile.truncated);
+        // key includes the state prefix
+        assert_eq!(profile.bytes_returned, 1000 * (1 + 4 + 16 + 100));
+        assert!(profile.block_read_count > 0);
+        assert!(profile.bytes_read >= profile.bytes_returned);
+        assert!(profile.max_blocks_read_per_key >= 1);
+        assert!(profile.blocks_read_per_key() > 0.0);
+        assert!(profile.blocks_read_per_key() <= profile.max_blocks_read_per_key as f64);
+        assert!((0.0..=1.0).contains(&profile.bloom_useful_rate()));
+
+        // counters are monotonic over the progress reports
+        assert_eq!(progress.len(), 10);
+        for (i, window) in progress.windows(2).enumerate() {
+            assert_eq!(window[0].keys, (i as u64 + 1) * 100);
+            assert!(window[0].keys < window[1].keys);
+            assert!(window[0].bytes_returned < window[1].bytes_returned);
+            assert!(window[0].bytes_read <= window[1].bytes_read);
+            assert!(window[0].block_read_count <= window[1].block_read_count);
+        }
+        assert!(progress.last().unwrap().bytes_read <= profile.bytes_read);
+    }
+
+    #[test]
+    fn test_profile_range_max_rows() {
+        let temp_dir = TempDir::new("test_profile_range_max_rows").unwrap();
+        let db = synthetic_db(&temp_dir);
+        let snapshot = StateSnapshot::new(&db);
+
+        let profile = profile_range(
+            &snapshot,
+            &options(&[0, 0, 0, 1], &[0, 0, 0, 3], 150),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(profile.keys, 150);
+        assert!(profile.truncated);
+
+        let profile = profile_range(
+            &snapshot,
+            &options(&[0, 0, 0, 3], &[0, 0, 0, 4], 150),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(profile.keys, 0);
+        assert_eq!(profile.bytes_returned, 0);
+        assert!(!profile.truncated);
+        assert_eq!(profile.blocks_read_per_key(), 0.0);
+    }
+}
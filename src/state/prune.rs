@@ -0,0 +1,359 @@
+/// prune provides the mark-and-sweep of the SMT nodes which are not reachable from the retained roots.
+/// Reachable nodes are never deleted and each batch is written atomically,
+/// so the pruning can be interrupted at any point and run again.
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::consts::{self, Prefix};
+use crate::database::column_families::{self, StateSnapshot};
+use crate::database::options::PruneSMTOption;
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt;
+use crate::sparse_merkle_tree::smt_db::OverlaySmtDB;
+use crate::state::check_diffs;
+use crate::state::current_state::CurrentState;
+use crate::state::store::{smt_apply_diff, DataStoreError};
+use crate::types::{BlockHeight, KeyLength};
+
+/// PruneReport holds the progress of the sweep.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// reachable is the number of the nodes reachable from the retained roots.
+    pub reachable: u64,
+    /// scanned is the number of the stored nodes checked.
+    pub scanned: u64,
+    /// deleted is the number of the nodes deleted.
+    pub deleted: u64,
+}
+
+fn unknown(err: impl ToString) -> DataStoreError {
+    DataStoreError::Unknown(err.to_string())
+}
+
+/// collect_node_keys inserts the node keys of the root into reachable.
+fn collect_node_keys(
+    db: &OverlaySmtDB,
+    root: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+    reachable: &mut HashSet<Vec<u8>>,
+) -> Result<(), DataStoreError> {
+    smt::SparseMerkleTree::new_with_hasher(root, key_length, consts::SUBTREE_HEIGHT, hasher)
+        .collect_node_keys(db, reachable)
+        .map_err(unknown)
+}
+
+/// mark returns the keys of the nodes reachable from the roots of the latest retain_heights heights,
+/// together with the current state which the roots are rebuilt from.
+/// The past roots are rebuilt from the stored diffs, and it fails with HeightNotRetained if a diff in the window is not stored.
+pub fn mark(
+    conn: &rocksdb::DB,
+    retain_heights: u32,
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<(HashSet<Vec<u8>>, CurrentState), DataStoreError> {
+    let current_state =
+        CurrentState::get_or_empty(conn, &hasher.empty_hash(0)).map_err(unknown)?;
+    // rebuilt nodes are kept in memory, so that the database is not changed
+    let mut smt_db = OverlaySmtDB::new(conn);
+    let mut reachable = HashSet::new();
+    let mut root = current_state.root().to_vec();
+    collect_node_keys(&smt_db, &root, key_length, hasher, &mut reachable)?;
+
+    let mut version = current_state.version();
+    for _ in 1..retain_heights {
+        if u32::from(version) == 0 {
+            break;
+        }
+        let prev_version = version - BlockHeight(1);
+        let diff_bytes =
+            column_families::get(conn, &[Prefix::DIFF, &version.to_be_bytes()].concat())
+                .map_err(unknown)?
+                .ok_or_else(|| DataStoreError::HeightNotRetained(prev_version.into()))?;
+        let diff = check_diffs::read_diff(conn, version.into(), &diff_bytes)?;
+        let prev_root =
+            smt_apply_diff(&mut smt_db, &root, key_length, hasher, &diff).map_err(unknown)?;
+        root = (**prev_root.lock().unwrap()).clone();
+        collect_node_keys(&smt_db, &root, key_length, hasher, &mut reachable)?;
+        version = prev_version;
+    }
+
+    Ok((reachable, current_state))
+}
+
+/// sweep deletes the stored nodes which are not in reachable in batches of batch_size.
+/// Each batch is written while holding write_lock, which the commits and the reverts hold while writing.
+/// When the current state is changed after marked, the nodes of the new root are added to reachable before deleting,
+/// so that the nodes written again by the commit are kept.
+/// on_progress is called with the report after every batch.
+pub fn sweep(
+    conn: &rocksdb::DB,
+    mut reachable: HashSet<Vec<u8>>,
+    marked: CurrentState,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    options: &PruneSMTOption,
+    write_lock: &Mutex<()>,
+    mut on_progress: impl FnMut(&PruneReport),
+) -> Result<PruneReport, DataStoreError> {
+    let mut last_state = marked;
+    let mut report = PruneReport {
+        reachable: reachable.len() as u64,
+        ..Default::default()
+    };
+    let mut delete_batch = |keys: &mut Vec<Vec<u8>>,
+                            reachable: &mut HashSet<Vec<u8>>,
+                            report: &mut PruneReport|
+     -> Result<(), DataStoreError> {
+        let _guard = write_lock.lock().unwrap();
+        let current_state =
+            CurrentState::get_or_empty(conn, &hasher.empty_hash(0)).map_err(unknown)?;
+        if current_state != last_state {
+            let smt_db = OverlaySmtDB::new(conn);
+            collect_node_keys(&smt_db, current_state.root(), key_length, hasher, reachable)?;
+            report.reachable = reachable.len() as u64;
+            last_state = current_state;
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        for key in keys.drain(..).filter(|key| !reachable.contains(key)) {
+            batch.delete([Prefix::SMT, &key].concat());
+            report.deleted += 1;
+        }
+        column_families::write(conn, batch).map_err(unknown)?;
+        on_progress(report);
+        Ok(())
+    };
+
+    let snapshot = StateSnapshot::new(conn);
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mode = rocksdb::IteratorMode::From(Prefix::SMT, rocksdb::Direction::Forward);
+    let mut pending = Vec::with_capacity(options.batch_size);
+    for key_val in snapshot.iterator_opt(Prefix::SMT, mode, read_options) {
+        let (key, _) = key_val.map_err(unknown)?;
+        if !key.starts_with(Prefix::SMT) {
+            break;
+        }
+        report.scanned += 1;
+        let node_key = &key[Prefix::SMT.len()..];
+        if reachable.contains(node_key) {
+            continue;
+        }
+        pending.push(node_key.to_vec());
+        if pending.len() >= options.batch_size {
+            delete_batch(&mut pending, &mut reachable, &mut report)?;
+        }
+    }
+    if !pending.is_empty() {
+        delete_batch(&mut pending, &mut reachable, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+/// prune_smt deletes the nodes which are not reachable from the roots of the latest retain_heights heights.
+pub fn prune_smt(
+    conn: &rocksdb::DB,
+    retain_heights: u32,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    options: &PruneSMTOption,
+    write_lock: &Mutex<()>,
+    on_progress: impl FnMut(&PruneReport),
+) -> Result<PruneReport, DataStoreError> {
+    let (reachable, marked) = mark(conn, retain_heights, key_length, hasher)?;
+    sweep(
+        conn,
+        reachable,
+        marked,
+        key_length,
+        hasher,
+        options,
+        write_lock,
+        on_progress,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::{commit, open, state_key};
+    use crate::types::{HashKind, HashWithKind};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn options() -> PruneSMTOption {
+        PruneSMTOption { batch_size: 3 }
+    }
+
+    fn node_count(conn: &rocksdb::DB) -> usize {
+        let mode = rocksdb::IteratorMode::From(Prefix::SMT, rocksdb::Direction::Forward);
+        column_families::iterator(conn, Prefix::SMT, mode)
+            .map(|key_val| key_val.unwrap().0)
+            .take_while(|key| key.starts_with(Prefix::SMT))
+            .count()
+    }
+
+    /// committed_store commits the heights 1 to 5, where each height updates all the keys, and returns the roots.
+    fn committed_store(temp_dir: &TempDir) -> (StateStore, Vec<Vec<u8>>) {
+        let store = open(temp_dir, false);
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=5_u8 {
+            let upserts = (0..30).map(|i| (i, vec![height, i]));
+            let root = commit(&store, height.into(), roots.last().unwrap(), upserts, []);
+            roots.push(root);
+        }
+        (store, roots)
+    }
+
+    fn assert_provable(store: &StateStore, root: &[u8], height: u8) {
+        let query_keys: Vec<Vec<u8>> = (0..30)
+            .step_by(7)
+            .map(|i| state_key(i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = store.prove(root, &query_keys).unwrap();
+        for (query, i) in proof.queries.iter().zip((0..30).step_by(7)) {
+            assert_eq!(
+                query.value(),
+                vec![height, i].hash_with_kind(HashKind::Value)
+            );
+        }
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, root, KEY_LENGTH).unwrap());
+    }
+
+    #[test]
+    fn test_prune_smt() {
+        let temp_dir = TempDir::new("test_prune_smt").unwrap();
+        let (store, roots) = committed_store(&temp_dir);
+        let conn = store.conn();
+        let before = node_count(conn);
+        let lock = Mutex::new(());
+
+        let mut progress = vec![];
+        let report = prune_smt(
+            conn,
+            2,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &lock,
+            |p| progress.push(p.clone()),
+        )
+        .unwrap();
+        assert!(report.deleted > 0);
+        assert_eq!(report.scanned, before as u64);
+        assert_eq!(node_count(conn) as u64, before as u64 - report.deleted);
+        assert_eq!(node_count(conn) as u64, report.reachable);
+        assert_eq!(progress.last(), Some(&report));
+
+        // proofs of the retained roots still verify
+        assert_provable(&store, &roots[5], 5);
+        assert_provable(&store, &roots[4], 4);
+        // nodes of the older roots are deleted
+        assert!(store
+            .prove(&roots[3], &[state_key(0).hash_with_kind(HashKind::Key)])
+            .is_err());
+        // historical state is rebuilt from the current root
+        let state = store.historical_state(BlockHeight(3)).unwrap();
+        assert_eq!(state.root(), roots[3].as_slice());
+
+        // rerun does not delete the retained nodes
+        let rerun = prune_smt(
+            conn,
+            2,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &lock,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(rerun.deleted, 0);
+        assert_provable(&store, &roots[4], 4);
+    }
+
+    #[test]
+    fn test_prune_smt_interrupted() {
+        let temp_dir = TempDir::new("test_prune_smt_interrupted").unwrap();
+        let (store, roots) = committed_store(&temp_dir);
+        let conn = store.conn();
+        let lock = Mutex::new(());
+
+        // stop after the first batch as if the process is killed
+        let (reachable, marked) = mark(conn, 3, KEY_LENGTH, HasherKind::Sha256).unwrap();
+        let mut pending: Vec<Vec<u8>> = vec![];
+        let mode = rocksdb::IteratorMode::From(Prefix::SMT, rocksdb::Direction::Forward);
+        for key_val in conn.iterator(mode) {
+            let key = key_val.unwrap().0;
+            if !reachable.contains(&key[Prefix::SMT.len()..]) {
+                pending.push(key.to_vec());
+            }
+            if pending.len() == options().batch_size {
+                break;
+            }
+        }
+        for key in pending {
+            conn.delete(key).unwrap();
+        }
+        assert_provable(&store, &roots[3], 3);
+
+        let report = sweep(
+            conn,
+            reachable,
+            marked,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &lock,
+            |_| {},
+        )
+        .unwrap();
+        assert!(report.deleted > 0);
+        assert_provable(&store, &roots[5], 5);
+        assert_provable(&store, &roots[4], 4);
+        assert_provable(&store, &roots[3], 3);
+    }
+
+    #[test]
+    fn test_prune_smt_with_commit_after_mark() {
+        let temp_dir = TempDir::new("test_prune_smt_with_commit_after_mark").unwrap();
+        let (store, roots) = committed_store(&temp_dir);
+        let conn = store.conn();
+        let lock = Mutex::new(());
+
+        let (reachable, marked) = mark(conn, 1, KEY_LENGTH, HasherKind::Sha256).unwrap();
+        // revert writes the nodes of the previous root again, which were not reachable when marked
+        let reverted = store.revert(BlockHeight(5), &roots[5]).unwrap();
+        assert_eq!(reverted, roots[4]);
+
+        sweep(
+            conn,
+            reachable,
+            marked,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &lock,
+            |_| {},
+        )
+        .unwrap();
+        assert_provable(&store, &roots[4], 4);
+    }
+
+    #[test]
+    fn test_prune_smt_without_diff() {
+        let temp_dir = TempDir::new("test_prune_smt_without_diff").unwrap();
+        let (store, _) = committed_store(&temp_dir);
+        let conn = store.conn();
+        conn.delete([Prefix::DIFF, &4_u32.to_be_bytes()].concat())
+            .unwrap();
+        let before = node_count(conn);
+
+        let err = prune_smt(conn, 3, KEY_LENGTH, &options(), &Mutex::new(()), |_| {}).unwrap_err();
+        assert!(matches!(err, DataStoreError::HeightNotRetained(3)));
+        assert_eq!(node_count(conn), before);
+    }
+}
@@ -0,0 +1,529 @@
+/// rebuild_smt provides the recovery of the SMT nodes from the state keys.
+/// The tree is built from the snapshot of the state keys in chunks, and the nodes are written with Prefix::SMT_REBUILD,
+/// so that the nodes with Prefix::SMT are replaced only if the rebuilt root matches the expected root.
+/// The state keys are never changed, and the rebuilt nodes are removed at the start, so the rebuild can be
+/// interrupted at any point and run again from scratch.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::consts::{self, Prefix};
+use crate::database::column_families::{self, StateSnapshot};
+use crate::database::options::RebuildSMTOption;
+use crate::database::traits::{Actions, NodeReader};
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt;
+use crate::state::current_state::CurrentState;
+use crate::state::store::DataStoreError;
+use crate::state::value_compression;
+use crate::types::{BlockHeight, Cache, HashKind, HashWithKind, KVPair, KeyLength, VecOption};
+use crate::utils;
+
+/// REBUILD_END is the end of the range of the rebuilt nodes.
+const REBUILD_END: &[u8] = &[15];
+
+/// RebuildReport holds the progress of the rebuild.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// keys is the number of the state keys.
+    pub keys: u64,
+    /// inserted is the number of the state keys inserted to the rebuilt tree.
+    pub inserted: u64,
+}
+
+/// StagedSmtDB reads the rebuilt nodes, and keeps the changes of a chunk in memory.
+struct StagedSmtDB<'a> {
+    conn: &'a rocksdb::DB,
+    /// overlay holds the changed nodes. None if the node is deleted.
+    overlay: HashMap<Vec<u8>, VecOption>,
+}
+
+impl<'a> StagedSmtDB<'a> {
+    fn new(conn: &'a rocksdb::DB) -> Self {
+        Self {
+            conn,
+            overlay: HashMap::new(),
+        }
+    }
+
+    /// into_batch returns the batch of the changed nodes with Prefix::SMT_REBUILD.
+    fn into_batch(self) -> rocksdb::WriteBatch {
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, value) in self.overlay {
+            let key = [Prefix::SMT_REBUILD, &key].concat();
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        batch
+    }
+}
+
+impl Actions for StagedSmtDB<'_> {
+    fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        self.read_node(key)
+    }
+
+    fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+        self.overlay
+            .insert(pair.key_as_vec(), Some(pair.value_as_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.overlay.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn node_reader(&self) -> Option<&dyn NodeReader> {
+        Some(self)
+    }
+}
+
+impl NodeReader for StagedSmtDB<'_> {
+    fn read_node(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        match self.overlay.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.conn.get([Prefix::SMT_REBUILD, key].concat()),
+        }
+    }
+}
+
+fn unknown(err: impl ToString) -> DataStoreError {
+    DataStoreError::Unknown(err.to_string())
+}
+
+fn read_options() -> rocksdb::ReadOptions {
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    read_options
+}
+
+/// state_iterator iterates the state keys on the snapshot.
+fn state_iterator<'a>(
+    snapshot: &'a StateSnapshot<'a>,
+) -> impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>> + 'a {
+    let mode = rocksdb::IteratorMode::From(Prefix::STATE, rocksdb::Direction::Forward);
+    snapshot
+        .iterator_opt(Prefix::STATE, mode, read_options())
+        .take_while(|key_val| {
+            key_val
+                .as_ref()
+                .map_or(true, |(key, _)| key.starts_with(Prefix::STATE))
+        })
+}
+
+/// node_iterator iterates the node keys and the values with the prefix, without the prefix.
+fn node_iterator<'a>(
+    conn: &'a rocksdb::DB,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = Result<(Vec<u8>, Box<[u8]>), rocksdb::Error>> + 'a {
+    let mode = rocksdb::IteratorMode::From(prefix, rocksdb::Direction::Forward);
+    column_families::iterator_opt(conn, prefix, mode, read_options())
+        .take_while(move |key_val| {
+            key_val
+                .as_ref()
+                .map_or(true, |(key, _)| key.starts_with(prefix))
+        })
+        .map(move |key_val| key_val.map(|(key, value)| (key[prefix.len()..].to_vec(), value)))
+}
+
+/// clear_staged deletes all the rebuilt nodes, including the ones left by an interrupted rebuild.
+fn clear_staged(conn: &rocksdb::DB) -> Result<(), DataStoreError> {
+    let mut batch = rocksdb::WriteBatch::default();
+    batch.delete_range(Prefix::SMT_REBUILD, REBUILD_END);
+    column_families::write(conn, batch).map_err(unknown)
+}
+
+fn check_root(root: &[u8], expected: &[u8]) -> Result<(), DataStoreError> {
+    if !utils::is_bytes_equal(root, expected) {
+        return Err(DataStoreError::InvalidRoot(format!(
+            "rebuilt root {} is not matching with expected {}",
+            hex::encode(root),
+            hex::encode(expected)
+        )));
+    }
+    Ok(())
+}
+
+/// snapshot_state returns the current state on the snapshot.
+fn snapshot_state(
+    snapshot: &StateSnapshot,
+    hasher: HasherKind,
+) -> Result<CurrentState, DataStoreError> {
+    match snapshot.get(Prefix::CURRENT_STATE).map_err(unknown)? {
+        Some(bytes) => Ok(CurrentState::from_bytes(&bytes).map_err(unknown)?.0),
+        None => Ok(CurrentState::new(&hasher.empty_hash(0), BlockHeight(0))),
+    }
+}
+
+/// stage writes the tree of the state keys on the snapshot with Prefix::SMT_REBUILD, and returns the root.
+/// The state keys are read and inserted in chunks of batch_size keys, so that only a chunk is held in memory.
+/// The subtrees replaced by a chunk are deleted by the commit of the tree, and the root of the previous chunk is deleted
+/// here, so that only the nodes of the returned root are left.
+fn stage(
+    conn: &rocksdb::DB,
+    snapshot: &StateSnapshot,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    options: &RebuildSMTOption,
+    report: &mut RebuildReport,
+    mut on_progress: impl FnMut(&RebuildReport),
+) -> Result<Vec<u8>, DataStoreError> {
+    clear_staged(conn)?;
+    let mut tree =
+        smt::SparseMerkleTree::new_with_hasher(&[], key_length, consts::SUBTREE_HEIGHT, hasher);
+    let mut rebuilt = hasher.empty_hash(0);
+    let mut state = state_iterator(snapshot).peekable();
+    while state.peek().is_some() {
+        let mut chunk = Cache::new();
+        for key_val in state.by_ref().take(options.batch_size) {
+            let (key, value) = key_val.map_err(unknown)?;
+            chunk.insert(
+                key[Prefix::STATE.len()..]
+                    .to_vec()
+                    .hash_with_kind(HashKind::Key),
+                value_compression::decode(&value).hash_with_kind(HashKind::Value),
+            );
+        }
+        let inserted = chunk.len() as u64;
+        let mut smt_db = StagedSmtDB::new(conn);
+        let root = tree
+            .commit(&mut smt_db, &smt::UpdateData::new_from(chunk))
+            .map_err(unknown)?;
+        let root = (**root.lock().unwrap()).clone();
+        // root of the previous chunk is not replaced by the commit, and no longer reachable
+        if !hasher.is_empty_hash(&rebuilt) && rebuilt != root {
+            smt_db.del(&rebuilt).map_err(unknown)?;
+        }
+        rebuilt = root;
+        column_families::write(conn, smt_db.into_batch()).map_err(unknown)?;
+        report.inserted += inserted;
+        on_progress(report);
+    }
+    Ok(rebuilt)
+}
+
+/// copy_staged writes the rebuilt nodes with Prefix::SMT in batches of batch_size nodes.
+/// The nodes are stored by the hash, so that the nodes written before the swap are the same as the valid ones
+/// already stored, and the commits do not read them unless they are reachable.
+fn copy_staged(conn: &rocksdb::DB, batch_size: usize) -> Result<(), DataStoreError> {
+    let mut batch = rocksdb::WriteBatch::default();
+    let mut pending = 0;
+    for key_val in node_iterator(conn, Prefix::SMT_REBUILD) {
+        let (key, value) = key_val.map_err(unknown)?;
+        column_families::put(conn, &mut batch, &[Prefix::SMT, &key].concat(), &value);
+        pending += 1;
+        if pending >= batch_size {
+            column_families::write(conn, std::mem::take(&mut batch)).map_err(unknown)?;
+            pending = 0;
+        }
+    }
+    column_families::write(conn, batch).map_err(unknown)
+}
+
+/// swap deletes the nodes with Prefix::SMT which are not rebuilt, while holding write_lock.
+/// The nodes are compared in the key order, so that none of them are held in memory.
+/// It fails without deleting any node if the current state is changed from marked, which the tree is rebuilt from.
+fn swap(
+    conn: &rocksdb::DB,
+    marked: &CurrentState,
+    batch_size: usize,
+    write_lock: &Mutex<()>,
+) -> Result<(), DataStoreError> {
+    let _guard = write_lock.lock().unwrap();
+    let current_state = CurrentState::get_or_empty(conn, marked.root()).map_err(unknown)?;
+    if &current_state != marked {
+        return Err(DataStoreError::Unknown(
+            "state is changed during the rebuild, run it again".to_string(),
+        ));
+    }
+    let mut staged = node_iterator(conn, Prefix::SMT_REBUILD);
+    let mut next_staged = staged.next().transpose().map_err(unknown)?;
+    let mut batch = rocksdb::WriteBatch::default();
+    let mut pending = 0;
+    for key_val in node_iterator(conn, Prefix::SMT) {
+        let (key, _) = key_val.map_err(unknown)?;
+        while next_staged
+            .as_ref()
+            .map_or(false, |(staged_key, _)| staged_key < &key)
+        {
+            next_staged = staged.next().transpose().map_err(unknown)?;
+        }
+        if next_staged
+            .as_ref()
+            .map_or(false, |(staged_key, _)| staged_key == &key)
+        {
+            continue;
+        }
+        column_families::delete(conn, &mut batch, &[Prefix::SMT, &key].concat());
+        pending += 1;
+        if pending >= batch_size {
+            column_families::write(conn, std::mem::take(&mut batch)).map_err(unknown)?;
+            pending = 0;
+        }
+    }
+    column_families::write(conn, batch).map_err(unknown)
+}
+
+/// rebuild_smt rebuilds the tree of the state keys in chunks of batch_size keys, and replaces all the SMT nodes with it.
+/// The tree is built from a snapshot without holding write_lock, and it fails with InvalidRoot without changing
+/// the SMT nodes if the rebuilt root does not match expected. write_lock is held only for the final swap, which deletes
+/// the nodes not in the rebuilt tree, and it fails if a commit or a revert is written during the rebuild.
+/// Nodes of the past roots are not rebuilt, therefore the proofs of the past heights are not available after the rebuild.
+/// on_progress is called with the report after every chunk.
+pub fn rebuild_smt(
+    conn: &rocksdb::DB,
+    expected: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+    options: &RebuildSMTOption,
+    write_lock: &Mutex<()>,
+    on_progress: impl FnMut(&RebuildReport),
+) -> Result<RebuildReport, DataStoreError> {
+    let snapshot = StateSnapshot::new(conn);
+    let marked = snapshot_state(&snapshot, hasher)?;
+    let mut report = RebuildReport::default();
+    for key_val in state_iterator(&snapshot) {
+        key_val.map_err(unknown)?;
+        report.keys += 1;
+    }
+    let result = stage(
+        conn,
+        &snapshot,
+        key_length,
+        hasher,
+        options,
+        &mut report,
+        on_progress,
+    )
+    .and_then(|root| check_root(&root, expected))
+    .and_then(|_| copy_staged(conn, options.batch_size))
+    .and_then(|_| swap(conn, &marked, options.batch_size, write_lock));
+    clear_staged(conn)?;
+    result.map(|_| report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::sparse_merkle_tree::smt_db::OverlaySmtDB;
+    use crate::state::state_writer::StateWriter;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::{commit, open, state_key};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn options() -> RebuildSMTOption {
+        RebuildSMTOption { batch_size: 7 }
+    }
+
+    fn keys_with_prefix(conn: &rocksdb::DB, prefix: &[u8]) -> Vec<Vec<u8>> {
+        node_iterator(conn, prefix)
+            .map(|key_val| key_val.unwrap().0)
+            .collect()
+    }
+
+    fn node_keys(conn: &rocksdb::DB) -> Vec<Vec<u8>> {
+        keys_with_prefix(conn, Prefix::SMT)
+    }
+
+    fn reachable_count(conn: &rocksdb::DB, root: &[u8]) -> usize {
+        let mut reachable = std::collections::HashSet::new();
+        smt::SparseMerkleTree::new(root, KEY_LENGTH, consts::SUBTREE_HEIGHT)
+            .collect_node_keys(&OverlaySmtDB::new(conn), &mut reachable)
+            .unwrap();
+        reachable.len()
+    }
+
+    /// committed_store commits the heights 1 and 2, where the second height updates and deletes some keys.
+    fn committed_store(temp_dir: &TempDir) -> (StateStore, Vec<u8>) {
+        let store = open(temp_dir, false);
+        let upserts = (0..30).map(|i| (i, vec![1, i]));
+        let root = commit(&store, 1, &smt::EMPTY_HASH, upserts, []);
+        let upserts = (0..10).map(|i| (i, vec![2, i]));
+        let root = commit(&store, 2, &root, upserts, 20..30);
+        (store, root)
+    }
+
+    fn assert_provable(store: &StateStore, root: &[u8]) {
+        let query_keys: Vec<Vec<u8>> = (0..30)
+            .step_by(3)
+            .map(|i| state_key(i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = store.prove(root, &query_keys).unwrap();
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, root, KEY_LENGTH).unwrap());
+    }
+
+    #[test]
+    fn test_rebuild_smt() {
+        let temp_dir = TempDir::new("test_rebuild_smt").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let conn = store.conn();
+        // lose the root node as if the nodes are corrupted
+        conn.delete([Prefix::SMT, &root].concat()).unwrap();
+        assert!(store.prove(&root, &[state_key(0)]).is_err());
+
+        let mut progress = vec![];
+        let report = rebuild_smt(
+            conn,
+            &root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &Mutex::new(()),
+            |p| progress.push(p.clone()),
+        )
+        .unwrap();
+        assert_eq!(report.keys, 20);
+        assert_eq!(report.inserted, 20);
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[0].inserted, 7);
+        assert_eq!(progress.last(), Some(&report));
+        assert_provable(&store, &root);
+        // only the nodes of the current root are kept
+        assert_eq!(node_keys(conn).len(), reachable_count(conn, &root));
+        assert!(keys_with_prefix(conn, Prefix::SMT_REBUILD).is_empty());
+
+        // rerun rebuilds the same nodes
+        let nodes = node_keys(conn);
+        let rerun = rebuild_smt(
+            conn,
+            &root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &Mutex::new(()),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(rerun, report);
+        assert_eq!(node_keys(conn), nodes);
+    }
+
+    #[test]
+    fn test_rebuild_smt_does_not_hold_lock() {
+        let temp_dir = TempDir::new("test_rebuild_smt_does_not_hold_lock").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let conn = store.conn();
+        let lock = Mutex::new(());
+
+        // the lock is free while the chunks are written, and held only for the swap
+        let mut locked = vec![];
+        rebuild_smt(
+            conn,
+            &root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &lock,
+            |_| locked.push(lock.try_lock().is_err()),
+        )
+        .unwrap();
+        assert_eq!(locked, vec![false; 3]);
+        assert_provable(&store, &root);
+    }
+
+    #[test]
+    fn test_rebuild_smt_interrupted() {
+        let temp_dir = TempDir::new("test_rebuild_smt_interrupted").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let conn = store.conn();
+        conn.delete([Prefix::SMT, &root].concat()).unwrap();
+
+        // stop before the swap as if the process is killed, leaving the rebuilt nodes and the copied ones
+        let snapshot = StateSnapshot::new(conn);
+        let mut report = RebuildReport::default();
+        stage(
+            conn,
+            &snapshot,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &mut report,
+            |_| {},
+        )
+        .unwrap();
+        drop(snapshot);
+        copy_staged(conn, 3).unwrap();
+        assert!(!keys_with_prefix(conn, Prefix::SMT_REBUILD).is_empty());
+        assert!(node_keys(conn).len() > reachable_count(conn, &root));
+
+        rebuild_smt(
+            conn,
+            &root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &Mutex::new(()),
+            |_| {},
+        )
+        .unwrap();
+        assert_provable(&store, &root);
+        assert_eq!(node_keys(conn).len(), reachable_count(conn, &root));
+        assert!(keys_with_prefix(conn, Prefix::SMT_REBUILD).is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_smt_with_state_changed() {
+        let temp_dir = TempDir::new("test_rebuild_smt_with_state_changed").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let conn = store.conn();
+
+        // commit between the rebuild from the snapshot and the swap
+        let snapshot = StateSnapshot::new(conn);
+        let marked = snapshot_state(&snapshot).unwrap();
+        let mut report = RebuildReport::default();
+        let rebuilt = stage(
+            conn,
+            &snapshot,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &mut report,
+            |_| {},
+        )
+        .unwrap();
+        drop(snapshot);
+        assert_eq!(rebuilt, root);
+        copy_staged(conn, 3).unwrap();
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[3, 0]).unwrap();
+        let next_root = store.commit(&writer, BlockHeight(3), &root).unwrap();
+        let nodes = node_keys(conn);
+
+        let err = swap(conn, &marked, 3, &Mutex::new(())).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("state is changed during the rebuild"));
+        assert_eq!(node_keys(conn), nodes);
+        assert_provable(&store, &next_root);
+    }
+
+    #[test]
+    fn test_rebuild_smt_with_wrong_root() {
+        let temp_dir = TempDir::new("test_rebuild_smt_with_wrong_root").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let conn = store.conn();
+        let nodes = node_keys(conn);
+
+        let expected = vec![1; 32];
+        let err = rebuild_smt(
+            conn,
+            &expected,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &options(),
+            &Mutex::new(()),
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, DataStoreError::InvalidRoot(_)));
+        assert!(err.to_string().contains(&hex::encode(&expected)));
+        assert_eq!(node_keys(conn), nodes);
+        assert!(keys_with_prefix(conn, Prefix::SMT_REBUILD).is_empty());
+        assert_provable(&store, &root);
+    }
+}
@@ -0,0 +1,209 @@
+/// root_history provides the index of the state root committed at each height.
+/// The roots are written together with the commit and removed together with the diffs,
+/// so that the root of a revertible height can be checked without applying the diff.
+/// The heights committed in the bulk load have the roots without the diffs, and they are recorded as the bulk-loaded ranges.
+use std::convert::TryInto;
+
+use crate::consts::Prefix;
+use crate::database::column_families;
+use crate::types::BlockHeight;
+
+/// RootAt is the result of looking up the root of a height.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RootAt {
+    /// Recorded holds the root committed at the height.
+    Recorded(Vec<u8>),
+    /// Pruned is the height whose root is removed together with the diffs.
+    Pruned,
+    /// NotRecorded is the height which is not committed, or committed before the index was written.
+    NotRecorded,
+}
+
+/// key returns the database key of the root of the height.
+pub fn key(height: BlockHeight) -> Vec<u8> {
+    [Prefix::ROOT_HISTORY, &height.to_be_bytes()].concat()
+}
+
+/// pruned_until returns the height below which the roots are removed. 0 if never pruned.
+pub fn pruned_until(conn: &rocksdb::DB) -> Result<u32, rocksdb::Error> {
+    Ok(conn
+        .get(Prefix::ROOT_HISTORY_PRUNED)?
+        .and_then(|bytes| bytes.as_slice().try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or(0))
+}
+
+/// prune adds the removal of the roots below until to the routed batch.
+/// The pruned height never decreases, so that the heights removed earlier are still reported as pruned.
+pub fn prune(
+    conn: &rocksdb::DB,
+    batch: &mut rocksdb::WriteBatch,
+    until: u32,
+) -> Result<(), rocksdb::Error> {
+    if until <= pruned_until(conn)? {
+        return Ok(());
+    }
+    column_families::delete_range(conn, batch, &key(BlockHeight(0)), &key(BlockHeight(until)));
+    batch.put(Prefix::ROOT_HISTORY_PRUNED, until.to_be_bytes());
+    Ok(())
+}
+
+/// get returns the root committed at the height.
+pub fn get(conn: &rocksdb::DB, height: BlockHeight) -> Result<RootAt, rocksdb::Error> {
+    if let Some(root) = conn.get(key(height))? {
+        return Ok(RootAt::Recorded(root));
+    }
+    if u32::from(height) < pruned_until(conn)? {
+        return Ok(RootAt::Pruned);
+    }
+    Ok(RootAt::NotRecorded)
+}
+
+/// latest returns at most limit heights with the roots in descending order of the height.
+pub fn latest(conn: &rocksdb::DB, limit: usize) -> Result<Vec<(u32, Vec<u8>)>, rocksdb::Error> {
+    let last = key(BlockHeight(u32::MAX));
+    let mode = rocksdb::IteratorMode::From(&last, rocksdb::Direction::Reverse);
+    let mut result = vec![];
+    for key_val in conn.iterator(mode) {
+        if result.len() >= limit {
+            break;
+        }
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::ROOT_HISTORY) {
+            break;
+        }
+        let height: [u8; 4] = match key[Prefix::ROOT_HISTORY.len()..].try_into() {
+            Ok(height) => height,
+            Err(_) => continue,
+        };
+        result.push((u32::from_be_bytes(height), value.to_vec()));
+    }
+    Ok(result)
+}
+
+/// bulk_loaded_key returns the database key of the bulk-loaded range ending at the height.
+fn bulk_loaded_key(end: BlockHeight) -> Vec<u8> {
+    [Prefix::BULK_LOADED, &end.to_be_bytes()].concat()
+}
+
+/// mark_bulk_loaded adds the height committed in the bulk load to the routed batch.
+/// The range ending at the previous height is extended, so that the consecutive heights are recorded as a single range.
+pub fn mark_bulk_loaded(
+    conn: &rocksdb::DB,
+    batch: &mut rocksdb::WriteBatch,
+    height: BlockHeight,
+) -> Result<(), rocksdb::Error> {
+    let mut start = height.to_be_bytes().to_vec();
+    if let Some(prev) = u32::from(height).checked_sub(1) {
+        let prev_key = bulk_loaded_key(BlockHeight(prev));
+        if let Some(prev_start) = conn.get(&prev_key)? {
+            batch.delete(prev_key);
+            start = prev_start;
+        }
+    }
+    batch.put(bulk_loaded_key(height), start);
+    Ok(())
+}
+
+/// bulk_loaded returns the bulk-loaded range (start, end) containing the height. None if the height has the diff.
+pub fn bulk_loaded(
+    conn: &rocksdb::DB,
+    height: BlockHeight,
+) -> Result<Option<(u32, u32)>, rocksdb::Error> {
+    let from = bulk_loaded_key(height);
+    let mode = rocksdb::IteratorMode::From(&from, rocksdb::Direction::Forward);
+    // the first range ending at or above the height is the only one which can contain it
+    if let Some(key_val) = conn.iterator(mode).next() {
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::BULK_LOADED) {
+            return Ok(None);
+        }
+        let end: [u8; 4] = match key[Prefix::BULK_LOADED.len()..].try_into() {
+            Ok(end) => end,
+            Err(_) => return Ok(None),
+        };
+        let start: [u8; 4] = match value.as_ref().try_into() {
+            Ok(start) => start,
+            Err(_) => return Ok(None),
+        };
+        let (start, end) = (u32::from_be_bytes(start), u32::from_be_bytes(end));
+        if start <= u32::from(height) {
+            return Ok(Some((start, end)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_root_history() {
+        let temp_dir = TempDir::new("test_root_history").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        assert_eq!(get(&db, BlockHeight(1)).unwrap(), RootAt::NotRecorded);
+        assert!(latest(&db, 10).unwrap().is_empty());
+
+        for height in 1..=5_u32 {
+            db.put(key(BlockHeight(height)), [height as u8; 32])
+                .unwrap();
+        }
+        // keys out of the prefix are not read
+        db.put(Prefix::ROOT_HISTORY_PRUNED, 0_u32.to_be_bytes())
+            .unwrap();
+        assert_eq!(
+            get(&db, BlockHeight(3)).unwrap(),
+            RootAt::Recorded(vec![3; 32])
+        );
+        assert_eq!(
+            latest(&db, 2).unwrap(),
+            vec![(5, vec![5; 32]), (4, vec![4; 32])]
+        );
+
+        let mut batch = rocksdb::WriteBatch::default();
+        prune(&db, &mut batch, 3).unwrap();
+        db.write(batch).unwrap();
+        assert_eq!(get(&db, BlockHeight(2)).unwrap(), RootAt::Pruned);
+        assert_eq!(
+            get(&db, BlockHeight(3)).unwrap(),
+            RootAt::Recorded(vec![3; 32])
+        );
+        assert_eq!(get(&db, BlockHeight(6)).unwrap(), RootAt::NotRecorded);
+        assert_eq!(latest(&db, 10).unwrap().len(), 3);
+
+        // lower height does not move the pruned height back
+        let mut batch = rocksdb::WriteBatch::default();
+        prune(&db, &mut batch, 1).unwrap();
+        db.write(batch).unwrap();
+        assert_eq!(pruned_until(&db).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_root_history_bulk_loaded() {
+        let temp_dir = TempDir::new("test_root_history_bulk_loaded").unwrap();
+        let db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for height in [3_u32, 4, 5, 8, 9] {
+            let mut batch = rocksdb::WriteBatch::default();
+            mark_bulk_loaded(&db, &mut batch, BlockHeight(height)).unwrap();
+            db.write(batch).unwrap();
+        }
+        assert_eq!(bulk_loaded(&db, BlockHeight(2)).unwrap(), None);
+        assert_eq!(bulk_loaded(&db, BlockHeight(3)).unwrap(), Some((3, 5)));
+        assert_eq!(bulk_loaded(&db, BlockHeight(5)).unwrap(), Some((3, 5)));
+        assert_eq!(bulk_loaded(&db, BlockHeight(6)).unwrap(), None);
+        assert_eq!(bulk_loaded(&db, BlockHeight(9)).unwrap(), Some((8, 9)));
+        assert_eq!(bulk_loaded(&db, BlockHeight(10)).unwrap(), None);
+        // consecutive heights are kept in a single range
+        let mut ranges = 0;
+        for key_val in db.prefix_iterator(Prefix::BULK_LOADED) {
+            let (key, _) = key_val.unwrap();
+            if key.starts_with(Prefix::BULK_LOADED) {
+                ranges += 1;
+            }
+        }
+        assert_eq!(ranges, 2);
+    }
+}
@@ -0,0 +1,251 @@
+/// smt_sync provides the SMT nodes served by their hash for the state sync, and the installation of the received nodes.
+/// The nodes are the stored subtrees keyed by the hash of their root, so that the syncing node walks the tree
+/// from the root by fetching each node and then the child subtrees referenced by it.
+/// The state keys are not synced by the nodes, and they are restored separately.
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+use crate::consts::{self, Prefix};
+use crate::database::column_families::{self, StateSnapshot};
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt::{self, SMTError, SparseMerkleTree, SubtreeCheck};
+use crate::sparse_merkle_tree::smt_db::SnapshotSmtDB;
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::types::{BlockHeight, KeyLength, VecOption};
+
+#[derive(Error, Debug)]
+pub enum SmtSyncError {
+    /// Disabled is the error of installing the nodes to the database opened without unsafeSync.
+    #[error("SMT sync is disabled. Open the database with unsafeSync: true to install the nodes")]
+    Disabled,
+    /// NodeMismatch is the error of the received node which does not hash to its key.
+    #[error("SMT node {0} does not match its hash")]
+    NodeMismatch(String),
+    /// Incomplete is the error of finalizing the tree which is missing or has mismatched nodes.
+    #[error("synced tree of root {root} is incomplete at node {node}")]
+    Incomplete { root: String, node: String },
+    #[error(transparent)]
+    Smt(#[from] SMTError),
+    #[error(transparent)]
+    CurrentState(#[from] CurrentStateError),
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+}
+
+/// get_node returns the encoded node stored with the hash, or None if it is not stored.
+/// The node of the empty tree is not stored.
+pub fn get_node(snapshot: &StateSnapshot, node_hash: &[u8]) -> Result<VecOption, rocksdb::Error> {
+    snapshot.get(&[Prefix::SMT, node_hash].concat())
+}
+
+/// get_children returns the hashes of the child subtrees referenced by the node, or None if the node is not stored.
+/// The leaves of the node are included in its encoding, therefore the node without the children is the bottom of the tree.
+pub fn get_children(
+    snapshot: &StateSnapshot,
+    node_hash: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<Option<Vec<Vec<u8>>>, SmtSyncError> {
+    match get_node(snapshot, node_hash)? {
+        Some(data) => Ok(Some(smt::child_node_hashes_with_hasher(
+            &data, key_length, hasher,
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// put_nodes writes the received nodes in a batch. Each node must hash to its key, and nothing is written otherwise.
+/// The current state is not changed until the tree is finalized.
+/// write_opts are the ones of the commit, so that the installed nodes are as durable as the committed ones.
+pub fn put_nodes(
+    conn: &rocksdb::DB,
+    nodes: &[(Vec<u8>, Vec<u8>)],
+    key_length: KeyLength,
+    hasher: HasherKind,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), SmtSyncError> {
+    let mut batch = rocksdb::WriteBatch::default();
+    for (node_hash, data) in nodes.iter() {
+        let matched = smt::calculate_node_hash_with_hasher(data, key_length, hasher)
+            .map(|hash| &hash == node_hash)
+            .unwrap_or(false);
+        if !matched {
+            return Err(SmtSyncError::NodeMismatch(hex::encode(node_hash)));
+        }
+        batch.put([Prefix::SMT, node_hash].concat(), data);
+    }
+    column_families::write_opt(conn, batch, write_opts)?;
+    Ok(())
+}
+
+/// finalize_sync walks the stored tree from the expected root, and writes the current state of the root at the height
+/// only if every node reachable from the root is stored and matches its hash.
+/// write_lock is held, so that no commit or revert is written in between.
+pub fn finalize_sync(
+    conn: &rocksdb::DB,
+    expected_root: &[u8],
+    height: BlockHeight,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    write_lock: &Mutex<()>,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<(), SmtSyncError> {
+    let _guard = write_lock.lock().unwrap();
+    let snapshot = StateSnapshot::new(conn);
+    let smt_db = SnapshotSmtDB::new(&snapshot);
+    let tree = SparseMerkleTree::new_with_hasher(
+        expected_root,
+        key_length,
+        consts::SUBTREE_HEIGHT,
+        hasher,
+    );
+    let mut incomplete = None;
+    tree.check_subtrees(&smt_db, |check| match check {
+        SubtreeCheck::Valid { .. } => true,
+        SubtreeCheck::Missing(node_hash) | SubtreeCheck::Mismatched(node_hash) => {
+            incomplete = Some(node_hash);
+            false
+        },
+    })?;
+    if let Some(node_hash) = incomplete {
+        return Err(SmtSyncError::Incomplete {
+            root: hex::encode(expected_root),
+            node: hex::encode(node_hash),
+        });
+    }
+    CurrentState::new(expected_root, height)
+        .with_hasher(hasher)
+        .put_opt(conn, write_opts)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::database::types::WriteDurability;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::{commit, open};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    /// committed_store commits the state of 100 keys at the height 1.
+    fn committed_store(temp_dir: &TempDir) -> (StateStore, Vec<u8>) {
+        let store = open(temp_dir, false);
+        let upserts = (0..100).map(|i| (i, vec![1, i]));
+        let root = commit(&store, 1, &smt::EMPTY_HASH, upserts, []);
+        (store, root)
+    }
+
+    /// fetch_nodes walks the tree of the root on the serving database from the root as the syncing node.
+    fn fetch_nodes(conn: &rocksdb::DB, root: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let snapshot = StateSnapshot::new(conn);
+        let mut nodes = vec![];
+        let mut pending = vec![root.to_vec()];
+        while let Some(node_hash) = pending.pop() {
+            let data = get_node(&snapshot, &node_hash).unwrap().unwrap();
+            let children = get_children(&snapshot, &node_hash, KEY_LENGTH, HasherKind::Sha256)
+                .unwrap()
+                .unwrap();
+            pending.extend(children);
+            nodes.push((node_hash, data));
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_get_unknown_node() {
+        let temp_dir = TempDir::new("test_get_unknown_node").unwrap();
+        let (store, _) = committed_store(&temp_dir);
+        let snapshot = StateSnapshot::new(store.conn());
+        assert_eq!(get_node(&snapshot, &[9; 32]).unwrap(), None);
+        assert!(
+            get_children(&snapshot, &[9; 32], KEY_LENGTH, HasherKind::Sha256)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_sync_nodes() {
+        let temp_dir = TempDir::new("test_sync_nodes_serving").unwrap();
+        let (serving, root) = committed_store(&temp_dir);
+        let nodes = fetch_nodes(serving.conn(), &root);
+        assert!(nodes.len() > 1);
+
+        let sync_dir = TempDir::new("test_sync_nodes").unwrap();
+        let syncing = open(&sync_dir, false);
+        let write_lock = Mutex::new(());
+        let write_opts = WriteDurability::default().write_options(true);
+        // the tree is not finalized until all the nodes are installed
+        put_nodes(
+            syncing.conn(),
+            &nodes[..nodes.len() - 1],
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &write_opts,
+        )
+        .unwrap();
+        let result = finalize_sync(
+            syncing.conn(),
+            &root,
+            BlockHeight(1),
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &write_lock,
+            &write_opts,
+        );
+        assert!(matches!(result, Err(SmtSyncError::Incomplete { .. })));
+        assert_eq!(
+            syncing.current_state().unwrap().root(),
+            &smt::EMPTY_HASH[..]
+        );
+
+        put_nodes(
+            syncing.conn(),
+            &nodes[nodes.len() - 1..],
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &write_opts,
+        )
+        .unwrap();
+        finalize_sync(
+            syncing.conn(),
+            &root,
+            BlockHeight(1),
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &write_lock,
+            &write_opts,
+        )
+        .unwrap();
+        let current_state = syncing.current_state().unwrap();
+        assert_eq!(current_state.root(), root.as_slice());
+        assert_eq!(current_state.version(), BlockHeight(1));
+    }
+
+    #[test]
+    fn test_put_mismatched_node() {
+        let temp_dir = TempDir::new("test_put_mismatched_node").unwrap();
+        let (store, root) = committed_store(&temp_dir);
+        let mut nodes = fetch_nodes(store.conn(), &root);
+        nodes[0].0 = vec![9; 32];
+
+        let sync_dir = TempDir::new("test_put_mismatched_node_syncing").unwrap();
+        let write_opts = WriteDurability::default().write_options(false);
+        let syncing = open(&sync_dir, false);
+        let result = put_nodes(
+            syncing.conn(),
+            &nodes,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &write_opts,
+        );
+        assert!(matches!(result, Err(SmtSyncError::NodeMismatch(_))));
+        // nothing is written
+        let snapshot = StateSnapshot::new(syncing.conn());
+        assert_eq!(get_node(&snapshot, &nodes[1].0).unwrap(), None);
+    }
+}
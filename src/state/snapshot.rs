@@ -0,0 +1,580 @@
+/// snapshot provides the export and import of the whole state in Lisk snapshot format,
+/// so that a node can bootstrap from the state of a height instead of replaying the blocks.
+/// The file consists of:
+/// - header: magic bytes and the version of the format.
+/// - records: length of the record followed by the key-value pair encoded with lisk-codec, sorted by the key.
+/// - footer: number of the records, height, state root and sha256 checksum of the records.
+///
+/// All the numbers are big endian.
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::batch;
+use crate::consts::{self, Prefix};
+use crate::database::column_families::{self, StateSnapshot};
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt::{self, SMTError};
+use crate::sparse_merkle_tree::smt_db::SmtDB;
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::state::value_compression;
+use crate::types::{BlockHeight, Cache, HashKind, HashWithKind, KVPair, KVPairCodec, KeyLength};
+use crate::utils;
+
+pub const MAGIC: &[u8] = b"lisk-db:state";
+pub const VERSION: u32 = 1;
+
+const ROOT_LENGTH: usize = 32;
+const CHECKSUM_LENGTH: usize = 32;
+const HEADER_LENGTH: usize = MAGIC.len() + 4;
+const FOOTER_LENGTH: usize = 8 + 4 + ROOT_LENGTH + CHECKSUM_LENGTH;
+
+#[derive(Error, Debug)]
+pub enum StateSnapshotError {
+    #[error("Invalid snapshot header")]
+    InvalidHeader,
+    #[error("Unsupported snapshot version `{0}`")]
+    UnsupportedVersion(u32),
+    #[error("Corrupted snapshot `{0}`")]
+    Corrupted(String),
+    #[error("height {height} is not the current height {current}")]
+    HeightNotCurrent { height: u32, current: u32 },
+    #[error("state is not empty")]
+    NotEmpty,
+    #[error("state root does not match with the snapshot")]
+    RootMismatch,
+    #[error("Snapshot file error `{0}`")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Database(#[from] rocksdb::Error),
+    #[error("{0}")]
+    CurrentState(#[from] CurrentStateError),
+    #[error("{0}")]
+    SMT(#[from] SMTError),
+}
+
+/// SnapshotFooter is the summary of the state in the snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotFooter {
+    /// records is the number of the key-value pairs.
+    pub records: u64,
+    pub height: BlockHeight,
+    pub root: Vec<u8>,
+}
+
+impl SnapshotFooter {
+    fn encode(&self, checksum: &[u8]) -> Vec<u8> {
+        [
+            self.records.to_be_bytes().as_slice(),
+            &self.height.to_be_bytes(),
+            &self.root,
+            checksum,
+        ]
+        .concat()
+    }
+
+    /// decode returns the footer and the checksum of the records.
+    fn decode(bytes: &[u8; FOOTER_LENGTH]) -> (Self, Vec<u8>) {
+        let (records, rest) = bytes.split_at(8);
+        let (height, rest) = rest.split_at(4);
+        let (root, checksum) = rest.split_at(ROOT_LENGTH);
+        let footer = Self {
+            records: u64::from_be_bytes(records.try_into().unwrap()),
+            height: BlockHeight(u32::from_be_bytes(height.try_into().unwrap())),
+            root: root.to_vec(),
+        };
+        (footer, checksum.to_vec())
+    }
+}
+
+/// export writes all the state records of the height to the file at the path.
+/// The records are read from a snapshot of the database, and the height must be the current height of the snapshot.
+/// The file is written to a temporary path and renamed, so that the existing file is not replaced by incomplete snapshot.
+/// on_progress is called with the number of the records written at every progress interval.
+/// When sync is set, the directory entry of the renamed file is also flushed to the disk before returning.
+pub fn export(
+    conn: &rocksdb::DB,
+    path: &Path,
+    height: BlockHeight,
+    hasher: HasherKind,
+    progress_interval: u64,
+    sync: bool,
+    mut on_progress: impl FnMut(u64),
+) -> Result<SnapshotFooter, StateSnapshotError> {
+    let snapshot = StateSnapshot::new(conn);
+    let current_state = match snapshot.get(Prefix::CURRENT_STATE)? {
+        Some(bytes) => CurrentState::from_bytes(&bytes)?.0,
+        None => CurrentState::new(&hasher.empty_hash(0), BlockHeight(0)),
+    };
+    if current_state.version() != height {
+        return Err(StateSnapshotError::HeightNotCurrent {
+            height: height.into(),
+            current: current_state.version().into(),
+        });
+    }
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut file = BufWriter::new(fs::File::create(&temp_path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_be_bytes())?;
+
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mode = rocksdb::IteratorMode::From(Prefix::STATE, rocksdb::Direction::Forward);
+    let mut hasher = Sha256::new();
+    let mut records = 0;
+    for key_val in snapshot.iterator_opt(Prefix::STATE, mode, read_options) {
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::STATE) {
+            break;
+        }
+        // the records hold the uncompressed values, so that the snapshot does not depend on the compression
+        let value = value_compression::decode(&value);
+        let record = KVPair::new(&key[Prefix::STATE.len()..], &value).encode();
+        let length = (record.len() as u32).to_be_bytes();
+        hasher.update(length);
+        hasher.update(&record);
+        file.write_all(&length)?;
+        file.write_all(&record)?;
+        records += 1;
+        if progress_interval > 0 && records % progress_interval == 0 {
+            on_progress(records);
+        }
+    }
+
+    let footer = SnapshotFooter {
+        records,
+        height,
+        root: current_state.root().to_vec(),
+    };
+    file.write_all(&footer.encode(&hasher.finalize()))?;
+    let file = file.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp_path, path)?;
+    if sync {
+        utils::sync_path(path)?;
+    }
+
+    Ok(footer)
+}
+
+/// read_footer validates the header and returns the footer with the checksum and the length of the records.
+fn read_footer(file: &mut fs::File) -> Result<(SnapshotFooter, Vec<u8>, u64), StateSnapshotError> {
+    let size = file.metadata()?.len();
+    if size < (HEADER_LENGTH + FOOTER_LENGTH) as u64 {
+        return Err(StateSnapshotError::InvalidHeader);
+    }
+    let mut header = [0; HEADER_LENGTH];
+    file.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(StateSnapshotError::InvalidHeader);
+    }
+    let version = u32::from_be_bytes(header[MAGIC.len()..].try_into().unwrap());
+    if version != VERSION {
+        return Err(StateSnapshotError::UnsupportedVersion(version));
+    }
+
+    file.seek(SeekFrom::End(-(FOOTER_LENGTH as i64)))?;
+    let mut footer = [0; FOOTER_LENGTH];
+    file.read_exact(&mut footer)?;
+    file.seek(SeekFrom::Start(HEADER_LENGTH as u64))?;
+    let (footer, checksum) = SnapshotFooter::decode(&footer);
+
+    Ok((
+        footer,
+        checksum,
+        size - (HEADER_LENGTH + FOOTER_LENGTH) as u64,
+    ))
+}
+
+/// write_records writes the records to the state prefix in a batch for every progress interval,
+/// and returns the hashed key-value pairs of the records for the tree.
+fn write_records(
+    conn: &rocksdb::DB,
+    reader: &mut impl Read,
+    mut remaining: u64,
+    footer: &SnapshotFooter,
+    checksum: &[u8],
+    progress_interval: u64,
+    on_progress: &mut impl FnMut(u64),
+) -> Result<Cache, StateSnapshotError> {
+    let mut write_batch = batch::PrefixWriteBatch::new();
+    write_batch.set_prefix(&Prefix::STATE);
+    let mut hasher = Sha256::new();
+    let mut hashed = Cache::new();
+    let mut records = 0;
+    let mut last_key: Option<Vec<u8>> = None;
+    while remaining > 0 {
+        let mut length = [0; 4];
+        if remaining < length.len() as u64 {
+            return Err(StateSnapshotError::Corrupted(String::from(
+                "truncated record",
+            )));
+        }
+        reader.read_exact(&mut length)?;
+        remaining -= length.len() as u64;
+        let record_length = u32::from_be_bytes(length) as u64;
+        if record_length > remaining {
+            return Err(StateSnapshotError::Corrupted(String::from(
+                "truncated record",
+            )));
+        }
+        let mut record = vec![0; record_length as usize];
+        reader.read_exact(&mut record)?;
+        remaining -= record_length;
+        hasher.update(length);
+        hasher.update(&record);
+
+        let pair = KVPair::decode(&record)
+            .map_err(|err| StateSnapshotError::Corrupted(err.to_string()))?;
+        if let Some(last_key) = last_key.as_ref() {
+            if utils::compare(last_key, pair.key()) != std::cmp::Ordering::Less {
+                return Err(StateSnapshotError::Corrupted(String::from(
+                    "records are not sorted by the key",
+                )));
+            }
+        }
+        write_batch.put(pair.key(), &value_compression::encode(pair.value(), None));
+        hashed.insert(
+            pair.key_as_vec().hash_with_kind(HashKind::Key),
+            pair.value_as_vec().hash_with_kind(HashKind::Value),
+        );
+        last_key = Some(pair.key_as_vec());
+        records += 1;
+        if progress_interval > 0 && records % progress_interval == 0 {
+            column_families::write(conn, std::mem::take(&mut write_batch.batch))?;
+            on_progress(records);
+        }
+    }
+    column_families::write(conn, write_batch.batch)?;
+
+    if records != footer.records {
+        return Err(StateSnapshotError::Corrupted(String::from(
+            "number of records mismatch",
+        )));
+    }
+    if hasher.finalize().as_slice() != checksum {
+        return Err(StateSnapshotError::Corrupted(String::from(
+            "checksum mismatch",
+        )));
+    }
+    Ok(hashed)
+}
+
+/// import restores the state from the snapshot file at the path into the empty database.
+/// The records are written in batches while reading, and the SMT nodes and the current state are written
+/// only after the root recomputed from the records matches the footer.
+/// Otherwise, the written records are removed and the database is left empty.
+/// on_progress is called with the number of the records written at every progress interval.
+pub fn import(
+    conn: &rocksdb::DB,
+    path: &Path,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    progress_interval: u64,
+    mut on_progress: impl FnMut(u64),
+) -> Result<SnapshotFooter, StateSnapshotError> {
+    if CurrentState::get(conn)?.is_some() {
+        return Err(StateSnapshotError::NotEmpty);
+    }
+    let mut file = fs::File::open(path)?;
+    let (footer, checksum, records_length) = read_footer(&mut file)?;
+    let mut reader = BufReader::new(file);
+
+    let result = (|| -> Result<(), StateSnapshotError> {
+        let hashed = write_records(
+            conn,
+            &mut reader,
+            records_length,
+            &footer,
+            &checksum,
+            progress_interval,
+            &mut on_progress,
+        )?;
+        let data = smt::UpdateData::new_from(hashed);
+        // the root is checked before creating the nodes
+        let root = smt::SparseMerkleTree::calculate_root_from_data_with_hasher(
+            &data, key_length, hasher,
+        )?;
+        if root != footer.root {
+            return Err(StateSnapshotError::RootMismatch);
+        }
+
+        let mut smt_db = SmtDB::new(conn);
+        let mut tree = smt::SparseMerkleTree::new_with_hasher(
+            &[],
+            key_length,
+            consts::SUBTREE_HEIGHT,
+            hasher,
+        );
+        tree.commit(&mut smt_db, &data)?;
+        let mut write_batch = batch::PrefixWriteBatch::new();
+        write_batch.set_prefix(&Prefix::SMT);
+        smt_db.batch.iterate(&mut write_batch);
+        write_batch.batch.put(
+            Prefix::CURRENT_STATE,
+            CurrentState::new(&footer.root, footer.height)
+                .with_hasher(hasher)
+                .encode(),
+        );
+        column_families::write(conn, write_batch.batch)?;
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        let mut batch = rocksdb::WriteBatch::default();
+        column_families::delete_range(conn, &mut batch, Prefix::STATE, Prefix::SMT);
+        conn.write(batch)?;
+        return Err(err);
+    }
+    Ok(footer)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::state::state_writer::StateWriter;
+    use crate::state::store::StateStore;
+    use crate::state::test_utils::open;
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn state_key(i: u32) -> Vec<u8> {
+        [
+            vec![0, 0, 0, 1, 0, 0],
+            vec![0; 28],
+            i.to_be_bytes().to_vec(),
+        ]
+        .concat()
+    }
+
+    /// commit_state commits the keys in two heights, and deletes some of them at the second height.
+    fn commit_state(store: &StateStore, count: u32) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        for i in 0..count {
+            store
+                .upsert(&mut writer, &state_key(i), &i.to_be_bytes())
+                .unwrap();
+        }
+        let root = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+        let mut writer = StateWriter::default();
+        for i in (0..count).step_by(3) {
+            store.delete(&mut writer, &state_key(i)).unwrap();
+        }
+        store.commit(&writer, BlockHeight(2), &root).unwrap()
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let source_dir = TempDir::new("test_state_snapshot_source").unwrap();
+        let source = open(&source_dir, false);
+        let root = commit_state(&source, 50);
+        let path = source_dir.path().join("state.snapshot");
+
+        let mut exported = vec![];
+        let footer = export(
+            source.conn(),
+            &path,
+            BlockHeight(2),
+            HasherKind::Sha256,
+            10,
+            false,
+            |count| exported.push(count),
+        )
+        .unwrap();
+        assert_eq!(
+            footer,
+            SnapshotFooter {
+                records: 33,
+                height: BlockHeight(2),
+                root: root.clone(),
+            }
+        );
+        assert_eq!(exported, vec![10, 20, 30]);
+
+        let target_dir = TempDir::new("test_state_snapshot_target").unwrap();
+        let target = open(&target_dir, false);
+        let mut imported = vec![];
+        let result = import(
+            target.conn(),
+            &path,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            10,
+            |count| imported.push(count),
+        )
+        .unwrap();
+        assert_eq!(result, footer);
+        assert_eq!(imported, vec![10, 20, 30]);
+
+        let current_state = target.current_state().unwrap();
+        assert_eq!(current_state.root(), root.as_slice());
+        assert_eq!(current_state.version(), BlockHeight(2));
+        for i in 0..50 {
+            assert_eq!(
+                target.get(&state_key(i)).unwrap(),
+                source.get(&state_key(i)).unwrap()
+            );
+        }
+        // nodes are created, so that the next height can be committed on the imported state
+        let mut next_roots = vec![];
+        for store in [&source, &target] {
+            let mut writer = StateWriter::default();
+            store.upsert(&mut writer, &state_key(1), &[1]).unwrap();
+            next_roots.push(store.commit(&writer, BlockHeight(3), &root).unwrap());
+        }
+        assert_eq!(next_roots[0], next_roots[1]);
+
+        // imported database is not empty
+        assert!(matches!(
+            import(
+                target.conn(),
+                &path,
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                10,
+                |_| {}
+            ),
+            Err(StateSnapshotError::NotEmpty)
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_export_syncs_the_path() {
+        let temp_dir = TempDir::new("test_state_snapshot_sync").unwrap();
+        let store = open(&temp_dir, false);
+        commit_state(&store, 5);
+        let path = temp_dir.path().join("state.snapshot");
+        utils::take_synced_paths();
+        export(
+            store.conn(),
+            &path,
+            BlockHeight(2),
+            HasherKind::Sha256,
+            10,
+            false,
+            |_| {},
+        )
+        .unwrap();
+        assert!(utils::take_synced_paths().is_empty());
+        export(
+            store.conn(),
+            &path,
+            BlockHeight(2),
+            HasherKind::Sha256,
+            10,
+            true,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(utils::take_synced_paths(), vec![path]);
+    }
+
+    #[test]
+    fn test_export_height_not_current() {
+        let temp_dir = TempDir::new("test_state_snapshot_height").unwrap();
+        let store = open(&temp_dir, false);
+        commit_state(&store, 5);
+        let path = temp_dir.path().join("state.snapshot");
+        assert!(matches!(
+            export(
+                store.conn(),
+                &path,
+                BlockHeight(1),
+                HasherKind::Sha256,
+                10,
+                false,
+                |_| {}
+            ),
+            Err(StateSnapshotError::HeightNotCurrent {
+                height: 1,
+                current: 2
+            })
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_import_invalid_snapshot() {
+        let source_dir = TempDir::new("test_state_snapshot_invalid").unwrap();
+        let source = open(&source_dir, false);
+        commit_state(&source, 20);
+        let path = source_dir.path().join("state.snapshot");
+        export(
+            source.conn(),
+            &path,
+            BlockHeight(2),
+            HasherKind::Sha256,
+            10,
+            false,
+            |_| {},
+        )
+        .unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let assert_rejected = |bytes: &[u8]| {
+            let target_dir = TempDir::new("test_state_snapshot_invalid_target").unwrap();
+            let target = open(&target_dir, false);
+            let invalid_path = target_dir.path().join("invalid.snapshot");
+            fs::write(&invalid_path, bytes).unwrap();
+            let result = import(
+                target.conn(),
+                &invalid_path,
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                5,
+                |_| {},
+            );
+            // nothing is left in the state
+            let mode = rocksdb::IteratorMode::From(Prefix::STATE, rocksdb::Direction::Forward);
+            let mut iter = column_families::iterator(target.conn(), Prefix::STATE, mode);
+            assert!(!matches!(iter.next(), Some(Ok((key, _))) if key.starts_with(Prefix::STATE)));
+            assert!(target.conn().get(Prefix::CURRENT_STATE).unwrap().is_none());
+            result.unwrap_err()
+        };
+
+        assert!(matches!(
+            assert_rejected(&bytes[..10]),
+            StateSnapshotError::InvalidHeader
+        ));
+        let mut version = bytes.clone();
+        version[MAGIC.len() + 3] += 1;
+        assert!(matches!(
+            assert_rejected(&version),
+            StateSnapshotError::UnsupportedVersion(2)
+        ));
+        // flipped byte in a value after the first batch is written
+        let mut flipped = bytes.clone();
+        let index = bytes.len() - FOOTER_LENGTH - 1;
+        flipped[index] ^= 0xff;
+        assert!(matches!(
+            assert_rejected(&flipped),
+            StateSnapshotError::Corrupted(_)
+        ));
+        // valid records with the root of other state
+        let mut root = bytes.clone();
+        let index = bytes.len() - ROOT_LENGTH - CHECKSUM_LENGTH;
+        root[index] ^= 0xff;
+        assert!(matches!(
+            assert_rejected(&root),
+            StateSnapshotError::RootMismatch
+        ));
+        let mut truncated = bytes[..bytes.len() - FOOTER_LENGTH - 1].to_vec();
+        truncated.extend_from_slice(&bytes[bytes.len() - FOOTER_LENGTH..]);
+        assert!(matches!(
+            assert_rejected(&truncated),
+            StateSnapshotError::Corrupted(_)
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+/// spill provides the temporary on-disk store for the StateWriter cache exceeding the memory threshold.
+use tempdir::TempDir;
+use thiserror::Error;
+
+/// LIVE_PREFIX is the prefix of the entries currently in the writer.
+const LIVE_PREFIX: u8 = 0;
+
+#[derive(Error, Debug)]
+pub enum SpillError {
+    #[error("failed to create the spill directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+}
+
+/// SpillStore keeps the spilled entries in a rocksdb under a temporary directory.
+/// The directory is removed when the store is dropped.
+pub struct SpillStore {
+    // db must be dropped before the directory is removed.
+    db: rocksdb::DB,
+    _dir: TempDir,
+}
+
+fn live_key(key: &[u8]) -> Vec<u8> {
+    [&[LIVE_PREFIX][..], key].concat()
+}
+
+impl SpillStore {
+    pub fn new() -> Result<Self, SpillError> {
+        let dir = TempDir::new("lisk-db-state-writer")?;
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        let db = rocksdb::DB::open(&opts, dir.path())?;
+        Ok(Self { db, _dir: dir })
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, SpillError> {
+        Ok(self.db.get(live_key(key))?)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> Result<bool, SpillError> {
+        Ok(self.db.get_pinned(live_key(key))?.is_some())
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), SpillError> {
+        self.db.put(live_key(key), value)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<(), SpillError> {
+        self.db.delete(live_key(key))?;
+        Ok(())
+    }
+
+    /// for_each_entry calls f with all the entries currently in the writer, ordered by the key.
+    pub fn for_each_entry(&self, mut f: impl FnMut(&[u8], &[u8])) -> Result<(), SpillError> {
+        let prefix = [LIVE_PREFIX];
+        for key_val in self.db.prefix_iterator(prefix) {
+            let (key, value) = key_val?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            f(&key[prefix.len()..], &value);
+        }
+        Ok(())
+    }
+
+    /// copy_to puts all the entries currently in the writer to the other store.
+    pub fn copy_to(&self, other: &SpillStore) -> Result<(), SpillError> {
+        let prefix = [LIVE_PREFIX];
+        for key_val in self.db.prefix_iterator(prefix) {
+            let (key, value) = key_val?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            other.db.put(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::KVPair;
+
+    fn entries(store: &SpillStore) -> Vec<KVPair> {
+        let mut result = vec![];
+        store
+            .for_each_entry(|key, value| result.push(KVPair::new(key, value)))
+            .unwrap();
+        result
+    }
+
+    #[test]
+    fn test_spill_store_entries() {
+        let store = SpillStore::new().unwrap();
+        store.put(&[2], &[20]).unwrap();
+        store.put(&[1], &[10]).unwrap();
+        assert!(store.contains(&[1]).unwrap());
+        assert_eq!(
+            entries(&store),
+            vec![KVPair::new(&[1], &[10]), KVPair::new(&[2], &[20])]
+        );
+
+        store.delete(&[1]).unwrap();
+        assert_eq!(store.get(&[1]).unwrap(), None);
+        assert_eq!(entries(&store), vec![KVPair::new(&[2], &[20])]);
+        let copied = SpillStore::new().unwrap();
+        store.copy_to(&copied).unwrap();
+        assert_eq!(entries(&copied), vec![KVPair::new(&[2], &[20])]);
+    }
+}
@@ -1,51 +1,82 @@
 use core::cell::RefCell;
 /// state_db is an authenticated storage using Sparse Merkle Tree extending Database using rocksdb.
 use std::cmp;
-use std::convert::TryInto;
-use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock, RwLockReadGuard};
 use std::thread;
 
+use neon::event::Channel;
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
-use thiserror::Error;
+use tempdir::TempDir;
 
-use crate::batch;
+use crate::batch::{self, BatchClaim};
 use crate::consts;
-use crate::database::options;
-use crate::database::traits::{JsNewWithBoxRef, NewDBWithContext, OptionsWithContext, Unwrap};
-use crate::database::types::{ArcOptionDB, DbMessage, DbOptions, JsBoxRef, Kind};
+use crate::database::checkpoint_group::{self, Member};
+use crate::database::column_families::{self, StateSnapshot};
+use crate::database::key_layout;
+use crate::database::metrics::{self, Operation};
+use crate::database::options::{self, CheckpointOption, ItemFields};
+use crate::database::reader_writer::open_readers::{OpenReaders, ReaderSlot, TooManyReaders};
+use crate::database::reader_writer::pool::ReaderPool;
+use crate::database::traits::{
+    Closable, JsNewWithBoxRef, NewDBWithContext, OptionsWithContext, Unwrap,
+};
+use crate::database::types::{
+    ArcOptionDB, DbMessage, DbOptions, JsBoxRef, Kind, SizeLimits, StateKeyLength, WriteDurability,
+};
 use crate::database::utils as DbUtils;
-use crate::database::utils::pair_to_js_object;
-use crate::database::DB;
-use crate::diff;
-use crate::sparse_merkle_tree::smt::{self, SMTError, EMPTY_HASH};
+use crate::database::utils::{pair_to_js_object, ChunkCallbacks, ChunkDecoder};
+use crate::database::{checkpoint_info_to_js_object, DB};
+use crate::diff::Diff;
+use crate::error::{self, DbError};
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt::{self, SMTError};
 use crate::sparse_merkle_tree::smt_db;
+use crate::state::bloom::StateBloom;
+use crate::state::bulk_load;
+use crate::state::check_consistency::{self, ConsistencyReport};
+use crate::state::check_diffs::{self, DiffStats, DiffsReport, SectionStats};
+use crate::state::check_integrity::{self, IntegrityLevel, IntegrityReport};
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::state::diff_values::RefChanges;
+use crate::state::migrations;
+use crate::state::negative_cache::NegativeCache;
+use crate::state::profile;
+use crate::state::prune::{self, PruneReport};
+use crate::state::rebuild_smt::{self, RebuildReport};
+use crate::state::root_history::{self, RootAt};
+use crate::state::smt_sync::{self, SmtSyncError};
+use crate::state::snapshot::{self, SnapshotFooter};
 use crate::state::state_writer;
+use crate::state::store;
+use crate::state::value_compression;
+use crate::state::verify_nodes::{self, NodesReport};
 use crate::types::{
-    ArcMutex, BlockHeight, CommitOptions, KVPair, KeyLength, NestedVec, SharedVec,
+    ArcRwLock, BlockHeight, Cache, CommitOptions, HashKind, HashWithKind, KVPair, KeyLength,
+    NestedVec, SharedVec, PREFIX_SIZE,
 };
 use crate::utils;
 
-pub type SharedStateDB = JsBoxRef<StateDB>;
-
-#[derive(Error, Debug)]
-pub enum DataStoreError {
-    #[error("unknown data store error `{0}`")]
-    Unknown(String),
-    #[error("Diff not found for height: `{0}`")]
-    DiffNotFound(usize),
-}
+pub use crate::state::store::{smt_apply_diff, DataStoreError};
 
-#[derive(Debug, PartialEq, Eq)]
-struct CurrentState<'a> {
-    root: &'a [u8],
-    version: BlockHeight,
-}
+pub type SharedStateDB = JsBoxRef<StateDB>;
+/// ProofValues holds the value of each query in the proof. None if the value is not attached.
+pub type ProofValues = Vec<Option<Vec<u8>>>;
 
 struct Commit {
     options: CommitOptions,
     check_expected: bool,
     expected: Vec<u8>,
+    /// max_batch_bytes splits the write into the chunks for the bulk load. Single atomic batch if None.
+    max_batch_bytes: Option<usize>,
+    /// sync syncs the WAL of the commit even if syncWrites is not set.
+    sync: bool,
+    /// staged_id is the id of the Database batch staged for the commit.
+    staged_id: Option<Vec<u8>>,
+    /// bulk_load skips the diff. It is set on the DB thread, so that the commit follows the mode switched before it.
+    bulk_load: bool,
 }
 
 struct CommitData {
@@ -58,26 +89,55 @@ struct CommitResultInfo {
     data: Commit,
 }
 
+/// ComputedRoot is the root which the writer would have when committed on the current state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComputedRoot {
+    pub root: Vec<u8>,
+    /// version is the version of the current state which the root is computed on.
+    pub version: BlockHeight,
+    /// changed_keys is the number of the keys updated or deleted by the writer.
+    pub changed_keys: usize,
+}
+
+/// OperationCancel cancels the operations started before the cancellation.
+#[derive(Clone, Default)]
+struct OperationCancel {
+    started: Arc<AtomicU64>,
+    cancelled: Arc<AtomicU64>,
+}
+
 /// StateDB maintains instance of database for authenticated storage using sparse merkle tree.
 pub struct StateDB {
     common: DB,
     options: DbOptions,
+    bloom: Option<StateBloom>,
+    /// negative_cache holds the state keys known not to exist. It is owned by the JS thread as the bloom filter.
+    negative_cache: Option<NegativeCache>,
+    /// write_lock is held by the commits and the reverts while writing, and by the pruning while deleting the nodes.
+    write_lock: Arc<Mutex<()>>,
+    commit_cancel: OperationCancel,
+    integrity_cancel: OperationCancel,
+    /// reader_pool runs the operations of the readers. It is started with the first reader.
+    reader_pool: Mutex<Option<Arc<ReaderPool>>>,
+    /// bulk_load is switched on the DB thread, and the commits in the bulk load write no diff.
+    bulk_load: Arc<AtomicBool>,
+    /// readers counts the open readers and the read-writers up to max_open_readers.
+    readers: Arc<OpenReaders>,
 }
 
-impl<'a> CurrentState<'a> {
-    fn new(root: &'a [u8], version: BlockHeight) -> Self {
-        Self { root, version }
+impl OperationCancel {
+    /// start returns the id of the new operation.
+    fn start(&self) -> u64 {
+        self.started.fetch_add(1, Ordering::SeqCst) + 1
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        [self.root, &self.version.to_be_bytes()].concat()
+    fn cancel(&self) {
+        self.cancelled
+            .store(self.started.load(Ordering::SeqCst), Ordering::SeqCst);
     }
 
-    fn from_bytes(bytes: &'a [u8]) -> Self {
-        let version_point = bytes.len() - 4;
-        let root = &bytes[..version_point];
-        let version = u32::from_be_bytes(bytes[version_point..].try_into().unwrap()).into();
-        Self { root, version }
+    fn is_cancelled(&self, id: u64) -> bool {
+        id <= self.cancelled.load(Ordering::SeqCst)
     }
 }
 
@@ -87,6 +147,10 @@ impl Commit {
             options,
             check_expected,
             expected,
+            max_batch_bytes: None,
+            sync: false,
+            staged_id: None,
+            bulk_load: false,
         }
     }
 }
@@ -109,13 +173,26 @@ impl NewDBWithContext for StateDB {
         path: String,
         db_options: DbOptions,
         kind: Kind,
-    ) -> Result<Self, rocksdb::Error>
+    ) -> Result<Self, DbError>
     where
         C: Context<'a>,
     {
+        // the migrations run before the thread is started, so that nothing is left on failure
+        let (conn, secondary_dir, db_options) =
+            open_migrated(&path, db_options, &kind, &mut |_, _| {})?;
+        let common = DB::start(ctx, conn, secondary_dir, &db_options, kind);
+
         Ok(Self {
-            common: DB::new_db_with_context(ctx, path, db_options, kind)?,
+            common,
             options: db_options,
+            bloom: db_options.bloom_filter.map(StateBloom::new),
+            negative_cache: db_options.negative_cache_size.map(NegativeCache::new),
+            write_lock: Arc::new(Mutex::new(())),
+            commit_cancel: OperationCancel::default(),
+            integrity_cancel: OperationCancel::default(),
+            reader_pool: Mutex::new(None),
+            bulk_load: Arc::new(AtomicBool::new(false)),
+            readers: Arc::new(OpenReaders::new()),
         })
     }
 }
@@ -128,77 +205,784 @@ impl JsNewWithBoxRef for StateDB {
         let options = ctx.argument_opt(1);
         let db_opts = T::new_with_context(&mut ctx, options)?;
         let db = U::new_db_with_context(&mut ctx, path, db_opts, Kind::State)
-            .or_else(|err| ctx.throw_error(&err))?;
+            .or_else(|err| err.throw(&mut ctx))?;
         let ref_db = RefCell::new(db);
 
-        return Ok(ctx.boxed(ref_db));
+        Ok(ctx.boxed(ref_db))
+    }
+}
+
+/// open_migrated opens the connection of StateDB, and checks or runs the format migrations by migrations::prepare.
+/// The database locked by another instance is opened as readonly. The legacy current state is upgraded
+/// after the format is accepted, so that the database rejected with NeedsMigration is not changed.
+fn open_migrated(
+    path: &str,
+    db_options: DbOptions,
+    kind: &Kind,
+    on_progress: &mut dyn FnMut(&migrations::Migration, usize),
+) -> Result<(rocksdb::DB, Option<TempDir>, DbOptions), DbError> {
+    let mut db_options = db_options;
+    let (conn, secondary_dir) = DB::open_connection(path, &db_options, kind)?;
+    if secondary_dir.is_some() {
+        db_options.set_readonly();
+    }
+    // the roots computed by the migrations depend on the hasher
+    CurrentState::check_hasher(&conn, db_options.hasher)?;
+    migrations::prepare(&conn, &db_options, on_progress)?;
+    if !db_options.is_readonly() {
+        // Records which cannot be decoded are kept as it is, and reported when reading them
+        if let Err(CurrentStateError::Database(err)) = CurrentState::migrate(&conn) {
+            return Err(err.into());
+        }
+    }
+
+    Ok((conn, secondary_dir, db_options))
+}
+
+/// send_migration_progress calls on_progress in JS with the name of the step and the number of the records processed.
+fn send_migration_progress(
+    channel: &Channel,
+    on_progress: Option<&Arc<Root<JsFunction>>>,
+    step: &migrations::Migration,
+    processed: usize,
+) {
+    if let Some(on_progress) = on_progress {
+        let on_progress = Arc::clone(on_progress);
+        let name = step.name;
+        channel.send(move |mut ctx| {
+            let on_progress = on_progress.to_inner(&mut ctx);
+            let this = ctx.undefined();
+            let args: Vec<Handle<JsValue>> = vec![
+                ctx.string(name).upcast(),
+                ctx.number(processed as f64).upcast(),
+            ];
+            on_progress.call(&mut ctx, this, args)?;
+            Ok(())
+        });
+    }
+}
+
+fn profile_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    profile: &profile::RangeProfile,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let counters = [
+        ("keys", profile.keys as f64),
+        ("bytesReturned", profile.bytes_returned as f64),
+        ("bytesRead", profile.bytes_read as f64),
+        ("blockReadCount", profile.block_read_count as f64),
+        ("blockCacheHitCount", profile.block_cache_hit_count as f64),
+        (
+            "maxBlocksReadPerKey",
+            profile.max_blocks_read_per_key as f64,
+        ),
+        (
+            "internalKeySkippedCount",
+            profile.internal_key_skipped_count as f64,
+        ),
+        (
+            "internalDeleteSkippedCount",
+            profile.internal_delete_skipped_count as f64,
+        ),
+        ("blocksReadPerKey", profile.blocks_read_per_key()),
+        ("bloomUsefulRate", profile.bloom_useful_rate()),
+    ];
+    for (name, value) in counters {
+        let value = ctx.number(value);
+        obj.set(ctx, name, value)?;
+    }
+    let truncated = ctx.boolean(profile.truncated);
+    obj.set(ctx, "truncated", truncated)?;
+
+    Ok(obj)
+}
+
+fn diffs_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: &DiffsReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let checked = ctx.number(report.checked as f64);
+    obj.set(ctx, "checked", checked)?;
+    let corrupted = ctx.empty_array();
+    for (i, diff) in report.corrupted.iter().enumerate() {
+        let item = ctx.empty_object();
+        let height = ctx.number(diff.height);
+        item.set(ctx, "height", height)?;
+        let error = ctx.string(&diff.error);
+        item.set(ctx, "error", error)?;
+        corrupted.set(ctx, i as u32, item)?;
+    }
+    obj.set(ctx, "corrupted", corrupted)?;
+
+    Ok(obj)
+}
+
+fn section_stats_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    stats: &SectionStats,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let count = ctx.number(stats.count as f64);
+    obj.set(ctx, "count", count)?;
+    let key_bytes = ctx.number(stats.key_bytes as f64);
+    obj.set(ctx, "keyBytes", key_bytes)?;
+    let value_bytes = ctx.number(stats.value_bytes as f64);
+    obj.set(ctx, "valueBytes", value_bytes)?;
+    let referenced = ctx.number(stats.referenced as f64);
+    obj.set(ctx, "referenced", referenced)?;
+
+    Ok(obj)
+}
+
+/// DecodedDiffs decodes the diffs of the iteration, resolving the values referenced from the old-value store.
+struct DecodedDiffs {
+    conn: ArcOptionDB,
+}
+
+impl ChunkDecoder for DecodedDiffs {
+    type Item = (u32, Diff);
+
+    fn decode(&self, pair: KVPair) -> Result<(u32, Diff), DbError> {
+        Ok(check_diffs::read_diff_entry(
+            self.conn.unwrap(),
+            pair.key(),
+            pair.value(),
+        )?)
+    }
+
+    fn to_js_object<'a>(
+        &self,
+        ctx: &mut TaskContext<'a>,
+        item: (u32, Diff),
+    ) -> NeonResult<Handle<'a, JsObject>> {
+        let (height, diff) = item;
+        let obj = ctx.empty_object();
+        let height = ctx.number(height);
+        obj.set(ctx, "height", height)?;
+        let created = ctx.empty_array();
+        for (i, key) in diff.created().iter().enumerate() {
+            let key = JsBuffer::external(ctx, key.clone());
+            created.set(ctx, i as u32, key)?;
+        }
+        obj.set(ctx, "created", created)?;
+        let updated = DbUtils::pairs_to_js_array(ctx, diff.updated(), ItemFields::KeyValue)?;
+        obj.set(ctx, "updated", updated)?;
+        let deleted = DbUtils::pairs_to_js_array(ctx, diff.deleted(), ItemFields::KeyValue)?;
+        obj.set(ctx, "deleted", deleted)?;
+
+        Ok(obj)
+    }
+}
+
+/// kind_prefix returns the prefix of the kind of the stored data iterated by iterate_kind.
+fn kind_prefix(kind: &str) -> Option<&'static [u8]> {
+    match batch::BatchKind::new(kind) {
+        Some(batch::BatchKind::Raw) | None => None,
+        Some(kind) => Some(kind.prefix()),
+    }
+}
+
+fn diff_stats_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    stats: &DiffStats,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let height = ctx.number(stats.height);
+    obj.set(ctx, "height", height)?;
+    let encoded_bytes = ctx.number(stats.encoded_bytes as f64);
+    obj.set(ctx, "encodedBytes", encoded_bytes)?;
+    let created = section_stats_to_js_object(ctx, &stats.created)?;
+    obj.set(ctx, "created", created)?;
+    let updated = section_stats_to_js_object(ctx, &stats.updated)?;
+    obj.set(ctx, "updated", updated)?;
+    let deleted = section_stats_to_js_object(ctx, &stats.deleted)?;
+    obj.set(ctx, "deleted", deleted)?;
+
+    Ok(obj)
+}
+
+fn consistency_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: ConsistencyReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let consistent = ctx.boolean(report.is_consistent());
+    obj.set(ctx, "consistent", consistent)?;
+    let height = ctx.number(report.height);
+    obj.set(ctx, "height", height)?;
+    let root = JsBuffer::external(ctx, report.root);
+    obj.set(ctx, "root", root)?;
+    let root_in_tree = ctx.boolean(report.root_in_tree);
+    obj.set(ctx, "rootInTree", root_in_tree)?;
+    let diff_exists = ctx.boolean(report.diff_exists);
+    obj.set(ctx, "diffExists", diff_exists)?;
+    if let Some(recorded_root) = report.recorded_root {
+        let recorded_root = JsBuffer::external(ctx, recorded_root);
+        obj.set(ctx, "recordedRoot", recorded_root)?;
+    }
+    let pending_commit = ctx.boolean(report.pending_commit);
+    obj.set(ctx, "pendingCommit", pending_commit)?;
+    let issues = ctx.empty_array();
+    for (i, issue) in report.issues.iter().enumerate() {
+        let issue = ctx.string(issue);
+        issues.set(ctx, i as u32, issue)?;
+    }
+    obj.set(ctx, "issues", issues)?;
+
+    Ok(obj)
+}
+
+fn nodes_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: &NodesReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let checked = ctx.number(report.checked as f64);
+    obj.set(ctx, "checked", checked)?;
+    let mismatches = ctx.number(report.mismatches as f64);
+    obj.set(ctx, "mismatches", mismatches)?;
+    let mismatched = ctx.empty_array();
+    for (i, key) in report.mismatched.iter().enumerate() {
+        let key = JsBuffer::external(ctx, key.clone());
+        mismatched.set(ctx, i as u32, key)?;
+    }
+    obj.set(ctx, "mismatched", mismatched)?;
+    let truncated = ctx.boolean(report.truncated());
+    obj.set(ctx, "truncated", truncated)?;
+
+    Ok(obj)
+}
+
+fn integrity_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: &IntegrityReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let ok = ctx.boolean(report.ok());
+    obj.set(ctx, "ok", ok)?;
+    let level = ctx.string(report.level.name());
+    obj.set(ctx, "level", level)?;
+    let height = ctx.number(report.height);
+    obj.set(ctx, "height", height)?;
+    let root = JsBuffer::external(ctx, report.root.clone());
+    obj.set(ctx, "root", root)?;
+    let checked_nodes = ctx.number(report.checked_nodes as f64);
+    obj.set(ctx, "checkedNodes", checked_nodes)?;
+    let checked_keys = ctx.number(report.checked_keys as f64);
+    obj.set(ctx, "checkedKeys", checked_keys)?;
+    let error_count = ctx.number(report.error_count as f64);
+    obj.set(ctx, "errorCount", error_count)?;
+    let errors = ctx.empty_array();
+    for (i, issue) in report.errors.iter().enumerate() {
+        let error = ctx.empty_object();
+        let message = ctx.string(&issue.message);
+        error.set(ctx, "message", message)?;
+        let key: Handle<JsValue> = match issue.key.as_ref() {
+            Some(key) => JsBuffer::external(ctx, key.clone()).upcast(),
+            None => ctx.null().upcast(),
+        };
+        error.set(ctx, "key", key)?;
+        let height: Handle<JsValue> = match issue.height {
+            Some(height) => ctx.number(height).upcast(),
+            None => ctx.null().upcast(),
+        };
+        error.set(ctx, "height", height)?;
+        errors.set(ctx, i as u32, error)?;
+    }
+    obj.set(ctx, "errors", errors)?;
+    let truncated = ctx.boolean(report.truncated());
+    obj.set(ctx, "truncated", truncated)?;
+
+    Ok(obj)
+}
+
+fn prune_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: &PruneReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let reachable = ctx.number(report.reachable as f64);
+    obj.set(ctx, "reachable", reachable)?;
+    let scanned = ctx.number(report.scanned as f64);
+    obj.set(ctx, "scanned", scanned)?;
+    let deleted = ctx.number(report.deleted as f64);
+    obj.set(ctx, "deleted", deleted)?;
+
+    Ok(obj)
+}
+
+fn rebuild_report_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    report: &RebuildReport,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let keys = ctx.number(report.keys as f64);
+    obj.set(ctx, "keys", keys)?;
+    let inserted = ctx.number(report.inserted as f64);
+    obj.set(ctx, "inserted", inserted)?;
+
+    Ok(obj)
+}
+
+fn snapshot_footer_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    footer: &SnapshotFooter,
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let records = ctx.number(footer.records as f64);
+    obj.set(ctx, "records", records)?;
+    let height = ctx.number(u32::from(footer.height));
+    obj.set(ctx, "height", height)?;
+    let root = JsBuffer::external(ctx, footer.root.clone());
+    obj.set(ctx, "root", root)?;
+
+    Ok(obj)
+}
+
+/// send_records_progress calls on_progress in JS with the number of the records processed.
+fn send_records_progress(
+    channel: &Channel,
+    on_progress: Option<&Arc<Root<JsFunction>>>,
+    records: u64,
+) {
+    if let Some(on_progress) = on_progress {
+        let on_progress = Arc::clone(on_progress);
+        channel.send(move |mut ctx| {
+            let on_progress = on_progress.to_inner(&mut ctx);
+            let this = ctx.undefined();
+            let records = ctx.number(records as f64);
+            on_progress.call(&mut ctx, this, vec![records.upcast::<JsValue>()])?;
+            Ok(())
+        });
+    }
+}
+
+/// send_commit_progress calls on_progress in JS with the number of the keys updated in the tree and the total.
+fn send_commit_progress(
+    channel: &Channel,
+    on_progress: Option<&Arc<Root<JsFunction>>>,
+    processed: usize,
+    total: usize,
+) {
+    if let Some(on_progress) = on_progress {
+        let on_progress = Arc::clone(on_progress);
+        channel.send(move |mut ctx| {
+            let on_progress = on_progress.to_inner(&mut ctx);
+            let this = ctx.undefined();
+            let args: Vec<Handle<JsValue>> = vec![
+                ctx.number(processed as f64).upcast(),
+                ctx.number(total as f64).upcast(),
+            ];
+            on_progress.call(&mut ctx, this, args)?;
+            Ok(())
+        });
+    }
+}
+
+/// compute_root calculates the root of committing the writer on the state of the snapshot.
+/// Updated nodes are kept in the overlay, so that neither the database nor the writer is changed.
+fn compute_root(
+    snapshot: &StateSnapshot,
+    writer: &state_writer::StateWriter,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    threads: usize,
+) -> Result<ComputedRoot, DbError> {
+    let current_state = check_consistency::current_state(snapshot, hasher)
+        .map_err(|err| smt::SMTError::Unknown(err.to_string()))?;
+    let data = smt::UpdateData::new_from(writer.get_hashed_updated()?);
+    let (changed_keys, _) = data.entries();
+    let mut smt_db = smt_db::SnapshotSmtDB::new(snapshot);
+    let mut tree = smt::SparseMerkleTree::new_with_hasher(
+        current_state.root(),
+        key_length,
+        consts::SUBTREE_HEIGHT,
+        hasher,
+    )
+    .with_threads(threads);
+    let root = tree.commit(&mut smt_db, &data)?;
+    let result = ComputedRoot {
+        root: (**root.lock().unwrap()).clone(),
+        version: current_state.version(),
+        changed_keys: changed_keys.len(),
+    };
+    Ok(result)
+}
+
+/// send_proof calls the callback in JS with the proof and the values of the inclusion queries.
+fn send_proof(
+    channel: &Channel,
+    callback: Root<JsFunction>,
+    result: Result<(smt::Proof, ProofValues), SMTError>,
+    include_size: bool,
+) {
+    channel.send(move |mut ctx| {
+        let callback = callback.into_inner(&mut ctx);
+        let this = ctx.undefined();
+        let args: Vec<Handle<JsValue>> = match result {
+            Ok((val, values)) => {
+                let obj = proof_to_js_object(&mut ctx, &val, &values)?;
+                if include_size {
+                    let size = ctx.number(val.encode().len() as f64);
+                    obj.set(&mut ctx, "size", size)?;
+                }
+                vec![ctx.null().upcast(), obj.upcast()]
+            },
+            Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+        };
+        callback.call(&mut ctx, this, args)?;
+
+        Ok(())
+    });
+}
+
+/// historical_state rebuilds the state of the past height, and checks that the root is the root of the height.
+fn historical_state<'a>(
+    conn: &'a rocksdb::DB,
+    root: &[u8],
+    height: BlockHeight,
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<store::HistoricalState<'a>, SMTError> {
+    let state = match store::HistoricalState::new(conn, height, key_length, hasher) {
+        Ok(state) => state,
+        Err(err @ DataStoreError::HeightNotRetained(_)) => {
+            return Err(SMTError::InvalidInput(err.to_string()))
+        },
+        Err(err) => return Err(SMTError::Unknown(err.to_string())),
+    };
+    if !utils::is_bytes_equal(root, state.root()) {
+        return Err(SMTError::InvalidRoot(String::from(
+            "Not matching with the state root of the height",
+        )));
+    }
+    Ok(state)
+}
+
+/// prove_with_values creates the proof of the state keys, and attaches the stored values to the inclusion queries.
+/// With height, the proof and the values are of the state of the past height.
+/// The nodes and the values are read from one snapshot.
+fn prove_with_values(
+    conn: &rocksdb::DB,
+    root: &[u8],
+    state_keys: &[Vec<u8>],
+    height: Option<BlockHeight>,
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<(smt::Proof, ProofValues), SMTError> {
+    if state_keys.iter().any(|key| key.len() < PREFIX_SIZE) {
+        return Err(SMTError::InvalidInput(String::from(
+            "state key must include the prefix",
+        )));
+    }
+    let query_keys: NestedVec = state_keys
+        .iter()
+        .map(|key| key.hash_with_kind(HashKind::Key))
+        .collect();
+    if let Some(height) = height {
+        let mut state = historical_state(conn, root, height, key_length, hasher)?;
+        let proof = state
+            .prove(&query_keys, key_length)
+            .map_err(|err| with_state_key(err, state_keys, &query_keys))?;
+        let values = stored_proof_values(|key| state.get(key), state_keys, &query_keys, &proof)?;
+        return Ok((proof, values));
+    }
+    let snapshot = StateSnapshot::new(conn);
+    let mut tree =
+        smt::SparseMerkleTree::new_with_hasher(root, key_length, consts::SUBTREE_HEIGHT, hasher);
+    let mut smtdb = smt_db::SnapshotSmtDB::new(&snapshot);
+    let proof = tree
+        .prove(&mut smtdb, &query_keys)
+        .map_err(|err| with_state_key(err, state_keys, &query_keys))?;
+    let values = stored_proof_values(
+        |key| {
+            snapshot
+                .get(&Kind::State.key(key.to_vec()))
+                .map(|value| value.map(value_compression::decode_vec))
+        },
+        state_keys,
+        &query_keys,
+        &proof,
+    )?;
+    Ok((proof, values))
+}
+
+/// read_folded returns the read lock of the writer after folding the pending increments into the updates,
+/// so that the SMT and the diff of the writer are computed from the ordinary updates.
+/// The counters of the increments are read from the current state.
+fn read_folded<'a>(
+    conn: &rocksdb::DB,
+    writer: &'a RwLock<state_writer::StateWriter>,
+) -> Result<RwLockReadGuard<'a, state_writer::StateWriter>, DbError> {
+    loop {
+        let w = writer.read().unwrap();
+        if !w.has_increments() {
+            return Ok(w);
+        }
+        drop(w);
+        writer.write().unwrap().fold_increments(|key| {
+            column_families::get(conn, &Kind::State.key(key.to_vec()))
+                .map(|value| value.map(value_compression::decode_vec))
+                .map_err(DbError::from)
+        })?;
+    }
+}
+
+/// update_tree_in_chunks updates the tree with the data in chunks of chunk_size keys.
+/// Cancellation is checked before each chunk, and on_progress is called after each chunk.
+/// The nodes of the chunks are kept in the overlay, so that nothing is written to the database.
+fn update_tree_in_chunks<'a>(
+    conn: &'a rocksdb::DB,
+    tree: &mut smt::SparseMerkleTree,
+    data: Cache,
+    chunk_size: usize,
+    is_cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Result<(SharedVec, smt_db::SmtDB<'a>), DbError> {
+    let total = data.len();
+    let mut smt_db = smt_db::SmtDB::new(conn);
+    if total <= chunk_size {
+        if is_cancelled() {
+            return Err(DbError::Cancelled("Commit"));
+        }
+        let root = tree.commit(&mut smt_db, &smt::UpdateData::new_from(data))?;
+        on_progress(total, total);
+        return Ok((root, smt_db));
+    }
+    // sorted chunks mostly update the disjoint subtrees. Descending order splits the chunk from the end.
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = data.into_iter().collect();
+    entries.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+    let mut overlay = smt_db::OverlaySmtDB::new(conn);
+    let mut root = None;
+    let mut processed = 0;
+    while !entries.is_empty() {
+        if is_cancelled() {
+            return Err(DbError::Cancelled("Commit"));
+        }
+        let chunk: Cache = entries
+            .split_off(entries.len().saturating_sub(chunk_size))
+            .into_iter()
+            .collect();
+        processed += chunk.len();
+        root = Some(tree.commit(&mut overlay, &smt::UpdateData::new_from(chunk))?);
+        on_progress(processed, total);
+    }
+    smt_db.batch = overlay.into_batch();
+
+    Ok((root.expect("data is not empty"), smt_db))
+}
+
+/// proof_to_js_object converts the proof to
+/// { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; rawValue?: &[u8]; }[]; }.
+/// rawValue is set to the query only if the value is attached.
+pub(crate) fn proof_to_js_object<'a, C: Context<'a>>(
+    ctx: &mut C,
+    proof: &smt::Proof,
+    values: &[Option<Vec<u8>>],
+) -> JsResult<'a, JsObject> {
+    let obj = ctx.empty_object();
+    let sibling_hashes = ctx.empty_array();
+    for (i, h) in proof.sibling_hashes.iter().enumerate() {
+        let val_res = JsBuffer::external(ctx, h.to_vec());
+        sibling_hashes.set(ctx, i as u32, val_res)?;
+    }
+    obj.set(ctx, "siblingHashes", sibling_hashes)?;
+    let queries = ctx.empty_array();
+    for (i, v) in proof.queries.iter().enumerate() {
+        let query = pair_to_js_object(ctx, &v.pair)?;
+        let bitmap = JsBuffer::external(ctx, v.bitmap.to_vec());
+        query.set(ctx, "bitmap", bitmap)?;
+        if let Some(Some(value)) = values.get(i) {
+            let value = JsBuffer::external(ctx, value.to_vec());
+            query.set(ctx, "rawValue", value)?;
+        }
+        queries.set(ctx, i as u32, query)?;
+    }
+    obj.set(ctx, "queries", queries)?;
+
+    Ok(obj)
+}
+
+/// is_inclusion returns true if the query proof is the inclusion proof of the query key.
+fn is_inclusion(query_key: &[u8], query: &smt::QueryProof) -> bool {
+    query.pair.key() == query_key && !query.pair.value().is_empty()
+}
+
+/// check_proof_values checks the values attached to the queries hash to the value hash of the inclusion proof.
+/// None represents no value is attached to the query.
+pub fn check_proof_values(
+    query_keys: &[Vec<u8>],
+    proof: &smt::Proof,
+    values: &[Option<Vec<u8>>],
+) -> Result<(), SMTError> {
+    let queries = query_keys
+        .iter()
+        .zip(proof.queries.iter())
+        .zip(values.iter());
+    for (i, ((query_key, query), value)) in queries.enumerate() {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        if !is_inclusion(query_key, query)
+            || value.hash_with_kind(HashKind::Value) != query.pair.value()
+        {
+            return Err(SMTError::InvalidValue(i));
+        }
+    }
+    Ok(())
+}
+
+/// with_state_key replaces the hashed key of the empty value error with the state key.
+fn with_state_key(err: SMTError, state_keys: &[Vec<u8>], query_keys: &[Vec<u8>]) -> SMTError {
+    match err {
+        SMTError::EmptyValue(hashed) => state_keys
+            .iter()
+            .zip(query_keys.iter())
+            .find(|(_, query_key)| hex::encode(query_key) == hashed)
+            .map_or(SMTError::EmptyValue(hashed), |(state_key, _)| {
+                SMTError::EmptyValue(hex::encode(state_key))
+            }),
+        err => err,
+    }
+}
+
+/// stored_proof_values reads the values of the inclusion queries with get_value.
+/// The values are checked against the proof, so that the proof of the root other than the state read is rejected.
+fn stored_proof_values(
+    get_value: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error>,
+    state_keys: &[Vec<u8>],
+    query_keys: &[Vec<u8>],
+    proof: &smt::Proof,
+) -> Result<ProofValues, SMTError> {
+    let mut values = Vec::with_capacity(state_keys.len());
+    let queries = state_keys
+        .iter()
+        .zip(query_keys.iter())
+        .zip(proof.queries.iter());
+    for (i, ((state_key, query_key), query)) in queries.enumerate() {
+        if !is_inclusion(query_key, query) {
+            values.push(None);
+            continue;
+        }
+        let value = get_value(state_key)
+            .map_err(|err| SMTError::Unknown(err.to_string()))?
+            .ok_or(SMTError::InvalidValue(i))?;
+        if value.is_empty() {
+            return Err(SMTError::EmptyValue(hex::encode(state_key)));
+        }
+        values.push(Some(value));
+    }
+    check_proof_values(query_keys, proof, &values)?;
+    Ok(values)
+}
+
+/// finalize closes the StateDB dropped by JS without close, in the same way as close.
+impl Finalize for StateDB {
+    fn finalize<'a, C: Context<'a>>(mut self, _: &mut C) {
+        let _ = self.common.close();
+    }
+}
+
+impl Closable for StateDB {
+    const NAME: &'static str = "StateDB";
+
+    fn closed(&self) -> &AtomicBool {
+        self.common.closed()
     }
 }
 
-impl Finalize for StateDB {}
 impl StateDB {
-    fn get_revert_result(
-        conn: &DB,
-        version: BlockHeight,
-        state_root: &[u8],
-        key_length: KeyLength,
-    ) -> Result<SharedVec, DataStoreError> {
-        let diff_bytes = conn
-            .get(&[consts::Prefix::DIFF, &version.to_be_bytes()].concat())
-            .map_err(|err| DataStoreError::Unknown(err.to_string()))?
-            .ok_or_else(|| DataStoreError::DiffNotFound(version.into()))?;
+    /// check_writable returns Readonly error if the database is opened with readonly.
+    fn check_writable(&self) -> Result<(), DataStoreError> {
+        if self.options.is_readonly() {
+            return Err(DataStoreError::Readonly);
+        }
+        Ok(())
+    }
 
-        let diff = diff::Diff::decode(&diff_bytes)
-            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
-        let data = smt::UpdateData::new_from(diff.revert_hashed_update());
-        let mut smt_db = smt_db::SmtDB::new(conn);
-        let mut tree = smt::SparseMerkleTree::new(state_root, key_length, consts::SUBTREE_HEIGHT);
-        let prev_root = tree
-            .commit(&mut smt_db, &data)
-            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+    /// throw_if_readonly throws Readonly error to JS for the mutating operations.
+    fn throw_if_readonly<'a, C: Context<'a>>(&self, ctx: &mut C) -> NeonResult<()> {
+        self.check_writable()
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
 
-        let mut write_batch = batch::PrefixWriteBatch::new();
-        // Insert state batch with diff
-        write_batch.set_prefix(&consts::Prefix::STATE);
-        diff.revert_commit(&mut write_batch);
-        write_batch.set_prefix(&consts::Prefix::DIFF);
-        write_batch.delete(&version.to_be_bytes());
+    /// throw_if_invalid_keys throws InvalidKeyLength error to JS if any of the state keys does not have the configured length.
+    fn throw_if_invalid_keys<'a, C: Context<'a>, K: AsRef<[u8]>>(
+        &self,
+        ctx: &mut C,
+        keys: &[K],
+    ) -> NeonResult<()> {
+        for key in keys {
+            self.options
+                .check_state_key(key.as_ref())
+                .or_else(|err| DbError::from(err).throw(ctx))?;
+        }
+        Ok(())
+    }
 
-        // insert SMT batch
-        write_batch.set_prefix(&consts::Prefix::SMT);
-        smt_db.batch.iterate(&mut write_batch);
-        // insert diff
-        conn.write(write_batch.batch)
-            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+    /// state_key_length returns the configured length of the state keys.
+    pub fn state_key_length(&self) -> Option<StateKeyLength> {
+        self.options.state_key_length
+    }
+
+    /// prefix_key_length returns the length of the stored key prefix of the prefix filters. None if they are disabled.
+    pub fn prefix_key_length(&self) -> Option<usize> {
+        self.common.prefix_key_length()
+    }
+
+    /// key_length returns the key length of the SMT.
+    pub fn key_length(&self) -> KeyLength {
+        self.options.key_length()
+    }
+
+    /// hasher returns the hasher of the SMT.
+    pub fn hasher(&self) -> HasherKind {
+        self.options.hasher
+    }
+
+    /// size_limits returns the limits of the state keys and the values accepted by the read writers.
+    pub fn size_limits(&self) -> SizeLimits {
+        self.options.size_limits
+    }
 
-        Ok(prev_root)
+    /// arc_conn returns the connection shared with the other databases, such as the recovery of the staged batches.
+    pub(crate) fn arc_conn(&self) -> ArcOptionDB {
+        self.common.arc_clone()
     }
 
+    /// revert reverts the commit of the version on the DB thread after the operations queued before it.
     fn revert(
-        &mut self,
+        &self,
         version: BlockHeight,
         state_root: Vec<u8>,
+        expected: Vec<u8>,
+        db_handle: Root<JsObject>,
         callback: Root<JsFunction>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
-        let key_length = self.options.key_length();
-        let result = StateDB::get_revert_result(&self.common, version, &state_root, key_length);
-        if result.is_ok() {
-            let value = (**result.as_ref().unwrap().lock().unwrap()).clone();
-            let state_info = CurrentState::new(&value, version - BlockHeight(1));
-            self.common
-                .put(consts::Prefix::CURRENT_STATE, &state_info.to_bytes())
-                .expect("Update state info should not be failed");
-        }
-        self.common.send(move |channel| {
+        let conn = self.common.arc_clone();
+        let db_options = self.options;
+        let write_lock = Arc::clone(&self.write_lock);
+        self.common.send_write(move |channel| {
+            let result = {
+                let _guard = write_lock.lock().unwrap();
+                store::revert(conn.unwrap(), version, &state_root, &expected, &db_options)
+            };
             channel.send(move |mut ctx| {
+                let db_handle = db_handle.into_inner(&mut ctx);
                 let callback = callback.into_inner(&mut ctx);
                 let this = ctx.undefined();
                 let args: Vec<Handle<JsValue>> = match result {
                     Ok(val) => {
+                        // deleted keys are restored, so the filter owned by the JS thread is rebuilt on next use
+                        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+                        db.borrow_mut().invalidate_lookups();
                         let buffer = JsBuffer::external(&mut ctx, (**val.lock().unwrap()).clone());
                         vec![ctx.null().upcast(), buffer.upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -208,12 +992,16 @@ impl StateDB {
         })
     }
 
+    /// handle_commit_result writes the commit, and returns the root with the keys created by the commit.
+    /// The writer is marked as consumed when the commit is written.
     fn handle_commit_result(
-        conn: &DB,
+        conn: &rocksdb::DB,
+        durability: WriteDurability,
         smt_db: &smt_db::SmtDB,
-        writer: MutexGuard<state_writer::StateWriter>,
+        writer: RwLockReadGuard<state_writer::StateWriter>,
         info: CommitResultInfo,
-    ) -> Result<SharedVec, smt::SMTError> {
+        db_options: &DbOptions,
+    ) -> Result<(SharedVec, NestedVec), smt::SMTError> {
         info.next_root.as_ref()?;
         let root = info.next_root.unwrap();
         if info.data.check_expected
@@ -224,60 +1012,114 @@ impl StateDB {
             )));
         }
         if info.data.options.is_readonly() {
-            return Ok(root);
-        }
-        // Create global batch
-        let mut write_batch = batch::PrefixWriteBatch::new();
-        // Insert state batch with diff
-        write_batch.set_prefix(&consts::Prefix::STATE);
-        let diff = writer.commit(&mut write_batch);
-        write_batch.set_prefix(&consts::Prefix::DIFF);
-        let key = info.data.options.version().to_be_bytes();
-        write_batch.put(&key, diff.encode().as_ref());
-
-        // insert SMT batch
-        write_batch.set_prefix(&consts::Prefix::SMT);
-        smt_db.batch.iterate(&mut write_batch);
-        // insert diff
-        let result = conn.write(write_batch.batch);
-        let version = info.data.options.version();
-        match result {
-            Ok(_) => {
-                let value = (**root.as_ref().lock().unwrap()).clone();
-                let state_info = CurrentState::new(&value, version);
-                conn.put(consts::Prefix::CURRENT_STATE, &state_info.to_bytes())
-                    .expect("Update state info should not be failed");
-                Ok(root)
-            },
-            Err(err) => Err(smt::SMTError::Unknown(err.to_string())),
+            return Ok((root, vec![]));
         }
+        let value = (**root.lock().unwrap()).clone();
+        let options = store::CommitWriteOption {
+            keep_diff_for_heights: db_options.keep_diff_for_heights,
+            max_batch_bytes: info.data.max_batch_bytes,
+            write_opts: durability.write_options(info.data.sync),
+            staged_id: info.data.staged_id.clone(),
+            value_ref_threshold: db_options.diff_value_ref_threshold,
+            value_compression: db_options.value_compression,
+            bulk_load: info.data.bulk_load,
+            hasher: db_options.hasher,
+        };
+        let diff = store::write_commit(
+            conn,
+            smt_db,
+            &writer,
+            &value,
+            info.data.options.version(),
+            &options,
+        )
+        .map_err(smt::SMTError::Unknown)?;
+        // marked under the read lock, so that no change is made to the writer between the write and the mark
+        writer.mark_consumed();
+        Ok((root, diff.into_created()))
     }
 
+    /// commit updates the tree and writes the writer on the DB thread.
+    /// Nothing is written if the commit is cancelled before the write.
     fn commit(
-        &mut self,
-        writer: ArcMutex<state_writer::StateWriter>,
+        &self,
+        writer: ArcRwLock<state_writer::StateWriter>,
         commit_data: CommitData,
+        on_progress: Option<Arc<Root<JsFunction>>>,
+        db_handle: Root<JsObject>,
         callback: Root<JsFunction>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let conn = self.common.arc_clone();
+        let durability = self.common.durability();
         let key_length = self.options.key_length();
-        let w = writer.lock().unwrap();
-        let data = smt::UpdateData::new_from(w.get_hashed_updated());
-        let mut smt_db = smt_db::SmtDB::new(&self.common);
-        let mut tree =
-            smt::SparseMerkleTree::new(&commit_data.prev_root, key_length, consts::SUBTREE_HEIGHT);
-        let root = tree.commit(&mut smt_db, &data);
-        let result_info = CommitResultInfo::new(root, commit_data.data);
-        let result = StateDB::handle_commit_result(&self.common, &smt_db, w, result_info);
-        self.common.send(move |channel| {
+        let db_options = self.options;
+        let smt_threads = self.smt_threads();
+        let write_lock = Arc::clone(&self.write_lock);
+        let commit_cancel = self.commit_cancel.clone();
+        let commit_id = commit_cancel.start();
+        let timer = self.common.timer(Operation::Commit);
+        let bulk_load = Arc::clone(&self.bulk_load);
+        self.common.send_write_timed(timer, move |channel| {
+            let conn = conn.unwrap();
+            let mut commit_data = commit_data;
+            commit_data.data.bulk_load = bulk_load.load(Ordering::SeqCst);
+            let is_cancelled = || commit_cancel.is_cancelled(commit_id);
+            let mut tree = smt::SparseMerkleTree::new_with_hasher(
+                &commit_data.prev_root,
+                key_length,
+                consts::SUBTREE_HEIGHT,
+                db_options.hasher,
+            )
+            .with_threads(smt_threads);
+            // the writer committed by the previous commit on the queue is not committed again
+            let result = read_folded(conn, &writer).and_then(|w| {
+                w.check_not_consumed()?;
+                let (root, smt_db) = update_tree_in_chunks(
+                    conn,
+                    &mut tree,
+                    w.get_hashed_updated()?,
+                    consts::COMMIT_CHUNK_SIZE,
+                    &is_cancelled,
+                    |processed, total| {
+                        send_commit_progress(channel, on_progress.as_ref(), processed, total)
+                    },
+                )?;
+                if is_cancelled() {
+                    return Err(DbError::Cancelled("Commit"));
+                }
+                let _guard = write_lock.lock().unwrap();
+                let result_info = CommitResultInfo::new(Ok(root), commit_data.data);
+                StateDB::handle_commit_result(
+                    conn,
+                    durability,
+                    &smt_db,
+                    w,
+                    result_info,
+                    &db_options,
+                )
+                .map_err(DbError::from)
+            });
+
             channel.send(move |mut ctx| {
+                let db_handle = db_handle.into_inner(&mut ctx);
                 let callback = callback.into_inner(&mut ctx);
                 let this = ctx.undefined();
                 let args: Vec<Handle<JsValue>> = match result {
-                    Ok(val) => {
+                    Ok((val, created)) => {
+                        // the filter is owned by the JS thread, so the created keys are added after the write
+                        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+                        let mut db = db.borrow_mut();
+                        if let Some(bloom) = db.bloom.as_mut() {
+                            bloom.insert(created.iter());
+                        }
+                        if let Some(cache) = db.negative_cache.as_mut() {
+                            cache.remove(created.iter());
+                        }
+                        drop(db);
                         let buffer = JsBuffer::external(&mut ctx, (**val.lock().unwrap()).clone());
                         vec![ctx.null().upcast(), buffer.upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
                 };
 
                 callback.call(&mut ctx, this, args)?;
@@ -291,39 +1133,103 @@ impl StateDB {
         &self,
         root: Vec<u8>,
         queries: NestedVec,
+        options: options::ProveOption,
         callback: Root<JsFunction>,
     ) -> Result<(), DataStoreError> {
+        let mut timer = self.common.timer(Operation::Prove);
         let key_length = self.options.key_length();
-        let mut tree = smt::SparseMerkleTree::new(&root, key_length, consts::SUBTREE_HEIGHT);
-        let mut smtdb = smt_db::SmtDB::new(&self.common);
-        let result = tree.prove(&mut smtdb, &queries);
+        let hasher = self.options.hasher;
+        let height = options.height.map(BlockHeight);
+        let include_values = options.include_values;
+        let include_size = options.include_size;
+        if let Some(height) = height {
+            // the past state is rebuilt on the DB thread from one snapshot, so that the commits do not change it
+            let conn = self.common.arc_clone();
+            let callback = self.common.watch_callback(callback);
+            return self
+                .common
+                .send_timed(timer, move |channel| {
+                    let callback = match callback.start() {
+                        Some(callback) => callback,
+                        None => return,
+                    };
+                    let conn = conn.unwrap();
+                    let result = if include_values {
+                        prove_with_values(conn, &root, &queries, Some(height), key_length, hasher)
+                    } else {
+                        historical_state(conn, &root, height, key_length, hasher).and_then(
+                            |mut state| {
+                                state
+                                    .prove(&queries, key_length)
+                                    .map(|proof| (proof, vec![]))
+                            },
+                        )
+                    };
+                    send_proof(channel, callback, result, include_size);
+                })
+                .map_err(|err| DataStoreError::Unknown(err.to_string()));
+        }
+        let result = if include_values {
+            prove_with_values(&self.common, &root, &queries, None, key_length, hasher)
+        } else {
+            let mut tree = smt::SparseMerkleTree::new_with_hasher(
+                &root,
+                key_length,
+                consts::SUBTREE_HEIGHT,
+                hasher,
+            );
+            let mut smtdb = smt_db::SmtDB::new(&self.common);
+            tree.prove(&mut smtdb, &queries)
+                .map(|proof| (proof, vec![]))
+        };
+        if let Some(timer) = timer.as_mut() {
+            timer.executed();
+        }
 
+        // the proof is created on the call, so that it is not timed out
         self.common
-            .send(move |channel| {
+            .send_timed(timer, move |channel| {
+                send_proof(channel, callback, result, include_size)
+            })
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))
+    }
+
+    /// prove_pending creates the proof of the queries for the root of committing the writer on the current state.
+    /// The root is computed in memory, so that neither the database nor the writer is changed.
+    fn prove_pending(
+        &self,
+        writer: &state_writer::StateWriter,
+        queries: NestedVec,
+        callback: Root<JsFunction>,
+    ) -> Result<(), DataStoreError> {
+        let mut timer = self.common.timer(Operation::Prove);
+        let result = store::prove_pending(
+            &self.common,
+            writer,
+            &queries,
+            self.options.key_length(),
+            self.options.hasher,
+        );
+        if let Some(timer) = timer.as_mut() {
+            timer.executed();
+        }
+
+        // the proof is created on the call, so that it is not timed out
+        self.common
+            .send_timed(timer, move |channel| {
                 channel.send(move |mut ctx| {
                     let callback = callback.into_inner(&mut ctx);
                     let this = ctx.undefined();
                     let args: Vec<Handle<JsValue>> = match result {
-                        Ok(val) => {
-                            let obj: Handle<JsObject> = ctx.empty_object();
-                            let sibling_hashes = ctx.empty_array();
-                            for (i, h) in val.sibling_hashes.iter().enumerate() {
-                                let val_res = JsBuffer::external(&mut ctx, h.to_vec());
-                                sibling_hashes.set(&mut ctx, i as u32, val_res)?;
-                            }
-                            obj.set(&mut ctx, "siblingHashes", sibling_hashes)?;
-                            let queries = ctx.empty_array();
-                            for (i, v) in val.queries.iter().enumerate() {
-                                let obj = pair_to_js_object(&mut ctx, &v.pair)?;
-                                let bitmap = JsBuffer::external(&mut ctx, v.bitmap.to_vec());
-                                obj.set(&mut ctx, "bitmap", bitmap)?;
-
-                                queries.set(&mut ctx, i as u32, obj)?;
-                            }
-                            obj.set(&mut ctx, "queries", queries)?;
+                        Ok((root, proof)) => {
+                            let obj = ctx.empty_object();
+                            let root = JsBuffer::external(&mut ctx, root);
+                            obj.set(&mut ctx, "root", root)?;
+                            let proof = proof_to_js_object(&mut ctx, &proof, &[])?;
+                            obj.set(&mut ctx, "proof", proof)?;
                             vec![ctx.null().upcast(), obj.upcast()]
                         },
-                        Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                     };
                     callback.call(&mut ctx, this, args)?;
 
@@ -333,6 +1239,48 @@ impl StateDB {
             .map_err(|err| DataStoreError::Unknown(err.to_string()))
     }
 
+    /// invalidate_lookups drops the bloom filter and the negative cache when the changed keys are not known.
+    fn invalidate_lookups(&mut self) {
+        if let Some(bloom) = self.bloom.as_mut() {
+            bloom.invalidate();
+        }
+        if let Some(cache) = self.negative_cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// set_bulk_load switches the bulk load on the DB thread after the operations queued before it.
+    /// The rocksdb options are restored and the database is compacted when it is turned off.
+    fn set_bulk_load(
+        &self,
+        enabled: bool,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let conn = self.common.arc_clone();
+        let tuning = self.options.tuning;
+        let bulk_load = Arc::clone(&self.bulk_load);
+        self.common.send_write(move |channel| {
+            let result = if bulk_load.load(Ordering::SeqCst) == enabled {
+                Ok(())
+            } else {
+                bulk_load::switch(conn.unwrap(), enabled, &tuning)
+                    .map(|_| bulk_load.store(enabled, Ordering::SeqCst))
+            };
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(_) => vec![ctx.null().upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        })
+    }
+
     fn clean_diff_until(
         &self,
         version: BlockHeight,
@@ -342,28 +1290,42 @@ impl StateDB {
             return Ok(());
         }
         let conn = self.common.arc_clone();
+        let durability = self.common.durability();
+        let write_lock = Arc::clone(&self.write_lock);
         self.common
-            .send(move |channel| {
+            .send_write(move |channel| {
                 let start = [consts::Prefix::DIFF, 0_u32.to_be_bytes().as_slice()].concat();
                 let bytes = (version - BlockHeight(1)).to_be_bytes();
                 let end = [consts::Prefix::DIFF, &bytes].concat();
                 let mut batch = rocksdb::WriteBatch::default();
+                // the reference counts are read and written under the lock, so that no revert changes them in between
+                let guard = write_lock.lock().unwrap();
 
-                let conn_iter = conn.unwrap().iterator(rocksdb::IteratorMode::From(
-                    end.as_ref(),
-                    rocksdb::Direction::Reverse,
-                ));
+                let conn_iter = column_families::iterator(
+                    conn.unwrap(),
+                    consts::Prefix::DIFF,
+                    rocksdb::IteratorMode::From(end.as_ref(), rocksdb::Direction::Reverse),
+                );
 
-                for key_val in conn_iter {
-                    if utils::compare(&(key_val.as_ref().unwrap().0), &start)
-                        == cmp::Ordering::Less
-                    {
-                        break;
+                let result = (|| -> Result<(), DbError> {
+                    let mut ref_changes = RefChanges::default();
+                    for key_val in conn_iter {
+                        let (key, value) = key_val?;
+                        if utils::compare(&key, &start) == cmp::Ordering::Less {
+                            break;
+                        }
+                        batch.delete(&key);
+                        ref_changes.remove_stored_diff(&key, &value)?;
                     }
-                    batch.delete(&(key_val.unwrap().0));
-                }
-
-                let result = conn.unwrap().write(batch);
+                    // the roots of the heights and the referenced values are removed together with the diffs
+                    let mut batch = column_families::route(conn.unwrap(), batch);
+                    root_history::prune(conn.unwrap(), &mut batch, version.into())?;
+                    ref_changes.write(conn.unwrap(), &mut batch)?;
+                    conn.unwrap()
+                        .write_opt(batch, &durability.write_options(false))?;
+                    Ok(())
+                })();
+                drop(guard);
 
                 channel.send(move |mut ctx| {
                     let callback = callback.into_inner(&mut ctx);
@@ -372,7 +1334,7 @@ impl StateDB {
                         Ok(_) => {
                             vec![ctx.null().upcast()]
                         },
-                        Err(err) => vec![ctx.error(&err)?.upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                     };
                     callback.call(&mut ctx, this, args)?;
 
@@ -382,7 +1344,7 @@ impl StateDB {
             .map_err(|err| DataStoreError::Unknown(err.to_string()))
     }
 
-    fn proof(ctx: &mut FunctionContext, pos: u8) -> NeonResult<smt::Proof> {
+    pub(crate) fn proof(ctx: &mut FunctionContext, pos: u8) -> NeonResult<smt::Proof> {
         let raw_proof = ctx.argument::<JsObject>(pos.into())?;
         let raw_sibling_hashes = raw_proof
             .get::<JsArray, _, _>(ctx, "siblingHashes")?
@@ -427,6 +1389,23 @@ impl StateDB {
         })
     }
 
+    /// raw_values returns rawValue of each query in the proof if exists.
+    fn raw_values(ctx: &mut FunctionContext, pos: u8) -> NeonResult<ProofValues> {
+        let raw_proof = ctx.argument::<JsObject>(pos.into())?;
+        let raw_queries = raw_proof
+            .get::<JsArray, _, _>(ctx, "queries")?
+            .to_vec(ctx)?;
+        let mut values = Vec::with_capacity(raw_queries.len());
+        for query in raw_queries.iter() {
+            let obj = query.downcast_or_throw::<JsObject, _>(ctx)?;
+            let value = obj
+                .get_opt::<JsTypedArray<u8>, _, _>(ctx, "rawValue")?
+                .map(|value| value.as_slice(ctx).to_vec());
+            values.push(value);
+        }
+        Ok(values)
+    }
+
     fn parse_query_keys(ctx: &mut FunctionContext) -> NeonResult<NestedVec> {
         let query_keys = ctx.argument::<JsArray>(1)?.to_vec(ctx)?;
         let parsed_query_keys = query_keys
@@ -442,55 +1421,220 @@ impl StateDB {
         Ok(parsed_query_keys)
     }
 
-    fn get_current_state(
-        &self,
-        callback: Root<JsFunction>,
+    /// build_bloom builds the bloom filter on the DB thread against a snapshot, so that the JS thread is not blocked
+    /// by the scan of the state. It is installed on the JS thread, and the lookups until then read the database.
+    fn build_bloom(
+        &mut self,
+        db_handle: Root<JsObject>,
     ) -> Result<(), mpsc::SendError<DbMessage>> {
-        let result = self.common.get(consts::Prefix::CURRENT_STATE);
+        let build = match self.bloom.as_mut().and_then(StateBloom::start_build) {
+            Some(build) => build,
+            None => return Ok(()),
+        };
+        let conn = self.common.arc_clone();
         self.common.send(move |channel| {
+            let result = build.run(conn.unwrap());
             channel.send(move |mut ctx| {
-                let callback = callback.into_inner(&mut ctx);
-                let this = ctx.undefined();
-                let args: Vec<Handle<JsValue>> = match result {
-                    Ok(value) => {
-                        let temp_value: Vec<u8>;
-                        let current_state_info = if let Some(value) = value {
-                            temp_value = value;
-                            CurrentState::from_bytes(&temp_value)
-                        } else {
-                            CurrentState::new(&EMPTY_HASH, BlockHeight(0))
-                        };
-                        let root = JsBuffer::external(&mut ctx, current_state_info.root.to_vec());
-                        let version = ctx.number::<u32>(current_state_info.version.into());
-                        let object = ctx.empty_object();
-                        object.set(&mut ctx, "root", root)?;
-                        object.set(&mut ctx, "version", version)?;
-                        vec![ctx.null().upcast(), object.upcast()]
-                    },
-                    Err(err) => vec![ctx.error(&err)?.upcast()],
-                };
-                callback.call(&mut ctx, this, args)?;
+                let db_handle = db_handle.into_inner(&mut ctx);
+                let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+                let mut db = db.borrow_mut();
+                if let Some(bloom) = db.bloom.as_mut() {
+                    bloom.finish_build(build, result);
+                }
+
                 Ok(())
             });
         })
     }
 
-    pub fn arc_clone(&self) -> ArcOptionDB {
-        self.common.arc_clone()
-    }
+    /// exists checks the existence of the key in the state.
+    fn exists(
+        &mut self,
+        key: Vec<u8>,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let result = self.key_exists(&key);
+        self.common.send_exists_result(result, callback)
+    }
+
+    /// key_exists returns true if the key exists in the state.
+    /// The key cached as missing is answered without reading the database, and the missing key is cached.
+    /// When the bloom filter is enabled, the database is not read if the key definitely does not exist.
+    fn key_exists(&mut self, key: &[u8]) -> Result<bool, rocksdb::Error> {
+        if let Some(cache) = self.negative_cache.as_mut() {
+            if cache.contains(key) {
+                return Ok(false);
+            }
+        }
+        let key_with_prefix = Kind::State.key(key.to_vec());
+        let result = match self.bloom.as_mut() {
+            Some(bloom) => match bloom.may_exist(key) {
+                Some(false) => Ok(false),
+                Some(true) => {
+                    let result = self.common.key_exists(&key_with_prefix);
+                    if let Ok(false) = result {
+                        bloom.record_false_positive();
+                    }
+                    result
+                },
+                // filter is advisory, so fallback to the database until it is built
+                None => self.common.key_exists(&key_with_prefix),
+            },
+            None => self.common.key_exists(&key_with_prefix),
+        };
+        if let (Ok(false), Some(cache)) = (&result, self.negative_cache.as_mut()) {
+            cache.insert(key);
+        }
+        result
+    }
+
+    fn get_current_state(
+        &self,
+        callback: Root<JsFunction>,
+    ) -> Result<(), mpsc::SendError<DbMessage>> {
+        let result = CurrentState::get_or_empty(&self.common, &self.options.hasher.empty_hash(0));
+        self.common.send(move |channel| {
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(current_state_info) => {
+                        let root =
+                            JsBuffer::external(&mut ctx, current_state_info.root().to_vec());
+                        let version = ctx.number::<u32>(current_state_info.version().into());
+                        let commit_count = ctx.number(current_state_info.commit_count() as f64);
+                        let timestamp = ctx.number(current_state_info.timestamp() as f64);
+                        let object = ctx.empty_object();
+                        object.set(&mut ctx, "root", root)?;
+                        object.set(&mut ctx, "version", version)?;
+                        object.set(&mut ctx, "height", version)?;
+                        object.set(&mut ctx, "commitCount", commit_count)?;
+                        object.set(&mut ctx, "timestamp", timestamp)?;
+                        if !current_state_info.reverted_from().is_empty() {
+                            let reverted_from = JsBuffer::external(
+                                &mut ctx,
+                                current_state_info.reverted_from().to_vec(),
+                            );
+                            object.set(&mut ctx, "revertedFrom", reverted_from)?;
+                        }
+                        vec![ctx.null().upcast(), object.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+                Ok(())
+            });
+        })
+    }
+
+    pub fn arc_clone(&self) -> ArcOptionDB {
+        self.common.arc_clone()
+    }
+
+    /// smt_threads returns the number of the threads updating the tree on commit.
+    fn smt_threads(&self) -> usize {
+        self.options
+            .smt_threads
+            .unwrap_or_else(smt::SparseMerkleTree::default_threads)
+    }
+
+    /// reader_pool returns the workers shared by the readers, and starts them for the first reader.
+    pub fn reader_pool(&self) -> Arc<ReaderPool> {
+        self.reader_pool
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| {
+                let size = self
+                    .options
+                    .reader_pool_size
+                    .unwrap_or_else(ReaderPool::default_size);
+                ReaderPool::new(size)
+            })
+            .clone()
+    }
+
+    /// open_reader returns the slot held by a new reader until it is closed, or TooManyReaders over max_open_readers.
+    pub(crate) fn open_reader(&self) -> Result<ReaderSlot, TooManyReaders> {
+        self.readers.open(self.options.max_open_readers)
+    }
+
+    /// max_iteration_result_size returns the bytes of the result of an iterate or a range of the readers.
+    pub fn max_iteration_result_size(&self) -> Option<usize> {
+        self.options.max_iteration_result_size
+    }
+
+    pub fn registration_id(&self) -> u64 {
+        self.common.registration_id()
+    }
 }
 
 impl StateDB {
+    /// js_migrate is handler for JS ffi.
+    /// It opens the database at the path in a dedicated thread, runs the pending format migrations and closes it,
+    /// so that the migration does not block the JS thread. The database must not be open.
+    /// - @params(0) - path of the database.
+    /// - @params(1) - DbOptions of the database. autoMigrate is set regardless of the options.
+    /// - @params(2) - Options {onProgress: (step: string, processed: number) => void}.
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error. If the database is locked, it will call the callback with ERR_DB_LOCKED code.
+    pub fn js_migrate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let options = ctx.argument_opt(1);
+        let mut db_options = DbOptions::new_with_context(&mut ctx, options)?;
+        db_options.auto_migrate = true;
+        db_options.exclusive = true;
+        if db_options.is_readonly() {
+            return DbError::InvalidArgument("readonly database cannot be migrated".to_string())
+                .throw(&mut ctx);
+        }
+        let option_inputs = ctx.argument::<JsObject>(2)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = open_migrated(&path, db_options, &Kind::State, &mut |step, processed| {
+                send_migration_progress(&channel, on_progress.as_ref(), step, processed)
+            })
+            .map(drop);
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(()) => vec![ctx.null().upcast()],
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
     /// js_close is handler for JS ffi.
+    /// The readers created from the StateDB are closed with it.
     /// js "this" - StateDB.
+    /// - @params(0) - callback called after the connection is released (optional).
+    /// - @callback(0) - Error.
     pub fn js_close(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx
+            .argument_opt(0)
+            .and_then(|val| val.downcast::<JsFunction, _>(&mut ctx).ok())
+            .map(|val| val.root(&mut ctx));
         // Get the `this` value as a `JsBox<Database>`
-        ctx.this()
-            .downcast_or_throw::<SharedStateDB, _>(&mut ctx)?
-            .borrow_mut()
-            .common
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let mut db = db.borrow_mut();
+        db.common
             .close()
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        if let Some(callback) = callback {
+            db.common.on_released(ctx.channel(), callback);
+        }
 
         Ok(ctx.undefined())
     }
@@ -499,7 +1643,7 @@ impl StateDB {
     /// js "this" - StateDB.
     /// - @params(0) - key to get from state db.
     /// - @params(1) - callback to return the fetched value.
-    /// - @callback(0) - Error. If data is not found, it will call the callback with "No data" as a first args.
+    /// - @callback(0) - Error. If data is not found, it will call the callback with ERR_NOT_FOUND code as a first args.
     /// - @callback(1) - [u8]. Value associated with the key.
     pub fn js_get(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
@@ -508,9 +1652,11 @@ impl StateDB {
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_keys(&mut ctx, &[&key])?;
         db.common
             .get_by_key(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -519,14 +1665,15 @@ impl StateDB {
     /// js "this" - StateDB.
     /// - @params(0) - callback to return the fetched value.
     /// - @callback(0) - Error
-    /// - @callback(1) - { root: [u8], version: u32 }.
+    /// - @callback(1) - { root: [u8], version: u32, height: u32, commitCount: u64, timestamp: u64, revertedFrom?: [u8] }.
     pub fn js_get_current_state(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
         // Get the `this` value as a `JsBox<Database>`
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
         db.get_current_state(callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -541,97 +1688,277 @@ impl StateDB {
         let key = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
         let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
         // Get the `this` value as a `JsBox<Database>`
-        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db_handle = ctx.this();
+        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
-        let db = db.borrow_mut();
-        db.common
-            .exists(key, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        let mut db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_invalid_keys(&mut ctx, &[&key])?;
+        db.exists(key, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        if db.bloom.as_ref().map_or(false, StateBloom::needs_build) {
+            let db_handle = db_handle.root(&mut ctx);
+            db.build_bloom(db_handle)
+                .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        }
 
         Ok(ctx.undefined())
     }
 
+    /// js_bloom_stats is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @return - { checks: u64, definiteMisses: u64, falsePositives: u64, rebuilds: u64 }, or null if bloom filter is disabled.
+    pub fn js_bloom_stats(mut ctx: FunctionContext) -> JsResult<JsValue> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let stats = match db.bloom.as_ref() {
+            Some(bloom) => bloom.stats(),
+            None => return Ok(ctx.null().upcast()),
+        };
+        let obj = ctx.empty_object();
+        let checks = ctx.number(stats.checks as f64);
+        obj.set(&mut ctx, "checks", checks)?;
+        let definite_misses = ctx.number(stats.definite_misses as f64);
+        obj.set(&mut ctx, "definiteMisses", definite_misses)?;
+        let false_positives = ctx.number(stats.false_positives as f64);
+        obj.set(&mut ctx, "falsePositives", false_positives)?;
+        let rebuilds = ctx.number(stats.rebuilds as f64);
+        obj.set(&mut ctx, "rebuilds", rebuilds)?;
+
+        Ok(obj.upcast())
+    }
+
+    /// js_negative_cache_stats is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @return - { hits: u64, misses: u64, evictions: u64, entries: u64 }, or null if the negative cache is disabled.
+    pub fn js_negative_cache_stats(mut ctx: FunctionContext) -> JsResult<JsValue> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let stats = match db.negative_cache.as_ref() {
+            Some(cache) => cache.stats(),
+            None => return Ok(ctx.null().upcast()),
+        };
+        let obj = ctx.empty_object();
+        let hits = ctx.number(stats.hits as f64);
+        obj.set(&mut ctx, "hits", hits)?;
+        let misses = ctx.number(stats.misses as f64);
+        obj.set(&mut ctx, "misses", misses)?;
+        let evictions = ctx.number(stats.evictions as f64);
+        obj.set(&mut ctx, "evictions", evictions)?;
+        let entries = ctx.number(stats.entries as f64);
+        obj.set(&mut ctx, "entries", entries)?;
+
+        Ok(obj.upcast())
+    }
+
+    /// js_list_readers is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @return - { count: number, agesMs: number[] } of the open readers and the read-writers, the oldest first.
+    pub fn js_list_readers(mut ctx: FunctionContext) -> JsResult<JsObject> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let ages = db.borrow().readers.ages();
+        let obj = ctx.empty_object();
+        let count = ctx.number(ages.len() as f64);
+        obj.set(&mut ctx, "count", count)?;
+        let ages_ms = ctx.empty_array();
+        for (i, age) in ages.iter().enumerate() {
+            let age = ctx.number(age.as_millis() as f64);
+            ages_ms.set(&mut ctx, i as u32, age)?;
+        }
+        obj.set(&mut ctx, "agesMs", ages_ms)?;
+
+        Ok(obj)
+    }
+
+    /// js_key_constants is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @return - { prefixes: { state: &[u8], smt: &[u8], diff: &[u8] }, moduleIdLength, storePrefixLength, keyLength } with the configured key length.
+    pub fn js_key_constants(mut ctx: FunctionContext) -> JsResult<JsObject> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let key_length = db.borrow().options.key_length();
+        key_layout::constants_to_js_object(&mut ctx, key_length)
+    }
+
+    /// js_get_metrics is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - reset the counters after the snapshot (optional).
+    /// - @return - { bucketBoundsMs: number[], [operation]: { queueWait, execution } }, or null if metrics are disabled.
+    pub fn js_get_metrics(mut ctx: FunctionContext) -> JsResult<JsValue> {
+        let reset = ctx
+            .argument_opt(0)
+            .and_then(|val| val.downcast::<JsBoolean, _>(&mut ctx).ok())
+            .map(|val| val.value(&mut ctx))
+            .unwrap_or(false);
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let snapshot = match db.common.metrics() {
+            Some(metrics) => metrics.snapshot(reset),
+            None => return Ok(ctx.null().upcast()),
+        };
+
+        Ok(metrics::metrics_to_js_object(&mut ctx, &snapshot)?.upcast())
+    }
+
     /// js_revert is handler for JS ffi.
     /// js "this" - StateDB.
     /// - @params(0) - State root of to revert back from.
     /// - @params(1) - Version of the state DB to revert back from.
-    /// - @params(2) - callback to return the result.
+    /// - @params(2) - expected state root after the revert. Empty bytes skips the check.
+    /// - @params(3) - callback to return the result.
     /// - @callback(0) - Error.
     /// - @callback(1) - &[u8] State root after the revert.
     pub fn js_revert(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let prev_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
         let height = ctx.argument::<JsNumber>(1)?.value(&mut ctx).into();
-        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+        let expected = ctx.argument::<JsTypedArray<u8>>(2)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
         // Get the `this` value as a `JsBox<Database>`
-        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db_handle = ctx.this();
+        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
-        let mut db = db.borrow_mut();
-        db.revert(height, prev_root, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        db.common.throw_if_backpressure(&mut ctx)?;
+        let db_handle = db_handle.root(&mut ctx);
+        db.revert(height, prev_root, expected, db_handle, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
     /// js_iterate is handler for JS ffi.
     /// js "this" - StateDB.
-    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8]}.
+    /// - @params(0) - Options for iteration. {limit: u32, reverse: bool, gte: &[u8], lte: &[u8], prefix: &[u8], chunkSize: u32, keysOnly: bool}.
     /// - @params(1) - Callback to be called on each data iteration.
     /// - @params(2) - callback to be called when completing the iteration.
     /// - @callback1(0) - Error.
-    /// - @callback1(1) - { key: &[u8], value: &[u8]}.
-    /// - @callback(0) - void.
+    /// - @callback1(1) - { key: &[u8], value: &[u8]}. Value is not set with keysOnly.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - bool. True if the iteration is stopped by maxIterationResultSize.
     pub fn js_iterate(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let option_inputs = ctx.argument::<JsObject>(0)?;
-        let options = options::IterationOption::new(&mut ctx, option_inputs);
+        let mut options = options::ChunkedIterationOption::new(&mut ctx, option_inputs)?;
         let callback_on_data = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
         let callback_done = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
         // Get the `this` value as a `JsBox<Database>`
 
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow_mut();
+        db.throw_if_closed(&mut ctx)?;
+        options.max_result_size = db.max_iteration_result_size();
 
-        let callback_on_data = Arc::new(Mutex::new(callback_on_data));
         let conn = db.common.arc_clone();
+        let closed = db.common.closed_flag();
+        let timer = db.common.timer(Operation::Iterate);
         db.common
-            .send(move |channel| {
-                let conn_iter = conn.unwrap().iterator(DbUtils::get_iteration_mode(
+            .send_timed(timer, move |channel| {
+                let mut start = vec![];
+                let conn_iter = column_families::iterator_opt(
+                    conn.unwrap(),
+                    consts::Prefix::STATE,
+                    DbUtils::get_iteration_mode(
+                        &options.iteration,
+                        &mut start,
+                        consts::Prefix::STATE,
+                    ),
+                    DbUtils::get_read_options(&options.iteration),
+                );
+                DbUtils::send_in_chunks(
+                    channel,
+                    conn_iter,
                     &options,
-                    &mut vec![],
-                    true,
-                ));
-                for (counter, key_val) in conn_iter.enumerate() {
-                    if DbUtils::is_key_out_of_range(
+                    consts::Prefix::STATE,
+                    &closed,
+                    callback_on_data,
+                    callback_done,
+                );
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_iterate_kind is handler for JS ffi.
+    /// It iterates the stored data of the kind, so that the diffs and the SMT nodes can be audited without rocksdb.
+    /// The keys are without the kind prefix, such as the 4 bytes height of the diff and the hash of the SMT node.
+    /// js "this" - StateDB.
+    /// - @params(0) - kind of the data. One of "state", "diff" and "smt".
+    /// - @params(1) - iteration options. decode: true returns the decoded diffs of the diff kind.
+    /// - @params(2) - callback called for each item. { key: &[u8], value: &[u8] }, or { height: u32, created: &[u8][], updated: { key: &[u8], value: &[u8] }[], deleted: { key: &[u8], value: &[u8] }[] } if decoded.
+    /// - @params(3) - callback called at the end. ERR_CORRUPTED on the callback of the item if the diff cannot be decoded.
+    ///   The second argument is true if the iteration is stopped by maxIterationResultSize.
+    pub fn js_iterate_kind(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let kind = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let prefix = match kind_prefix(&kind) {
+            Some(prefix) => prefix,
+            None => {
+                return error::invalid_argument(format!(
+                    "kind must be one of state, diff and smt, got {}",
+                    kind
+                ))
+                .throw(&mut ctx)
+            },
+        };
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let mut options = options::ChunkedIterationOption::new(&mut ctx, option_inputs)?;
+        let decode = option_inputs
+            .get_opt::<JsBoolean, _, _>(&mut ctx, "decode")?
+            .map(|val| val.value(&mut ctx))
+            .unwrap_or(false);
+        if decode && prefix != consts::Prefix::DIFF {
+            return error::invalid_argument("decode is only supported for the diff kind")
+                .throw(&mut ctx);
+        }
+        if decode && options.iteration.item_fields() != ItemFields::KeyValue {
+            return error::invalid_argument("decode cannot be used with keysOnly or inventory")
+                .throw(&mut ctx);
+        }
+        let callbacks = ChunkCallbacks {
+            on_data: ctx.argument::<JsFunction>(2)?.root(&mut ctx),
+            done: ctx.argument::<JsFunction>(3)?.root(&mut ctx),
+        };
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        options.max_result_size = db.max_iteration_result_size();
+
+        let conn = db.common.arc_clone();
+        let closed = db.common.closed_flag();
+        let timer = db.common.timer(Operation::Iterate);
+        db.common
+            .send_timed(timer, move |channel| {
+                let mut start = vec![];
+                let conn_iter = column_families::iterator_opt(
+                    conn.unwrap(),
+                    prefix,
+                    DbUtils::get_iteration_mode(&options.iteration, &mut start, prefix),
+                    DbUtils::get_read_options(&options.iteration),
+                );
+                if decode {
+                    let decoder = DecodedDiffs {
+                        conn: Arc::clone(&conn),
+                    };
+                    DbUtils::send_decoded_in_chunks(
+                        channel, conn_iter, &options, prefix, &closed, callbacks, decoder,
+                    );
+                } else {
+                    DbUtils::send_in_chunks(
+                        channel,
+                        conn_iter,
                         &options,
-                        &(key_val.as_ref().unwrap().0),
-                        counter as i64,
-                        true,
-                    ) {
-                        break;
-                    }
-                    let callback_on_data = Arc::clone(&callback_on_data);
-                    channel.send(move |mut ctx| {
-                        let (_, key_without_prefix) =
-                            key_val.as_ref().unwrap().0.split_first().unwrap();
-                        let temp_pair =
-                            KVPair::new(key_without_prefix, &(key_val.as_ref().unwrap().1));
-                        let obj = pair_to_js_object(&mut ctx, &temp_pair)?;
-                        let callback = callback_on_data.lock().unwrap().to_inner(&mut ctx);
-                        let this = ctx.undefined();
-                        let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast(), obj.upcast()];
-                        callback.call(&mut ctx, this, args)?;
-                        Ok(())
-                    });
+                        prefix,
+                        &closed,
+                        callbacks.on_data,
+                        callbacks.done,
+                    );
                 }
-                channel.send(move |mut ctx| {
-                    let callback_done = callback_done.into_inner(&mut ctx);
-                    let this = ctx.undefined();
-                    let args: Vec<Handle<JsValue>> = vec![ctx.null().upcast()];
-                    callback_done.call(&mut ctx, this, args)?;
-
-                    Ok(())
-                });
             })
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -644,8 +1971,12 @@ impl StateDB {
     /// - @params(3) - readonly not update the state to the physical storage.
     /// - @params(4) - expected state root to compare.
     /// - @params(5) - whether to check the root before storing to the physical storage.
-    /// - @params(6) - callback to return the result.
-    /// - @callback(0) - Error.
+    /// - @params(6) - max bytes of a chunk to write the commit in chunks. 0 writes in a single atomic batch.
+    /// - @params(7) - whether to sync the WAL of the commit regardless of syncWrites.
+    /// - @params(8) - Options {onProgress: (processed, total) => void, stagedId: &[u8]}. onProgress is called after each chunk of the keys updated in the tree.
+    ///   stagedId marks the batch staged in the Database with writePrepared in the same write as the current state.
+    /// - @params(9) - callback to return the result.
+    /// - @callback(0) - Error. ERR_CANCELLED code if the commit is cancelled before the write.
     /// - @callback(1) - &[u8] State root after the commit.
     pub fn js_commit(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let writer = ctx.argument::<state_writer::SendableStateWriter>(0)?;
@@ -659,20 +1990,117 @@ impl StateDB {
         let expected = ctx.argument::<JsTypedArray<u8>>(4)?.as_slice(&ctx).to_vec();
 
         let check_root = ctx.argument::<JsBoolean>(5)?.value(&mut ctx);
-        let callback = ctx.argument::<JsFunction>(6)?.root(&mut ctx);
+        let max_batch_bytes = ctx.argument::<JsNumber>(6)?.value(&mut ctx);
+        if max_batch_bytes < 0.0 || max_batch_bytes.fract() != 0.0 {
+            return error::invalid_argument("maxBatchBytes must be a non-negative integer")
+                .throw(&mut ctx);
+        }
+        let sync = ctx.argument::<JsBoolean>(7)?.value(&mut ctx);
+        let option_inputs = ctx.argument::<JsObject>(8)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let staged_id = option_inputs
+            .get_opt::<JsTypedArray<u8>, _, _>(&mut ctx, "stagedId")?
+            .map(|id| id.as_slice(&ctx).to_vec());
+        if staged_id.as_ref().map_or(false, |id| id.is_empty()) {
+            return error::invalid_argument("stagedId must not be empty").throw(&mut ctx);
+        }
+        let callback = ctx.argument::<JsFunction>(9)?.root(&mut ctx);
         // Get the `this` value as a `JsBox<Database>`
-        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db_handle = ctx.this();
+        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
 
-        let mut db = db.borrow_mut();
-        if db.options.is_readonly() {
-            return ctx.throw_error(String::from("Readonly DB cannot be committed."));
-        }
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        db.common.throw_if_backpressure(&mut ctx)?;
         let options = CommitOptions::new(readonly, version);
-        let commit = Commit::new(expected, options, check_root);
+        let mut commit = Commit::new(expected, options, check_root);
+        if max_batch_bytes > 0.0 {
+            commit.max_batch_bytes = Some(max_batch_bytes as usize);
+        }
+        if sync && db.common.durability().disable_wal {
+            return error::invalid_argument("sync cannot be used with disableWal").throw(&mut ctx);
+        }
+        commit.sync = sync;
+        commit.staged_id = staged_id;
         let writer = Arc::clone(&writer.borrow());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        writer.read().unwrap().throw_if_consumed(&mut ctx)?;
         let commit_data = CommitData::new(commit, prev_root);
-        db.commit(writer, commit_data, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        let db_handle = db_handle.root(&mut ctx);
+        db.commit(writer, commit_data, on_progress, db_handle, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_commit_cancel is handler for JS ffi.
+    /// It cancels the commits started before the call. A commit which already started writing is not cancelled.
+    /// js "this" - StateDB.
+    pub fn js_commit_cancel(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        db.borrow().commit_cancel.cancel();
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_compute_root is handler for JS ffi.
+    /// The root is computed on the DB thread against a snapshot, so that the commits do not change the state read by it.
+    /// js "this" - StateDB.
+    /// - @params(0) - StateWriter to compute the root with.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { root: &[u8]; version: u32; changedKeys: u32; }
+    pub fn js_compute_root(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let writer = ctx.argument::<state_writer::SendableStateWriter>(0)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&writer.borrow());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let threads = db.smt_threads();
+        let conn = db.common.arc_clone();
+        let callback = db.common.watch_callback(callback);
+
+        db.common
+            .send(move |channel| {
+                let callback = match callback.start() {
+                    Some(callback) => callback,
+                    None => return,
+                };
+                let conn = conn.unwrap();
+                let snapshot = StateSnapshot::new(conn);
+                let result = read_folded(conn, &writer).and_then(|writer| {
+                    compute_root(&snapshot, &writer, key_length, hasher, threads)
+                });
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(val) => {
+                            let obj = ctx.empty_object();
+                            let root = JsBuffer::external(&mut ctx, val.root);
+                            obj.set(&mut ctx, "root", root)?;
+                            let version = ctx.number::<u32>(val.version.into());
+                            obj.set(&mut ctx, "version", version)?;
+                            let changed_keys = ctx.number(val.changed_keys as f64);
+                            obj.set(&mut ctx, "changedKeys", changed_keys)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -680,13 +2108,16 @@ impl StateDB {
     /// js_prove is handler for JS ffi.
     /// js "this" - StateDB.
     /// - @params(0) - current state root (required).
-    /// - @params(1) - queries in format of &[&[u8]]
-    /// - @params(2) - callback to return the result.
+    /// - @params(1) - queries in format of &[&[u8]]. State keys if includeValues is true.
+    /// - @params(2) - options { includeValues: bool, height?: u32, includeSize: bool }. With height, root must be the state root of the height.
+    /// - @params(3) - callback to return the result.
     /// - @callback(0) - Error.
-    /// - @callback(1) - { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }
+    /// - @callback(1) - { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; rawValue?: &[u8]; }[]; size?: u32 }
+    ///   size is the bytes of the proof encoded by lisk-codec, set if includeSize is true.
     pub fn js_prove(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
 
         let state_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
 
@@ -698,10 +2129,47 @@ impl StateDB {
             queries.push(key);
         }
 
+        let options = ctx.argument_opt(2);
+        let options = options::ProveOption::new(&mut ctx, options)?;
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+        // queries are the state keys only with the values, otherwise they are validated as the SMT keys
+        if options.include_values {
+            db.throw_if_invalid_keys(&mut ctx, &queries)?;
+        }
+
+        db.prove(state_root, queries, options, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_prove_pending is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - StateWriter to prove the uncommitted state of.
+    /// - @params(1) - queries in format of &[&[u8]].
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { root: &[u8]; proof: { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }; }
+    pub fn js_prove_pending(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let writer = ctx.argument::<state_writer::SendableStateWriter>(0)?;
+        let input = ctx.argument::<JsArray>(1)?.to_vec(&mut ctx)?;
+        let mut queries = NestedVec::new();
+        for item in input.iter() {
+            let obj = item.downcast_or_throw::<JsTypedArray<u8>, _>(&mut ctx)?;
+            let key = obj.as_slice(&ctx).to_vec();
+            queries.push(key);
+        }
         let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
 
-        db.prove(state_root, queries, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let writer = Arc::clone(&writer.borrow());
+        writer.read().unwrap().throw_if_closed(&mut ctx)?;
+        let writer = read_folded(&db.common, &writer).or_else(|err| err.throw(&mut ctx))?;
+
+        db.prove_pending(&writer, queries, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
@@ -710,24 +2178,36 @@ impl StateDB {
     /// js "this" - StateDB.
     /// - @params(0) - current state root.
     /// - @params(1) - queries in format of &[&[u8]]
-    /// - @params(2) - proof { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }
+    /// - @params(2) - proof { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; rawValue?: &[u8]; }[]; }
     /// - @params(3) - callback to return the result.
-    /// - @callback(0) - Error.
+    /// - @callback(0) - Error. If rawValue does not match the value hash of the query, it will call the callback with the error.
     /// - @callback(1) - bool represents true if proof is valid.
     pub fn js_verify(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
         let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
         let state_root = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
 
         let proof = Self::proof(&mut ctx, 2)?;
+        let raw_values = Self::raw_values(&mut ctx, 2)?;
         let parsed_query_keys = Self::parse_query_keys(&mut ctx)?;
         let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
         let channel = ctx.channel();
 
         thread::spawn(move || {
-            let result =
-                smt::SparseMerkleTree::verify(&parsed_query_keys, &proof, &state_root, key_length);
+            let result = smt::validate_key_lengths(&parsed_query_keys, key_length)
+                .and_then(|_| check_proof_values(&parsed_query_keys, &proof, &raw_values))
+                .and_then(|_| {
+                    smt::SparseMerkleTree::verify_with_hasher(
+                        &parsed_query_keys,
+                        &proof,
+                        &state_root,
+                        key_length,
+                        hasher,
+                    )
+                });
 
             channel.send(move |mut ctx| {
                 let callback = callback.into_inner(&mut ctx);
@@ -736,7 +2216,7 @@ impl StateDB {
                     Ok(val) => {
                         vec![ctx.null().upcast(), JsBoolean::new(&mut ctx, val).upcast()]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -747,45 +2227,1257 @@ impl StateDB {
         Ok(ctx.undefined())
     }
 
-    /// js_clean_diff_until is handler for JS ffi.
+    /// js_write is handler for JS ffi.
     /// js "this" - StateDB.
-    /// - @params(0) - version to delete state diff upto.
+    /// - @params(0) - Batch with the keys of "state", "diff", "smt" or "raw" kind.
     /// - @params(1) - callback to return the result.
     /// - @callback(0) - Error.
-    pub fn js_clean_diff_until(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
-        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
-        let db = db.borrow();
+    pub fn js_write(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let batch = ctx
+            .argument::<batch::SendableWriteBatch>(0)?
+            .downcast_or_throw::<batch::SendableWriteBatch, _>(&mut ctx)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
 
-        let version = ctx.argument::<JsNumber>(0)?.value(&mut ctx).into();
+        let db_handle = ctx.this();
+        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        db.common.throw_if_backpressure(&mut ctx)?;
 
-        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let batch = Arc::clone(&batch.borrow());
+        batch.lock().unwrap().throw_if_closed(&mut ctx)?;
+        db.common
+            .throw_if_batch_over_limits(&mut ctx, &batch.lock().unwrap().batch)?;
+        let claim = BatchClaim::new(&batch).or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        let conn = db.common.arc_clone();
+        let durability = db.common.durability();
+        let write_lock = Arc::clone(&db.write_lock);
+        let db_handle = db_handle.root(&mut ctx);
+        let callback = db.common.watch_callback(callback);
+        // written on the DB thread, so that the write is ordered with the queued commits and reverts
+        db.common
+            .send_write_timed(db.common.timer(Operation::Write), move |channel| {
+                let callback = match callback.start() {
+                    Some(callback) => callback,
+                    None => return,
+                };
+                let write_batch = batch.lock().unwrap().clone();
+                let result = {
+                    let _guard = write_lock.lock().unwrap();
+                    column_families::write_opt(
+                        conn.unwrap(),
+                        write_batch.batch,
+                        &durability.write_options(false),
+                    )
+                };
+                claim.finish(result.is_ok());
+                channel.send(move |mut ctx| {
+                    let db_handle = db_handle.into_inner(&mut ctx);
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(()) => {
+                            // the keys are not validated, so the filter owned by the JS thread is rebuilt on next use
+                            let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+                            db.borrow_mut().invalidate_lookups();
+                            vec![ctx.null().upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
 
-        db.clean_diff_until(version, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
-    /// js_checkpoint is handler for JS ffi.
+    /// js_set_bulk_load is handler for JS ffi.
     /// js "this" - StateDB.
-    /// - @params(0) - path to create the checkpoint.
+    /// - @params(0) - true to start the bulk load, where the commits write no diff and cannot be reverted.
     /// - @params(1) - callback to return the result.
     /// - @callback(0) - Error.
-    pub fn js_checkpoint(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    pub fn js_set_bulk_load(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let enabled = ctx.argument::<JsBoolean>(0)?.value(&mut ctx);
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
         let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
         let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
 
-        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
-        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
-
-        db.common
-            .checkpoint(path, callback)
-            .or_else(|err| ctx.throw_error(err.to_string()))?;
+        db.set_bulk_load(enabled, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
 
         Ok(ctx.undefined())
     }
 
-    /// js_calculate_root is handler for JS ffi.
+    /// js_clean_diff_until is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - version to delete state diff upto.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_clean_diff_until(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        db.common.throw_if_backpressure(&mut ctx)?;
+
+        let version = ctx.argument::<JsNumber>(0)?.value(&mut ctx).into();
+
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        db.clean_diff_until(version, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_get_root_at is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - height to get the committed root.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error. ERR_NOT_RETAINED if the root is removed with the diffs, and ERR_NOT_FOUND if the root is never recorded.
+    /// - @callback(1) - [u8]. Root committed at the height.
+    pub fn js_get_root_at(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let height = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as u32;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.common.arc_clone();
+        db.common
+            .send(move |channel| {
+                let result = root_history::get(conn.unwrap(), BlockHeight(height));
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(RootAt::Recorded(root)) => {
+                            let buffer = JsBuffer::external(&mut ctx, root);
+                            vec![ctx.null().upcast(), buffer.upcast()]
+                        },
+                        Ok(RootAt::Pruned) => {
+                            let err = DataStoreError::HeightNotRetained(height as usize);
+                            vec![DbError::from(err).to_js(&mut ctx)?.upcast()]
+                        },
+                        Ok(RootAt::NotRecorded) => {
+                            vec![DbError::NotFound.to_js(&mut ctx)?.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_get_latest_heights is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - maximum number of the heights to return.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { height: u32, root: [u8] }[] in descending order of the height.
+    pub fn js_get_latest_heights(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let limit = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as usize;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.common.arc_clone();
+        db.common
+            .send(move |channel| {
+                let result = root_history::latest(conn.unwrap(), limit);
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(heights) => {
+                            let arr = JsArray::new(&mut ctx, heights.len() as u32);
+                            for (i, (height, root)) in heights.into_iter().enumerate() {
+                                let obj = ctx.empty_object();
+                                let height = ctx.number(height);
+                                obj.set(&mut ctx, "height", height)?;
+                                let root = JsBuffer::external(&mut ctx, root);
+                                obj.set(&mut ctx, "root", root)?;
+                                arr.set(&mut ctx, i as u32, obj)?;
+                            }
+                            vec![ctx.null().upcast(), arr.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_pending_operations is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @returns - number of the operations queued on the DB thread, including the delayed commits and writes.
+    pub fn js_pending_operations(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let pending = db.borrow().common.pending_operations();
+
+        Ok(ctx.number(pending as f64))
+    }
+
+    /// js_check_diffs is handler for JS ffi.
+    /// It decodes all the stored diffs without reverting the state.
+    /// js "this" - StateDB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { checked: u64, corrupted: { height: u32, error: string }[] }.
+    pub fn js_check_diffs(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.common.arc_clone();
+        db.common
+            .send(move |channel| {
+                let result = check_diffs::check_diffs(conn.unwrap());
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(report) => {
+                            let obj = diffs_report_to_js_object(&mut ctx, &report)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_diff_stats is handler for JS ffi.
+    /// It decodes the diff of the height, resolving the values referenced from the old-value store.
+    /// js "this" - StateDB.
+    /// - @params(0) - height of the diff.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error. ERR_NOT_RETAINED if the diff is not stored, and ERR_CORRUPTED if it cannot be decoded.
+    /// - @callback(1) - { height: u32, encodedBytes: u64, created: SectionStats, updated: SectionStats, deleted: SectionStats }.
+    ///   SectionStats is { count: u64, keyBytes: u64, valueBytes: u64, referenced: u64 }.
+    pub fn js_diff_stats(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let height = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as u32;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.common.arc_clone();
+        let callback = db.common.watch_callback(callback);
+        db.common
+            .send(move |channel| {
+                let callback = match callback.start() {
+                    Some(callback) => callback,
+                    None => return,
+                };
+                let result = check_diffs::diff_stats(conn.unwrap(), height);
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(stats) => {
+                            let obj = diff_stats_to_js_object(&mut ctx, &stats)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_check_consistency is handler for JS ffi.
+    /// It checks that the root and the diff of the latest commit are stored, to diagnose the database after a crash.
+    /// js "this" - StateDB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { consistent: bool, height: u32, root: &[u8], rootInTree: bool, diffExists: bool, recordedRoot?: &[u8], pendingCommit: bool, issues: string[] }.
+    pub fn js_check_consistency(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        db.common
+            .send(move |channel| {
+                let result =
+                    check_consistency::check_consistency(conn.unwrap(), key_length, hasher);
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(report) => {
+                            let obj = consistency_report_to_js_object(&mut ctx, report)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_checkpoint is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - path to create the checkpoint.
+    /// - @params(1) - Options for checkpoint. {sync: bool, flushBeforeCheckpoint: bool}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error. If the root of the current state cannot be read from the checkpoint, it will call the callback with ERR_CORRUPTED code.
+    /// - @callback(1) - { size: u64, sstFiles: u64, root: &[u8] }.
+    pub fn js_checkpoint(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let option = ctx.argument_opt(1);
+        let option = CheckpointOption::new(&mut ctx, option)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        db.common
+            .checkpoint(path, option, Some(db.options.key_length()), None, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_checkpoint_with_group is handler for JS ffi.
+    /// The checkpoint is the state member of the group, and the manifest is written when both members finished.
+    /// js "this" - StateDB.
+    /// - @params(0) - token of the checkpoint group.
+    /// - @params(1) - path to create the checkpoint.
+    /// - @params(2) - Options for checkpoint. {sync: bool, flushBeforeCheckpoint: bool}.
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error. If the manifest cannot be written, it will call the callback with the error.
+    /// - @callback(1) - { size: u64, sstFiles: u64, root: &[u8] }.
+    pub fn js_checkpoint_with_group(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+
+        let token = checkpoint_group::get_token(&mut ctx, 0)?;
+        let path = ctx.argument::<JsString>(1)?.value(&mut ctx);
+        let option = ctx.argument_opt(2);
+        let option = CheckpointOption::new(&mut ctx, option)?;
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+
+        let key_length = db.options.key_length();
+        let membership = checkpoint_group::join(token, Member::State, &path, Some(key_length))
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+        db.common
+            .checkpoint(path, option, Some(key_length), Some(membership), callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_verify_checkpoint is handler for JS ffi.
+    /// It verifies the checkpoint created before without restoring it in a dedicated thread.
+    /// js "this" - StateDB.
+    /// - @params(0) - path of the checkpoint, opened as readonly.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error. If the root of the current state cannot be read from the checkpoint, it will call the callback with ERR_CORRUPTED code.
+    /// - @callback(1) - { size: u64, sstFiles: u64, root: &[u8] }.
+    pub fn js_verify_checkpoint(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let key_length = db.options.key_length();
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = DB::verify_checkpoint(&path, Some(key_length));
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(info) => {
+                        let obj = checkpoint_info_to_js_object(&mut ctx, &info)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_compact is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - start key of the range to compact within the state. Compacts from the beginning if null.
+    /// - @params(1) - end key of the range to compact within the state. Compacts until the end if null.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_compact(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let start = DbUtils::get_optional_bytes(&mut ctx, 0)?;
+        let end = DbUtils::get_optional_bytes(&mut ctx, 1)?;
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+
+        db.common
+            .compact(start, end, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// module_range returns the physical range of the state keys with the module prefix.
+    fn module_range(prefix: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let start = [consts::Prefix::STATE, prefix].concat();
+        let end = DbUtils::next_prefix(&start);
+        (start, end)
+    }
+
+    /// js_approximate_size is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - module prefix of the state keys. Empty prefix covers all the state.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { sstSize: number, memtableSize: number }.
+    pub fn js_approximate_size(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let prefix = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.common
+            .approximate_sizes(vec![Self::module_range(&prefix)], true, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_approximate_sizes is handler for JS ffi.
+    /// The sizes are estimated at once, so that the live files are read once for all the prefixes.
+    /// js "this" - StateDB.
+    /// - @params(0) - module prefixes of the state keys.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { sstSize: number, memtableSize: number }[] in the order of the prefixes.
+    pub fn js_approximate_sizes(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let prefixes = DbUtils::get_bytes_array(&mut ctx, 0)?;
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        let ranges = prefixes
+            .iter()
+            .map(|prefix| Self::module_range(prefix))
+            .collect();
+        db.common
+            .approximate_sizes(ranges, false, callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_flush is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_flush(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.common
+            .flush(callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_stats is handler for JS ffi.
+    /// js "this" - StateDB.
+    /// - @params(0) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { [name: string]: number }. Properties which are not available are omitted.
+    pub fn js_stats(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = ctx.argument::<JsFunction>(0)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+
+        db.common
+            .stats(callback)
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_profile_range is handler for JS ffi.
+    /// It scans the range on a snapshot in a dedicated thread, so it does not block the other operations.
+    /// js "this" - StateDB.
+    /// - @params(0) - Options for the scan. {gte: &[u8], lte: &[u8], maxRows: u64, progressInterval: u64, onProgress: (profile) => void}.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - profile of the scan.
+    pub fn js_profile_range(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let option_inputs = ctx.argument::<JsObject>(0)?;
+        let options = options::ProfileRangeOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let conn = conn.unwrap();
+            let snapshot = StateSnapshot::new(conn);
+            let result = profile::profile_range(&snapshot, &options, |progress| {
+                if let Some(on_progress) = on_progress.as_ref() {
+                    let on_progress = Arc::clone(on_progress);
+                    let progress = progress.clone();
+                    channel.send(move |mut ctx| {
+                        let obj = profile_to_js_object(&mut ctx, &progress)?;
+                        let on_progress = on_progress.to_inner(&mut ctx);
+                        let this = ctx.undefined();
+                        on_progress.call(&mut ctx, this, vec![obj.upcast::<JsValue>()])?;
+                        Ok(())
+                    });
+                }
+            });
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(val) => {
+                        let obj = profile_to_js_object(&mut ctx, &val)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_verify_nodes is handler for JS ffi.
+    /// It recomputes the hash of every stored SMT node in a dedicated thread.
+    /// js "this" - StateDB.
+    /// - @params(0) - path of the database to check, opened as readonly. The open database is checked if null.
+    /// - @params(1) - Options for the scan. {maxErrors: usize, progressInterval: u64, onProgress: (report) => void}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { checked: u64, mismatches: u64, mismatched: &[&[u8]], truncated: bool }.
+    pub fn js_verify_nodes(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = match ctx.argument_opt(0) {
+            Some(value) if value.is_a::<JsString, _>(&mut ctx) => Some(
+                value
+                    .downcast_or_throw::<JsString, _>(&mut ctx)?
+                    .value(&mut ctx),
+            ),
+            _ => None,
+        };
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::VerifyNodesOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let on_progress = |report: &NodesReport| {
+                if let Some(on_progress) = on_progress.as_ref() {
+                    let on_progress = Arc::clone(on_progress);
+                    let report = report.clone();
+                    channel.send(move |mut ctx| {
+                        let obj = nodes_report_to_js_object(&mut ctx, &report)?;
+                        let on_progress = on_progress.to_inner(&mut ctx);
+                        let this = ctx.undefined();
+                        on_progress.call(&mut ctx, this, vec![obj.upcast::<JsValue>()])?;
+                        Ok(())
+                    });
+                }
+            };
+            let result = match path {
+                Some(path) => column_families::open(&rocksdb::Options::default(), path, true)
+                    .and_then(|backup| {
+                        verify_nodes::verify_nodes(
+                            &backup,
+                            &options,
+                            key_length,
+                            hasher,
+                            on_progress,
+                        )
+                    }),
+                None => verify_nodes::verify_nodes(
+                    conn.unwrap(),
+                    &options,
+                    key_length,
+                    hasher,
+                    on_progress,
+                ),
+            };
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(val) => {
+                        let obj = nodes_report_to_js_object(&mut ctx, &val)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_check_integrity is handler for JS ffi.
+    /// It scans the state at the level in a dedicated thread, and the problems found are reported in the result.
+    /// js "this" - StateDB.
+    /// - @params(0) - level of the scan. "quick" checks the current state, the root node and the latest diff,
+    ///   and "full" additionally checks every node reachable from the root and the leaf of every state key.
+    /// - @params(1) - Options for the scan. {maxErrors: usize, progressInterval: u64, onProgress: (report) => void}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error. ERR_CANCELLED code if the scan is cancelled.
+    /// - @callback(1) - { ok: bool, level: string, height: u32, root: &[u8], checkedNodes: u64, checkedKeys: u64, errorCount: u64, errors: { message: string, key: &[u8] | null, height: u32 | null }[], truncated: bool }.
+    pub fn js_check_integrity(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let level = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let level = match IntegrityLevel::from_name(&level) {
+            Some(level) => level,
+            None => {
+                return error::invalid_argument("level must be \"quick\" or \"full\"")
+                    .throw(&mut ctx)
+            },
+        };
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::VerifyNodesOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let integrity_cancel = db.integrity_cancel.clone();
+        let check_id = integrity_cancel.start();
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let on_progress = |report: &IntegrityReport| {
+                if let Some(on_progress) = on_progress.as_ref() {
+                    let on_progress = Arc::clone(on_progress);
+                    let report = report.clone();
+                    channel.send(move |mut ctx| {
+                        let obj = integrity_report_to_js_object(&mut ctx, &report)?;
+                        let on_progress = on_progress.to_inner(&mut ctx);
+                        let this = ctx.undefined();
+                        on_progress.call(&mut ctx, this, vec![obj.upcast::<JsValue>()])?;
+                        Ok(())
+                    });
+                }
+            };
+            let result = check_integrity::check_integrity(
+                conn.unwrap(),
+                level,
+                key_length,
+                hasher,
+                &options,
+                on_progress,
+                || integrity_cancel.is_cancelled(check_id),
+            );
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(val) => {
+                        let obj = integrity_report_to_js_object(&mut ctx, &val)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![err.to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_check_integrity_cancel is handler for JS ffi.
+    /// It cancels the integrity scans started before the call.
+    /// js "this" - StateDB.
+    pub fn js_check_integrity_cancel(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        db.borrow().integrity_cancel.cancel();
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_prune_smt is handler for JS ffi.
+    /// It deletes the SMT nodes which are not reachable from the roots of the latest heights on the DB thread.
+    /// js "this" - StateDB.
+    /// - @params(0) - number of the latest heights whose roots are retained, including the current height.
+    /// - @params(1) - Options for the pruning. {batchSize: usize, onProgress: (report) => void}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { reachable: u64, scanned: u64, deleted: u64 }.
+    pub fn js_prune_smt(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let retain_heights = ctx.argument::<JsNumber>(0)?.value(&mut ctx);
+        if retain_heights < 1.0
+            || retain_heights.fract() != 0.0
+            || retain_heights > u32::MAX as f64
+        {
+            return error::invalid_argument("retainHeights must be a positive integer")
+                .throw(&mut ctx);
+        }
+        let retain_heights = retain_heights as u32;
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::PruneSMTOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let write_lock = Arc::clone(&db.write_lock);
+
+        db.common
+            .send(move |channel| {
+                let on_progress = |report: &PruneReport| {
+                    if let Some(on_progress) = on_progress.as_ref() {
+                        let on_progress = Arc::clone(on_progress);
+                        let report = report.clone();
+                        channel.send(move |mut ctx| {
+                            let obj = prune_report_to_js_object(&mut ctx, &report)?;
+                            let on_progress = on_progress.to_inner(&mut ctx);
+                            let this = ctx.undefined();
+                            on_progress.call(&mut ctx, this, vec![obj.upcast::<JsValue>()])?;
+                            Ok(())
+                        });
+                    }
+                };
+                let result = prune::prune_smt(
+                    conn.unwrap(),
+                    retain_heights,
+                    key_length,
+                    hasher,
+                    &options,
+                    &write_lock,
+                    on_progress,
+                );
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(val) => {
+                            let obj = prune_report_to_js_object(&mut ctx, &val)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_rebuild_smt is handler for JS ffi.
+    /// It rebuilds the tree of the state keys on the DB thread, and replaces all the SMT nodes with it.
+    /// The SMT nodes are not changed unless the root of the state keys matches the expected root.
+    /// It fails if a commit or a revert is written during the rebuild.
+    /// js "this" - StateDB.
+    /// - @params(0) - expected state root, such as the one in the block header.
+    /// - @params(1) - Options for the rebuild. {batchSize: usize, onProgress: (report) => void}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { keys: u64, inserted: u64 }.
+    pub fn js_rebuild_smt(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let expected = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::RebuildSMTOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let write_lock = Arc::clone(&db.write_lock);
+
+        db.common
+            .send_write(move |channel| {
+                let on_progress = |report: &RebuildReport| {
+                    if let Some(on_progress) = on_progress.as_ref() {
+                        let on_progress = Arc::clone(on_progress);
+                        let report = report.clone();
+                        channel.send(move |mut ctx| {
+                            let obj = rebuild_report_to_js_object(&mut ctx, &report)?;
+                            let on_progress = on_progress.to_inner(&mut ctx);
+                            let this = ctx.undefined();
+                            on_progress.call(&mut ctx, this, vec![obj.upcast::<JsValue>()])?;
+                            Ok(())
+                        });
+                    }
+                };
+                let result = rebuild_smt::rebuild_smt(
+                    conn.unwrap(),
+                    &expected,
+                    key_length,
+                    hasher,
+                    &options,
+                    &write_lock,
+                    on_progress,
+                );
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(val) => {
+                            let obj = rebuild_report_to_js_object(&mut ctx, &val)?;
+                            vec![ctx.null().upcast(), obj.upcast()]
+                        },
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_get_smt_node is handler for JS ffi.
+    /// It serves the SMT node to the syncing node from the snapshot of the database.
+    /// js "this" - StateDB.
+    /// - @params(0) - hash of the node.
+    /// - @params(1) - callback to return the node.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - encoded node, which is the subtree with the leaves and the hashes of the child subtrees.
+    ///   undefined if the node is not stored.
+    pub fn js_get_smt_node(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let node_hash = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+
+        db.common
+            .send(move |channel| {
+                let snapshot = StateSnapshot::new(conn.unwrap());
+                let result = smt_sync::get_node(&snapshot, &node_hash);
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(Some(data)) => {
+                            let buffer = JsBuffer::external(&mut ctx, data);
+                            vec![ctx.null().upcast(), buffer.upcast()]
+                        },
+                        Ok(None) => vec![ctx.null().upcast(), ctx.undefined().upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_get_smt_children is handler for JS ffi.
+    /// It serves the hashes of the child subtrees of the SMT node from the snapshot of the database.
+    /// js "this" - StateDB.
+    /// - @params(0) - hash of the node.
+    /// - @params(1) - callback to return the hashes.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - [&[u8]] of the child subtrees in the order of the keys, which is empty at the bottom of the tree.
+    ///   undefined if the node is not stored.
+    pub fn js_get_smt_children(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let node_hash = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+
+        db.common
+            .send(move |channel| {
+                let snapshot = StateSnapshot::new(conn.unwrap());
+                let result = smt_sync::get_children(&snapshot, &node_hash, key_length, hasher);
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(Some(children)) => {
+                            let arr = ctx.empty_array();
+                            for (i, child) in children.into_iter().enumerate() {
+                                let buffer = JsBuffer::external(&mut ctx, child);
+                                arr.set(&mut ctx, i as u32, buffer)?;
+                            }
+                            vec![ctx.null().upcast(), arr.upcast()]
+                        },
+                        Ok(None) => vec![ctx.null().upcast(), ctx.undefined().upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_put_smt_nodes is handler for JS ffi.
+    /// It installs the SMT nodes received by the syncing node in a batch. The database must be opened with unsafeSync.
+    /// js "this" - StateDB.
+    /// - @params(0) - nodes to install. { hash: &[u8], data: &[u8] }[]. Each node must hash to its hash.
+    /// - @params(1) - callback to return the result.
+    /// - @callback(0) - Error.
+    pub fn js_put_smt_nodes(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let raw_nodes = ctx.argument::<JsArray>(0)?.to_vec(&mut ctx)?;
+        let mut nodes = Vec::with_capacity(raw_nodes.len());
+        for node in raw_nodes.iter() {
+            let obj = node.downcast_or_throw::<JsObject, _>(&mut ctx)?;
+            let node_hash = obj
+                .get::<JsTypedArray<u8>, _, _>(&mut ctx, "hash")?
+                .as_slice(&ctx)
+                .to_vec();
+            let data = obj
+                .get::<JsTypedArray<u8>, _, _>(&mut ctx, "data")?
+                .as_slice(&ctx)
+                .to_vec();
+            nodes.push((node_hash, data));
+        }
+        let callback = ctx.argument::<JsFunction>(1)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        if !db.options.unsafe_sync {
+            return DbError::from(SmtSyncError::Disabled).throw(&mut ctx);
+        }
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let durability = db.common.durability();
+
+        db.common
+            .send_write(move |channel| {
+                let result = smt_sync::put_nodes(
+                    conn.unwrap(),
+                    &nodes,
+                    key_length,
+                    hasher,
+                    &durability.write_options(false),
+                );
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(()) => vec![ctx.null().upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_finalize_sync is handler for JS ffi.
+    /// It writes the current state of the synced root only if every node reachable from it is installed.
+    /// The database must be opened with unsafeSync.
+    /// js "this" - StateDB.
+    /// - @params(0) - expected state root, such as the one in the block header.
+    /// - @params(1) - height of the state.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error. ERR_INVALID_ROOT code if the tree of the root is incomplete.
+    pub fn js_finalize_sync(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let expected = ctx.argument::<JsTypedArray<u8>>(0)?.as_slice(&ctx).to_vec();
+        let height: BlockHeight = ctx.argument::<JsNumber>(1)?.value(&mut ctx).into();
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        if !db.options.unsafe_sync {
+            return DbError::from(SmtSyncError::Disabled).throw(&mut ctx);
+        }
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let write_lock = Arc::clone(&db.write_lock);
+        let durability = db.common.durability();
+
+        db.common
+            .send_write(move |channel| {
+                let result = smt_sync::finalize_sync(
+                    conn.unwrap(),
+                    &expected,
+                    height,
+                    key_length,
+                    hasher,
+                    &write_lock,
+                    &durability.write_options(false),
+                );
+
+                channel.send(move |mut ctx| {
+                    let callback = callback.into_inner(&mut ctx);
+                    let this = ctx.undefined();
+                    let args: Vec<Handle<JsValue>> = match result {
+                        Ok(()) => vec![ctx.null().upcast()],
+                        Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                    };
+                    callback.call(&mut ctx, this, args)?;
+
+                    Ok(())
+                });
+            })
+            .or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_export_snapshot is handler for JS ffi.
+    /// It writes the state records of the current height to the file in a dedicated thread.
+    /// js "this" - StateDB.
+    /// - @params(0) - path of the snapshot file.
+    /// - @params(1) - height of the state, which must be the current height.
+    /// - @params(2) - Options for the export. {progressInterval: u64, onProgress: (records) => void, sync: bool}.
+    /// - @params(3) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { records: u64, height: u32, root: &[u8] }.
+    pub fn js_export_snapshot(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let height: BlockHeight = ctx.argument::<JsNumber>(1)?.value(&mut ctx).into();
+        let option_inputs = ctx.argument::<JsObject>(2)?;
+        let options = options::StateSnapshotOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(3)?.root(&mut ctx);
+
+        let db = ctx.this().downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let hasher = db.options.hasher;
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = snapshot::export(
+                conn.unwrap(),
+                Path::new(&path),
+                height,
+                hasher,
+                options.progress_interval,
+                options.sync,
+                |records| send_records_progress(&channel, on_progress.as_ref(), records),
+            );
+
+            channel.send(move |mut ctx| {
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(footer) => {
+                        let obj = snapshot_footer_to_js_object(&mut ctx, &footer)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_import_snapshot is handler for JS ffi.
+    /// It restores the state from the snapshot file into the empty database in a dedicated thread.
+    /// Nothing is left in the state if the root recomputed from the records does not match the snapshot.
+    /// js "this" - StateDB.
+    /// - @params(0) - path of the snapshot file.
+    /// - @params(1) - Options for the import. {progressInterval: u64, onProgress: (records) => void}.
+    /// - @params(2) - callback to return the result.
+    /// - @callback(0) - Error.
+    /// - @callback(1) - { records: u64, height: u32, root: &[u8] }.
+    pub fn js_import_snapshot(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = ctx.argument::<JsString>(0)?.value(&mut ctx);
+        let option_inputs = ctx.argument::<JsObject>(1)?;
+        let options = options::StateSnapshotOption::new(&mut ctx, option_inputs)?;
+        let on_progress = option_inputs
+            .get_opt::<JsFunction, _, _>(&mut ctx, "onProgress")?
+            .map(|f| Arc::new(f.root(&mut ctx)));
+        let callback = ctx.argument::<JsFunction>(2)?.root(&mut ctx);
+
+        let db_handle = ctx.this();
+        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+        let db = db.borrow();
+        db.throw_if_closed(&mut ctx)?;
+        db.throw_if_readonly(&mut ctx)?;
+        let conn = db.common.arc_clone();
+        let key_length = db.options.key_length();
+        let hasher = db.options.hasher;
+        let db_handle = db_handle.root(&mut ctx);
+        let channel = ctx.channel();
+
+        thread::spawn(move || {
+            let result = snapshot::import(
+                conn.unwrap(),
+                Path::new(&path),
+                key_length,
+                hasher,
+                options.progress_interval,
+                |records| send_records_progress(&channel, on_progress.as_ref(), records),
+            );
+
+            channel.send(move |mut ctx| {
+                let db_handle = db_handle.into_inner(&mut ctx);
+                let callback = callback.into_inner(&mut ctx);
+                let this = ctx.undefined();
+                let args: Vec<Handle<JsValue>> = match result {
+                    Ok(footer) => {
+                        // the state is replaced, so the filter is rebuilt on next use
+                        let db = db_handle.downcast_or_throw::<SharedStateDB, _>(&mut ctx)?;
+                        db.borrow_mut().invalidate_lookups();
+                        let obj = snapshot_footer_to_js_object(&mut ctx, &footer)?;
+                        vec![ctx.null().upcast(), obj.upcast()]
+                    },
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
+                };
+                callback.call(&mut ctx, this, args)?;
+
+                Ok(())
+            });
+        });
+
+        Ok(ctx.undefined())
+    }
+
+    /// js_calculate_root is handler for JS ffi.
     /// js "this" - StateDB.
     /// - @params(0) - proof { siblingHashes: &[&[u8]]; queries: { key: &[u8]; value: &[u8]; bitmap: &[u8]; }[]; }
     /// - @params(1) - callback to return the result.
@@ -822,7 +3514,7 @@ impl StateDB {
                             JsBuffer::external(&mut ctx, val).upcast(),
                         ]
                     },
-                    Err(err) => vec![ctx.error(err.to_string())?.upcast()],
+                    Err(err) => vec![DbError::from(err).to_js(&mut ctx)?.upcast()],
                 };
                 callback.call(&mut ctx, this, args)?;
 
@@ -836,37 +3528,1496 @@ impl StateDB {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Mutex, RwLock};
+
+    use rocksdb::WriteOptions;
+    use tempdir::TempDir;
+
     use super::*;
-    use crate::types::BlockHeight;
+    use crate::batch::BatchKind;
+    use crate::consts::Prefix;
+    use crate::database::chunked_write;
+    use crate::database::options::IterationOption;
+    use crate::database::reader_writer::read_writer_db;
+    use crate::database::traits::{Actions, NewDBWithKeyLength};
+    use crate::diff;
+    use crate::sparse_merkle_tree::smt::EMPTY_HASH;
+    use crate::types::{Cache, KeyLength, SharedKVPair, VecOption};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    fn temp_db() -> (DB, TempDir) {
+        let temp_dir = TempDir::new("test_smt_apply_diff").unwrap();
+        let rocks_db = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let (tx, _) = mpsc::channel::<DbMessage>();
+        (DB::new(rocks_db, tx, Kind::State), temp_dir)
+    }
+
+    fn state_key(i: u8) -> Vec<u8> {
+        [vec![0, 0, 0, 1, 0, 0], vec![i; 10]].concat()
+    }
+
+    fn stored<'a>(
+        snapshot: &'a StateSnapshot<'a>,
+    ) -> impl Fn(&[u8]) -> Result<Option<Vec<u8>>, rocksdb::Error> + 'a {
+        move |key| snapshot.get(&Kind::State.key(key.to_vec()))
+    }
+
+    fn hashed(data: &[(Vec<u8>, Option<Vec<u8>>)]) -> smt::UpdateData {
+        let mut cache = Cache::new();
+        for (key, value) in data {
+            let value = value
+                .as_ref()
+                .map_or(vec![], |v| v.hash_with_kind(HashKind::Value));
+            cache.insert(key.hash_with_kind(HashKind::Key), value);
+        }
+        smt::UpdateData::new_from(cache)
+    }
+
+    fn commit(db: &mut impl Actions, root: &[u8], data: &smt::UpdateData) -> Vec<u8> {
+        let mut tree = smt::SparseMerkleTree::new(root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let root = tree.commit(db, data).unwrap();
+        let result = (**root.lock().unwrap()).clone();
+        result
+    }
+
+    fn commit_to_db(conn: &DB, root: &[u8], data: &smt::UpdateData) -> Vec<u8> {
+        let mut smt_db = smt_db::SmtDB::new(conn);
+        let next_root = commit(&mut smt_db, root, data);
+        let mut write_batch = batch::PrefixWriteBatch::new();
+        write_batch.set_prefix(&consts::Prefix::SMT);
+        smt_db.batch.iterate(&mut write_batch);
+        conn.write(write_batch.batch).unwrap();
+        next_root
+    }
+
+    fn commit_writer(
+        conn: &DB,
+        writer: &RwLock<state_writer::StateWriter>,
+        prev_root: &[u8],
+        version: BlockHeight,
+    ) -> Vec<u8> {
+        commit_writer_keeping_diff(conn, writer, prev_root, version, None)
+    }
+
+    fn commit_writer_keeping_diff(
+        conn: &DB,
+        writer: &RwLock<state_writer::StateWriter>,
+        prev_root: &[u8],
+        version: BlockHeight,
+        keep_diff_for_heights: Option<u32>,
+    ) -> Vec<u8> {
+        let commit = Commit::new(vec![], CommitOptions::new(false, version), false);
+        try_commit_writer(conn, writer, prev_root, commit, keep_diff_for_heights).unwrap()
+    }
+
+    fn try_commit_writer(
+        conn: &DB,
+        writer: &RwLock<state_writer::StateWriter>,
+        prev_root: &[u8],
+        commit: Commit,
+        keep_diff_for_heights: Option<u32>,
+    ) -> Result<Vec<u8>, SMTError> {
+        let w = writer.read().unwrap();
+        let data = smt::UpdateData::new_from(w.get_hashed_updated().unwrap());
+        let mut smt_db = smt_db::SmtDB::new(conn);
+        let mut tree = smt::SparseMerkleTree::new(prev_root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let root = tree.commit(&mut smt_db, &data);
+        let info = CommitResultInfo::new(root, commit);
+        let mut db_options = DbOptions::new(false, KEY_LENGTH);
+        db_options.keep_diff_for_heights = keep_diff_for_heights;
+        let (root, _) =
+            StateDB::handle_commit_result(conn, conn.durability(), &smt_db, w, info, &db_options)?;
+        let result = (**root.lock().unwrap()).clone();
+        Ok(result)
+    }
+
+    fn stored_nodes(conn: &DB) -> smt_db::InMemorySmtDB {
+        let mut nodes = smt_db::InMemorySmtDB::default();
+        let mode = rocksdb::IteratorMode::From(consts::Prefix::SMT, rocksdb::Direction::Forward);
+        for key_val in conn.iterator(mode) {
+            let (key, value) = key_val.unwrap();
+            if !key.starts_with(consts::Prefix::SMT) {
+                break;
+            }
+            nodes
+                .set(&KVPair::new(&key[consts::Prefix::SMT.len()..], &value))
+                .unwrap();
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_smt_apply_diff_matches_revert() {
+        let initial: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..20).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        // update 0..5, delete 5..10 and create 20..25
+        let forward: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..5)
+            .map(|i| (state_key(i), Some(vec![i + 100; 3])))
+            .chain((5..10).map(|i| (state_key(i), None)))
+            .chain((20..25).map(|i| (state_key(i), Some(vec![i; 7]))))
+            .collect();
+        let diff = diff::Diff::new(
+            (20..25).map(state_key).collect(),
+            (0..5)
+                .map(|i| KVPair::new(&state_key(i), &[i; 5]))
+                .collect(),
+            (5..10)
+                .map(|i| KVPair::new(&state_key(i), &[i; 5]))
+                .collect(),
+        );
+
+        // apply the diff on in memory tree
+        let mut in_memory = smt_db::InMemorySmtDB::default();
+        let initial_root = commit(&mut in_memory, &[], &hashed(&initial));
+        let next_root = commit(&mut in_memory, &initial_root, &hashed(&forward));
+        assert_ne!(initial_root, next_root);
+        let reverted = smt_apply_diff(
+            &mut in_memory,
+            &next_root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            &diff,
+        )
+        .unwrap();
+        assert_eq!(**reverted.lock().unwrap(), initial_root);
+
+        // revert the same commits on the physical storage
+        let (conn, _temp_dir) = temp_db();
+        let version = BlockHeight(1);
+        assert_eq!(commit_to_db(&conn, &[], &hashed(&initial)), initial_root);
+        assert_eq!(
+            commit_to_db(&conn, &initial_root, &hashed(&forward)),
+            next_root
+        );
+        conn.put(
+            &[consts::Prefix::DIFF, &version.to_be_bytes()].concat(),
+            &diff.encode(),
+        )
+        .unwrap();
+        let reverted = store::revert_diff(
+            &conn,
+            version,
+            &next_root,
+            &initial_root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(**reverted.lock().unwrap(), initial_root);
+
+        assert_eq!(stored_nodes(&conn), in_memory);
+    }
+
+    #[test]
+    fn test_verify_checkpoint() {
+        let data: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..10).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        let (conn, _temp_dir) = temp_db();
+        let temp_dir = TempDir::new("test_verify_checkpoint").unwrap();
+        let checkpoint = |name: &str| {
+            let path = temp_dir.path().join(name);
+            let path = path.to_str().unwrap().to_string();
+            DB::create_checkpoint(&conn, &path, &CheckpointOption::default()).unwrap();
+            DB::verify_checkpoint(&path, Some(KEY_LENGTH))
+        };
+
+        // empty state does not have the root node
+        let info = checkpoint("empty").unwrap();
+        assert_eq!(info.root, Some(EMPTY_HASH.to_vec()));
+
+        let root = commit_to_db(&conn, &[], &hashed(&data));
+        CurrentState::new(&root, BlockHeight(1)).put(&conn).unwrap();
+        let info = checkpoint("committed").unwrap();
+        assert_eq!(info.root, Some(root.clone()));
+        assert!(info.size > 0);
+
+        // checkpoint without the root node is rejected
+        conn.delete(&[consts::Prefix::SMT, &root].concat()).unwrap();
+        let err = checkpoint("missing_root").unwrap_err();
+        assert_eq!(DbError::from(err).code(), "ERR_CORRUPTED");
+    }
+
+    #[test]
+    fn test_revert_v0_diff() {
+        let initial: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..10).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        // update 0..3, delete 3..5 and create 20..22
+        let forward: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..3)
+            .map(|i| (state_key(i), Some(vec![i + 100; 3])))
+            .chain((3..5).map(|i| (state_key(i), None)))
+            .chain((20..22).map(|i| (state_key(i), Some(vec![i; 7]))))
+            .collect();
+        let diff = diff::Diff::new(
+            (20..22).map(state_key).collect(),
+            (0..3)
+                .map(|i| KVPair::new(&state_key(i), &[i; 5]))
+                .collect(),
+            (3..5)
+                .map(|i| KVPair::new(&state_key(i), &[i; 5]))
+                .collect(),
+        );
+
+        // diff written by the release without the version header
+        let (conn, _temp_dir) = temp_db();
+        let version = BlockHeight(1);
+        let initial_root = commit_to_db(&conn, &[], &hashed(&initial));
+        let next_root = commit_to_db(&conn, &initial_root, &hashed(&forward));
+        let v0 = diff.encode_with_version(diff::DiffVersion::V0);
+        assert_ne!(v0, diff.encode());
+        conn.put(
+            &[consts::Prefix::DIFF, &version.to_be_bytes()].concat(),
+            &v0,
+        )
+        .unwrap();
+        let reverted = store::revert_diff(
+            &conn,
+            version,
+            &next_root,
+            &initial_root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(**reverted.lock().unwrap(), initial_root);
+        for i in 0..5 {
+            assert_eq!(
+                conn.get(&Kind::State.key(state_key(i))).unwrap(),
+                Some(vec![i; 5])
+            );
+        }
+        for i in 20..22 {
+            assert_eq!(conn.get(&Kind::State.key(state_key(i))).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_revert_with_tampered_diff() {
+        let initial: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..10).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        let forward: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..3)
+            .map(|i| (state_key(i), Some(vec![i + 100; 3])))
+            .collect();
+        // original value of the key 0 is tampered
+        let tampered = diff::Diff::new(
+            vec![],
+            (0..3)
+                .map(|i| KVPair::new(&state_key(i), &[i + 1; 5]))
+                .collect(),
+            vec![],
+        );
+
+        let (conn, _temp_dir) = temp_db();
+        let version = BlockHeight(1);
+        let initial_root = commit_to_db(&conn, &[], &hashed(&initial));
+        let next_root = commit_to_db(&conn, &initial_root, &hashed(&forward));
+        for (key, value) in forward.iter() {
+            conn.put(&Kind::State.key(key.clone()), value.as_ref().unwrap())
+                .unwrap();
+        }
+        let diff_key = [consts::Prefix::DIFF, &version.to_be_bytes()].concat();
+        conn.put(&diff_key, &tampered.encode()).unwrap();
+        let nodes_before = stored_nodes(&conn);
+
+        let result = store::revert_diff(
+            &conn,
+            version,
+            &next_root,
+            &initial_root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        );
+        assert!(matches!(result, Err(DataStoreError::InvalidRoot(_))));
+
+        // nothing is written
+        assert_eq!(stored_nodes(&conn), nodes_before);
+        assert_eq!(conn.get(&diff_key).unwrap(), Some(tampered.encode()));
+        for (key, value) in forward.iter() {
+            assert_eq!(
+                conn.get(&Kind::State.key(key.clone())).unwrap(),
+                value.clone()
+            );
+        }
+
+        // without expected root, the tampered diff is applied
+        let result = store::revert_diff(
+            &conn,
+            version,
+            &next_root,
+            &[],
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        );
+        assert_ne!(**result.unwrap().lock().unwrap(), initial_root);
+        assert_eq!(conn.get(&diff_key).unwrap(), None);
+    }
 
     #[test]
-    fn test_current_state_convert() {
-        let block_zero = BlockHeight(0);
-        let block_ten = BlockHeight(10);
-        let block_hundred = BlockHeight(100);
-        let root = Vec::with_capacity(30);
-
-        let test_data = vec![
-            (
-                CurrentState::new(&[], block_zero),
-                [vec![], block_zero.to_be_bytes().to_vec()].concat(),
+    fn test_revert_with_corrupted_diff() {
+        let (conn, _temp_dir) = temp_db();
+        let version = BlockHeight(7);
+        let diff_key = [consts::Prefix::DIFF, &version.to_be_bytes()].concat();
+        // updated pair with the length longer than the bytes
+        conn.put(&diff_key, &[0x12, 0x03, 0x0a, 0x09, 0x01])
+            .unwrap();
+
+        let result = store::revert_diff(
+            &conn,
+            version,
+            &EMPTY_HASH,
+            &[],
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        );
+        match result {
+            Err(DataStoreError::DiffCorrupted(message)) => assert_eq!(
+                message,
+                "diff at height 7 is corrupted: Invalid bytes length (bytes: 12030a0901)"
             ),
-            (
-                CurrentState::new(&[1, 2, 3, 4], block_ten),
-                [vec![1, 2, 3, 4], block_ten.to_be_bytes().to_vec()].concat(),
+            _ => panic!("corrupted diff must fail"),
+        }
+        assert!(conn.get(&diff_key).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_smt_apply_diff_empty() {
+        let initial: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..3).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        let mut db = smt_db::InMemorySmtDB::default();
+        let root = commit(&mut db, &[], &hashed(&initial));
+
+        let diff = diff::Diff::new(vec![], vec![], vec![]);
+        let result =
+            smt_apply_diff(&mut db, &root, KEY_LENGTH, HasherKind::Sha256, &diff).unwrap();
+        assert_eq!(**result.lock().unwrap(), root);
+    }
+
+    #[test]
+    fn test_update_tree_in_chunks() {
+        let initial: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            (0..10).map(|i| (state_key(i), Some(vec![i; 5]))).collect();
+        // updates and deletions over the stored tree
+        let mut data = Cache::new();
+        for i in 5..20 {
+            // deleted key has the empty value
+            let value = if i < 8 {
+                vec![]
+            } else {
+                vec![i + 1; 5].hash_with_kind(HashKind::Value)
+            };
+            data.insert(state_key(i).hash_with_kind(HashKind::Key), value);
+        }
+
+        let update = |chunk_size| {
+            let (conn, temp_dir) = temp_db();
+            let initial_root = commit_to_db(&conn, &[], &hashed(&initial));
+            let mut tree =
+                smt::SparseMerkleTree::new(&initial_root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+            let mut progress = vec![];
+            let (root, smt_db) = update_tree_in_chunks(
+                &conn,
+                &mut tree,
+                data.clone(),
+                chunk_size,
+                || false,
+                |processed, total| progress.push((processed, total)),
+            )
+            .unwrap();
+            let mut write_batch = batch::PrefixWriteBatch::new();
+            write_batch.set_prefix(&consts::Prefix::SMT);
+            smt_db.batch.iterate(&mut write_batch);
+            conn.write(write_batch.batch).unwrap();
+            let root = (**root.lock().unwrap()).clone();
+            (root, stored_nodes(&conn), progress, temp_dir)
+        };
+        let (root, nodes, progress, _) = update(100);
+        assert_eq!(progress, vec![(15, 15)]);
+        let (chunked_root, chunked_nodes, progress, _) = update(4);
+        assert_eq!(progress, vec![(4, 15), (8, 15), (12, 15), (15, 15)]);
+        assert_eq!(chunked_root, root);
+        assert_eq!(chunked_nodes, nodes);
+
+        // cancelled update stops before the next chunk
+        let (conn, _temp_dir) = temp_db();
+        let mut tree = smt::SparseMerkleTree::new(&[], KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let chunks = std::cell::Cell::new(0);
+        let result = update_tree_in_chunks(
+            &conn,
+            &mut tree,
+            data,
+            4,
+            || chunks.get() == 2,
+            |_, _| chunks.set(chunks.get() + 1),
+        );
+        assert!(matches!(result, Err(DbError::Cancelled(_))));
+        assert_eq!(chunks.get(), 2);
+    }
+
+    #[test]
+    fn test_revert_delete_range() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        for i in 0..10 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let initial_root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        let new_key = [state_key(4), vec![1]].concat();
+        {
+            let mut w = writer.write().unwrap();
+            // key 3 is updated before the range deletion, and new key is created in the range
+            read_writer_db::upsert(&mut w, &state_key(3), &[100; 3], Some(vec![3; 5])).unwrap();
+            read_writer_db::upsert(&mut w, &new_key, &[200; 3], None).unwrap();
+            read_writer_db::upsert(&mut w, &state_key(8), &[108; 3], Some(vec![8; 5])).unwrap();
+
+            let db = conn.arc_clone();
+            let snapshot = StateSnapshot::new(db.unwrap());
+            let stored =
+                read_writer_db::stored_keys_between(&snapshot, &state_key(2), &state_key(7))
+                    .unwrap();
+            assert_eq!(stored.len(), 5);
+            read_writer_db::delete_range_with_writer(
+                &mut w,
+                &stored,
+                &state_key(2),
+                &state_key(7),
+            )
+            .unwrap();
+
+            for i in 2..7 {
+                assert_eq!(
+                    read_writer_db::get_with_writer(&mut w, &state_key(i), None).unwrap(),
+                    None
+                );
+            }
+            assert_eq!(
+                read_writer_db::get_with_writer(&mut w, &new_key, None).unwrap(),
+                None
+            );
+            assert_eq!(
+                read_writer_db::get_with_writer(&mut w, &state_key(7), Some(vec![7; 5])).unwrap(),
+                Some(vec![7; 5])
+            );
+        }
+        let next_root = commit_writer(&conn, &writer, &initial_root, BlockHeight(2));
+        assert_ne!(next_root, initial_root);
+
+        for i in 2..7 {
+            assert_eq!(conn.get(&Kind::State.key(state_key(i))).unwrap(), None);
+        }
+        let diff_bytes = conn
+            .get(&[consts::Prefix::DIFF, &BlockHeight(2).to_be_bytes()].concat())
+            .unwrap()
+            .unwrap();
+        let diff = diff::Diff::decode(&diff_bytes).unwrap();
+        assert!(diff.created().is_empty());
+        let mut deleted = diff.deleted().to_vec();
+        deleted.sort_by(|a, b| a.key().cmp(b.key()));
+        let expected: Vec<KVPair> = (2..7)
+            .map(|i| KVPair::new(&state_key(i), &[i; 5]))
+            .collect();
+        assert_eq!(deleted, expected);
+
+        let reverted = store::revert_diff(
+            &conn,
+            BlockHeight(2),
+            &next_root,
+            &initial_root,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(**reverted.lock().unwrap(), initial_root);
+        for i in 0..10 {
+            assert_eq!(
+                conn.get(&Kind::State.key(state_key(i))).unwrap(),
+                Some(vec![i; 5])
+            );
+        }
+        assert_eq!(conn.get(&Kind::State.key(new_key)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_proof_values() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        for i in 0..5 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+
+        // the last key does not exist
+        let state_keys: NestedVec = vec![state_key(1), state_key(3), state_key(9)];
+        let query_keys: NestedVec = state_keys
+            .iter()
+            .map(|key| key.hash_with_kind(HashKind::Key))
+            .collect();
+        let mut tree = smt::SparseMerkleTree::new(&root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let proof = tree
+            .prove(&mut smt_db::SmtDB::new(&conn), &query_keys)
+            .unwrap();
+
+        let db = conn.arc_clone();
+        let snapshot = StateSnapshot::new(db.unwrap());
+        let values =
+            stored_proof_values(stored(&snapshot), &state_keys, &query_keys, &proof).unwrap();
+        assert_eq!(values, vec![Some(vec![1; 5]), Some(vec![3; 5]), None]);
+        assert!(check_proof_values(&query_keys, &proof, &values).is_ok());
+        assert!(check_proof_values(&query_keys, &proof, &[None, None, None]).is_ok());
+
+        let mut tampered = values.clone();
+        tampered[1] = Some(vec![4; 5]);
+        assert_eq!(
+            check_proof_values(&query_keys, &proof, &tampered),
+            Err(SMTError::InvalidValue(1))
+        );
+
+        // exclusion proof does not carry value
+        let mut exclusion = values;
+        exclusion[2] = Some(vec![]);
+        assert_eq!(
+            check_proof_values(&query_keys, &proof, &exclusion),
+            Err(SMTError::InvalidValue(2))
+        );
+    }
+
+    #[test]
+    fn test_upsert_with_previous() {
+        let mut writer = state_writer::StateWriter::default();
+        let upsert = |writer: &mut state_writer::StateWriter, i: u8, stored: VecOption| {
+            read_writer_db::upsert_with_previous(writer, &state_key(i), &[i; 3], stored, true)
+                .unwrap()
+        };
+
+        // new key
+        assert_eq!(
+            upsert(&mut writer, 1, None),
+            read_writer_db::Upserted::default()
+        );
+        // key existing only in the snapshot
+        let upserted = upsert(&mut writer, 2, Some(vec![2; 5]));
+        assert!(upserted.existed);
+        assert_eq!(upserted.previous_value, Some(vec![2; 5]));
+        // cached key uses the state in the writer instead of the stored value
+        let upserted = upsert(&mut writer, 2, Some(vec![2; 5]));
+        assert_eq!(upserted.previous_value, Some(vec![2; 3]));
+        let upserted = upsert(&mut writer, 1, None);
+        assert!(upserted.existed);
+        assert_eq!(upserted.previous_value, Some(vec![1; 3]));
+
+        // key deleted in the writer
+        read_writer_db::delete_with_writer(&mut writer, &state_key(3), Some(vec![3; 5])).unwrap();
+        assert_eq!(
+            upsert(&mut writer, 3, Some(vec![3; 5])),
+            read_writer_db::Upserted::default()
+        );
+
+        // previous value is not copied unless requested
+        let upserted =
+            read_writer_db::upsert_with_previous(&mut writer, &state_key(3), &[0], None, false)
+                .unwrap();
+        assert!(upserted.existed);
+        assert_eq!(upserted.previous_value, None);
+    }
+
+    #[test]
+    fn test_writer_consumed_by_commit() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(1), &[1; 5], None)
+            .unwrap();
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+        assert!(writer.read().unwrap().is_consumed());
+        assert!(matches!(
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(2), &[2; 5], None),
+            Err(state_writer::StateWriterError::Consumed)
+        ));
+
+        // the reset writer is reused for the next height without the changes of the previous one
+        writer.write().unwrap().reset();
+        read_writer_db::upsert(
+            &mut writer.write().unwrap(),
+            &state_key(1),
+            &[11; 5],
+            Some(vec![1; 5]),
+        )
+        .unwrap();
+        read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(2), &[2; 5], None)
+            .unwrap();
+        commit_writer(&conn, &writer, &root, BlockHeight(2));
+        let diff_bytes = conn
+            .get(&[consts::Prefix::DIFF, &BlockHeight(2).to_be_bytes()].concat())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            diff::Diff::decode(&diff_bytes).unwrap(),
+            diff::Diff::new(
+                vec![state_key(2)],
+                vec![KVPair::new(&state_key(1), &[1; 5])],
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_proof_values_with_stale_root() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(1), &[1; 5], None)
+            .unwrap();
+        let initial_root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        read_writer_db::upsert(
+            &mut writer.write().unwrap(),
+            &state_key(1),
+            &[2; 5],
+            Some(vec![1; 5]),
+        )
+        .unwrap();
+        commit_writer(&conn, &writer, &initial_root, BlockHeight(2));
+
+        // stored value is the latest, and it does not match the proof of the previous root
+        let state_keys: NestedVec = vec![state_key(1)];
+        let query_keys: NestedVec = vec![state_key(1).hash_with_kind(HashKind::Key)];
+        let mut tree =
+            smt::SparseMerkleTree::new(&initial_root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let proof = tree
+            .prove(&mut smt_db::SmtDB::new(&conn), &query_keys)
+            .unwrap();
+        let db = conn.arc_clone();
+        let snapshot = StateSnapshot::new(db.unwrap());
+        assert_eq!(
+            stored_proof_values(stored(&snapshot), &state_keys, &query_keys, &proof),
+            Err(SMTError::InvalidValue(0))
+        );
+    }
+
+    #[test]
+    fn test_prove_empty_value() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        for i in [1, 3] {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        // upsert rejects the empty value, so that it is cached directly as the data written before the check
+        writer
+            .write()
+            .unwrap()
+            .cache_new(&SharedKVPair::new(&state_key(2), &[]));
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+        // empty value is planted with the raw handle after the commit
+        conn.put(&Kind::State.key(state_key(3)), &[]).unwrap();
+
+        let state_keys: NestedVec = vec![state_key(1), state_key(2), state_key(3)];
+        let query_keys: NestedVec = state_keys
+            .iter()
+            .map(|key| key.hash_with_kind(HashKind::Key))
+            .collect();
+        let mut tree = smt::SparseMerkleTree::new(&root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        let mut smtdb = smt_db::SmtDB::new(&conn);
+        let err = tree.prove(&mut smtdb, &query_keys[..2]).unwrap_err();
+        assert_eq!(err, SMTError::EmptyValue(hex::encode(&query_keys[1])));
+        assert_eq!(
+            with_state_key(err, &state_keys, &query_keys),
+            SMTError::EmptyValue(hex::encode(state_key(2)))
+        );
+        // in memory tree with the same data has the same rule
+        let mut nodes = stored_nodes(&conn);
+        assert_eq!(
+            tree.prove(&mut nodes, &query_keys[1..2]).unwrap_err(),
+            SMTError::EmptyValue(hex::encode(&query_keys[1]))
+        );
+
+        // the leaf of the key planted after the commit is not empty, but the stored value is
+        let keys = [query_keys[0].clone(), query_keys[2].clone()];
+        let proof = tree.prove(&mut smtdb, &keys).unwrap();
+        let db = conn.arc_clone();
+        let snapshot = StateSnapshot::new(db.unwrap());
+        assert_eq!(
+            stored_proof_values(
+                stored(&snapshot),
+                &[state_key(1), state_key(3)],
+                &keys,
+                &proof
             ),
-            (
-                CurrentState::new(&root, block_hundred),
-                [root.clone(), block_hundred.to_be_bytes().to_vec()].concat(),
+            Err(SMTError::EmptyValue(hex::encode(state_key(3))))
+        );
+
+        // proof of the empty value is rejected by verify instead of being an exclusion proof
+        let mut forged = proof;
+        forged.queries[0].pair = Arc::new(KVPair::new(&query_keys[0], &EMPTY_HASH));
+        assert_eq!(
+            smt::SparseMerkleTree::verify(&keys, &forged, &root, KEY_LENGTH),
+            Err(SMTError::EmptyValue(hex::encode(&query_keys[0])))
+        );
+        assert_eq!(
+            check_proof_values(&keys, &forged, &[Some(vec![]), None]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_prove_on_readonly_reopen() {
+        let (conn, temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        for i in 0..5 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+
+        // readonly instance is opened while the writable instance is still open
+        let rocks_db =
+            rocksdb::DB::open_for_read_only(&rocksdb::Options::default(), &temp_dir, false)
+                .unwrap();
+        let (tx, _) = mpsc::channel::<DbMessage>();
+        let db = StateDB {
+            common: DB::new(rocks_db, tx, Kind::State),
+            options: DbOptions::new(true, KEY_LENGTH),
+            bloom: None,
+            negative_cache: None,
+            write_lock: Arc::new(Mutex::new(())),
+            commit_cancel: OperationCancel::default(),
+            integrity_cancel: OperationCancel::default(),
+            reader_pool: Mutex::new(None),
+            bulk_load: Arc::new(AtomicBool::new(false)),
+            readers: Arc::new(OpenReaders::new()),
+        };
+        assert!(matches!(db.check_writable(), Err(DataStoreError::Readonly)));
+
+        let state_keys: NestedVec = vec![state_key(1), state_key(9)];
+        let (proof, values) = prove_with_values(
+            &db.common,
+            &root,
+            &state_keys,
+            None,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+        )
+        .unwrap();
+        assert_eq!(values, vec![Some(vec![1; 5]), None]);
+        let query_keys: NestedVec = state_keys
+            .iter()
+            .map(|key| key.hash_with_kind(HashKind::Key))
+            .collect();
+        assert_eq!(
+            smt::SparseMerkleTree::verify(&query_keys, &proof, &root, KEY_LENGTH),
+            Ok(true)
+        );
+        assert!(db
+            .common
+            .put(&Kind::State.key(state_key(1)), &[2; 5])
+            .is_err());
+    }
+
+    #[test]
+    fn test_peek_with_writer() {
+        let mut writer = state_writer::StateWriter::default();
+        let stored = Some(vec![1; 5]);
+        // stored value is returned without caching
+        assert_eq!(
+            read_writer_db::peek_with_writer(&writer, &state_key(1), stored.clone()).unwrap(),
+            stored
+        );
+        assert!(!writer.is_cached(&state_key(1)).unwrap());
+        assert_eq!(
+            read_writer_db::peek_with_writer(&writer, &state_key(1), None).unwrap(),
+            None
+        );
+
+        // cached view is returned instead of the stale stored value
+        read_writer_db::upsert(&mut writer, &state_key(1), &[11; 5], stored.clone()).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(2), &[2; 5], None).unwrap();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(3), Some(vec![3; 5])).unwrap();
+        assert_eq!(
+            read_writer_db::peek_with_writer(&writer, &state_key(1), stored).unwrap(),
+            Some(vec![11; 5])
+        );
+        assert_eq!(
+            read_writer_db::peek_with_writer(&writer, &state_key(2), None).unwrap(),
+            Some(vec![2; 5])
+        );
+        assert_eq!(
+            read_writer_db::peek_with_writer(&writer, &state_key(3), Some(vec![3; 5])).unwrap(),
+            None
+        );
+        assert_eq!(writer.cache.len(), 3);
+
+        // get caches the stored value
+        assert_eq!(
+            read_writer_db::get_with_writer(&mut writer, &state_key(4), Some(vec![4; 5])).unwrap(),
+            Some(vec![4; 5])
+        );
+        assert!(writer.is_cached(&state_key(4)).unwrap());
+        assert_eq!(
+            read_writer_db::get_with_writer(&mut writer, &state_key(3), Some(vec![3; 5])).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_many_with_writer() {
+        let temp_dir = TempDir::new("test_get_many_with_writer").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 1..=3 {
+            conn.put(Kind::State.key(state_key(i)), [i; 5]).unwrap();
+        }
+        let keys: Vec<Vec<u8>> = [2, 4, 1, 3].iter().map(|i| state_key(*i)).collect();
+        let stored = read_writer_db::stored_values(&StateSnapshot::new(&conn), &keys).unwrap();
+        assert_eq!(
+            stored,
+            vec![Some(vec![2; 5]), None, Some(vec![1; 5]), Some(vec![3; 5])]
+        );
+
+        // deleted key is not found even if stored, and the rest are kept in order
+        let mut writer = state_writer::StateWriter::default();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(3), Some(vec![3; 5])).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(4), &[4; 5], None).unwrap();
+        let values = read_writer_db::get_many_with_writer(&mut writer, &keys, stored).unwrap();
+        assert_eq!(
+            values,
+            vec![Some(vec![2; 5]), Some(vec![4; 5]), Some(vec![1; 5]), None]
+        );
+        // stored values are cached like get
+        assert!(writer.is_cached(&state_key(1)).unwrap());
+        assert!(writer.is_cached(&state_key(2)).unwrap());
+    }
+
+    #[test]
+    fn test_get_with_locked_writer() {
+        let writer = Arc::new(RwLock::new(state_writer::StateWriter::default()));
+        // stored value is cached, and the cached view is returned afterwards
+        assert_eq!(
+            read_writer_db::get_with_locked_writer(&writer, &state_key(1), Some(vec![1; 5]))
+                .unwrap(),
+            Some(vec![1; 5])
+        );
+        assert!(writer.read().unwrap().is_cached(&state_key(1)).unwrap());
+        assert_eq!(
+            read_writer_db::get_with_locked_writer(&writer, &state_key(2), None).unwrap(),
+            None
+        );
+        assert!(!writer.read().unwrap().is_cached(&state_key(2)).unwrap());
+        read_writer_db::delete_with_writer(&mut writer.write().unwrap(), &state_key(1), None)
+            .unwrap();
+        assert_eq!(
+            read_writer_db::get_with_locked_writer(&writer, &state_key(1), Some(vec![1; 5]))
+                .unwrap(),
+            None
+        );
+
+        // readers of the cached keys run while the writer updates and restores the other keys
+        for i in 10..20 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let readers: Vec<thread::JoinHandle<()>> = (0..8)
+            .map(|_| {
+                let writer = Arc::clone(&writer);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let keys: Vec<Vec<u8>> = (10..20).map(state_key).collect();
+                        let values = read_writer_db::get_many_with_locked_writer(
+                            &writer,
+                            &keys,
+                            vec![None; keys.len()],
+                        )
+                        .unwrap();
+                        let expected: Vec<VecOption> =
+                            (10..20).map(|i| Some(vec![i; 5])).collect();
+                        assert_eq!(values, expected);
+                    }
+                })
+            })
+            .collect();
+        for i in 0..100 {
+            let mut w = writer.write().unwrap();
+            let index = w.snapshot();
+            read_writer_db::upsert(&mut w, &state_key(30), &[i; 5], None).unwrap();
+            w.restore_snapshot(index).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(!writer.read().unwrap().is_cached(&state_key(30)).unwrap());
+    }
+
+    #[test]
+    fn test_count_range_with_writer() {
+        let temp_dir = TempDir::new("test_count_range_with_writer").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 1..=5 {
+            conn.put(Kind::State.key(state_key(i)), [i; 5]).unwrap();
+        }
+        // key out of the state prefix is not counted
+        conn.put([Prefix::SMT, &state_key(3)].concat(), [3])
+            .unwrap();
+        let options = |limit: i64| IterationOption {
+            limit,
+            reverse: false,
+            gte: Some(state_key(0)),
+            lte: Some(state_key(255)),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+
+        let mut writer = state_writer::StateWriter::default();
+        let count = |writer: &state_writer::StateWriter, limit| {
+            read_writer_db::count_range_with_writer(
+                &StateSnapshot::new(&conn),
+                writer,
+                &options(limit),
+            )
+            .unwrap()
+        };
+        assert_eq!(count(&writer, -1), 5);
+
+        // created keys are counted, and deleted keys are not even if stored
+        read_writer_db::upsert(&mut writer, &state_key(1), &[11; 5], Some(vec![1; 5])).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(6), &[6; 5], None).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(7), &[7; 5], None).unwrap();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(2), Some(vec![2; 5])).unwrap();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(7), None).unwrap();
+        assert_eq!(count(&writer, -1), 5);
+        assert_eq!(count(&writer, 3), 3);
+        assert_eq!(count(&writer, 1), 1);
+        assert_eq!(count(&writer, 0), 0);
+        // counting does not cache the stored keys
+        assert!(!writer.is_cached(&state_key(3)).unwrap());
+
+        let mut writer = state_writer::StateWriter::default();
+        for i in 1..=5 {
+            read_writer_db::delete_with_writer(&mut writer, &state_key(i), Some(vec![i; 5]))
+                .unwrap();
+        }
+        assert_eq!(count(&writer, 1), 0);
+    }
+
+    #[test]
+    fn test_exists_prefix_with_writer() {
+        let temp_dir = TempDir::new("test_exists_prefix_with_writer").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        let module_key = |module: u8, i: u8| [vec![0, 0, 0, module, 0, 0], vec![i; 10]].concat();
+        for i in 1..=2 {
+            conn.put(Kind::State.key(module_key(1, i)), [i; 5]).unwrap();
+        }
+        let exists = |writer: &state_writer::StateWriter, module: u8| {
+            read_writer_db::exists_prefix_with_writer(
+                &StateSnapshot::new(&conn),
+                writer,
+                &[0, 0, 0, module, 0, 0],
+                None,
+            )
+            .unwrap()
+        };
+
+        let mut writer = state_writer::StateWriter::default();
+        assert!(exists(&writer, 1));
+        assert!(!exists(&writer, 2));
+
+        // created key makes the prefix exist
+        read_writer_db::upsert(&mut writer, &module_key(2, 1), &[1; 5], None).unwrap();
+        assert!(exists(&writer, 2));
+
+        // the prefix exists while any stored key is not deleted
+        read_writer_db::delete_with_writer(&mut writer, &module_key(1, 1), Some(vec![1; 5]))
+            .unwrap();
+        assert!(exists(&writer, 1));
+        read_writer_db::delete_with_writer(&mut writer, &module_key(1, 2), Some(vec![2; 5]))
+            .unwrap();
+        assert!(!exists(&writer, 1));
+    }
+
+    #[test]
+    fn test_range_with_writer_keys_only() {
+        let temp_dir = TempDir::new("test_range_with_writer_keys_only").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 1..=3 {
+            conn.put(Kind::State.key(state_key(i)), [i; 5]).unwrap();
+        }
+        let options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: Some(state_key(0)),
+            lte: Some(state_key(255)),
+            prefix: None,
+            keys_only: true,
+            inventory: false,
+            min_value_size: None,
+        };
+
+        let mut writer = state_writer::StateWriter::default();
+        read_writer_db::upsert(&mut writer, &state_key(4), &[4; 5], None).unwrap();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(2), Some(vec![2; 5])).unwrap();
+        let stored = read_writer_db::stored_range(&StateSnapshot::new(&conn), &options).unwrap();
+        assert!(stored.iter().all(|pair| pair.value().is_empty()));
+        let pairs = read_writer_db::merge_range(&mut writer, &stored, &options).unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                KVPair::new(&state_key(1), &[]),
+                KVPair::new(&state_key(3), &[]),
+                KVPair::new(&state_key(4), &[]),
+            ]
+        );
+        // stored keys are not cached since the values are not read
+        assert!(!writer.is_cached(&state_key(1)).unwrap());
+        assert!(!writer.is_cached(&state_key(3)).unwrap());
+    }
+
+    #[test]
+    fn test_range_with_writer_inventory() {
+        let temp_dir = TempDir::new("test_range_with_writer_inventory").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in 1..=4 {
+            conn.put(Kind::State.key(state_key(i)), vec![i; i as usize])
+                .unwrap();
+        }
+        let options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: Some(state_key(0)),
+            lte: Some(state_key(255)),
+            prefix: None,
+            keys_only: false,
+            inventory: true,
+            min_value_size: Some(3),
+        };
+
+        let mut writer = state_writer::StateWriter::default();
+        // the pending value is reported instead of the stored one
+        read_writer_db::upsert(&mut writer, &state_key(1), &[1; 3], Some(vec![1])).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(4), &[4; 2], Some(vec![4; 4])).unwrap();
+        read_writer_db::upsert(&mut writer, &state_key(5), &[5; 6], None).unwrap();
+        let stored = read_writer_db::stored_range(&StateSnapshot::new(&conn), &options).unwrap();
+        let pairs = read_writer_db::merge_range(&mut writer, &stored, &options).unwrap();
+        let sizes: Vec<(Vec<u8>, usize)> = pairs
+            .iter()
+            .map(|pair| (pair.key_as_vec(), pair.value().len()))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![(state_key(1), 3), (state_key(3), 3), (state_key(5), 6)]
+        );
+        // stored values are not cached by the inventory
+        assert!(!writer.is_cached(&state_key(3)).unwrap());
+
+        // the limit is applied after the filter
+        let limited = IterationOption {
+            limit: 2,
+            ..options
+        };
+        let pairs = read_writer_db::merge_range(&mut writer, &stored, &limited).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].key(), state_key(3).as_slice());
+    }
+
+    #[test]
+    fn test_range_with_writer_truncated() {
+        let temp_dir = TempDir::new("test_range_with_writer_truncated").unwrap();
+        let conn = rocksdb::DB::open_default(&temp_dir).unwrap();
+        for i in [1, 2, 3, 5, 6] {
+            conn.put(Kind::State.key(state_key(i)), [i; 10]).unwrap();
+        }
+        let options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: Some(state_key(0)),
+            lte: Some(state_key(255)),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+        let pair_size = Prefix::STATE.len() + state_key(1).len() + 10;
+
+        let mut writer = state_writer::StateWriter::default();
+        read_writer_db::upsert(&mut writer, &state_key(4), &[4; 10], None).unwrap();
+        read_writer_db::delete_with_writer(&mut writer, &state_key(1), Some(vec![1; 10])).unwrap();
+        let snapshot = StateSnapshot::new(&conn);
+        // the deleted key is not counted
+        let (stored, stored_truncated) =
+            read_writer_db::stored_range_within(&snapshot, &writer, &options, Some(pair_size * 2))
+                .unwrap();
+        assert!(stored_truncated);
+        assert_eq!(stored.last().unwrap().key(), Kind::State.key(state_key(3)));
+        let mut pairs = read_writer_db::merge_range(&mut writer, &stored, &options).unwrap();
+        // the created key after the last stored key is removed, as the stored keys before it are not read
+        assert!(read_writer_db::truncate_merged(
+            &mut pairs,
+            &stored,
+            stored_truncated,
+            &options,
+            Some(pair_size * 2),
+        ));
+        let keys: Vec<Vec<u8>> = pairs.iter().map(|pair| pair.key_as_vec()).collect();
+        assert_eq!(keys, vec![state_key(2), state_key(3)]);
+
+        // the whole range within the size is not truncated
+        let (stored, stored_truncated) = read_writer_db::stored_range_within(
+            &snapshot,
+            &writer,
+            &options,
+            Some(pair_size * 10),
+        )
+        .unwrap();
+        assert!(!stored_truncated);
+        let mut pairs = read_writer_db::merge_range(&mut writer, &stored, &options).unwrap();
+        assert!(!read_writer_db::truncate_merged(
+            &mut pairs,
+            &stored,
+            stored_truncated,
+            &options,
+            Some(pair_size * 10),
+        ));
+        assert_eq!(pairs.len(), 5);
+    }
+
+    #[test]
+    fn test_compute_root() {
+        let (conn, _temp_dir) = temp_db();
+        let compute = |snapshot: &StateSnapshot, writer: &RwLock<state_writer::StateWriter>| {
+            compute_root(
+                snapshot,
+                &writer.read().unwrap(),
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                2,
+            )
+            .unwrap()
+        };
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        // empty writer on the empty state
+        let computed = compute(&StateSnapshot::new(&conn), &writer);
+        assert_eq!(computed.root, EMPTY_HASH.to_vec());
+        assert_eq!(computed.version, BlockHeight(0));
+        assert_eq!(computed.changed_keys, 0);
+
+        for i in 0..10 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        {
+            let mut w = writer.write().unwrap();
+            read_writer_db::upsert(&mut w, &state_key(1), &[11; 5], Some(vec![1; 5])).unwrap();
+            read_writer_db::upsert(&mut w, &state_key(20), &[20; 5], None).unwrap();
+            w.cache_existing(&SharedKVPair::new(&state_key(2), &[2; 5]))
+                .unwrap();
+            w.cache_existing(&SharedKVPair::new(&state_key(3), &[3; 5]))
+                .unwrap();
+            w.delete(&state_key(3)).unwrap();
+        }
+        let nodes = stored_nodes(&conn);
+        let updated = writer.read().unwrap().get_hashed_updated().unwrap();
+
+        let snapshot = StateSnapshot::new(&conn);
+        let computed = compute(&snapshot, &writer);
+        assert_ne!(computed.root, root);
+        assert_eq!(computed.version, BlockHeight(1));
+        // read only key is not changed
+        assert_eq!(computed.changed_keys, 3);
+        assert_eq!(stored_nodes(&conn), nodes);
+        assert_eq!(
+            writer.read().unwrap().get_hashed_updated().unwrap(),
+            updated
+        );
+
+        let next_root = commit_writer(&conn, &writer, &root, BlockHeight(2));
+        assert_eq!(next_root, computed.root);
+        // the snapshot taken before the commit computes on the state of height 1
+        assert_eq!(compute(&snapshot, &writer), computed);
+    }
+
+    #[test]
+    fn test_write_batch_with_kinds() {
+        let (conn, _temp_dir) = temp_db();
+        let writer = RwLock::new(state_writer::StateWriter::default());
+        for i in 0..3 {
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(i), &[i; 5], None)
+                .unwrap();
+        }
+        let root = commit_writer(&conn, &writer, &[], BlockHeight(1));
+        let height = BlockHeight(1).to_be_bytes();
+        let diff = conn.get(&[consts::Prefix::DIFF, &height].concat()).unwrap();
+        let nodes = stored_nodes(&conn);
+
+        // restore the state, diff and nodes to another database with a single batch
+        let (restored, _restored_dir) = temp_db();
+        let mut restore = batch::WriteBatch::new_db_with_key_length(None);
+        for i in 0..3 {
+            restore
+                .batch
+                .put(BatchKind::State.key(&state_key(i)), [i; 5]);
+        }
+        restore
+            .batch
+            .put(BatchKind::Diff.key(&height), diff.unwrap());
+        let mode = rocksdb::IteratorMode::From(consts::Prefix::SMT, rocksdb::Direction::Forward);
+        for key_val in conn.iterator(mode) {
+            let (key, value) = key_val.unwrap();
+            if !key.starts_with(consts::Prefix::SMT) {
+                break;
+            }
+            restore
+                .batch
+                .put(BatchKind::Smt.key(&key[consts::Prefix::SMT.len()..]), value);
+        }
+        restore.batch.put(BatchKind::Raw.key(&[9, 9]), [1]);
+        let mut db = StateDB {
+            common: restored,
+            options: DbOptions::new(false, KEY_LENGTH),
+            bloom: Some(StateBloom::new(consts::BLOOM_FILTER_FALSE_POSITIVE_RATE)),
+            negative_cache: Some(NegativeCache::new(10)),
+            write_lock: Arc::new(Mutex::new(())),
+            commit_cancel: OperationCancel::default(),
+            integrity_cancel: OperationCancel::default(),
+            reader_pool: Mutex::new(None),
+            bulk_load: Arc::new(AtomicBool::new(false)),
+            readers: Arc::new(OpenReaders::new()),
+        };
+        let bloom = db.bloom.as_mut().unwrap();
+        let build = bloom.start_build().unwrap();
+        let result = build.run(&db.common);
+        bloom.finish_build(build, result);
+        assert_eq!(bloom.may_exist(&state_key(1)), Some(false));
+        assert!(!db.key_exists(&state_key(1)).unwrap());
+        assert!(!db.key_exists(&state_key(1)).unwrap());
+        assert_eq!(db.negative_cache.as_ref().unwrap().stats().hits, 1);
+        db.common.write(restore.batch).unwrap();
+        db.invalidate_lookups();
+        // the key cached as missing is read after the write
+        assert!(db.key_exists(&state_key(1)).unwrap());
+
+        // the filter is rebuilt against the written state
+        let bloom = db.bloom.as_mut().unwrap();
+        assert_eq!(bloom.may_exist(&state_key(1)), None);
+        let build = bloom.start_build().unwrap();
+        let result = build.run(&db.common);
+        bloom.finish_build(build, result);
+        assert_eq!(bloom.may_exist(&state_key(1)), Some(true));
+        assert_eq!(db.common.get(&[9, 9]).unwrap(), Some(vec![1]));
+        assert_eq!(stored_nodes(&db.common), nodes);
+        let (proof, values) = prove_with_values(
+            &db.common,
+            &root,
+            &[state_key(2)],
+            None,
+            KEY_LENGTH,
+            HasherKind::Sha256,
+        )
+        .unwrap();
+        assert_eq!(values, vec![Some(vec![2; 5])]);
+        assert!(smt::SparseMerkleTree::verify(
+            &[state_key(2).hash_with_kind(HashKind::Key)],
+            &proof,
+            &root,
+            KEY_LENGTH
+        )
+        .unwrap());
+        let reverted = store::revert_diff(
+            &db.common,
+            BlockHeight(1),
+            &root,
+            &[],
+            KEY_LENGTH,
+            HasherKind::Sha256,
+            None,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(**reverted.lock().unwrap(), EMPTY_HASH.to_vec());
+    }
+
+    #[test]
+    fn test_keep_diff_for_heights() {
+        let (conn, _temp_dir) = temp_db();
+        let mut roots = vec![vec![]];
+        for height in 1..=5_u32 {
+            let writer = RwLock::new(state_writer::StateWriter::default());
+            let prev = if height > 1 {
+                Some(vec![height as u8 - 1; 5])
+            } else {
+                None
+            };
+            read_writer_db::upsert(
+                &mut writer.write().unwrap(),
+                &state_key(1),
+                &[height as u8; 5],
+                prev,
+            )
+            .unwrap();
+            let root = commit_writer_keeping_diff(
+                &conn,
+                &writer,
+                roots.last().unwrap(),
+                BlockHeight(height),
+                Some(2),
+            );
+            roots.push(root);
+        }
+        let diff_exists = |height: u32| {
+            conn.get(&[consts::Prefix::DIFF, &height.to_be_bytes()].concat())
+                .unwrap()
+                .is_some()
+        };
+        assert_eq!(
+            (1..=5).map(diff_exists).collect::<Vec<bool>>(),
+            vec![false, false, true, true, true]
+        );
+        // the roots are removed together with the diffs
+        assert_eq!(
+            root_history::get(&conn, BlockHeight(2)).unwrap(),
+            RootAt::Pruned
+        );
+        assert_eq!(
+            root_history::latest(&conn, 10).unwrap(),
+            vec![
+                (5, roots[5].clone()),
+                (4, roots[4].clone()),
+                (3, roots[3].clone())
+            ]
+        );
+        // state root different from the recorded one is rejected before applying the diff
+        assert!(matches!(
+            store::revert_diff(
+                &conn,
+                BlockHeight(5),
+                &roots[4],
+                &[],
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                None,
+                &WriteOptions::default()
             ),
-            (
-                CurrentState::new(&EMPTY_HASH, block_zero),
-                [EMPTY_HASH.to_vec(), block_zero.to_be_bytes().to_vec()].concat(),
+            Err(DataStoreError::InvalidRoot(_))
+        ));
+
+        for height in (3..=5).rev() {
+            let reverted = store::revert_diff(
+                &conn,
+                BlockHeight(height),
+                &roots[height as usize],
+                &[],
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                None,
+                &WriteOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(**reverted.lock().unwrap(), roots[height as usize - 1]);
+            assert_eq!(
+                root_history::get(&conn, BlockHeight(height)).unwrap(),
+                RootAt::NotRecorded
+            );
+        }
+        assert!(matches!(
+            store::revert_diff(
+                &conn,
+                BlockHeight(2),
+                &roots[2],
+                &[],
+                KEY_LENGTH,
+                HasherKind::Sha256,
+                None,
+                &WriteOptions::default()
             ),
-        ];
-        for (state_as_struct, state_as_bytes) in test_data {
-            assert_eq!(state_as_struct.to_bytes(), state_as_bytes);
-            assert_eq!(CurrentState::from_bytes(&state_as_bytes), state_as_struct);
+            Err(DataStoreError::DiffNotFound(2))
+        ));
+        // nothing is applied when the diff is not available
+        assert_eq!(
+            conn.get(&Kind::State.key(state_key(1))).unwrap(),
+            Some(vec![2; 5])
+        );
+    }
+
+    #[test]
+    fn test_keep_diff_for_heights_disabled() {
+        let (conn, _temp_dir) = temp_db();
+        let mut root = vec![];
+        for height in 1..=3 {
+            let writer = RwLock::new(state_writer::StateWriter::default());
+            read_writer_db::upsert(&mut writer.write().unwrap(), &state_key(height), &[1], None)
+                .unwrap();
+            root = commit_writer(&conn, &writer, &root, BlockHeight(height.into()));
+        }
+        for height in 1..=3_u32 {
+            assert!(conn
+                .get(&[consts::Prefix::DIFF, &height.to_be_bytes()].concat())
+                .unwrap()
+                .is_some());
         }
     }
+
+    #[test]
+    fn test_commit_in_chunks_resume() {
+        let new_writer = || {
+            let writer = RwLock::new(state_writer::StateWriter::default());
+            for i in 0..100 {
+                read_writer_db::upsert(
+                    &mut writer.write().unwrap(),
+                    &state_key(i),
+                    &[i; 50],
+                    None,
+                )
+                .unwrap();
+            }
+            writer
+        };
+        let chunked_commit = || {
+            let mut commit = Commit::new(vec![], CommitOptions::new(false, BlockHeight(1)), false);
+            commit.max_batch_bytes = Some(1000);
+            commit
+        };
+
+        let (atomic_conn, _atomic_dir) = temp_db();
+        let expected_root = commit_writer(&atomic_conn, &new_writer(), &[], BlockHeight(1));
+
+        let (conn, _temp_dir) = temp_db();
+        chunked_write::set_failpoint(Some(5));
+        let result = try_commit_writer(&conn, &new_writer(), &[], chunked_commit(), None);
+        chunked_write::set_failpoint(None);
+        assert!(matches!(result, Err(SMTError::Unknown(msg)) if msg.contains("interrupted")));
+        assert!(conn.get(consts::Prefix::JOURNAL).unwrap().is_some());
+        // current state is not updated until the last chunk is written
+        assert!(conn.get(consts::Prefix::CURRENT_STATE).unwrap().is_none());
+
+        // resuming with the new writer of the same data
+        let root = try_commit_writer(&conn, &new_writer(), &[], chunked_commit(), None).unwrap();
+        assert_eq!(root, expected_root);
+        assert!(conn.get(consts::Prefix::JOURNAL).unwrap().is_none());
+        assert_eq!(
+            CurrentState::get(&conn).unwrap().unwrap().root(),
+            expected_root.as_slice()
+        );
+        let contents = |db: &DB| -> Vec<KVPair> {
+            db.iterator(rocksdb::IteratorMode::Start)
+                .map(|kv| kv.unwrap())
+                .filter(|(key, _)| !key.starts_with(consts::Prefix::DIFF))
+                .map(|(key, value)| KVPair::new(&key, &value))
+                .collect()
+        };
+        assert_eq!(contents(&conn), contents(&atomic_conn));
+    }
 }
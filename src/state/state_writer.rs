@@ -1,30 +1,141 @@
 // state_wirter provides batch feature for StateDB. The data written to the writer will not be stored to the physical storage unless "commit" using StateDB.
-use std::cmp;
-use std::collections::HashMap;
-use std::sync::Arc;
-
+use std::borrow::Cow;
+#[cfg(feature = "node")]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "node")]
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "node")]
 use neon::prelude::*;
 use thiserror::Error;
 
 use crate::batch;
 use crate::database::options::IterationOption;
-use crate::database::traits::{DatabaseKind, JsNewWithArcMutex, NewDBWithKeyLength};
-use crate::database::types::{JsArcMutex, Kind as DBKind};
+#[cfg(feature = "node")]
+use crate::database::options::StateWriterOption;
+use crate::database::registry::{Registration, REGISTRY};
+use crate::database::traits::{Closable, DatabaseKind, NewDBWithKeyLength};
+#[cfg(feature = "node")]
+use crate::database::types::JsArcRwLock;
+use crate::database::types::Kind as DBKind;
+use crate::database::utils::is_key_in_range;
 use crate::diff;
+#[cfg(feature = "node")]
+use crate::error::DbError;
+use crate::state::spill::{SpillError, SpillStore};
 use crate::types::{Cache, HashKind, HashWithKind, KVPair, KeyLength, SharedKVPair, VecOption};
-use crate::utils;
-
-pub type SendableStateWriter = JsArcMutex<StateWriter>;
 
-trait Batch {
-    fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>);
-    fn delete(&mut self, key: Box<[u8]>);
-}
+/// SendableStateWriter is shared with RwLock, so that the reads of the cached keys do not block each other.
+#[cfg(feature = "node")]
+pub type SendableStateWriter = JsArcRwLock<StateWriter>;
 
 #[derive(Error, Debug)]
 pub enum StateWriterError {
     #[error("Invalid usage")]
     InvalidUsage,
+    /// Consumed is the error of changing the writer after it is committed, until it is reset.
+    #[error("StateWriter is consumed by the commit")]
+    Consumed,
+    /// EmptyValue is the error of setting an empty value, as the SMT treats it as the deletion.
+    #[error("value must not be empty")]
+    EmptyValue,
+    /// CounterOverflow is the error of folding the increments of the counter out of the range of its width.
+    #[error("counter {0} overflows or underflows")]
+    CounterOverflow(String),
+    /// InvalidCounter is the error of incrementing the value which is not a counter of the width.
+    #[error("value of counter {key} must be {width} bytes, got {length} bytes")]
+    InvalidCounter {
+        key: String,
+        width: usize,
+        length: usize,
+    },
+    /// CounterWidthMismatch is the error of incrementing the counter with the width other than the pending increments.
+    #[error("counter {0} is incremented with another width")]
+    CounterWidthMismatch(String),
+    /// Spill is the error of the spill store holding the cache over the spill threshold.
+    #[error(transparent)]
+    Spill(#[from] SpillError),
+}
+
+/// CounterWidth is the width of the big endian unsigned counter updated by the increments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterWidth {
+    U32,
+    U64,
+}
+
+impl CounterWidth {
+    pub fn from_bytes(bytes: usize) -> Option<Self> {
+        match bytes {
+            4 => Some(Self::U32),
+            8 => Some(Self::U64),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::U32 => 4,
+            Self::U64 => 8,
+        }
+    }
+
+    fn max(&self) -> i128 {
+        match self {
+            Self::U32 => u32::MAX.into(),
+            Self::U64 => u64::MAX.into(),
+        }
+    }
+
+    fn decode(&self, value: &[u8]) -> Option<i128> {
+        match self {
+            Self::U32 => value.try_into().ok().map(|v| u32::from_be_bytes(v).into()),
+            Self::U64 => value.try_into().ok().map(|v| u64::from_be_bytes(v).into()),
+        }
+    }
+
+    fn encode(&self, value: i128) -> Vec<u8> {
+        match self {
+            Self::U32 => (value as u32).to_be_bytes().to_vec(),
+            Self::U64 => (value as u64).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Increment is the sum of the deltas pending for the counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Increment {
+    pub width: CounterWidth,
+    pub delta: i128,
+}
+
+/// WriterSize is the number of the cached entries and their approximate bytes, including the spilled ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriterSize {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// PendingState is the change of the key pending in the writer, as it is committed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PendingState {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl PendingState {
+    /// as_str returns the name of the state passed to JS.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -35,25 +146,58 @@ pub struct StateCache {
     deleted: bool,
 }
 
+/// Undo is the previous state of the key changed while any snapshot exists. None if the key was not cached or pending.
+#[derive(Clone, Debug)]
+enum Undo {
+    Entry(Vec<u8>, Option<StateCache>),
+    Increment(Vec<u8>, Option<Increment>),
+}
+
 /// StateWriter holds batch of operation for state_db.
-#[derive(Default)]
+/// When the spill threshold is set and the cached keys and values reach the threshold in bytes,
+/// new keys are cached to the temporary spill store instead of the memory.
+/// A key is either in the memory cache or in the spill store.
+/// Increments of the counters are pending on top of the cache without reading the counters, and they are folded into
+/// the cached values with the stored values by fold_increment before the key is read or the writer is committed.
+/// Snapshots do not copy the cache. The changes after the first snapshot are recorded in the undo log,
+/// so that the memory of the snapshots is proportional to the changes.
 pub struct StateWriter {
     counter: u32,
-    pub backup: HashMap<u32, HashMap<Vec<u8>, StateCache>>,
+    /// undo holds the previous state of the keys changed while any snapshot exists.
+    undo: Vec<Undo>,
+    /// snapshots holds the snapshot id and the length of undo when it was taken, in the order of the id.
+    snapshots: Vec<(u32, usize)>,
     pub cache: HashMap<Vec<u8>, StateCache>,
+    increments: HashMap<Vec<u8>, Increment>,
+    spill_threshold: Option<usize>,
+    cache_bytes: usize,
+    spill: Option<SpillStore>,
+    /// consumed is set when the writer is committed. It is set under the read lock of the commit,
+    /// so that it is exclusive with the changes made under the write lock.
+    consumed: AtomicBool,
+    registration: Registration,
 }
 
-impl DatabaseKind for StateWriter {
-    fn db_kind() -> DBKind {
-        DBKind::StateWriter
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self {
+            counter: 0,
+            undo: vec![],
+            snapshots: vec![],
+            cache: HashMap::new(),
+            increments: HashMap::new(),
+            spill_threshold: None,
+            cache_bytes: 0,
+            spill: None,
+            consumed: AtomicBool::new(false),
+            registration: REGISTRY.register(Self::NAME, None),
+        }
     }
 }
 
-impl Clone for StateWriter {
-    fn clone(&self) -> Self {
-        let mut cloned = StateWriter::default();
-        cloned.cache.clone_from(&self.cache);
-        cloned
+impl DatabaseKind for StateWriter {
+    fn db_kind() -> DBKind {
+        DBKind::StateWriter
     }
 }
 
@@ -63,8 +207,20 @@ impl NewDBWithKeyLength for StateWriter {
     }
 }
 
-impl JsNewWithArcMutex for StateWriter {}
-impl Finalize for StateWriter {}
+#[cfg(feature = "node")]
+impl Finalize for StateWriter {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        self.mark_closed();
+    }
+}
+
+impl Closable for StateWriter {
+    const NAME: &'static str = "StateWriter";
+
+    fn closed(&self) -> &AtomicBool {
+        self.registration.closed()
+    }
+}
 
 impl StateCache {
     fn new(val: &[u8]) -> Self {
@@ -84,25 +240,247 @@ impl StateCache {
             deleted: false,
         }
     }
+
+    /// pending_state returns the change to be committed, or None if the cached value is only read.
+    fn pending_state(&self) -> Option<PendingState> {
+        if self.init.is_none() {
+            Some(PendingState::Created)
+        } else if self.deleted {
+            Some(PendingState::Deleted)
+        } else if self.dirty {
+            Some(PendingState::Updated)
+        } else {
+            None
+        }
+    }
+
+    /// size returns the bytes of the key and the cached values counted against the spill threshold.
+    fn size(&self, key: &[u8]) -> usize {
+        key.len() + self.value.len() + self.init.as_ref().map_or(0, |init| init.len())
+    }
+
+    /// encode returns the bytes stored in the spill store.
+    /// The format is flags (init, dirty, deleted), length of init (u32 big endian), init and value.
+    fn encode(&self) -> Vec<u8> {
+        let init = self.init.as_deref().unwrap_or(&[]);
+        let flags =
+            (self.init.is_some() as u8) | ((self.dirty as u8) << 1) | ((self.deleted as u8) << 2);
+        let mut result = Vec::with_capacity(5 + init.len() + self.value.len());
+        result.push(flags);
+        result.extend_from_slice(&(init.len() as u32).to_be_bytes());
+        result.extend_from_slice(init);
+        result.extend_from_slice(&self.value);
+        result
+    }
+
+    fn decode(val: &[u8]) -> Self {
+        let flags = val[0];
+        let init_len = u32::from_be_bytes(val[1..5].try_into().unwrap()) as usize;
+        Self {
+            init: (flags & 1 != 0).then(|| val[5..5 + init_len].to_vec()),
+            value: val[5 + init_len..].to_vec(),
+            dirty: flags & (1 << 1) != 0,
+            deleted: flags & (1 << 2) != 0,
+        }
+    }
 }
 
 impl StateWriter {
+    /// with_spill_threshold creates StateWriter which spills the cache over the threshold in bytes.
+    /// The cache is kept in the memory if the threshold is None.
+    pub fn with_spill_threshold(spill_threshold: Option<usize>) -> Self {
+        Self {
+            spill_threshold,
+            ..Self::default()
+        }
+    }
+
+    /// try_clone copies the writer without the snapshots. The spilled entries are copied to a new spill store.
+    pub fn try_clone(&self) -> Result<Self, StateWriterError> {
+        let mut cloned = StateWriter::with_spill_threshold(self.spill_threshold);
+        cloned.cache.clone_from(&self.cache);
+        cloned.increments.clone_from(&self.increments);
+        cloned.cache_bytes = self.cache_bytes;
+        if let Some(spill) = &self.spill {
+            spill.copy_to(cloned.spill_store()?)?;
+        }
+        Ok(cloned)
+    }
+
+    /// spill_store returns the spill store, which is created on the first spill.
+    fn spill_store(&mut self) -> Result<&SpillStore, SpillError> {
+        if self.spill.is_none() {
+            self.spill = Some(SpillStore::new()?);
+        }
+        Ok(self.spill.as_ref().unwrap())
+    }
+
+    fn is_spilled(&self, key: &[u8]) -> Result<bool, SpillError> {
+        match &self.spill {
+            Some(spill) => spill.contains(key),
+            None => Ok(false),
+        }
+    }
+
+    /// entry returns the cached value from the memory or the spill store.
+    fn entry(&self, key: &[u8]) -> Result<Option<Cow<StateCache>>, SpillError> {
+        if let Some(cached) = self.cache.get(key) {
+            return Ok(Some(Cow::Borrowed(cached)));
+        }
+        let spilled = match &self.spill {
+            Some(spill) => spill.get(key)?,
+            None => None,
+        };
+        Ok(spilled.map(|spilled| Cow::Owned(StateCache::decode(&spilled))))
+    }
+
+    /// record_entry records the current state of the key to the undo log if any snapshot exists.
+    fn record_entry(&mut self, key: &[u8]) -> Result<(), SpillError> {
+        if self.snapshots.is_empty() {
+            return Ok(());
+        }
+        let prev = self.entry(key)?.map(Cow::into_owned);
+        self.undo.push(Undo::Entry(key.to_vec(), prev));
+        Ok(())
+    }
+
+    /// record_increment records the pending increments of the key to the undo log if any snapshot exists.
+    fn record_increment(&mut self, key: &[u8]) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        let prev = self.increments.get(key).copied();
+        self.undo.push(Undo::Increment(key.to_vec(), prev));
+    }
+
+    /// remove_increment discards the pending increments of the key.
+    fn remove_increment(&mut self, key: &[u8]) {
+        if self.increments.contains_key(key) {
+            self.record_increment(key);
+            self.increments.remove(key);
+        }
+    }
+
+    /// set_entry replaces the cached value in the tier holding the key.
+    /// New key is cached to the spill store if the memory cache reached the threshold.
+    fn set_entry(&mut self, key: &[u8], value: StateCache) -> Result<(), SpillError> {
+        self.record_entry(key)?;
+        if let Some(cached) = self.cache.get_mut(key) {
+            self.cache_bytes = self.cache_bytes - cached.size(key) + value.size(key);
+            *cached = value;
+            return Ok(());
+        }
+        let reached =
+            matches!(self.spill_threshold, Some(threshold) if self.cache_bytes >= threshold);
+        if reached || self.is_spilled(key)? {
+            return self.spill_store()?.put(key, &value.encode());
+        }
+        self.cache_bytes += value.size(key);
+        self.cache.insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, key: &[u8]) -> Result<(), SpillError> {
+        self.record_entry(key)?;
+        if let Some(removed) = self.cache.remove(key) {
+            self.cache_bytes -= removed.size(key);
+            return Ok(());
+        }
+        match &self.spill {
+            Some(spill) => spill.delete(key),
+            None => Ok(()),
+        }
+    }
+
+    /// for_each_entry calls f with all the cached values in the memory and the spill store, ordered by the key.
+    /// The memory cache is sorted here instead of keeping it ordered, so that the lookups stay constant time
+    /// and only the iteration pays for the order.
+    fn for_each_entry(&self, mut f: impl FnMut(&[u8], &StateCache)) -> Result<(), SpillError> {
+        let mut keys: Vec<&Vec<u8>> = self.cache.keys().collect();
+        keys.sort_unstable();
+        let mut memory = keys.into_iter().peekable();
+        if let Some(spill) = &self.spill {
+            spill.for_each_entry(|key, value| {
+                while let Some(cached) = memory.next_if(|cached| cached.as_slice() < key) {
+                    f(cached, &self.cache[cached]);
+                }
+                f(key, &StateCache::decode(value));
+            })?;
+        }
+        for cached in memory {
+            f(cached, &self.cache[cached]);
+        }
+        Ok(())
+    }
+
     /// empty makes StateWriter as an empty HashMap to handle of releasing the memory from JS.
+    #[cfg(feature = "node")]
     fn empty(&mut self) {
-        self.backup = HashMap::new();
+        self.undo = vec![];
+        self.snapshots = vec![];
         self.cache = HashMap::new();
+        self.increments = HashMap::new();
+        self.cache_bytes = 0;
+        self.spill = None;
+    }
+
+    /// mark_consumed marks the writer as committed. The changes are rejected until it is reset.
+    pub fn mark_consumed(&self) {
+        self.consumed.store(true, Ordering::SeqCst);
+    }
+
+    /// is_consumed returns true if the writer is committed and not reset.
+    pub fn is_consumed(&self) -> bool {
+        self.consumed.load(Ordering::SeqCst)
+    }
+
+    /// check_not_consumed returns Consumed error if the writer is committed and not reset.
+    pub fn check_not_consumed(&self) -> Result<(), StateWriterError> {
+        if self.is_consumed() {
+            return Err(StateWriterError::Consumed);
+        }
+        Ok(())
+    }
+
+    /// reset clears the cache, the snapshots and the consumed mark, so that the writer is reused for the next commit.
+    /// The memory cache keeps its capacity.
+    pub fn reset(&mut self) {
+        self.counter = 0;
+        self.undo.clear();
+        self.snapshots.clear();
+        self.cache.clear();
+        self.increments.clear();
+        self.cache_bytes = 0;
+        self.spill = None;
+        *self.consumed.get_mut() = false;
+    }
+
+    /// size returns the number of the cached entries and their bytes counted against the spill threshold.
+    /// The spilled entries are counted by their encoded bytes, and the pending increments by their keys.
+    pub fn size(&self) -> Result<WriterSize, StateWriterError> {
+        let mut size = WriterSize {
+            entries: self.cache.len() + self.increments.len(),
+            bytes: self.cache_bytes + self.increments.keys().map(Vec::len).sum::<usize>(),
+        };
+        if let Some(spill) = &self.spill {
+            spill.for_each_entry(|key, value| {
+                size.entries += 1;
+                size.bytes += key.len() + value.len();
+            })?;
+        }
+        Ok(size)
     }
 
     /// cache_new inserts key-value pair as new value.
-    pub fn cache_new(&mut self, pair: &SharedKVPair) {
-        let cache = StateCache::new(pair.value());
-        self.cache.insert(pair.key_as_vec(), cache);
+    pub fn cache_new(&mut self, pair: &SharedKVPair) -> Result<(), StateWriterError> {
+        self.set_entry(pair.key(), StateCache::new(pair.value()))?;
+        Ok(())
     }
 
     /// cache_existing inserts key-value pair as updated value.
-    pub fn cache_existing(&mut self, pair: &SharedKVPair) {
-        let cache = StateCache::new_existing(pair.value());
-        self.cache.insert(pair.key_as_vec(), cache);
+    pub fn cache_existing(&mut self, pair: &SharedKVPair) -> Result<(), StateWriterError> {
+        self.set_entry(pair.key(), StateCache::new_existing(pair.value()))?;
+        Ok(())
     }
 
     /// get returns the value associated with the key.
@@ -110,135 +488,401 @@ impl StateWriter {
     /// - if the value does not exist in the writer it returns ([], false, false).
     /// - if the value exist in the writer but mark as deleted, it returns (val, true, true).
     /// - if the value exists, it returns (val, false, true).
-    pub fn get(&self, key: &[u8]) -> (Vec<u8>, bool, bool) {
-        let val = self.cache.get(key);
+    pub fn get(&self, key: &[u8]) -> Result<(Vec<u8>, bool, bool), StateWriterError> {
+        let val = self.entry(key)?;
         if val.is_none() {
-            return (vec![], false, false);
+            return Ok((vec![], false, false));
         }
         let val = val.unwrap();
         if val.deleted {
-            return (vec![], true, true);
+            return Ok((vec![], true, true));
         }
-        (val.value.clone(), false, true)
+        Ok((val.value.clone(), false, true))
     }
 
     /// is_cached returns true if there is value associated with the key.
     /// it is possible key is marked as deleted.
-    pub fn is_cached(&self, key: &[u8]) -> bool {
-        self.cache.get(key).is_some()
+    pub fn is_cached(&self, key: &[u8]) -> Result<bool, StateWriterError> {
+        Ok(self.cache.contains_key(key) || self.is_spilled(key)?)
     }
 
-    /// get_range key-value pairs with option specified.
-    pub fn get_range(&self, options: &IterationOption) -> Cache {
-        let start = options.gte.as_ref().unwrap();
-        let end = options.lte.as_ref().unwrap();
-        self.cache
+    /// is_deleted returns true if the key is cached and marked as deleted.
+    pub fn is_deleted(&self, key: &[u8]) -> Result<bool, StateWriterError> {
+        Ok(self.entry(key)?.is_some_and(|cached| cached.deleted))
+    }
+
+    /// has_prefix returns true if any cached key with the prefix is not deleted.
+    /// The keys only read are included, as they exist in the state.
+    pub fn has_prefix(&self, prefix: &[u8]) -> Result<bool, StateWriterError> {
+        if self
+            .cache
             .iter()
-            .filter_map(|(k, v)| {
-                if utils::compare(k, start) != cmp::Ordering::Less
-                    && utils::compare(k, end) != cmp::Ordering::Greater
-                    && !v.deleted
-                {
-                    Some((k.to_vec(), v.value.to_vec()))
-                } else {
-                    None
+            .any(|(key, value)| key.starts_with(prefix) && !value.deleted)
+        {
+            return Ok(true);
+        }
+        let mut found = false;
+        if let Some(spill) = &self.spill {
+            spill.for_each_entry(|key, value| {
+                if !found && key.starts_with(prefix) {
+                    found = !StateCache::decode(value).deleted;
                 }
-            })
-            .collect::<Cache>()
+            })?;
+        }
+        Ok(found)
+    }
+
+    /// get_range key-value pairs with option specified.
+    /// When either side of the range is not specified, the range is open to the side.
+    /// The result is ordered by the key regardless of reverse, and limit is not applied.
+    pub fn get_range(
+        &self,
+        options: &IterationOption,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, StateWriterError> {
+        let mut result = BTreeMap::new();
+        self.for_each_entry(|k, v| {
+            if is_key_in_range(options, k) && !v.deleted {
+                result.insert(k.to_vec(), v.value.to_vec());
+            }
+        })?;
+        Ok(result)
+    }
+
+    /// get_range_with_deleted returns the cached key-value pairs in the range including the deleted keys, whose value is None.
+    /// The result is ordered by the key regardless of reverse, and limit is not applied.
+    pub fn get_range_with_deleted(
+        &self,
+        options: &IterationOption,
+    ) -> Result<BTreeMap<Vec<u8>, VecOption>, StateWriterError> {
+        let mut result = BTreeMap::new();
+        self.for_each_entry(|k, v| {
+            if is_key_in_range(options, k) {
+                result.insert(k.to_vec(), (!v.deleted).then(|| v.value.to_vec()));
+            }
+        })?;
+        Ok(result)
+    }
+
+    /// count_range returns the number of the keys in the range which are not deleted.
+    pub fn count_range(&self, options: &IterationOption) -> Result<usize, StateWriterError> {
+        let mut count = 0;
+        self.for_each_entry(|k, v| {
+            if is_key_in_range(options, k) && !v.deleted {
+                count += 1;
+            }
+        })?;
+        Ok(count)
+    }
+
+    /// get_pending_range returns the changes pending in the writer within the range, ordered by the key with
+    /// the direction and limited by the limit. The keys which are only read are not included, and the value of
+    /// the deleted key is empty. The stored values are not read.
+    pub fn get_pending_range(
+        &self,
+        options: &IterationOption,
+    ) -> Result<Vec<(KVPair, PendingState)>, StateWriterError> {
+        let mut result = vec![];
+        self.for_each_entry(|key, value| {
+            if !is_key_in_range(options, key) {
+                return;
+            }
+            if let Some(state) = value.pending_state() {
+                let value: &[u8] = if state == PendingState::Deleted || options.keys_only {
+                    &[]
+                } else {
+                    &value.value
+                };
+                result.push((KVPair::new(key, value), state));
+            }
+        })?;
+        if options.reverse {
+            result.reverse();
+        }
+        if options.limit != -1 && result.len() > options.limit as usize {
+            result.truncate(options.limit as usize);
+        }
+        Ok(result)
+    }
+
+    /// keys_between returns the cached keys from start (inclusive) to end (exclusive), including the deleted keys.
+    pub fn keys_between(
+        &self,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Vec<Vec<u8>>, StateWriterError> {
+        let mut keys = vec![];
+        self.for_each_entry(|key, _| {
+            if key >= start && key < end {
+                keys.push(key.to_vec());
+            }
+        })?;
+        Ok(keys)
     }
 
     /// update the key with corresponding value.
     pub fn update(&mut self, pair: &KVPair) -> Result<(), StateWriterError> {
-        let cached = self
-            .cache
-            .get_mut(pair.key())
-            .ok_or(StateWriterError::InvalidUsage)?;
+        let mut cached = self
+            .entry(pair.key())?
+            .ok_or(StateWriterError::InvalidUsage)?
+            .into_owned();
         cached.value = pair.value_as_vec();
         cached.dirty = true;
         cached.deleted = false;
+        self.set_entry(pair.key(), cached)?;
         Ok(())
     }
 
-    /// delete the key in the cache.
-    pub fn delete(&mut self, key: &[u8]) {
-        let cached = self.cache.get_mut(key);
+    /// delete the key in the cache. The pending increments of the key are discarded.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), StateWriterError> {
+        self.remove_increment(key);
+        let cached = self.entry(key)?;
         if cached.is_none() {
-            return;
+            return Ok(());
         }
-        let cached = cached.unwrap();
+        let mut cached = cached.unwrap().into_owned();
         if cached.init.is_none() {
-            self.cache.remove(key);
-            return;
+            self.remove_entry(key)?;
+            return Ok(());
         }
         cached.dirty = false;
         cached.deleted = true;
+        self.set_entry(key, cached)?;
+        Ok(())
     }
 
-    /// snapshot creates snapshot of the current writer and return the snapshot id.
-    fn snapshot(&mut self) -> u32 {
-        self.backup.insert(self.counter, self.cache.clone());
+    /// snapshot records the current position of the undo log and returns the snapshot id.
+    /// It does not copy the cache, so that it takes the same time regardless of the size.
+    pub fn snapshot(&mut self) -> u32 {
         let index = self.counter;
+        self.snapshots.push((index, self.undo.len()));
         self.counter += 1;
         index
     }
 
-    /// restore_snapshot reverts the writer to the snapshot id.
-    fn restore_snapshot(&mut self, index: u32) -> Result<(), StateWriterError> {
-        let backup = self
-            .backup
-            .get(&index)
-            .ok_or(StateWriterError::InvalidUsage)?;
-        self.cache.clone_from(backup);
-        self.backup = HashMap::new();
+    /// snapshot_position returns the position of the snapshot id in the snapshots.
+    fn snapshot_position(&self, index: u32) -> Result<usize, StateWriterError> {
+        self.snapshots
+            .iter()
+            .position(|(id, _)| *id == index)
+            .ok_or(StateWriterError::InvalidUsage)
+    }
+
+    /// restore_snapshot reverts the writer to the snapshot id by undoing the changes after it in the reverse order.
+    /// All the snapshots are removed.
+    pub fn restore_snapshot(&mut self, index: u32) -> Result<(), StateWriterError> {
+        let position = self.snapshot_position(index)?;
+        let (_, undo_len) = self.snapshots[position];
+        let undo = self.undo.split_off(undo_len);
+        // the changes of the undo are not recorded without the snapshots
+        self.undo.clear();
+        self.snapshots.clear();
+        for change in undo.into_iter().rev() {
+            match change {
+                Undo::Entry(key, Some(prev)) => self.set_entry(&key, prev)?,
+                Undo::Entry(key, None) => self.remove_entry(&key)?,
+                Undo::Increment(key, Some(prev)) => {
+                    self.increments.insert(key, prev);
+                },
+                Undo::Increment(key, None) => {
+                    self.increments.remove(&key);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// release_snapshot drops the snapshot id and the ones taken after it, keeping the changes.
+    /// The undo log is kept for the earlier snapshots, and it is dropped when no snapshot remains.
+    pub fn release_snapshot(&mut self, index: u32) -> Result<(), StateWriterError> {
+        let position = self.snapshot_position(index)?;
+        self.snapshots.truncate(position);
+        if self.snapshots.is_empty() {
+            self.undo.clear();
+        }
+        Ok(())
+    }
+
+    /// increment adds the delta to the pending increments of the counter, without reading the counter.
+    /// The increments of the same key are summed, and they must have the same width.
+    /// The range of the counter is checked when the increments are folded.
+    pub fn increment(
+        &mut self,
+        key: &[u8],
+        delta: i64,
+        width: CounterWidth,
+    ) -> Result<(), StateWriterError> {
+        self.check_not_consumed()?;
+        self.record_increment(key);
+        let pending = self
+            .increments
+            .entry(key.to_vec())
+            .or_insert(Increment { width, delta: 0 });
+        if pending.width != width {
+            return Err(StateWriterError::CounterWidthMismatch(hex::encode(key)));
+        }
+        pending.delta += i128::from(delta);
+        Ok(())
+    }
+
+    /// has_increments returns true if any increment is pending.
+    pub fn has_increments(&self) -> bool {
+        !self.increments.is_empty()
+    }
+
+    /// has_increment returns true if the increments of the key are pending.
+    pub fn has_increment(&self, key: &[u8]) -> bool {
+        self.increments.contains_key(key)
+    }
+
+    /// increment_keys returns the keys with the pending increments, ordered by the key.
+    pub fn increment_keys(&self) -> Vec<Vec<u8>> {
+        let mut keys: Vec<Vec<u8>> = self.increments.keys().cloned().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// discard_increment discards the pending increments of the key, which is overwritten.
+    pub fn discard_increment(&mut self, key: &[u8]) {
+        self.remove_increment(key);
+    }
+
+    /// incremented returns the value of the counter after the pending increments, or None if nothing is pending.
+    /// The counter is the cached value, or the stored value if the key is not cached. The deleted or missing counter is 0.
+    pub fn incremented(
+        &self,
+        key: &[u8],
+        stored: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, StateWriterError> {
+        let pending = match self.increments.get(key) {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+        let cached = self.entry(key)?;
+        let base = match cached.as_deref() {
+            Some(cached) if cached.deleted => None,
+            Some(cached) => Some(cached.value.as_slice()),
+            None => stored,
+        };
+        let base =
+            match base {
+                Some(value) => pending.width.decode(value).ok_or_else(|| {
+                    StateWriterError::InvalidCounter {
+                        key: hex::encode(key),
+                        width: pending.width.bytes(),
+                        length: value.len(),
+                    }
+                })?,
+                None => 0,
+            };
+        let value = base + pending.delta;
+        if value < 0 || value > pending.width.max() {
+            return Err(StateWriterError::CounterOverflow(hex::encode(key)));
+        }
+        Ok(Some(pending.width.encode(value)))
+    }
+
+    /// fold_increment folds the pending increments of the key into the ordinary put of the counter.
+    /// stored is the stored value of the key, which is cached if the key is not cached.
+    /// The increments are kept pending if the counter is out of range.
+    pub fn fold_increment(
+        &mut self,
+        key: &[u8],
+        stored: Option<Vec<u8>>,
+    ) -> Result<(), StateWriterError> {
+        let value = match self.incremented(key, stored.as_deref())? {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        self.remove_increment(key);
+        if !self.is_cached(key)? {
+            match stored {
+                Some(stored) => self.cache_existing(&SharedKVPair::new(key, &stored))?,
+                None => return self.cache_new(&SharedKVPair::new(key, &value)),
+            }
+        }
+        self.update(&KVPair::new(key, &value))
+    }
+
+    /// fold_increments folds all the pending increments. stored returns the stored value of the key.
+    pub fn fold_increments<E: From<StateWriterError>>(
+        &mut self,
+        mut stored: impl FnMut(&[u8]) -> Result<VecOption, E>,
+    ) -> Result<(), E> {
+        for key in self.increment_keys() {
+            let value = stored(&key)?;
+            self.fold_increment(&key, value)?;
+        }
         Ok(())
     }
 
     /// get_hashed_updated returns all the updated key-value pairs.
+    /// The pairs are keyed by the hashed key, so the order is decided by the consumer.
+    /// The key updated more than once has the latest value only, as UpdateData keeps the last occurrence.
     /// if the key is removed, value will be empty slice.
-    pub fn get_hashed_updated(&self) -> Cache {
-        let mut result = Cache::new();
-        for (key, value) in self.cache.iter() {
+    /// The pairs are hashed from the borrowed cache, so that only the hashes are allocated.
+    pub fn get_hashed_updated(&self) -> Result<Cache, StateWriterError> {
+        let mut result = Cache::with_capacity(self.cache.len());
+        self.for_each_entry(|key, value| {
             if value.init.is_none() || value.dirty {
                 result.insert(
                     key.hash_with_kind(HashKind::Key),
                     value.value.hash_with_kind(HashKind::Value),
                 );
-                continue;
+                return;
             }
             if value.deleted {
                 result.insert(key.hash_with_kind(HashKind::Key), vec![]);
             }
-        }
-        result
+        })?;
+        Ok(result)
     }
 
-    pub fn commit(&self, batch: &mut impl batch::BatchWriter) -> diff::Diff {
+    /// commit writes the changes to the batch, and returns the diff.
+    /// The batch and the lists of the diff are ordered by the key, so that the diff bytes are reproducible.
+    /// The pairs are passed to the batch borrowed from the cache, and only the diff owns the copies of the old values.
+    pub fn commit(
+        &self,
+        batch: &mut impl batch::BatchWriter,
+    ) -> Result<diff::Diff, StateWriterError> {
         let mut created = vec![];
         let mut updated = vec![];
         let mut deleted = vec![];
-        for (key, value) in self.cache.iter() {
-            let kv = KVPair::new(key, &value.value);
-            if value.init.is_none() {
-                created.push(key.to_vec());
-                batch.put(&kv);
-                continue;
+        self.for_each_entry(|key, value| {
+            let kv = SharedKVPair::new(key, &value.value);
+            match value.pending_state() {
+                Some(PendingState::Created) => {
+                    created.push(key.to_vec());
+                    batch.put(&kv);
+                },
+                Some(PendingState::Deleted) => {
+                    // value might be updated before the deletion, so the stored value is kept in the diff
+                    deleted.push(KVPair::new(key, value.init.as_ref().unwrap()));
+                    batch.delete(key);
+                },
+                Some(PendingState::Updated) => {
+                    updated.push(KVPair::new(key, value.init.as_ref().unwrap()));
+                    batch.put(&kv);
+                },
+                None => {},
             }
-            if value.deleted {
-                deleted.push(KVPair::new(key, &value.value));
-                batch.delete(key);
-                continue;
-            }
-            if value.dirty {
-                updated.push(KVPair::new(key, value.init.as_ref().unwrap()));
-                batch.put(&kv);
-                continue;
-            }
-        }
-        diff::Diff::new(created, updated, deleted)
+        })?;
+        Ok(diff::Diff::new(created, updated, deleted))
     }
 }
 
+#[cfg(feature = "node")]
 impl StateWriter {
+    /// js_new is handler for JS ffi.
+    /// - @params(0) - options (optional). spillThreshold is the bytes of the cache kept in the memory.
+    /// - @returns - StateWriter
+    pub fn js_new(mut ctx: FunctionContext) -> JsResult<SendableStateWriter> {
+        let options = ctx.argument_opt(0);
+        let options = StateWriterOption::new(&mut ctx, options)?;
+        let writer = Self::with_spill_threshold(options.spill_threshold);
+
+        Ok(ctx.boxed(RefCell::new(Arc::new(RwLock::new(writer)))))
+    }
+
     /// js_close is handler for JS ffi.
     /// js "this" - StateWriter.
     pub fn js_close(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
@@ -247,12 +891,59 @@ impl StateWriter {
             .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
 
         let batch = Arc::clone(&writer.borrow());
-        let mut inner_writer = batch.lock().unwrap();
+        let mut inner_writer = batch.write().unwrap();
         inner_writer.empty();
+        inner_writer.mark_closed();
+
+        Ok(ctx.undefined())
+    }
+
+    /// throw_if_consumed throws ERR_WRITER_CONSUMED if the writer is committed and not reset.
+    pub fn throw_if_consumed<'a, C: Context<'a>>(&self, ctx: &mut C) -> NeonResult<()> {
+        self.check_not_consumed()
+            .or_else(|err| DbError::from(err).throw(ctx))
+    }
+
+    /// js_reset is handler for JS ffi.
+    /// It clears the writer, so that it is reused after the commit.
+    /// js "this" - StateWriter.
+    pub fn js_reset(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let writer = ctx
+            .this()
+            .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
+
+        let batch = Arc::clone(&writer.borrow());
+        let mut inner_writer = batch.write().unwrap();
+        inner_writer.throw_if_closed(&mut ctx)?;
+        inner_writer.reset();
 
         Ok(ctx.undefined())
     }
 
+    /// js_size is handler for JS ffi.
+    /// js "this" - StateWriter.
+    /// - @returns - { entries: u64, bytes: u64 }. Number of the cached entries and their approximate bytes.
+    pub fn js_size(mut ctx: FunctionContext) -> JsResult<JsObject> {
+        let writer = ctx
+            .this()
+            .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
+
+        let batch = Arc::clone(&writer.borrow());
+        let inner_writer = batch.read().unwrap();
+        inner_writer.throw_if_closed(&mut ctx)?;
+        let size = inner_writer.size();
+        drop(inner_writer);
+        let size = size.or_else(|err| DbError::from(err).throw(&mut ctx))?;
+
+        let obj = ctx.empty_object();
+        let entries = ctx.number(size.entries as f64);
+        obj.set(&mut ctx, "entries", entries)?;
+        let bytes = ctx.number(size.bytes as f64);
+        obj.set(&mut ctx, "bytes", bytes)?;
+
+        Ok(obj)
+    }
+
     /// js_snapshot is handler for JS ffi.
     /// js "this" - StateWriter.
     /// - @returns - snapshot id
@@ -262,13 +953,36 @@ impl StateWriter {
             .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
 
         let batch = Arc::clone(&writer.borrow());
-        let mut inner_writer = batch.lock().unwrap();
+        let mut inner_writer = batch.write().unwrap();
+        inner_writer.throw_if_closed(&mut ctx)?;
+        inner_writer.throw_if_consumed(&mut ctx)?;
 
         let index = inner_writer.snapshot();
 
         Ok(ctx.number(index))
     }
 
+    /// js_release_snapshot is handler for JS ffi.
+    /// It drops the snapshot id and the later ones once the changes after them are kept.
+    /// js "this" - StateWriter.
+    /// - @params(0) - snapshot id
+    pub fn js_release_snapshot(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+        let writer = ctx
+            .this()
+            .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
+
+        let batch = Arc::clone(&writer.borrow());
+        let mut inner_writer = batch.write().unwrap();
+        inner_writer.throw_if_closed(&mut ctx)?;
+        inner_writer.throw_if_consumed(&mut ctx)?;
+        let index = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as u32;
+
+        match inner_writer.release_snapshot(index) {
+            Ok(()) => Ok(ctx.undefined()),
+            Err(err) => DbError::from(err).throw(&mut ctx),
+        }
+    }
+
     /// js_restore_snapshot is handler for JS ffi.
     /// js "this" - StateWriter.
     /// - @params(0) - snapshot id
@@ -278,12 +992,14 @@ impl StateWriter {
             .downcast_or_throw::<SendableStateWriter, _>(&mut ctx)?;
 
         let batch = Arc::clone(&writer.borrow());
-        let mut inner_writer = batch.lock().unwrap();
+        let mut inner_writer = batch.write().unwrap();
+        inner_writer.throw_if_closed(&mut ctx)?;
+        inner_writer.throw_if_consumed(&mut ctx)?;
         let index = ctx.argument::<JsNumber>(0)?.value(&mut ctx) as u32;
 
         match inner_writer.restore_snapshot(index) {
             Ok(()) => Ok(ctx.undefined()),
-            Err(error) => ctx.throw_error(error.to_string())?,
+            Err(err) => DbError::from(err).throw(&mut ctx),
         }
     }
 }
@@ -295,7 +1011,7 @@ mod tests {
 
     use std::cell::RefCell;
     use std::convert::TryInto;
-    use std::sync::Mutex;
+    use std::sync::{Arc, RwLock};
     use std::thread;
 
     use rand::RngCore;
@@ -318,7 +1034,7 @@ mod tests {
                 pairs.push(KVPair::new(&key, &value));
             }
 
-            let sendable_writer = RefCell::new(Arc::new(Mutex::new(StateWriter::default())));
+            let sendable_writer = RefCell::new(Arc::new(RwLock::new(StateWriter::default())));
             let mut counter = 0;
             for i in 1..inner_loop_iteration {
                 let mut key = [0u8; 32];
@@ -335,17 +1051,19 @@ mod tests {
                 let batch = sendable_writer.borrow_mut();
                 let writer = Arc::clone(&batch);
                 thread::spawn(move || {
-                    let w = writer.lock();
+                    let w = writer.write();
                     assert!(w.is_ok());
-                    w.unwrap().cache_new(&SharedKVPair::new(&key, &value));
+                    w.unwrap()
+                        .cache_new(&SharedKVPair::new(&key, &value))
+                        .unwrap();
                 });
             }
 
             let writer = Arc::clone(&sendable_writer.borrow_mut());
             thread::spawn(move || {
-                let mut w = writer.lock().unwrap();
+                let mut w = writer.write().unwrap();
                 for kv in pairs.iter() {
-                    assert!(w.is_cached(kv.key()));
+                    assert!(w.is_cached(kv.key()).unwrap());
                     let mut new_value = [0u8; 32];
                     rand::thread_rng().fill_bytes(&mut new_value);
                     assert!(w.update(&KVPair::new(kv.key(), &new_value)).is_ok());
@@ -358,20 +1076,24 @@ mod tests {
     fn test_cache() {
         let mut writer = StateWriter::default();
 
-        writer.cache_new(&SharedKVPair::new(&[0, 0, 2], &[1, 2, 3]));
-        writer.cache_existing(&SharedKVPair::new(&[0, 0, 3], &[1, 2, 4]));
+        writer
+            .cache_new(&SharedKVPair::new(&[0, 0, 2], &[1, 2, 3]))
+            .unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[0, 0, 3], &[1, 2, 4]))
+            .unwrap();
 
-        let (value, deleted, exists) = writer.get(&[0, 0, 2]);
+        let (value, deleted, exists) = writer.get(&[0, 0, 2]).unwrap();
         assert_eq!(value, &[1, 2, 3]);
         assert!(!deleted);
         assert!(exists);
 
-        let (value, deleted, exists) = writer.get(&[0, 0, 3]);
+        let (value, deleted, exists) = writer.get(&[0, 0, 3]).unwrap();
         assert_eq!(value, &[1, 2, 4]);
         assert!(!deleted);
         assert!(exists);
 
-        let (value, deleted, exists) = writer.get(&[0, 0, 1]);
+        let (value, deleted, exists) = writer.get(&[0, 0, 1]).unwrap();
         assert_eq!(value, &[]);
         assert!(!deleted);
         assert!(!exists)
@@ -380,17 +1102,21 @@ mod tests {
     #[test]
     fn test_state_writer_clone() {
         let mut writer = StateWriter::default();
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
-        writer.cache_new(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
+        writer
+            .cache_new(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]))
+            .unwrap();
 
-        let cloned = writer.clone();
+        let cloned = writer.try_clone().unwrap();
 
-        let (value, deleted, exists) = cloned.get(&[1, 2, 3, 4]);
+        let (value, deleted, exists) = cloned.get(&[1, 2, 3, 4]).unwrap();
         assert_eq!(value, &[5, 6, 7, 8]);
         assert!(!deleted);
         assert!(exists);
 
-        let (value, deleted, exists) = cloned.get(&[10, 20, 30, 40]);
+        let (value, deleted, exists) = cloned.get(&[10, 20, 30, 40]).unwrap();
         assert_eq!(value, &[50, 60, 70, 80]);
         assert!(!deleted);
         assert!(exists);
@@ -400,9 +1126,13 @@ mod tests {
     fn test_state_writer_cache_new() {
         let mut writer = StateWriter::default();
         assert_eq!(writer.cache.len(), 0);
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
         assert_eq!(writer.cache.len(), 1);
-        writer.cache_new(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]));
+        writer
+            .cache_new(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]))
+            .unwrap();
         assert_eq!(writer.cache.len(), 2);
     }
 
@@ -410,38 +1140,46 @@ mod tests {
     fn test_state_writer_cache_existing() {
         let mut writer = StateWriter::default();
         assert_eq!(writer.cache.len(), 0);
-        writer.cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
         assert_eq!(writer.cache.len(), 1);
-        writer.cache_existing(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]));
+        writer
+            .cache_existing(&SharedKVPair::new(&[10, 20, 30, 40], &[50, 60, 70, 80]))
+            .unwrap();
         assert_eq!(writer.cache.len(), 2);
     }
 
     #[test]
     fn test_state_writer_is_cached() {
         let mut writer = StateWriter::default();
-        assert!(!writer.is_cached(&[1, 2, 3, 4]));
+        assert!(!writer.is_cached(&[1, 2, 3, 4]).unwrap());
 
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
-        assert!(writer.is_cached(&[1, 2, 3, 4]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
+        assert!(writer.is_cached(&[1, 2, 3, 4]).unwrap());
     }
 
     #[test]
     fn test_state_writer_get() {
         let mut writer = StateWriter::default();
 
-        let result = writer.get(&[1, 2, 3, 4]);
+        let result = writer.get(&[1, 2, 3, 4]).unwrap();
         assert_eq!(result.0, &[]);
         assert!(!result.1);
         assert!(!result.2);
 
-        writer.cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
-        let result = writer.get(&[1, 2, 3, 4]);
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
+        let result = writer.get(&[1, 2, 3, 4]).unwrap();
         assert_eq!(result.0, &[5, 6, 7, 8]);
         assert!(!result.1);
         assert!(result.2);
 
-        writer.delete(&[1, 2, 3, 4]);
-        let result = writer.get(&[1, 2, 3, 4]);
+        writer.delete(&[1, 2, 3, 4]).unwrap();
+        let result = writer.get(&[1, 2, 3, 4]).unwrap();
         assert_eq!(result.0, &[]);
         assert!(result.1);
         assert!(result.2);
@@ -452,17 +1190,23 @@ mod tests {
         let mut writer = StateWriter::default();
 
         let key = &[1, 2, 3, 4, 5, 6, 7, 8];
-        writer.cache_new(&SharedKVPair::new(key, &[5, 6, 7, 8]));
+        writer
+            .cache_new(&SharedKVPair::new(key, &[5, 6, 7, 8]))
+            .unwrap();
         writer.update(&KVPair::new(key, &[9, 10, 11, 12])).unwrap();
 
         let empty_key = &[2, 2, 3, 4, 5, 6, 7, 8];
-        writer.cache_new(&SharedKVPair::new(empty_key, &[]));
+        writer
+            .cache_new(&SharedKVPair::new(empty_key, &[]))
+            .unwrap();
 
         let deleting_key = &[9, 2, 3, 4, 5, 6, 7, 8];
-        writer.cache_existing(&SharedKVPair::new(deleting_key, &[7, 7, 7]));
-        writer.delete(deleting_key);
+        writer
+            .cache_existing(&SharedKVPair::new(deleting_key, &[7, 7, 7]))
+            .unwrap();
+        writer.delete(deleting_key).unwrap();
 
-        let result = writer.get_hashed_updated();
+        let result = writer.get_hashed_updated().unwrap();
         assert_eq!(result.len(), 3);
         assert_eq!(
             result
@@ -491,31 +1235,131 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_state_writer_update_same_key() {
+        let mut writer = StateWriter::default();
+
+        let key = &[1, 2, 3, 4, 5, 6, 7, 8];
+        writer
+            .cache_existing(&SharedKVPair::new(key, &[0]))
+            .unwrap();
+        writer.update(&KVPair::new(key, &[1])).unwrap();
+        writer.update(&KVPair::new(key, &[2])).unwrap();
+
+        let result = writer.get_hashed_updated().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result
+                .get(&key.to_vec().hash_with_kind(HashKind::Key))
+                .unwrap(),
+            &[2].to_vec().hash_with_kind(HashKind::Value),
+        );
+
+        writer.delete(key).unwrap();
+        let result = writer.get_hashed_updated().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result
+                .get(&key.to_vec().hash_with_kind(HashKind::Key))
+                .unwrap(),
+            &Vec::<u8>::new(),
+        );
+    }
+
+    #[test]
+    fn test_state_writer_get_pending_range() {
+        let mut writer = StateWriter::default();
+        writer.cache_new(&SharedKVPair::new(&[1, 1], &[1])).unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 2], &[2]))
+            .unwrap();
+        writer.update(&KVPair::new(&[1, 2], &[3])).unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 3], &[3]))
+            .unwrap();
+        writer.delete(&[1, 3]).unwrap();
+        // read only
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 4], &[4]))
+            .unwrap();
+        writer.cache_new(&SharedKVPair::new(&[2, 1], &[5])).unwrap();
+
+        let mut options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: Some(vec![1]),
+            lte: Some(vec![1, 255]),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+        let result = writer.get_pending_range(&options).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (KVPair::new(&[1, 1], &[1]), PendingState::Created),
+                (KVPair::new(&[1, 2], &[3]), PendingState::Updated),
+                (KVPair::new(&[1, 3], &[]), PendingState::Deleted),
+            ]
+        );
+
+        options.reverse = true;
+        options.limit = 2;
+        let result = writer.get_pending_range(&options).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (KVPair::new(&[1, 3], &[]), PendingState::Deleted),
+                (KVPair::new(&[1, 2], &[3]), PendingState::Updated),
+            ]
+        );
+
+        let result: Vec<(Vec<u8>, VecOption)> = writer
+            .get_range_with_deleted(&options)
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                (vec![1, 1], Some(vec![1])),
+                (vec![1, 2], Some(vec![3])),
+                (vec![1, 3], None),
+                (vec![1, 4], Some(vec![4])),
+            ]
+        );
+    }
+
     #[test]
     fn test_state_writer_delete() {
         let mut writer = StateWriter::default();
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
 
-        writer.delete(&[1, 2, 3, 4]);
-        let result = writer.get(&[1, 2, 3, 4]);
+        writer.delete(&[1, 2, 3, 4]).unwrap();
+        let result = writer.get(&[1, 2, 3, 4]).unwrap();
         assert_eq!(result.0, &[]);
         assert!(!result.1);
         assert!(!result.2);
 
         let mut writer = StateWriter::default();
-        writer.cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]));
+        writer
+            .cache_existing(&SharedKVPair::new(&[1, 2, 3, 4], &[5, 6, 7, 8]))
+            .unwrap();
 
         writer
             .update(&KVPair::new(&[1, 2, 3, 4], &[7, 7, 7, 7]))
             .unwrap();
-        assert!(writer.cache.get(&[1, 2, 3, 4].to_vec()).unwrap().dirty);
+        assert!(writer.cache.get([1, 2, 3, 4].as_slice()).unwrap().dirty);
 
-        writer.delete(&[1, 2, 3, 4]);
-        let result = writer.get(&[1, 2, 3, 4]);
-        assert!(!writer.cache.get(&[1, 2, 3, 4].to_vec()).unwrap().dirty);
+        writer.delete(&[1, 2, 3, 4]).unwrap();
+        let result = writer.get(&[1, 2, 3, 4]).unwrap();
+        assert!(!writer.cache.get([1, 2, 3, 4].as_slice()).unwrap().dirty);
         assert_eq!(
-            writer.cache.get(&[1, 2, 3, 4].to_vec()).unwrap().dirty,
-            !writer.cache.get(&[1, 2, 3, 4].to_vec()).unwrap().deleted
+            writer.cache.get([1, 2, 3, 4].as_slice()).unwrap().dirty,
+            !writer.cache.get([1, 2, 3, 4].as_slice()).unwrap().deleted
         );
         assert_eq!(result.0, &[]);
         assert!(result.1);
@@ -525,13 +1369,21 @@ mod tests {
     #[test]
     fn test_state_writer_snapshot() {
         let mut writer = StateWriter::default();
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]));
-        writer.cache_new(&SharedKVPair::new(&[5, 6, 7, 8], &[50, 60, 70, 80]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]))
+            .unwrap();
+        writer
+            .cache_new(&SharedKVPair::new(&[5, 6, 7, 8], &[50, 60, 70, 80]))
+            .unwrap();
 
         writer.snapshot();
-        writer.cache_new(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]));
+        writer
+            .cache_new(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]))
+            .unwrap();
         writer.snapshot();
-        writer.cache_new(&SharedKVPair::new(&[13, 14, 15, 16], &[130, 140, 150, 160]));
+        writer
+            .cache_new(&SharedKVPair::new(&[13, 14, 15, 16], &[130, 140, 150, 160]))
+            .unwrap();
 
         assert_eq!(writer.cache.len(), 4);
 
@@ -539,25 +1391,460 @@ mod tests {
         assert_eq!(writer.cache.len(), 3);
     }
 
+    #[test]
+    fn test_state_writer_nested_snapshot() {
+        let mut writer = StateWriter::default();
+        writer
+            .cache_existing(&SharedKVPair::new(&[1], &[10]))
+            .unwrap();
+        let original = writer.cache.clone();
+
+        let outer = writer.snapshot();
+        writer.update(&KVPair::new(&[1], &[11])).unwrap();
+        let inner = writer.snapshot();
+        writer.update(&KVPair::new(&[1], &[12])).unwrap();
+        writer.cache_new(&SharedKVPair::new(&[2], &[20])).unwrap();
+        // released snapshot keeps the changes, and the outer snapshot reverts them
+        writer.release_snapshot(inner).unwrap();
+        assert_eq!(writer.get(&[1]).unwrap().0, &[12]);
+        assert!(writer.restore_snapshot(inner).is_err());
+        let next = writer.snapshot();
+        writer.delete(&[1]).unwrap();
+        writer.restore_snapshot(next).unwrap();
+        assert_eq!(writer.get(&[1]).unwrap().0, &[12]);
+        assert!(writer.is_cached(&[2]).unwrap());
+        // restore removes all the snapshots
+        assert!(writer.restore_snapshot(outer).is_err());
+
+        let outer = writer.snapshot();
+        writer.update(&KVPair::new(&[1], &[13])).unwrap();
+        let inner = writer.snapshot();
+        writer.delete(&[2]).unwrap();
+        writer.release_snapshot(inner).unwrap();
+        writer.restore_snapshot(outer).unwrap();
+        assert_eq!(writer.get(&[1]).unwrap().0, &[12]);
+        assert!(writer.is_cached(&[2]).unwrap());
+
+        // releasing the outer snapshot drops the inner ones and the undo log
+        let outer = writer.snapshot();
+        writer.delete(&[2]).unwrap();
+        let inner = writer.snapshot();
+        writer.update(&KVPair::new(&[1], &[10])).unwrap();
+        writer.release_snapshot(outer).unwrap();
+        assert!(writer.release_snapshot(inner).is_err());
+        assert!(writer.undo.is_empty());
+        assert_eq!(writer.cache.len(), original.len());
+        assert_eq!(writer.get(&[1]).unwrap().0, &[10]);
+    }
+
+    #[test]
+    fn test_state_writer_snapshot_does_not_copy() {
+        let mut writer = StateWriter::default();
+        for i in 0..100_000u32 {
+            writer
+                .cache_existing(&SharedKVPair::new(&i.to_be_bytes(), &[1]))
+                .unwrap();
+        }
+        let block = writer.snapshot();
+        for i in 0..1000u32 {
+            let index = writer.snapshot();
+            writer.update(&KVPair::new(&i.to_be_bytes(), &[2])).unwrap();
+            writer.release_snapshot(index).unwrap();
+        }
+        // only the changes are recorded for the remaining snapshot
+        assert_eq!(writer.snapshots.len(), 1);
+        assert_eq!(writer.undo.len(), 1000);
+
+        writer.restore_snapshot(block).unwrap();
+        assert!(writer.undo.is_empty());
+        assert_eq!(writer.cache.len(), 100_000);
+        assert_eq!(writer.get(&0u32.to_be_bytes()).unwrap().0, &[1]);
+        assert_eq!(writer.get(&999u32.to_be_bytes()).unwrap().0, &[1]);
+        assert!(writer.get_hashed_updated().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_writer_reset_and_size() {
+        let mut writer = StateWriter::with_spill_threshold(Some(10));
+        assert_eq!(writer.size().unwrap(), WriterSize::default());
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]))
+            .unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[5, 6, 7, 8], &[50]))
+            .unwrap();
+        // the third entry is spilled, and counted by the encoded bytes
+        writer
+            .cache_new(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]))
+            .unwrap();
+        assert_eq!(
+            writer.size().unwrap(),
+            WriterSize {
+                entries: 3,
+                bytes: 8 + 6 + 4 + 9,
+            }
+        );
+        writer.snapshot();
+
+        writer.mark_consumed();
+        assert!(writer.is_consumed());
+        assert!(matches!(
+            writer.check_not_consumed(),
+            Err(StateWriterError::Consumed)
+        ));
+
+        writer.reset();
+        assert!(!writer.is_consumed());
+        assert!(writer.check_not_consumed().is_ok());
+        assert_eq!(writer.size().unwrap(), WriterSize::default());
+        assert!(!writer.is_cached(&[9, 10, 11, 12]).unwrap());
+        assert!(writer.restore_snapshot(0).is_err());
+        assert_eq!(writer.snapshot(), 0);
+    }
+
+    #[test]
+    fn test_state_writer_closed() {
+        let writer = StateWriter::default();
+        assert!(!writer.is_closed());
+
+        writer.mark_closed();
+        assert!(writer.is_closed());
+        assert_eq!(
+            writer.check_open().unwrap_err().to_string(),
+            "StateWriter is closed"
+        );
+    }
+
     #[test]
     fn test_state_writer_commit() {
         let mut writer = StateWriter::default();
-        writer.cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]));
-        writer.cache_existing(&SharedKVPair::new(&[5, 6, 7, 8], &[50, 60, 70, 80]));
-        writer.cache_existing(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]))
+            .unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[5, 6, 7, 8], &[50, 60, 70, 80]))
+            .unwrap();
+        writer
+            .cache_existing(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]))
+            .unwrap();
 
-        writer.delete(&[5, 6, 7, 8]);
+        writer.delete(&[5, 6, 7, 8]).unwrap();
         writer
             .update(&KVPair::new(&[9, 10, 11, 12], &[130, 140, 150, 160]))
             .unwrap();
 
         let mut write_batch = batch::PrefixWriteBatch::new();
         write_batch.set_prefix(&Prefix::STATE);
-        let diff = writer.commit(&mut write_batch);
+        let diff = writer.commit(&mut write_batch).unwrap();
 
         let mut batch = batch::PrefixWriteBatch::new();
         batch.set_prefix(&Prefix::STATE);
         diff.revert_commit(&mut batch);
         assert_eq!(batch.batch.len(), 3);
     }
+
+    fn write_pairs(writer: &mut StateWriter) {
+        for i in 0..100u8 {
+            if i % 2 == 0 {
+                writer
+                    .cache_new(&SharedKVPair::new(&[i; 8], &[i; 16]))
+                    .unwrap();
+            } else {
+                writer
+                    .cache_existing(&SharedKVPair::new(&[i; 8], &[i; 16]))
+                    .unwrap();
+            }
+        }
+        for i in 0..100u8 {
+            match i % 5 {
+                0 => writer.delete(&[i; 8]).unwrap(),
+                1 => writer.update(&KVPair::new(&[i; 8], &[i + 1; 4])).unwrap(),
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_writer_spill() {
+        let mut in_memory = StateWriter::default();
+        write_pairs(&mut in_memory);
+        // each pair is at least 24 bytes, so the most of the pairs are spilled
+        let mut spilled = StateWriter::with_spill_threshold(Some(240));
+        write_pairs(&mut spilled);
+        assert!(spilled.cache.len() < 100);
+        assert!(spilled.spill.is_some());
+
+        assert_eq!(
+            in_memory.get(&[99; 8]).unwrap(),
+            spilled.get(&[99; 8]).unwrap()
+        );
+        assert_eq!(
+            in_memory.get(&[95; 8]).unwrap(),
+            spilled.get(&[95; 8]).unwrap()
+        );
+        assert_eq!(
+            in_memory.get(&[96; 8]).unwrap(),
+            spilled.get(&[96; 8]).unwrap()
+        );
+        let options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: Some(vec![10; 8]),
+            lte: Some(vec![80; 8]),
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+        assert_eq!(
+            in_memory.get_range(&options).unwrap(),
+            spilled.get_range(&options).unwrap()
+        );
+        assert_eq!(
+            in_memory.count_range(&options).unwrap(),
+            spilled.count_range(&options).unwrap()
+        );
+        assert_eq!(
+            in_memory.get_hashed_updated().unwrap(),
+            spilled.get_hashed_updated().unwrap()
+        );
+
+        let mut in_memory_batch = batch::PrefixWriteBatch::new();
+        in_memory_batch.set_prefix(&Prefix::STATE);
+        let in_memory_diff = in_memory.commit(&mut in_memory_batch).unwrap();
+        let mut spilled_batch = batch::PrefixWriteBatch::new();
+        spilled_batch.set_prefix(&Prefix::STATE);
+        let spilled_diff = spilled.commit(&mut spilled_batch).unwrap();
+        assert_eq!(in_memory_diff.encode(), spilled_diff.encode());
+        assert_eq!(in_memory_batch.batch.len(), spilled_batch.batch.len());
+    }
+
+    #[test]
+    fn test_state_writer_has_prefix() {
+        // all but the first key are spilled with the threshold
+        for threshold in [None, Some(1)] {
+            let mut writer = StateWriter::with_spill_threshold(threshold);
+            writer
+                .cache_new(&SharedKVPair::new(&[1, 1, 1], &[1]))
+                .unwrap();
+            writer
+                .cache_existing(&SharedKVPair::new(&[2, 1, 1], &[2]))
+                .unwrap();
+            writer
+                .cache_existing(&SharedKVPair::new(&[3, 1, 1], &[3]))
+                .unwrap();
+            writer.delete(&[3, 1, 1]).unwrap();
+            writer
+                .cache_new(&SharedKVPair::new(&[4, 1, 1], &[4]))
+                .unwrap();
+            writer.delete(&[4, 1, 1]).unwrap();
+
+            assert!(writer.has_prefix(&[1]).unwrap());
+            assert!(writer.has_prefix(&[1, 1, 1]).unwrap());
+            assert!(writer.has_prefix(&[2, 1]).unwrap());
+            assert!(!writer.has_prefix(&[3]).unwrap());
+            assert!(!writer.has_prefix(&[4]).unwrap());
+            assert!(writer.has_prefix(&[]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_state_writer_commit_order() {
+        let operations = |writer: &mut StateWriter, order: &[u8]| {
+            for i in order.iter() {
+                writer
+                    .cache_existing(&SharedKVPair::new(&[*i; 4], &[*i; 2]))
+                    .unwrap();
+                writer
+                    .cache_new(&SharedKVPair::new(&[*i + 100; 4], &[*i; 3]))
+                    .unwrap();
+            }
+            for i in order.iter() {
+                if i % 2 == 0 {
+                    writer.delete(&[*i; 4]).unwrap();
+                } else {
+                    writer.update(&KVPair::new(&[*i; 4], &[*i; 5])).unwrap();
+                }
+            }
+        };
+        let forward: Vec<u8> = (0..50).collect();
+        let backward: Vec<u8> = (0..50).rev().collect();
+
+        let mut encoded = vec![];
+        for (order, spill_threshold) in
+            [(&forward, None), (&backward, None), (&backward, Some(50))]
+        {
+            let mut writer = StateWriter::with_spill_threshold(spill_threshold);
+            operations(&mut writer, order);
+            let mut write_batch = batch::PrefixWriteBatch::new();
+            write_batch.set_prefix(&Prefix::STATE);
+            let diff = writer.commit(&mut write_batch).unwrap();
+
+            let mut created = diff.created().clone();
+            created.sort();
+            assert_eq!(&created, diff.created());
+            assert!(diff.updated().windows(2).all(|w| w[0].key() < w[1].key()));
+            assert!(diff.deleted().windows(2).all(|w| w[0].key() < w[1].key()));
+            encoded.push(diff.encode());
+        }
+        assert_eq!(encoded[0], encoded[1]);
+        assert_eq!(encoded[0], encoded[2]);
+
+        let mut writer = StateWriter::with_spill_threshold(Some(50));
+        operations(&mut writer, &backward);
+        let options = IterationOption {
+            limit: -1,
+            reverse: false,
+            gte: None,
+            lte: None,
+            prefix: None,
+            keys_only: false,
+            inventory: false,
+            min_value_size: None,
+        };
+        let keys: Vec<Vec<u8>> = writer.get_range(&options).unwrap().into_keys().collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert_eq!(keys.len(), 75);
+    }
+
+    #[test]
+    fn test_state_writer_spill_snapshot() {
+        let mut writer = StateWriter::with_spill_threshold(Some(1));
+        writer
+            .cache_new(&SharedKVPair::new(&[1, 2, 3, 4], &[10, 20, 30, 50]))
+            .unwrap();
+        writer
+            .cache_new(&SharedKVPair::new(&[5, 6, 7, 8], &[50, 60, 70, 80]))
+            .unwrap();
+        let index = writer.snapshot();
+        writer
+            .cache_new(&SharedKVPair::new(&[9, 10, 11, 12], &[90, 100, 110, 120]))
+            .unwrap();
+        writer
+            .update(&KVPair::new(&[5, 6, 7, 8], &[1, 1, 1, 1]))
+            .unwrap();
+
+        let cloned = writer.try_clone().unwrap();
+        assert_eq!(cloned.get(&[5, 6, 7, 8]).unwrap().0, &[1, 1, 1, 1]);
+        assert!(cloned.is_cached(&[9, 10, 11, 12]).unwrap());
+
+        writer.restore_snapshot(index).unwrap();
+        assert_eq!(writer.get(&[5, 6, 7, 8]).unwrap().0, &[50, 60, 70, 80]);
+        assert!(!writer.is_cached(&[9, 10, 11, 12]).unwrap());
+        assert!(writer.restore_snapshot(index).is_err());
+    }
+
+    #[test]
+    fn test_state_writer_increment() {
+        let key = [1, 2, 3, 4];
+        let mut writer = StateWriter::default();
+        // the increments are summed without reading the counter
+        writer.increment(&key, 5, CounterWidth::U64).unwrap();
+        writer.increment(&key, -2, CounterWidth::U64).unwrap();
+        assert!(writer.has_increment(&key));
+        assert!(!writer.is_cached(&key).unwrap());
+        assert!(matches!(
+            writer.increment(&key, 1, CounterWidth::U32),
+            Err(StateWriterError::CounterWidthMismatch(_))
+        ));
+
+        // missing counter starts from 0
+        assert_eq!(
+            writer.incremented(&key, None).unwrap(),
+            Some(3_u64.to_be_bytes().to_vec())
+        );
+        let stored = 10_u64.to_be_bytes().to_vec();
+        writer.fold_increment(&key, Some(stored.clone())).unwrap();
+        assert!(!writer.has_increments());
+        assert_eq!(writer.get(&key).unwrap().0, 13_u64.to_be_bytes());
+        assert_eq!(writer.get_hashed_updated().unwrap().len(), 1);
+
+        // cached counter is used instead of the stored one, and the deleted counter is 0
+        writer.increment(&key, 1, CounterWidth::U64).unwrap();
+        writer.fold_increment(&key, Some(stored.clone())).unwrap();
+        assert_eq!(writer.get(&key).unwrap().0, 14_u64.to_be_bytes());
+        writer.delete(&key).unwrap();
+        writer.increment(&key, 7, CounterWidth::U64).unwrap();
+        writer.fold_increment(&key, Some(stored)).unwrap();
+        assert_eq!(writer.get(&key).unwrap().0, 7_u64.to_be_bytes());
+
+        // delete discards the pending increments
+        writer.increment(&key, 7, CounterWidth::U64).unwrap();
+        writer.delete(&key).unwrap();
+        assert!(!writer.has_increments());
+    }
+
+    #[test]
+    fn test_state_writer_increment_out_of_range() {
+        let key = [1, 2, 3, 4];
+        let mut writer = StateWriter::default();
+        writer.increment(&key, -1, CounterWidth::U32).unwrap();
+        assert!(matches!(
+            writer.fold_increment(&key, Some(vec![0, 0, 0, 0])),
+            Err(StateWriterError::CounterOverflow(key)) if key == "01020304"
+        ));
+        // the increments are kept pending on the error
+        assert!(writer.has_increment(&key));
+        assert!(!writer.is_cached(&key).unwrap());
+        writer.fold_increment(&key, Some(vec![0, 0, 0, 1])).unwrap();
+        assert_eq!(writer.get(&key).unwrap().0, &[0, 0, 0, 0]);
+
+        writer
+            .increment(&key, i64::from(u32::MAX), CounterWidth::U32)
+            .unwrap();
+        assert_eq!(
+            writer.incremented(&key, None).unwrap(),
+            Some(vec![255, 255, 255, 255])
+        );
+        writer.increment(&key, 1, CounterWidth::U32).unwrap();
+        assert!(matches!(
+            writer.incremented(&key, None),
+            Err(StateWriterError::CounterOverflow(_))
+        ));
+
+        let mut writer = StateWriter::default();
+        writer.increment(&key, 1, CounterWidth::U64).unwrap();
+        assert!(matches!(
+            writer.incremented(&key, Some(&[1, 2])),
+            Err(StateWriterError::InvalidCounter {
+                width: 8,
+                length: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_state_writer_increment_snapshot() {
+        let key = [1, 2, 3, 4];
+        let mut writer = StateWriter::default();
+        writer.increment(&key, 1, CounterWidth::U64).unwrap();
+        let index = writer.snapshot();
+        writer.increment(&key, 2, CounterWidth::U64).unwrap();
+        writer
+            .increment(&[5, 6, 7, 8], 2, CounterWidth::U64)
+            .unwrap();
+        assert_eq!(
+            writer.increment_keys(),
+            vec![key.to_vec(), vec![5, 6, 7, 8]]
+        );
+
+        writer.restore_snapshot(index).unwrap();
+        assert_eq!(writer.increment_keys(), vec![key.to_vec()]);
+        assert_eq!(
+            writer.incremented(&key, None).unwrap(),
+            Some(1_u64.to_be_bytes().to_vec())
+        );
+        let mut keys = vec![];
+        writer
+            .fold_increments(|key| {
+                keys.push(key.to_vec());
+                Ok::<_, StateWriterError>(None)
+            })
+            .unwrap();
+        assert_eq!(keys, vec![key.to_vec()]);
+        assert!(!writer.has_increments());
+    }
 }
@@ -0,0 +1,1437 @@
+/// store provides the state storage on the rocksdb connection without neon.
+/// StateDB uses the functions to commit and revert on its connection thread,
+/// and StateStore calls them directly for the Rust users.
+use std::collections::HashMap;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::batch;
+use crate::consts;
+use crate::database::chunked_write;
+use crate::database::column_families::{self, StateSnapshot};
+use crate::database::reader_writer::read_writer_db;
+use crate::database::staged;
+use crate::database::traits::Actions;
+use crate::database::types::{DbOptions, Kind, ValueCompression};
+use crate::diff::Diff;
+use crate::sparse_merkle_tree::hasher::{HasherKind, TreeHasher};
+use crate::sparse_merkle_tree::smt::{self, SMTError};
+use crate::sparse_merkle_tree::smt_db::{OverlaySmtDB, SmtDB, SnapshotSmtDB};
+use crate::state::bulk_load;
+use crate::state::check_consistency;
+use crate::state::check_diffs;
+use crate::state::current_state::{CurrentState, CurrentStateError};
+use crate::state::diff_values::RefChanges;
+use crate::state::migrations::{self, MigrationError};
+use crate::state::root_history::{self, RootAt};
+use crate::state::state_writer::{StateWriter, StateWriterError};
+use crate::state::value_compression::{self, CompressedBatch};
+use crate::types::{BlockHeight, KeyLength, SharedKVPair, SharedVec, VecOption};
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum DataStoreError {
+    #[error("unknown data store error `{0}`")]
+    Unknown(String),
+    #[error("Diff not found for height: `{0}`")]
+    DiffNotFound(usize),
+    #[error("diff not available for height {0}, it is older than keepDiffForHeights")]
+    DiffNotAvailable(usize),
+    #[error("Invalid state root `{0}`")]
+    InvalidRoot(String),
+    #[error("database is readonly")]
+    Readonly,
+    #[error("{0}")]
+    DiffCorrupted(String),
+    #[error("state at height {0} is not available with the retained diffs")]
+    HeightNotRetained(usize),
+    /// EmptyValue is the error of upserting an empty value, as the SMT treats it as the deletion.
+    #[error("value must not be empty")]
+    EmptyValue,
+    /// BulkLoaded is the error of reverting the height committed in the bulk load, which has no diff.
+    #[error("height {height} is committed in the bulk load from {start} to {end}, and cannot be reverted")]
+    BulkLoaded { height: u32, start: u32, end: u32 },
+}
+
+impl From<StateWriterError> for DataStoreError {
+    fn from(err: StateWriterError) -> Self {
+        match err {
+            StateWriterError::EmptyValue => Self::EmptyValue,
+            err => Self::Unknown(err.to_string()),
+        }
+    }
+}
+
+/// HistoricalState is the state of a past height rebuilt from the stored diffs.
+/// The diffs, the nodes and the values are read from one snapshot, so that the commits do not change them.
+/// Changes are kept in memory, and the database is not changed.
+pub struct HistoricalState<'a> {
+    snapshot: StateSnapshot<'a>,
+    root: Vec<u8>,
+    hasher: HasherKind,
+    /// nodes holds the nodes changed by applying the diffs. None if the node is deleted.
+    nodes: HashMap<Vec<u8>, VecOption>,
+    /// values holds the state values changed after the height. None if the key did not exist at the height.
+    values: HashMap<Vec<u8>, VecOption>,
+}
+
+/// smt_apply_diff updates the tree from the root with the diff, and returns the new root.
+/// Created keys are removed, and updated and deleted keys are restored to the original values,
+/// therefore the new root is the root before the state change which produced the diff.
+pub fn smt_apply_diff(
+    db: &mut impl Actions,
+    root: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+    diff: &Diff,
+) -> Result<SharedVec, SMTError> {
+    let data = smt::UpdateData::new_from(diff.revert_hashed_update());
+    let mut tree =
+        smt::SparseMerkleTree::new_with_hasher(root, key_length, consts::SUBTREE_HEIGHT, hasher);
+    tree.commit(db, &data)
+}
+
+/// check_recorded_root returns InvalidRoot if the root recorded at the height is different from the root.
+/// Heights without the recorded root, such as the ones committed before the index, are not checked.
+fn check_recorded_root(
+    conn: &rocksdb::DB,
+    height: BlockHeight,
+    root: &[u8],
+) -> Result<(), DataStoreError> {
+    match root_history::get(conn, height) {
+        Ok(RootAt::Recorded(recorded)) if !utils::is_bytes_equal(&recorded, root) => {
+            Err(DataStoreError::InvalidRoot(format!(
+                "Not matching with the root recorded at height {}",
+                u32::from(height)
+            )))
+        },
+        Ok(_) => Ok(()),
+        Err(err) => Err(DataStoreError::Unknown(err.to_string())),
+    }
+}
+
+/// revert_diff applies the diff of the version and writes the result in a single batch.
+/// When expected is not empty, nothing is written unless the resulting root matches it.
+/// The roots recorded at the version and the previous version must match the state root and the resulting root.
+/// The current state is updated to the previous version in the same batch.
+pub fn revert_diff(
+    conn: &rocksdb::DB,
+    version: BlockHeight,
+    state_root: &[u8],
+    expected: &[u8],
+    key_length: KeyLength,
+    hasher: HasherKind,
+    compression: Option<ValueCompression>,
+    write_opts: &rocksdb::WriteOptions,
+) -> Result<SharedVec, DataStoreError> {
+    let diff_bytes = column_families::get(
+        conn,
+        &[consts::Prefix::DIFF, &version.to_be_bytes()].concat(),
+    )
+    .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+    .ok_or_else(|| DataStoreError::DiffNotFound(version.into()))?;
+
+    // the values referenced only by the diff are removed with it
+    let mut ref_changes = RefChanges::default();
+    ref_changes.remove_diff(
+        &check_diffs::decode_diff(version.into(), &diff_bytes)
+            .map_err(DataStoreError::DiffCorrupted)?,
+    );
+    let diff = check_diffs::read_diff(conn, version.into(), &diff_bytes)?;
+    check_recorded_root(conn, version, state_root)?;
+    let mut smt_db = SmtDB::new(conn);
+    let prev_root = smt_apply_diff(&mut smt_db, state_root, key_length, hasher, &diff)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+    if !expected.is_empty() && !utils::is_bytes_equal(expected, &prev_root.lock().unwrap()) {
+        return Err(DataStoreError::InvalidRoot(String::from(
+            "Not matching with expected",
+        )));
+    }
+    if let Some(prev_version) = u32::from(version).checked_sub(1) {
+        check_recorded_root(conn, BlockHeight(prev_version), &prev_root.lock().unwrap())?;
+    }
+
+    let mut write_batch = batch::PrefixWriteBatch::new();
+    // Insert state batch with diff
+    write_batch.set_prefix(&consts::Prefix::STATE);
+    // old values in the diff are uncompressed, and they are compressed again as the commit does
+    diff.revert_commit(&mut CompressedBatch::new(&mut write_batch, compression));
+    write_batch.set_prefix(&consts::Prefix::DIFF);
+    write_batch.delete(&version.to_be_bytes());
+    write_batch.set_prefix(&consts::Prefix::ROOT_HISTORY);
+    write_batch.delete(&version.to_be_bytes());
+
+    // insert SMT batch
+    write_batch.set_prefix(&consts::Prefix::SMT);
+    smt_db.batch.iterate(&mut write_batch);
+    // current state is reverted together with the state
+    CurrentState::get(conn)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+        .unwrap_or_default()
+        .reverted(
+            &prev_root.lock().unwrap(),
+            version - BlockHeight(1),
+            state_root,
+        )
+        .put_batch(&mut write_batch.batch);
+    ref_changes
+        .write(conn, &mut write_batch.batch)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+    // insert diff
+    column_families::write_opt(conn, write_batch.batch, write_opts)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+
+    Ok(prev_root)
+}
+
+/// revert reverts the diff of the version together with the current state.
+/// Missing diff is reported as not available when the diffs are removed by keep_diff_for_heights.
+/// The height committed in the bulk load is rejected with BulkLoaded.
+pub fn revert(
+    conn: &rocksdb::DB,
+    version: BlockHeight,
+    state_root: &[u8],
+    expected: &[u8],
+    options: &DbOptions,
+) -> Result<SharedVec, DataStoreError> {
+    if let Some((start, end)) = root_history::bulk_loaded(conn, version)
+        .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+    {
+        return Err(DataStoreError::BulkLoaded {
+            height: version.into(),
+            start,
+            end,
+        });
+    }
+    let write_opts = options.durability.write_options(false);
+    revert_diff(
+        conn,
+        version,
+        state_root,
+        expected,
+        options.key_length(),
+        options.hasher,
+        options.value_compression,
+        &write_opts,
+    )
+    .map_err(|err| match err {
+        // the diff is removed by the commit when it is out of the retained window
+        DataStoreError::DiffNotFound(height) if options.keep_diff_for_heights.is_some() => {
+            DataStoreError::DiffNotAvailable(height)
+        },
+        err => err,
+    })
+}
+
+/// CommitWriteOption holds how write_commit writes the commit.
+pub struct CommitWriteOption {
+    /// keep_diff_for_heights removes the diffs older than the window together with the commit.
+    pub keep_diff_for_heights: Option<u32>,
+    /// max_batch_bytes splits the write into the chunks for the bulk load. Single atomic batch if None.
+    pub max_batch_bytes: Option<usize>,
+    /// write_opts is the rocksdb options of the single batch and the current state.
+    pub write_opts: rocksdb::WriteOptions,
+    /// staged_id marks the staged batch of the Database with the current state, so that it is applied on recovery.
+    pub staged_id: Option<Vec<u8>>,
+    /// value_ref_threshold stores the old values of the size or larger in the old-value store,
+    /// and the diff holds their hashes. All the values are inline if None.
+    pub value_ref_threshold: Option<usize>,
+    /// value_compression compresses the state values of the threshold or larger. The diff holds the values uncompressed.
+    pub value_compression: Option<ValueCompression>,
+    /// bulk_load skips the diff, and records the version as bulk loaded, which cannot be reverted.
+    pub bulk_load: bool,
+    /// hasher is recorded in the current state together with the root.
+    pub hasher: HasherKind,
+}
+
+/// write_commit writes the writer, the diff of the version and the updated nodes in a single batch,
+/// or in chunks of max_batch_bytes for the bulk load. Diffs older than keep_diff_for_heights are removed with it.
+/// The current state is updated to the root in the same batch, or after the chunks for the bulk load, and the diff is returned.
+/// The staged id is marked together with the current state, and so are the references to the old-value store.
+/// In the bulk load, the diff is not written and the version is recorded in the bulk-loaded ranges instead.
+pub fn write_commit(
+    conn: &rocksdb::DB,
+    smt_db: &SmtDB,
+    writer: &StateWriter,
+    root: &[u8],
+    version: BlockHeight,
+    options: &CommitWriteOption,
+) -> Result<Diff, String> {
+    // Create global batch
+    let mut write_batch = batch::PrefixWriteBatch::new();
+    // Insert state batch with diff
+    write_batch.set_prefix(&consts::Prefix::STATE);
+    let diff = writer
+        .commit(&mut CompressedBatch::new(
+            &mut write_batch,
+            options.value_compression,
+        ))
+        .map_err(|err| err.to_string())?;
+    let mut ref_changes = RefChanges::default();
+    if !options.bulk_load {
+        let encoded = match options.value_ref_threshold {
+            Some(threshold) => {
+                let (encoded, referenced) = diff.encode_with_refs(threshold);
+                for pair in referenced.iter() {
+                    ref_changes.add(pair.key(), pair.value());
+                }
+                encoded
+            },
+            None => diff.encode(),
+        };
+        write_batch.set_prefix(&consts::Prefix::DIFF);
+        write_batch.put(&version.to_be_bytes(), &encoded);
+    }
+
+    write_batch.set_prefix(&consts::Prefix::ROOT_HISTORY);
+    write_batch.put(&version.to_be_bytes(), root);
+
+    // insert SMT batch
+    write_batch.set_prefix(&consts::Prefix::SMT);
+    smt_db.batch.iterate(&mut write_batch);
+    // diffs older than the window are removed together with writing the new diff
+    let expired_until = options
+        .keep_diff_for_heights
+        .and_then(|keep| u32::from(version).checked_sub(keep));
+    // the roots of the heights and the values referenced by the diffs are removed together with the diffs
+    let delete_expired = |batch: &mut rocksdb::WriteBatch| -> Result<(), String> {
+        let mut ref_changes = ref_changes;
+        if let Some(until) = expired_until {
+            let start = [consts::Prefix::DIFF, &0_u32.to_be_bytes()].concat();
+            let end = [consts::Prefix::DIFF, &until.to_be_bytes()].concat();
+            ref_changes
+                .remove_diffs(conn, &start, &end)
+                .map_err(|err| err.to_string())?;
+            column_families::delete_range(conn, batch, &start, &end);
+            root_history::prune(conn, batch, until).map_err(|err| err.to_string())?;
+        }
+        ref_changes
+            .write(conn, batch)
+            .map_err(|err| err.to_string())
+    };
+    let put_staged = |batch: &mut rocksdb::WriteBatch| {
+        if let Some(id) = &options.staged_id {
+            batch.put(staged::marker_key(id), root);
+        }
+    };
+    let put_bulk_loaded = |batch: &mut rocksdb::WriteBatch| -> Result<(), String> {
+        if options.bulk_load {
+            root_history::mark_bulk_loaded(conn, batch, version).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    };
+    let current_state = CurrentState::get(conn)
+        .map_err(|err| err.to_string())?
+        .unwrap_or_default()
+        .committed(root, version)
+        .with_hasher(options.hasher);
+    // insert diff
+    match options.max_batch_bytes {
+        Some(max_batch_bytes) => {
+            // same commit results in the same root, so that the interrupted write can be resumed
+            let identity = [&version.to_be_bytes(), root].concat();
+            chunked_write::write_in_chunks(
+                conn,
+                &write_batch.batch,
+                max_batch_bytes,
+                consts::Prefix::JOURNAL,
+                &identity,
+                &options.write_opts,
+            )
+            .map_err(|err| err.to_string())?;
+            // expired diffs are removed after the chunks with the current state, so that they are kept until the commit completes
+            let mut batch = rocksdb::WriteBatch::default();
+            delete_expired(&mut batch)?;
+            current_state.put_batch(&mut batch);
+            put_staged(&mut batch);
+            put_bulk_loaded(&mut batch)?;
+            conn.write_opt(batch, &options.write_opts)
+                .map_err(|err| err.to_string())?;
+        },
+        None => {
+            let mut batch = column_families::route(conn, write_batch.batch);
+            delete_expired(&mut batch)?;
+            current_state.put_batch(&mut batch);
+            put_staged(&mut batch);
+            put_bulk_loaded(&mut batch)?;
+            conn.write_opt(batch, &options.write_opts)
+                .map_err(|err| err.to_string())?
+        },
+    };
+
+    Ok(diff)
+}
+
+/// prove_pending commits the writer on the current state in memory, and creates the proof of the query keys,
+/// which are the hashed state keys, for the prospective root. The root is returned with the proof.
+/// Updated nodes are kept in the overlay on the persisted nodes, so that neither the database nor the writer is changed.
+pub fn prove_pending(
+    conn: &rocksdb::DB,
+    writer: &StateWriter,
+    query_keys: &[Vec<u8>],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<(Vec<u8>, smt::Proof), SMTError> {
+    let current_state = CurrentState::get_or_empty(conn, &hasher.empty_hash(0))
+        .map_err(|err| SMTError::Unknown(err.to_string()))?;
+    let updated = writer
+        .get_hashed_updated()
+        .map_err(|err| SMTError::Unknown(err.to_string()))?;
+    let data = smt::UpdateData::new_from(updated);
+    let mut smt_db = OverlaySmtDB::new(conn);
+    let mut tree = smt::SparseMerkleTree::new_with_hasher(
+        current_state.root(),
+        key_length,
+        consts::SUBTREE_HEIGHT,
+        hasher,
+    );
+    let root = tree.commit(&mut smt_db, &data)?;
+    let root = (**root.lock().unwrap()).clone();
+    let mut tree =
+        smt::SparseMerkleTree::new_with_hasher(&root, key_length, consts::SUBTREE_HEIGHT, hasher);
+    let proof = tree.prove(&mut smt_db, query_keys)?;
+
+    Ok((root, proof))
+}
+
+/// prove_snapshot creates the proof of the query keys, which are the hashed state keys, for the root of the latest commit
+/// in the snapshot. The root is returned with the proof. The nodes are read from the snapshot,
+/// so that the proof matches the data read from the same snapshot even after the later commits.
+pub fn prove_snapshot(
+    snapshot: &StateSnapshot,
+    query_keys: &[Vec<u8>],
+    key_length: KeyLength,
+    hasher: HasherKind,
+) -> Result<(Vec<u8>, smt::Proof), SMTError> {
+    let current_state = match snapshot
+        .get(consts::Prefix::CURRENT_STATE)
+        .map_err(|err| SMTError::Unknown(err.to_string()))?
+    {
+        Some(bytes) => {
+            CurrentState::from_bytes(&bytes)
+                .map_err(|err| SMTError::Unknown(err.to_string()))?
+                .0
+        },
+        None => CurrentState::new(&hasher.empty_hash(0), BlockHeight(0)),
+    };
+    let root = current_state.root().to_vec();
+    let mut smt_db = SnapshotSmtDB::new(snapshot);
+    let mut tree =
+        smt::SparseMerkleTree::new_with_hasher(&root, key_length, consts::SUBTREE_HEIGHT, hasher);
+    let proof = tree.prove(&mut smt_db, query_keys)?;
+
+    Ok((root, proof))
+}
+
+impl batch::BatchWriter for HistoricalState<'_> {
+    fn put(&mut self, pair: &SharedKVPair) {
+        self.values
+            .insert(pair.key_as_vec(), Some(pair.value_as_vec()));
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.values.insert(key.to_vec(), None);
+    }
+}
+
+impl<'a> HistoricalState<'a> {
+    /// new rebuilds the state of the height by applying the diffs from the current version in reverse.
+    /// It fails with HeightNotRetained if the height is above the current version or a diff in between is not stored.
+    pub fn new(
+        conn: &'a rocksdb::DB,
+        height: BlockHeight,
+        key_length: KeyLength,
+        hasher: HasherKind,
+    ) -> Result<Self, DataStoreError> {
+        let snapshot = StateSnapshot::new(conn);
+        let current_state = check_consistency::current_state(&snapshot, hasher)
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        if u32::from(height) > u32::from(current_state.version()) {
+            return Err(DataStoreError::HeightNotRetained(height.into()));
+        }
+        let mut state = Self {
+            snapshot,
+            root: current_state.root().to_vec(),
+            hasher,
+            nodes: HashMap::new(),
+            values: HashMap::new(),
+        };
+        let mut version = current_state.version();
+        while u32::from(version) > u32::from(height) {
+            let diff_key = [consts::Prefix::DIFF, &version.to_be_bytes()].concat();
+            let diff_bytes = state
+                .snapshot
+                .get(&diff_key)
+                .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+                .ok_or_else(|| DataStoreError::HeightNotRetained(height.into()))?;
+            let diff =
+                check_diffs::read_snapshot_diff(&state.snapshot, version.into(), &diff_bytes)?;
+            let mut smt_db =
+                SnapshotSmtDB::with_overlay(&state.snapshot, std::mem::take(&mut state.nodes));
+            let root = smt_apply_diff(&mut smt_db, &state.root, key_length, hasher, &diff)
+                .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+            state.nodes = smt_db.into_overlay();
+            state.root = (**root.lock().unwrap()).clone();
+            diff.revert_commit(&mut state);
+            version = version - BlockHeight(1);
+        }
+
+        Ok(state)
+    }
+
+    /// root returns the state root of the height.
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// get returns the value of the state key at the height.
+    pub fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        match self.values.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self
+                .snapshot
+                .get(&Kind::State.key(key.to_vec()))?
+                .map(value_compression::decode_vec)),
+        }
+    }
+
+    /// prove creates the proof of the query keys, which are the hashed state keys, for the root of the height.
+    pub fn prove(
+        &mut self,
+        query_keys: &[Vec<u8>],
+        key_length: KeyLength,
+    ) -> Result<smt::Proof, SMTError> {
+        let mut tree = smt::SparseMerkleTree::new_with_hasher(
+            &self.root,
+            key_length,
+            consts::SUBTREE_HEIGHT,
+            self.hasher,
+        );
+        let mut smt_db =
+            SnapshotSmtDB::with_overlay(&self.snapshot, std::mem::take(&mut self.nodes));
+        let proof = tree.prove(&mut smt_db, query_keys);
+        self.nodes = smt_db.into_overlay();
+        proof
+    }
+}
+
+/// StateStore is the authenticated state storage on the rocksdb connection.
+/// Unlike StateDB, all the operations run on the calling thread.
+pub struct StateStore {
+    conn: rocksdb::DB,
+    options: DbOptions,
+    /// bulk_load commits without the diffs.
+    bulk_load: bool,
+}
+
+impl StateStore {
+    /// open opens the database at the path with the column families.
+    /// Database written by an older format, including the legacy keyspace, fails with NeedsMigration
+    /// unless auto_migrate is set. The legacy current state is upgraded unless readonly.
+    pub fn open(path: impl AsRef<Path>, options: DbOptions) -> Result<Self, MigrationError> {
+        let mut option = rocksdb::Options::default();
+        option.create_if_missing(true);
+        options.tuning.apply(&mut option);
+        let conn = column_families::open_with_state_options(
+            &option,
+            &option,
+            path,
+            options.is_readonly(),
+            options.auto_migrate,
+        )?;
+        // the roots computed by the migrations depend on the hasher
+        CurrentState::check_hasher(&conn, options.hasher)?;
+        migrations::prepare(&conn, &options, &mut |_, _| {})?;
+        if !options.is_readonly() {
+            // Records which cannot be decoded are kept as it is, and reported when reading them
+            if let Err(CurrentStateError::Database(err)) = CurrentState::migrate(&conn) {
+                return Err(err.into());
+            }
+        }
+
+        Ok(Self {
+            conn,
+            options,
+            bulk_load: false,
+        })
+    }
+
+    /// conn returns the rocksdb connection.
+    pub fn conn(&self) -> &rocksdb::DB {
+        &self.conn
+    }
+
+    fn check_writable(&self) -> Result<(), DataStoreError> {
+        if self.options.is_readonly() {
+            return Err(DataStoreError::Readonly);
+        }
+        Ok(())
+    }
+
+    /// get returns the committed value of the state key.
+    pub fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+        Ok(
+            column_families::get(&self.conn, &Kind::State.key(key.to_vec()))?
+                .map(value_compression::decode_vec),
+        )
+    }
+
+    /// current_state returns the root and the version of the latest commit.
+    pub fn current_state(&self) -> Result<CurrentState, CurrentStateError> {
+        CurrentState::get_or_empty(&self.conn, &self.options.hasher.empty_hash(0))
+    }
+
+    /// get_with_writer returns the value from the writer, or the committed value which is cached to the writer.
+    pub fn get_with_writer(
+        &self,
+        writer: &mut StateWriter,
+        key: &[u8],
+    ) -> Result<VecOption, DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+                .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+        };
+        read_writer_db::get_with_writer(writer, key, stored).map_err(DataStoreError::from)
+    }
+
+    /// upsert sets the value of the key in the writer, caching the committed value first.
+    pub fn upsert(
+        &self,
+        writer: &mut StateWriter,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+                .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+        };
+        read_writer_db::upsert(writer, key, value, stored).map_err(DataStoreError::from)
+    }
+
+    /// delete marks the key as deleted in the writer, caching the committed value first.
+    pub fn delete(&self, writer: &mut StateWriter, key: &[u8]) -> Result<(), DataStoreError> {
+        let stored = if writer.is_cached(key)? {
+            None
+        } else {
+            self.get(key)
+                .map_err(|err| DataStoreError::Unknown(err.to_string()))?
+        };
+        read_writer_db::delete_with_writer(writer, key, stored).map_err(DataStoreError::from)
+    }
+
+    /// commit writes the writer as the version on top of prev_root, and returns the new root.
+    pub fn commit(
+        &self,
+        writer: &StateWriter,
+        version: BlockHeight,
+        prev_root: &[u8],
+    ) -> Result<Vec<u8>, DataStoreError> {
+        self.check_writable()?;
+        let data = smt::UpdateData::new_from(writer.get_hashed_updated()?);
+        let mut smt_db = SmtDB::new(&self.conn);
+        let mut tree = smt::SparseMerkleTree::new_with_hasher(
+            prev_root,
+            self.options.key_length(),
+            consts::SUBTREE_HEIGHT,
+            self.options.hasher,
+        );
+        let root = tree
+            .commit(&mut smt_db, &data)
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        let root = (**root.lock().unwrap()).clone();
+        let options = CommitWriteOption {
+            keep_diff_for_heights: self.options.keep_diff_for_heights,
+            max_batch_bytes: None,
+            write_opts: self.options.durability.write_options(false),
+            staged_id: None,
+            value_ref_threshold: self.options.diff_value_ref_threshold,
+            value_compression: self.options.value_compression,
+            bulk_load: self.bulk_load,
+            hasher: self.options.hasher,
+        };
+        write_commit(&self.conn, &smt_db, writer, &root, version, &options)
+            .map_err(DataStoreError::Unknown)?;
+
+        Ok(root)
+    }
+
+    /// set_bulk_load switches the bulk load of the initial sync, where the commits write no diff and cannot be reverted.
+    /// The rocksdb options are restored and the database is compacted when it is turned off.
+    pub fn set_bulk_load(&mut self, enabled: bool) -> Result<(), DataStoreError> {
+        self.check_writable()?;
+        if self.bulk_load == enabled {
+            return Ok(());
+        }
+        bulk_load::switch(&self.conn, enabled, &self.options.tuning)
+            .map_err(|err| DataStoreError::Unknown(err.to_string()))?;
+        self.bulk_load = enabled;
+        Ok(())
+    }
+
+    /// revert reverts the commit of the version on state_root, and returns the previous root.
+    pub fn revert(
+        &self,
+        version: BlockHeight,
+        state_root: &[u8],
+    ) -> Result<Vec<u8>, DataStoreError> {
+        self.check_writable()?;
+        let root = revert(&self.conn, version, state_root, &[], &self.options)?;
+        let root = (**root.lock().unwrap()).clone();
+        Ok(root)
+    }
+
+    /// prove creates the proof of the query keys, which are the hashed state keys, for the root.
+    pub fn prove(&self, root: &[u8], query_keys: &[Vec<u8>]) -> Result<smt::Proof, SMTError> {
+        let mut tree = smt::SparseMerkleTree::new_with_hasher(
+            root,
+            self.options.key_length(),
+            consts::SUBTREE_HEIGHT,
+            self.options.hasher,
+        );
+        let mut smt_db = SmtDB::new(&self.conn);
+        tree.prove(&mut smt_db, query_keys)
+    }
+
+    /// prove_pending creates the proof of the query keys for the root of committing the writer on the current state.
+    pub fn prove_pending(
+        &self,
+        writer: &StateWriter,
+        query_keys: &[Vec<u8>],
+    ) -> Result<(Vec<u8>, smt::Proof), SMTError> {
+        prove_pending(
+            &self.conn,
+            writer,
+            query_keys,
+            self.options.key_length(),
+            self.options.hasher,
+        )
+    }
+
+    /// historical_state rebuilds the state of the past height from the stored diffs.
+    pub fn historical_state(
+        &self,
+        height: BlockHeight,
+    ) -> Result<HistoricalState, DataStoreError> {
+        HistoricalState::new(
+            &self.conn,
+            height,
+            self.options.key_length(),
+            self.options.hasher,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::error::{self, DbError};
+    use crate::state::diff_values;
+    use crate::state::test_utils::{open, state_key};
+    use crate::types::{HashKind, HashWithKind};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    #[test]
+    fn test_state_store_commit_and_revert() {
+        let temp_dir = TempDir::new("test_state_store_commit").unwrap();
+        let store = open(&temp_dir, false);
+
+        let mut writer = StateWriter::default();
+        for i in 0..3 {
+            store.upsert(&mut writer, &state_key(i), &[i; 4]).unwrap();
+        }
+        let root1 = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+        let current_state = store.current_state().unwrap();
+        assert_eq!(current_state.root(), root1.as_slice());
+        assert_eq!(current_state.version(), BlockHeight(1));
+        assert_eq!(current_state.commit_count(), 1);
+        assert!(current_state.reverted_from().is_empty());
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+
+        let mut writer = StateWriter::default();
+        assert_eq!(
+            store.get_with_writer(&mut writer, &state_key(0)).unwrap(),
+            Some(vec![0; 4])
+        );
+        store.upsert(&mut writer, &state_key(0), &[9; 4]).unwrap();
+        store.delete(&mut writer, &state_key(1)).unwrap();
+        assert_eq!(
+            store.get_with_writer(&mut writer, &state_key(1)).unwrap(),
+            None
+        );
+        let root2 = store.commit(&writer, BlockHeight(2), &root1).unwrap();
+        assert_ne!(root1, root2);
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![9; 4]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+
+        let query_keys: Vec<Vec<u8>> = [0, 1, 5]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = store.prove(&root2, &query_keys).unwrap();
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, &root2, KEY_LENGTH).unwrap());
+
+        assert_eq!(store.revert(BlockHeight(2), &root2).unwrap(), root1);
+        let current_state = store.current_state().unwrap();
+        assert_eq!(current_state.root(), root1.as_slice());
+        assert_eq!(current_state.version(), BlockHeight(1));
+        // revert keeps the commit count, and records the root reverted from
+        assert_eq!(current_state.commit_count(), 2);
+        assert_eq!(current_state.reverted_from(), root2.as_slice());
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![0; 4]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+        assert!(matches!(
+            store.revert(BlockHeight(2), &root1),
+            Err(DataStoreError::DiffNotFound(2))
+        ));
+    }
+
+    #[test]
+    fn test_state_store_diff_not_available() {
+        let temp_dir = TempDir::new("test_state_store_diff_not_available").unwrap();
+        let mut options = DbOptions::new(false, KEY_LENGTH);
+        options.keep_diff_for_heights = Some(1);
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=3 {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(0), &[height; 4])
+                .unwrap();
+            let root = store
+                .commit(&writer, BlockHeight(height.into()), roots.last().unwrap())
+                .unwrap();
+            roots.push(root);
+        }
+        assert_eq!(store.revert(BlockHeight(3), &roots[3]).unwrap(), roots[2]);
+        assert_eq!(store.revert(BlockHeight(2), &roots[2]).unwrap(), roots[1]);
+        // the diff of the height 1 is removed by the commit of the height 3
+        let err = store.revert(BlockHeight(1), &roots[1]).unwrap_err();
+        assert!(matches!(err, DataStoreError::DiffNotAvailable(1)));
+        assert_eq!(DbError::from(err).code(), error::ERR_NOT_RETAINED);
+    }
+
+    #[test]
+    fn test_write_commit_in_chunks_with_kept_diffs() {
+        let temp_dir = TempDir::new("test_write_commit_in_chunks").unwrap();
+        let store = open(&temp_dir, false);
+        // commit as StateStore::commit, in chunks and removing the diffs out of the window with the range deletion
+        let commit = |writer: &StateWriter, version: u32, prev_root: &[u8]| -> Vec<u8> {
+            let data = smt::UpdateData::new_from(writer.get_hashed_updated().unwrap());
+            let mut smt_db = SmtDB::new(&store.conn);
+            let mut tree =
+                smt::SparseMerkleTree::new(prev_root, KEY_LENGTH, consts::SUBTREE_HEIGHT);
+            let root = tree.commit(&mut smt_db, &data).unwrap();
+            let root = (**root.lock().unwrap()).clone();
+            let options = CommitWriteOption {
+                keep_diff_for_heights: Some(1),
+                max_batch_bytes: Some(64),
+                write_opts: rocksdb::WriteOptions::default(),
+                staged_id: None,
+                value_ref_threshold: None,
+                value_compression: None,
+                bulk_load: false,
+                hasher: HasherKind::Sha256,
+            };
+            write_commit(
+                &store.conn,
+                &smt_db,
+                writer,
+                &root,
+                BlockHeight(version),
+                &options,
+            )
+            .unwrap();
+            root
+        };
+
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=3_u8 {
+            let mut writer = StateWriter::default();
+            for i in 0..5 {
+                store
+                    .upsert(&mut writer, &state_key(i), &[height; 40])
+                    .unwrap();
+            }
+            let root = commit(&writer, height.into(), roots.last().unwrap());
+            roots.push(root);
+        }
+
+        let diff_key = |height: u32| [consts::Prefix::DIFF, &height.to_be_bytes()].concat();
+        assert_eq!(
+            column_families::get(&store.conn, &diff_key(1)).unwrap(),
+            None
+        );
+        for height in 2..=3 {
+            assert!(column_families::get(&store.conn, &diff_key(height))
+                .unwrap()
+                .is_some());
+        }
+        assert_eq!(store.get(&state_key(4)).unwrap(), Some(vec![3; 40]));
+        assert_eq!(store.current_state().unwrap().root(), roots[3].as_slice());
+        assert_eq!(store.conn.get(consts::Prefix::JOURNAL).unwrap(), None);
+        // all the nodes of the last root are written
+        let query_keys: Vec<Vec<u8>> = (0..5)
+            .map(|i| state_key(i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = store.prove(&roots[3], &query_keys).unwrap();
+        assert!(
+            smt::SparseMerkleTree::verify(&query_keys, &proof, &roots[3], KEY_LENGTH).unwrap()
+        );
+
+        assert_eq!(store.revert(BlockHeight(3), &roots[3]).unwrap(), roots[2]);
+        assert_eq!(store.get(&state_key(4)).unwrap(), Some(vec![2; 40]));
+        assert_eq!(store.revert(BlockHeight(2), &roots[2]).unwrap(), roots[1]);
+    }
+
+    #[test]
+    fn test_state_store_empty_value() {
+        let temp_dir = TempDir::new("test_state_store_empty_value").unwrap();
+        let store = open(&temp_dir, false);
+
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[1; 4]).unwrap();
+        let root1 = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+
+        // empty value is rejected for both the existing and the new key, and the writer is not changed
+        let mut writer = StateWriter::default();
+        for i in 0..2 {
+            assert!(matches!(
+                store.upsert(&mut writer, &state_key(i), &[]),
+                Err(DataStoreError::EmptyValue)
+            ));
+        }
+        assert_eq!(
+            store.get_with_writer(&mut writer, &state_key(0)).unwrap(),
+            Some(vec![1; 4])
+        );
+        assert_eq!(
+            store.get_with_writer(&mut writer, &state_key(1)).unwrap(),
+            None
+        );
+        store.upsert(&mut writer, &state_key(2), &[2; 4]).unwrap();
+        let root2 = store.commit(&writer, BlockHeight(2), &root1).unwrap();
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![1; 4]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+
+        // the proof agrees with get: inclusion of the existing key, and non-inclusion of the rejected new key
+        let query_keys: Vec<Vec<u8>> = [0, 1]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = store.prove(&root2, &query_keys).unwrap();
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, &root2, KEY_LENGTH).unwrap());
+        assert_eq!(proof.queries[0].key(), query_keys[0].as_slice());
+        assert_eq!(
+            proof.queries[0].value(),
+            vec![1; 4].hash_with_kind(HashKind::Value).as_slice()
+        );
+        assert!(
+            proof.queries[1].key() != query_keys[1].as_slice()
+                || proof.queries[1].value().is_empty()
+        );
+
+        assert_eq!(store.revert(BlockHeight(2), &root2).unwrap(), root1);
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![1; 4]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+        assert_eq!(store.get(&state_key(2)).unwrap(), None);
+
+        // empty value written with the raw handle, such as the one stored before the check, is read as it is
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put(Kind::State.key(state_key(3)), []);
+        column_families::write(store.conn(), batch).unwrap();
+        assert_eq!(store.get(&state_key(3)).unwrap(), Some(vec![]));
+        let mut writer = StateWriter::default();
+        assert_eq!(
+            store.get_with_writer(&mut writer, &state_key(3)).unwrap(),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_state_store_diff_value_refs() {
+        let temp_dir = TempDir::new("test_state_store_diff_value_refs").unwrap();
+        let mut options = DbOptions::new(false, KEY_LENGTH);
+        options.diff_value_ref_threshold = Some(4);
+        options.keep_diff_for_heights = Some(1);
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+        let hash = |value: u8| vec![value; 4].hash_with_kind(HashKind::Value);
+
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=3 {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(0), &[height; 4])
+                .unwrap();
+            if height < 3 {
+                store
+                    .upsert(&mut writer, &state_key(1), &[height; 4])
+                    .unwrap();
+            } else {
+                store.delete(&mut writer, &state_key(1)).unwrap();
+            }
+            let root = store
+                .commit(&writer, BlockHeight(height.into()), roots.last().unwrap())
+                .unwrap();
+            roots.push(root);
+        }
+        // same old value of the keys is stored once
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(1)).unwrap(), 2);
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(2)).unwrap(), 2);
+        let stats = check_diffs::diff_stats(store.conn(), 3).unwrap();
+        assert_eq!(stats.updated.count, 1);
+        assert_eq!(stats.updated.referenced, 1);
+        assert_eq!(stats.deleted.count, 1);
+        assert_eq!(stats.deleted.value_bytes, 4);
+        assert!(check_diffs::check_diffs(store.conn())
+            .unwrap()
+            .corrupted
+            .is_empty());
+
+        // revert resolves the values, and removes the references of the diff
+        assert_eq!(store.revert(BlockHeight(3), &roots[3]).unwrap(), roots[2]);
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![2; 4]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![2; 4]));
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(2)).unwrap(), 0);
+
+        // the references are removed with the diffs out of the window
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[3; 4]).unwrap();
+        let root = store.commit(&writer, BlockHeight(3), &roots[2]).unwrap();
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[4; 4]).unwrap();
+        store.commit(&writer, BlockHeight(4), &root).unwrap();
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(1)).unwrap(), 0);
+        assert_eq!(diff_values::get(store.conn(), &hash(1)).unwrap(), None);
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(2)).unwrap(), 1);
+        assert_eq!(diff_values::ref_count(store.conn(), &hash(3)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_state_store_value_compression() {
+        let stored = |store: &StateStore, i: u8| {
+            column_families::get(store.conn(), &Kind::State.key(state_key(i)))
+                .unwrap()
+                .unwrap()
+        };
+        // database is written without the compression first
+        let temp_dir = TempDir::new("test_state_store_value_compression").unwrap();
+        let store = open(&temp_dir, false);
+        let mut writer = StateWriter::default();
+        store
+            .upsert(&mut writer, &state_key(0), &[1; 1000])
+            .unwrap();
+        let root1 = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+        assert_eq!(stored(&store, 0), vec![1; 1000]);
+        drop(store);
+
+        let mut options = DbOptions::new(false, KEY_LENGTH);
+        options.value_compression = Some(ValueCompression { threshold: 64 });
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+        let mut writer = StateWriter::default();
+        store
+            .upsert(&mut writer, &state_key(0), &[2; 1000])
+            .unwrap();
+        store
+            .upsert(&mut writer, &state_key(1), &[3; 1000])
+            .unwrap();
+        store.upsert(&mut writer, &state_key(2), &[4; 8]).unwrap();
+        let root2 = store.commit(&writer, BlockHeight(2), &root1).unwrap();
+        assert_eq!(stored(&store, 1)[0], value_compression::MARKER);
+        assert!(stored(&store, 1).len() < 1000);
+        assert_eq!(stored(&store, 2), vec![4; 8]);
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![2; 1000]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![3; 1000]));
+
+        // root and proof are of the uncompressed values
+        let uncompressed_dir = TempDir::new("test_state_store_value_compression_raw").unwrap();
+        let uncompressed = open(&uncompressed_dir, false);
+        let mut writer = StateWriter::default();
+        uncompressed
+            .upsert(&mut writer, &state_key(0), &[2; 1000])
+            .unwrap();
+        uncompressed
+            .upsert(&mut writer, &state_key(1), &[3; 1000])
+            .unwrap();
+        uncompressed
+            .upsert(&mut writer, &state_key(2), &[4; 8])
+            .unwrap();
+        assert_eq!(
+            uncompressed
+                .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+                .unwrap(),
+            root2
+        );
+        let query_keys = vec![state_key(1).hash_with_kind(HashKind::Key)];
+        let proof = store.prove(&root2, &query_keys).unwrap();
+        assert_eq!(
+            proof.queries[0].value(),
+            vec![3; 1000].hash_with_kind(HashKind::Value).as_slice()
+        );
+
+        // old value of the mixed database is restored by revert
+        let mut writer = StateWriter::default();
+        store.delete(&mut writer, &state_key(1)).unwrap();
+        let root3 = store.commit(&writer, BlockHeight(3), &root2).unwrap();
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+        assert_eq!(store.revert(BlockHeight(3), &root3).unwrap(), root2);
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![3; 1000]));
+        assert_eq!(store.revert(BlockHeight(2), &root2).unwrap(), root1);
+        assert_eq!(store.get(&state_key(0)).unwrap(), Some(vec![1; 1000]));
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+        assert_eq!(stored(&store, 0)[0], value_compression::MARKER);
+    }
+
+    #[test]
+    fn test_state_store_bulk_load() {
+        let bulk_dir = TempDir::new("test_state_store_bulk_load").unwrap();
+        let plain_dir = TempDir::new("test_state_store_bulk_load_plain").unwrap();
+        let mut bulk = open(&bulk_dir, false);
+        let plain = open(&plain_dir, false);
+
+        bulk.set_bulk_load(true).unwrap();
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=4_u8 {
+            if height == 3 {
+                bulk.set_bulk_load(false).unwrap();
+            }
+            let mut bulk_writer = StateWriter::default();
+            let mut plain_writer = StateWriter::default();
+            for store_writer in [(&bulk, &mut bulk_writer), (&plain, &mut plain_writer)] {
+                let (store, writer) = store_writer;
+                store.upsert(writer, &state_key(0), &[height; 4]).unwrap();
+                store
+                    .upsert(writer, &state_key(height), &[height; 4])
+                    .unwrap();
+                if height > 1 {
+                    store.delete(writer, &state_key(height - 1)).unwrap();
+                }
+            }
+            let prev_root = roots.last().unwrap();
+            let root = bulk
+                .commit(&bulk_writer, BlockHeight(height.into()), prev_root)
+                .unwrap();
+            // roots do not depend on the bulk load
+            assert_eq!(
+                root,
+                plain
+                    .commit(&plain_writer, BlockHeight(height.into()), prev_root)
+                    .unwrap()
+            );
+            roots.push(root);
+        }
+
+        let diff_exists = |height: u32| {
+            column_families::get(
+                bulk.conn(),
+                &[consts::Prefix::DIFF, &height.to_be_bytes()].concat(),
+            )
+            .unwrap()
+            .is_some()
+        };
+        assert!(!diff_exists(1));
+        assert!(!diff_exists(2));
+        assert!(diff_exists(3));
+        assert_eq!(
+            root_history::get(bulk.conn(), BlockHeight(2)).unwrap(),
+            RootAt::Recorded(roots[2].clone())
+        );
+
+        // heights after the bulk load are reverted as usual
+        assert_eq!(bulk.revert(BlockHeight(4), &roots[4]).unwrap(), roots[3]);
+        assert_eq!(bulk.revert(BlockHeight(3), &roots[3]).unwrap(), roots[2]);
+        assert!(matches!(
+            bulk.revert(BlockHeight(2), &roots[2]),
+            Err(DataStoreError::BulkLoaded {
+                height: 2,
+                start: 1,
+                end: 2
+            })
+        ));
+        assert_eq!(bulk.current_state().unwrap().root(), roots[2].as_slice());
+        assert_eq!(bulk.get(&state_key(2)).unwrap(), Some(vec![2; 4]));
+    }
+
+    #[test]
+    fn test_state_store_historical_state() {
+        let temp_dir = TempDir::new("test_state_store_historical_state").unwrap();
+        let store = open(&temp_dir, false);
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=5 {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(0), &[height; 4])
+                .unwrap();
+            store
+                .upsert(&mut writer, &state_key(height), &[height; 4])
+                .unwrap();
+            if height == 4 {
+                store.delete(&mut writer, &state_key(1)).unwrap();
+            }
+            let root = store
+                .commit(&writer, BlockHeight(height.into()), roots.last().unwrap())
+                .unwrap();
+            roots.push(root);
+        }
+
+        let mut state = store.historical_state(BlockHeight(3)).unwrap();
+        assert_eq!(state.root(), roots[3].as_slice());
+        assert_eq!(state.get(&state_key(0)).unwrap(), Some(vec![3; 4]));
+        assert_eq!(state.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+        assert_eq!(state.get(&state_key(4)).unwrap(), None);
+        let query_keys: Vec<Vec<u8>> = [0, 1, 4]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let proof = state.prove(&query_keys, KEY_LENGTH).unwrap();
+        assert!(
+            smt::SparseMerkleTree::verify(&query_keys, &proof, &roots[3], KEY_LENGTH).unwrap()
+        );
+        // the database is not changed
+        let current_state = store.current_state().unwrap();
+        assert_eq!(current_state.root(), roots[5].as_slice());
+        assert_eq!(current_state.version(), BlockHeight(5));
+        assert_eq!(store.get(&state_key(1)).unwrap(), None);
+
+        // the state is read from the snapshot taken on rebuilding, so that the revert does not change it
+        let mut state = store.historical_state(BlockHeight(5)).unwrap();
+        assert_eq!(store.revert(BlockHeight(5), &roots[5]).unwrap(), roots[4]);
+        assert_eq!(state.get(&state_key(5)).unwrap(), Some(vec![5; 4]));
+        let proof = state.prove(&query_keys, KEY_LENGTH).unwrap();
+        assert!(
+            smt::SparseMerkleTree::verify(&query_keys, &proof, &roots[5], KEY_LENGTH).unwrap()
+        );
+        drop(state);
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[5; 4]).unwrap();
+        store.upsert(&mut writer, &state_key(5), &[5; 4]).unwrap();
+        let root = store.commit(&writer, BlockHeight(5), &roots[4]).unwrap();
+        assert_eq!(root, roots[5]);
+
+        let state = store.historical_state(BlockHeight(5)).unwrap();
+        assert_eq!(state.root(), roots[5].as_slice());
+        assert!(matches!(
+            store.historical_state(BlockHeight(6)),
+            Err(DataStoreError::HeightNotRetained(6))
+        ));
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete([consts::Prefix::DIFF, &BlockHeight(2).to_be_bytes()].concat());
+        column_families::write(store.conn(), batch).unwrap();
+        assert!(matches!(
+            store.historical_state(BlockHeight(1)),
+            Err(DataStoreError::HeightNotRetained(1))
+        ));
+    }
+
+    #[test]
+    fn test_state_store_prove_pending() {
+        let temp_dir = TempDir::new("test_state_store_prove_pending").unwrap();
+        let store = open(&temp_dir, false);
+        let mut writer = StateWriter::default();
+        for i in 0..4 {
+            store.upsert(&mut writer, &state_key(i), &[i; 4]).unwrap();
+        }
+        let prev_root = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(0), &[9; 4]).unwrap();
+        store.delete(&mut writer, &state_key(1)).unwrap();
+        store.upsert(&mut writer, &state_key(4), &[4; 4]).unwrap();
+        let query_keys: Vec<Vec<u8>> = [0, 1, 2, 4, 5]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let (root, proof) = store.prove_pending(&writer, &query_keys).unwrap();
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, &root, KEY_LENGTH).unwrap());
+        // the deleted key is proven not to exist
+        let deleted = &proof.queries[1];
+        assert!(deleted.pair.key() != query_keys[1].as_slice() || deleted.pair.value().is_empty());
+        // the database and the writer are not changed
+        assert_eq!(store.current_state().unwrap().root(), prev_root.as_slice());
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+        assert!(writer.is_deleted(&state_key(1)).unwrap());
+
+        let committed = store.commit(&writer, BlockHeight(2), &prev_root).unwrap();
+        assert_eq!(root, committed);
+        let committed_proof = store.prove(&committed, &query_keys).unwrap();
+        assert_eq!(proof.sibling_hashes, committed_proof.sibling_hashes);
+    }
+
+    #[test]
+    fn test_prove_snapshot() {
+        let temp_dir = TempDir::new("test_prove_snapshot").unwrap();
+        let store = open(&temp_dir, false);
+        let mut writer = StateWriter::default();
+        for i in 0..3 {
+            store
+                .upsert(&mut writer, &state_key(i), &[i + 1; 4])
+                .unwrap();
+        }
+        let root = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+        let snapshot = StateSnapshot::new(store.conn());
+
+        // the commits after the snapshot update and remove the nodes of the root
+        let mut prev_root = root.clone();
+        for height in 2..=3 {
+            let mut writer = StateWriter::default();
+            store
+                .upsert(&mut writer, &state_key(0), &[height as u8; 4])
+                .unwrap();
+            store.delete(&mut writer, &state_key(1)).unwrap();
+            store
+                .upsert(&mut writer, &state_key(4), &[height as u8; 4])
+                .unwrap();
+            prev_root = store
+                .commit(&writer, BlockHeight(height), &prev_root)
+                .unwrap();
+        }
+
+        let query_keys: Vec<Vec<u8>> = [0, 1, 4]
+            .iter()
+            .map(|i| state_key(*i).hash_with_kind(HashKind::Key))
+            .collect();
+        let (snapshot_root, proof) =
+            prove_snapshot(&snapshot, &query_keys, KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert_eq!(snapshot_root, root);
+        assert!(smt::SparseMerkleTree::verify(&query_keys, &proof, &root, KEY_LENGTH).unwrap());
+        assert_eq!(
+            proof.queries[0].value(),
+            vec![1; 4].hash_with_kind(HashKind::Value).as_slice()
+        );
+
+        let latest = StateSnapshot::new(store.conn());
+        let (latest_root, _) =
+            prove_snapshot(&latest, &query_keys, KEY_LENGTH, HasherKind::Sha256).unwrap();
+        assert_eq!(latest_root, prev_root);
+    }
+
+    #[cfg(not(feature = "legacy-keyspace"))]
+    #[test]
+    fn test_state_store_migrates_legacy_keyspace() {
+        let temp_dir = TempDir::new("test_state_store_legacy_keyspace").unwrap();
+        let mut option = rocksdb::Options::default();
+        option.create_if_missing(true);
+        // store on the database without the column families writes all the keys to the default one
+        let legacy = StateStore {
+            conn: rocksdb::DB::open(&option, temp_dir.path()).unwrap(),
+            options: DbOptions::new(false, KEY_LENGTH),
+        };
+        let mut roots = vec![smt::EMPTY_HASH.to_vec()];
+        for height in 1..=2_u8 {
+            let mut writer = StateWriter::default();
+            legacy
+                .upsert(&mut writer, &state_key(height), &[height; 4])
+                .unwrap();
+            let root = legacy
+                .commit(&writer, BlockHeight(height.into()), roots.last().unwrap())
+                .unwrap();
+            roots.push(root);
+        }
+        let legacy_key = Kind::State.key(state_key(1));
+        assert!(legacy.conn().get(&legacy_key).unwrap().is_some());
+        drop(legacy);
+
+        // the database written without the format version is migrated
+        let mut options = DbOptions::new(false, KEY_LENGTH);
+        options.auto_migrate = true;
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+        assert!(column_families::is_enabled(store.conn()));
+        assert_eq!(store.conn().get(&legacy_key).unwrap(), None);
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+        let query_keys = vec![state_key(2).hash_with_kind(HashKind::Key)];
+        let proof = store.prove(&roots[2], &query_keys).unwrap();
+        assert!(
+            smt::SparseMerkleTree::verify(&query_keys, &proof, &roots[2], KEY_LENGTH).unwrap()
+        );
+        // revert reads the diff from the diff column family
+        assert_eq!(store.revert(BlockHeight(2), &roots[2]).unwrap(), roots[1]);
+        assert_eq!(store.get(&state_key(2)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_state_store_readonly() {
+        let temp_dir = TempDir::new("test_state_store_readonly").unwrap();
+        let store = open(&temp_dir, false);
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(1), &[1; 4]).unwrap();
+        let root = store
+            .commit(&writer, BlockHeight(1), &smt::EMPTY_HASH)
+            .unwrap();
+        drop(store);
+
+        let store = open(&temp_dir, true);
+        assert_eq!(store.get(&state_key(1)).unwrap(), Some(vec![1; 4]));
+        assert!(matches!(
+            store.commit(&StateWriter::default(), BlockHeight(2), &root),
+            Err(DataStoreError::Readonly)
+        ));
+        assert!(matches!(
+            store.revert(BlockHeight(1), &root),
+            Err(DataStoreError::Readonly)
+        ));
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_state_store_hasher() {
+        let temp_dir = TempDir::new("test_state_store_hasher").unwrap();
+        let mut options = DbOptions::new(false, KEY_LENGTH);
+        options.hasher = HasherKind::Blake3;
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+        assert_eq!(
+            store.current_state().unwrap().root(),
+            HasherKind::Blake3.empty_hash(0).as_slice()
+        );
+        let mut writer = StateWriter::default();
+        store.upsert(&mut writer, &state_key(1), &[1; 4]).unwrap();
+        let root = store
+            .commit(&writer, BlockHeight(1), &HasherKind::Blake3.empty_hash(0))
+            .unwrap();
+        assert_eq!(store.current_state().unwrap().hasher(), "blake3");
+        drop(store);
+
+        // the hasher of the state is recorded on the commit, and another one is rejected
+        let err = StateStore::open(temp_dir.path(), DbOptions::new(false, KEY_LENGTH))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            MigrationError::CurrentState(CurrentStateError::HasherMismatch { .. })
+        ));
+        assert_eq!(DbError::from(err).code(), error::ERR_INVALID_ARGUMENT);
+
+        let store = StateStore::open(temp_dir.path(), options).unwrap();
+        let query_keys = vec![state_key(1).hash_with_kind(HashKind::Key)];
+        let proof = store.prove(&root, &query_keys).unwrap();
+        assert!(smt::SparseMerkleTree::verify_with_hasher(
+            &query_keys,
+            &proof,
+            &root,
+            KEY_LENGTH,
+            HasherKind::Blake3
+        )
+        .unwrap());
+        assert_eq!(
+            store.revert(BlockHeight(1), &root).unwrap(),
+            HasherKind::Blake3.empty_hash(0)
+        );
+    }
+}
@@ -0,0 +1,40 @@
+/// test_utils provides the fixtures shared by the tests of the state storage.
+use tempdir::TempDir;
+
+use crate::consts;
+use crate::database::types::DbOptions;
+use crate::state::state_writer::StateWriter;
+use crate::state::store::StateStore;
+use crate::types::BlockHeight;
+
+/// state_key returns the key of the default key length in the module 1 and the substore 0.
+pub(crate) fn state_key(i: u8) -> Vec<u8> {
+    [vec![0, 0, 0, 1, 0, 0], vec![i; 32]].concat()
+}
+
+/// open opens the store in the temporary directory with the default key length.
+pub(crate) fn open(temp_dir: &TempDir, readonly: bool) -> StateStore {
+    StateStore::open(
+        temp_dir.path(),
+        DbOptions::new(readonly, consts::KEY_LENGTH),
+    )
+    .unwrap()
+}
+
+/// commit upserts and deletes the state keys at the height on top of the root, and returns the new root.
+pub(crate) fn commit(
+    store: &StateStore,
+    height: u32,
+    root: &[u8],
+    upserts: impl IntoIterator<Item = (u8, Vec<u8>)>,
+    deletes: impl IntoIterator<Item = u8>,
+) -> Vec<u8> {
+    let mut writer = StateWriter::default();
+    for (i, value) in upserts {
+        store.upsert(&mut writer, &state_key(i), &value).unwrap();
+    }
+    for i in deletes {
+        store.delete(&mut writer, &state_key(i)).unwrap();
+    }
+    store.commit(&writer, BlockHeight(height), root).unwrap()
+}
@@ -0,0 +1,134 @@
+/// value_compression provides the envelope of the state values compressed on commit.
+/// The compressed value is the marker byte followed by the zstd frame, and the other values are stored as they are,
+/// so that the values written without the compression are read as they are from the same database.
+use std::borrow::Cow;
+
+use crate::batch::BatchWriter;
+use crate::database::types::ValueCompression;
+use crate::types::SharedKVPair;
+
+/// MARKER is the first byte of the compressed value.
+pub const MARKER: u8 = 0xfe;
+/// ZSTD_MAGIC is the magic number starting the zstd frame after the marker.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// LEVEL is the zstd compression level of the values.
+const LEVEL: i32 = 3;
+
+/// is_envelope returns true if the stored value starts with the marker and the zstd frame.
+fn is_envelope(stored: &[u8]) -> bool {
+    stored.len() > ZSTD_MAGIC.len()
+        && stored[0] == MARKER
+        && stored[1..=ZSTD_MAGIC.len()] == ZSTD_MAGIC
+}
+
+fn compress(value: &[u8]) -> Option<Vec<u8>> {
+    zstd::encode_all(value, LEVEL)
+        .ok()
+        .map(|frame| [&[MARKER][..], &frame].concat())
+}
+
+/// encode returns the stored value. The value of the threshold or larger is compressed only if it becomes smaller.
+/// The value which looks like the envelope is compressed regardless of the options, so that it is not mistaken for one on read.
+pub fn encode(value: &[u8], compression: Option<ValueCompression>) -> Cow<[u8]> {
+    let escaped = is_envelope(value);
+    let eligible = compression.map_or(false, |compression| value.len() >= compression.threshold);
+    if !escaped && !eligible {
+        return Cow::Borrowed(value);
+    }
+    match compress(value) {
+        Some(compressed) if escaped || compressed.len() < value.len() => Cow::Owned(compressed),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// decode returns the value of the stored value. The stored value which is not the envelope is the value itself.
+pub fn decode(stored: &[u8]) -> Cow<[u8]> {
+    if !is_envelope(stored) {
+        return Cow::Borrowed(stored);
+    }
+    match zstd::decode_all(&stored[1..]) {
+        Ok(value) => Cow::Owned(value),
+        Err(_) => Cow::Borrowed(stored),
+    }
+}
+
+/// decode_vec is decode of the owned value, which is returned without copying if it is not the envelope.
+pub fn decode_vec(stored: Vec<u8>) -> Vec<u8> {
+    let decoded = match decode(&stored) {
+        Cow::Owned(value) => Some(value),
+        Cow::Borrowed(_) => None,
+    };
+    decoded.unwrap_or(stored)
+}
+
+/// CompressedBatch encodes the values put to the batch.
+pub struct CompressedBatch<'a, B: BatchWriter> {
+    batch: &'a mut B,
+    compression: Option<ValueCompression>,
+}
+
+impl<'a, B: BatchWriter> CompressedBatch<'a, B> {
+    pub fn new(batch: &'a mut B, compression: Option<ValueCompression>) -> Self {
+        Self { batch, compression }
+    }
+}
+
+impl<B: BatchWriter> BatchWriter for CompressedBatch<'_, B> {
+    fn put(&mut self, pair: &SharedKVPair) {
+        match encode(pair.value(), self.compression) {
+            Cow::Borrowed(_) => self.batch.put(pair),
+            Cow::Owned(stored) => self.batch.put(&SharedKVPair::new(pair.key(), &stored)),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.batch.delete(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZSTD: Option<ValueCompression> = Some(ValueCompression { threshold: 64 });
+
+    #[test]
+    fn test_encode_threshold() {
+        let small = vec![7; 63];
+        assert_eq!(encode(&small, ZSTD), Cow::Borrowed(&small[..]));
+        let large = vec![7; 1000];
+        let stored = encode(&large, ZSTD);
+        assert_eq!(stored[0], MARKER);
+        assert!(stored.len() < large.len());
+        assert_eq!(decode(&stored), large);
+        // disabled compression stores the value as it is
+        assert_eq!(encode(&large, None), Cow::Borrowed(&large[..]));
+        // value which does not become smaller is stored as it is
+        let distinct: Vec<u8> = (0..64).collect();
+        assert_eq!(encode(&distinct, ZSTD), Cow::Borrowed(&distinct[..]));
+    }
+
+    #[test]
+    fn test_decode_raw() {
+        for raw in [
+            vec![],
+            vec![MARKER],
+            vec![1, 2, 3],
+            vec![MARKER, 0x28, 0xb5, 0x2f, 0xfd, 0],
+        ] {
+            assert_eq!(decode(&raw), Cow::Borrowed(&raw[..]));
+            assert_eq!(decode_vec(raw.clone()), raw);
+        }
+    }
+
+    #[test]
+    fn test_encode_escapes_envelope() {
+        // raw value which is the valid envelope of another value is compressed even below the threshold
+        let envelope = encode(&[7; 1000], ZSTD).into_owned();
+        for compression in [ZSTD, None] {
+            let stored = encode(&envelope, compression);
+            assert_ne!(stored, Cow::Borrowed(&envelope[..]));
+            assert_eq!(decode(&stored), envelope);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+/// verify_nodes provides the integrity check of the stored SMT nodes for the backups.
+/// Each node is checked against its key, so the nodes which are not reachable from any root are checked too.
+use crate::consts::Prefix;
+use crate::database::column_families::StateSnapshot;
+use crate::database::options::VerifyNodesOption;
+use crate::sparse_merkle_tree::hasher::HasherKind;
+use crate::sparse_merkle_tree::smt;
+use crate::types::KeyLength;
+
+/// NodesReport holds the result of the scan.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodesReport {
+    /// checked is the number of the nodes checked.
+    pub checked: u64,
+    /// mismatches is the number of the nodes whose hash does not match the key.
+    pub mismatches: u64,
+    /// mismatched holds the keys of the mismatched nodes without the prefix, up to the max errors.
+    pub mismatched: Vec<Vec<u8>>,
+}
+
+impl NodesReport {
+    /// truncated returns true if more nodes are mismatched than the keys reported.
+    pub fn truncated(&self) -> bool {
+        self.mismatches > self.mismatched.len() as u64
+    }
+}
+
+/// verify_nodes recomputes the hash of all the nodes in the SMT prefix on the snapshot of the database.
+/// Node which cannot be decoded is reported as mismatched.
+/// on_progress is called with the report at every progress interval.
+pub fn verify_nodes(
+    conn: &rocksdb::DB,
+    options: &VerifyNodesOption,
+    key_length: KeyLength,
+    hasher: HasherKind,
+    mut on_progress: impl FnMut(&NodesReport),
+) -> Result<NodesReport, rocksdb::Error> {
+    let snapshot = StateSnapshot::new(conn);
+    let mut read_options = rocksdb::ReadOptions::default();
+    read_options.fill_cache(false);
+    let mode = rocksdb::IteratorMode::From(Prefix::SMT, rocksdb::Direction::Forward);
+
+    let mut report = NodesReport::default();
+    for key_val in snapshot.iterator_opt(Prefix::SMT, mode, read_options) {
+        let (key, value) = key_val?;
+        if !key.starts_with(Prefix::SMT) {
+            break;
+        }
+        let node_key = &key[Prefix::SMT.len()..];
+        let matched = smt::calculate_node_hash_with_hasher(&value, key_length, hasher)
+            .map(|hash| hash == node_key)
+            .unwrap_or(false);
+        report.checked += 1;
+        if !matched {
+            report.mismatches += 1;
+            if report.mismatched.len() < options.max_errors {
+                report.mismatched.push(node_key.to_vec());
+            }
+        }
+        if options.progress_interval > 0 && report.checked % options.progress_interval == 0 {
+            on_progress(&report);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::consts;
+    use crate::database::traits::Actions;
+    use crate::types::{Cache, HashKind, HashWithKind, KVPair, VecOption};
+
+    const KEY_LENGTH: KeyLength = KeyLength(38);
+
+    /// Nodes writes the nodes to the SMT prefix of the database directly.
+    struct Nodes<'a>(&'a rocksdb::DB);
+
+    impl Actions for Nodes<'_> {
+        fn get(&self, key: &[u8]) -> Result<VecOption, rocksdb::Error> {
+            self.0.get([Prefix::SMT, key].concat())
+        }
+
+        fn set(&mut self, pair: &KVPair) -> Result<(), rocksdb::Error> {
+            self.0.put([Prefix::SMT, pair.key()].concat(), pair.value())
+        }
+
+        fn del(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+            self.0.delete([Prefix::SMT, key].concat())
+        }
+    }
+
+    fn options(max_errors: usize) -> VerifyNodesOption {
+        VerifyNodesOption {
+            max_errors,
+            progress_interval: 10,
+        }
+    }
+
+    /// committed_db writes the nodes of the tree with the keys into the database.
+    fn committed_db(temp_dir: &TempDir) -> (rocksdb::DB, Vec<Vec<u8>>) {
+        let db = rocksdb::DB::open_default(temp_dir).unwrap();
+        let mut cache = Cache::new();
+        for i in 0..200_u8 {
+            let key = [vec![0, 0, 0, 1, 0, 0], vec![i; 10]].concat();
+            cache.insert(
+                key.hash_with_kind(HashKind::Key),
+                vec![i; 5].hash_with_kind(HashKind::Value),
+            );
+        }
+        let mut tree = smt::SparseMerkleTree::new(&[], KEY_LENGTH, consts::SUBTREE_HEIGHT);
+        tree.commit(&mut Nodes(&db), &smt::UpdateData::new_from(cache))
+            .unwrap();
+        let mode = rocksdb::IteratorMode::From(Prefix::SMT, rocksdb::Direction::Forward);
+        let keys = db
+            .iterator(mode)
+            .map(|key_val| key_val.unwrap().0)
+            .take_while(|key| key.starts_with(Prefix::SMT))
+            .map(|key| key[Prefix::SMT.len()..].to_vec())
+            .collect();
+        // key in the other prefix is not checked
+        db.put([Prefix::STATE, &[1; 32]].concat(), [1]).unwrap();
+        (db, keys)
+    }
+
+    #[test]
+    fn test_verify_nodes() {
+        let temp_dir = TempDir::new("test_verify_nodes").unwrap();
+        let (db, keys) = committed_db(&temp_dir);
+
+        let mut progress = vec![];
+        let report = verify_nodes(&db, &options(10), KEY_LENGTH, HasherKind::Sha256, |p| {
+            progress.push(p.clone())
+        })
+        .unwrap();
+        assert_eq!(report.checked, keys.len() as u64);
+        assert_eq!(report.mismatches, 0);
+        assert!(!report.truncated());
+        assert_eq!(progress.len(), keys.len() / 10);
+        assert_eq!(progress[0].checked, 10);
+    }
+
+    #[test]
+    fn test_verify_nodes_with_flipped_byte() {
+        let temp_dir = TempDir::new("test_verify_nodes_with_flipped_byte").unwrap();
+        let (db, keys) = committed_db(&temp_dir);
+
+        let stored_key = [Prefix::SMT, &keys[3]].concat();
+        let mut value = db.get(&stored_key).unwrap().unwrap();
+        let index = value.len() - 1;
+        value[index] ^= 0xff;
+        db.put(&stored_key, &value).unwrap();
+        // node which cannot be decoded
+        let truncated_key = [Prefix::SMT, &keys[7]].concat();
+        db.put(&truncated_key, [3, 1]).unwrap();
+
+        let report =
+            verify_nodes(&db, &options(10), KEY_LENGTH, HasherKind::Sha256, |_| {}).unwrap();
+        assert_eq!(report.checked, keys.len() as u64);
+        assert_eq!(report.mismatches, 2);
+        assert_eq!(report.mismatched, vec![keys[3].clone(), keys[7].clone()]);
+        assert!(!report.truncated());
+
+        // error list is bounded
+        let report =
+            verify_nodes(&db, &options(1), KEY_LENGTH, HasherKind::Sha256, |_| {}).unwrap();
+        assert_eq!(report.mismatches, 2);
+        assert_eq!(report.mismatched, vec![keys[3].clone()]);
+        assert!(report.truncated());
+    }
+}
@@ -1,13 +1,17 @@
 use std::collections::HashMap;
 use std::ops::{Add, Sub};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use sha2::{Digest, Sha256};
 
 use crate::codec;
 use crate::consts::PREFIX_BRANCH_HASH;
 
-const PREFIX_SIZE: usize = 6;
+pub(crate) const PREFIX_SIZE: usize = 6;
+/// MODULE_ID_SIZE is the bytes of the module id starting the prefix of the state key.
+pub(crate) const MODULE_ID_SIZE: usize = 4;
+/// STORE_PREFIX_SIZE is the bytes of the store prefix following the module id.
+pub(crate) const STORE_PREFIX_SIZE: usize = PREFIX_SIZE - MODULE_ID_SIZE;
 
 pub type NestedVecGeneric<T> = Vec<Vec<T>>;
 pub type NestedVec = NestedVecGeneric<u8>;
@@ -17,6 +21,7 @@ pub type Cache = HashMap<Vec<u8>, Vec<u8>>;
 pub type VecOption = Option<Vec<u8>>;
 pub type SharedVec = Arc<Mutex<Arc<Vec<u8>>>>;
 pub type ArcMutex<T> = Arc<Mutex<T>>;
+pub type ArcRwLock<T> = Arc<RwLock<T>>;
 pub type CommitOptions = Options<BlockHeight>;
 
 // Strong type of SMT with max value KEY_LENGTH * 8
@@ -35,10 +40,10 @@ pub struct StructurePosition(pub u16);
 #[derive(Clone, Debug, Copy)]
 pub struct SubtreeHeight(pub SubtreeHeightKind);
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub struct KeyLength(pub u16);
 
-// Options is a base class for type CommitOptions and DbOptions
+// Options is a base class for type CommitOptions
 #[derive(Debug, Copy, Clone)]
 pub struct Options<T> {
     readonly: bool,
@@ -255,7 +260,7 @@ impl From<StructurePosition> for Height {
     }
 }
 
-impl HashWithKind for Vec<u8> {
+impl HashWithKind for [u8] {
     fn hash_with_kind(&self, kind: HashKind) -> Vec<u8> {
         let mut hasher = Sha256::new();
         match kind {
@@ -364,16 +369,6 @@ impl Height {
     pub fn is_equal_to(self, value: u16) -> bool {
         self.0 == value
     }
-
-    #[inline]
-    pub fn div_to_usize(self, value: u16) -> usize {
-        (self.0 / value) as usize
-    }
-
-    #[inline]
-    pub fn mod_to_u8(self, value: u16) -> u8 {
-        (self.0 % value) as u8
-    }
 }
 
 impl BlockHeight {
@@ -1,4 +1,11 @@
+#[cfg(feature = "testing")]
+use std::cell::RefCell;
 use std::cmp;
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(feature = "testing")]
+use std::path::PathBuf;
 
 use bitvec::prelude::*;
 
@@ -48,11 +55,8 @@ pub fn is_bools_equal(a: &[bool], b: &[bool]) -> bool {
 }
 
 pub fn bools_to_bytes(a: &[bool]) -> Vec<u8> {
-    let mut result = vec![0; (a.len() + 7) / 8];
-    let mut missing_byte = 0;
-    if a.len() % 8 != 0 {
-        missing_byte = 8 - a.len() % 8;
-    }
+    let missing_byte = (8 - a.len() % 8) % 8;
+    let mut result = vec![0; (a.len() + missing_byte) / 8];
     let mut target = vec![false; missing_byte];
     target.extend(a);
 
@@ -99,15 +103,6 @@ pub fn strip_left_false(a: &[bool]) -> Vec<bool> {
     result
 }
 
-pub fn bytes_in(list: &[Vec<u8>], a: &[u8]) -> bool {
-    for v in list {
-        if is_bytes_equal(v, a) {
-            return true;
-        }
-    }
-    false
-}
-
 pub fn array_equal_bool(a: &[bool], b: &[bool]) -> bool {
     if a.len() != b.len() {
         return false;
@@ -120,15 +115,6 @@ pub fn array_equal_bool(a: &[bool], b: &[bool]) -> bool {
     true
 }
 
-pub fn have_all_arrays_same_length<T>(list: &[&[T]], length: usize) -> bool {
-    for v in list {
-        if v.len() != length {
-            return false;
-        }
-    }
-    true
-}
-
 pub fn binary_search<T>(list: &[T], callback: impl Fn(&T) -> bool) -> i32 {
     let mut lo = -1;
     let mut hi = list.len() as i32;
@@ -143,10 +129,57 @@ pub fn binary_search<T>(list: &[T], callback: impl Fn(&T) -> bool) -> i32 {
     hi
 }
 
+#[cfg(feature = "testing")]
+thread_local! {
+    /// SYNCED_PATHS records the paths given to sync_path on the current thread.
+    static SYNCED_PATHS: RefCell<Vec<PathBuf>> = RefCell::new(vec![]);
+}
+
+/// take_synced_paths returns the paths synced by sync_path on the current thread since the last call.
+#[cfg(feature = "testing")]
+pub fn take_synced_paths() -> Vec<PathBuf> {
+    SYNCED_PATHS.with(|paths| paths.take())
+}
+
+/// sync_path flushes the file, or all the files directly under the directory, to the disk.
+/// It also flushes the directory entries of the path and its parent so that the created path survives a power cut.
+pub fn sync_path(path: &Path) -> io::Result<()> {
+    #[cfg(feature = "testing")]
+    SYNCED_PATHS.with(|paths| paths.borrow_mut().push(path.to_path_buf()));
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::File::open(entry.path())?.sync_all()?;
+            }
+        }
+        sync_dir(path)?;
+    } else {
+        fs::File::open(path)?.sync_all()?;
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+// Directory cannot be opened as a file on windows, and NTFS journals the metadata.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::cmp::Ordering;
 
+    use tempdir::TempDir;
+
     use super::*;
 
     #[test]
@@ -317,39 +350,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_bytes_in() {
-        assert!(bytes_in(&[vec![0b00000000]], &[0b00000000]));
-        assert!(bytes_in(&[vec![0b00000001]], &[0b00000001]));
-        assert!(bytes_in(
-            &[vec![0b00000001], vec![0b00000001]],
-            &[0b00000001]
-        ));
-        assert!(bytes_in(&[vec![0b00000001]], &[0b00000001]));
-        assert!(bytes_in(
-            &[vec![0b11010001], vec![0b11010001]],
-            &[0b11010001]
-        ));
-        assert!(bytes_in(
-            &[vec![0b11010001], vec![0b11010001], vec![0b11010001]],
-            &[0b11010001]
-        ));
-
-        assert!(!bytes_in(&[vec![0b00000000]], &[0b01000000]));
-        assert!(!bytes_in(
-            &[vec![0b01110000], vec![0b01000000]],
-            &[0b00000001]
-        ));
-        assert!(!bytes_in(
-            &[vec![0b01100001], vec![0b00100001]],
-            &[0b00000001]
-        ));
-        assert!(!bytes_in(
-            &[vec![0b11010011], vec![0b11000001], vec![0b11011001]],
-            &[0b11010001]
-        ));
-    }
-
     #[test]
     fn test_array_equal_bool() {
         assert!(array_equal_bool(&[true, false, true], &[true, false, true]));
@@ -375,28 +375,6 @@ mod tests {
         assert!(!array_equal_bool(&[false, false], &[false, false, false]));
     }
 
-    #[test]
-    fn test_have_all_arrays_same_length() {
-        // arrays have the same length
-        assert!(have_all_arrays_same_length(&[&[1, 2, 3], &[1, 2, 3]], 3));
-        assert!(have_all_arrays_same_length(&[&[1, 2], &[3, 4]], 2));
-        assert!(have_all_arrays_same_length(
-            &[&[1, 2, 3], &[1, 2, 3], &[1, 2, 3]],
-            3
-        ));
-        assert!(have_all_arrays_same_length(&[&[1], &[2], &[3], &[4]], 1));
-
-        // arrays have different length
-        assert!(!have_all_arrays_same_length(&[&[1, 2, 3], &[1, 2, 3]], 2));
-        assert!(!have_all_arrays_same_length(&[&[1, 2], &[3, 4]], 3));
-        assert!(!have_all_arrays_same_length(
-            &[&[1, 2, 3], &[1, 2, 3], &[1, 2, 3]],
-            2
-        ));
-        assert!(!have_all_arrays_same_length(&[&[1, 2, 3], &[4, 5]], 2));
-        assert!(!have_all_arrays_same_length(&[&[1, 2, 3], &[4, 5]], 3));
-    }
-
     #[test]
     fn test_binary_search() {
         let test_data = vec![
@@ -453,4 +431,35 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_sync_path() {
+        let temp_dir = TempDir::new("test_sync_path").unwrap();
+        let dir = temp_dir.path().join("sub");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file1"), [1, 2, 3]).unwrap();
+        fs::write(dir.join("file2"), [4, 5, 6]).unwrap();
+        fs::create_dir(dir.join("nested")).unwrap();
+
+        assert!(sync_path(&dir).is_ok());
+        assert!(sync_path(&dir.join("file1")).is_ok());
+        assert!(sync_path(&dir.join("not_exist")).is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_take_synced_paths() {
+        let temp_dir = TempDir::new("test_take_synced_paths").unwrap();
+        let file = temp_dir.path().join("file");
+        fs::write(&file, [1, 2, 3]).unwrap();
+        take_synced_paths();
+
+        sync_path(&file).unwrap();
+        sync_path(temp_dir.path()).unwrap();
+        assert_eq!(
+            take_synced_paths(),
+            vec![file.clone(), temp_dir.path().to_path_buf()]
+        );
+        assert!(take_synced_paths().is_empty());
+    }
 }
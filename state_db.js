@@ -15,40 +15,110 @@
 
 const {
     state_db_new,
+    state_db_migrate,
     state_db_close,
     state_db_get,
     state_db_get_current_state,
     state_db_exists,
     state_db_iterate,
+    state_db_iterate_kind,
     state_db_revert,
     state_db_commit,
+    state_db_commit_cancel,
+    state_db_compute_root,
+    state_db_write,
     state_db_prove,
+    state_db_prove_pending,
     state_db_verify,
     state_db_clean_diff_until,
+    state_db_set_bulk_load,
+    state_db_get_root_at,
+    state_db_get_latest_heights,
+    state_db_check_diffs,
+    state_db_diff_stats,
+    state_db_check_consistency,
     state_db_checkpoint,
+    state_db_verify_checkpoint,
+    state_db_checkpoint_with_group,
     state_db_calculate_root,
+    state_db_compact,
+    state_db_flush,
+    state_db_bloom_stats,
+    state_db_negative_cache_stats,
+    state_db_key_constants,
+    state_db_list_readers,
+    state_db_pending_operations,
+    state_db_get_metrics,
+    state_db_stats,
+    state_db_approximate_size,
+    state_db_approximate_sizes,
+    state_db_profile_range,
+    state_db_verify_nodes,
+    state_db_check_integrity,
+    state_db_check_integrity_cancel,
+    state_db_prune_smt,
+    state_db_rebuild_smt,
+    state_db_get_smt_node,
+    state_db_get_smt_children,
+    state_db_put_smt_nodes,
+    state_db_finalize_sync,
+    state_db_export_snapshot,
+    state_db_import_snapshot,
     state_writer_new,
     state_writer_close,
     state_writer_snapshot,
     state_writer_restore_snapshot,
+    state_writer_release_snapshot,
+    state_writer_reset,
+    state_writer_size,
     state_db_reader_new,
     state_db_reader_close,
     state_db_reader_get,
     state_db_reader_exists,
+    state_db_reader_get_many,
+    state_db_reader_exists_many,
     state_db_reader_iterate,
+    state_db_reader_count_range,
+    state_db_reader_prove,
+    state_db_reader_exists_prefix,
     state_db_read_writer_new,
     state_db_read_writer_close,
     state_db_read_writer_upsert_key,
     state_db_read_writer_get_key,
+    state_db_read_writer_get_key_peek,
+    state_db_read_writer_get_many,
+    state_db_read_writer_exists_many,
+    state_db_read_writer_exists_prefix,
     state_db_read_writer_delete,
+    state_db_read_writer_increment,
     state_db_read_writer_range,
+    state_db_read_writer_range_pending,
+    state_db_read_writer_range_open,
+    state_db_read_writer_range_next,
+    state_db_read_writer_range_close,
+    state_db_read_writer_delete_range,
+    state_db_read_writer_count_range,
+    in_memory_state_db_new,
+    in_memory_state_db_commit,
+    in_memory_state_db_revert,
+    in_memory_state_db_prove,
+    in_memory_state_db_verify,
+    in_memory_state_db_get,
+    in_memory_state_db_iterate,
 } = require("./bin-package/index.node");
 
+const { InMemoryIterator } = require('./database');
 const { NotFoundError } = require('./error');
 const { Iterator } = require("./iterator");
 const { getOptionsWithDefault } = require('./options');
 const { isInclusionProofForQueryKey } = require('./utils');
 
+const DEFAULT_KEY_LENGTH = 38;
+
+// If a value is empty, force to use different memory space from what's given from binding
+// Issue: https://github.com/nodejs/node/issues/32463
+const copyEmptyValues = values => values.map(value => (value !== undefined && value.length === 0 ? Buffer.alloc(0) : value));
+
 class StateReader {
     constructor(db) {
         this._db = state_db_reader_new(db);
@@ -62,7 +132,7 @@ class StateReader {
         return new Promise((resolve, reject) => {
             state_db_reader_get.call(this._db, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Key ${key.toString('hex')} does not exist.`));
                     }
                     return reject(err);
@@ -89,6 +159,41 @@ class StateReader {
         });
     }
 
+    // getMany reads all the keys on the snapshot at once. Value is undefined if the key does not exist
+    async getMany(keys) {
+        return new Promise((resolve, reject) => {
+            state_db_reader_get_many.call(this._db, keys, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(copyEmptyValues(result));
+            });
+        });
+    }
+
+    async hasMany(keys) {
+        return new Promise((resolve, reject) => {
+            state_db_reader_exists_many.call(this._db, keys, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // existsPrefix returns true if any key in the snapshot starts with the prefix
+    async existsPrefix(prefix) {
+        return new Promise((resolve, reject) => {
+            state_db_reader_exists_prefix.call(this._db, prefix, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
     iterate(options = {}) {
         return new Iterator(this._db, state_db_reader_iterate, getOptionsWithDefault(options));
     }
@@ -96,12 +201,67 @@ class StateReader {
     createReadStream(options = {}) {
         return new Iterator(this._db, state_db_reader_iterate, getOptionsWithDefault(options));
     }
+
+    // countRange counts the keys in the range, and stops counting at the limit
+    async countRange(options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_reader_count_range.call(this._db, getOptionsWithDefault(options), (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // prove proves the queries against the state root of the snapshot, which is returned with the proof
+    async prove(queries) {
+        return new Promise((resolve, reject) => {
+            state_db_reader_prove.call(this._db, queries, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                // If result is empty, force to use different memory space from what's given from binding
+                // Issue: https://github.com/nodejs/node/issues/32463
+                for (const query of result.proof.queries) {
+                    if (query.value.length === 0) {
+                        query.value = Buffer.alloc(0);
+                    }
+                }
+                resolve(result);
+            });
+        });
+    }
+}
+
+// StateRangeCursor pages the range of the read writer. The pages are consistent with the writer at open,
+// and the cursor is not affected by the changes or the commit of the writer after it.
+class StateRangeCursor {
+    constructor(readWriter, options) {
+        this._cursor = state_db_read_writer_range_open.call(readWriter._db, readWriter.writer, getOptionsWithDefault(options));
+    }
+
+    // next returns up to count pairs following the previous page, and an empty page when the range is exhausted
+    async next(count) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_range_next.call(this._cursor, count, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    close() {
+        state_db_read_writer_range_close.call(this._cursor);
+    }
 }
 
 class StateReadWriter {
-    constructor(db) {
+    constructor(db, options) {
         this._db = state_db_read_writer_new(db);
-        this._writer = state_writer_new();
+        this._writer = state_writer_new(options);
     }
 
     get writer() {
@@ -114,10 +274,19 @@ class StateReadWriter {
     }
 
     async get(key) {
+        return this._getKey(state_db_read_writer_get_key, key);
+    }
+
+    // peek reads the key without caching the stored value to the writer
+    async peek(key) {
+        return this._getKey(state_db_read_writer_get_key_peek, key);
+    }
+
+    async _getKey(getKey, key) {
         const value = await new Promise((resolve, reject) => {
-            state_db_read_writer_get_key.call(this._db, this.writer, key, (err, result) => {
+            getKey.call(this._db, this.writer, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Key ${key.toString('hex')} does not exist.`));
                     }
                     return reject(err);
@@ -146,9 +315,33 @@ class StateReadWriter {
         }
     }
 
-    async set(key, value) {
-        await new Promise((resolve, reject) => {
-            state_db_read_writer_upsert_key.call(this._db, this.writer, key, value, (err, result) => {
+    // getMany reads all the keys at once, and the stored values are cached to the writer like get
+    async getMany(keys) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_get_many.call(this._db, this.writer, keys, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(copyEmptyValues(result));
+            });
+        });
+    }
+
+    async hasMany(keys) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_exists_many.call(this._db, this.writer, keys, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // set resolves whether the key existed, or { existed, previousValue } with the previousValue option
+    async set(key, value, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_upsert_key.call(this._db, this.writer, key, value, !!options.previousValue, (err, result) => {
                 if (err) {
                     return reject(err);
                 }
@@ -168,13 +361,34 @@ class StateReadWriter {
         });
     }
 
+    // increment adds the delta to the big-endian counter of the width without reading it.
+    // The counter is read on get or commit, and the commit rejects if it overflows
+    async increment(key, delta, width = 8) {
+        state_db_read_writer_increment.call(this._db, this.writer, key, delta, width);
+    }
+
+    async deleteRange(start, end) {
+        await new Promise((resolve, reject) => {
+            state_db_read_writer_delete_range.call(this._db, this.writer, start, end, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // range returns the pairs merged with the writer. The result has truncated set to true
+    // if it is cut by maxIterationResultSize, and the next range continues after its last key
     async range(options = {}) {
         const defaultOptions = getOptionsWithDefault(options);
+        let truncated = false;
         const result = await new Promise((resolve, reject) => {
-            state_db_read_writer_range.call(this._db, this.writer, defaultOptions, (err, result) => {
+            state_db_read_writer_range.call(this._db, this.writer, defaultOptions, (err, result, isTruncated) => {
                 if (err) {
                     return reject(err);
                 }
+                truncated = isTruncated;
                 resolve(result);
             });
         });
@@ -187,9 +401,51 @@ class StateReadWriter {
         if (options.limit) {
             result.splice(options.limit);
         }
+        Object.defineProperty(result, 'truncated', { value: truncated });
         return result;
     }
 
+    // openRange returns the cursor to read the range page by page
+    openRange(options = {}) {
+        return new StateRangeCursor(this, options);
+    }
+
+    // rangePending returns only the changes pending in the writer with their state, without reading the stored values
+    async rangePending(options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_range_pending.call(this._db, this.writer, getOptionsWithDefault(options), (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // countRange counts the keys in the range including the changes in the writer, and stops counting at the limit
+    async countRange(options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_count_range.call(this._db, this.writer, getOptionsWithDefault(options), (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // existsPrefix returns true if any key starts with the prefix including the changes in the writer
+    async existsPrefix(prefix) {
+        return new Promise((resolve, reject) => {
+            state_db_read_writer_exists_prefix.call(this._db, this.writer, prefix, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
     snapshot() {
         let result = state_writer_snapshot.call(this._writer);
         return result;
@@ -198,6 +454,21 @@ class StateReadWriter {
     restoreSnapshot(index = 0) {
         state_writer_restore_snapshot.call(this._writer, index);
     }
+
+    // releaseSnapshot drops the snapshot and the later ones, keeping the changes after them
+    releaseSnapshot(index) {
+        state_writer_release_snapshot.call(this._writer, index);
+    }
+
+    // reset clears the writer committed, so that it is reused for the next commit
+    reset() {
+        state_writer_reset.call(this._writer);
+    }
+
+    // size returns the number of the cached entries and their approximate bytes
+    size() {
+        return state_writer_size.call(this._writer);
+    }
 }
 
 class StateDB {
@@ -205,11 +476,36 @@ class StateDB {
         this._db = state_db_new(path, opts);
     }
 
+    // migrate runs the pending format migrations of the database at the path without blocking the JS thread.
+    // The database must not be open, and it is opened without autoMigrate afterwards
+    static async migrate(path, opts = {}, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_migrate(path, opts, { onProgress: options.onProgress }, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // openWithIntegrityCheck opens the database and scans it at the level. The database is closed if the scan fails
+    static async openWithIntegrityCheck(path, opts = {}, level = 'quick', options = {}) {
+        const db = new StateDB(path, opts);
+        try {
+            const report = await db.checkIntegrity(level, options);
+            return { db, report };
+        } catch (err) {
+            await db.close();
+            throw err;
+        }
+    }
+
     async get(key) {
         return new Promise((resolve, reject) => {
             state_db_get.call(this._db, key, (err, result) => {
                 if (err) {
-                    if (err.message === 'No data') {
+                    if (err.code === 'ERR_NOT_FOUND') {
                         return reject(new NotFoundError(`Key ${key.toString('hex')} does not exist.`));
                     }
                     return reject(err);
@@ -231,10 +527,7 @@ class StateDB {
                 if (err) {
                     return reject(err);
                 }
-                resolve({
-                    root: result.root,
-                    version: result.version,
-                });
+                resolve(result);
             });
         });
     }
@@ -258,9 +551,22 @@ class StateDB {
         return new Iterator(this._db, state_db_iterate, getOptionsWithDefault(options));
     }
 
-    async revert(prev_root, height) {
+    // iterateKind iterates the stored data of the kind ("state", "diff" or "smt") with the kind prefix removed from the keys.
+    // decode: true returns the decoded diffs of the diff kind.
+    iterateKind(kind, options = {}) {
+        const iterateFunc = function (opts, onData, onDone) {
+            state_db_iterate_kind.call(this, kind, opts, onData, onDone);
+        };
+        return new Iterator(this._db, iterateFunc, {
+            ...getOptionsWithDefault(options),
+            decode: options.decode === true,
+        });
+    }
+
+    async revert(prev_root, height, options = {}) {
+        const expectedRoot = options.expectedRoot !== undefined ? options.expectedRoot : Buffer.alloc(0);
         return new Promise((resolve, reject) => {
-            state_db_revert.call(this._db, prev_root, height, (err, result) => {
+            state_db_revert.call(this._db, prev_root, height, expectedRoot, (err, result) => {
                 if (err) {
                     return reject(err);
                 }
@@ -274,9 +580,11 @@ class StateDB {
             readonly: options.readonly !== undefined ? options.readonly : false,
             checkRoot: options.checkRoot !== undefined ? options.checkRoot : false,
             expectedRoot: options.expectedRoot !== undefined ? options.expectedRoot : Buffer.alloc(0),
+            maxBatchBytes: options.maxBatchBytes !== undefined ? options.maxBatchBytes : 0,
+            sync: options.sync !== undefined ? options.sync : false,
         };
         return new Promise((resolve, reject) => {
-            state_db_commit.call(this._db, readWriter.writer, height, prevRoot, defaultOptions.readonly, defaultOptions.expectedRoot, defaultOptions.checkRoot, (err, result) => {
+            state_db_commit.call(this._db, readWriter.writer, height, prevRoot, defaultOptions.readonly, defaultOptions.expectedRoot, defaultOptions.checkRoot, defaultOptions.maxBatchBytes, defaultOptions.sync, { onProgress: options.onProgress, stagedId: options.stagedId }, (err, result) => {
                 if (err) {
                     return reject(err);
                 }
@@ -285,9 +593,37 @@ class StateDB {
         });
     }
 
-    async prove(root, queries) {
+    // cancelCommit cancels the commits in progress. Cancelled commit is rejected with ERR_CANCELLED without writing anything
+    cancelCommit() {
+        state_db_commit_cancel.call(this._db);
+    }
+
+    async computeRoot(readWriter) {
+        return new Promise((resolve, reject) => {
+            state_db_compute_root.call(this._db, readWriter.writer, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async write(batch) {
+        return new Promise((resolve, reject) => {
+            state_db_write.call(this._db, batch.inner, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async prove(root, queries, options = {}) {
         return new Promise((resolve, reject) => {
-            state_db_prove.call(this._db, root, queries, (err, result) => {
+            const proveOptions = { includeValues: options.includeValues === true, height: options.height, includeSize: options.includeSize === true };
+            state_db_prove.call(this._db, root, queries, proveOptions, (err, result) => {
                 if (err) {
                     return reject(err);
                 }
@@ -297,6 +633,28 @@ class StateDB {
                     if (query.value.length === 0) {
                         query.value = Buffer.alloc(0);
                     }
+                    if (query.rawValue !== undefined && query.rawValue.length === 0) {
+                        query.rawValue = Buffer.alloc(0);
+                    }
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // provePending proves the queries against the root of committing the readWriter, without writing anything
+    async provePending(readWriter, queries) {
+        return new Promise((resolve, reject) => {
+            state_db_prove_pending.call(this._db, readWriter.writer, queries, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                // If result is empty, force to use different memory space from what's given from binding
+                // Issue: https://github.com/nodejs/node/issues/32463
+                for (const query of result.proof.queries) {
+                    if (query.value.length === 0) {
+                        query.value = Buffer.alloc(0);
+                    }
                 }
                 resolve(result);
             });
@@ -344,21 +702,95 @@ class StateDB {
         });
     }
 
-    newReader() {
-        return new StateReader(this._db);
+    // setBulkLoad switches the bulk load of the initial sync after the operations queued before it.
+    // The commits in the bulk load write no diff, and reverting them rejects with ERR_BULK_LOADED.
+    // Turning it off restores the rocksdb options and compacts the database.
+    async setBulkLoad(enabled) {
+        return new Promise((resolve, reject) => {
+            state_db_set_bulk_load.call(this._db, enabled, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // getRootAt returns the root committed at the height. Roots removed together with the diffs
+    // are rejected with ERR_NOT_RETAINED, and the heights never recorded with NotFoundError
+    async getRootAt(height) {
+        return new Promise((resolve, reject) => {
+            state_db_get_root_at.call(this._db, height, (err, result) => {
+                if (err) {
+                    if (err.code === 'ERR_NOT_FOUND') {
+                        return reject(new NotFoundError(`Root at height ${height} is not recorded.`));
+                    }
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
     }
 
-    newReadWriter() {
-        return new StateReadWriter(this._db);
+    async getLatestHeights(count) {
+        return new Promise((resolve, reject) => {
+            state_db_get_latest_heights.call(this._db, count, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
     }
 
-    close() {
-        state_db_close.call(this._db);
+    // checkDiffs reports the heights of the stored diffs which cannot be decoded without reverting
+    async checkDiffs() {
+        return new Promise((resolve, reject) => {
+            state_db_check_diffs.call(this._db, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // diffStats reports the sizes of the diff of the height, including the values referenced from the old-value store
+    async diffStats(height) {
+        return new Promise((resolve, reject) => {
+            state_db_diff_stats.call(this._db, height, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
     }
 
-    async checkpoint(path) {
+    // checkConsistency reports whether the root and the diff of the latest commit are stored
+    async checkConsistency() {
         return new Promise((resolve, reject) => {
-            state_db_checkpoint.call(this._db, path, err => {
+            state_db_check_consistency.call(this._db, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    newReader() {
+        return new StateReader(this._db);
+    }
+
+    newReadWriter(options) {
+        return new StateReadWriter(this._db, options);
+    }
+
+    // close closes the readers of the database, and resolves after the connection is released
+    async close() {
+        await new Promise((resolve, reject) => {
+            state_db_close.call(this._db, err => {
                 if (err) {
                     return reject(err);
                 }
@@ -367,9 +799,370 @@ class StateDB {
         });
     }
 
-    async calculateRoot(proof) {
-        return new Promise((resolve, _reject) => {
-            state_db_calculate_root.call(this._db, proof, (_err, result) => {
+    // checkpoint creates the state checkpoint at the path. The files and the directory entries of the checkpoint
+    // are fsynced before it resolves unless sync is false, so that the checkpoint survives a power loss
+    async checkpoint(path, options = {}) {
+        const defaultOptions = {
+            sync: options.sync !== undefined ? options.sync : true,
+            flushBeforeCheckpoint: options.flushBeforeCheckpoint !== undefined ? options.flushBeforeCheckpoint : false,
+        };
+        return new Promise((resolve, reject) => {
+            state_db_checkpoint.call(this._db, path, defaultOptions, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // checkpointWithGroup creates the state checkpoint of the group
+    async checkpointWithGroup(group, path, options = {}) {
+        const defaultOptions = {
+            sync: options.sync !== undefined ? options.sync : true,
+            flushBeforeCheckpoint: options.flushBeforeCheckpoint !== undefined ? options.flushBeforeCheckpoint : false,
+        };
+        return new Promise((resolve, reject) => {
+            state_db_checkpoint_with_group.call(this._db, group._token, path, defaultOptions, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // verifyCheckpoint verifies the checkpoint created before without restoring it
+    async verifyCheckpoint(path) {
+        return new Promise((resolve, reject) => {
+            state_db_verify_checkpoint.call(this._db, path, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async compact(start, end) {
+        return new Promise((resolve, reject) => {
+            state_db_compact.call(this._db, start, end, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async flush() {
+        return new Promise((resolve, reject) => {
+            state_db_flush.call(this._db, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async stats() {
+        return new Promise((resolve, reject) => {
+            state_db_stats.call(this._db, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // approximateSize estimates the bytes of the state keys with the module prefix
+    async approximateSize(prefix) {
+        return new Promise((resolve, reject) => {
+            state_db_approximate_size.call(this._db, prefix, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // approximateSizes estimates the bytes of the module prefixes at once, keyed by the hex prefix
+    async approximateSizes(prefixes) {
+        return new Promise((resolve, reject) => {
+            state_db_approximate_sizes.call(this._db, prefixes, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                const sizes = new Map();
+                for (let i = 0; i < prefixes.length; i += 1) {
+                    sizes.set(prefixes[i].toString('hex'), result[i]);
+                }
+                resolve(sizes);
+            });
+        });
+    }
+
+    bloomStats() {
+        return state_db_bloom_stats.call(this._db);
+    }
+
+    // negativeCacheStats returns the counters of the cache of the missing keys, or null if it is disabled
+    negativeCacheStats() {
+        return state_db_negative_cache_stats.call(this._db);
+    }
+
+    // constants returns the prefixes of the keyspaces and the lengths of the parts of the keys with the key length of the database
+    constants() {
+        return state_db_key_constants.call(this._db);
+    }
+
+    // listReaders returns the number of the open readers and read-writers, and their ages in ms with the oldest first
+    listReaders() {
+        return state_db_list_readers.call(this._db);
+    }
+
+    // pendingOperations returns the number of the operations queued on the database thread
+    pendingOperations() {
+        return state_db_pending_operations.call(this._db);
+    }
+
+    // getMetrics returns the latency histograms of the operations, or null if metrics are disabled
+    getMetrics(reset = false) {
+        return state_db_get_metrics.call(this._db, reset);
+    }
+
+    async profileRange(options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_profile_range.call(this._db, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async verifyNodes(path, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_verify_nodes.call(this._db, path !== undefined ? path : null, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // checkIntegrity scans the state at the level "quick" or "full". Problems found are reported in the result instead of rejected
+    async checkIntegrity(level = 'quick', options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_check_integrity.call(this._db, level, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // cancelCheckIntegrity cancels the integrity scans in progress. Cancelled scan is rejected with ERR_CANCELLED
+    cancelCheckIntegrity() {
+        state_db_check_integrity_cancel.call(this._db);
+    }
+
+    async pruneSMT(retainHeights, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_prune_smt.call(this._db, retainHeights, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // rebuildSMT rebuilds the tree from the state keys. It is safe to run again from scratch when interrupted.
+    // Commits are not blocked while the tree is rebuilt, and it is rejected if the state is changed in the meantime
+    async rebuildSMT(expectedRoot, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_rebuild_smt.call(this._db, expectedRoot, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // getSmtNode resolves the encoded node of the hash, or undefined if it is not stored
+    async getSmtNode(nodeHash) {
+        return new Promise((resolve, reject) => {
+            state_db_get_smt_node.call(this._db, nodeHash, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // getSmtChildren resolves the hashes of the child nodes, or undefined if the node is not stored
+    async getSmtChildren(nodeHash) {
+        return new Promise((resolve, reject) => {
+            state_db_get_smt_children.call(this._db, nodeHash, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    // putSmtNodes installs the received nodes. The database must be opened with unsafeSync
+    async putSmtNodes(entries) {
+        return new Promise((resolve, reject) => {
+            state_db_put_smt_nodes.call(this._db, entries, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    // finalizeSync sets the current state to the expected root once its tree is complete
+    async finalizeSync(expectedRoot, height) {
+        return new Promise((resolve, reject) => {
+            state_db_finalize_sync.call(this._db, expectedRoot, height, err => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve();
+            });
+        });
+    }
+
+    async exportSnapshot(path, height, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_export_snapshot.call(this._db, path, height, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async importSnapshot(path, options = {}) {
+        return new Promise((resolve, reject) => {
+            state_db_import_snapshot.call(this._db, path, options, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async calculateRoot(proof) {
+        return new Promise((resolve, _reject) => {
+            state_db_calculate_root.call(this._db, proof, (_err, result) => {
+                resolve(result);
+            });
+        });
+    }
+}
+
+// InMemoryStateDB commits, reverts and proves the state in memory with the same roots as StateDB
+class InMemoryStateDB {
+    constructor(keyLength = DEFAULT_KEY_LENGTH) {
+        this._db = in_memory_state_db_new(keyLength);
+    }
+
+    async get(key) {
+        return new Promise((resolve, reject) => {
+            in_memory_state_db_get.call(this._db, key, (err, result) => {
+                if (err) {
+                    if (err.code === 'ERR_NOT_FOUND') {
+                        return reject(new NotFoundError(`Key ${key.toString('hex')} does not exist.`));
+                    }
+                    return reject(err);
+                }
+                // If result is empty, force to use different memory space from what's given from binding
+                // Issue: https://github.com/nodejs/node/issues/32463
+                if (result.length === 0) {
+                    resolve(Buffer.alloc(0));
+                    return;
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async has(key) {
+        try {
+            await this.get(key);
+            return true;
+        } catch (error) {
+            if (!(error instanceof NotFoundError)) {
+                throw error;
+            }
+            return false;
+        }
+    }
+
+    iterate(options = {}) {
+        return new InMemoryIterator(this._db, in_memory_state_db_iterate, getOptionsWithDefault(options));
+    }
+
+    // commit applies the changes on top of prevRoot. The key is deleted when the value is not set
+    async commit(changes, height, prevRoot) {
+        return new Promise((resolve, reject) => {
+            in_memory_state_db_commit.call(this._db, changes, height, prevRoot, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async revert(prevRoot, height) {
+        return new Promise((resolve, reject) => {
+            in_memory_state_db_revert.call(this._db, prevRoot, height, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async prove(root, queries) {
+        return new Promise((resolve, reject) => {
+            in_memory_state_db_prove.call(this._db, root, queries, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
+                for (const query of result.queries) {
+                    if (query.value.length === 0) {
+                        query.value = Buffer.alloc(0);
+                    }
+                }
+                resolve(result);
+            });
+        });
+    }
+
+    async verify(root, queries, proof) {
+        return new Promise((resolve, reject) => {
+            in_memory_state_db_verify.call(this._db, root, queries, proof, (err, result) => {
+                if (err) {
+                    return reject(err);
+                }
                 resolve(result);
             });
         });
@@ -378,6 +1171,7 @@ class StateDB {
 
 module.exports = {
     StateDB,
+    InMemoryStateDB,
     StateReadWriter,
     StateReader,
 };
@@ -17,7 +17,7 @@ const os = require('os');
 const path = require('path');
 const fs = require('fs');
 const { Database, Batch, NotFoundError, InMemoryDatabase } = require('../main');
-const { getRandomBytes } = require('./utils');
+const { getRandomBytes, collectGarbageUntil } = require('./utils');
 
 describe('database', () => {
     describe('Database', () => {
@@ -39,16 +39,202 @@ describe('database', () => {
             const key = getRandomBytes();
             const value = getRandomBytes();
             await newDB.set(key, value);
-            newDB.close();
+            await newDB.close();
 
             const reopenDB = new Database(newDBPath);
             await expect(reopenDB.get(key)).resolves.toEqual(value);
         });
 
+        it('should release the threads and the files of the databases closed or dropped without close', async () => {
+            // the threads and the files of the process are only listed on linux
+            if (process.platform !== 'linux') {
+                return;
+            }
+            const countEntries = dir => fs.readdirSync(dir).length;
+            const openDB = async name => {
+                const releasePath = path.join(os.tmpdir(), 'db_release', `${Date.now().toString()}_${name}`);
+                fs.mkdirSync(releasePath, { recursive: true });
+                const releaseDB = new Database(releasePath);
+                await releaseDB.set(getRandomBytes(), getRandomBytes());
+                return { releaseDB, releasePath };
+            };
+            // the threads shared by the databases are started by the first one
+            const first = await openDB('first');
+            await first.releaseDB.close();
+            const threads = countEntries('/proc/self/task');
+            const files = countEntries('/proc/self/fd');
+
+            for (let i = 0; i < 100; i += 1) {
+                const { releaseDB, releasePath } = await openDB(`closed_${i}`);
+                await releaseDB.close();
+                await releaseDB.close();
+                // the LOCK file is released, so that the directory can be removed
+                fs.rmSync(releasePath, { recursive: true });
+            }
+            expect(countEntries('/proc/self/task')).toBeLessThanOrEqual(threads + 2);
+            expect(countEntries('/proc/self/fd')).toBeLessThanOrEqual(files + 2);
+
+            // the databases dropped without close are closed on the garbage collection
+            for (let i = 0; i < 100; i += 1) {
+                await openDB(`dropped_${i}`);
+            }
+            await collectGarbageUntil(
+                () => countEntries('/proc/self/task') <= threads + 2 && countEntries('/proc/self/fd') <= files + 2,
+            );
+            expect(countEntries('/proc/self/task')).toBeLessThanOrEqual(threads + 2);
+            expect(countEntries('/proc/self/fd')).toBeLessThanOrEqual(files + 2);
+        });
+
+        it('should reject all the methods with closed error after close', async () => {
+            const closedDBPath = path.join(os.tmpdir(), 'db', `closed_${Date.now().toString()}`);
+            fs.mkdirSync(closedDBPath, { recursive: true });
+            const closedDB = new Database(closedDBPath);
+            const key = getRandomBytes();
+            await closedDB.set(key, getRandomBytes());
+            closedDB.close();
+
+            const message = 'Database is closed';
+            await expect(closedDB.get(key)).rejects.toThrow(message);
+            await expect(closedDB.has(key)).rejects.toThrow(message);
+            await expect(closedDB.set(key, getRandomBytes())).rejects.toThrow(message);
+            await expect(closedDB.del(key)).rejects.toThrow(message);
+            await expect(closedDB.write(new Batch())).rejects.toThrow(message);
+            await expect(closedDB.clear()).rejects.toThrow(message);
+            await expect(closedDB.compact()).rejects.toThrow(message);
+            await expect(closedDB.deleteRange(key, key)).rejects.toThrow(message);
+            await expect(closedDB.stats()).rejects.toThrow(message);
+            await expect(closedDB.checkpoint(path.join(closedDBPath, 'checkpoint'))).rejects.toThrow(message);
+            expect(() => closedDB.iterate()).toThrow(message);
+            await expect(closedDB.get(key)).rejects.toHaveProperty('code', 'ERR_CLOSED');
+        });
+
+        it('should end the iteration with closed error once when closed during the iteration', async () => {
+            const closedDBPath = path.join(os.tmpdir(), 'db', `closed_iterate_${Date.now().toString()}`);
+            fs.mkdirSync(closedDBPath, { recursive: true });
+            const closedDB = new Database(closedDBPath);
+            const batch = new Batch();
+            for (let i = 0; i < 100; i += 1) {
+                batch.set(getRandomBytes(), getRandomBytes());
+            }
+            await closedDB.write(batch);
+
+            const stream = closedDB.iterate({ chunkSize: 1 });
+            const { count, errors } = await new Promise((resolve, reject) => {
+                let received = 0;
+                const result = [];
+                stream
+                    .on('data', () => {
+                        received += 1;
+                        closedDB.close();
+                    })
+                    .on('error', err => {
+                        result.push(err);
+                        // close resolves after the iteration releases the connection, so that the callback called
+                        // more than once would have been called by the next turn of the event loop
+                        closedDB
+                            .close()
+                            .then(() => new Promise(setImmediate))
+                            .then(() => resolve({ count: received, errors: result }), reject);
+                    })
+                    .on('end', () => {
+                        reject(new Error('iteration should not end'));
+                    });
+            });
+
+            expect(count).toBeLessThan(100);
+            expect(errors).toHaveLength(1);
+            expect(errors[0].message).toEqual('Database is closed');
+        });
+
+        it('should ignore close when the database is already closed', () => {
+            const closedDBPath = path.join(os.tmpdir(), 'db', `closed_twice_${Date.now().toString()}`);
+            fs.mkdirSync(closedDBPath, { recursive: true });
+            const closedDB = new Database(closedDBPath);
+            closedDB.close();
+            expect(() => closedDB.close()).not.toThrow();
+        });
+
+        it('should throw closed error when closed batch is used', async () => {
+            const batch = new Batch();
+            batch.set(getRandomBytes(), getRandomBytes());
+            batch.close();
+
+            const message = 'WriteBatch is closed';
+            expect(() => batch.set(getRandomBytes(), getRandomBytes())).toThrow(message);
+            expect(() => batch.del(getRandomBytes())).toThrow(message);
+            await expect(db.write(batch)).rejects.toThrow(message);
+        });
+
+        it('should consume the written batch until it is reset', async () => {
+            const kv = { key: getRandomBytes(), value: getRandomBytes() };
+            const batch = new Batch();
+            batch.set(kv.key, kv.value);
+            await db.write(batch);
+
+            expect(() => batch.set(getRandomBytes(), getRandomBytes())).toThrow(expect.objectContaining({ code: 'ERR_BATCH_CONSUMED' }));
+            expect(() => batch.del(kv.key)).toThrow('WriteBatch is consumed by a write');
+            await expect(db.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+
+            batch.reset();
+            batch.del(kv.key);
+            await db.write(batch);
+            await expect(db.has(kv.key)).resolves.toEqual(false);
+        });
+
+        it('should write the batch only once when the writes race', async () => {
+            const kv = { key: getRandomBytes(), value: getRandomBytes() };
+            const batch = new Batch();
+            batch.set(kv.key, kv.value);
+
+            const writes = [db.write(batch), db.write(batch)];
+            // the batch cannot be updated while it is written
+            expect(() => batch.set(getRandomBytes(), getRandomBytes())).toThrow('WriteBatch is consumed by a write');
+            const results = await Promise.allSettled(writes);
+            expect(results.filter(r => r.status === 'fulfilled')).toHaveLength(1);
+            const rejected = results.filter(r => r.status === 'rejected');
+            expect(rejected).toHaveLength(1);
+            expect(rejected[0].reason.code).toEqual('ERR_BATCH_CONSUMED');
+            await expect(db.get(kv.key)).resolves.toEqual(kv.value);
+        });
+
         it('should open DB', () => {
             expect(db).not.toBeUndefined();
         });
 
+        it('should open DB with tuning options', async () => {
+            const newDBPath = path.join(os.tmpdir(), 'db', Date.now().toString());
+            fs.mkdirSync(newDBPath, { recursive: true });
+            const newDB = new Database(newDBPath, {
+                blockCacheSize: 8 * 1024 * 1024,
+                writeBufferSize: 4 * 1024 * 1024,
+                maxOpenFiles: 64,
+                compression: 'lz4',
+                compactionStyle: 'universal',
+            });
+            const key = getRandomBytes();
+            const value = getRandomBytes();
+            await newDB.set(key, value);
+            await expect(newDB.get(key)).resolves.toEqual(value);
+            newDB.close();
+        });
+
+        it('should throw when tuning options are invalid', () => {
+            const newDBPath = path.join(os.tmpdir(), 'db', Date.now().toString());
+            fs.mkdirSync(newDBPath, { recursive: true });
+            expect(() => new Database(newDBPath, { compression: 'snappy' })).toThrow(
+                'compression must be one of none, lz4 or zstd, got snappy',
+            );
+            expect(() => new Database(newDBPath, { compactionStyle: 'fifo' })).toThrow(
+                'compactionStyle must be one of level or universal, got fifo',
+            );
+            expect(() => new Database(newDBPath, { blockCacheSize: -1 })).toThrow(
+                'blockCacheSize must be a positive integer',
+            );
+            expect(() => new Database(newDBPath, { maxOpenFiles: 0 })).toThrow(
+                'maxOpenFiles must be a positive integer or -1',
+            );
+        });
+
         it('should return false when called has if key does not exist', async () => {
             await expect(db.has(getRandomBytes())).resolves.toEqual(false);
         });
@@ -64,6 +250,7 @@ describe('database', () => {
 
         it('should throw NotFoundError when data does not exist', async () => {
             await expect(db.get(getRandomBytes())).rejects.toThrow(NotFoundError);
+            await expect(db.get(getRandomBytes())).rejects.toHaveProperty('code', 'ERR_NOT_FOUND');
         });
 
         it('should get the value if exist', async () => {
@@ -212,6 +399,131 @@ describe('database', () => {
                 expect(values).toEqual(pairs.slice(2, 4).reverse());
             });
 
+            it('should iterate all the pairs in order regardless of chunk size', async () => {
+                for (const chunkSize of [1, 2, 3, 1000]) {
+                    const stream = db.iterate({ chunkSize });
+                    const { values, endCount } = await new Promise((resolve, reject) => {
+                        const result = [];
+                        let count = 0;
+                        stream
+                            .on('data', kv => {
+                                result.push(kv);
+                            })
+                            .on('error', err => {
+                                reject(err);
+                            })
+                            .on('end', () => {
+                                count += 1;
+                                resolve({ values: result, endCount: count });
+                            });
+                    });
+
+                    expect(values).toEqual(pairs);
+                    expect(endCount).toEqual(1);
+                }
+            });
+
+            it('should iterate only the keys with keysOnly', async () => {
+                const stream = db.iterate({ keysOnly: true, limit: 2 });
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+
+                expect(values).toEqual(pairs.slice(0, 2).map(({ key }) => ({ key })));
+                expect(values[0]).not.toHaveProperty('value');
+            });
+
+            it('should iterate the keys and the value sizes with inventory', async () => {
+                const collect = async options => new Promise((resolve, reject) => {
+                    const result = [];
+                    db.iterate(options)
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+                const expected = pairs.map(({ key, value }) => ({ key, valueSize: value.length }));
+
+                const values = await collect({ inventory: true, limit: 3 });
+                expect(values).toEqual(expected.slice(0, 3));
+                expect(values[0]).not.toHaveProperty('value');
+                // the value of the threshold size is reported
+                await expect(collect({ inventory: true, minValueSize: 32 })).resolves.toEqual(expected);
+                await expect(collect({ inventory: true, minValueSize: 33 })).resolves.toEqual([]);
+            });
+
+            it('should throw when minValueSize is used without inventory', () => {
+                expect(() => db.iterate({ minValueSize: 1 })).toThrow('minValueSize can only be used with inventory');
+                expect(() => db.iterate({ inventory: true, keysOnly: true })).toThrow();
+                expect(() => db.iterate({ inventory: true, minValueSize: 0 })).toThrow();
+            });
+
+            it('should iterate the same pairs with zeroCopy', async () => {
+                const stream = db.iterate({ zeroCopy: true });
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+
+                expect(values).toEqual(pairs);
+            });
+
+            it('should not change the stored data when the zeroCopy buffer is mutated', async () => {
+                const stream = db.iterate({ zeroCopy: true, limit: 1 });
+                const [kv] = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', data => {
+                            result.push(data);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+                kv.key.fill(255);
+                kv.value.fill(255);
+
+                await expect(db.get(pairs[0].key)).resolves.toEqual(pairs[0].value);
+                await expect(db.has(kv.key)).resolves.toEqual(false);
+            });
+
+            it('should throw when chunk size is not positive', async () => {
+                expect(() => db.iterate({ chunkSize: 0 })).toThrow('chunkSize must be a positive integer');
+            });
+
+            it('should throw when chunk size is not an integer', async () => {
+                for (const chunkSize of [0.5, 1.5]) {
+                    expect(() => db.iterate({ chunkSize })).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+                }
+            });
+
             it('should return empty if no data exist', async () => {
                 const stream = db.iterate({
                     gte: Buffer.from([2, 0, 1]),
@@ -236,6 +548,32 @@ describe('database', () => {
 
                 expect(values).toEqual([]);
             });
+
+            it('should stop the iteration over maxIterationResultSize', async () => {
+                const limitPath = path.join(os.tmpdir(), 'db', `limit_${Date.now().toString()}`);
+                fs.mkdirSync(limitPath, { recursive: true });
+                const limitDB = new Database(limitPath, { maxIterationResultSize: 25 });
+                for (let i = 1; i <= 5; i += 1) {
+                    await limitDB.set(Buffer.from([i]), Buffer.alloc(10, i));
+                }
+                const stream = limitDB.iterate();
+                const result = await new Promise((resolve, reject) => {
+                    const keys = [];
+                    stream
+                        .on('data', kv => {
+                            keys.push(kv.key);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(keys);
+                        });
+                });
+                expect(result).toEqual([Buffer.from([1]), Buffer.from([2])]);
+                expect(stream.truncated).toEqual(true);
+                limitDB.close();
+            });
         });
 
         describe('DatabaseReader', () => {
@@ -469,6 +807,26 @@ describe('database', () => {
                 await expect(db.has(pairs[1].key)).resolves.toEqual(true);
             });
 
+            it('should create checkpoint without sync', async () => {
+                const kv = { key: getRandomBytes(), value: getRandomBytes() };
+                await db.set(kv.key, kv.value);
+
+                await db.checkpoint(tmpPath + '/test_db_no_sync', { sync: false });
+
+                db = new Database(tmpPath + '/test_db_no_sync');
+                await expect(db.get(kv.key)).resolves.toEqual(kv.value);
+            });
+
+            it('should return the metadata of the flushed checkpoint', async () => {
+                const kv = { key: getRandomBytes(), value: getRandomBytes() };
+                await db.set(kv.key, kv.value);
+
+                const info = await db.checkpoint(tmpPath + '/test_db_flushed', { flushBeforeCheckpoint: true });
+                expect(info.size).toBeGreaterThan(0);
+                expect(info.sstFiles).toBeGreaterThan(0);
+                expect(info).not.toHaveProperty('root');
+            });
+
             it('should failed to create checkpoint because directory is not empty', async () => {
                 const pairs = [
                     { key: getRandomBytes(), value: getRandomBytes() },
@@ -486,6 +844,554 @@ describe('database', () => {
                 await expect(db.checkpoint(tmpPath)).rejects.toThrow();
             });
         });
+
+        describe('compact', () => {
+            it('should compact the whole range', async () => {
+                const kv = { key: getRandomBytes(), value: getRandomBytes() };
+                await db.set(kv.key, kv.value);
+
+                await expect(db.compact()).resolves.toBeUndefined();
+                await expect(db.get(kv.key)).resolves.toEqual(kv.value);
+            });
+
+            it('should compact the specified range', async () => {
+                const kv = { key: Buffer.from([1, 2, 3]), value: getRandomBytes() };
+                await db.set(kv.key, kv.value);
+
+                await expect(db.compact(Buffer.from([1]), Buffer.from([2]))).resolves.toBeUndefined();
+                await expect(db.compact(Buffer.from([1]))).resolves.toBeUndefined();
+                await expect(db.compact(undefined, Buffer.from([2]))).resolves.toBeUndefined();
+                await expect(db.get(kv.key)).resolves.toEqual(kv.value);
+            });
+        });
+
+        describe('flush', () => {
+            it('should flush the memtables', async () => {
+                const kv = { key: getRandomBytes(), value: getRandomBytes() };
+                await db.set(kv.key, kv.value);
+
+                await expect(db.flush()).resolves.toBeUndefined();
+                await expect(db.get(kv.key)).resolves.toEqual(kv.value);
+            });
+
+            it('should keep the writes without WAL after flush and reopen', async () => {
+                const dbPath = path.join(os.tmpdir(), 'db_disable_wal', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const walDB = new Database(dbPath, { disableWal: true });
+                const kv = { key: getRandomBytes(), value: getRandomBytes() };
+                await walDB.set(kv.key, kv.value);
+                await walDB.flush();
+                walDB.close();
+
+                const reopened = new Database(dbPath, { syncWrites: true });
+                await expect(reopened.get(kv.key)).resolves.toEqual(kv.value);
+                reopened.close();
+            });
+        });
+
+        describe('deleteRange', () => {
+            it('should delete the keys from start to end excluding end', async () => {
+                const keys = [0, 1, 2, 3, 4].map(i => Buffer.from([9, 9, i]));
+                for (const key of keys) {
+                    await db.set(key, getRandomBytes());
+                }
+
+                await expect(db.deleteRange(keys[1], keys[4])).resolves.toBeUndefined();
+                await expect(db.has(keys[0])).resolves.toEqual(true);
+                await expect(db.has(keys[1])).resolves.toEqual(false);
+                await expect(db.has(keys[2])).resolves.toEqual(false);
+                await expect(db.has(keys[3])).resolves.toEqual(false);
+                await expect(db.has(keys[4])).resolves.toEqual(true);
+            });
+        });
+
+        describe('countRange', () => {
+            it('should count the keys in the range up to the limit', async () => {
+                const keys = [0, 1, 2, 3, 4].map(i => Buffer.from([7, 7, i]));
+                for (const key of keys) {
+                    await db.set(key, getRandomBytes());
+                }
+
+                await expect(db.countRange({ gte: keys[0], lte: keys[4] })).resolves.toEqual(5);
+                await expect(db.countRange({ gte: keys[1], lte: keys[3] })).resolves.toEqual(3);
+                await expect(db.countRange({ gte: keys[0], lte: keys[4], limit: 1 })).resolves.toEqual(1);
+                await expect(db.countRange({ gte: Buffer.from([7, 8]), lte: Buffer.from([7, 9]) })).resolves.toEqual(0);
+            });
+        });
+
+        describe('existsPrefix', () => {
+            it('should check whether any key starts with the prefix', async () => {
+                await db.set(Buffer.from([8, 8, 1]), getRandomBytes());
+                await expect(db.existsPrefix(Buffer.from([8, 8]))).resolves.toEqual(true);
+                await expect(db.existsPrefix(Buffer.from([8, 8, 1]))).resolves.toEqual(true);
+                await expect(db.existsPrefix(Buffer.from([8, 8, 2]))).resolves.toEqual(false);
+                await expect(db.existsPrefix(Buffer.from([8, 9]))).resolves.toEqual(false);
+            });
+
+            it('should use the prefix filters of the length and refuse another length', async () => {
+                const newDBPath = path.join(os.tmpdir(), 'db', Date.now().toString());
+                fs.mkdirSync(newDBPath, { recursive: true });
+                const newDB = new Database(newDBPath, { prefixFilterLength: 2 });
+                const keys = [Buffer.from([1, 1, 1]), Buffer.from([1, 3, 1]), Buffer.from([1, 3, 2])];
+                for (const key of keys) {
+                    await newDB.set(key, getRandomBytes());
+                }
+                await newDB.flush();
+                await newDB.set(Buffer.from([1, 5, 1]), getRandomBytes());
+
+                await expect(newDB.existsPrefix(Buffer.from([1, 1]))).resolves.toEqual(true);
+                await expect(newDB.existsPrefix(Buffer.from([1, 2]))).resolves.toEqual(false);
+                await expect(newDB.existsPrefix(Buffer.from([1, 3, 2]))).resolves.toEqual(true);
+                await expect(newDB.existsPrefix(Buffer.from([1, 5]))).resolves.toEqual(true);
+                await expect(newDB.existsPrefix(Buffer.from([1]))).resolves.toEqual(true);
+                // ranges across the prefixes are not affected by the filters
+                await expect(newDB.countRange({ gte: Buffer.from([1, 2]), lte: Buffer.from([1, 4]) })).resolves.toEqual(2);
+                newDB.close();
+
+                expect(() => new Database(newDBPath, { prefixFilterLength: 3 })).toThrow(
+                    'prefix filter length 3 does not match the length 2 of the database',
+                );
+                try {
+                    new Database(newDBPath, { prefixFilterLength: 3 });
+                } catch (err) {
+                    expect(err.code).toEqual('ERR_INVALID_ARGUMENT');
+                }
+                const reopened = new Database(newDBPath, { prefixFilterLength: 2 });
+                await expect(reopened.existsPrefix(Buffer.from([1, 3]))).resolves.toEqual(true);
+                reopened.close();
+            });
+        });
+
+        describe('iteration with prefix', () => {
+            const keys = [
+                Buffer.from([9, 254, 255]),
+                Buffer.from([9, 255]),
+                Buffer.from([9, 255, 0]),
+                Buffer.from([9, 255, 255]),
+                Buffer.from([9, 255, 255, 1]),
+                Buffer.from([10]),
+            ];
+            const collect = async stream => new Promise((resolve, reject) => {
+                const result = [];
+                stream
+                    .on('data', ({ key }) => result.push(key))
+                    .on('error', reject)
+                    .on('end', () => resolve(result));
+            });
+
+            beforeAll(async () => {
+                for (const key of keys) {
+                    await db.set(key, getRandomBytes());
+                }
+            });
+
+            it('should iterate the keys with the prefix ending with 0xff', async () => {
+                await expect(collect(db.iterate({ prefix: Buffer.from([9, 255]) }))).resolves.toEqual(keys.slice(1, 5));
+                await expect(collect(db.iterate({ prefix: Buffer.from([9, 255]), reverse: true }))).resolves.toEqual(
+                    keys.slice(1, 5).reverse(),
+                );
+                await expect(collect(db.iterate({ prefix: Buffer.from([9, 255, 255]), reverse: true }))).resolves.toEqual(
+                    keys.slice(3, 5).reverse(),
+                );
+                await expect(collect(db.newReader().iterate({ prefix: Buffer.from([9, 255]), limit: 2 }))).resolves.toEqual(
+                    keys.slice(1, 3),
+                );
+            });
+
+            it('should iterate to the end of the database with the prefix of all 0xff', async () => {
+                const last = [Buffer.from([255, 255]), Buffer.from([255, 255, 255])];
+                for (const key of last) {
+                    await db.set(key, getRandomBytes());
+                }
+                await expect(collect(db.iterate({ prefix: Buffer.from([255, 255]) }))).resolves.toEqual(last);
+                await expect(collect(db.iterate({ prefix: Buffer.from([255, 255]), reverse: true }))).resolves.toEqual(
+                    [...last].reverse(),
+                );
+                await db.clear({ prefix: Buffer.from([255]) });
+                await expect(db.countRange({ prefix: Buffer.from([255]) })).resolves.toEqual(0);
+            });
+
+            it('should count the keys with the prefix', async () => {
+                await expect(db.countRange({ prefix: Buffer.from([9, 255]) })).resolves.toEqual(4);
+                await expect(db.countRange({ prefix: Buffer.from([9, 255]), limit: 1 })).resolves.toEqual(1);
+            });
+
+            it('should throw when prefix is used with gte or lte', async () => {
+                expect(() => db.iterate({ prefix: Buffer.from([9]), gte: Buffer.from([9, 0]) })).toThrow(
+                    'prefix cannot be used with gte or lte',
+                );
+                await expect(db.countRange({ prefix: Buffer.from([9]), lte: Buffer.from([9, 0]) })).rejects.toThrow(
+                    'prefix cannot be used with gte or lte',
+                );
+            });
+        });
+
+        describe('withPrefix', () => {
+            const prefix = Buffer.from([5, 255]);
+            const collect = async stream => new Promise((resolve, reject) => {
+                const keys = [];
+                stream
+                    .on('data', ({ key }) => keys.push(key))
+                    .on('error', reject)
+                    .on('end', () => resolve(keys));
+            });
+
+            beforeAll(async () => {
+                await db.set(Buffer.from([5, 254, 255]), getRandomBytes());
+                await db.set(Buffer.from([6]), getRandomBytes());
+                await db.set(Buffer.from([6, 0]), getRandomBytes());
+            });
+
+            it('should read and write the keys with the prefix', async () => {
+                const prefixed = db.withPrefix(prefix);
+                const key = Buffer.from([1, 2]);
+                const value = getRandomBytes();
+                await prefixed.set(key, value);
+
+                await expect(db.get(Buffer.concat([prefix, key]))).resolves.toEqual(value);
+                await expect(prefixed.get(key)).resolves.toEqual(value);
+                await expect(prefixed.has(key)).resolves.toEqual(true);
+                await expect(prefixed.has(Buffer.from([6]))).resolves.toEqual(false);
+
+                await prefixed.del(key);
+                await expect(db.has(Buffer.concat([prefix, key]))).resolves.toEqual(false);
+                await expect(prefixed.get(key)).rejects.toHaveProperty('code', 'ERR_NOT_FOUND');
+            });
+
+            it('should write the batch with the prefix', async () => {
+                const prefixed = db.withPrefix(prefix);
+                const batch = new Batch();
+                batch.set(Buffer.from([3]), Buffer.from([1]));
+                batch.set(Buffer.from([4]), Buffer.from([2]));
+                await prefixed.write(batch);
+
+                await expect(db.get(Buffer.from([5, 255, 3]))).resolves.toEqual(Buffer.from([1]));
+                await expect(prefixed.get(Buffer.from([4]))).resolves.toEqual(Buffer.from([2]));
+                // the written batch is consumed until it is reset
+                await expect(prefixed.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+                await expect(db.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+            });
+
+            it('should iterate only the keys with the prefix without the prefix', async () => {
+                const prefixed = db.withPrefix(prefix);
+                await prefixed.set(Buffer.from([0]), getRandomBytes());
+                await prefixed.set(Buffer.from([255, 255]), getRandomBytes());
+                const keys = [Buffer.from([0]), Buffer.from([3]), Buffer.from([4]), Buffer.from([255, 255])];
+
+                await expect(collect(prefixed.iterate())).resolves.toEqual(keys);
+                await expect(collect(prefixed.iterate({ reverse: true }))).resolves.toEqual([...keys].reverse());
+                await expect(collect(prefixed.iterate({ gte: Buffer.from([3]), lte: Buffer.from([4]) }))).resolves.toEqual(keys.slice(1, 3));
+                await expect(collect(prefixed.iterate({ limit: 2, reverse: true }))).resolves.toEqual([keys[3], keys[2]]);
+            });
+
+            it('should reject with closed error after the parent is closed', async () => {
+                const closedDBPath = path.join(os.tmpdir(), 'db', `prefixed_${Date.now().toString()}`);
+                fs.mkdirSync(closedDBPath, { recursive: true });
+                const closedDB = new Database(closedDBPath);
+                const prefixed = closedDB.withPrefix(prefix);
+                closedDB.close();
+
+                await expect(prefixed.get(Buffer.from([1]))).rejects.toThrow('Database is closed');
+                await expect(prefixed.set(Buffer.from([1]), getRandomBytes())).rejects.toHaveProperty('code', 'ERR_CLOSED');
+                expect(() => closedDB.withPrefix(prefix)).toThrow('Database is closed');
+            });
+
+            it('should throw with empty prefix', () => {
+                expect(() => db.withPrefix(Buffer.alloc(0))).toThrow('prefix must not be empty');
+            });
+        });
+
+        describe('write with maxBatchBytes', () => {
+            it('should write all the pairs in chunks', async () => {
+                const batch = new Batch();
+                const pairs = [];
+                for (let i = 0; i < 50; i += 1) {
+                    const pair = { key: Buffer.from([8, 8, i]), value: getRandomBytes() };
+                    pairs.push(pair);
+                    batch.set(pair.key, pair.value);
+                }
+                batch.del(pairs[0].key);
+                const journalKey = Buffer.from('journal');
+
+                await expect(db.write(batch, { maxBatchBytes: 100, journalKey })).resolves.toBeUndefined();
+                await expect(db.has(pairs[0].key)).resolves.toEqual(false);
+                for (const pair of pairs.slice(1)) {
+                    await expect(db.get(pair.key)).resolves.toEqual(pair.value);
+                }
+                await expect(db.has(journalKey)).resolves.toEqual(false);
+            });
+
+            it('should reject invalid options', async () => {
+                const batch = new Batch();
+                batch.set(getRandomBytes(), getRandomBytes());
+
+                await expect(db.write(batch, { maxBatchBytes: 100 })).rejects.toThrow('journalKey is required with maxBatchBytes');
+                await expect(db.write(batch, { maxBatchBytes: 0, journalKey: Buffer.from('journal') })).rejects.toThrow('maxBatchBytes must be a positive integer');
+                await expect(db.write(batch, { maxBatchBytes: 100 })).rejects.toHaveProperty('code', 'ERR_INVALID_ARGUMENT');
+            });
+        });
+
+        describe('conditionalWrite', () => {
+            it('should write the batch only if all the conditions hold', async () => {
+                const existing = getRandomBytes();
+                const missing = getRandomBytes();
+                const value = getRandomBytes();
+                await db.set(existing, value);
+                const batch = new Batch();
+                batch.set(missing, Buffer.from([1]));
+                batch.del(existing);
+
+                const failed = db.conditionalWrite([{ key: existing, expectedValue: value }, { key: existing, mustNotExist: true }], batch);
+                await expect(failed).rejects.toHaveProperty('code', 'ERR_CONDITION_FAILED');
+                await expect(db.conditionalWrite([{ key: missing, expectedValue: value }], batch)).rejects.toThrow(`condition failed for key ${missing.toString('hex')}`);
+                await expect(db.has(missing)).resolves.toEqual(false);
+                await expect(db.get(existing)).resolves.toEqual(value);
+
+                await expect(db.conditionalWrite([{ key: existing, expectedValue: value }, { key: missing, mustNotExist: true }], batch)).resolves.toBeUndefined();
+                await expect(db.get(missing)).resolves.toEqual(Buffer.from([1]));
+                await expect(db.has(existing)).resolves.toEqual(false);
+                // the batch is consumed only by the write which succeeded
+                await expect(db.conditionalWrite([{ key: missing, expectedValue: Buffer.from([1]) }], batch)).rejects.toHaveProperty(
+                    'code',
+                    'ERR_BATCH_CONSUMED',
+                );
+            });
+
+            it('should not lose any of the concurrent increments', async () => {
+                const key = getRandomBytes();
+                await db.set(key, Buffer.from([0]));
+                const increment = async () => {
+                    for (;;) {
+                        const current = await db.get(key);
+                        const batch = new Batch();
+                        batch.set(key, Buffer.from([current[0] + 1]));
+                        try {
+                            await db.conditionalWrite([{ key, expectedValue: current }], batch);
+                            return;
+                        } catch (err) {
+                            expect(err.code).toEqual('ERR_CONDITION_FAILED');
+                        } finally {
+                            batch.close();
+                        }
+                    }
+                };
+                await Promise.all([...Array(20).keys()].map(async () => increment()));
+                await expect(db.get(key)).resolves.toEqual(Buffer.from([20]));
+            });
+
+            it('should reject invalid conditions', async () => {
+                const batch = new Batch();
+                batch.set(getRandomBytes(), getRandomBytes());
+                const key = getRandomBytes();
+
+                await expect(db.conditionalWrite([{ key }], batch)).rejects.toHaveProperty('code', 'ERR_INVALID_ARGUMENT');
+                await expect(db.conditionalWrite([{ key, expectedValue: getRandomBytes(), mustNotExist: true }], batch)).rejects.toThrow(
+                    'condition must have either expectedValue or mustNotExist',
+                );
+            });
+        });
+
+        describe('metrics', () => {
+            it('should return null if metrics are disabled', async () => {
+                expect(db.getMetrics()).toBeNull();
+            });
+
+            it('should record the latency of the operations', async () => {
+                const dbPath = path.join(os.tmpdir(), 'db_metrics', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const metricsDB = new Database(dbPath, { metrics: true });
+                const key = getRandomBytes();
+                await metricsDB.set(key, getRandomBytes());
+                await metricsDB.get(key);
+                await metricsDB.get(key);
+
+                const metrics = metricsDB.getMetrics(true);
+                expect(metrics.bucketBoundsMs[0]).toEqual(0.1);
+                expect(metrics.bucketBoundsMs[metrics.bucketBoundsMs.length - 1]).toEqual(10000);
+                expect(metrics.get.execution.count).toEqual(2);
+                expect(metrics.get.queueWait.count).toEqual(2);
+                expect(metrics.get.execution.buckets).toHaveLength(metrics.bucketBoundsMs.length + 1);
+                expect(metrics.get.execution.buckets.reduce((sum, count) => sum + count, 0)).toEqual(2);
+                expect(metrics.write.execution.count).toEqual(1);
+                expect(metrics.commit.execution.count).toEqual(0);
+                // counters are reset after the snapshot
+                expect(metricsDB.getMetrics().get.execution.count).toEqual(0);
+                metricsDB.close();
+            });
+        });
+
+        describe('stats', () => {
+            it('should return the database properties as numbers', async () => {
+                await db.set(getRandomBytes(), getRandomBytes());
+                const stats = await db.stats();
+                expect(typeof stats.estimateNumKeys).toBe('number');
+                expect(typeof stats.curSizeAllMemTables).toBe('number');
+                expect(typeof stats.numFilesAtLevel0).toBe('number');
+                for (const value of Object.values(stats)) {
+                    expect(typeof value).toBe('number');
+                }
+            });
+        });
+
+        describe('approximateSize', () => {
+            it('should return the SST and memtable sizes of the range', async () => {
+                const start = Buffer.from([9, 9]);
+                for (let i = 0; i < 50; i += 1) {
+                    await db.set(Buffer.concat([start, getRandomBytes()]), getRandomBytes());
+                }
+                const before = await db.approximateSize(start, Buffer.from([9, 10]));
+                expect(before.memtableSize).toBeGreaterThan(0);
+
+                await db.flush();
+                const size = await db.approximateSize(start, Buffer.from([9, 10]));
+                expect(size.sstSize).toBeGreaterThan(0);
+                await expect(db.approximateSize(Buffer.from([9, 10]), Buffer.from([9, 11]))).resolves.toHaveProperty('sstSize', 0);
+                const all = await db.approximateSize();
+                expect(all.sstSize).toBeGreaterThanOrEqual(size.sstSize);
+            });
+        });
+
+        describe('maxPendingOperations', () => {
+            const openDB = options => {
+                const dbPath = path.join(os.tmpdir(), 'db', `pending_${Date.now().toString()}_${Math.random()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                return new Database(dbPath, options);
+            };
+
+            it('should reject the writes with backpressure error when the queue is full', async () => {
+                const limitedDB = openDB({ maxPendingOperations: 1 });
+                const pairs = Array.from({ length: 200 }, () => ({ key: getRandomBytes(), value: getRandomBytes() }));
+                const results = await Promise.allSettled(pairs.map(pair => limitedDB.set(pair.key, pair.value)));
+
+                for (let i = 0; i < results.length; i += 1) {
+                    if (results[i].status === 'rejected') {
+                        expect(results[i].reason.message).toMatch('backpressure');
+                        await expect(limitedDB.has(pairs[i].key)).resolves.toEqual(false);
+                    } else {
+                        await expect(limitedDB.get(pairs[i].key)).resolves.toEqual(pairs[i].value);
+                    }
+                }
+                expect(limitedDB.pendingOperations()).toEqual(0);
+                limitedDB.close();
+            });
+
+            it('should delay the writes until the queue drains with blockOnFull', async () => {
+                const limitedDB = openDB({ maxPendingOperations: 2, blockOnFull: true });
+                const pairs = Array.from({ length: 200 }, () => ({ key: getRandomBytes(), value: getRandomBytes() }));
+                const order = [];
+                await Promise.all(pairs.map((pair, i) => limitedDB.set(pair.key, pair.value).then(() => order.push(i))));
+
+                expect(order).toEqual(pairs.map((_, i) => i));
+                for (const pair of pairs) {
+                    await expect(limitedDB.get(pair.key)).resolves.toEqual(pair.value);
+                }
+                expect(limitedDB.pendingOperations()).toEqual(0);
+                limitedDB.close();
+            });
+
+            it('should not throttle the reads', async () => {
+                const limitedDB = openDB({ maxPendingOperations: 1 });
+                const key = getRandomBytes();
+                await limitedDB.set(key, getRandomBytes());
+
+                const results = await Promise.allSettled(Array.from({ length: 100 }, () => limitedDB.has(key)));
+                expect(results.every(result => result.status === 'fulfilled' && result.value)).toBe(true);
+                limitedDB.close();
+            });
+
+            it('should throw when maxPendingOperations is invalid', () => {
+                expect(() => openDB({ maxPendingOperations: 0 })).toThrow('maxPendingOperations must be a positive integer');
+            });
+        });
+
+        describe('locked database', () => {
+            it('should throw ERR_DB_LOCKED with the path when the database is already open', async () => {
+                const dbPath = path.join(os.tmpdir(), 'db', `locked_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                const primary = new Database(dbPath);
+                const key = getRandomBytes();
+                const value = getRandomBytes();
+                await primary.set(key, value);
+                await primary.flush();
+
+                expect(() => new Database(dbPath)).toThrow(dbPath);
+                try {
+                    new Database(dbPath);
+                } catch (err) {
+                    expect(err.code).toEqual('ERR_DB_LOCKED');
+                }
+
+                const secondary = new Database(dbPath, { exclusive: false });
+                await expect(secondary.get(key)).resolves.toEqual(value);
+                await expect(secondary.set(getRandomBytes(), getRandomBytes())).rejects.toThrow();
+                secondary.close();
+                primary.close();
+            });
+        });
+
+        describe('size limits', () => {
+            let limitedDB;
+            beforeAll(() => {
+                const dbPath = path.join(os.tmpdir(), 'db', `limits_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                limitedDB = new Database(dbPath, { maxKeySize: 8, maxValueSize: 16 });
+            });
+
+            afterAll(() => {
+                limitedDB.close();
+            });
+
+            it('should reject the empty key and the key or the value over the limits', async () => {
+                await expect(limitedDB.set(Buffer.alloc(0), getRandomBytes(1))).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+                await expect(limitedDB.set(getRandomBytes(9), getRandomBytes(1))).rejects.toThrow('key is too large: maximum 8 bytes, got 9');
+                await expect(limitedDB.set(getRandomBytes(8), getRandomBytes(17))).rejects.toThrow('value is too large: maximum 16 bytes, got 17');
+                await expect(limitedDB.del(Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+
+                const key = getRandomBytes(8);
+                await limitedDB.set(key, getRandomBytes(16));
+                await expect(limitedDB.has(key)).resolves.toBe(true);
+            });
+
+            it('should reject the whole batch if any operation is over the limits', async () => {
+                const valid = { key: getRandomBytes(8), value: getRandomBytes(16) };
+                const batch = new Batch();
+                batch.set(valid.key, valid.value);
+                batch.set(getRandomBytes(4), getRandomBytes(17));
+                await expect(limitedDB.write(batch)).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+                await expect(limitedDB.has(valid.key)).resolves.toBe(false);
+            });
+
+            it('should reject the empty key on the batch', () => {
+                const batch = new Batch();
+                expect(() => batch.set(Buffer.alloc(0), getRandomBytes())).toThrow('key must not be empty');
+                expect(() => batch.del(Buffer.alloc(0))).toThrow('key must not be empty');
+            });
+
+            it('should store the empty value and return the empty Buffer', async () => {
+                const keys = [getRandomBytes(8), getRandomBytes(8), getRandomBytes(8)];
+                await limitedDB.set(keys[0], Buffer.alloc(0));
+                await limitedDB.withPrefix(Buffer.from([1])).set(keys[1], Buffer.alloc(0));
+                const batch = new Batch();
+                batch.set(keys[2], Buffer.alloc(0));
+                await limitedDB.write(batch);
+
+                await expect(limitedDB.get(keys[0])).resolves.toEqual(Buffer.alloc(0));
+                await expect(limitedDB.withPrefix(Buffer.from([1])).get(keys[1])).resolves.toEqual(Buffer.alloc(0));
+                await expect(limitedDB.get(keys[2])).resolves.toEqual(Buffer.alloc(0));
+                await expect(limitedDB.has(keys[0])).resolves.toBe(true);
+            });
+
+            it('should apply the default limits of the key length', async () => {
+                await expect(db.set(getRandomBytes(102), getRandomBytes())).resolves.toBeUndefined();
+                await expect(db.set(getRandomBytes(103), getRandomBytes())).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+            });
+
+            it('should throw when maxValueSize is invalid', () => {
+                const dbPath = path.join(os.tmpdir(), 'db', `limits_invalid_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new Database(dbPath, { maxValueSize: 0 })).toThrow('maxValueSize must be a positive integer');
+            });
+        });
     });
 
     describe('InMemoryDatabase', () => {
@@ -516,6 +1422,18 @@ describe('database', () => {
             await expect(db.get(getRandomBytes())).rejects.toThrow(NotFoundError);
         });
 
+        it('should reject the empty key and the batch over the limits', async () => {
+            await expect(db.set(Buffer.alloc(0), getRandomBytes())).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+            await expect(db.del(Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+
+            const kv = { key: getRandomBytes(), value: getRandomBytes() };
+            const batch = new Batch();
+            batch.set(kv.key, kv.value);
+            batch.set(getRandomBytes(103), getRandomBytes());
+            await expect(db.write(batch)).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+            await expect(db.has(kv.key)).resolves.toEqual(false);
+        });
+
         it('should get the value if exist', async () => {
             const kv = { key: getRandomBytes(), value: getRandomBytes() };
             const batch = new Batch();
@@ -525,6 +1443,19 @@ describe('database', () => {
             await expect(db.get(kv.key)).resolves.toEqual(kv.value);
         });
 
+        it('should consume the written batch until it is reset', async () => {
+            const kv = { key: getRandomBytes(), value: getRandomBytes() };
+            const batch = new Batch();
+            batch.set(kv.key, kv.value);
+            await db.write(batch);
+            await expect(db.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+
+            batch.reset();
+            batch.del(kv.key);
+            await db.write(batch);
+            await expect(db.has(kv.key)).resolves.toEqual(false);
+        });
+
         it('should clone the data', async () => {
             const kv = { key: getRandomBytes(), value: getRandomBytes() };
             const batch = new Batch();
@@ -541,6 +1472,54 @@ describe('database', () => {
             await expect(cloned.get(kv.key)).resolves.toEqual(kv.value);
         });
 
+        describe('snapshot and restoreSnapshot', () => {
+            it('should undo the changes after the snapshot', async () => {
+                const snapshotDB = new InMemoryDatabase();
+                const existing = { key: Buffer.from([1, 0, 0]), value: getRandomBytes() };
+                await snapshotDB.set(existing.key, existing.value);
+
+                const first = snapshotDB.snapshot();
+                await snapshotDB.set(existing.key, getRandomBytes());
+                const second = snapshotDB.snapshot();
+                const batch = new Batch();
+                batch.set(Buffer.from([0, 0, 1]), getRandomBytes());
+                batch.del(existing.key);
+                await snapshotDB.write(batch);
+                await expect(snapshotDB.has(existing.key)).resolves.toEqual(false);
+
+                snapshotDB.restoreSnapshot(second);
+                await expect(snapshotDB.has(Buffer.from([0, 0, 1]))).resolves.toEqual(false);
+                await expect(snapshotDB.get(existing.key)).resolves.not.toEqual(existing.value);
+
+                snapshotDB.restoreSnapshot(first);
+                const stream = snapshotDB.iterate();
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('error', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+                expect(values).toEqual([existing]);
+            });
+
+            it('should throw when the snapshot is dropped', () => {
+                const snapshotDB = new InMemoryDatabase();
+                const first = snapshotDB.snapshot();
+                const second = snapshotDB.snapshot();
+                snapshotDB.restoreSnapshot(first);
+
+                expect(() => snapshotDB.restoreSnapshot(second)).toThrow('Invalid usage');
+                expect(() => snapshotDB.restoreSnapshot(100)).toThrow('Invalid usage');
+            });
+        });
+
         describe('iteration', () => {
             let pairs;
             beforeAll(async () => {
@@ -594,6 +1573,31 @@ describe('database', () => {
                 expect(values).toEqual(pairs.slice(1, 3));
             });
 
+            it('should iterate all the keys greater than gte when lte is not specified', async () => {
+                const longKey = { key: Buffer.from([1, 0, 1, 255]), value: getRandomBytes() };
+                await db.set(longKey.key, longKey.value);
+                const stream = db.iterate({
+                    gte: Buffer.from([1, 0, 1]),
+                });
+
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('err', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+                await db.del(longKey.key);
+
+                expect(values).toEqual([pairs[3], longKey]);
+            });
+
             it('should iterate with specified range with limit using createReadStream', async () => {
                 const stream = db.createReadStream({
                     gte: Buffer.from([0, 0, 1]),
@@ -668,5 +1672,138 @@ describe('database', () => {
                 expect(values).toEqual([]);
             });
         });
+
+        describe('snapshotToFile and loadFromFile', () => {
+            let tmpPath;
+
+            beforeAll(() => {
+                tmpPath = fs.mkdtempSync(path.join(os.tmpdir(), 'in_memory_snapshot'));
+            });
+
+            it('should restore all the key-value pairs into a new database', async () => {
+                const original = new InMemoryDatabase();
+                const pairs = [
+                    { key: Buffer.from([0, 0, 1]), value: getRandomBytes() },
+                    { key: Buffer.from([0, 0, 2]), value: Buffer.alloc(0) },
+                    { key: getRandomBytes(), value: getRandomBytes(1000) },
+                ];
+                for (const pair of pairs) {
+                    await original.set(pair.key, pair.value);
+                }
+                const filePath = path.join(tmpPath, 'snapshot');
+                await original.snapshotToFile(filePath, { sync: true });
+
+                const restored = await InMemoryDatabase.loadFromFile(filePath);
+                for (const pair of pairs) {
+                    await expect(restored.get(pair.key)).resolves.toEqual(pair.value);
+                }
+                // restored database is independent from the original
+                await restored.del(pairs[0].key);
+                await expect(original.has(pairs[0].key)).resolves.toEqual(true);
+            });
+
+            it('should reject when the file is corrupted', async () => {
+                const original = new InMemoryDatabase();
+                await original.set(getRandomBytes(), getRandomBytes(100));
+                const filePath = path.join(tmpPath, 'corrupted');
+                await original.snapshotToFile(filePath);
+
+                const data = fs.readFileSync(filePath);
+                fs.writeFileSync(filePath, data.subarray(0, data.length - 10));
+                await expect(InMemoryDatabase.loadFromFile(filePath)).rejects.toThrow('Corrupted snapshot');
+
+                fs.writeFileSync(filePath, getRandomBytes(100));
+                await expect(InMemoryDatabase.loadFromFile(filePath)).rejects.toThrow('Invalid snapshot header');
+            });
+
+            it('should reject when the file does not exist', async () => {
+                await expect(InMemoryDatabase.loadFromFile(path.join(tmpPath, 'unknown'))).rejects.toThrow();
+            });
+        });
+    });
+
+    describe('iteration parity', () => {
+        const keys = [
+            Buffer.from([0]),
+            Buffer.from([0, 0]),
+            Buffer.from([0, 255]),
+            Buffer.from([1, 2, 3]),
+            Buffer.from([1, 2, 3, 0]),
+            Buffer.from([254, 255]),
+            Buffer.from([255]),
+            Buffer.from([255, 0]),
+            Buffer.from([255, 255]),
+        ];
+        const cases = [
+            {},
+            { reverse: true },
+            { limit: 0 },
+            { limit: 3 },
+            { limit: 3, reverse: true },
+            { gte: Buffer.from([0, 128]) },
+            { lte: Buffer.from([255]), reverse: true },
+            { gte: Buffer.from([0, 255]), lte: Buffer.from([255, 0]), limit: 4 },
+            { gte: Buffer.from([0, 255]), lte: Buffer.from([255, 0]), limit: 2, reverse: true },
+            { gte: Buffer.from([255, 255, 255]) },
+            { gte: Buffer.from([255]), reverse: true },
+            { lte: Buffer.from([0, 0]) },
+            { prefix: Buffer.from([0]) },
+            { prefix: Buffer.from([1, 2]), reverse: true },
+            { prefix: Buffer.from([255]), limit: 2, reverse: true },
+            { prefix: Buffer.from([255]), keysOnly: true },
+        ];
+        let backends;
+
+        const collect = async stream => new Promise((resolve, reject) => {
+            const result = [];
+            stream
+                .on('data', kv => result.push(kv))
+                .on('error', reject)
+                .on('end', () => resolve(result));
+        });
+
+        beforeAll(async () => {
+            const dbPath = path.join(os.tmpdir(), 'db_parity', Date.now().toString());
+            fs.mkdirSync(dbPath, { recursive: true });
+            backends = [new Database(dbPath), new InMemoryDatabase()];
+            for (const backend of backends) {
+                const batch = new Batch();
+                for (const [i, key] of keys.entries()) {
+                    batch.set(key, Buffer.from([i]));
+                }
+                await backend.write(batch);
+            }
+        });
+
+        afterAll(() => {
+            backends[0].close();
+        });
+
+        it.each(cases)('should iterate the same on both databases with %p', async options => {
+            const [onDisk, inMemory] = await Promise.all(backends.map(async backend => collect(backend.iterate(options))));
+            expect(inMemory).toEqual(onDisk);
+
+            const compare = options.reverse ? (a, b) => Buffer.compare(b, a) : Buffer.compare;
+            // the side not specified defaults to 0x00 or 0xff with the length of the other side
+            const gte = options.gte || (options.lte && Buffer.alloc(options.lte.length, 0));
+            const lte = options.lte || (options.gte && Buffer.alloc(options.gte.length, 255));
+            const expected = keys
+                .map((key, i) => ({ key, value: Buffer.from([i]) }))
+                .filter(({ key }) => !gte || Buffer.compare(key, gte) >= 0)
+                .filter(({ key }) => !lte || Buffer.compare(key, lte) <= 0)
+                .filter(({ key }) => !options.prefix || key.subarray(0, options.prefix.length).equals(options.prefix))
+                .sort((a, b) => compare(a.key, b.key))
+                .slice(0, options.limit === undefined ? keys.length : options.limit)
+                .map(({ key, value }) => (options.keysOnly ? { key } : { key, value }));
+            expect(onDisk).toEqual(expected);
+        });
+
+        it('should reject prefix with gte on both databases', () => {
+            for (const backend of backends) {
+                expect(() => backend.iterate({ prefix: Buffer.from([0]), gte: Buffer.from([0, 0]) })).toThrow(
+                    'prefix cannot be used with gte or lte',
+                );
+            }
+        });
     });
 });
@@ -11,6 +11,7 @@
  *
  * Removal or modification of this copyright notice is prohibited.
  */
+const crypto = require('crypto');
 const { SparseMerkleTree } = require('../main');
 const { getRandomBytes } = require('./utils');
 const { isInclusionProofForQueryKey } = require('../utils');
@@ -46,6 +47,25 @@ describe('SparseMerkleTree', () => {
 		}
 	});
 
+	describe('duplicated keys', () => {
+		it('should keep the last occurrence of the key', async () => {
+			const key = getRandomBytes(32);
+			const other = { key: getRandomBytes(32), value: getRandomBytes(32) };
+			const first = getRandomBytes(32);
+			const last = getRandomBytes(32);
+
+			const expected = await new SparseMerkleTree(32).update(Buffer.alloc(0), [other, { key, value: last }]);
+			for (let i = 0; i < 5; i += 1) {
+				const smt = new SparseMerkleTree(32);
+				const rootHash = await smt.update(Buffer.alloc(0), [{ key, value: first }, other, { key, value: last }]);
+				expect(rootHash).toEqual(expected);
+			}
+
+			const reversed = await new SparseMerkleTree(32).update(Buffer.alloc(0), [{ key, value: last }, other, { key, value: first }]);
+			expect(reversed).not.toEqual(expected);
+		});
+	});
+
 	describe('inclusion and non-inclusion proof', () => {
 		for (const test of [...FixturesInclusionProof.testCases, ...FixturesNonInclusionProof.testCases]) {
 			it(test.description, async () => {
@@ -339,6 +359,235 @@ describe('SparseMerkleTree', () => {
 		}
 	});
 
+	describe('prove with empty value', () => {
+		const emptyValueHash = crypto.createHash('sha256').update(Buffer.alloc(0)).digest();
+
+		it('should reject prove of the key with the hash of empty value', async () => {
+			const smt = new SparseMerkleTree(32);
+			const key = getRandomBytes();
+			const rootHash = await smt.update(Buffer.alloc(0), [
+				{ key, value: emptyValueHash },
+				{ key: getRandomBytes(), value: getRandomBytes() },
+			]);
+			await expect(smt.prove(rootHash, [key])).rejects.toThrow(
+				`Empty value in the state for the key \`${key.toString('hex')}\``,
+			);
+		});
+
+		it('should reject verify of the inclusion proof of empty value', async () => {
+			const smt = new SparseMerkleTree(32);
+			const key = getRandomBytes();
+			const rootHash = await smt.update(Buffer.alloc(0), [{ key, value: getRandomBytes() }]);
+			const proof = await smt.prove(rootHash, [key]);
+			proof.queries[0].value = emptyValueHash;
+			await expect(smt.verify(rootHash, [key], proof)).rejects.toThrow(
+				`Empty value in the state for the key \`${key.toString('hex')}\``,
+			);
+		});
+	});
+
+	describe('calculateRootFromData', () => {
+		for (const test of [...FixturesInclusionProof.testCases, ...FixturesNonInclusionProof.testCases]) {
+			// eslint-disable-next-line no-loop-func
+			it(test.description, async () => {
+				const smt = new SparseMerkleTree(32);
+				const deletedKeys = new Set(test.input.deleteKeys);
+				const kvpair = [];
+				for (let i = 0; i < test.input.keys.length; i += 1) {
+					if (deletedKeys.has(test.input.keys[i])) {
+						continue;
+					}
+					kvpair.push({ key: Buffer.from(test.input.keys[i], 'hex'), value: Buffer.from(test.input.values[i], 'hex') });
+				}
+
+				await expect(smt.calculateRootFromData(kvpair)).resolves.toEqual(Buffer.from(test.output.merkleRoot, 'hex'));
+			});
+		}
+
+		it('should match the root of the incremental update on random data', async () => {
+			const smt = new SparseMerkleTree(32);
+			const kvpair = [];
+			for (let i = 0; i < 500; i += 1) {
+				kvpair.push({ key: getRandomBytes(32), value: getRandomBytes(32) });
+			}
+			let root = Buffer.alloc(0);
+			for (let i = 0; i < kvpair.length; i += 100) {
+				root = await smt.update(root, kvpair.slice(i, i + 100));
+			}
+
+			await expect(smt.calculateRootFromData(kvpair)).resolves.toEqual(root);
+		});
+
+		it('should reject keys with invalid length', async () => {
+			const smt = new SparseMerkleTree(32);
+
+			await expect(smt.calculateRootFromData([{ key: getRandomBytes(31), value: getRandomBytes(32) }])).rejects.toThrow(
+				'invalid key length',
+			);
+		});
+	});
+
+	describe('verifyBatch', () => {
+		const createEntries = async (smt, count) => {
+			const entries = [];
+			let root = Buffer.alloc(0);
+			for (let i = 0; i < count; i += 1) {
+				const key = getRandomBytes(32);
+				root = await smt.update(root, [{ key, value: getRandomBytes(32) }]);
+				const proof = await smt.prove(root, [key]);
+				entries.push({ root, proof, queryKeys: [key] });
+			}
+			return entries;
+		};
+
+		it('should resolve with the results of the entries against the different roots in order', async () => {
+			const smt = new SparseMerkleTree(32);
+			const entries = await createEntries(smt, 30);
+			entries[3] = { ...entries[3], root: entries[4].root };
+
+			const result = await smt.verifyBatch(entries);
+
+			const expected = await Promise.all(entries.map(async ({ root, proof, queryKeys }) => smt.verify(root, queryKeys, proof)));
+			expect(result).toEqual(expected);
+			expect(result.filter(verified => !verified)).toHaveLength(1);
+			expect(result[3]).toBe(false);
+		});
+
+		it('should resolve with false for the malformed entries without failing the others', async () => {
+			const smt = new SparseMerkleTree(32);
+			const entries = await createEntries(smt, 4);
+			const malformed = [
+				{ ...entries[0], proof: { siblingHashes: 'invalid', queries: [] } },
+				{ ...entries[1], queryKeys: [getRandomBytes(20)] },
+				{ root: entries[2].root },
+				'invalid',
+			];
+
+			await expect(smt.verifyBatch([...malformed, entries[3]])).resolves.toEqual([false, false, false, false, true]);
+			await expect(smt.verifyBatch([])).resolves.toEqual([]);
+		});
+	});
+
+	describe('hasher', () => {
+		it('should calculate the same roots and proofs with sha256 as the default', async () => {
+			const smt = new SparseMerkleTree(32);
+			const sha256 = new SparseMerkleTree(32, { hasher: 'sha256' });
+			const kvpairs = [];
+			for (let i = 0; i < 20; i += 1) {
+				kvpairs.push({ key: getRandomBytes(32), value: getRandomBytes(32) });
+			}
+			const root = await smt.update(Buffer.alloc(0), kvpairs);
+
+			await expect(sha256.update(Buffer.alloc(0), kvpairs)).resolves.toEqual(root);
+			const queryKeys = [kvpairs[0].key, getRandomBytes(32)];
+			const proof = await sha256.prove(root, queryKeys);
+			await expect(smt.verify(root, queryKeys, proof)).resolves.toBe(true);
+			await expect(sha256.verify(root, queryKeys, proof)).resolves.toBe(true);
+			await expect(sha256.calculateRoot(proof)).resolves.toEqual(root);
+		});
+
+		it('should throw when the hasher is unknown', () => {
+			expect(() => new SparseMerkleTree(32, { hasher: 'keccak' })).toThrow('hasher must be one of the enabled hashers, got keccak');
+			expect(() => new SparseMerkleTree(32, { hasher: 1 })).toThrow();
+		});
+	});
+
+	describe('exportNodes and importNodes', () => {
+		const updatedTree = async () => {
+			const smt = new SparseMerkleTree(32);
+			const kvpair = [];
+			for (let i = 0; i < 200; i += 1) {
+				kvpair.push({ key: getRandomBytes(32), value: getRandomBytes(32) });
+			}
+			const root = await smt.update(Buffer.alloc(0), kvpair);
+			return { smt, root, kvpair };
+		};
+
+		it('should export the nodes in depth-first order ending with the root', async () => {
+			const { smt, root, kvpair } = await updatedTree();
+			const nodes = await smt.exportNodes(root);
+
+			expect(nodes[nodes.length - 1]).toEqual({ nodeHash: root, kind: 'branch', height: 0 });
+			const leaves = nodes.filter(node => node.kind === 'leaf');
+			const sorted = [...kvpair].sort((a, b) => Buffer.compare(a.key, b.key));
+			expect(leaves.map(leaf => leaf.key)).toEqual(sorted.map(pair => pair.key));
+			expect(leaves.map(leaf => leaf.value)).toEqual(sorted.map(pair => pair.value));
+			for (const node of nodes) {
+				expect(['leaf', 'branch', 'empty']).toContain(node.kind);
+			}
+			await expect(smt.exportNodes(root)).resolves.toEqual(nodes);
+		});
+
+		it('should export the empty tree', async () => {
+			const smt = new SparseMerkleTree(32);
+			const nodes = await smt.exportNodes(Buffer.alloc(0));
+			expect(nodes).toHaveLength(1);
+			expect(nodes[0].kind).toEqual('empty');
+		});
+
+		it('should import the exported nodes and resolve with the root', async () => {
+			const { smt, root } = await updatedTree();
+			const nodes = await smt.exportNodes(root);
+
+			const imported = new SparseMerkleTree(32);
+			await expect(imported.importNodes(nodes)).resolves.toEqual(root);
+			await expect(imported.exportNodes(root)).resolves.toEqual(nodes);
+		});
+
+		it('should reject the tampered nodes', async () => {
+			const { smt, root } = await updatedTree();
+			const nodes = await smt.exportNodes(root);
+			const imported = new SparseMerkleTree(32);
+
+			const leafIndex = nodes.findIndex(node => node.kind === 'leaf');
+			const tamperedValue = nodes.map((node, i) => (i === leafIndex ? { ...node, value: getRandomBytes(32) } : node));
+			await expect(imported.importNodes(tamperedValue)).rejects.toThrow('Not matching with the exported nodes');
+			await expect(imported.importNodes(nodes.slice(1))).rejects.toThrow('Not matching with the exported nodes');
+			await expect(imported.exportNodes(root)).rejects.toThrow();
+
+			const invalidKind = nodes.map((node, i) => (i === 0 ? { ...node, kind: 'stub' } : node));
+			await expect(imported.importNodes(invalidKind)).rejects.toThrow('kind must be one of leaf, branch or empty, got stub');
+		});
+	});
+
+	describe('runVectors', () => {
+		it('should compute the same outputs as the fixtures', async () => {
+			for (const fixtures of [FixturesInclusionProof, FixturesNonInclusionProof]) {
+				const vectors = { ...fixtures, testCases: fixtures.testCases.slice(0, 5) };
+				await expect(SparseMerkleTree.runVectors(JSON.stringify(vectors))).resolves.toEqual(vectors);
+			}
+		});
+
+		it('should resolve with the roots after each batch', async () => {
+			const keys = [getRandomBytes(32), getRandomBytes(32)].map(k => k.toString('hex'));
+			const result = await SparseMerkleTree.runVectors({
+				testCases: [
+					{
+						input: {
+							batches: [
+								{ keys, values: ['01', '02'] },
+								{ keys: [], values: [], deleteKeys: [keys[0]] },
+							],
+							queryKeys: keys,
+						},
+					},
+				],
+			});
+			const { output } = result.testCases[0];
+
+			const smt = new SparseMerkleTree(32);
+			const first = await smt.update(Buffer.alloc(0), keys.map((k, i) => ({ key: Buffer.from(k, 'hex'), value: Buffer.from([i + 1]) })));
+			const second = await smt.update(first, [{ key: Buffer.from(keys[0], 'hex'), value: Buffer.alloc(0) }]);
+			expect(output.roots).toEqual([first.toString('hex'), second.toString('hex')]);
+			expect(output.merkleRoot).toEqual(second.toString('hex'));
+			expect(output.proof.queries).toHaveLength(2);
+		});
+
+		it('should reject the invalid vectors', async () => {
+			await expect(SparseMerkleTree.runVectors('{')).rejects.toThrow('invalid test vectors');
+		});
+	});
+
 	describe('calculateRoot', () => {
 		for (const test of [...FixturesInclusionProof.testCases, ...FixturesNonInclusionProof.testCases]) {
 			// eslint-disable-next-line no-loop-func
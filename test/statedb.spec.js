@@ -17,8 +17,32 @@ const os = require('os');
 const path = require('path');
 const fs = require('fs');
 const crypto = require('crypto');
-const { StateDB, NotFoundError } = require('../main');
-const { getRandomBytes } = require('./utils');
+const { spawnSync } = require('child_process');
+const { StateDB, InMemoryStateDB, Database, Batch, NotFoundError, CheckpointGroup, SparseMerkleTree, shutdownAll, keyEncode, keyDecode, constants } = require('../main');
+const { getRandomBytes, collectGarbageUntil } = require('./utils');
+
+// commitAndKill commits a key in a child process and kills it without closing the DB.
+// It returns the committed root and the key.
+const commitAndKill = (dbPath, dbOptions, commitOptions, flush = false) => {
+    const script = `
+        const fs = require('fs');
+        const { StateDB } = require(${JSON.stringify(path.join(__dirname, '../main'))});
+        (async () => {
+            const db = new StateDB(${JSON.stringify(dbPath)}, ${JSON.stringify(dbOptions)});
+            const writer = db.newReadWriter();
+            await writer.set(Buffer.from([0, 0, 0, 1, 0, 0, 1]), Buffer.from([1, 2, 3]));
+            const root = await db.commit(writer, 1, Buffer.alloc(0), ${JSON.stringify(commitOptions)});
+            if (${flush}) {
+                await db.flush();
+            }
+            fs.writeSync(1, root.toString('hex'));
+            process.kill(process.pid, 'SIGKILL');
+        })();
+    `;
+    const result = spawnSync(process.execPath, ['-e', script], { encoding: 'utf8' });
+    expect(result.signal).toEqual('SIGKILL');
+    return { root: Buffer.from(result.stdout, 'hex'), key: Buffer.from([0, 0, 0, 1, 0, 0, 1]) };
+};
 
 const sha256 = val => {
     const hasher = crypto.createHash('sha256');
@@ -56,11 +80,9 @@ describe('statedb', () => {
             key: Buffer.from('0000000d800067656e657369735f323', 'hex'),
             value: getRandomBytes(),
         },
-        {
-            key: Buffer.from([0, 0, 0, 15, 0, 0]),
-            value: Buffer.alloc(0),
-        },
     ];
+    // emptyValueKey holds the empty value written directly to the state, as the writer rejects it
+    const emptyValueKey = Buffer.from([0, 0, 0, 15, 0, 0]);
 
     let db;
     let root;
@@ -74,6 +96,9 @@ describe('statedb', () => {
             await writer.set(pair.key, pair.value);
         }
         root = await db.commit(writer, 0, Buffer.alloc(0));
+        const batch = new Batch();
+        batch.set(emptyValueKey, Buffer.alloc(0), 'state');
+        await db.write(batch);
     });
 
     afterAll(() => {
@@ -98,14 +123,84 @@ describe('statedb', () => {
         });
 
         it('should return zero buffer when value length is zero', async () => {
-            await expect(db.get(Buffer.from([0, 0, 0, 15, 0, 0]))).resolves.toEqual(Buffer.alloc(0));
+            await expect(db.get(emptyValueKey)).resolves.toEqual(Buffer.alloc(0));
         });
 
         it('should get the empty Buffer if exist but empty', async () => {
+            const writer = db.newReadWriter();
+            await expect(writer.get(emptyValueKey)).resolves.toEqual(Buffer.alloc(0));
+            await expect(writer.has(emptyValueKey)).resolves.toEqual(true);
+        });
+
+        it('should reject the empty value with ERR_EMPTY_VALUE', async () => {
             const writer = db.newReadWriter();
             const key = getRandomBytes();
-            await writer.set(key, Buffer.alloc(0));
-            await expect(writer.get(key)).resolves.toEqual(Buffer.alloc(0));
+            await expect(writer.set(key, Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_EMPTY_VALUE');
+            await expect(writer.has(key)).resolves.toEqual(false);
+        });
+
+        it('should keep get, prove and revert consistent after the empty value is rejected', async () => {
+            const dbPath = path.join(os.tmpdir(), 'state_empty_value', Date.now().toString());
+            fs.mkdirSync(dbPath, { recursive: true });
+            const emptyDB = new StateDB(dbPath);
+            const existingKey = Buffer.from([0, 0, 0, 15, 0, 0]);
+            const newKey = Buffer.from([0, 0, 0, 15, 0, 1]);
+            const value = getRandomBytes();
+            const writer = emptyDB.newReadWriter();
+            await writer.set(existingKey, value);
+            const root1 = await emptyDB.commit(writer, 1, Buffer.alloc(0));
+
+            const nextWriter = emptyDB.newReadWriter();
+            await expect(nextWriter.set(existingKey, Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_EMPTY_VALUE');
+            await expect(nextWriter.set(newKey, Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_EMPTY_VALUE');
+            await expect(nextWriter.get(existingKey)).resolves.toEqual(value);
+            await expect(nextWriter.has(newKey)).resolves.toEqual(false);
+            await nextWriter.set(Buffer.from([0, 0, 0, 15, 0, 2]), getRandomBytes());
+            const root2 = await emptyDB.commit(nextWriter, 2, root1);
+            await expect(emptyDB.get(existingKey)).resolves.toEqual(value);
+            await expect(emptyDB.has(newKey)).resolves.toEqual(false);
+
+            const toQueryKey = key => Buffer.concat([key.slice(0, 6), sha256(key.slice(6))]);
+            const inclusionProof = await emptyDB.prove(root2, [toQueryKey(existingKey)]);
+            await expect(emptyDB.verifyInclusionProof(root2, [toQueryKey(existingKey)], inclusionProof)).resolves.toEqual(true);
+            const nonInclusionProof = await emptyDB.prove(root2, [toQueryKey(newKey)]);
+            await expect(emptyDB.verifyNonInclusionProof(root2, [toQueryKey(newKey)], nonInclusionProof)).resolves.toEqual(true);
+
+            await expect(emptyDB.revert(root2, 2)).resolves.toEqual(root1);
+            await expect(emptyDB.get(existingKey)).resolves.toEqual(value);
+            await expect(emptyDB.has(newKey)).resolves.toEqual(false);
+            emptyDB.close();
+        });
+
+        it('should sum the increments of the counter and fold them on get and commit', async () => {
+            const dbPath = path.join(os.tmpdir(), 'state_increment', Date.now().toString());
+            fs.mkdirSync(dbPath, { recursive: true });
+            const counterDB = new StateDB(dbPath);
+            const counter = Buffer.from([0, 0, 0, 16, 0, 0]);
+            const small = Buffer.from([0, 0, 0, 16, 0, 1]);
+            const writer = counterDB.newReadWriter();
+            await writer.set(counter, Buffer.from([0, 0, 0, 0, 0, 0, 0, 10]));
+            const root1 = await counterDB.commit(writer, 1, Buffer.alloc(0));
+
+            const nextWriter = counterDB.newReadWriter();
+            await Promise.all([nextWriter.increment(counter, 5), nextWriter.increment(counter, -3)]);
+            await nextWriter.increment(small, 2, 4);
+            await expect(nextWriter.increment(small, 1, 8)).rejects.toHaveProperty('code', 'ERR_INVALID_USAGE');
+            await expect(nextWriter.increment(small, 0.5, 4)).rejects.toHaveProperty('code', 'ERR_INVALID_ARGUMENT');
+            await expect(nextWriter.get(small)).resolves.toEqual(Buffer.from([0, 0, 0, 2]));
+            await nextWriter.increment(small, 1, 4);
+            const root2 = await counterDB.commit(nextWriter, 2, root1);
+            await expect(counterDB.get(counter)).resolves.toEqual(Buffer.from([0, 0, 0, 0, 0, 0, 0, 12]));
+            await expect(counterDB.get(small)).resolves.toEqual(Buffer.from([0, 0, 0, 3]));
+
+            const underflowWriter = counterDB.newReadWriter();
+            await underflowWriter.increment(small, -4, 4);
+            await expect(counterDB.commit(underflowWriter, 3, root2)).rejects.toMatchObject({
+                code: 'ERR_COUNTER_OVERFLOW',
+                message: expect.stringContaining(small.toString('hex')),
+            });
+            await expect(counterDB.get(small)).resolves.toEqual(Buffer.from([0, 0, 0, 3]));
+            counterDB.close();
         });
 
         it('should return true when called has if key exist', async () => {
@@ -186,10 +281,110 @@ describe('statedb', () => {
             expect(values).toEqual([]);
         });
 
+        it('should iterate all the pairs in order regardless of chunk size', async () => {
+            const collect = async (iterable, options) => new Promise((resolve, reject) => {
+                const result = [];
+                let endCount = 0;
+                iterable.iterate(options)
+                    .on('data', kv => {
+                        result.push(kv);
+                    })
+                    .on('error', err => {
+                        reject(err);
+                    })
+                    .on('end', () => {
+                        endCount += 1;
+                        resolve({ result, endCount });
+                    });
+            });
+            const range = {
+                gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+            };
+
+            const expected = await collect(db, range);
+            expect(expected.result).toEqual(initState.slice(0, 4));
+            for (const chunkSize of [1, 3, 4, 1000]) {
+                await expect(collect(db, { ...range, chunkSize })).resolves.toEqual({ result: expected.result, endCount: 1 });
+                await expect(collect(db.newReader(), { ...range, chunkSize })).resolves.toEqual({ result: expected.result, endCount: 1 });
+                await expect(collect(db, { ...range, chunkSize, limit: 3 })).resolves.toEqual({ result: expected.result.slice(0, 3), endCount: 1 });
+            }
+        });
+
+        it('should iterate only the keys with keysOnly', async () => {
+            const collect = async (iterable, options) => new Promise((resolve, reject) => {
+                const result = [];
+                iterable.iterate(options)
+                    .on('data', kv => {
+                        result.push(kv);
+                    })
+                    .on('error', err => {
+                        reject(err);
+                    })
+                    .on('end', () => {
+                        resolve(result);
+                    });
+            });
+            const range = {
+                gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                keysOnly: true,
+            };
+            const expected = initState.slice(0, 4).map(({ key }) => ({ key }));
+
+            for (const iterable of [db, db.newReader()]) {
+                const result = await collect(iterable, range);
+                expect(result).toEqual(expected);
+                expect(result[0]).not.toHaveProperty('value');
+            }
+        });
+
+        it('should iterate the keys and the value sizes with inventory', async () => {
+            const collect = async (iterable, options) => new Promise((resolve, reject) => {
+                const result = [];
+                iterable.iterate(options)
+                    .on('data', kv => {
+                        result.push(kv);
+                    })
+                    .on('error', err => {
+                        reject(err);
+                    })
+                    .on('end', () => {
+                        resolve(result);
+                    });
+            });
+            const range = {
+                gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                inventory: true,
+            };
+            const expected = initState.slice(0, 4).map(({ key, value }) => ({ key, valueSize: value.length }));
+
+            for (const iterable of [db, db.newReader()]) {
+                const result = await collect(iterable, range);
+                expect(result).toEqual(expected);
+                expect(result[0]).not.toHaveProperty('value');
+                await expect(collect(iterable, { ...range, minValueSize: 32, limit: 2 })).resolves.toEqual(expected.slice(0, 2));
+                await expect(collect(iterable, { ...range, minValueSize: 33 })).resolves.toEqual([]);
+            }
+        });
+
+        it('should throw when chunk size is not positive', async () => {
+            expect(() => db.iterate({ chunkSize: 0 })).toThrow('chunkSize must be a positive integer');
+            expect(() => db.newReader().iterate({ chunkSize: -1 })).toThrow('chunkSize must be a positive integer');
+        });
+
+        it('should throw when chunk size is not an integer', async () => {
+            expect(() => db.iterate({ chunkSize: 0.5 })).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+            const reader = db.newReader();
+            expect(() => reader.iterate({ chunkSize: 1.5 })).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+            reader.close();
+        });
+
         it('should get empty buffer multiple times', async () => {
             const writer = db.newReadWriter();
-            const val = await writer.get(initState[7].key);
-            expect(val).toEqual(Buffer.alloc(0));
+            await expect(writer.get(emptyValueKey)).resolves.toEqual(Buffer.alloc(0));
+            await expect(writer.get(emptyValueKey)).resolves.toEqual(Buffer.alloc(0));
         });
 
         describe('currentState', () => {
@@ -246,6 +441,40 @@ describe('statedb', () => {
                 expect(afterRevert.version).toEqual(0);
                 expect(afterRevert.root).toEqual(root);
             });
+
+            it('should return the commit metadata', async () => {
+                const before = Date.now();
+                const writer = db.newReadWriter();
+                await writer.set(initState[0].key, getRandomBytes());
+                const nextRoot = await db.commit(writer, 1, root);
+                const afterCommit = await db.getCurrentState();
+                expect(afterCommit).toEqual({
+                    root: nextRoot,
+                    version: 1,
+                    height: 1,
+                    commitCount: expect.any(Number),
+                    timestamp: expect.any(Number),
+                });
+                expect(afterCommit.timestamp).toBeGreaterThanOrEqual(before);
+
+                await db.revert(nextRoot, 1);
+                const afterRevert = await db.getCurrentState();
+                expect(afterRevert).toMatchObject({
+                    root,
+                    version: 0,
+                    height: 0,
+                    revertedFrom: nextRoot,
+                });
+                // revert does not decrease the commit count
+                expect(afterRevert.commitCount).toEqual(afterCommit.commitCount);
+
+                const nextWriter = db.newReadWriter();
+                await nextWriter.set(initState[0].key, getRandomBytes());
+                await db.commit(nextWriter, 1, root);
+                const afterRecommit = await db.getCurrentState();
+                expect(afterRecommit.commitCount).toEqual(afterCommit.commitCount + 1);
+                expect(afterRecommit.revertedFrom).toBeUndefined();
+            });
         });
 
         describe('should not have same values for a snapshot and main db after commit or revert', () => {
@@ -283,6 +512,89 @@ describe('statedb', () => {
         });
 
         describe('commit', () => {
+            it('should not show the commits after the creation to the reader', async () => {
+                const key = initState[0].key;
+                const readers = [];
+                let currentRoot = root;
+                for (let height = 1; height <= 20; height += 1) {
+                    const writer = db.newReadWriter();
+                    const value = Buffer.from([height]);
+                    await writer.set(key, value);
+                    const previous = await db.get(key);
+                    // reader created right before the commit must not see the commit
+                    readers.push({ reader: db.newReader(), expected: previous });
+                    currentRoot = await db.commit(writer, height, currentRoot);
+                    readers.push({ reader: db.newReader(), expected: value });
+                }
+
+                for (const { reader, expected } of readers) {
+                    await expect(reader.get(key)).resolves.toEqual(expected);
+                    reader.close();
+                }
+            });
+
+            it('should prove against the snapshot of the reader after the commits', async () => {
+                const key = initState[0].key;
+                const queryKey = sha256(key);
+                const { root: readerRoot } = await db.getCurrentState();
+                const reader = db.newReader();
+                const value = await reader.get(key);
+
+                let currentRoot = readerRoot;
+                const { version } = await db.getCurrentState();
+                for (let height = version + 1; height <= version + 3; height += 1) {
+                    const writer = db.newReadWriter();
+                    await writer.set(key, getRandomBytes());
+                    await writer.set(getRandomBytes(), getRandomBytes());
+                    currentRoot = await db.commit(writer, height, currentRoot);
+                }
+
+                const { root: provenRoot, proof } = await reader.prove([queryKey]);
+                expect(provenRoot).toEqual(readerRoot);
+                expect(proof.queries[0].value).toEqual(sha256(value));
+                await expect(reader.get(key)).resolves.toEqual(value);
+                await expect(db.verify(provenRoot, [queryKey], proof)).resolves.toEqual(true);
+                await expect(db.verify(currentRoot, [queryKey], proof)).resolves.toEqual(false);
+                reader.close();
+                await expect(reader.prove([queryKey])).rejects.toHaveProperty('code', 'ERR_CLOSED');
+            });
+
+            it('should report the progress of the commit', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state', `progress_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                const progressDB = new StateDB(dbPath);
+                const writer = progressDB.newReadWriter();
+                for (let i = 0; i < 10; i += 1) {
+                    await writer.set(getRandomBytes(), getRandomBytes());
+                }
+                const progress = [];
+                const nextRoot = await progressDB.commit(writer, 1, Buffer.alloc(0), {
+                    onProgress: (processed, total) => progress.push([processed, total]),
+                });
+
+                expect(nextRoot).toHaveLength(32);
+                expect(progress).toEqual([[10, 10]]);
+                progressDB.close();
+            });
+
+            it('should not write anything if the commit is cancelled', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state', `cancel_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                const cancelDB = new StateDB(dbPath);
+                const writer = cancelDB.newReadWriter();
+                const key = getRandomBytes();
+                await writer.set(key, getRandomBytes());
+                const cancelled = cancelDB.commit(writer, 1, Buffer.alloc(0));
+                cancelDB.cancelCommit();
+
+                await expect(cancelled).rejects.toHaveProperty('code', 'ERR_CANCELLED');
+                await expect(cancelDB.has(key)).resolves.toEqual(false);
+                // cancellation does not affect the commits started after it
+                await expect(cancelDB.commit(writer, 1, Buffer.alloc(0))).resolves.toHaveLength(32);
+                await expect(cancelDB.has(key)).resolves.toEqual(true);
+                cancelDB.close();
+            });
+
             it('should not update state if readonly is specified', async () => {
                 const writer = db.newReadWriter();
                 await writer.set(initState[0].key, getRandomBytes());
@@ -298,6 +610,25 @@ describe('statedb', () => {
                 await expect(db.commit(writer, 1, root, { readonly: true, checkRoot: true, expectedRoot: getRandomBytes() }))
                     .rejects.toThrow('Invalid state root `Not matching with expected`');
             });
+
+            it('should write the same state and root with maxBatchBytes', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_chunked', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const chunkedDB = new StateDB(dbPath);
+                const writer = chunkedDB.newReadWriter();
+                for (const pair of initState) {
+                    await writer.set(pair.key, pair.value);
+                }
+
+                const chunkedRoot = await chunkedDB.commit(writer, 0, Buffer.alloc(0), { maxBatchBytes: 64 });
+                expect(chunkedRoot).toEqual(root);
+                for (const pair of initState) {
+                    await expect(chunkedDB.get(pair.key)).resolves.toEqual(pair.value);
+                }
+                await expect(chunkedDB.getCurrentState()).resolves.toMatchObject({ root, version: 0 });
+                await expect(chunkedDB.commit(chunkedDB.newReadWriter(), 1, root, { maxBatchBytes: -1 })).rejects.toThrow('maxBatchBytes must be a non-negative integer');
+                chunkedDB.close();
+            });
         });
 
         describe('revert', () => {
@@ -338,6 +669,105 @@ describe('statedb', () => {
                 await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
                 expect(original).toEqual(root);
             });
+
+            it('should revert when the resulting root matches the expected root', async () => {
+                const writer = db.newReadWriter();
+                await writer.set(initState[0].key, getRandomBytes());
+                const nextRoot = await db.commit(writer, 1, root);
+
+                await expect(db.revert(nextRoot, 1, { expectedRoot: root })).resolves.toEqual(root);
+                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
+            });
+
+            it('should reject and keep the state when the resulting root does not match the expected root', async () => {
+                const writer = db.newReadWriter();
+                const newValue = getRandomBytes();
+                await writer.set(initState[0].key, newValue);
+                const nextRoot = await db.commit(writer, 1, root);
+
+                await expect(db.revert(nextRoot, 1, { expectedRoot: getRandomBytes(32) }))
+                    .rejects.toThrow('Invalid state root `Not matching with expected`');
+                await expect(db.get(initState[0].key)).resolves.toEqual(newValue);
+                const currentState = await db.getCurrentState();
+                expect(currentState.root).toEqual(nextRoot);
+                expect(currentState.version).toEqual(1);
+
+                // diff is kept, so that it can be reverted again
+                await expect(db.revert(nextRoot, 1)).resolves.toEqual(root);
+            });
+        });
+
+        describe('deleteRange', () => {
+            const start = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            const end = Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]);
+
+            it('should delete the keys in the range excluding end', async () => {
+                const writer = db.newReadWriter();
+                await writer.deleteRange(start, end);
+
+                await expect(writer.has(initState[0].key)).resolves.toEqual(true);
+                await expect(writer.has(initState[1].key)).resolves.toEqual(false);
+                await expect(writer.has(initState[2].key)).resolves.toEqual(false);
+                await expect(writer.has(initState[3].key)).resolves.toEqual(true);
+                const result = await writer.range({ gte: start, lte: end });
+                expect(result).toEqual([initState[3]]);
+            });
+
+            it('should restore the range deleted state with original values on revert', async () => {
+                const writer = db.newReadWriter();
+                const newKey = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 2]);
+                await writer.set(initState[1].key, getRandomBytes());
+                await writer.set(newKey, getRandomBytes());
+                await writer.deleteRange(start, end);
+
+                const nextRoot = await db.commit(writer, 1, root);
+                await expect(db.has(initState[1].key)).resolves.toEqual(false);
+                await expect(db.has(initState[2].key)).resolves.toEqual(false);
+                await expect(db.has(newKey)).resolves.toEqual(false);
+
+                const original = await db.revert(nextRoot, 1, { expectedRoot: root });
+                expect(original).toEqual(root);
+                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
+                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+                await expect(db.has(newKey)).resolves.toEqual(false);
+            });
+        });
+
+        describe('writer lifecycle', () => {
+            it('should reject the changes to the committed writer until it is reset', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_writer_lifecycle', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const lifecycleDB = new StateDB(dbPath);
+                const keys = [getRandomBytes(), getRandomBytes()];
+                const writer = lifecycleDB.newReadWriter();
+                await writer.set(keys[0], Buffer.from([1]));
+                expect(writer.size()).toEqual({ entries: 1, bytes: keys[0].length + 1 });
+                const root1 = await lifecycleDB.commit(writer, 1, Buffer.alloc(0));
+
+                await expect(writer.set(keys[1], Buffer.from([2]))).rejects.toHaveProperty('code', 'ERR_WRITER_CONSUMED');
+                await expect(writer.del(keys[0])).rejects.toHaveProperty('code', 'ERR_WRITER_CONSUMED');
+                expect(() => writer.snapshot()).toThrow('StateWriter is consumed by the commit');
+                await expect(lifecycleDB.commit(writer, 2, root1)).rejects.toHaveProperty('code', 'ERR_WRITER_CONSUMED');
+
+                writer.reset();
+                expect(writer.size()).toEqual({ entries: 0, bytes: 0 });
+                await writer.set(keys[0], Buffer.from([3]));
+                await writer.set(keys[1], Buffer.from([2]));
+                const root2 = await lifecycleDB.commit(writer, 2, root1);
+                await expect(lifecycleDB.get(keys[0])).resolves.toEqual(Buffer.from([3]));
+                await expect(lifecycleDB.diffStats(2)).resolves.toMatchObject({
+                    created: { count: 1 },
+                    updated: { count: 1, valueBytes: 1 },
+                    deleted: { count: 0 },
+                });
+
+                // revert of the reused writer restores the first commit
+                await expect(lifecycleDB.revert(root2, 2)).resolves.toEqual(root1);
+                await expect(lifecycleDB.get(keys[0])).resolves.toEqual(Buffer.from([1]));
+                await expect(lifecycleDB.has(keys[1])).resolves.toEqual(false);
+                writer.close();
+                lifecycleDB.close();
+            });
         });
 
         describe('finalize', () => {
@@ -362,229 +792,2387 @@ describe('statedb', () => {
             });
         });
 
-        describe('StateReadWriter', () => {
-            it('should return values with range', async () => {
-                const writer = db.newReadWriter();
-                await writer.set(Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 3]), getRandomBytes());
-
-                const result = await writer.range({
-                    gte: Buffer.from('00000002800000000000', 'hex'),
-                    lte: Buffer.from('000000028000ffffffff', 'hex'),
-                });
-
-                expect(result).toHaveLength(0);
+        describe('checkDiffs', () => {
+            it('should report the corrupted diff and reject the revert with the height', async () => {
+                const height = Buffer.from([0, 0, 0, 98]);
+                const before = await db.checkDiffs();
+                expect(before.corrupted).toEqual([]);
+
+                const batch = new Batch();
+                batch.set(height, Buffer.from([0x12, 0x03, 0x0a, 0x09, 0x01]), 'diff');
+                await db.write(batch);
+                batch.close();
+
+                const report = await db.checkDiffs();
+                expect(report.checked).toEqual(before.checked + 1);
+                expect(report.corrupted).toHaveLength(1);
+                expect(report.corrupted[0].height).toEqual(98);
+                expect(report.corrupted[0].error).toContain('12030a0901');
+                await expect(db.revert(getRandomBytes(), 98)).rejects.toThrow('diff at height 98 is corrupted');
+
+                const deleteBatch = new Batch();
+                deleteBatch.del(height, 'diff');
+                await db.write(deleteBatch);
+                deleteBatch.close();
+                await expect(db.checkDiffs()).resolves.toEqual(before);
             });
+        });
 
-            it('should return updated value with range', async () => {
-                const writer = db.newReadWriter();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
-
-                const result = await writer.range({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                });
-
-                expect(result).toHaveLength(2);
-                expect(result[0]).toEqual({
-                    key: initState[1].key,
-                    value: newValue,
-                });
-                expect(result[1]).toEqual(initState[2]);
+        describe('diffValueRefThreshold', () => {
+            it('should store the large old values once and revert them', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_diff_value_ref', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new StateDB(dbPath, { diffValueRefThreshold: 0 })).toThrow('diffValueRefThreshold must be a positive integer');
+                const refDB = new StateDB(dbPath, { diffValueRefThreshold: 64 });
+                const keys = [getRandomBytes(), getRandomBytes()];
+                const large = crypto.randomBytes(100);
+                const small = crypto.randomBytes(10);
+
+                const writer = refDB.newReadWriter();
+                await writer.set(keys[0], large);
+                await writer.set(keys[1], small);
+                const root1 = await refDB.commit(writer, 1, Buffer.alloc(0));
+                const nextWriter = refDB.newReadWriter();
+                await nextWriter.set(keys[0], getRandomBytes());
+                await nextWriter.del(keys[1]);
+                const root2 = await refDB.commit(nextWriter, 2, root1);
+
+                const stats = await refDB.diffStats(2);
+                expect(stats.height).toEqual(2);
+                expect(stats.updated).toEqual({ count: 1, keyBytes: keys[0].length, valueBytes: 100, referenced: 1 });
+                expect(stats.deleted).toEqual({ count: 1, keyBytes: keys[1].length, valueBytes: 10, referenced: 0 });
+                // the large value is stored as the hash
+                expect(stats.encodedBytes).toBeLessThan(keys[0].length + keys[1].length + 100);
+                await expect(refDB.diffStats(3)).rejects.toHaveProperty('code', 'ERR_NOT_RETAINED');
+                await expect(refDB.checkDiffs()).resolves.toMatchObject({ corrupted: [] });
+
+                await expect(refDB.revert(root2, 2)).resolves.toEqual(root1);
+                await expect(refDB.get(keys[0])).resolves.toEqual(large);
+                await expect(refDB.get(keys[1])).resolves.toEqual(small);
+                refDB.close();
             });
+        });
 
-            it('should return updated value with range in reverse', async () => {
-                const writer = db.newReadWriter();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
+        describe('smtThreads', () => {
+            it('should commit the same root with the threads', async () => {
+                const basePath = path.join(os.tmpdir(), 'state_smt_threads', Date.now().toString());
+                fs.mkdirSync(basePath, { recursive: true });
+                expect(() => new StateDB(basePath, { smtThreads: 0 })).toThrow('smtThreads must be a positive integer');
+                const pairs = Array.from({ length: 2000 }, () => [getRandomBytes(), getRandomBytes()]);
+                const roots = [];
+                for (const smtThreads of [1, 4]) {
+                    const dbPath = path.join(basePath, smtThreads.toString());
+                    fs.mkdirSync(dbPath, { recursive: true });
+                    const threadsDB = new StateDB(dbPath, { smtThreads });
+                    const writer = threadsDB.newReadWriter();
+                    for (const [key, value] of pairs) {
+                        await writer.set(key, value);
+                    }
+                    const root1 = await threadsDB.commit(writer, 1, Buffer.alloc(0));
+                    const nextWriter = threadsDB.newReadWriter();
+                    for (const [key] of pairs.slice(0, 500)) {
+                        await nextWriter.del(key);
+                    }
+                    roots.push([root1, await threadsDB.commit(nextWriter, 2, root1)]);
+                    threadsDB.close();
+                }
+                expect(roots[1]).toEqual(roots[0]);
+            });
+        });
 
-                const result = await writer.range({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                    reverse: true,
+        describe('checkConsistency', () => {
+            it('should report the missing diff of the latest height', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_consistency', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const consistencyDB = new StateDB(dbPath);
+                let consistencyRoot = (await consistencyDB.getCurrentState()).root;
+                for (let height = 1; height <= 3; height += 1) {
+                    const writer = consistencyDB.newReadWriter();
+                    await writer.set(getRandomBytes(), getRandomBytes());
+                    consistencyRoot = await consistencyDB.commit(writer, height, consistencyRoot);
+                }
+                await expect(consistencyDB.checkConsistency()).resolves.toEqual({
+                    consistent: true,
+                    height: 3,
+                    root: consistencyRoot,
+                    rootInTree: true,
+                    diffExists: true,
+                    recordedRoot: consistencyRoot,
+                    pendingCommit: false,
+                    issues: [],
                 });
 
-                expect(result).toHaveLength(2);
-                expect(result[0].value).toEqual(initState[3].value);
-                expect(result[1].value).toEqual(initState[2].value);
+                const batch = new Batch();
+                batch.del(Buffer.from([0, 0, 0, 3]), 'diff');
+                await consistencyDB.write(batch);
+                batch.close();
+                const report = await consistencyDB.checkConsistency();
+                expect(report.consistent).toBe(false);
+                expect(report.diffExists).toBe(false);
+                expect(report.issues).toEqual(['diff of height 3 is missing']);
+                consistencyDB.close();
             });
+        });
 
-            it('should return to original value after restoreSnapshot', async () => {
-                const writer = db.newReadWriter();
-                const index = writer.snapshot();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
-                writer.restoreSnapshot(index);
-
-                const result = await writer.range({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                });
+        describe('iteration with prefix', () => {
+            const keys = [
+                Buffer.from([3, 254, 255, 0]),
+                Buffer.from([3, 255, 255, 0]),
+                Buffer.from([3, 255, 255, 255]),
+                Buffer.from([4, 0, 0, 0]),
+                Buffer.from([255, 255, 0, 0]),
+                Buffer.from([255, 255, 255, 255]),
+            ];
+            const collect = async stream => new Promise((resolve, reject) => {
+                const result = [];
+                stream
+                    .on('data', ({ key }) => result.push(key))
+                    .on('error', reject)
+                    .on('end', () => resolve(result));
+            });
+            let prefixDB;
 
-                expect(result).toHaveLength(2);
-                expect(result[0].value).toEqual(initState[1].value);
-                expect(result[1].value).toEqual(initState[2].value);
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_prefix', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                prefixDB = new StateDB(dbPath);
+                const writer = prefixDB.newReadWriter();
+                for (const key of keys) {
+                    await writer.set(key, getRandomBytes());
+                }
+                await prefixDB.commit(writer, 1, Buffer.alloc(0));
             });
 
-            it('should return to original value after restoreSnapshot with multiple snapshot', async () => {
-                const writer = db.newReadWriter();
-                const index = writer.snapshot();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
-                writer.snapshot()
-                await writer.set(initState[1].key, getRandomBytes());
-                writer.restoreSnapshot(index);
+            afterAll(() => {
+                prefixDB.close();
+            });
 
-                const result = await writer.range({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                });
+            it('should iterate the keys with the prefix ending with 0xff', async () => {
+                const prefix = Buffer.from([3, 255]);
+                await expect(collect(prefixDB.iterate({ prefix }))).resolves.toEqual(keys.slice(1, 3));
+                await expect(collect(prefixDB.iterate({ prefix, reverse: true }))).resolves.toEqual(keys.slice(1, 3).reverse());
+                const reader = prefixDB.newReader();
+                await expect(collect(reader.iterate({ prefix, reverse: true }))).resolves.toEqual(keys.slice(1, 3).reverse());
+                await expect(reader.countRange({ prefix: Buffer.from([3]) })).resolves.toEqual(3);
+                reader.close();
+            });
 
-                expect(result).toHaveLength(2);
-                expect(result[0].value).toEqual(initState[1].value);
-                expect(result[1].value).toEqual(initState[2].value);
+            it('should iterate to the end of the state with the prefix of all 0xff', async () => {
+                const prefix = Buffer.from([255, 255]);
+                await expect(collect(prefixDB.iterate({ prefix }))).resolves.toEqual(keys.slice(4));
+                await expect(collect(prefixDB.iterate({ prefix, reverse: true }))).resolves.toEqual(keys.slice(4).reverse());
             });
 
-            it('should throw error with non existing snapshot', async () => {
-                const writer = db.newReadWriter();
-                writer.snapshot();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
-                writer.snapshot()
-                await writer.set(initState[1].key, getRandomBytes());
-                expect(() => writer.restoreSnapshot(99)).toThrow('Invalid usage');
+            it('should merge the range with the prefix in the read writer', async () => {
+                const writer = prefixDB.newReadWriter();
+                await writer.set(Buffer.from([3, 255, 0, 0]), getRandomBytes());
+                await writer.del(keys[2]);
+                const range = await writer.range({ prefix: Buffer.from([3, 255]), reverse: true });
+                expect(range.map(({ key }) => key)).toEqual([keys[1], Buffer.from([3, 255, 0, 0])]);
+                await expect(writer.countRange({ prefix: Buffer.from([255, 255]) })).resolves.toEqual(2);
             });
 
-            it('should throw an error when the writer is closed', async () => {
-                const writer = db.newReadWriter();
-                const newValue = getRandomBytes();
-                await writer.set(initState[1].key, newValue);
-                await expect(writer.get(initState[1].key)).resolves.toEqual(newValue);
-                writer.close();
-                expect(() => writer.get(initState[1].key)).rejects.toThrow();
+            it('should reject the prefix with gte or lte', async () => {
+                const writer = prefixDB.newReadWriter();
+                await expect(writer.range({ prefix: Buffer.from([3]), gte: Buffer.from([3, 0]) })).rejects.toThrow(
+                    'prefix cannot be used with gte or lte',
+                );
             });
         });
 
-        describe('StateReader', () => {
-            const nonExistingKey = Buffer.from([255, 255]);
+        describe('keepDiffForHeights', () => {
+            let windowDB;
 
-            it('should not have set', () => {
-                const reader = db.newReader();
-                expect(reader.set).toBeUndefined();
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_keep_diff', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                windowDB = new StateDB(dbPath, { keepDiffForHeights: 2 });
             });
 
-            it('should not have del', () => {
-                const reader = db.newReader();
-                expect(reader.del).toBeUndefined();
+            afterAll(() => {
+                windowDB.close();
             });
 
-            it('should fail if DB does not have key', async () => {
+            it('should remove the diff out of the window on commit', async () => {
+                const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+                let windowRoot = Buffer.alloc(0);
+                for (let height = 1; height <= 5; height += 1) {
+                    const writer = windowDB.newReadWriter();
+                    await writer.set(key, Buffer.from([height]));
+                    windowRoot = await windowDB.commit(writer, height, windowRoot);
+                }
+
+                for (let height = 5; height >= 3; height -= 1) {
+                    windowRoot = await windowDB.revert(windowRoot, height);
+                }
+                await expect(windowDB.get(key)).resolves.toEqual(Buffer.from([2]));
+                await expect(windowDB.revert(windowRoot, 2)).rejects.toThrow('diff not available for height 2');
+                await expect(windowDB.revert(windowRoot, 2)).rejects.toHaveProperty('code', 'ERR_NOT_RETAINED');
+                // nothing is reverted
+                await expect(windowDB.get(key)).resolves.toEqual(Buffer.from([2]));
+                await expect(windowDB.getCurrentState()).resolves.toMatchObject({ root: windowRoot, version: 2 });
+            });
+
+            it('should throw when the option is invalid', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_keep_diff_invalid', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new StateDB(dbPath, { keepDiffForHeights: -1 })).toThrow('keepDiffForHeights must be a non-negative integer');
+                expect(() => new StateDB(dbPath, { keepDiffForHeights: 1.5 })).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+            });
+        });
+
+        describe('reader pool', () => {
+            let poolDB;
+            const keys = [];
+
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_reader_pool', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                poolDB = new StateDB(dbPath, { readerPoolSize: 2 });
+                const writer = poolDB.newReadWriter();
+                for (let i = 0; i < 10; i += 1) {
+                    const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, i]);
+                    keys.push(key);
+                    await writer.set(key, Buffer.from([i]));
+                }
+                await poolDB.commit(writer, 1, Buffer.alloc(0));
+            });
+
+            afterAll(() => {
+                poolDB.close();
+            });
+
+            const iterateCount = async reader => new Promise((resolve, reject) => {
+                let count = 0;
+                reader.iterate({ gte: keys[0], lte: keys[9], chunkSize: 3 })
+                    .on('data', () => {
+                        count += 1;
+                    })
+                    .on('error', reject)
+                    .on('end', () => resolve(count));
+            });
+
+            it('should run the operations of a reader in the submission order', async () => {
+                const reader = poolDB.newReader();
+                const others = [poolDB.newReader(), poolDB.newReader()];
+                const order = [];
+                const operations = [];
+                for (let i = 0; i < 200; i += 1) {
+                    for (const other of others) {
+                        operations.push(other.get(keys[i % 10]));
+                    }
+                    if (i % 2 === 0) {
+                        operations.push(reader.get(keys[i % 10]).then(value => {
+                            expect(value).toEqual(Buffer.from([i % 10]));
+                            order.push(i);
+                        }));
+                    } else {
+                        operations.push(iterateCount(reader).then(count => {
+                            expect(count).toEqual(10);
+                            order.push(i);
+                        }));
+                    }
+                }
+                await Promise.all(operations);
+
+                expect(order).toEqual([...Array(200).keys()]);
+                for (const each of [reader, ...others]) {
+                    each.close();
+                }
+            });
+
+            it('should reject the operations after close', async () => {
+                const reader = poolDB.newReader();
+                reader.close();
+                await expect(reader.get(keys[0])).rejects.toHaveProperty('code', 'ERR_CLOSED');
+            });
+        });
+
+        describe('root history', () => {
+            let historyDB;
+
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_root_history', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                historyDB = new StateDB(dbPath, { keepDiffForHeights: 3 });
+            });
+
+            afterAll(() => {
+                historyDB.close();
+            });
+
+            it('should record the root of each height and remove it with the diffs', async () => {
+                const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+                const roots = [Buffer.alloc(0)];
+                await expect(historyDB.getRootAt(1)).rejects.toThrow(NotFoundError);
+                for (let height = 1; height <= 5; height += 1) {
+                    const writer = historyDB.newReadWriter();
+                    await writer.set(key, Buffer.from([height]));
+                    roots.push(await historyDB.commit(writer, height, roots[height - 1]));
+                }
+
+                await expect(historyDB.getRootAt(4)).resolves.toEqual(roots[4]);
+                await expect(historyDB.getRootAt(1)).rejects.toHaveProperty('code', 'ERR_NOT_RETAINED');
+                await expect(historyDB.getRootAt(6)).rejects.toHaveProperty('code', 'ERR_NOT_FOUND');
+                await expect(historyDB.getLatestHeights(2)).resolves.toEqual([
+                    { height: 5, root: roots[5] },
+                    { height: 4, root: roots[4] },
+                ]);
+
+                await historyDB.finalize(4);
+                await expect(historyDB.getRootAt(3)).rejects.toHaveProperty('code', 'ERR_NOT_RETAINED');
+                await expect(historyDB.getLatestHeights(10)).resolves.toHaveLength(2);
+            });
+
+            it('should reject the revert from the root which is not recorded at the height', async () => {
+                const { root, version } = await historyDB.getCurrentState();
+                const previous = await historyDB.getRootAt(version - 1);
+                await expect(historyDB.revert(previous, version)).rejects.toHaveProperty('code', 'ERR_INVALID_ROOT');
+
+                await expect(historyDB.revert(root, version)).resolves.toEqual(previous);
+                await expect(historyDB.getRootAt(version)).rejects.toHaveProperty('code', 'ERR_NOT_FOUND');
+            });
+        });
+
+        describe('autoMigrate', () => {
+            const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+            // createOldLayout commits 3 heights and removes the format version and the root history to get the layout of the older format
+            const createOldLayout = async dbPath => {
+                fs.mkdirSync(dbPath, { recursive: true });
+                const oldDB = new StateDB(dbPath);
+                const roots = [Buffer.alloc(0)];
+                for (let height = 1; height <= 3; height += 1) {
+                    const writer = oldDB.newReadWriter();
+                    await writer.set(key, Buffer.from([height]));
+                    roots.push(await oldDB.commit(writer, height, roots[height - 1]));
+                }
+                const batch = new Batch();
+                batch.del(Buffer.from([11]), 'raw');
+                for (let height = 1; height <= 3; height += 1) {
+                    const heightBytes = Buffer.alloc(4);
+                    heightBytes.writeUInt32BE(height);
+                    batch.del(Buffer.concat([Buffer.from([7]), heightBytes]), 'raw');
+                }
+                await oldDB.write(batch);
+                batch.close();
+                await oldDB.close();
+                return roots;
+            };
+
+            it('should migrate the database written without the format version only with autoMigrate', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_auto_migrate', Date.now().toString());
+                const roots = await createOldLayout(dbPath);
+
+                expect(() => new StateDB(dbPath)).toThrow('database needs migration: column_families (0 -> 1), root_history (1 -> 2)');
+                const migratedDB = new StateDB(dbPath, { autoMigrate: true });
+                for (let height = 1; height <= 3; height += 1) {
+                    await expect(migratedDB.getRootAt(height)).resolves.toEqual(roots[height]);
+                }
+                await expect(migratedDB.get(key)).resolves.toEqual(Buffer.from([3]));
+                migratedDB.close();
+
+                const reopenedDB = new StateDB(dbPath);
+                await expect(reopenedDB.revert(roots[3], 3)).resolves.toEqual(roots[2]);
+                reopenedDB.close();
+            });
+
+            it('should migrate the database by StateDB.migrate with the progress', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_migrate', Date.now().toString());
+                const roots = await createOldLayout(dbPath);
+
+                const progress = [];
+                await StateDB.migrate(dbPath, {}, { onProgress: (step, processed) => progress.push([step, processed]) });
+                expect(progress).toContainEqual(['root_history', 3]);
+
+                const migratedDB = new StateDB(dbPath);
+                for (let height = 1; height <= 3; height += 1) {
+                    await expect(migratedDB.getRootAt(height)).resolves.toEqual(roots[height]);
+                }
+                await expect(migratedDB.get(key)).resolves.toEqual(Buffer.from([3]));
+                // the open database is locked
+                await expect(StateDB.migrate(dbPath)).rejects.toHaveProperty('code', 'ERR_DB_LOCKED');
+                await migratedDB.close();
+            });
+        });
+
+        describe('stateKeyLength', () => {
+            let keyDB;
+            const key = Buffer.from([0, 0, 0, 1, 0, 0, 1, 2, 3, 4]);
+            const shortKey = key.slice(0, 9);
+            const longKey = Buffer.concat([key, Buffer.from([5])]);
+
+            beforeAll(() => {
+                const dbPath = path.join(os.tmpdir(), 'state_key_length', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                keyDB = new StateDB(dbPath, { stateKeyLength: 4 });
+            });
+
+            afterAll(() => {
+                keyDB.close();
+            });
+
+            it('should accept the keys with the prefix and the key length', async () => {
+                const writer = keyDB.newReadWriter();
+                await writer.set(key, Buffer.from([1]));
+                await expect(writer.get(key)).resolves.toEqual(Buffer.from([1]));
+                const root = await keyDB.commit(writer, 1, Buffer.alloc(0));
+                writer.close();
+
+                await expect(keyDB.get(key)).resolves.toEqual(Buffer.from([1]));
+                await expect(keyDB.has(key)).resolves.toEqual(true);
+                const proof = await keyDB.prove(root, [key], { includeValues: true });
+                expect(proof.queries[0].rawValue).toEqual(Buffer.from([1]));
+            });
+
+            it('should reject the off-by-one keys on every entry point', async () => {
+                for (const [invalidKey, got] of [
+                    [shortKey, 9],
+                    [longKey, 11],
+                ]) {
+                    const message = `Invalid key length: expected 10, got ${got}`;
+                    await expect(keyDB.get(invalidKey)).rejects.toThrow(message);
+                    await expect(keyDB.has(invalidKey)).rejects.toThrow(message);
+                    const { root } = await keyDB.getCurrentState();
+                    await expect(keyDB.prove(root, [key, invalidKey], { includeValues: true })).rejects.toThrow(message);
+
+                    const writer = keyDB.newReadWriter();
+                    await expect(writer.set(invalidKey, Buffer.from([1]))).rejects.toThrow(message);
+                    await expect(writer.get(invalidKey)).rejects.toThrow(message);
+                    await expect(writer.del(invalidKey)).rejects.toThrow(message);
+                    writer.close();
+
+                    const reader = keyDB.newReader();
+                    await expect(reader.get(invalidKey)).rejects.toThrow(message);
+                    await expect(reader.has(invalidKey)).rejects.toThrow(message);
+                    reader.close();
+                }
+            });
+
+            it('should count the configured prefix length', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_key_length_prefix', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const prefixDB = new StateDB(dbPath, { statePrefixLength: 8, stateKeyLength: 2 });
+                await expect(prefixDB.has(key)).resolves.toEqual(false);
+                await expect(prefixDB.has(longKey)).rejects.toThrow('Invalid key length: expected 10, got 11');
+                prefixDB.close();
+            });
+
+            it('should throw when the options are invalid', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_key_length_invalid', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new StateDB(dbPath, { stateKeyLength: 0 })).toThrow('stateKeyLength must be a positive integer');
+                expect(() => new StateDB(dbPath, { statePrefixLength: 5, stateKeyLength: 4 })).toThrow('statePrefixLength must be at least 6');
+            });
+        });
+
+        describe('size limits', () => {
+            it('should reject the read writer and the batch over the limits before writing', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_size_limits', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const limitedDB = new StateDB(dbPath, { maxValueSize: 4 });
+                const key = Buffer.from([0, 0, 0, 1, 0, 0, 1]);
+
+                const writer = limitedDB.newReadWriter();
+                await expect(writer.set(key, Buffer.alloc(5))).rejects.toThrow('value is too large: maximum 4 bytes, got 5');
+                await expect(writer.set(Buffer.alloc(0), Buffer.alloc(1))).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+                await expect(writer.del(Buffer.alloc(0))).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+                await expect(writer.has(key)).resolves.toBe(false);
+                writer.close();
+
+                const batch = new Batch();
+                batch.set(key, Buffer.alloc(4), 'state');
+                batch.set(Buffer.from([0, 0, 0, 1, 0, 0, 2]), Buffer.alloc(5), 'state');
+                await expect(limitedDB.write(batch)).rejects.toHaveProperty('code', 'ERR_SIZE_LIMIT');
+                await expect(limitedDB.has(key)).resolves.toBe(false);
+                limitedDB.close();
+            });
+        });
+
+        describe('hasher', () => {
+            it('should open with the default hasher and reject the unknown hasher', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_hasher', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new StateDB(dbPath, { hasher: 'keccak' })).toThrow('hasher must be one of the enabled hashers, got keccak');
+
+                const hasherDB = new StateDB(dbPath, { hasher: 'sha256' });
+                await expect(hasherDB.getCurrentState()).resolves.toMatchObject({ version: 0 });
+                hasherDB.close();
+            });
+
+            it('should reject the hasher other than the one recorded by the commit', async () => {
+                // blake3 is only enabled with the blake3 feature of the native module
+                try {
+                    new SparseMerkleTree(32, { hasher: 'blake3' });
+                } catch (err) {
+                    return;
+                }
+                const dbPath = path.join(os.tmpdir(), 'state_hasher_mismatch', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const blake3DB = new StateDB(dbPath, { hasher: 'blake3' });
+                const writer = blake3DB.newReadWriter();
+                await writer.set(Buffer.from([0, 0, 0, 15, 0, 0]), getRandomBytes());
+                const root = await blake3DB.commit(writer, 1, Buffer.alloc(0));
+                const queryKeys = [Buffer.concat([Buffer.from([0, 0, 0, 15, 0, 0]), getRandomBytes(32)])];
+                const proof = await blake3DB.prove(root, queryKeys);
+                await expect(blake3DB.verify(root, queryKeys, proof)).resolves.toBe(true);
+                blake3DB.close();
+
+                expect(() => new StateDB(dbPath)).toThrow('hasher sha256 does not match the hasher blake3 of the state');
+                expect(() => new StateDB(dbPath, { hasher: 'sha256' })).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+                const reopened = new StateDB(dbPath, { hasher: 'blake3' });
+                await expect(reopened.getCurrentState()).resolves.toMatchObject({ root, version: 1 });
+                reopened.close();
+            });
+        });
+
+        describe('operationTimeoutMs', () => {
+            // the native get is called directly to count the calls of the callback, which the promise hides
+            const { state_db_get } = require('../bin-package/index.node');
+
+            it('should throw when operationTimeoutMs is invalid', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_timeout_invalid', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                expect(() => new StateDB(dbPath, { operationTimeoutMs: 0 })).toThrow('operationTimeoutMs must be a positive integer');
+            });
+
+            it('should reject the reads queued behind the commit with ERR_TIMEOUT and call each callback once', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_timeout', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const timedDB = new StateDB(dbPath, { operationTimeoutMs: 1 });
+                const key = getRandomBytes();
+                const writer = timedDB.newReadWriter();
+                await writer.set(key, key);
+                for (let i = 0; i < 20000; i += 1) {
+                    await writer.set(getRandomBytes(), getRandomBytes());
+                }
+
+                // commit is exempt from the timeout, and keeps the database thread busy for the reads behind it
+                const committed = timedDB.commit(writer, 1, Buffer.alloc(0));
+                const calls = Array.from({ length: 100 }, () => []);
+                await Promise.all(
+                    calls.map(
+                        codes =>
+                            new Promise(resolve => {
+                                state_db_get.call(timedDB._db, key, err => {
+                                    codes.push(err.code);
+                                    resolve();
+                                });
+                            }),
+                    ),
+                );
+                await expect(committed).resolves.toHaveLength(32);
+                // the reads timed out start after the commit, and must not call the callbacks again.
+                // getCurrentState is not timed, and it is queued on the DB thread behind them,
+                // so that their callbacks would be called before it resolves.
+                await timedDB.getCurrentState();
+
+                for (const codes of calls) {
+                    expect(codes).toHaveLength(1);
+                    expect(['ERR_TIMEOUT', 'ERR_NOT_FOUND']).toContain(codes[0]);
+                }
+                expect(calls.some(codes => codes[0] === 'ERR_TIMEOUT')).toBe(true);
+                await expect(timedDB.get(key)).resolves.toEqual(key);
+                timedDB.close();
+            });
+
+            it('should not apply the writes timed out behind the commit', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_timeout_write', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const timedDB = new StateDB(dbPath, { operationTimeoutMs: 1 });
+                const writer = timedDB.newReadWriter();
+                for (let i = 0; i < 20000; i += 1) {
+                    await writer.set(getRandomBytes(), getRandomBytes());
+                }
+
+                const committed = timedDB.commit(writer, 1, Buffer.alloc(0));
+                const keys = Array.from({ length: 20 }, () => getRandomBytes());
+                const results = await Promise.allSettled(
+                    keys.map(key => {
+                        const batch = new Batch();
+                        batch.set(key, key, 'state');
+                        return timedDB.write(batch);
+                    }),
+                );
+                await committed;
+
+                // the write rejected with ERR_TIMEOUT is skipped, so that it is safe to retry
+                for (const [i, result] of results.entries()) {
+                    if (result.status === 'rejected') {
+                        expect(result.reason.code).toEqual('ERR_TIMEOUT');
+                        await expect(timedDB.has(keys[i])).resolves.toBe(false);
+                    } else {
+                        await expect(timedDB.has(keys[i])).resolves.toBe(true);
+                    }
+                }
+                expect(results.some(result => result.status === 'rejected')).toBe(true);
+                timedDB.close();
+            });
+        });
+
+        describe('prove with height', () => {
+            let historyDB;
+            const roots = [];
+            const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            const queryKey = Buffer.concat([key.slice(0, 6), sha256(key.slice(6))]);
+
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_prove_height', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                historyDB = new StateDB(dbPath, { keepDiffForHeights: 2 });
+                let historyRoot = Buffer.alloc(0);
+                for (let height = 1; height <= 5; height += 1) {
+                    const writer = historyDB.newReadWriter();
+                    await writer.set(key, Buffer.from([height]));
+                    historyRoot = await historyDB.commit(writer, height, historyRoot);
+                    roots[height] = historyRoot;
+                }
+            });
+
+            afterAll(() => {
+                historyDB.close();
+            });
+
+            it('should prove the key at the past height against the root of the height', async () => {
+                const proof = await historyDB.prove(roots[3], [queryKey], { height: 3 });
+
+                await expect(historyDB.verifyInclusionProof(roots[3], [queryKey], proof)).resolves.toEqual(true);
+                expect(proof.queries[0].value).toEqual(sha256(Buffer.from([3])));
+                await expect(historyDB.verify(roots[5], [queryKey], proof)).resolves.toEqual(false);
+                // the current state is not changed
+                await expect(historyDB.get(key)).resolves.toEqual(Buffer.from([5]));
+                await expect(historyDB.getCurrentState()).resolves.toMatchObject({ root: roots[5], version: 5 });
+            });
+
+            it('should attach the value of the past height', async () => {
+                const proof = await historyDB.prove(roots[4], [key], { height: 4, includeValues: true });
+
+                expect(proof.queries[0].rawValue).toEqual(Buffer.from([4]));
+                await expect(historyDB.verify(roots[4], [queryKey], proof)).resolves.toEqual(true);
+            });
+
+            it('should reject when the root is not the root of the height', async () => {
+                await expect(historyDB.prove(roots[5], [queryKey], { height: 3 })).rejects.toThrow('Not matching with the state root of the height');
+            });
+
+            it('should reject when the height is out of the retained diffs', async () => {
+                await expect(historyDB.prove(roots[1], [queryKey], { height: 1 })).rejects.toThrow('state at height 1 is not available with the retained diffs');
+                await expect(historyDB.prove(roots[5], [queryKey], { height: 6 })).rejects.toThrow('state at height 6 is not available with the retained diffs');
+            });
+
+            it('should prove the past height on the state before the commit queued after it', async () => {
+                const writer = historyDB.newReadWriter();
+                await writer.set(key, Buffer.from([6]));
+                const proving = historyDB.prove(roots[4], [queryKey], { height: 4 });
+                roots[6] = await historyDB.commit(writer, 6, roots[5]);
+
+                const proof = await proving;
+                await expect(historyDB.verifyInclusionProof(roots[4], [queryKey], proof)).resolves.toEqual(true);
+                expect(proof.queries[0].value).toEqual(sha256(Buffer.from([4])));
+            });
+        });
+
+        describe('readonly', () => {
+            let writableDB;
+            let readonlyDB;
+            let committedRoot;
+            const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+            beforeAll(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_readonly', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                writableDB = new StateDB(dbPath);
+                const writer = writableDB.newReadWriter();
+                await writer.set(key, Buffer.from([1, 2, 3]));
+                committedRoot = await writableDB.commit(writer, 1, Buffer.alloc(0));
+                readonlyDB = new StateDB(dbPath, { readonly: true });
+            });
+
+            afterAll(() => {
+                readonlyDB.close();
+                writableDB.close();
+            });
+
+            it('should allow reading and proving', async () => {
+                await expect(readonlyDB.get(key)).resolves.toEqual(Buffer.from([1, 2, 3]));
+                const reader = readonlyDB.newReader();
+                await expect(reader.get(key)).resolves.toEqual(Buffer.from([1, 2, 3]));
+                reader.close();
+
+                const proof = await readonlyDB.prove(committedRoot, [key], { includeValues: true });
+                expect(proof.queries[0].rawValue).toEqual(Buffer.from([1, 2, 3]));
+                await expect(readonlyDB.verify(committedRoot, [key], proof)).resolves.toEqual(true);
+            });
+
+            it('should iterate the stored data of the kind', async () => {
+                const readStream = (kind, options) => new Promise((resolve, reject) => {
+                    const items = [];
+                    readonlyDB.iterateKind(kind, options)
+                        .on('data', item => items.push(item))
+                        .on('error', reject)
+                        .on('end', () => resolve(items));
+                });
+                const states = await readStream('state');
+                expect(states).toEqual([{ key, value: Buffer.from([1, 2, 3]) }]);
+                const diffs = await readStream('diff');
+                expect(diffs).toHaveLength(1);
+                // the key is the height
+                expect(diffs[0].key).toEqual(Buffer.from([0, 0, 0, 1]));
+                await expect(readStream('diff', { decode: true })).resolves.toEqual([
+                    { height: 1, created: [key], updated: [], deleted: [] },
+                ]);
+                const nodes = await readStream('smt', { keysOnly: true });
+                expect(nodes.map(node => node.key)).toContainEqual(committedRoot);
+
+                expect(() => readonlyDB.iterateKind('journal')).toThrow('kind must be one of state, diff and smt');
+                expect(() => readonlyDB.iterateKind('smt', { decode: true })).toThrow('decode is only supported for the diff kind');
+            });
+
+            it('should reject mutating operations', async () => {
+                const writer = readonlyDB.newReadWriter();
+                await writer.set(key, Buffer.from([4]));
+                await expect(readonlyDB.commit(writer, 2, committedRoot)).rejects.toThrow('database is readonly');
+                await expect(readonlyDB.revert(committedRoot, 1)).rejects.toThrow('database is readonly');
+                await expect(readonlyDB.finalize(1)).rejects.toThrow('database is readonly');
+                const checkpointPath = path.join(os.tmpdir(), 'state_readonly_checkpoint', Date.now().toString());
+                await expect(readonlyDB.checkpoint(checkpointPath)).rejects.toThrow('database is readonly');
+                await expect(readonlyDB.compact()).rejects.toThrow('database is readonly');
+                await expect(readonlyDB.setBulkLoad(true)).rejects.toThrow('database is readonly');
+            });
+        });
+
+        describe('locked database', () => {
+            it('should throw ERR_DB_LOCKED, or open as the readonly secondary instance without exclusive', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_locked', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const primary = new StateDB(dbPath);
+                const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+                const writer = primary.newReadWriter();
+                await writer.set(key, Buffer.from([1, 2, 3]));
+                const root = await primary.commit(writer, 1, Buffer.alloc(0));
+                await primary.flush();
+
+                expect(() => new StateDB(dbPath)).toThrow(dbPath);
+                try {
+                    new StateDB(dbPath);
+                } catch (err) {
+                    expect(err.code).toEqual('ERR_DB_LOCKED');
+                }
+
+                const secondary = new StateDB(dbPath, { exclusive: false });
+                await expect(secondary.get(key)).resolves.toEqual(Buffer.from([1, 2, 3]));
+                const secondaryWriter = secondary.newReadWriter();
+                await secondaryWriter.set(key, Buffer.from([4]));
+                await expect(secondary.commit(secondaryWriter, 2, root)).rejects.toThrow('database is readonly');
+                secondary.close();
+                primary.close();
+            });
+        });
+
+        describe('computeRoot', () => {
+            it('should return the root of the commit without writing', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_compute_root', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const computeDB = new StateDB(dbPath);
+                const initWriter = computeDB.newReadWriter();
+                for (const pair of initState) {
+                    await initWriter.set(pair.key, pair.value);
+                }
+                const prevRoot = await computeDB.commit(initWriter, 1, Buffer.alloc(0));
+
+                const writer = computeDB.newReadWriter();
+                await writer.set(initState[0].key, getRandomBytes());
+                await writer.del(initState[1].key);
+                await writer.get(initState[2].key);
+                const computed = await computeDB.computeRoot(writer);
+                expect(computed.version).toEqual(1);
+                expect(computed.changedKeys).toEqual(2);
+                expect(computed.root).not.toEqual(prevRoot);
+                await expect(computeDB.getCurrentState()).resolves.toMatchObject({ root: prevRoot, version: 1 });
+                await expect(computeDB.get(initState[1].key)).resolves.toEqual(initState[1].value);
+
+                // computing again with the same writer returns the same root
+                await expect(computeDB.computeRoot(writer)).resolves.toEqual(computed);
+                await expect(computeDB.commit(writer, 2, prevRoot)).resolves.toEqual(computed.root);
+                computeDB.close();
+            });
+
+            it('should compute the root on the state after the commit queued before it', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_compute_root_queued', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const computeDB = new StateDB(dbPath);
+                const initWriter = computeDB.newReadWriter();
+                await initWriter.set(initState[0].key, initState[0].value);
+                const committing = computeDB.commit(initWriter, 1, Buffer.alloc(0));
+
+                const writer = computeDB.newReadWriter();
+                await writer.set(initState[1].key, initState[1].value);
+                const computed = await computeDB.computeRoot(writer);
+                const prevRoot = await committing;
+                expect(computed.version).toEqual(1);
+                await expect(computeDB.commit(writer, 2, prevRoot)).resolves.toEqual(computed.root);
+                computeDB.close();
+            });
+        });
+
+        describe('provePending', () => {
+            it('should prove the queries against the root of the commit without writing', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_prove_pending', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const pendingDB = new StateDB(dbPath);
+                const initWriter = pendingDB.newReadWriter();
+                for (const pair of initState) {
+                    await initWriter.set(pair.key, pair.value);
+                }
+                const prevRoot = await pendingDB.commit(initWriter, 1, Buffer.alloc(0));
+
+                const writer = pendingDB.newReadWriter();
+                await writer.set(initState[0].key, getRandomBytes());
+                await writer.del(initState[1].key);
+                const hashKey = key => Buffer.concat([key.slice(0, 6), sha256(key.slice(6))]);
+                const queries = [hashKey(initState[0].key), hashKey(initState[1].key), hashKey(initState[2].key)];
+                const pending = await pendingDB.provePending(writer, queries);
+                expect(pending.root).not.toEqual(prevRoot);
+                await expect(pendingDB.verify(pending.root, queries, pending.proof)).resolves.toEqual(true);
+                // the deleted key is not included
+                await expect(pendingDB.verifyInclusionProof(pending.root, queries, pending.proof)).resolves.toEqual(false);
+                await expect(pendingDB.getCurrentState()).resolves.toMatchObject({ root: prevRoot, version: 1 });
+                await expect(pendingDB.get(initState[1].key)).resolves.toEqual(initState[1].value);
+
+                // the root is the same as the commit of the writer
+                await expect(pendingDB.commit(writer, 2, prevRoot)).resolves.toEqual(pending.root);
+                const proof = await pendingDB.prove(pending.root, queries);
+                expect(pending.proof).toEqual(proof);
+                pendingDB.close();
+            });
+        });
+
+        describe('write', () => {
+            it('should write the keys of the kinds atomically', async () => {
+                const key = Buffer.from([0, 0, 0, 9, 0, 0, 1]);
+                const batch = new Batch();
+                batch.set(key, Buffer.from([1, 2]), 'state');
+                batch.set(Buffer.from([0, 0, 0, 99]), Buffer.from([3]), 'diff');
+                batch.set(Buffer.from([0, 0, 0, 0, 0, 0, 9]), Buffer.from([4]), 'raw');
+                await db.write(batch);
+                batch.close();
+                await expect(db.get(key)).resolves.toEqual(Buffer.from([1, 2]));
+
+                const deleteBatch = new Batch();
+                deleteBatch.del(key, 'state');
+                deleteBatch.del(Buffer.from([0, 0, 0, 99]), 'diff');
+                deleteBatch.del(Buffer.from([0, 0, 0, 0, 0, 0, 9]));
+                await db.write(deleteBatch);
+                deleteBatch.close();
+                await expect(db.get(key)).rejects.toThrow(NotFoundError);
+            });
+
+            it('should write after the commit queued before it', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_write_order', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const orderDB = new StateDB(dbPath);
+                const key = getRandomBytes();
+                const writer = orderDB.newReadWriter();
+                await writer.set(key, getRandomBytes());
+                const batch = new Batch();
+                batch.del(key, 'state');
+
+                await Promise.all([orderDB.commit(writer, 1, Buffer.alloc(0)), orderDB.write(batch)]);
+                batch.close();
+                // the key created by the commit is deleted by the write
+                await expect(orderDB.get(key)).rejects.toThrow(NotFoundError);
+                orderDB.close();
+            });
+
+            it('should consume the written batch until it is reset', async () => {
+                const key = Buffer.from([0, 0, 0, 9, 0, 0, 3]);
+                const batch = new Batch();
+                batch.set(key, Buffer.from([1]), 'state');
+                await db.write(batch);
+                await expect(db.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+
+                batch.reset();
+                batch.del(key, 'state');
+                await db.write(batch);
+                batch.close();
+                await expect(db.has(key)).resolves.toBe(false);
+            });
+
+            it('should throw for the unknown kind', () => {
+                const batch = new Batch();
+                expect(() => batch.set(Buffer.from([1]), Buffer.from([1]), 'current')).toThrow(
+                    'kind must be one of state, diff, smt or raw',
+                );
+                expect(() => batch.del(Buffer.from([1]), 'STATE')).toThrow(
+                    'kind must be one of state, diff, smt or raw',
+                );
+                batch.close();
+            });
+        });
+
+        describe('maxPendingOperations', () => {
+            it('should delay the writes until the queue drains with blockOnFull', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_pending', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const limitedDB = new StateDB(dbPath, { maxPendingOperations: 2, blockOnFull: true });
+                const keys = Array.from({ length: 50 }, (_, i) => Buffer.from([0, 0, 0, 9, 0, 1, i]));
+                const batches = keys.map(key => {
+                    const batch = new Batch();
+                    batch.set(key, key, 'state');
+                    return batch;
+                });
+
+                await Promise.all(batches.map(batch => limitedDB.write(batch)));
+                for (const key of keys) {
+                    await expect(limitedDB.get(key)).resolves.toEqual(key);
+                }
+                expect(limitedDB.pendingOperations()).toEqual(0);
+                for (const batch of batches) {
+                    batch.close();
+                }
+                limitedDB.close();
+            });
+
+            it('should reject the writes only with backpressure error when the queue is full', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_pending_reject', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const limitedDB = new StateDB(dbPath, { maxPendingOperations: 1 });
+                // each write has its own batch, as the batch is consumed by the write
+                const batches = Array.from({ length: 100 }, () => {
+                    const batch = new Batch();
+                    batch.set(Buffer.from([0, 0, 0, 9, 0, 2]), Buffer.from([1]), 'state');
+                    return batch;
+                });
+
+                const results = await Promise.allSettled(batches.map(batch => limitedDB.write(batch)));
+                for (const result of results.filter(r => r.status === 'rejected')) {
+                    expect(result.reason.message).toMatch('backpressure');
+                }
+                expect(limitedDB.pendingOperations()).toEqual(0);
+                for (const batch of batches) {
+                    batch.close();
+                }
+                limitedDB.close();
+            });
+        });
+
+        describe('StateReadWriter', () => {
+            describe('set', () => {
+                it('should resolve false for a new key', async () => {
+                    const writer = db.newReadWriter();
+                    const key = getRandomBytes();
+                    await expect(writer.set(key, getRandomBytes())).resolves.toBe(false);
+                    // the key created in the writer exists on the next upsert
+                    await expect(writer.set(key, getRandomBytes())).resolves.toBe(true);
+                    await expect(writer.set(getRandomBytes(), getRandomBytes(), { previousValue: true })).resolves.toEqual({
+                        existed: false,
+                    });
+                });
+
+                it('should resolve true with the previous value for a key existing only in the snapshot', async () => {
+                    const writer = db.newReadWriter();
+                    const newValue = getRandomBytes();
+                    await expect(writer.set(initState[0].key, newValue, { previousValue: true })).resolves.toEqual({
+                        existed: true,
+                        previousValue: initState[0].value,
+                    });
+                    await expect(writer.set(initState[0].key, getRandomBytes(), { previousValue: true })).resolves.toEqual({
+                        existed: true,
+                        previousValue: newValue,
+                    });
+                    await expect(writer.set(initState[1].key, getRandomBytes())).resolves.toBe(true);
+                });
+
+                it('should resolve false for a key deleted in the same writer', async () => {
+                    const writer = db.newReadWriter();
+                    await writer.del(initState[2].key);
+                    await expect(writer.set(initState[2].key, getRandomBytes(), { previousValue: true })).resolves.toEqual({
+                        existed: false,
+                    });
+
+                    const key = getRandomBytes();
+                    await writer.set(key, getRandomBytes());
+                    await writer.del(key);
+                    await expect(writer.set(key, getRandomBytes())).resolves.toBe(false);
+                });
+            });
+
+            it('should commit the same root with the cache spilled over spillThreshold', async () => {
+                const inMemory = db.newReadWriter();
+                const spilled = db.newReadWriter({ spillThreshold: 1 });
+                const newPairs = Array.from({ length: 20 }, () => ({ key: getRandomBytes(), value: getRandomBytes() }));
+                for (const writer of [inMemory, spilled]) {
+                    for (const pair of newPairs) {
+                        await writer.set(pair.key, pair.value);
+                    }
+                    await writer.set(initState[1].key, newPairs[0].value);
+                    await writer.del(initState[2].key);
+                }
+
+                const options = { gte: Buffer.alloc(1, 0), lte: Buffer.alloc(40, 255) };
+                await expect(spilled.range(options)).resolves.toEqual(await inMemory.range(options));
+                const inMemoryRoot = await db.commit(inMemory, 1, root, { readonly: true });
+                await expect(db.commit(spilled, 1, root, { readonly: true })).resolves.toEqual(inMemoryRoot);
+            });
+
+            it('should reject the invalid spillThreshold', () => {
+                expect(() => db.newReadWriter({ spillThreshold: -1 })).toThrow('spillThreshold must be a positive integer');
+            });
+
+            it('should peek the stored value and the cached view', async () => {
+                const writer = db.newReadWriter();
+                const stored = await db.get(initState[1].key);
+                await expect(writer.peek(initState[1].key)).resolves.toEqual(stored);
+                await expect(writer.peek(getRandomBytes())).rejects.toThrow(NotFoundError);
+
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                await writer.del(initState[2].key);
+                await expect(writer.peek(initState[1].key)).resolves.toEqual(newValue);
+                await expect(writer.peek(initState[2].key)).rejects.toThrow(NotFoundError);
+                await expect(writer.get(initState[2].key)).rejects.toThrow(NotFoundError);
+            });
+
+            it('should return the values of the keys in order with getMany', async () => {
+                const writer = db.newReadWriter();
+                const newKey = getRandomBytes();
+                const newValue = getRandomBytes();
+                await writer.set(newKey, newValue);
+                await writer.del(initState[2].key);
+
+                const keys = [initState[1].key, newKey, initState[2].key, getRandomBytes()];
+                await expect(writer.getMany(keys)).resolves.toEqual([initState[1].value, newValue, undefined, undefined]);
+                await expect(writer.hasMany(keys)).resolves.toEqual([true, true, false, false]);
+                await expect(writer.getMany([])).resolves.toEqual([]);
+            });
+
+            it('should return values with range', async () => {
+                const writer = db.newReadWriter();
+                await writer.set(Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 3]), getRandomBytes());
+
+                const result = await writer.range({
+                    gte: Buffer.from('00000002800000000000', 'hex'),
+                    lte: Buffer.from('000000028000ffffffff', 'hex'),
+                });
+
+                expect(result).toHaveLength(0);
+            });
+
+            it('should return only the keys with range with keysOnly', async () => {
+                const writer = db.newReadWriter();
+                const newKey = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 3]);
+                await writer.set(newKey, getRandomBytes());
+                await writer.del(initState[1].key);
+
+                const result = await writer.range({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    keysOnly: true,
+                });
+
+                expect(result).toEqual([initState[0].key, newKey, initState[2].key, initState[3].key].map(key => ({ key })));
+                expect(result[0]).not.toHaveProperty('value');
+                // stored values are still read when the key is updated after the keys only range
+                await expect(writer.get(initState[2].key)).resolves.toEqual(initState[2].value);
+            });
+
+            it('should return the value sizes of the pending values with range with inventory', async () => {
+                const writer = db.newReadWriter();
+                const newKey = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 3]);
+                await writer.set(newKey, getRandomBytes(64));
+                await writer.set(initState[2].key, getRandomBytes(31));
+                await writer.del(initState[1].key);
+                const range = {
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    inventory: true,
+                };
+
+                await expect(writer.range(range)).resolves.toEqual([
+                    { key: initState[0].key, valueSize: 32 },
+                    { key: newKey, valueSize: 64 },
+                    { key: initState[2].key, valueSize: 31 },
+                    { key: initState[3].key, valueSize: 32 },
+                ]);
+                // the value of the threshold size is reported, and the limit counts only the reported values
+                await expect(writer.range({ ...range, minValueSize: 32, limit: 2 })).resolves.toEqual([
+                    { key: initState[0].key, valueSize: 32 },
+                    { key: newKey, valueSize: 64 },
+                ]);
+                await expect(writer.range({ ...range, minValueSize: 33 })).resolves.toEqual([
+                    { key: newKey, valueSize: 64 },
+                ]);
+            });
+
+            it('should return updated value with range', async () => {
+                const writer = db.newReadWriter();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+
+                const result = await writer.range({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                });
+
+                expect(result).toHaveLength(2);
+                expect(result[0]).toEqual({
+                    key: initState[1].key,
+                    value: newValue,
+                });
+                expect(result[1]).toEqual(initState[2]);
+            });
+
+            it('should return updated value with range in reverse', async () => {
+                const writer = db.newReadWriter();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+
+                const result = await writer.range({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                    reverse: true,
+                });
+
+                expect(result).toHaveLength(2);
+                expect(result[0].value).toEqual(initState[3].value);
+                expect(result[1].value).toEqual(initState[2].value);
+            });
+
+            it('should return all the values in the state when range is not specified', async () => {
+                const writer = db.newReadWriter();
+                const newKey = Buffer.from([255, 255]);
+                await writer.set(newKey, getRandomBytes());
+                await writer.del(initState[0].key);
+
+                const result = await writer.range({ limit: 2 });
+                expect(result).toHaveLength(2);
+                expect(result[0].key).toEqual(initState[1].key);
+
+                const all = await writer.range({ gte: Buffer.from([255]) });
+                expect(all).toHaveLength(1);
+                expect(all[0].key).toEqual(newKey);
+            });
+
+            it('should return to original value after restoreSnapshot', async () => {
+                const writer = db.newReadWriter();
+                const index = writer.snapshot();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                writer.restoreSnapshot(index);
+
+                const result = await writer.range({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                });
+
+                expect(result).toHaveLength(2);
+                expect(result[0].value).toEqual(initState[1].value);
+                expect(result[1].value).toEqual(initState[2].value);
+            });
+
+            it('should return to original value after restoreSnapshot with multiple snapshot', async () => {
+                const writer = db.newReadWriter();
+                const index = writer.snapshot();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                writer.snapshot()
+                await writer.set(initState[1].key, getRandomBytes());
+                writer.restoreSnapshot(index);
+
+                const result = await writer.range({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                });
+
+                expect(result).toHaveLength(2);
+                expect(result[0].value).toEqual(initState[1].value);
+                expect(result[1].value).toEqual(initState[2].value);
+            });
+
+            it('should keep the changes after releaseSnapshot and revert them by the outer snapshot', async () => {
+                const writer = db.newReadWriter();
+                const outer = writer.snapshot();
+                const inner = writer.snapshot();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                writer.releaseSnapshot(inner);
+
+                await expect(writer.get(initState[1].key)).resolves.toEqual(newValue);
+                expect(() => writer.restoreSnapshot(inner)).toThrow('Invalid usage');
+                writer.restoreSnapshot(outer);
+                await expect(writer.get(initState[1].key)).resolves.toEqual(initState[1].value);
+                expect(() => writer.releaseSnapshot(outer)).toThrow('Invalid usage');
+            });
+
+            it('should count the keys in the range with the changes in the writer', async () => {
+                const writer = db.newReadWriter();
+                const options = {
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                };
+                await expect(writer.countRange(options)).resolves.toEqual(3);
+
+                await writer.set(Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 2]), getRandomBytes());
+                await writer.del(initState[1].key);
+                await writer.del(initState[2].key);
+                await expect(writer.countRange(options)).resolves.toEqual(2);
+                await expect(writer.countRange({ ...options, limit: 1 })).resolves.toEqual(1);
+                await expect(db.newReader().countRange(options)).resolves.toEqual(3);
+            });
+
+            it('should check the prefix with the changes in the writer', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_exists_prefix', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const prefixDB = new StateDB(dbPath, { prefixFilterLength: 6 });
+                const storedKeys = [Buffer.from([0, 0, 0, 1, 0, 0, 1]), Buffer.from([0, 0, 0, 1, 0, 0, 2])];
+                const initWriter = prefixDB.newReadWriter();
+                for (const key of storedKeys) {
+                    await initWriter.set(key, getRandomBytes());
+                }
+                await prefixDB.commit(initWriter, 0, Buffer.alloc(0));
+
+                const writer = prefixDB.newReadWriter();
+                const stored = Buffer.from([0, 0, 0, 1, 0, 0]);
+                const created = Buffer.from([0, 0, 0, 2, 0, 0]);
+                await expect(writer.existsPrefix(stored)).resolves.toEqual(true);
+                await expect(writer.existsPrefix(created)).resolves.toEqual(false);
+
+                // the key created in the writer makes the prefix exist
+                await writer.set(Buffer.concat([created, Buffer.from([1])]), getRandomBytes());
+                await expect(writer.existsPrefix(created)).resolves.toEqual(true);
+                await expect(prefixDB.newReader().existsPrefix(created)).resolves.toEqual(false);
+
+                // the prefix does not exist once all the stored keys are deleted in the writer
+                await writer.del(storedKeys[0]);
+                await expect(writer.existsPrefix(stored)).resolves.toEqual(true);
+                await writer.del(storedKeys[1]);
+                await expect(writer.existsPrefix(stored)).resolves.toEqual(false);
+                await expect(prefixDB.newReader().existsPrefix(stored)).resolves.toEqual(true);
+                prefixDB.close();
+            });
+
+            it('should return only the pending changes with their state', async () => {
+                const writer = db.newReadWriter();
+                const options = {
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                };
+                await expect(writer.rangePending(options)).resolves.toEqual([]);
+
+                const newKey = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 2]);
+                const newValue = getRandomBytes();
+                const updatedValue = getRandomBytes();
+                await writer.get(initState[0].key);
+                await writer.set(newKey, newValue);
+                await writer.set(initState[1].key, updatedValue);
+                await writer.del(initState[2].key);
+
+                await expect(writer.rangePending(options)).resolves.toEqual([
+                    { key: initState[1].key, value: updatedValue, state: 'updated' },
+                    { key: newKey, value: newValue, state: 'created' },
+                    { key: initState[2].key, value: Buffer.alloc(0), state: 'deleted' },
+                ]);
+                await expect(writer.rangePending({ ...options, reverse: true, limit: 1 })).resolves.toEqual([
+                    { key: initState[2].key, value: Buffer.alloc(0), state: 'deleted' },
+                ]);
+            });
+
+            it('should page the range consistently with the writer at open', async () => {
+                const writer = db.newReadWriter();
+                const options = {
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                };
+                await writer.set(Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 2]), getRandomBytes());
+                await writer.set(initState[1].key, getRandomBytes());
+                await writer.del(initState[2].key);
+                const expected = await writer.range(options);
+
+                const cursor = writer.openRange(options);
+                const pages = [await cursor.next(2)];
+                // changes and the commit after the open are not observed by the cursor
+                await writer.set(Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 3]), getRandomBytes());
+                await writer.del(initState[3].key);
+                await db.commit(writer, 1, root);
+                for (let page = await cursor.next(2); page.length > 0; page = await cursor.next(2)) {
+                    pages.push(page);
+                }
+                expect(pages.every(page => page.length <= 2)).toBe(true);
+                expect(pages.flat()).toEqual(expected);
+
+                cursor.close();
+                await expect(cursor.next(2)).rejects.toThrow('RangeCursor is closed');
+            });
+
+            it('should throw error with non existing snapshot', async () => {
+                const writer = db.newReadWriter();
+                writer.snapshot();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                writer.snapshot()
+                await writer.set(initState[1].key, getRandomBytes());
+                expect(() => writer.restoreSnapshot(99)).toThrow('Invalid usage');
+            });
+
+            it('should throw an error when the writer is closed', async () => {
+                const writer = db.newReadWriter();
+                const newValue = getRandomBytes();
+                await writer.set(initState[1].key, newValue);
+                await expect(writer.get(initState[1].key)).resolves.toEqual(newValue);
+                writer.close();
+                expect(() => writer.get(initState[1].key)).rejects.toThrow();
+            });
+        });
+
+        describe('StateReader', () => {
+            const nonExistingKey = Buffer.from([255, 255]);
+
+            it('should check whether any key in the snapshot starts with the prefix', async () => {
+                const reader = db.newReader();
+                await expect(reader.existsPrefix(Buffer.from([0, 0, 0, 0, 0, 1]))).resolves.toEqual(true);
+                await expect(reader.existsPrefix(initState[4].key)).resolves.toEqual(true);
+                await expect(reader.existsPrefix(Buffer.from([255, 255]))).resolves.toEqual(false);
+            });
+
+            it('should not have set', () => {
+                const reader = db.newReader();
+                expect(reader.set).toBeUndefined();
+            });
+
+            it('should not have del', () => {
+                const reader = db.newReader();
+                expect(reader.del).toBeUndefined();
+            });
+
+            it('should fail if DB does not have key', async () => {
                 const reader = db.newReader();
                 await expect(reader.get(nonExistingKey)).rejects.toThrow('does not exist');
             });
 
-            it('should return zero buffer when value length is zero', async () => {
-                const reader = db.newReader();
-                await expect(reader.get(Buffer.from([0, 0, 0, 15, 0, 0]))).resolves.toEqual(Buffer.alloc(0));
+            it('should return zero buffer when value length is zero', async () => {
+                const reader = db.newReader();
+                await expect(reader.get(emptyValueKey)).resolves.toEqual(Buffer.alloc(0));
+            });
+
+            it('should return associated value', async () => {
+                const reader = db.newReader();
+                await expect(reader.get(initState[0].key)).resolves.toEqual(initState[0].value);
+            });
+
+            it('should return true when called has if key exist', async () => {
+                const reader = db.newReader();
+                await expect(reader.has(initState[0].key)).resolves.toEqual(true);
+            });
+
+            it('should return the values of the keys in order with getMany', async () => {
+                const reader = db.newReader();
+                const keys = [initState[1].key, nonExistingKey, initState[0].key, emptyValueKey];
+                await expect(reader.getMany(keys)).resolves.toEqual([initState[1].value, undefined, initState[0].value, Buffer.alloc(0)]);
+                await expect(reader.hasMany(keys)).resolves.toEqual([true, false, true, true]);
+            });
+
+            it('should iterate with specified range with limit', async () => {
+                const reader = db.newReader();
+                const stream = reader.iterate({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                });
+
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('err', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+
+                expect(values).toEqual(initState.slice(1, 3));
+            });
+
+            it('should create read stream with specified range with limit', async () => {
+                const reader = db.newReader();
+                const stream = reader.createReadStream({
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                    limit: 2,
+                });
+
+                const values = await new Promise((resolve, reject) => {
+                    const result = [];
+                    stream
+                        .on('data', kv => {
+                            result.push(kv);
+                        })
+                        .on('err', err => {
+                            reject(err);
+                        })
+                        .on('end', () => {
+                            resolve(result);
+                        });
+                });
+
+                expect(values).toEqual(initState.slice(1, 3));
+            });
+
+            it('should count the keys in the range up to the limit', async () => {
+                const reader = db.newReader();
+                const options = {
+                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                };
+                await expect(reader.countRange(options)).resolves.toEqual(3);
+                await expect(reader.countRange({ ...options, limit: 2 })).resolves.toEqual(2);
+                await expect(reader.countRange({ gte: nonExistingKey, lte: nonExistingKey })).resolves.toEqual(0);
+            });
+
+            it('should throw an error when the reader is closed', async () => {
+                const reader = db.newReader();
+                await expect(reader.get(initState[0].key)).resolves.toEqual(initState[0].value);
+                reader.close();
+                expect(() => reader.get(initState[1].key)).rejects.toThrow();
+            });
+        });
+
+        describe('checkpoint', () => {
+            let tmpPath;
+            beforeEach(() => {
+                tmpPath = fs.mkdtempSync("");
+            });
+
+            it('should create checkpoint', async () => {
+                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
+                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
+                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+
+                await db.checkpoint(tmpPath + '/test_db');
+
+                db = new StateDB(tmpPath + '/test_db');
+                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
+                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
+                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+            });
+
+            it('should failed to create checkpoint because directory is not empty', async () => {
+                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
+                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
+                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+
+                await expect(db.checkpoint(tmpPath)).rejects.toThrow();
+            });
+
+            it('should return the metadata of the verified checkpoint', async () => {
+                const { root } = await db.getCurrentState();
+                const info = await db.checkpoint(tmpPath + '/test_db_flushed', { flushBeforeCheckpoint: true });
+                expect(info.root).toEqual(root);
+                expect(info.size).toBeGreaterThan(0);
+                expect(info.sstFiles).toBeGreaterThan(0);
+
+                await expect(db.verifyCheckpoint(tmpPath + '/test_db_flushed')).resolves.toEqual(info);
+                await expect(db.verifyCheckpoint(tmpPath + '/not_exist')).rejects.toThrow();
+            });
+
+            describe('group', () => {
+                let blockchainDB;
+                beforeEach(() => {
+                    blockchainDB = new Database(path.join(tmpPath, 'blockchain.db'));
+                });
+
+                afterEach(async () => {
+                    await blockchainDB.close();
+                });
+
+                it('should write the manifest after both checkpoints are created', async () => {
+                    await blockchainDB.set(Buffer.from([1, 2, 3]), Buffer.from([4, 5, 6]));
+                    const manifestPath = path.join(tmpPath, 'manifest');
+                    const group = new CheckpointGroup(manifestPath);
+
+                    const blockchain = await blockchainDB.checkpointWithGroup(group, path.join(tmpPath, 'blockchain_checkpoint'));
+                    expect(fs.existsSync(manifestPath)).toBe(false);
+                    const state = await db.checkpointWithGroup(group, path.join(tmpPath, 'state_checkpoint'));
+                    expect(fs.existsSync(manifestPath)).toBe(true);
+                    expect(fs.existsSync(`${manifestPath}.tmp`)).toBe(false);
+
+                    const { root } = await db.getCurrentState();
+                    expect(state.root).toEqual(root);
+                    await expect(CheckpointGroup.verify(manifestPath)).resolves.toEqual({ blockchain, state });
+                    // each member is taken once, and the manifest is not replaced
+                    await expect(db.checkpointWithGroup(group, path.join(tmpPath, 'state_again'))).rejects.toThrow();
+                    expect(() => new CheckpointGroup(manifestPath)).toThrow('already exists');
+                });
+
+                it('should mark the group incomplete when the second checkpoint fails', async () => {
+                    const manifestPath = path.join(tmpPath, 'manifest');
+                    const group = new CheckpointGroup(manifestPath);
+
+                    await blockchainDB.checkpointWithGroup(group, path.join(tmpPath, 'blockchain_checkpoint'));
+                    // checkpoint cannot be created on the existing directory
+                    await expect(db.checkpointWithGroup(group, tmpPath)).rejects.toThrow();
+
+                    expect(fs.existsSync(manifestPath)).toBe(true);
+                    await expect(CheckpointGroup.verify(manifestPath)).rejects.toThrow('checkpoint group is incomplete');
+                    await expect(CheckpointGroup.verify(path.join(tmpPath, 'not_exist'))).rejects.toThrow();
+                });
+            });
+        });
+
+        describe('compact', () => {
+            it('should compact the state range', async () => {
+                await expect(db.compact()).resolves.toBeUndefined();
+                await expect(db.compact(initState[0].key, initState[0].key)).resolves.toBeUndefined();
+
+                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
+                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
+            });
+        });
+
+        describe('stats', () => {
+            it('should return the database properties as numbers', async () => {
+                const stats = await db.stats();
+                expect(typeof stats.estimateNumKeys).toBe('number');
+                expect(typeof stats.estimatePendingCompactionBytes).toBe('number');
+            });
+        });
+
+        describe('approximateSize', () => {
+            it('should return the sizes of the state keys with the module prefix', async () => {
+                await db.flush();
+                const prefix = initState[0].key.subarray(0, 4);
+                const size = await db.approximateSize(prefix);
+                expect(size.sstSize).toBeGreaterThan(0);
+                expect(typeof size.memtableSize).toBe('number');
+
+                const sizes = await db.approximateSizes([prefix, Buffer.from([255, 255, 255, 255])]);
+                expect(sizes.get(prefix.toString('hex'))).toEqual(size);
+                expect(sizes.size).toEqual(2);
+                expect(typeof sizes.get('ffffffff').sstSize).toBe('number');
+            });
+        });
+
+        describe('verifyNodes', () => {
+            it('should report no mismatch for the open database and its checkpoint', async () => {
+                const progress = [];
+                const report = await db.verifyNodes(undefined, { progressInterval: 1, onProgress: r => progress.push(r) });
+                expect(report.checked).toBeGreaterThan(0);
+                expect(report.mismatches).toEqual(0);
+                expect(report.mismatched).toEqual([]);
+                expect(report.truncated).toBe(false);
+                expect(progress).toHaveLength(report.checked);
+
+                const checkpointPath = path.join(os.tmpdir(), 'state_verify_nodes', Date.now().toString());
+                await db.checkpoint(checkpointPath);
+                await expect(db.verifyNodes(checkpointPath)).resolves.toEqual(report);
+            });
+
+            it('should reject when maxErrors is invalid', async () => {
+                await expect(db.verifyNodes(undefined, { maxErrors: 0 })).rejects.toThrow('maxErrors must be a positive integer');
+            });
+        });
+
+        describe('checkIntegrity', () => {
+            it('should report no error at the quick and the full level', async () => {
+                const quick = await db.checkIntegrity('quick');
+                expect(quick.ok).toBe(true);
+                expect(quick.level).toEqual('quick');
+                expect(quick.errors).toEqual([]);
+                expect(quick.checkedNodes).toEqual(0);
+
+                const progress = [];
+                const full = await db.checkIntegrity('full', { progressInterval: 1, onProgress: r => progress.push(r) });
+                expect(full.ok).toBe(true);
+                expect(full.root).toEqual(quick.root);
+                expect(full.checkedNodes).toBeGreaterThan(0);
+                expect(full.checkedKeys).toBeGreaterThan(0);
+                expect(progress).toHaveLength(full.checkedNodes + full.checkedKeys);
+            });
+
+            it('should reject when the level is invalid', async () => {
+                await expect(db.checkIntegrity('deep')).rejects.toThrow('level must be "quick" or "full"');
+            });
+
+            it('should reject with ERR_CANCELLED when cancelled', async () => {
+                const cancelled = db.checkIntegrity('full');
+                db.cancelCheckIntegrity();
+
+                await expect(cancelled).rejects.toHaveProperty('code', 'ERR_CANCELLED');
+                // cancellation does not affect the scans started after it
+                await expect(db.checkIntegrity('full')).resolves.toHaveProperty('ok', true);
+            });
+
+            it('should open the database with the report of the scan', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_check_integrity', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const checkedDB = new StateDB(dbPath);
+                const writer = checkedDB.newReadWriter();
+                const key = getRandomBytes();
+                await writer.set(key, getRandomBytes());
+                const checkedRoot = await checkedDB.commit(writer, 1, Buffer.alloc(0));
+                checkedDB.close();
+
+                const { db: reopened, report } = await StateDB.openWithIntegrityCheck(dbPath, {}, 'full');
+                expect(report.ok).toBe(true);
+                expect(report.height).toEqual(1);
+                expect(report.root).toEqual(checkedRoot);
+                expect(report.checkedKeys).toEqual(1);
+                await expect(reopened.has(key)).resolves.toEqual(true);
+                reopened.close();
+            });
+        });
+
+        describe('pruneSMT', () => {
+            let pruneDB;
+            const roots = [];
+            const key = Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            const queryKey = Buffer.concat([key.slice(0, 6), sha256(key.slice(6))]);
+
+            beforeEach(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_prune_smt', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                pruneDB = new StateDB(dbPath);
+                let pruneRoot = Buffer.alloc(0);
+                for (let height = 1; height <= 5; height += 1) {
+                    const writer = pruneDB.newReadWriter();
+                    await writer.set(key, Buffer.from([height]));
+                    pruneRoot = await pruneDB.commit(writer, height, pruneRoot);
+                    roots[height] = pruneRoot;
+                }
+            });
+
+            afterEach(() => {
+                pruneDB.close();
+            });
+
+            it('should keep the proofs of the retained roots valid', async () => {
+                const progress = [];
+                const report = await pruneDB.pruneSMT(2, { batchSize: 1, onProgress: r => progress.push(r) });
+                expect(report.deleted).toBeGreaterThan(0);
+                expect(progress).toHaveLength(report.deleted);
+                expect(progress[progress.length - 1]).toEqual(report);
+
+                for (const height of [4, 5]) {
+                    const proof = await pruneDB.prove(roots[height], [queryKey]);
+                    expect(proof.queries[0].value).toEqual(sha256(Buffer.from([height])));
+                    await expect(pruneDB.verify(roots[height], [queryKey], proof)).resolves.toEqual(true);
+                }
+                await expect(pruneDB.prove(roots[3], [queryKey])).rejects.toThrow();
+                // rerun does not delete the nodes of the retained roots
+                await expect(pruneDB.pruneSMT(2)).resolves.toEqual({ ...report, scanned: report.scanned - report.deleted, deleted: 0 });
+            });
+
+            it('should reject when retainHeights is invalid', async () => {
+                await expect(pruneDB.pruneSMT(0)).rejects.toThrow('retainHeights must be a positive integer');
+                await expect(pruneDB.pruneSMT(1, { batchSize: 0 })).rejects.toThrow('batchSize must be a positive integer');
+            });
+        });
+
+        describe('rebuildSMT', () => {
+            let rebuildDB;
+            let rebuildRoot;
+            const queryKeys = initState.map(data => Buffer.concat([data.key.slice(0, 6), sha256(data.key.slice(6))]));
+
+            beforeEach(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_rebuild_smt', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                rebuildDB = new StateDB(dbPath);
+                rebuildRoot = Buffer.alloc(0);
+                for (let height = 1; height <= 2; height += 1) {
+                    const writer = rebuildDB.newReadWriter();
+                    for (const data of initState) {
+                        await writer.set(data.key, Buffer.concat([data.value, Buffer.from([height])]));
+                    }
+                    rebuildRoot = await rebuildDB.commit(writer, height, rebuildRoot);
+                }
+            });
+
+            afterEach(() => {
+                rebuildDB.close();
+            });
+
+            it('should rebuild the tree of the state keys', async () => {
+                // lose the root node as if the nodes are corrupted
+                const batch = new Batch();
+                batch.del(rebuildRoot, 'smt');
+                await rebuildDB.write(batch);
+                batch.close();
+                await expect(rebuildDB.prove(rebuildRoot, queryKeys)).rejects.toThrow();
+
+                const progress = [];
+                const report = await rebuildDB.rebuildSMT(rebuildRoot, { batchSize: 2, onProgress: r => progress.push(r) });
+                expect(report).toEqual({ keys: initState.length, inserted: initState.length });
+                expect(progress).toHaveLength(Math.ceil(initState.length / 2));
+                expect(progress[progress.length - 1]).toEqual(report);
+                const proof = await rebuildDB.prove(rebuildRoot, queryKeys);
+                await expect(rebuildDB.verifyInclusionProof(rebuildRoot, queryKeys, proof)).resolves.toEqual(true);
+                await expect(rebuildDB.getCurrentState()).resolves.toMatchObject({ root: rebuildRoot, version: 2 });
+
+                // rerun rebuilds the same tree
+                await expect(rebuildDB.rebuildSMT(rebuildRoot)).resolves.toEqual(report);
+            });
+
+            it('should reject without changing the tree when the root does not match', async () => {
+                const expected = getRandomBytes(32);
+                await expect(rebuildDB.rebuildSMT(expected)).rejects.toThrow(`not matching with expected ${expected.toString('hex')}`);
+                const proof = await rebuildDB.prove(rebuildRoot, queryKeys);
+                await expect(rebuildDB.verifyInclusionProof(rebuildRoot, queryKeys, proof)).resolves.toEqual(true);
+                await expect(rebuildDB.rebuildSMT(rebuildRoot, { batchSize: 0 })).rejects.toThrow('batchSize must be a positive integer');
+            });
+        });
+
+        describe('SMT sync', () => {
+            let servingDB;
+            let servingRoot;
+            let syncingDB;
+            const newPath = name => {
+                const dbPath = path.join(os.tmpdir(), 'state_smt_sync', `${name}_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                return dbPath;
+            };
+
+            beforeEach(async () => {
+                servingDB = new StateDB(newPath('serving'));
+                const writer = servingDB.newReadWriter();
+                for (const data of initState) {
+                    await writer.set(data.key, data.value);
+                }
+                servingRoot = await servingDB.commit(writer, 1, Buffer.alloc(0));
+                syncingDB = new StateDB(newPath('syncing'), { unsafeSync: true });
+            });
+
+            afterEach(() => {
+                servingDB.close();
+                syncingDB.close();
+            });
+
+            it('should install the nodes walked from the root and finalize the root', async () => {
+                const entries = [];
+                const pending = [servingRoot];
+                while (pending.length > 0) {
+                    const hash = pending.pop();
+                    const data = await servingDB.getSmtNode(hash);
+                    expect(data).toBeInstanceOf(Buffer);
+                    pending.push(...(await servingDB.getSmtChildren(hash)));
+                    entries.push({ hash, data });
+                }
+
+                await syncingDB.putSmtNodes(entries.slice(1));
+                await expect(syncingDB.finalizeSync(servingRoot, 1)).rejects.toHaveProperty('code', 'ERR_INVALID_ROOT');
+                await syncingDB.putSmtNodes(entries.slice(0, 1));
+                await syncingDB.finalizeSync(servingRoot, 1);
+                await expect(syncingDB.getCurrentState()).resolves.toMatchObject({ root: servingRoot, version: 1 });
+                await expect(syncingDB.getSmtNode(servingRoot)).resolves.toEqual(entries[0].data);
+            });
+
+            it('should resolve undefined for the unknown node', async () => {
+                await expect(servingDB.getSmtNode(getRandomBytes(32))).resolves.toBeUndefined();
+                await expect(servingDB.getSmtChildren(getRandomBytes(32))).resolves.toBeUndefined();
+            });
+
+            it('should reject the node which does not match its hash', async () => {
+                const data = await servingDB.getSmtNode(servingRoot);
+                await expect(syncingDB.putSmtNodes([{ hash: getRandomBytes(32), data }])).rejects.toHaveProperty('code', 'ERR_INVALID_ARGUMENT');
+            });
+
+            it('should reject installing the nodes without unsafeSync', async () => {
+                const data = await servingDB.getSmtNode(servingRoot);
+                await expect(servingDB.putSmtNodes([{ hash: servingRoot, data }])).rejects.toHaveProperty('code', 'ERR_INVALID_USAGE');
+                await expect(servingDB.finalizeSync(servingRoot, 1)).rejects.toHaveProperty('code', 'ERR_INVALID_USAGE');
+            });
+        });
+
+        describe('exportSnapshot and importSnapshot', () => {
+            const newPath = name => {
+                const dbPath = path.join(os.tmpdir(), 'state_snapshot', `${name}_${Date.now().toString()}`);
+                fs.mkdirSync(dbPath, { recursive: true });
+                return dbPath;
+            };
+            let source;
+            let sourceRoot;
+
+            beforeEach(async () => {
+                source = new StateDB(newPath('source'));
+                const writer = source.newReadWriter();
+                for (const data of initState) {
+                    await writer.set(data.key, data.value);
+                }
+                const prevRoot = await source.commit(writer, 1, Buffer.alloc(0));
+                const nextWriter = source.newReadWriter();
+                await nextWriter.del(initState[0].key);
+                sourceRoot = await source.commit(nextWriter, 2, prevRoot);
+            });
+
+            afterEach(() => {
+                source.close();
+            });
+
+            it('should restore the state of the height with the progress', async () => {
+                const snapshotPath = path.join(newPath('file'), 'state.snapshot');
+                const exportProgress = [];
+                const exported = await source.exportSnapshot(snapshotPath, 2, {
+                    progressInterval: 2,
+                    onProgress: records => exportProgress.push(records),
+                    sync: true,
+                });
+                expect(exported).toEqual({ records: initState.length - 1, height: 2, root: sourceRoot });
+                expect(exportProgress).toEqual([2, 4, 6]);
+
+                const target = new StateDB(newPath('target'));
+                const importProgress = [];
+                const imported = await target.importSnapshot(snapshotPath, {
+                    progressInterval: 2,
+                    onProgress: records => importProgress.push(records),
+                });
+                expect(imported).toEqual(exported);
+                expect(importProgress).toEqual([2, 4, 6]);
+                await expect(target.getCurrentState()).resolves.toMatchObject({ root: sourceRoot, version: 2 });
+                await expect(target.has(initState[0].key)).resolves.toBe(false);
+                for (const data of initState.slice(1)) {
+                    await expect(target.get(data.key)).resolves.toEqual(data.value);
+                }
+                const queries = [getRandomBytes(38)];
+                await expect(target.prove(sourceRoot, queries)).resolves.toEqual(
+                    await source.prove(sourceRoot, queries),
+                );
+                await expect(target.importSnapshot(snapshotPath)).rejects.toThrow('state is not empty');
+                target.close();
+            });
+
+            it('should reject export when the height is not the current height', async () => {
+                const snapshotPath = path.join(newPath('file'), 'state.snapshot');
+                await expect(source.exportSnapshot(snapshotPath, 1)).rejects.toThrow(
+                    'height 1 is not the current height 2',
+                );
+                expect(fs.existsSync(snapshotPath)).toBe(false);
+            });
+
+            it('should reject import when the root does not match and leave the state empty', async () => {
+                const snapshotPath = path.join(newPath('file'), 'state.snapshot');
+                await source.exportSnapshot(snapshotPath, 2);
+                const bytes = fs.readFileSync(snapshotPath);
+                // first byte of the root in the footer
+                bytes[bytes.length - 64] ^= 0xff;
+                fs.writeFileSync(snapshotPath, bytes);
+
+                const target = new StateDB(newPath('target'));
+                await expect(target.importSnapshot(snapshotPath, { progressInterval: 1 })).rejects.toThrow(
+                    'state root does not match with the snapshot',
+                );
+                await expect(target.has(initState[1].key)).resolves.toBe(false);
+                await expect(target.getCurrentState()).resolves.toHaveProperty('version', 0);
+                target.close();
+            });
+
+            it('should reject when progressInterval is invalid', async () => {
+                await expect(source.exportSnapshot('unused', 2, { progressInterval: 0 })).rejects.toThrow(
+                    'progressInterval must be a positive integer',
+                );
+            });
+        });
+
+        describe('profileRange', () => {
+            it('should return non-negative counters for the range', async () => {
+                const progress = [];
+                const profile = await db.profileRange({
+                    gte: Buffer.from([0, 0, 0, 0]),
+                    lte: Buffer.from([255, 255, 255, 255]),
+                    progressInterval: 1,
+                    onProgress: p => progress.push(p),
+                });
+                expect(profile.keys).toBeGreaterThan(0);
+                expect(profile.truncated).toBe(false);
+                for (const name of ['bytesReturned', 'bytesRead', 'blockReadCount', 'blockCacheHitCount', 'blocksReadPerKey', 'bloomUsefulRate']) {
+                    expect(profile[name]).toBeGreaterThanOrEqual(0);
+                }
+                expect(progress).toHaveLength(profile.keys);
+                for (let i = 1; i < progress.length; i += 1) {
+                    expect(progress[i].keys).toBeGreaterThan(progress[i - 1].keys);
+                    expect(progress[i].bytesReturned).toBeGreaterThan(progress[i - 1].bytesReturned);
+                    expect(progress[i].bytesRead).toBeGreaterThanOrEqual(progress[i - 1].bytesRead);
+                }
+            });
+
+            it('should stop at the row cap', async () => {
+                const profile = await db.profileRange({ maxRows: 1 });
+                expect(profile.keys).toEqual(1);
+                expect(profile.truncated).toBe(true);
+            });
+
+            it('should reject when maxRows is invalid', async () => {
+                await expect(db.profileRange({ maxRows: 0 })).rejects.toThrow('maxRows must be a positive integer');
+            });
+        });
+
+        describe('metrics', () => {
+            it('should return null if metrics are disabled', () => {
+                expect(db.getMetrics()).toBeNull();
+            });
+
+            it('should record the latency of commit, get and prove', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_metrics', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const metricsDB = new StateDB(dbPath, { metrics: true });
+                const writer = metricsDB.newReadWriter();
+                for (const pair of initState) {
+                    await writer.set(pair.key, pair.value);
+                }
+                const metricsRoot = await metricsDB.commit(writer, 1, Buffer.alloc(0));
+                await metricsDB.get(initState[0].key);
+                await metricsDB.prove(metricsRoot, [initState[0].key]);
+
+                const metrics = metricsDB.getMetrics();
+                for (const operation of ['commit', 'get', 'prove']) {
+                    expect(metrics[operation].queueWait.count).toEqual(1);
+                    expect(metrics[operation].execution.count).toEqual(1);
+                    expect(metrics[operation].execution.sumMs).toBeGreaterThanOrEqual(0);
+                }
+                expect(metrics.iterate.execution.count).toEqual(0);
+                metricsDB.close();
             });
+        });
 
-            it('should return associated value', async () => {
-                const reader = db.newReader();
-                await expect(reader.get(initState[0].key)).resolves.toEqual(initState[0].value);
+        describe('bloomFilter', () => {
+            let bloomDB;
+            let bloomRoot;
+
+            beforeEach(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_bloom', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                bloomDB = new StateDB(dbPath, { bloomFilter: true, bloomFilterFalsePositiveRate: 0.001 });
+                const writer = bloomDB.newReadWriter();
+                for (const pair of initState) {
+                    await writer.set(pair.key, pair.value);
+                }
+                bloomRoot = await bloomDB.commit(writer, 0, Buffer.alloc(0));
             });
 
-            it('should return true when called has if key exist', async () => {
-                const reader = db.newReader();
-                await expect(reader.has(initState[0].key)).resolves.toEqual(true);
+            afterEach(() => {
+                bloomDB.close();
             });
 
-            it('should iterate with specified range with limit', async () => {
-                const reader = db.newReader();
-                const stream = reader.iterate({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                });
+            // waitForRebuilds waits until the filter built on the DB thread is installed.
+            // getCurrentState is queued on the DB thread behind the build, and it is answered after the filter is installed.
+            const waitForRebuilds = async rebuilds => {
+                await bloomDB.getCurrentState();
+                expect(bloomDB.bloomStats().rebuilds).toEqual(rebuilds);
+            };
 
-                const values = await new Promise((resolve, reject) => {
-                    const result = [];
+            it('should return null stats if bloom filter is disabled', () => {
+                expect(db.bloomStats()).toBeNull();
+            });
+
+            it('should answer non existing key without reading the database', async () => {
+                // the database is read until the filter started by the first lookup is built
+                await expect(bloomDB.has(getRandomBytes())).resolves.toEqual(false);
+                await waitForRebuilds(1);
+                expect(bloomDB.bloomStats().checks).toEqual(0);
+
+                await expect(bloomDB.has(getRandomBytes())).resolves.toEqual(false);
+                for (const pair of initState) {
+                    await expect(bloomDB.has(pair.key)).resolves.toEqual(true);
+                }
+                const stats = bloomDB.bloomStats();
+                expect(stats.checks).toEqual(initState.length + 1);
+                expect(stats.definiteMisses + stats.falsePositives).toEqual(1);
+                expect(stats.rebuilds).toEqual(1);
+            });
+
+            it('should not have false negative after commit and revert', async () => {
+                await bloomDB.has(initState[0].key);
+                await waitForRebuilds(1);
+                const writer = bloomDB.newReadWriter();
+                const newKey = getRandomBytes();
+                await writer.set(newKey, getRandomBytes());
+                await writer.del(initState[1].key);
+                const nextRoot = await bloomDB.commit(writer, 1, bloomRoot);
+                await expect(bloomDB.has(newKey)).resolves.toEqual(true);
+                await expect(bloomDB.has(initState[1].key)).resolves.toEqual(false);
+
+                await bloomDB.revert(nextRoot, 1);
+                await expect(bloomDB.has(newKey)).resolves.toEqual(false);
+                await expect(bloomDB.has(initState[1].key)).resolves.toEqual(true);
+                await waitForRebuilds(2);
+                await expect(bloomDB.has(newKey)).resolves.toEqual(false);
+                await expect(bloomDB.has(initState[1].key)).resolves.toEqual(true);
+            });
+
+            it('should reject invalid false positive rate', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_bloom_invalid', Date.now().toString());
+                expect(() => new StateDB(dbPath, { bloomFilter: true, bloomFilterFalsePositiveRate: 1 })).toThrow(
+                    'bloomFilterFalsePositiveRate must be between 0 and 1',
+                );
+            });
+        });
+
+        describe('negativeCache', () => {
+            let cacheDB;
+            let cacheRoot;
+
+            beforeEach(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_negative_cache', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                cacheDB = new StateDB(dbPath, { negativeCacheSize: 100, bloomBitsPerKey: 12 });
+                const writer = cacheDB.newReadWriter();
+                for (const pair of initState) {
+                    await writer.set(pair.key, pair.value);
+                }
+                cacheRoot = await cacheDB.commit(writer, 0, Buffer.alloc(0));
+            });
+
+            afterEach(() => {
+                cacheDB.close();
+            });
+
+            it('should return null stats if the negative cache is disabled', () => {
+                expect(db.negativeCacheStats()).toBeNull();
+            });
+
+            it('should answer the repeated missing key from the cache', async () => {
+                const missing = getRandomBytes();
+                await expect(cacheDB.has(missing)).resolves.toEqual(false);
+                await expect(cacheDB.has(missing)).resolves.toEqual(false);
+                await expect(cacheDB.has(initState[0].key)).resolves.toEqual(true);
+                expect(cacheDB.negativeCacheStats()).toEqual({ hits: 1, misses: 2, evictions: 0, entries: 1 });
+            });
+
+            it('should see the key created by the commit and restored by the revert', async () => {
+                const newKey = getRandomBytes();
+                await expect(cacheDB.has(newKey)).resolves.toEqual(false);
+                await expect(cacheDB.has(initState[1].key)).resolves.toEqual(true);
+                const writer = cacheDB.newReadWriter();
+                await writer.set(newKey, getRandomBytes());
+                await writer.del(initState[1].key);
+                const nextRoot = await cacheDB.commit(writer, 1, cacheRoot);
+                await expect(cacheDB.has(newKey)).resolves.toEqual(true);
+                await expect(cacheDB.has(initState[1].key)).resolves.toEqual(false);
+
+                await cacheDB.revert(nextRoot, 1);
+                await expect(cacheDB.has(initState[1].key)).resolves.toEqual(true);
+                await expect(cacheDB.has(newKey)).resolves.toEqual(false);
+            });
+
+            it('should reject invalid options', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_negative_cache_invalid', Date.now().toString());
+                expect(() => new StateDB(dbPath, { negativeCacheSize: 0 })).toThrow('negativeCacheSize must be a positive integer');
+                expect(() => new StateDB(dbPath, { bloomBitsPerKey: 100 })).toThrow('bloomBitsPerKey must be between 1 and 64');
+            });
+        });
+
+        describe('valueCompression', () => {
+            const stateKey = i => Buffer.from([0, 0, 0, 2, 0, 0, 0, 0, i]);
+            const collect = async iterable => new Promise((resolve, reject) => {
+                const result = [];
+                iterable.iterate({ gte: stateKey(0), lte: stateKey(255) })
+                    .on('data', kv => {
+                        result.push(kv);
+                    })
+                    .on('error', err => {
+                        reject(err);
+                    })
+                    .on('end', () => {
+                        resolve(result);
+                    });
+            });
+
+            it('should read the values written before and after enabling the compression', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_value_compression', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const plainDB = new StateDB(dbPath);
+                let writer = plainDB.newReadWriter();
+                await writer.set(stateKey(0), Buffer.alloc(1000, 1));
+                const root1 = await plainDB.commit(writer, 1, Buffer.alloc(0));
+                await plainDB.close();
+
+                const compressedDB = new StateDB(dbPath, { valueCompression: 'zstd', valueCompressionThreshold: 64 });
+                writer = compressedDB.newReadWriter();
+                await expect(writer.get(stateKey(0))).resolves.toEqual(Buffer.alloc(1000, 1));
+                await writer.set(stateKey(1), Buffer.alloc(1000, 2));
+                await writer.set(stateKey(2), Buffer.from([3]));
+                const root2 = await compressedDB.commit(writer, 2, root1);
+
+                const expected = [
+                    { key: stateKey(0), value: Buffer.alloc(1000, 1) },
+                    { key: stateKey(1), value: Buffer.alloc(1000, 2) },
+                    { key: stateKey(2), value: Buffer.from([3]) },
+                ];
+                await expect(compressedDB.get(stateKey(1))).resolves.toEqual(Buffer.alloc(1000, 2));
+                const reader = compressedDB.newReader();
+                await expect(reader.get(stateKey(1))).resolves.toEqual(Buffer.alloc(1000, 2));
+                await expect(collect(reader)).resolves.toEqual(expected);
+                reader.close();
+                await expect(collect(compressedDB)).resolves.toEqual(expected);
+
+                writer = compressedDB.newReadWriter();
+                await writer.del(stateKey(1));
+                const root3 = await compressedDB.commit(writer, 3, root2);
+                await compressedDB.revert(root3, 3);
+                await expect(compressedDB.get(stateKey(1))).resolves.toEqual(Buffer.alloc(1000, 2));
+                await compressedDB.revert(root2, 2);
+                await expect(compressedDB.get(stateKey(0))).resolves.toEqual(Buffer.alloc(1000, 1));
+                await expect(compressedDB.get(stateKey(1))).rejects.toThrow(NotFoundError);
+                await compressedDB.close();
+            });
+
+            it('should reject invalid options', () => {
+                const dbPath = path.join(os.tmpdir(), 'state_value_compression_invalid', Date.now().toString());
+                expect(() => new StateDB(dbPath, { valueCompression: 'lz4' })).toThrow('valueCompression must be one of none or zstd, got lz4');
+                expect(() => new StateDB(dbPath, { valueCompression: 'zstd', valueCompressionThreshold: 0 })).toThrow('valueCompressionThreshold must be a positive integer');
+            });
+        });
+
+        describe('setBulkLoad', () => {
+            const stateKey = i => Buffer.from([0, 0, 0, 2, 0, 0, 0, 0, i]);
+            const commitHeights = async (stateDB, from, to, prevRoot) => {
+                const roots = [];
+                let root = prevRoot;
+                for (let height = from; height <= to; height += 1) {
+                    const writer = stateDB.newReadWriter();
+                    await writer.set(stateKey(0), Buffer.from([height]));
+                    await writer.set(stateKey(height), Buffer.from([height]));
+                    if (height > 1) {
+                        await writer.del(stateKey(height - 1));
+                    }
+                    root = await stateDB.commit(writer, height, root);
+                    roots.push(root);
+                }
+                return roots;
+            };
+
+            it('should commit the same roots as without the bulk load and resume after disabling it', async () => {
+                const bulkPath = path.join(os.tmpdir(), 'state_bulk_load', Date.now().toString());
+                const plainPath = path.join(os.tmpdir(), 'state_bulk_load_plain', Date.now().toString());
+                fs.mkdirSync(bulkPath, { recursive: true });
+                fs.mkdirSync(plainPath, { recursive: true });
+                const bulkDB = new StateDB(bulkPath);
+                const plainDB = new StateDB(plainPath);
+
+                await bulkDB.setBulkLoad(true);
+                const bulkRoots = await commitHeights(bulkDB, 1, 3, Buffer.alloc(0));
+                await bulkDB.setBulkLoad(false);
+                bulkRoots.push(...await commitHeights(bulkDB, 4, 5, bulkRoots[2]));
+                const plainRoots = await commitHeights(plainDB, 1, 5, Buffer.alloc(0));
+                expect(bulkRoots).toEqual(plainRoots);
+                await expect(bulkDB.getRootAt(2)).resolves.toEqual(bulkRoots[1]);
+
+                // heights after the bulk load are reverted as usual
+                await expect(bulkDB.revert(bulkRoots[4], 5)).resolves.toEqual(bulkRoots[3]);
+                await expect(bulkDB.revert(bulkRoots[3], 4)).resolves.toEqual(bulkRoots[2]);
+                await expect(bulkDB.revert(bulkRoots[2], 3)).rejects.toHaveProperty('code', 'ERR_BULK_LOADED');
+                await expect(bulkDB.get(stateKey(3))).resolves.toEqual(Buffer.from([3]));
+
+                const [root] = await commitHeights(bulkDB, 4, 4, bulkRoots[2]);
+                expect(root).toEqual(plainRoots[3]);
+                await expect(bulkDB.revert(root, 4)).resolves.toEqual(bulkRoots[2]);
+                await bulkDB.close();
+                await plainDB.close();
+            });
+        });
+
+        describe('reader limits', () => {
+            const stateKey = i => Buffer.from([0, 0, 0, 2, 0, 0, 0, 0, i]);
+            let limitDB;
+
+            beforeEach(async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_reader_limits', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                limitDB = new StateDB(dbPath, { maxOpenReaders: 2, maxIterationResultSize: 45 });
+                const writer = limitDB.newReadWriter();
+                for (let i = 1; i <= 5; i += 1) {
+                    await writer.set(stateKey(i), Buffer.alloc(10, i));
+                }
+                await limitDB.commit(writer, 0, Buffer.alloc(0));
+                writer.close();
+            });
+
+            afterEach(() => {
+                limitDB.close();
+            });
+
+            it('should reject the reader over maxOpenReaders until one is closed', () => {
+                const reader = limitDB.newReader();
+                const readWriter = limitDB.newReadWriter();
+                expect(() => limitDB.newReader()).toThrow(expect.objectContaining({ code: 'ERR_TOO_MANY_READERS' }));
+                expect(() => limitDB.newReadWriter()).toThrow(expect.objectContaining({ code: 'ERR_TOO_MANY_READERS' }));
+                expect(limitDB.listReaders().count).toEqual(2);
+
+                reader.close();
+                // closing twice does not release another slot
+                reader.close();
+                expect(limitDB.listReaders().count).toEqual(1);
+                const next = limitDB.newReader();
+                next.close();
+                readWriter.close();
+                expect(limitDB.listReaders()).toEqual({ count: 0, agesMs: [] });
+            });
+
+            it('should release the slot of the reader dropped without close on the garbage collection', async () => {
+                (() => {
+                    limitDB.newReader();
+                    limitDB.newReadWriter();
+                })();
+                expect(() => limitDB.newReader()).toThrow(expect.objectContaining({ code: 'ERR_TOO_MANY_READERS' }));
+                await collectGarbageUntil(() => limitDB.listReaders().count === 0);
+                expect(limitDB.listReaders().count).toEqual(0);
+                limitDB.newReader().close();
+            });
+
+            it('should list the ages of the open readers with the oldest first', () => {
+                const openedAt = Date.now();
+                const oldest = limitDB.newReader();
+                const newest = limitDB.newReader();
+                const { count, agesMs } = limitDB.listReaders();
+                const elapsedMs = Date.now() - openedAt;
+                expect(count).toEqual(2);
+                // the ages are the milliseconds since the readers are opened, and the order of the different ages
+                // is covered by the test of OpenReaders
+                expect(agesMs[0]).toBeLessThanOrEqual(elapsedMs + 1);
+                expect(agesMs[0]).toBeGreaterThanOrEqual(agesMs[1]);
+                oldest.close();
+                newest.close();
+            });
+
+            it('should stop the iteration over maxIterationResultSize', async () => {
+                const reader = limitDB.newReader();
+                const stream = reader.iterate({ gte: stateKey(0), lte: stateKey(255) });
+                const result = await new Promise((resolve, reject) => {
+                    const pairs = [];
                     stream
                         .on('data', kv => {
-                            result.push(kv);
+                            pairs.push(kv);
                         })
-                        .on('err', err => {
+                        .on('error', err => {
                             reject(err);
                         })
                         .on('end', () => {
-                            resolve(result);
+                            resolve(pairs);
                         });
                 });
-
-                expect(values).toEqual(initState.slice(1, 3));
+                expect(result.map(kv => kv.key)).toEqual([stateKey(1), stateKey(2)]);
+                expect(stream.truncated).toEqual(true);
+                reader.close();
             });
 
-            it('should create read stream with specified range with limit', async () => {
-                const reader = db.newReader();
-                const stream = reader.createReadStream({
-                    gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 1]),
-                    lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
-                    limit: 2,
-                });
-
-                const values = await new Promise((resolve, reject) => {
-                    const result = [];
+            it('should stop the iteration of StateDB over maxIterationResultSize', async () => {
+                const collect = stream => new Promise((resolve, reject) => {
+                    const keys = [];
                     stream
                         .on('data', kv => {
-                            result.push(kv);
+                            keys.push(kv.key);
                         })
-                        .on('err', err => {
+                        .on('error', err => {
                             reject(err);
                         })
                         .on('end', () => {
-                            resolve(result);
+                            resolve(keys);
                         });
                 });
+                const stream = limitDB.iterate({ gte: stateKey(0), lte: stateKey(255) });
+                expect(await collect(stream)).toEqual([stateKey(1), stateKey(2)]);
+                expect(stream.truncated).toEqual(true);
 
-                expect(values).toEqual(initState.slice(1, 3));
+                const kindStream = limitDB.iterateKind('state');
+                expect(await collect(kindStream)).toHaveLength(2);
+                expect(kindStream.truncated).toEqual(true);
             });
 
-            it('should throw an error when the reader is closed', async () => {
-                const reader = db.newReader();
-                await expect(reader.get(initState[0].key)).resolves.toEqual(initState[0].value);
-                reader.close();
-                expect(() => reader.get(initState[1].key)).rejects.toThrow();
+            it('should truncate the range over maxIterationResultSize', async () => {
+                const readWriter = limitDB.newReadWriter();
+                await readWriter.del(stateKey(1));
+                const result = await readWriter.range({ gte: stateKey(0), lte: stateKey(255) });
+                expect(result.map(kv => kv.key)).toEqual([stateKey(2), stateKey(3)]);
+                expect(result.truncated).toEqual(true);
+
+                const next = await readWriter.range({ gte: stateKey(4), lte: stateKey(255) });
+                expect(next.map(kv => kv.key)).toEqual([stateKey(4), stateKey(5)]);
+                expect(next.truncated).toEqual(false);
+                readWriter.close();
             });
         });
 
-        describe('checkpoint', () => {
-            let tmpPath;
-            beforeEach(() => {
-                tmpPath = fs.mkdtempSync("");
+        describe('key layout', () => {
+            const moduleId = Buffer.from([0, 0, 0, 2]);
+            const storePrefix = Buffer.from([0, 1]);
+            const collectKind = async (stateDB, kind) => new Promise((resolve, reject) => {
+                const result = [];
+                stateDB.iterateKind(kind)
+                    .on('data', kv => {
+                        result.push(kv);
+                    })
+                    .on('error', err => {
+                        reject(err);
+                    })
+                    .on('end', () => {
+                        resolve(result);
+                    });
             });
 
-            it('should create checkpoint', async () => {
-                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
-                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
-                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+            it('should decode the encoded keys', () => {
+                const key = getRandomBytes(20);
+                for (const kind of ['state', 'raw']) {
+                    const encoded = keyEncode(kind, moduleId, storePrefix, key);
+                    expect(keyDecode(kind, encoded)).toEqual({ moduleId, storePrefix, key });
+                }
+                expect(keyDecode('raw', Buffer.concat([moduleId, storePrefix]))).toEqual({ moduleId, storePrefix, key: Buffer.alloc(0) });
+            });
 
-                await db.checkpoint(tmpPath + '/test_db');
+            it('should return the prefixes and the lengths', () => {
+                expect(constants()).toEqual({
+                    prefixes: {
+                        state: Buffer.from([0]),
+                        smt: Buffer.from([1]),
+                        diff: Buffer.from([2]),
+                    },
+                    moduleIdLength: 4,
+                    storePrefixLength: 2,
+                    keyLength: 38,
+                });
+            });
 
-                db = new StateDB(tmpPath + '/test_db');
-                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
-                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
-                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+            it('should return the key length of the database', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_key_layout_length', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const stateDB = new StateDB(dbPath, { keyLength: 22 });
+                expect(stateDB.constants().keyLength).toEqual(22);
+                await stateDB.close();
             });
 
-            it('should failed to create checkpoint because directory is not empty', async () => {
-                await expect(db.get(initState[0].key)).resolves.toEqual(initState[0].value);
-                await expect(db.get(initState[1].key)).resolves.toEqual(initState[1].value);
-                await expect(db.get(initState[2].key)).resolves.toEqual(initState[2].value);
+            it('should reject the malformed parts and keys', () => {
+                expect(() => keyEncode('smt', moduleId, storePrefix, Buffer.alloc(0))).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+                expect(() => keyEncode('state', Buffer.from([0, 2]), storePrefix, Buffer.alloc(0))).toThrow(expect.objectContaining({ code: 'ERR_INVALID_KEY_LENGTH' }));
+                expect(() => keyEncode('state', moduleId, Buffer.from([1]), Buffer.alloc(0))).toThrow(expect.objectContaining({ code: 'ERR_INVALID_KEY_LENGTH' }));
+                expect(() => keyDecode('state', Buffer.from([0, 0, 0, 0, 2, 0]))).toThrow(expect.objectContaining({ code: 'ERR_INVALID_KEY_LENGTH' }));
+                expect(() => keyDecode('state', Buffer.from([1, 0, 0, 0, 2, 0, 1]))).toThrow(expect.objectContaining({ code: 'ERR_INVALID_ARGUMENT' }));
+            });
 
-                await expect(db.checkpoint(tmpPath)).rejects.toThrow();
+            it('should agree with the keys written by the read writer', async () => {
+                const dbPath = path.join(os.tmpdir(), 'state_key_layout', Date.now().toString());
+                fs.mkdirSync(dbPath, { recursive: true });
+                const stateDB = new StateDB(dbPath);
+                const keys = [getRandomBytes(32), getRandomBytes(32)];
+                const writer = stateDB.newReadWriter();
+                for (const key of keys) {
+                    await writer.set(keyEncode('raw', moduleId, storePrefix, key), key);
+                }
+                await stateDB.commit(writer, 1, Buffer.alloc(0));
+
+                const stored = await collectKind(stateDB, 'state');
+                expect(stored).toHaveLength(keys.length);
+                const { prefixes } = stateDB.constants();
+                for (const { key, value } of stored) {
+                    expect(keyDecode('raw', key)).toEqual({ moduleId, storePrefix, key: value });
+                    const stateKey = keyEncode('state', moduleId, storePrefix, value);
+                    expect(stateKey).toEqual(Buffer.concat([prefixes.state, key]));
+                    expect(keyDecode('state', stateKey).key).toEqual(value);
+                }
+                await stateDB.close();
             });
         });
 
@@ -638,6 +3226,86 @@ describe('statedb', () => {
                 await expect(db.verifyNonInclusionProof(root, queries, proof)).resolves.toEqual(false);
                 await expect(db.verifyInclusionProof(root, queries, proof)).resolves.toEqual(false);
             });
+
+            it('should return the size of the encoded proof with includeSize', async () => {
+                const queries = [
+                    Buffer.concat([initState[0].key.slice(0, 6), crypto.createHash('sha256').update(initState[0].key.slice(6)).digest()]),
+                    getRandomBytes(38),
+                ];
+                const proof = await db.prove(root, queries, { includeSize: true });
+                // each field is the key, the length and the bytes, and all the lengths are under 128
+                const fieldSize = length => 2 + length;
+                const expected =
+                    proof.siblingHashes.reduce((sum, hash) => sum + fieldSize(hash.length), 0) +
+                    proof.queries.reduce(
+                        (sum, query) => sum + fieldSize(fieldSize(query.key.length) + fieldSize(query.value.length) + fieldSize(query.bitmap.length)),
+                        0,
+                    );
+                expect(proof.size).toEqual(expected);
+                await expect(db.verify(root, queries, proof)).resolves.toEqual(true);
+
+                const withoutSize = await db.prove(root, queries);
+                expect(withoutSize.size).toBeUndefined();
+            });
+
+            it('should reject when query key length is invalid', async () => {
+                const queries = [getRandomBytes(38), getRandomBytes(38)];
+                const proof = await db.prove(root, queries);
+
+                await expect(db.prove(root, [getRandomBytes(38), getRandomBytes(32)])).rejects.toThrow('invalid key length: expected 38, got 32');
+                await expect(db.prove(root, [Buffer.alloc(0)])).rejects.toThrow('invalid key length: expected 38, got 0');
+                await expect(db.verify(root, [queries[0], getRandomBytes(39)], proof)).rejects.toThrow('invalid key length: expected 38, got 39');
+            });
+
+            describe('includeValues', () => {
+                const hashKey = key => Buffer.concat([key.slice(0, 6), crypto.createHash('sha256').update(key.slice(6)).digest()]);
+
+                it('should attach the value to inclusion queries only', async () => {
+                    const { root: currentRoot } = await db.getCurrentState();
+                    const queries = [initState[0].key, initState[2].key, Buffer.from([0, 0, 0, 0, 0, 9, 9])];
+                    const proof = await db.prove(currentRoot, queries, { includeValues: true });
+
+                    expect(proof.queries).toHaveLength(3);
+                    expect(proof.queries[0].key).toEqual(hashKey(initState[0].key));
+                    expect(proof.queries[0].rawValue).toEqual(await db.get(initState[0].key));
+                    expect(proof.queries[1].rawValue).toEqual(await db.get(initState[2].key));
+                    expect(proof.queries[2].rawValue).toBeUndefined();
+
+                    const queryKeys = queries.map(hashKey);
+                    await expect(db.verify(currentRoot, queryKeys, proof)).resolves.toEqual(true);
+                });
+
+                it('should not attach the value without the option', async () => {
+                    const { root: currentRoot } = await db.getCurrentState();
+                    const proof = await db.prove(currentRoot, [hashKey(initState[0].key)]);
+
+                    expect(proof.queries[0].rawValue).toBeUndefined();
+                });
+
+                it('should reject a tampered value on verify', async () => {
+                    const { root: currentRoot } = await db.getCurrentState();
+                    const queries = [initState[0].key, initState[1].key];
+                    const proof = await db.prove(currentRoot, queries, { includeValues: true });
+                    proof.queries[1].rawValue = getRandomBytes();
+
+                    await expect(db.verify(currentRoot, queries.map(hashKey), proof)).rejects.toThrow('Value does not match the value hash of the query `1`');
+                });
+
+                it('should reject a value attached to exclusion query on verify', async () => {
+                    const { root: currentRoot } = await db.getCurrentState();
+                    const queries = [Buffer.from([0, 0, 0, 0, 0, 9, 9])];
+                    const proof = await db.prove(currentRoot, queries, { includeValues: true });
+                    proof.queries[0].rawValue = getRandomBytes();
+
+                    await expect(db.verify(currentRoot, queries.map(hashKey), proof)).rejects.toThrow('Value does not match the value hash of the query `0`');
+                });
+
+                it('should reject when state key does not include the prefix', async () => {
+                    const { root: currentRoot } = await db.getCurrentState();
+
+                    await expect(db.prove(currentRoot, [Buffer.from([0, 0, 0])], { includeValues: true })).rejects.toThrow('state key must include the prefix');
+                });
+            });
         });
 
         describe('calculateRoot', () => {
@@ -706,4 +3374,365 @@ describe('statedb', () => {
             });
         });
     });
+
+    describe('InMemoryStateDB', () => {
+        let stateDB;
+
+        beforeEach(() => {
+            const dbPath = path.join(os.tmpdir(), 'in_memory_state', Date.now().toString());
+            fs.mkdirSync(dbPath, { recursive: true });
+            stateDB = new StateDB(dbPath);
+        });
+
+        afterEach(() => {
+            stateDB.close();
+        });
+
+        it('should result in the same roots as StateDB for the commits and the reverts', async () => {
+            const inMemory = new InMemoryStateDB();
+            const writer = stateDB.newReadWriter();
+            for (const pair of initState) {
+                await writer.set(pair.key, pair.value);
+            }
+            const root1 = await stateDB.commit(writer, 1, Buffer.alloc(0));
+            await expect(inMemory.commit(initState, 1, Buffer.alloc(0))).resolves.toEqual(root1);
+
+            const nextWriter = stateDB.newReadWriter();
+            await nextWriter.del(initState[0].key);
+            await nextWriter.set(initState[1].key, Buffer.from([1, 2, 3]));
+            const root2 = await stateDB.commit(nextWriter, 2, root1);
+            const changes = [{ key: initState[0].key }, { key: initState[1].key, value: Buffer.from([1, 2, 3]) }];
+            await expect(inMemory.commit(changes, 2, root1)).resolves.toEqual(root2);
+
+            await expect(inMemory.get(initState[1].key)).resolves.toEqual(Buffer.from([1, 2, 3]));
+            await expect(inMemory.get(initState[7].key)).resolves.toEqual(Buffer.alloc(0));
+            await expect(inMemory.get(initState[0].key)).rejects.toThrow(NotFoundError);
+            await expect(inMemory.has(initState[0].key)).resolves.toBe(false);
+
+            const key = initState[2].key;
+            const queries = [Buffer.concat([key.subarray(0, 6), sha256(key.subarray(6))])];
+            const proof = await inMemory.prove(root2, queries);
+            expect(proof).toEqual(await stateDB.prove(root2, queries));
+            await expect(inMemory.verify(root2, queries, proof)).resolves.toBe(true);
+
+            await expect(inMemory.revert(root2, 2)).resolves.toEqual(await stateDB.revert(root2, 2));
+            await expect(inMemory.get(initState[0].key)).resolves.toEqual(initState[0].value);
+            await expect(inMemory.revert(root1, 2)).rejects.toHaveProperty('code', 'ERR_NOT_RETAINED');
+        });
+
+        it('should iterate the committed state with the range', async () => {
+            const inMemory = new InMemoryStateDB();
+            await inMemory.commit(initState, 1, Buffer.alloc(0));
+            const stream = inMemory.iterate({
+                gte: Buffer.from([0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                lte: Buffer.from([0, 0, 0, 0, 0, 1, 1, 0, 1]),
+                reverse: true,
+            });
+            const result = await new Promise((resolve, reject) => {
+                const pairs = [];
+                stream
+                    .on('data', pair => pairs.push(pair))
+                    .on('error', reject)
+                    .on('end', () => resolve(pairs));
+            });
+            expect(result.map(pair => pair.key)).toEqual(initState.slice(0, 4).map(pair => pair.key).reverse());
+        });
+    });
+
+    describe('durability', () => {
+        const newPath = name => {
+            const dbPath = path.join(os.tmpdir(), name, Date.now().toString());
+            fs.mkdirSync(dbPath, { recursive: true });
+            return dbPath;
+        };
+
+        const expectCommitted = async (dbPath, root, key) => {
+            const reopened = new StateDB(dbPath);
+            const currentState = await reopened.getCurrentState();
+            expect(currentState.version).toEqual(1);
+            expect(currentState.root).toEqual(root);
+            await expect(reopened.get(key)).resolves.toEqual(Buffer.from([1, 2, 3]));
+            reopened.close();
+        };
+
+        it('should keep the commit with sync after the process is killed', async () => {
+            const dbPath = newPath('state_sync_commit');
+            const { root, key } = commitAndKill(dbPath, {}, { sync: true });
+            await expectCommitted(dbPath, root, key);
+        });
+
+        it('should keep the commit with syncWrites after the process is killed', async () => {
+            const dbPath = newPath('state_sync_writes');
+            const { root, key } = commitAndKill(dbPath, { syncWrites: true }, {});
+            await expectCommitted(dbPath, root, key);
+        });
+
+        it('should keep the commit without WAL after flush and the process is killed', async () => {
+            const dbPath = newPath('state_disable_wal');
+            const { root, key } = commitAndKill(dbPath, { disableWal: true }, {}, true);
+            await expectCommitted(dbPath, root, key);
+        });
+
+        it('should reject syncWrites with disableWal', () => {
+            expect(() => new StateDB(newPath('state_sync_without_wal'), { syncWrites: true, disableWal: true }))
+                .toThrow('syncWrites cannot be used with disableWal');
+        });
+
+        it('should reject the commit with sync when WAL is disabled', async () => {
+            const walDB = new StateDB(newPath('state_commit_sync_without_wal'), { disableWal: true });
+            const writer = walDB.newReadWriter();
+            await writer.set(Buffer.from([0, 0, 0, 1, 0, 0, 1]), Buffer.from([1]));
+            await expect(walDB.commit(writer, 1, Buffer.alloc(0), { sync: true }))
+                .rejects.toThrow('sync cannot be used with disableWal');
+            walDB.close();
+        });
+    });
+
+    describe('staged write', () => {
+        const stateKey = Buffer.from([0, 0, 0, 1, 0, 0, 1]);
+        const stagedId = Buffer.from('block-1');
+        let statePath;
+        let dbPath;
+
+        const openAll = () => ({ stateDB: new StateDB(statePath), database: new Database(dbPath) });
+
+        const closeAll = ({ stateDB, database }) => {
+            stateDB.close();
+            database.close();
+        };
+
+        const prepare = async ({ database }) => {
+            const batch = new Batch();
+            batch.set(Buffer.from('staged'), Buffer.from([1, 2, 3]));
+            await database.writePrepared(stagedId, batch);
+        };
+
+        beforeEach(() => {
+            const base = path.join(os.tmpdir(), 'staged_write', Date.now().toString());
+            statePath = path.join(base, 'state');
+            dbPath = path.join(base, 'db');
+            fs.mkdirSync(statePath, { recursive: true });
+            fs.mkdirSync(dbPath, { recursive: true });
+        });
+
+        it('should discard the staged batch when the commit did not land', async () => {
+            const dbs = openAll();
+            await prepare(dbs);
+            closeAll(dbs);
+
+            const reopened = openAll();
+            await expect(reopened.database.has(Buffer.from('staged'))).resolves.toBe(false);
+            await expect(reopened.database.recoverStaged(reopened.stateDB)).resolves.toEqual({ applied: [], discarded: [stagedId] });
+            await expect(reopened.database.has(Buffer.from('staged'))).resolves.toBe(false);
+            closeAll(reopened);
+        });
+
+        it('should apply the staged batch when the commit landed', async () => {
+            const dbs = openAll();
+            await prepare(dbs);
+            closeAll(dbs);
+
+            const committing = openAll();
+            const writer = committing.stateDB.newReadWriter();
+            await writer.set(stateKey, Buffer.from([1]));
+            const root = await committing.stateDB.commit(writer, 1, Buffer.alloc(0), { stagedId });
+            closeAll(committing);
+
+            const reopened = openAll();
+            await expect(reopened.stateDB.getCurrentState()).resolves.toMatchObject({ root, version: 1 });
+            await expect(reopened.database.has(Buffer.from('staged'))).resolves.toBe(false);
+            await expect(reopened.database.recoverStaged(reopened.stateDB)).resolves.toEqual({ applied: [stagedId], discarded: [] });
+            await expect(reopened.database.get(Buffer.from('staged'))).resolves.toEqual(Buffer.from([1, 2, 3]));
+            await expect(reopened.database.recoverStaged(reopened.stateDB)).resolves.toEqual({ applied: [], discarded: [] });
+            closeAll(reopened);
+        });
+
+        it('should reject the empty staged id', async () => {
+            const dbs = openAll();
+            const writer = dbs.stateDB.newReadWriter();
+            await writer.set(stateKey, Buffer.from([1]));
+            await expect(dbs.stateDB.commit(writer, 1, Buffer.alloc(0), { stagedId: Buffer.alloc(0) }))
+                .rejects.toThrow('stagedId must not be empty');
+            await expect(dbs.database.writePrepared(Buffer.alloc(0), new Batch())).rejects.toThrow('id must not be empty');
+            closeAll(dbs);
+        });
+
+        it('should consume the staged batch', async () => {
+            const dbs = openAll();
+            const batch = new Batch();
+            batch.set(Buffer.from('staged'), Buffer.from([1, 2, 3]));
+            await dbs.database.writePrepared(stagedId, batch);
+            await expect(dbs.database.writePrepared(Buffer.from('block-2'), batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+            await expect(dbs.database.write(batch)).rejects.toHaveProperty('code', 'ERR_BATCH_CONSUMED');
+            batch.close();
+            closeAll(dbs);
+        });
+    });
+
+    describe('closed handles', () => {
+        let closedDB;
+
+        beforeEach(async () => {
+            const dbPath = path.join(os.tmpdir(), 'state', `closed_${Date.now().toString()}`);
+            fs.mkdirSync(dbPath, { recursive: true });
+            closedDB = new StateDB(dbPath);
+        });
+
+        it('should reject StateDB methods with closed error after close', async () => {
+            const writer = closedDB.newReadWriter();
+            await writer.set(initState[0].key, initState[0].value);
+            const nextRoot = await closedDB.commit(writer, 1, Buffer.alloc(0));
+            const pendingWriter = closedDB.newReadWriter();
+            closedDB.close();
+
+            const message = 'StateDB is closed';
+            await expect(closedDB.get(initState[0].key)).rejects.toThrow(message);
+            await expect(closedDB.has(initState[0].key)).rejects.toThrow(message);
+            await expect(closedDB.getCurrentState()).rejects.toThrow(message);
+            await expect(closedDB.commit(pendingWriter, 2, nextRoot)).rejects.toThrow(message);
+            await expect(closedDB.revert(nextRoot, 1)).rejects.toThrow(message);
+            await expect(closedDB.prove(nextRoot, [getRandomBytes(38)])).rejects.toThrow(message);
+            await expect(closedDB.finalize(1)).rejects.toThrow(message);
+            await expect(closedDB.compact()).rejects.toThrow(message);
+            await expect(closedDB.stats()).rejects.toThrow(message);
+            expect(() => closedDB.iterate()).toThrow(message);
+            expect(() => closedDB.newReader()).toThrow(message);
+            expect(() => closedDB.newReadWriter()).toThrow(message);
+        });
+
+        it('should reject reader methods with closed error after close', async () => {
+            const reader = closedDB.newReader();
+            reader.close();
+
+            const message = 'Reader is closed';
+            await expect(reader.get(initState[0].key)).rejects.toThrow(message);
+            await expect(reader.has(initState[0].key)).rejects.toThrow(message);
+            expect(() => reader.iterate()).toThrow(message);
+            closedDB.close();
+        });
+
+        it('should reject read writer and writer methods with closed error after close', async () => {
+            const writer = closedDB.newReadWriter();
+            await writer.set(initState[0].key, initState[0].value);
+            writer.close();
+
+            const message = 'Reader is closed';
+            await expect(writer.get(initState[0].key)).rejects.toThrow(message);
+            await expect(writer.has(initState[0].key)).rejects.toThrow(message);
+            await expect(writer.set(initState[0].key, getRandomBytes())).rejects.toThrow(message);
+            await expect(writer.del(initState[0].key)).rejects.toThrow(message);
+            await expect(writer.range()).rejects.toThrow(message);
+            await expect(writer.deleteRange(initState[0].key, initState[1].key)).rejects.toThrow(message);
+            expect(() => writer.snapshot()).toThrow('StateWriter is closed');
+            expect(() => writer.restoreSnapshot(0)).toThrow('StateWriter is closed');
+            await expect(closedDB.commit(writer, 1, Buffer.alloc(0))).rejects.toThrow(
+                'StateWriter is closed',
+            );
+            closedDB.close();
+        });
+
+        it('should end the reader iteration with closed error once when closed during the iteration', async () => {
+            const writer = closedDB.newReadWriter();
+            for (const data of initState) {
+                await writer.set(data.key, data.value);
+            }
+            await closedDB.commit(writer, 1, Buffer.alloc(0));
+            const reader = closedDB.newReader();
+
+            const stream = reader.iterate({ chunkSize: 1 });
+            const { count, errors } = await new Promise((resolve, reject) => {
+                let received = 0;
+                const result = [];
+                stream
+                    .on('data', () => {
+                        received += 1;
+                        reader.close();
+                    })
+                    .on('error', err => {
+                        result.push(err);
+                        // close resolves after the iteration releases the connection, so that the callback called
+                        // more than once would have been called by the next turn of the event loop
+                        closedDB
+                            .close()
+                            .then(() => new Promise(setImmediate))
+                            .then(() => resolve({ count: received, errors: result }), reject);
+                    })
+                    .on('end', () => {
+                        reject(new Error('iteration should not end'));
+                    });
+            });
+
+            expect(count).toBeLessThan(initState.length);
+            expect(errors).toHaveLength(1);
+            expect(errors[0].message).toEqual('Reader is closed');
+        });
+
+        it('should ignore close when the handles are already closed', () => {
+            const reader = closedDB.newReader();
+            const writer = closedDB.newReadWriter();
+            reader.close();
+            writer.close();
+            closedDB.close();
+
+            expect(() => reader.close()).not.toThrow();
+            expect(() => writer.close()).not.toThrow();
+            expect(() => closedDB.close()).not.toThrow();
+        });
+    });
+
+    describe('shutdownAll', () => {
+        const newPath = name => {
+            const dbPath = path.join(os.tmpdir(), 'state', `${name}_${Date.now().toString()}`);
+            fs.mkdirSync(dbPath, { recursive: true });
+            return dbPath;
+        };
+
+        it('should reject invalid timeout', async () => {
+            await expect(shutdownAll({ timeoutMs: -1 })).rejects.toThrow('timeoutMs must be a non-negative integer');
+        });
+
+        it('should close all the open handles after the pending operations', async () => {
+            const stateDB = new StateDB(newPath('shutdown_state'));
+            const writer = stateDB.newReadWriter();
+            for (const data of initState) {
+                await writer.set(data.key, data.value);
+            }
+            await stateDB.commit(writer, 1, Buffer.alloc(0));
+            const reader = stateDB.newReader();
+            const database = new Database(newPath('shutdown_db'));
+            const batch = new Batch();
+            for (let i = 0; i < 1000; i += 1) {
+                batch.set(getRandomBytes(), getRandomBytes(100));
+            }
+            await database.write(batch);
+            const closedDB = new Database(newPath('shutdown_closed'));
+            closedDB.close();
+
+            const pending = [
+                database.compact(),
+                database.stats(),
+                stateDB.compact(),
+                ...initState.map(data => reader.get(data.key)),
+            ];
+            const report = await shutdownAll({ timeoutMs: 10000 });
+
+            await expect(Promise.all(pending)).resolves.toHaveLength(pending.length);
+            expect(report.forced).toEqual([]);
+            const closed = report.closed.map(handle => handle.name);
+            expect(closed).toContain('Database');
+            expect(closed).toContain('WriteBatch');
+            expect(closed).toContain('StateWriter');
+            // readers are closed before the StateDB
+            expect(closed.lastIndexOf('Reader')).toBeLessThan(closed.indexOf('StateDB'));
+            expect(closed.filter(name => name === 'Database')).toHaveLength(1);
+
+            await expect(database.get(getRandomBytes())).rejects.toThrow('Database is closed');
+            await expect(stateDB.get(initState[0].key)).rejects.toThrow('StateDB is closed');
+            await expect(reader.get(initState[0].key)).rejects.toThrow('Reader is closed');
+            expect(() => batch.set(getRandomBytes(), getRandomBytes())).toThrow('WriteBatch is closed');
+
+            // nothing is left to close
+            await expect(shutdownAll()).resolves.toEqual({ closed: [], forced: [] });
+        });
+    });
 });
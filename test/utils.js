@@ -13,9 +13,33 @@
  */
 
 const crypto = require('crypto');
+const v8 = require('v8');
+const vm = require('vm');
 
 const getRandomBytes = (size = 32) => crypto.randomBytes(size);
 
+// collectGarbage runs the garbage collection, so that the finalizers of the dropped objects are called.
+// The gc function is exposed at runtime when jest is not run with --expose-gc.
+const collectGarbage = () => {
+    if (typeof global.gc === 'function') {
+        global.gc();
+        return;
+    }
+    v8.setFlagsFromString('--expose-gc');
+    vm.runInNewContext('gc')();
+};
+
+// collectGarbageUntil runs the garbage collection until the condition holds. It yields to the event loop between the runs,
+// because the finalizers are called by the tasks queued after the garbage collection.
+const collectGarbageUntil = async (condition, maxRuns = 100) => {
+    for (let i = 0; i < maxRuns && !condition(); i += 1) {
+        collectGarbage();
+        await new Promise(setImmediate);
+    }
+};
+
 module.exports = {
     getRandomBytes,
+    collectGarbage,
+    collectGarbageUntil,
 };
@@ -12,13 +12,296 @@
  * Removal or modification of this copyright notice is prohibited.
  */
 
-export interface Options {
+// Hasher is the hash function of the SMT. blake3 requires the blake3 feature of the native module.
+export type Hasher = 'sha256' | 'blake3';
+
+export interface TuningOptions {
+    blockCacheSize?: number;
+    writeBufferSize?: number;
+    maxOpenFiles?: number;
+    compression?: 'none' | 'lz4' | 'zstd';
+    compactionStyle?: 'level' | 'universal';
+    // bloom filters of the table files, on by default with 10 bits for the state keys of StateDB
+    bloomBitsPerKey?: number;
+}
+
+export interface WriteQueueOptions {
+    maxPendingOperations?: number;
+    blockOnFull?: boolean;
+}
+
+export interface DurabilityOptions {
+    syncWrites?: boolean;
+    disableWal?: boolean;
+}
+
+export interface SizeLimitOptions {
+    maxKeySize?: number;
+    maxValueSize?: number;
+}
+
+export interface PrefixFilterOptions {
+    // length of the key prefix, such as the module and the store prefix, checked by the bloom filters of existsPrefix
+    // It cannot be changed after the first open with it, and the database opened with another length fails with ERR_INVALID_ARGUMENT
+    prefixFilterLength?: number;
+}
+
+export interface Options extends TuningOptions, WriteQueueOptions, DurabilityOptions, SizeLimitOptions, PrefixFilterOptions {
     readonly?: boolean;
+    exclusive?: boolean;
+    metrics?: boolean;
+    // reject the operations which do not start on the database thread within the time with ERR_TIMEOUT.
+    // The operation rejected with ERR_TIMEOUT is never applied, so that it is safe to retry.
+    // set, del and has run on the call, and they are not timed out
+    operationTimeoutMs?: number;
+    // bytes of the keys and the values of an iterate, the iteration over it stops with truncated set
+    maxIterationResultSize?: number;
 }
 
-export interface StateDBOptions {
+export interface StateDBOptions extends TuningOptions, WriteQueueOptions, DurabilityOptions, SizeLimitOptions, PrefixFilterOptions {
     readonly?: boolean;
+    exclusive?: boolean;
+    readerPoolSize?: number;
+    metrics?: boolean;
+    // reject the operations which do not start on the database thread within the time with ERR_TIMEOUT.
+    // The write rejected with ERR_TIMEOUT is never applied, so that it is safe to retry.
+    // commit and revert are not timed out, and neither are has and prove of the current state which run on the call
+    operationTimeoutMs?: number;
     keyLength?: number;
+    // the filter is built on the database thread on the first has, and has reads the database until it is built
+    bloomFilter?: boolean;
+    bloomFilterFalsePositiveRate?: number;
+    // number of the missing state keys cached for exists, disabled if not set
+    negativeCacheSize?: number;
+    keepDiffForHeights?: number;
+    // old values of the size or larger are stored once by the hash, and the diffs reference them
+    diffValueRefThreshold?: number;
+    // compress the state values of valueCompressionThreshold (256 by default) or larger on commit
+    valueCompression?: 'none' | 'zstd';
+    valueCompressionThreshold?: number;
+    // readers and read-writers open at once, creating more throws ERR_TOO_MANY_READERS
+    maxOpenReaders?: number;
+    // bytes of the keys and the values of an iterate, iterateKind or a range of a reader, the result over it is truncated
+    maxIterationResultSize?: number;
+    // threads updating the SMT on commit, defaults to the number of the CPUs up to 4
+    smtThreads?: number;
+    // run the pending format migrations on open instead of failing with ERR_NEEDS_MIGRATION
+    autoMigrate?: boolean;
+    statePrefixLength?: number;
+    stateKeyLength?: number;
+    // recorded on the first commit, and another hasher is rejected on open with ERR_INVALID_ARGUMENT
+    hasher?: Hasher;
+    // allow installing the SMT nodes received by the state sync
+    unsafeSync?: boolean;
+}
+
+interface BloomStats {
+    checks: number;
+    definiteMisses: number;
+    falsePositives: number;
+    rebuilds: number;
+}
+
+interface NegativeCacheStats {
+    hits: number;
+    misses: number;
+    evictions: number;
+    entries: number;
+}
+
+interface OpenReaders {
+    count: number;
+    // time since each reader is created, the oldest first
+    agesMs: number[];
+}
+
+interface KeyConstants {
+    prefixes: {
+        state: Buffer;
+        smt: Buffer;
+        diff: Buffer;
+    };
+    moduleIdLength: number;
+    storePrefixLength: number;
+    keyLength: number;
+}
+
+interface DecodedKey {
+    moduleId: Buffer;
+    storePrefix: Buffer;
+    key: Buffer;
+}
+
+interface LatencyHistogram {
+    buckets: number[];
+    count: number;
+    sumMs: number;
+}
+
+interface OperationMetrics {
+    queueWait: LatencyHistogram;
+    execution: LatencyHistogram;
+}
+
+export interface Metrics {
+    bucketBoundsMs: number[];
+    get: OperationMetrics;
+    write: OperationMetrics;
+    commit: OperationMetrics;
+    prove: OperationMetrics;
+    iterate: OperationMetrics;
+}
+
+interface RangeProfile {
+    keys: number;
+    bytesReturned: number;
+    bytesRead: number;
+    blockReadCount: number;
+    blockCacheHitCount: number;
+    maxBlocksReadPerKey: number;
+    internalKeySkippedCount: number;
+    internalDeleteSkippedCount: number;
+    blocksReadPerKey: number;
+    bloomUsefulRate: number;
+    truncated: boolean;
+}
+
+interface ProfileRangeOptions {
+    gte?: Buffer;
+    lte?: Buffer;
+    prefix?: Buffer;
+    maxRows?: number;
+    progressInterval?: number;
+    onProgress?: (profile: RangeProfile) => void;
+}
+
+interface RootAtHeight {
+    height: number;
+    root: Buffer;
+}
+
+interface DiffsReport {
+    checked: number;
+    corrupted: { height: number, error: string }[];
+}
+
+interface DiffSectionStats {
+    count: number;
+    keyBytes: number;
+    valueBytes: number;
+    referenced: number;
+}
+
+interface DiffStats {
+    height: number;
+    encodedBytes: number;
+    created: DiffSectionStats;
+    updated: DiffSectionStats;
+    deleted: DiffSectionStats;
+}
+
+interface ConsistencyReport {
+    consistent: boolean;
+    height: number;
+    root: Buffer;
+    rootInTree: boolean;
+    diffExists: boolean;
+    recordedRoot?: Buffer;
+    pendingCommit: boolean;
+    issues: string[];
+}
+
+interface NodesReport {
+    checked: number;
+    mismatches: number;
+    mismatched: Buffer[];
+    truncated: boolean;
+}
+
+interface VerifyNodesOptions {
+    maxErrors?: number;
+    progressInterval?: number;
+    onProgress?: (report: NodesReport) => void;
+}
+
+type IntegrityLevel = 'quick' | 'full';
+
+interface IntegrityIssue {
+    message: string;
+    key: Buffer | null;
+    height: number | null;
+}
+
+interface IntegrityReport {
+    ok: boolean;
+    level: IntegrityLevel;
+    height: number;
+    root: Buffer;
+    checkedNodes: number;
+    checkedKeys: number;
+    errorCount: number;
+    errors: IntegrityIssue[];
+    truncated: boolean;
+}
+
+interface CheckIntegrityOptions {
+    maxErrors?: number;
+    progressInterval?: number;
+    onProgress?: (report: IntegrityReport) => void;
+}
+
+interface PruneReport {
+    reachable: number;
+    scanned: number;
+    deleted: number;
+}
+
+interface PruneSMTOptions {
+    batchSize?: number;
+    onProgress?: (report: PruneReport) => void;
+}
+
+interface RebuildReport {
+    keys: number;
+    inserted: number;
+}
+
+interface RebuildSMTOptions {
+    batchSize?: number;
+    onProgress?: (report: RebuildReport) => void;
+}
+
+interface SmtNode {
+    hash: Buffer;
+    data: Buffer;
+}
+
+interface StagedReport {
+    applied: Buffer[];
+    discarded: Buffer[];
+}
+
+interface StateSnapshotInfo {
+    records: number;
+    height: number;
+    root: Buffer;
+}
+
+interface MigrateOptions {
+    // called with the name of the step and the number of the records processed by it
+    onProgress?: (step: string, processed: number) => void;
+}
+
+interface StateSnapshotOptions {
+    progressInterval?: number;
+    onProgress?: (records: number) => void;
+    // flush the exported file and its directory entry to the disk, off by default. Ignored by the import
+    sync?: boolean;
+}
+
+interface SnapshotToFileOptions {
+    // flush the file and its directory entry to the disk, off by default
+    sync?: boolean;
 }
 
 export interface IterateOptions {
@@ -26,9 +309,131 @@ export interface IterateOptions {
     reverse?: boolean;
     gte?: Buffer;
     lte?: Buffer;
+    prefix?: Buffer;
+    chunkSize?: number;
+    keysOnly?: boolean;
+    // inventory returns { key, valueSize } instead of the value
+    inventory?: boolean;
+    // only with inventory
+    minValueSize?: number;
+    zeroCopy?: boolean;
 }
 
-export class NotFoundError extends Error { }
+export type IterateKind = 'state' | 'diff' | 'smt';
+
+export interface IterateKindOptions extends IterateOptions {
+    // only for the diff kind
+    decode?: boolean;
+}
+
+export interface DecodedDiff {
+    height: number;
+    created: Buffer[];
+    updated: { key: Buffer; value: Buffer }[];
+    deleted: { key: Buffer; value: Buffer }[];
+}
+
+export interface CountRangeOptions {
+    limit?: number;
+    gte?: Buffer;
+    lte?: Buffer;
+    prefix?: Buffer;
+}
+
+export interface DatabaseStats {
+    estimateNumKeys?: number;
+    curSizeAllMemTables?: number;
+    estimatePendingCompactionBytes?: number;
+    blockCacheUsage?: number;
+    numFilesAtLevel0?: number;
+    numFilesAtLevel1?: number;
+    numFilesAtLevel2?: number;
+    numFilesAtLevel3?: number;
+    numFilesAtLevel4?: number;
+    numFilesAtLevel5?: number;
+    numFilesAtLevel6?: number;
+}
+
+export interface ApproximateSize {
+    sstSize: number;
+    memtableSize: number;
+}
+
+interface RevertOptions {
+    expectedRoot?: Buffer;
+}
+
+export interface CheckpointOptions {
+    // fsync the files of the checkpoint and their directory entries before resolving, true by default
+    sync?: boolean;
+    // flush the memtables before creating the checkpoint, false by default
+    flushBeforeCheckpoint?: boolean;
+}
+
+export interface CheckpointInfo {
+    size: number;
+    sstFiles: number;
+}
+
+export interface StateCheckpointInfo extends CheckpointInfo {
+    root: Buffer;
+}
+
+export interface CheckpointGroupInfo {
+    blockchain: CheckpointInfo;
+    state: StateCheckpointInfo;
+}
+
+export class CheckpointGroup {
+    constructor(manifestPath: string);
+    static verify(manifestPath: string): Promise<CheckpointGroupInfo>;
+}
+
+export interface ReadWriterOptions {
+    // bytes of the cache kept in the memory. The rest is spilled to a temporary database, and its errors reject with ERR_IO or ERR_ROCKSDB
+    spillThreshold?: number;
+}
+
+// either expectedValue or mustNotExist must be set
+export interface WriteCondition {
+    key: Buffer;
+    expectedValue?: Buffer;
+    mustNotExist?: boolean;
+}
+
+export interface WriteOptions {
+    maxBatchBytes?: number;
+    journalKey?: Buffer;
+}
+
+export type ErrorCode =
+    | 'ERR_NOT_FOUND'
+    | 'ERR_CLOSED'
+    | 'ERR_INVALID_KEY_LENGTH'
+    | 'ERR_CODEC'
+    | 'ERR_ROCKSDB'
+    | 'ERR_IO'
+    | 'ERR_BUSY'
+    | 'ERR_INVALID_USAGE'
+    | 'ERR_INVALID_ARGUMENT'
+    | 'ERR_READONLY'
+    | 'ERR_INVALID_ROOT'
+    | 'ERR_NOT_RETAINED'
+    | 'ERR_CORRUPTED'
+    | 'ERR_INTERRUPTED'
+    | 'ERR_CANCELLED'
+    | 'ERR_TIMEOUT'
+    | 'ERR_WRITER_CONSUMED'
+    | 'ERR_EMPTY_VALUE'
+    | 'ERR_NEEDS_MIGRATION'
+    | 'ERR_CONDITION_FAILED'
+    | 'ERR_BULK_LOADED'
+    | 'ERR_TOO_MANY_READERS'
+    | 'ERR_UNKNOWN';
+
+export class NotFoundError extends Error {
+    code: 'ERR_NOT_FOUND';
+}
 
 interface DatabaseReader {
     get(key: Buffer): Promise<Buffer>;
@@ -37,19 +442,39 @@ interface DatabaseReader {
     createReadStream(options?: IterateOptions): NodeJS.ReadableStream;
 }
 
+interface PrefixedDatabase extends DatabaseReader {
+    set(key: Buffer, value: Buffer): Promise<void>;
+    del(key: Buffer): Promise<void>;
+    write(batch: Batch): Promise<void>;
+}
+
 export class Database {
     constructor(path: string, option?: Options);
     get(key: Buffer): Promise<Buffer>;
     has(key: Buffer): Promise<boolean>;
+    existsPrefix(prefix: Buffer): Promise<boolean>;
     set(key: Buffer, value: Buffer): Promise<void>;
     del(key: Buffer): Promise<void>;
-    write(batch: Batch): Promise<void>;
+    write(batch: Batch, options?: WriteOptions): Promise<void>;
+    conditionalWrite(conditions: WriteCondition[], batch: Batch): Promise<void>;
+    writePrepared(id: Buffer, batch: Batch): Promise<void>;
+    recoverStaged(stateDB: StateDB): Promise<StagedReport>;
     iterate(options?: IterateOptions): NodeJS.ReadableStream;
     createReadStream(options?: IterateOptions): NodeJS.ReadableStream;
     clear(options?: IterateOptions): Promise<void>;
-    close(): void;
+    close(): Promise<void>;
     newReader(): DatabaseReader;
-    checkpoint(path: string): Promise<void>;
+    withPrefix(prefix: Buffer): PrefixedDatabase;
+    checkpoint(path: string, options?: CheckpointOptions): Promise<CheckpointInfo>;
+    checkpointWithGroup(group: CheckpointGroup, path: string, options?: CheckpointOptions): Promise<CheckpointInfo>;
+    compact(start?: Buffer, end?: Buffer): Promise<void>;
+    flush(): Promise<void>;
+    deleteRange(start: Buffer, end: Buffer): Promise<void>;
+    stats(): Promise<DatabaseStats>;
+    approximateSize(start?: Buffer, end?: Buffer): Promise<ApproximateSize>;
+    countRange(options?: CountRangeOptions): Promise<number>;
+    pendingOperations(): number;
+    getMetrics(reset?: boolean): Metrics | null;
 }
 
 export class InMemoryDatabase {
@@ -64,36 +489,91 @@ export class InMemoryDatabase {
     clear(options?: IterateOptions): Promise<void>;
     clone(): InMemoryDatabase;
     close(): void;
+    snapshot(): number;
+    restoreSnapshot(index?: number): void;
+    snapshotToFile(path: string, options?: SnapshotToFileOptions): Promise<void>;
+    static loadFromFile(path: string): Promise<InMemoryDatabase>;
 }
 
+export type BatchKind = 'state' | 'diff' | 'smt' | 'raw';
+
 export class Batch {
-    set(key: Buffer, value: Buffer): void;
-    del(key: Buffer): void;
+    set(key: Buffer, value: Buffer, kind?: BatchKind): void;
+    del(key: Buffer, kind?: BatchKind): void;
+    reset(): void;
+    close(): void;
 }
 
 declare class StateReader {
     get(key: Buffer): Promise<Buffer>;
     has(key: Buffer): Promise<boolean>;
+    getMany(keys: Buffer[]): Promise<(Buffer | undefined)[]>;
+    hasMany(keys: Buffer[]): Promise<boolean[]>;
     iterate(options?: IterateOptions): NodeJS.ReadableStream;
     createReadStream(options?: IterateOptions): NodeJS.ReadableStream;
+    countRange(options?: CountRangeOptions): Promise<number>;
+    existsPrefix(prefix: Buffer): Promise<boolean>;
+    prove(queries: Buffer[]): Promise<PendingProof>;
+    close(): void;
+}
+
+interface PendingChange {
+    key: Buffer;
+    value?: Buffer;
+    state: 'created' | 'updated' | 'deleted';
+}
+
+interface UpsertOptions {
+    previousValue?: boolean;
+}
+
+interface UpsertResult {
+    existed: boolean;
+    previousValue?: Buffer;
+}
+
+declare class StateRangeCursor {
+    next(count: number): Promise<{ key: Buffer, value?: Buffer, valueSize?: number }[]>;
     close(): void;
 }
 
 declare class StateReadWriter {
     get(key: Buffer): Promise<Buffer>;
+    peek(key: Buffer): Promise<Buffer>;
     has(key: Buffer): Promise<boolean>;
-    set(key: Buffer, value: Buffer): Promise<void>;
+    getMany(keys: Buffer[]): Promise<(Buffer | undefined)[]>;
+    hasMany(keys: Buffer[]): Promise<boolean[]>;
+    set(key: Buffer, value: Buffer, options: UpsertOptions & { previousValue: true }): Promise<UpsertResult>;
+    set(key: Buffer, value: Buffer, options?: UpsertOptions): Promise<boolean>;
     del(key: Buffer): Promise<void>;
-    range(options?: IterateOptions): Promise<{ key: Buffer, value: Buffer }[]>;
+    increment(key: Buffer, delta: number, width?: 4 | 8): Promise<void>;
+    range(options?: IterateOptions): Promise<{ key: Buffer, value?: Buffer, valueSize?: number }[] & { truncated: boolean }>;
+    rangePending(options?: IterateOptions): Promise<PendingChange[]>;
+    openRange(options?: IterateOptions): StateRangeCursor;
+    deleteRange(start: Buffer, end: Buffer): Promise<void>;
+    countRange(options?: CountRangeOptions): Promise<number>;
+    existsPrefix(prefix: Buffer): Promise<boolean>;
     snapshot(): number;
     restoreSnapshot(index: number): void;
+    releaseSnapshot(index: number): void;
+    reset(): void;
+    size(): WriterSize;
     close(): void;
 }
 
+interface WriterSize {
+    entries: number;
+    bytes: number;
+}
+
 interface StateCommitOption {
     readonly?: boolean;
     checkRoot?: boolean;
     expectedRoot?: Buffer;
+    maxBatchBytes?: number;
+    sync?: boolean;
+    onProgress?: (processed: number, total: number) => void;
+    stagedId?: Buffer;
 }
 
 interface Proof {
@@ -102,42 +582,196 @@ interface Proof {
         key: Buffer;
         value: Buffer;
         bitmap: Buffer;
+        rawValue?: Buffer;
     }[];
+    // bytes of the proof encoded by lisk-codec, set by prove with includeSize
+    size?: number;
+}
+
+interface ProveOptions {
+    includeValues?: boolean;
+    height?: number;
+    includeSize?: boolean;
 }
 
 interface CurrentState {
     root: Buffer;
     version: number;
+    height: number;
+    commitCount: number;
+    timestamp: number;
+    revertedFrom?: Buffer;
+}
+
+interface ComputedRoot {
+    root: Buffer;
+    version: number;
+    changedKeys: number;
+}
+
+interface PendingProof {
+    root: Buffer;
+    proof: Proof;
 }
 
 export class StateDB {
     constructor(path: string, option?: StateDBOptions);
+    static migrate(path: string, option?: StateDBOptions, options?: MigrateOptions): Promise<void>;
+    static openWithIntegrityCheck(path: string, option?: StateDBOptions, level?: IntegrityLevel, options?: CheckIntegrityOptions): Promise<{ db: StateDB, report: IntegrityReport }>;
     get(key: Buffer): Promise<Buffer>;
     has(key: Buffer): Promise<boolean>;
     iterate(options?: IterateOptions): NodeJS.ReadableStream;
     createReadStream(options?: IterateOptions): NodeJS.ReadableStream;
-    revert(prevRoot: Buffer, height: number): Promise<Buffer>;
+    iterateKind(kind: IterateKind, options?: IterateKindOptions): NodeJS.ReadableStream;
+    revert(prevRoot: Buffer, height: number, options?: RevertOptions): Promise<Buffer>;
     commit(readWriter: StateReadWriter, height: number, prevRoot: Buffer, options?: StateCommitOption): Promise<Buffer>;
-    prove(root: Buffer, queries: Buffer[]): Promise<Proof>;
+    cancelCommit(): void;
+    computeRoot(readWriter: StateReadWriter): Promise<ComputedRoot>;
+    write(batch: Batch): Promise<void>;
+    prove(root: Buffer, queries: Buffer[], options?: ProveOptions): Promise<Proof>;
+    provePending(readWriter: StateReadWriter, queries: Buffer[]): Promise<PendingProof>;
     verify(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
     verifyInclusionProof(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
     verifyNonInclusionProof(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
     finalize(height: number): Promise<void>;
+    getRootAt(height: number): Promise<Buffer>;
+    getLatestHeights(count: number): Promise<RootAtHeight[]>;
+    checkDiffs(): Promise<DiffsReport>;
+    diffStats(height: number): Promise<DiffStats>;
+    checkConsistency(): Promise<ConsistencyReport>;
     newReader(): StateReader;
-    newReadWriter(): StateReadWriter;
-    close(): void;
-    checkpoint(path: string): Promise<void>;
+    newReadWriter(options?: ReadWriterOptions): StateReadWriter;
+    close(): Promise<void>;
+    checkpoint(path: string, options?: CheckpointOptions): Promise<StateCheckpointInfo>;
+    checkpointWithGroup(group: CheckpointGroup, path: string, options?: CheckpointOptions): Promise<StateCheckpointInfo>;
+    verifyCheckpoint(path: string): Promise<StateCheckpointInfo>;
     getCurrentState(): Promise<CurrentState>;
     calculateRoot(proof: Proof): Promise<Buffer>;
+    compact(start?: Buffer, end?: Buffer): Promise<void>;
+    flush(): Promise<void>;
+    stats(): Promise<DatabaseStats>;
+    approximateSize(prefix: Buffer): Promise<ApproximateSize>;
+    approximateSizes(prefixes: Buffer[]): Promise<Map<string, ApproximateSize>>;
+    bloomStats(): BloomStats | null;
+    negativeCacheStats(): NegativeCacheStats | null;
+    setBulkLoad(enabled: boolean): Promise<void>;
+    constants(): KeyConstants;
+    listReaders(): OpenReaders;
+    pendingOperations(): number;
+    getMetrics(reset?: boolean): Metrics | null;
+    profileRange(options?: ProfileRangeOptions): Promise<RangeProfile>;
+    verifyNodes(path?: string, options?: VerifyNodesOptions): Promise<NodesReport>;
+    checkIntegrity(level?: IntegrityLevel, options?: CheckIntegrityOptions): Promise<IntegrityReport>;
+    cancelCheckIntegrity(): void;
+    pruneSMT(retainHeights: number, options?: PruneSMTOptions): Promise<PruneReport>;
+    rebuildSMT(expectedRoot: Buffer, options?: RebuildSMTOptions): Promise<RebuildReport>;
+    getSmtNode(nodeHash: Buffer): Promise<Buffer | undefined>;
+    getSmtChildren(nodeHash: Buffer): Promise<Buffer[] | undefined>;
+    putSmtNodes(entries: SmtNode[]): Promise<void>;
+    finalizeSync(expectedRoot: Buffer, height: number): Promise<void>;
+    exportSnapshot(path: string, height: number, options?: StateSnapshotOptions): Promise<StateSnapshotInfo>;
+    importSnapshot(path: string, options?: StateSnapshotOptions): Promise<StateSnapshotInfo>;
 }
 
-export class SparseMerkleTree {
+export class InMemoryStateDB {
     constructor(keyLength?: number);
+    get(key: Buffer): Promise<Buffer>;
+    has(key: Buffer): Promise<boolean>;
+    iterate(options?: IterateOptions): NodeJS.ReadableStream;
+    commit(changes: { key: Buffer, value?: Buffer }[], height: number, prevRoot: Buffer): Promise<Buffer>;
+    revert(prevRoot: Buffer, height: number): Promise<Buffer>;
+    prove(root: Buffer, queries: Buffer[]): Promise<Proof>;
+    verify(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
+}
+
+export interface ShutdownOptions {
+    timeoutMs?: number;
+}
+
+export interface ShutdownHandleReport {
+    name: 'Database' | 'StateDB' | 'Reader' | 'StateWriter' | 'WriteBatch';
+    pending: number;
+}
+
+export interface ShutdownReport {
+    closed: ShutdownHandleReport[];
+    forced: ShutdownHandleReport[];
+}
+
+export function shutdownAll(options?: ShutdownOptions): Promise<ShutdownReport>;
+
+export type KeyKind = 'state' | 'raw';
+
+export function keyEncode(kind: KeyKind, moduleId: Buffer, storePrefix: Buffer, key: Buffer): Buffer;
+
+export function keyDecode(kind: KeyKind, fullKey: Buffer): DecodedKey;
+
+export function constants(): KeyConstants;
+
+interface SMTNode {
+    nodeHash: Buffer;
+    kind: 'leaf' | 'branch' | 'empty';
+    height: number;
+    key?: Buffer;
+    value?: Buffer;
+}
+
+interface VerifyEntry {
+    root: Buffer;
+    proof: Proof;
+    queryKeys: Buffer[];
+}
+
+export interface SparseMerkleTreeOptions {
+    hasher?: Hasher;
+}
+
+export interface SMTVectorBatch {
+    keys: string[];
+    values: string[];
+    deleteKeys?: string[];
+}
+
+export interface SMTVectorCase {
+    description?: string;
+    input: {
+        keys?: string[];
+        values?: string[];
+        deleteKeys?: string[];
+        batches?: SMTVectorBatch[];
+        queryKeys?: string[];
+    };
+    output?: {
+        merkleRoot: string;
+        roots?: string[];
+        proof: {
+            siblingHashes: string[];
+            queries: { bitmap: string, key: string, value: string }[];
+        };
+    };
+}
+
+export interface SMTVectors {
+    title?: string;
+    summary?: string;
+    config?: { keyLength?: number, hasher?: Hasher };
+    runner?: string;
+    handler?: string;
+    testCases: SMTVectorCase[];
+}
+
+export class SparseMerkleTree {
+    constructor(keyLength?: number, opts?: SparseMerkleTreeOptions);
     update(root: Buffer, kvpair: { key: Buffer, value: Buffer }[]): Promise<Buffer>;
     prove(root: Buffer, queries: Buffer[]): Promise<Proof>;
     verify(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
+    verifyBatch(entries: VerifyEntry[]): Promise<boolean[]>;
     verifyInclusionProof(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
     verifyNonInclusionProof(root: Buffer, queries: Buffer[], proof: Proof): Promise<boolean>;
     calculateRoot(proof: Proof): Promise<Buffer>;
+    calculateRootFromData(kvpairs: { key: Buffer, value: Buffer }[]): Promise<Buffer>;
     removeKeysFromProof(proof: Proof, removedKeys: Buffer[]): Promise<Proof>;
+    exportNodes(root: Buffer): Promise<SMTNode[]>;
+    importNodes(nodes: SMTNode[]): Promise<Buffer>;
+    static runVectors(input: SMTVectors | string): Promise<SMTVectors>;
 }